@@ -0,0 +1,769 @@
+use crate::data::{DataType, TableOperation};
+use crate::table::{Table, TableError, WriteResult, WriteToken};
+use futures_util::future;
+use futures_util::stream::{FuturesUnordered, Stream, StreamExt, TryStreamExt};
+use std::io::{self, BufRead};
+use std::mem;
+use tower_service::Service;
+
+/// A failed bulk load.
+#[derive(Debug, Fail)]
+pub enum BulkLoadError {
+    /// The load was asked to send batches of no rows, or to keep no batches in flight.
+    #[fail(display = "{} must be at least one", _0)]
+    Zero(&'static str),
+
+    /// The input could not be read.
+    #[fail(display = "{}", _0)]
+    Io(#[cause] io::Error),
+
+    /// The input is not valid CSV or SQL.
+    #[fail(display = "line {}: {}", _0, _1)]
+    Malformed(usize, String),
+
+    /// An `INSERT` statement named a column that the table does not have.
+    #[fail(display = "no column named '{}'", _0)]
+    NoSuchColumn(String),
+
+    /// The rows could not be written to the table.
+    #[fail(display = "{}", _0)]
+    Table(#[cause] TableError),
+}
+
+impl From<io::Error> for BulkLoadError {
+    fn from(e: io::Error) -> Self {
+        BulkLoadError::Io(e)
+    }
+}
+
+impl From<TableError> for BulkLoadError {
+    fn from(e: TableError) -> Self {
+        BulkLoadError::Table(e)
+    }
+}
+
+/// The progress of a [`Table::bulk_load`].
+#[derive(Clone, Debug, Default)]
+pub struct BulkLoadProgress {
+    /// The number of rows sent to the table so far.
+    pub sent: usize,
+    /// The number of sent rows that the table has acknowledged.
+    pub acknowledged: usize,
+    /// The number of acknowledged rows that were not applied, because they collided with an
+    /// existing row.
+    pub rejected: usize,
+    /// A token that covers every acknowledged row, if the table hands out write tokens.
+    ///
+    /// Pass this to `View::lookup_after` to read the loaded rows.
+    pub token: WriteToken,
+}
+
+impl BulkLoadProgress {
+    fn acknowledge(&mut self, r: WriteResult) {
+        self.acknowledged += r.results.len();
+        self.rejected += r.results.iter().filter(|r| !r.is_applied()).count();
+        self.token.merge(r.token);
+    }
+}
+
+impl Table {
+    /// Insert a large number of rows into this base table.
+    ///
+    /// The rows are sent in batches of `batch_size` rows, and up to `in_flight` batches are sent
+    /// before waiting for the table to acknowledge the first of them. `progress` is called every
+    /// time a batch has been acknowledged by the table.
+    ///
+    /// The batches take a path of their own into the base: since a bulk load only ever adds rows,
+    /// the base checks each of them with a single lookup per key and adds it straight to its
+    /// state, rather than applying the batch operation by operation like it does other writes.
+    /// Batches are also never merged with other writes. The rows still reach the views of the
+    /// table like any other insert, one update per batch.
+    ///
+    /// `rows` is a stream so that waiting for rows never blocks the executor. Rows that come from
+    /// a blocking source, such as [`CsvRows`] or [`DumpReader`] over a file, should be read on a
+    /// thread of their own and passed on through a channel, like the `noria-load` binary does.
+    ///
+    /// Like any other insert, the rows are converted to the types of the table's columns, and the
+    /// load stops at the first batch that contains a row that does not fit the table. Rows sent
+    /// before that stay in the table. Rows that collide with an existing row do not stop the load,
+    /// but are counted as rejected.
+    pub async fn bulk_load<S, F>(
+        &mut self,
+        rows: S,
+        batch_size: usize,
+        in_flight: usize,
+        mut progress: F,
+    ) -> Result<BulkLoadProgress, BulkLoadError>
+    where
+        S: Stream<Item = Vec<DataType>>,
+        F: FnMut(&BulkLoadProgress),
+    {
+        if batch_size == 0 {
+            return Err(BulkLoadError::Zero("batch size"));
+        }
+        if in_flight == 0 {
+            return Err(BulkLoadError::Zero("number of batches in flight"));
+        }
+
+        let rows = rows.fuse();
+        futures_util::pin_mut!(rows);
+        let mut p = BulkLoadProgress::default();
+        let mut sent = FuturesUnordered::new();
+        loop {
+            let mut batch = Vec::new();
+            while batch.len() < batch_size {
+                match rows.next().await {
+                    Some(row) => batch.push(TableOperation::Insert(row)),
+                    None => break,
+                }
+            }
+            if batch.is_empty() {
+                break;
+            }
+
+            if sent.len() == in_flight {
+                let r = sent.try_next().await?.expect("batches are in flight");
+                p.acknowledge(r.v);
+                progress(&p);
+            }
+
+            p.sent += batch.len();
+            future::poll_fn(|cx| self.poll_ready(cx)).await?;
+            let mut i = self.prep_records(batch);
+            i.bulk = true;
+            sent.push(self.input(i));
+        }
+
+        while let Some(r) = sent.try_next().await? {
+            p.acknowledge(r.v);
+            progress(&p);
+        }
+        Ok(p)
+    }
+}
+
+/// The rows of a CSV file.
+///
+/// Fields are separated by commas (or the delimiter given to [`CsvRows::delimiter`]), and may be
+/// enclosed in double quotes, in which case they may contain delimiters, newlines, and doubled
+/// double quotes. An unquoted field that is empty or `\N` is `NULL`. All other fields are read as
+/// text, and are converted to the types of the table's columns when they are written.
+pub struct CsvRows<R> {
+    reader: R,
+    delimiter: char,
+    header: bool,
+    line: usize,
+}
+
+impl<R: BufRead> CsvRows<R> {
+    /// Read CSV rows from `reader`.
+    pub fn new(reader: R) -> Self {
+        CsvRows {
+            reader,
+            delimiter: ',',
+            header: false,
+            line: 0,
+        }
+    }
+
+    /// Separate fields by `delimiter` rather than by commas.
+    pub fn delimiter(mut self, delimiter: char) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Skip the first row of the input, which holds the names of the columns.
+    pub fn skip_header(mut self) -> Self {
+        self.header = true;
+        self
+    }
+
+    fn field(field: String, quoted: bool) -> DataType {
+        if !quoted && (field.is_empty() || field == "\\N") {
+            DataType::None
+        } else {
+            field.into()
+        }
+    }
+
+    fn read_row(&mut self) -> Result<Option<Vec<DataType>>, BulkLoadError> {
+        let mut row = Vec::new();
+        let mut field = String::new();
+        // whether the current field started with a quote, and whether that quote is still open
+        let mut quoted = false;
+        let mut in_quotes = false;
+        let mut first_line = None;
+
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                if let Some(first_line) = first_line {
+                    return Err(BulkLoadError::Malformed(
+                        first_line,
+                        String::from("unterminated quoted field"),
+                    ));
+                }
+                return Ok(None);
+            }
+            self.line += 1;
+
+            let content = line.trim_end_matches(|c| c == '\n' || c == '\r');
+            if first_line.is_none() {
+                if content.is_empty() {
+                    // skip blank lines between rows
+                    continue;
+                }
+                first_line = Some(self.line);
+            }
+
+            let mut chars = content.chars().peekable();
+            while let Some(c) = chars.next() {
+                if in_quotes {
+                    if c != '"' {
+                        field.push(c);
+                    } else if chars.peek() == Some(&'"') {
+                        chars.next();
+                        field.push('"');
+                    } else {
+                        in_quotes = false;
+                    }
+                } else if c == self.delimiter {
+                    row.push(Self::field(mem::take(&mut field), quoted));
+                    quoted = false;
+                } else if quoted {
+                    return Err(BulkLoadError::Malformed(
+                        self.line,
+                        format!("unexpected {:?} after quoted field", c),
+                    ));
+                } else if c == '"' && field.is_empty() {
+                    quoted = true;
+                    in_quotes = true;
+                } else {
+                    field.push(c);
+                }
+            }
+
+            if in_quotes {
+                // the newline is part of the quoted field
+                field.push_str(&line[content.len()..]);
+            } else {
+                row.push(Self::field(field, quoted));
+                return Ok(Some(row));
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for CsvRows<R> {
+    type Item = Result<Vec<DataType>, BulkLoadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.header {
+            self.header = false;
+            if let Err(e) = self.read_row() {
+                return Some(Err(e));
+            }
+        }
+        self.read_row().transpose()
+    }
+}
+
+/// The rows of a single `INSERT` statement of a SQL dump.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DumpInsert {
+    /// The table the rows are inserted into.
+    pub table: String,
+    /// The columns the values of each row are given for, if the statement names them.
+    pub columns: Option<Vec<String>>,
+    /// The inserted rows.
+    pub rows: Vec<Vec<DataType>>,
+}
+
+impl DumpInsert {
+    /// Arrange the values of the rows of this statement to match the columns of the table.
+    ///
+    /// Columns that the statement does not give values for are `NULL`. Like any other `NULL`
+    /// written to the table, that is replaced by the column's default value when the rows are
+    /// written if the column is `NOT NULL`, and is kept otherwise.
+    pub fn rows_for(self, columns: &[String]) -> Result<Vec<Vec<DataType>>, BulkLoadError> {
+        let given = match self.columns {
+            None => return Ok(self.rows),
+            Some(given) => given,
+        };
+
+        let mut positions = Vec::with_capacity(given.len());
+        for name in given {
+            match columns.iter().position(|c| *c == name) {
+                Some(i) => positions.push(i),
+                None => return Err(BulkLoadError::NoSuchColumn(name)),
+            }
+        }
+
+        Ok(self
+            .rows
+            .into_iter()
+            .map(|values| {
+                let mut row = vec![DataType::None; columns.len()];
+                for (&i, v) in positions.iter().zip(values) {
+                    row[i] = v;
+                }
+                row
+            })
+            .collect())
+    }
+}
+
+/// The `INSERT` statements of a SQL dump, such as one produced by `mysqldump`.
+///
+/// Comments and all other statements in the dump (`CREATE TABLE`, `LOCK TABLES`, `SET`, ...) are
+/// skipped. Numbers are read as text, and are converted to the types of the table's columns when
+/// they are written, so that decimal values are not rounded along the way.
+pub struct DumpReader<R> {
+    reader: R,
+    /// The current line of input, and how much of it has been consumed.
+    buf: Vec<u8>,
+    pos: usize,
+    line: usize,
+}
+
+impl<R: BufRead> DumpReader<R> {
+    /// Read `INSERT` statements from `reader`.
+    pub fn new(reader: R) -> Self {
+        DumpReader {
+            reader,
+            buf: Vec::new(),
+            pos: 0,
+            line: 0,
+        }
+    }
+
+    /// Read the next statement, with comments removed, along with the line it starts on.
+    fn read_statement(&mut self) -> Result<Option<(usize, Vec<u8>)>, BulkLoadError> {
+        let mut stmt = Vec::new();
+        let mut start = None;
+        let mut quote = None;
+        let mut escaped = false;
+        let mut in_comment = false;
+
+        loop {
+            if self.pos == self.buf.len() {
+                self.buf.clear();
+                self.pos = 0;
+                if self.reader.read_until(b'\n', &mut self.buf)? == 0 {
+                    if quote.is_some() {
+                        return Err(BulkLoadError::Malformed(
+                            start.unwrap_or(self.line),
+                            String::from("unterminated quoted string"),
+                        ));
+                    }
+                    // a final statement need not be terminated
+                    return Ok(start.map(|start| (start, stmt)));
+                }
+                self.line += 1;
+            }
+
+            let b = self.buf[self.pos];
+            let next = self.buf.get(self.pos + 1).cloned();
+            self.pos += 1;
+
+            if in_comment {
+                if b == b'*' && next == Some(b'/') {
+                    self.pos += 1;
+                    in_comment = false;
+                }
+                continue;
+            }
+
+            if let Some(q) = quote {
+                stmt.push(b);
+                if escaped {
+                    escaped = false;
+                } else if b == b'\\' && q != b'`' {
+                    escaped = true;
+                } else if b == q {
+                    quote = None;
+                }
+                continue;
+            }
+
+            match b {
+                b'-' if next == Some(b'-') => {
+                    let after = self.buf.get(self.pos + 1).cloned();
+                    if after.map(|c| c.is_ascii_whitespace()).unwrap_or(true) {
+                        self.pos = self.buf.len();
+                        continue;
+                    }
+                }
+                b'#' => {
+                    self.pos = self.buf.len();
+                    continue;
+                }
+                b'/' if next == Some(b'*') => {
+                    // this also skips MySQL's /*!40101 ... */ statements, which only set options
+                    self.pos += 1;
+                    in_comment = true;
+                    continue;
+                }
+                b'\'' | b'"' | b'`' => quote = Some(b),
+                b';' => {
+                    if let Some(start) = start {
+                        return Ok(Some((start, stmt)));
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            if start.is_none() {
+                if b.is_ascii_whitespace() {
+                    continue;
+                }
+                start = Some(self.line);
+            }
+            stmt.push(b);
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for DumpReader<R> {
+    type Item = Result<DumpInsert, BulkLoadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (line, stmt) = match self.read_statement() {
+                Ok(Some(stmt)) => stmt,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let mut p = StatementParser { s: &stmt, pos: 0 };
+            if !p.keyword("insert") {
+                continue;
+            }
+            return Some(
+                p.insert()
+                    .map_err(|reason| BulkLoadError::Malformed(line, reason)),
+            );
+        }
+    }
+}
+
+/// A parser for the subset of SQL that appears in the `INSERT` statements of SQL dumps.
+struct StatementParser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StatementParser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.s.len() && self.s[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.s.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+        match self.peek() {
+            Some(got) if got == c => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(got) => Err(format!("expected {:?}, found {:?}", c as char, got as char)),
+            None => Err(format!("expected {:?}, found end of statement", c as char)),
+        }
+    }
+
+    /// Consume the keyword `kw` if it comes next.
+    fn keyword(&mut self, kw: &str) -> bool {
+        self.skip_whitespace();
+        let end = self.pos + kw.len();
+        let matches = self.s.len() >= end
+            && self.s[self.pos..end].eq_ignore_ascii_case(kw.as_bytes())
+            && self
+                .s
+                .get(end)
+                .map(|&c| !c.is_ascii_alphanumeric() && c != b'_')
+                .unwrap_or(true);
+        if matches {
+            self.pos = end;
+        }
+        matches
+    }
+
+    /// Consume the bytes for which `f` holds.
+    fn take_while(&mut self, f: impl Fn(u8) -> bool) -> &'a [u8] {
+        let start = self.pos;
+        while self.pos < self.s.len() && f(self.s[self.pos]) {
+            self.pos += 1;
+        }
+        &self.s[start..self.pos]
+    }
+
+    fn identifier(&mut self) -> Result<String, String> {
+        let name = if self.peek() == Some(b'`') {
+            self.pos += 1;
+            let name = self.take_while(|c| c != b'`');
+            self.expect(b'`')?;
+            name
+        } else {
+            self.take_while(|c| c.is_ascii_alphanumeric() || c == b'_' || c == b'$')
+        };
+        if name.is_empty() {
+            return Err(String::from("expected an identifier"));
+        }
+        String::from_utf8(name.to_vec()).map_err(|_| String::from("identifier is not UTF-8"))
+    }
+
+    fn quoted(&mut self, q: u8) -> Result<Vec<u8>, String> {
+        let mut v = Vec::new();
+        self.pos += 1;
+        loop {
+            let b = match self.s.get(self.pos) {
+                Some(&b) => b,
+                None => return Err(String::from("unterminated quoted string")),
+            };
+            self.pos += 1;
+            if b == q {
+                if self.s.get(self.pos) == Some(&q) {
+                    self.pos += 1;
+                    v.push(q);
+                    continue;
+                }
+                return Ok(v);
+            }
+            if b != b'\\' {
+                v.push(b);
+                continue;
+            }
+            let e = match self.s.get(self.pos) {
+                Some(&e) => e,
+                None => return Err(String::from("unterminated quoted string")),
+            };
+            self.pos += 1;
+            match e {
+                b'0' => v.push(0),
+                b'b' => v.push(8),
+                b'n' => v.push(b'\n'),
+                b'r' => v.push(b'\r'),
+                b't' => v.push(b'\t'),
+                b'Z' => v.push(26),
+                // MySQL only treats \% and \_ as escapes in patterns
+                b'%' | b'_' => v.extend_from_slice(&[b'\\', e]),
+                e => v.push(e),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<DataType, String> {
+        match self.peek() {
+            Some(q @ b'\'') | Some(q @ b'"') => {
+                let v = self.quoted(q)?;
+                Ok(match String::from_utf8(v) {
+                    Ok(s) => s.into(),
+                    Err(e) => e.into_bytes().into(),
+                })
+            }
+            Some(b'_') if self.keyword("_binary") => {
+                let q = self.peek().filter(|&q| q == b'\'' || q == b'"');
+                let q = q.ok_or_else(|| String::from("expected a string after _binary"))?;
+                Ok(self.quoted(q)?.into())
+            }
+            Some(b'0') if self.s.get(self.pos + 1) == Some(&b'x') => {
+                self.pos += 2;
+                let hex = self.take_while(|c| c.is_ascii_hexdigit());
+                if hex.len() % 2 != 0 {
+                    return Err(String::from("odd number of hex digits"));
+                }
+                let bytes = hex
+                    .chunks(2)
+                    .map(|h| u8::from_str_radix(std::str::from_utf8(h).unwrap(), 16).unwrap())
+                    .collect::<Vec<u8>>();
+                Ok(bytes.into())
+            }
+            Some(c) if c == b'-' || c == b'+' || c == b'.' || c.is_ascii_digit() => {
+                let n = self.take_while(|c| {
+                    c.is_ascii_alphanumeric() || c == b'.' || c == b'-' || c == b'+'
+                });
+                let n = std::str::from_utf8(n).unwrap();
+                if let Ok(i) = n.parse::<i64>() {
+                    Ok(i.into())
+                } else if let Ok(i) = n.parse::<u64>() {
+                    Ok(i.into())
+                } else {
+                    // keep the exact digits around until we know the type of the column
+                    Ok(n.into())
+                }
+            }
+            _ if self.keyword("null") => Ok(DataType::None),
+            _ if self.keyword("true") => Ok(1.into()),
+            _ if self.keyword("false") => Ok(0.into()),
+            Some(c) => Err(format!("unsupported value starting with {:?}", c as char)),
+            None => Err(String::from("expected a value, found end of statement")),
+        }
+    }
+
+    /// Parse the rest of an `INSERT` statement.
+    fn insert(&mut self) -> Result<DumpInsert, String> {
+        self.keyword("ignore");
+        if !self.keyword("into") {
+            return Err(String::from("expected INTO"));
+        }
+        let mut table = self.identifier()?;
+        while self.peek() == Some(b'.') {
+            // skip the name of the database
+            self.pos += 1;
+            table = self.identifier()?;
+        }
+
+        let mut columns = None;
+        if self.peek() == Some(b'(') {
+            self.pos += 1;
+            let mut cols = vec![self.identifier()?];
+            while self.peek() == Some(b',') {
+                self.pos += 1;
+                cols.push(self.identifier()?);
+            }
+            self.expect(b')')?;
+            columns = Some(cols);
+        }
+
+        if !self.keyword("values") && !self.keyword("value") {
+            return Err(String::from("expected VALUES"));
+        }
+
+        let mut rows = Vec::new();
+        loop {
+            self.expect(b'(')?;
+            let mut row = vec![self.value()?];
+            while self.peek() == Some(b',') {
+                self.pos += 1;
+                row.push(self.value()?);
+            }
+            self.expect(b')')?;
+            rows.push(row);
+
+            if self.peek() != Some(b',') {
+                break;
+            }
+            self.pos += 1;
+        }
+
+        if self.peek().is_some() {
+            return Err(String::from(
+                "expected end of statement (ON DUPLICATE KEY UPDATE is not supported)",
+            ));
+        }
+
+        Ok(DumpInsert {
+            table,
+            columns,
+            rows,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_reads_csv() {
+        let csv = "id,name,note\n\
+                   1,alice,\n\
+                   2,\"bob, jr.\",\"says \"\"hi\"\"\"\n\
+                   \n\
+                   3,\"multi\nline\",\\N\r\n\
+                   4,\"\",x";
+        let rows: Vec<_> = CsvRows::new(csv.as_bytes())
+            .skip_header()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                vec!["1".into(), "alice".into(), DataType::None],
+                vec!["2".into(), "bob, jr.".into(), "says \"hi\"".into()],
+                vec!["3".into(), "multi\nline".into(), DataType::None],
+                vec!["4".into(), "".into(), "x".into()],
+            ]
+        );
+    }
+
+    #[test]
+    fn it_rejects_malformed_csv() {
+        let mut rows = CsvRows::new("1\t\"a\"b\n".as_bytes()).delimiter('\t');
+        match rows.next() {
+            Some(Err(BulkLoadError::Malformed(1, _))) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+
+        let mut rows = CsvRows::new("1,2\n3,\"open\n".as_bytes());
+        assert!(rows.next().unwrap().is_ok());
+        match rows.next() {
+            Some(Err(BulkLoadError::Malformed(2, _))) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn it_reads_dumps() {
+        let dump = "-- MySQL dump 10.13\n\
+                    /*!40101 SET NAMES utf8 */;\n\
+                    DROP TABLE IF EXISTS `t`;\n\
+                    CREATE TABLE `t` (`id` int, `v` text, `d` decimal(5,2));\n\
+                    LOCK TABLES `t` WRITE;\n\
+                    INSERT INTO `t` VALUES (1,'a;b',1.05),(2,'it''s \\'q\\'\\n',-0.50),\n\
+                    (3,NULL,NULL);\n\
+                    INSERT INTO db.t (d, id) VALUES (7, 4); # trailing comment\n\
+                    UNLOCK TABLES;\n\
+                    insert ignore into t values (5, 0x6869, 18446744073709551615)";
+        let stmts: Vec<_> = DumpReader::new(dump.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(stmts.len(), 3);
+
+        assert_eq!(stmts[0].table, "t");
+        assert_eq!(stmts[0].columns, None);
+        assert_eq!(
+            stmts[0].rows,
+            vec![
+                vec![1.into(), "a;b".into(), "1.05".into()],
+                vec![2.into(), "it's 'q'\n".into(), "-0.50".into()],
+                vec![3.into(), DataType::None, DataType::None],
+            ]
+        );
+
+        let columns = vec![String::from("id"), String::from("v"), String::from("d")];
+        assert_eq!(stmts[1].table, "t");
+        assert_eq!(
+            stmts[1].clone().rows_for(&columns).unwrap(),
+            vec![vec![4.into(), DataType::None, 7.into()]]
+        );
+
+        assert_eq!(
+            stmts[2].rows,
+            vec![vec![5.into(), b"hi".to_vec().into(), std::u64::MAX.into()]]
+        );
+    }
+
+    #[test]
+    fn it_rejects_malformed_dumps() {
+        let dump = "SET x = 1;\nINSERT INTO t VALUES (1, 'a'\n;";
+        match DumpReader::new(dump.as_bytes()).next() {
+            Some(Err(BulkLoadError::Malformed(2, _))) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+
+        let dump = "INSERT INTO t (a, b) VALUES (1, 2);";
+        let stmt = DumpReader::new(dump.as_bytes()).next().unwrap().unwrap();
+        match stmt.rows_for(&[String::from("a")]) {
+            Err(BulkLoadError::NoSuchColumn(ref c)) if c == "b" => {}
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+}
@@ -205,6 +205,10 @@ impl<K: Eq + Hash + Clone, T> ChannelCoordinator<K, T> {
 
     pub fn insert_remote(&self, key: K, addr: SocketAddr) {
         let mut inner = self.inner.write().unwrap();
+        if inner.addrs.get(&key).map_or(false, |&old| old != addr) {
+            // the domain has moved away, and its local channel goes nowhere
+            inner.locals.remove(&key);
+        }
         inner.addrs.insert(key, addr);
     }
 
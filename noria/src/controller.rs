@@ -1,8 +1,14 @@
 use crate::consensus::{self, Authority};
 use crate::debug::stats;
-use crate::table::{Table, TableBuilder, TableRpc};
-use crate::view::{View, ViewBuilder, ViewRpc};
-use crate::ActivationResult;
+use crate::internal::DomainIndex;
+use crate::snapshot::{Snapshot, SnapshotError, SnapshotPage};
+use crate::table::{Refetch, Refetching, Table, TableBuilder, TableRpc, WriteResult, WriteToken};
+use crate::transaction::Transaction;
+use crate::view::{ChangeCursor, Changes, View, ViewBuilder, ViewRpc};
+use crate::{
+    ActivationResult, DrainError, MoveDomainError, OperationResult, RecipeError, ReshardError,
+    TransactionError, WorkerState,
+};
 use failure::{self, ResultExt};
 use futures_util::future;
 use petgraph::graph::NodeIndex;
@@ -339,6 +345,25 @@ impl<A: Authority + 'static> ControllerHandle<A> {
             .handle
             .call(ControllerRequest::new("table_builder", &name).unwrap());
 
+        // the table fetches its builder again when its shards may have moved
+        let handle = self.handle.clone();
+        let table = name.clone();
+        let refetch: Refetch = Arc::new(move || -> Refetching {
+            let mut handle = handle.clone();
+            let req = ControllerRequest::new("table_builder", &table).unwrap();
+            Box::pin(async move {
+                future::poll_fn(|cx| handle.poll_ready(cx))
+                    .await
+                    .map_err(failure::Error::from_boxed_compat)?;
+                let body: hyper::body::Bytes = handle
+                    .call(req)
+                    .await
+                    .map_err(failure::Context::new)
+                    .context("failed to fetch table builder")?;
+                Ok::<_, failure::Error>(serde_json::from_slice(&body)?)
+            })
+        });
+
         async move {
             let body: hyper::body::Bytes = fut
                 .await
@@ -346,7 +371,7 @@ impl<A: Authority + 'static> ControllerHandle<A> {
                 .context("failed to fetch table builder")?;
 
             match serde_json::from_slice::<Option<TableBuilder>>(&body) {
-                Ok(Some(tb)) => Ok(tb.build(domains)?),
+                Ok(Some(tb)) => Ok(tb.build(domains, Some(refetch))?),
                 Ok(None) => Err(failure::err_msg("view table not exist")),
                 Err(e) => Err(failure::Error::from(e)),
             }
@@ -354,6 +379,248 @@ impl<A: Authority + 'static> ControllerHandle<A> {
         }
     }
 
+    /// Obtain a stream of the changes made to the given base table, starting at `cursor`.
+    ///
+    /// Use [`ChangeCursor::oldest`] to start at the oldest change the table still retains, or
+    /// [`Changes::cursor`] of an earlier stream to resume right where that stream left off.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn changes(
+        &mut self,
+        name: &str,
+        cursor: ChangeCursor,
+    ) -> impl Future<Output = Result<Changes, failure::Error>> {
+        let views = self.views.clone();
+        let name = name.to_string();
+        let fut = self
+            .handle
+            .call(ControllerRequest::new("changes_builder", &name).unwrap());
+
+        async move {
+            let body: hyper::body::Bytes = fut
+                .await
+                .map_err(failure::Context::new)
+                .context("failed to fetch change log builder")?;
+
+            match serde_json::from_slice::<Option<ViewBuilder>>(&body) {
+                Ok(Some(vb)) => Ok(vb.build(views)?.into_changes(cursor)?),
+                Ok(None) => Err(failure::err_msg("table does not exist")),
+                Err(e) => Err(failure::Error::from(e)),
+            }
+            .map_err(move |e| e.context(format!("following changes to {}", name)).into())
+        }
+    }
+
+    /// Take a snapshot of the current contents of the given base table or view.
+    ///
+    /// Only base tables and fully materialized views hold all of their rows, so for partially
+    /// materialized views, this fails with [`SnapshotError::NotMaterialized`]. See [`Snapshot`]
+    /// for which writes a snapshot reflects.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn snapshot(
+        &mut self,
+        name: &str,
+    ) -> impl Future<Output = Result<Snapshot, failure::Error>> {
+        let handle = self.clone();
+        let fut = self.rpc::<_, Result<(Vec<String>, SnapshotPage), SnapshotError>>(
+            "snapshot",
+            name,
+            "failed to take snapshot",
+        );
+
+        async move {
+            let (columns, first) = fut.await??;
+            let id = first.id;
+            let rows = futures_util::stream::unfold(
+                Some((handle, Some(first))),
+                move |state| async move {
+                    let (mut handle, page) = state?;
+                    let page = match page {
+                        Some(page) => page,
+                        None => {
+                            if let Err(e) = handle.ready().await {
+                                return Some((Err(e), None));
+                            }
+                            match handle
+                                .rpc::<_, Result<SnapshotPage, SnapshotError>>(
+                                    "snapshot_page",
+                                    id,
+                                    "failed to read snapshot",
+                                )
+                                .await
+                            {
+                                Ok(Ok(page)) => page,
+                                Ok(Err(e)) => return Some((Err(e.into()), None)),
+                                Err(e) => return Some((Err(e), None)),
+                            }
+                        }
+                    };
+
+                    if page.done && page.rows.is_empty() {
+                        return None;
+                    }
+                    let next = if page.done {
+                        None
+                    } else {
+                        Some((handle, None))
+                    };
+                    Some((Ok(page.rows), next))
+                },
+            );
+
+            Ok(Snapshot {
+                columns,
+                inner: Box::pin(rows),
+            })
+        }
+    }
+
+    /// Split the given base table or view, and everything downstream of it, across `shards`
+    /// shards.
+    ///
+    /// A base table without a primary key is sharded by `column` if one is given. Otherwise the
+    /// shard key is chosen the same way as when the table or view was first added. Tables keep
+    /// their rows, and both reads and writes are served throughout. Existing `Table` handles keep
+    /// working, as do `View` handles for views whose own shards did not change, but transactions
+    /// through `Table` handles obtained before fail with `TransactionError::StaleTable`.
+    ///
+    /// The new sharding is persisted, so it survives a restart of the controller.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn reshard(
+        &mut self,
+        name: &str,
+        column: Option<&str>,
+        shards: usize,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        let fut = self.rpc::<_, Result<(), ReshardError>>(
+            "reshard",
+            (name, column, shards),
+            "failed to reshard table",
+        );
+
+        async move { Ok(fut.await??) }
+    }
+
+    /// List the workers of this deployment, along with their state and how long ago their last
+    /// heartbeat was.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn instances(
+        &mut self,
+    ) -> impl Future<Output = Result<Vec<(SocketAddr, WorkerState, Duration)>, failure::Error>>
+    {
+        self.rpc("instances", (), "failed to list workers")
+    }
+
+    /// Move all the domains off the worker at `worker`, and stop giving it new ones.
+    ///
+    /// This returns once the worker holds no more domains, at which point `Self::instances`
+    /// reports it as [`WorkerState::Drained`], and it can be shut down without the rest of the
+    /// deployment having to recover from its failure. Domains are moved as with
+    /// `Self::move_domain`, all at once, and the copies they leave behind on the worker are shut
+    /// down once the moved domains have taken over. If some of them are too busy to move, or
+    /// their old copies cannot be shut down yet, this fails with `DrainError::StillHoldsDomains`,
+    /// and the worker stays draining; draining it again moves the rest.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn drain(
+        &mut self,
+        worker: SocketAddr,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        let fut = self.rpc::<_, Result<(), DrainError>>("drain", worker, "failed to drain worker");
+
+        async move { Ok(fut.await??) }
+    }
+
+    /// Stop draining the worker at `worker`, so that it may be given new domains again.
+    ///
+    /// Domains already moved off the worker stay where they are.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn cancel_drain(
+        &mut self,
+        worker: SocketAddr,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        let fut = self.rpc::<_, Result<(), DrainError>>(
+            "cancel_drain",
+            worker,
+            "failed to stop draining worker",
+        );
+
+        async move { Ok(fut.await??) }
+    }
+
+    /// Move the given shard of a domain to the worker at `worker`.
+    ///
+    /// The state of the shard is handed over to a copy of it on the new worker, except for
+    /// partially materialized state, which is filled again as it is read. Writes in flight to the
+    /// shard are passed on to the copy, and existing `Table` handles keep working as long as the
+    /// old worker does. Existing `View` handles for views the shard holds stop working, and must
+    /// be fetched again. Moving fails with `MoveDomainError::Busy` if the shard is in the middle
+    /// of something it cannot hand over, such as a replay or a transaction.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn move_domain(
+        &mut self,
+        domain: DomainIndex,
+        shard: usize,
+        worker: SocketAddr,
+    ) -> impl Future<Output = Result<(), failure::Error>> {
+        let fut = self.rpc::<_, Result<(), MoveDomainError>>(
+            "move_domain",
+            (domain, shard, worker),
+            "failed to move domain",
+        );
+
+        async move { Ok(fut.await??) }
+    }
+
+    /// Start a new transaction that can write to any number of base tables.
+    ///
+    /// See [`Transaction`] for what guarantees a transaction provides.
+    pub fn transaction(&self) -> Transaction {
+        Transaction::new()
+    }
+
+    /// Apply all the operations of the given transaction, or none of them.
+    ///
+    /// Every operation is first checked against the columns of its table, and then against the
+    /// rows of its table. If any operation cannot be applied, say because it would violate a
+    /// unique key, or because its condition does not hold, none of the transaction's writes are
+    /// applied, and the operations that could have been applied are reported as
+    /// [`OperationResult::Aborted`](crate::OperationResult::Aborted). Once this returns, the
+    /// writes have been applied by every base table involved, but, just like regular writes, they
+    /// may not yet be reflected in all views. Pass the returned token to `View::lookup_after` to
+    /// wait until they are.
+    ///
+    /// The result of each operation is reported in the order the operations were added.
+    ///
+    /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
+    pub fn commit(
+        &mut self,
+        txn: Transaction,
+    ) -> impl Future<Output = Result<WriteResult, failure::Error>> {
+        let prepared = txn.prepare();
+        let fut = prepared.as_ref().ok().map(|(writes, _)| {
+            self.rpc::<_, Result<(WriteToken, Vec<Vec<Vec<OperationResult>>>), TransactionError>>(
+                "commit_transaction",
+                writes,
+                "failed to commit transaction",
+            )
+        });
+
+        async move {
+            let (_, order) = prepared?;
+            let (token, results) = fut.unwrap().await??;
+            Ok(WriteResult {
+                token,
+                results: order.arrange(results),
+            })
+        }
+    }
+
     #[doc(hidden)]
     pub fn rpc<Q: Serialize, R: 'static>(
         &mut self,
@@ -388,22 +655,40 @@ impl<A: Authority + 'static> ControllerHandle<A> {
 
     /// Extend the existing recipe with the given set of queries.
     ///
+    /// If any of the queries cannot be added, none of them are, and the returned error is a
+    /// [`RecipeError`] that names the offending query.
+    ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
     pub fn extend_recipe(
         &mut self,
         recipe_addition: &str,
     ) -> impl Future<Output = Result<ActivationResult, failure::Error>> {
-        self.rpc("extend_recipe", recipe_addition, "failed to extend recipe")
+        let fut = self.rpc::<_, Result<ActivationResult, RecipeError>>(
+            "extend_recipe",
+            recipe_addition,
+            "failed to extend recipe",
+        );
+
+        async move { Ok(fut.await??) }
     }
 
     /// Replace the existing recipe with this one.
     ///
+    /// If the new recipe cannot be applied, the existing one stays in place, and the returned
+    /// error is a [`RecipeError`] that names the offending query.
+    ///
     /// `Self::poll_ready` must have returned `Async::Ready` before you call this method.
     pub fn install_recipe(
         &mut self,
         new_recipe: &str,
     ) -> impl Future<Output = Result<ActivationResult, failure::Error>> {
-        self.rpc("install_recipe", new_recipe, "failed to install recipe")
+        let fut = self.rpc::<_, Result<ActivationResult, RecipeError>>(
+            "install_recipe",
+            new_recipe,
+            "failed to install recipe",
+        );
+
+        async move { Ok(fut.await??) }
     }
 
     /// Fetch a graphviz description of the dataflow graph.
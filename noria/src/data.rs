@@ -1,13 +1,16 @@
 use arccstr::ArcCStr;
 
-use chrono::{self, NaiveDate, NaiveDateTime};
+use chrono::{self, NaiveDate, NaiveDateTime, Timelike};
 
-use nom_sql::Literal;
+use nom_sql::{Literal, SqlType};
 
 use std::convert::TryFrom;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::ops::{Add, Div, Mul, Sub};
+use std::sync::Arc;
+
+use crate::Decimal;
 
 const FLOAT_PRECISION: f64 = 1_000_000_000.0;
 const TINYTEXT_WIDTH: usize = 15;
@@ -42,6 +45,37 @@ pub enum DataType {
     TinyText([u8; TINYTEXT_WIDTH]),
     /// A timestamp for date/time types.
     Timestamp(NaiveDateTime),
+    /// A lossless fixed-point decimal value.
+    Decimal(Arc<Decimal>),
+    /// A calendar date without a time.
+    Date(NaiveDate),
+    /// A time of day or a time interval, in microseconds. Like MySQL's `TIME`, this may be
+    /// negative and may exceed 24 hours.
+    Time(i64),
+    /// A boolean value.
+    Bool(bool),
+    /// A reference-counted array of arbitrary bytes.
+    ByteArray(Arc<Vec<u8>>),
+    /// A reference-counted JSON document, stored in its canonical textual form.
+    Json(ArcCStr),
+}
+
+fn fmt_time(f: &mut fmt::Formatter<'_>, micros: i64) -> fmt::Result {
+    let sign = if micros < 0 { "-" } else { "" };
+    let micros = micros.unsigned_abs();
+    let secs = micros / 1_000_000;
+    write!(
+        f,
+        "{}{:02}:{:02}:{:02}",
+        sign,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )?;
+    if micros % 1_000_000 != 0 {
+        write!(f, ".{:06}", micros % 1_000_000)?;
+    }
+    Ok(())
 }
 
 impl fmt::Display for DataType {
@@ -66,6 +100,18 @@ impl fmt::Display for DataType {
                 }
             }
             DataType::Timestamp(ts) => write!(f, "{}", ts.format("%c")),
+            DataType::Decimal(ref d) => write!(f, "{}", d),
+            DataType::Date(d) => write!(f, "{}", d.format("%Y-%m-%d")),
+            DataType::Time(t) => fmt_time(f, t),
+            DataType::Bool(b) => write!(f, "{}", b),
+            DataType::ByteArray(ref bytes) => {
+                write!(f, "X'")?;
+                for b in bytes.iter() {
+                    write!(f, "{:02X}", b)?;
+                }
+                write!(f, "'")
+            }
+            DataType::Json(ref json) => write!(f, "{}", json.to_str().unwrap()),
         }
     }
 }
@@ -88,6 +134,12 @@ impl fmt::Debug for DataType {
             DataType::UnsignedInt(n) => write!(f, "UnsignedInt({})", n),
             DataType::BigInt(n) => write!(f, "BigInt({})", n),
             DataType::UnsignedBigInt(n) => write!(f, "UnsignedBigInt({})", n),
+            DataType::Decimal(ref d) => write!(f, "Decimal({})", d),
+            DataType::Date(d) => write!(f, "Date({:?})", d),
+            DataType::Time(..) => write!(f, "Time({})", self),
+            DataType::Bool(b) => write!(f, "Bool({})", b),
+            DataType::ByteArray(ref bytes) => write!(f, "ByteArray({:?})", bytes),
+            DataType::Json(ref json) => write!(f, "Json({:?})", json.to_str().unwrap()),
         }
     }
 }
@@ -100,6 +152,9 @@ impl DataType {
     pub fn deep_clone(&self) -> Self {
         match *self {
             DataType::Text(ref cstr) => DataType::Text(ArcCStr::from(&**cstr)),
+            DataType::Decimal(ref d) => DataType::Decimal(Arc::new(**d)),
+            DataType::ByteArray(ref bytes) => DataType::ByteArray(Arc::new((**bytes).clone())),
+            DataType::Json(ref json) => DataType::Json(ArcCStr::from(&**json)),
             ref dt => dt.clone(),
         }
     }
@@ -144,6 +199,260 @@ impl DataType {
             _ => false,
         }
     }
+
+    /// Parse `json` into a `DataType::Json`, canonicalizing its textual form so that equal
+    /// documents compare and hash equal regardless of their formatting.
+    pub fn parse_json(json: &str) -> Result<Self, serde_json::Error> {
+        let v: serde_json::Value = serde_json::from_str(json)?;
+        Ok(v.into())
+    }
+
+    /// Checks if this value is a number, which can be compared by value with any other number.
+    fn is_number(&self) -> bool {
+        match *self {
+            DataType::Int(..)
+            | DataType::UnsignedInt(..)
+            | DataType::BigInt(..)
+            | DataType::UnsignedBigInt(..)
+            | DataType::Real(..)
+            | DataType::Decimal(..) => true,
+            _ => false,
+        }
+    }
+
+    /// The rank of this value's type when ordering values of different types.
+    ///
+    /// All numbers share a rank, since they are ordered by value.
+    fn type_rank(&self) -> u8 {
+        match *self {
+            DataType::Bool(..) => 0,
+            DataType::Int(..)
+            | DataType::UnsignedInt(..)
+            | DataType::BigInt(..)
+            | DataType::UnsignedBigInt(..)
+            | DataType::Real(..)
+            | DataType::Decimal(..) => 1,
+            DataType::Text(..) | DataType::TinyText(..) => 2,
+            DataType::ByteArray(..) => 3,
+            DataType::Json(..) => 4,
+            DataType::Date(..) => 5,
+            DataType::Time(..) => 6,
+            DataType::Timestamp(..) => 7,
+            DataType::None => 8,
+        }
+    }
+}
+
+impl DataType {
+    /// Coerce this value into one that can be stored in a column of type `ty`.
+    ///
+    /// Values that already have the column's type are kept as-is, while values that have a
+    /// lossless representation in that type (e.g., the text `"42"` in an `INT` column, or the
+    /// integer `42` in a `DOUBLE` column) are converted. Decimals are rounded to the column's
+    /// scale. Returns `None` if the value cannot be stored in the column, either because of its
+    /// type or because it does not fit within the column's range or width. `DataType::None` can
+    /// be stored in any column; `NOT NULL` constraints must be checked separately.
+    pub fn coerce_to(&self, ty: &SqlType) -> Option<DataType> {
+        if self.is_none() {
+            return Some(DataType::None);
+        }
+
+        match *ty {
+            SqlType::Bool => match *self {
+                DataType::Bool(_) => Some(self.clone()),
+                _ => match self.coerce_to_integer(0, 1)? {
+                    0 => Some(DataType::Bool(false)),
+                    _ => Some(DataType::Bool(true)),
+                },
+            },
+            SqlType::Tinyint(_) => self
+                .coerce_to_integer(i128::from(std::i8::MIN), i128::from(std::i8::MAX))
+                .map(|i| DataType::Int(i as i32)),
+            SqlType::UnsignedTinyint(_) => self
+                .coerce_to_integer(0, i128::from(std::u8::MAX))
+                .map(|i| DataType::UnsignedInt(i as u32)),
+            SqlType::Int(_) => self
+                .coerce_to_integer(i128::from(std::i32::MIN), i128::from(std::i32::MAX))
+                .map(|i| DataType::Int(i as i32)),
+            SqlType::UnsignedInt(_) => self
+                .coerce_to_integer(0, i128::from(std::u32::MAX))
+                .map(|i| DataType::UnsignedInt(i as u32)),
+            SqlType::Bigint(_) => self
+                .coerce_to_integer(i128::from(std::i64::MIN), i128::from(std::i64::MAX))
+                .map(|i| DataType::BigInt(i as i64)),
+            SqlType::UnsignedBigint(_) => self
+                .coerce_to_integer(0, i128::from(std::u64::MAX))
+                .map(|i| DataType::UnsignedBigInt(i as u64)),
+            SqlType::Double | SqlType::Float | SqlType::Real => match *self {
+                DataType::Real(..) => Some(self.clone()),
+                DataType::Int(..)
+                | DataType::UnsignedInt(..)
+                | DataType::BigInt(..)
+                | DataType::UnsignedBigInt(..) => Some(DataType::from(i128::from(self) as f64)),
+                DataType::Decimal(ref d) => Some(DataType::from(d.to_f64())),
+                DataType::Text(..) | DataType::TinyText(..) => {
+                    let s: &str = self.into();
+                    s.trim()
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|f| f.is_finite())
+                        .map(DataType::from)
+                }
+                _ => None,
+            },
+            SqlType::Decimal(precision, scale) => {
+                let d = match *self {
+                    DataType::Decimal(ref d) => **d,
+                    DataType::Int(..)
+                    | DataType::UnsignedInt(..)
+                    | DataType::BigInt(..)
+                    | DataType::UnsignedBigInt(..)
+                    | DataType::Real(..) => self.into(),
+                    DataType::Text(..) | DataType::TinyText(..) => {
+                        let s: &str = self.into();
+                        s.parse().ok()?
+                    }
+                    _ => return None,
+                };
+                let d = d.round_to(scale)?;
+                if d.integral_digits() > precision.saturating_sub(scale) {
+                    return None;
+                }
+                Some(d.into())
+            }
+            SqlType::Char(width) | SqlType::Varchar(width) => {
+                let s = self.coerce_to_text()?;
+                let text: &str = (&s).into();
+                if text.chars().count() > usize::from(width) {
+                    return None;
+                }
+                Some(s)
+            }
+            SqlType::Tinytext | SqlType::Text | SqlType::Mediumtext | SqlType::Longtext => {
+                if let DataType::Json(..) = *self {
+                    return Some(self.clone());
+                }
+                let s = self.coerce_to_text()?;
+                let text: &str = (&s).into();
+                if text.len() > max_width(ty) {
+                    return None;
+                }
+                Some(s)
+            }
+            SqlType::Tinyblob
+            | SqlType::Blob
+            | SqlType::Mediumblob
+            | SqlType::Longblob
+            | SqlType::Binary(_)
+            | SqlType::Varbinary(_) => {
+                let bytes = match *self {
+                    DataType::ByteArray(ref bytes) => bytes.clone(),
+                    DataType::Text(..) | DataType::TinyText(..) => {
+                        let s: &str = self.into();
+                        Arc::new(s.as_bytes().to_vec())
+                    }
+                    _ => return None,
+                };
+                if bytes.len() > max_width(ty) {
+                    return None;
+                }
+                Some(DataType::ByteArray(bytes))
+            }
+            SqlType::Date => match *self {
+                DataType::Date(_) => Some(self.clone()),
+                DataType::Timestamp(ts) => Some(DataType::Date(ts.date())),
+                DataType::Text(..) | DataType::TinyText(..) => {
+                    let s: &str = self.into();
+                    NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d")
+                        .ok()
+                        .map(DataType::Date)
+                }
+                _ => None,
+            },
+            SqlType::DateTime(_) | SqlType::Timestamp => match *self {
+                DataType::Timestamp(_) => Some(self.clone()),
+                DataType::Date(d) => Some(DataType::Timestamp(d.and_hms(0, 0, 0))),
+                DataType::Text(..) | DataType::TinyText(..) => {
+                    let s: &str = self.into();
+                    let s = s.trim();
+                    NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f")
+                        .or_else(|_| {
+                            NaiveDate::parse_from_str(s, "%Y-%m-%d").map(|d| d.and_hms(0, 0, 0))
+                        })
+                        .ok()
+                        .map(DataType::Timestamp)
+                }
+                _ => None,
+            },
+            SqlType::Enum(ref variants) => match *self {
+                DataType::Text(..) | DataType::TinyText(..) => {
+                    let s: &str = self.into();
+                    if variants.iter().any(|v| match *v {
+                        Literal::String(ref v) => v == s,
+                        _ => false,
+                    }) {
+                        Some(self.clone())
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            },
+        }
+    }
+
+    /// Coerce this value into an integer in the range `[min, max]`.
+    fn coerce_to_integer(&self, min: i128, max: i128) -> Option<i128> {
+        let i = match *self {
+            DataType::Int(..)
+            | DataType::UnsignedInt(..)
+            | DataType::BigInt(..)
+            | DataType::UnsignedBigInt(..) => i128::from(self),
+            DataType::Bool(b) => i128::from(b),
+            DataType::Real(i, 0) => i128::from(i),
+            DataType::Decimal(ref d) => {
+                let i = d.round_to(0)?;
+                if **d != i {
+                    // would lose the fractional part
+                    return None;
+                }
+                i.mantissa()
+            }
+            DataType::Text(..) | DataType::TinyText(..) => {
+                let s: &str = self.into();
+                s.trim().parse().ok()?
+            }
+            _ => return None,
+        };
+        if i < min || i > max {
+            return None;
+        }
+        Some(i)
+    }
+
+    /// Coerce this value into text, if it has a canonical textual representation.
+    fn coerce_to_text(&self) -> Option<DataType> {
+        match *self {
+            DataType::Text(..) | DataType::TinyText(..) => Some(self.clone()),
+            DataType::Int(n) => Some(n.to_string().into()),
+            DataType::UnsignedInt(n) => Some(n.to_string().into()),
+            DataType::BigInt(n) => Some(n.to_string().into()),
+            DataType::UnsignedBigInt(n) => Some(n.to_string().into()),
+            DataType::Decimal(ref d) => Some(d.to_string().into()),
+            _ => None,
+        }
+    }
+}
+
+/// The maximum number of bytes that can be stored in a column of type `ty`.
+fn max_width(ty: &SqlType) -> usize {
+    match *ty {
+        SqlType::Binary(width) | SqlType::Varbinary(width) => usize::from(width),
+        SqlType::Tinytext | SqlType::Tinyblob => (1 << 8) - 1,
+        SqlType::Text | SqlType::Blob => (1 << 16) - 1,
+        SqlType::Mediumtext | SqlType::Mediumblob => (1 << 24) - 1,
+        _ => std::usize::MAX,
+    }
 }
 
 impl PartialEq for DataType {
@@ -191,7 +500,14 @@ impl PartialEq for DataType {
             }
             (&DataType::Real(ai, af), &DataType::Real(bi, bf)) => ai == bi && af == bf,
             (&DataType::Timestamp(tsa), &DataType::Timestamp(tsb)) => tsa == tsb,
+            (&DataType::Decimal(ref a), &DataType::Decimal(ref b)) => a == b,
+            (&DataType::Date(a), &DataType::Date(b)) => a == b,
+            (&DataType::Time(a), &DataType::Time(b)) => a == b,
+            (&DataType::Bool(a), &DataType::Bool(b)) => a == b,
+            (&DataType::ByteArray(ref a), &DataType::ByteArray(ref b)) => a == b,
+            (&DataType::Json(ref a), &DataType::Json(ref b)) => a == b,
             (&DataType::None, &DataType::None) => true,
+            (a, b) if a.is_number() && b.is_number() => Decimal::from(a) == Decimal::from(b),
 
             _ => false,
         }
@@ -240,17 +556,17 @@ impl Ord for DataType {
                 ai.cmp(bi).then_with(|| af.cmp(bf))
             }
             (&DataType::Timestamp(tsa), &DataType::Timestamp(ref tsb)) => tsa.cmp(tsb),
+            (&DataType::Decimal(ref a), &DataType::Decimal(ref b)) => a.cmp(b),
+            (&DataType::Date(a), &DataType::Date(ref b)) => a.cmp(b),
+            (&DataType::Time(a), &DataType::Time(ref b)) => a.cmp(b),
+            (&DataType::Bool(a), &DataType::Bool(ref b)) => a.cmp(b),
+            (&DataType::ByteArray(ref a), &DataType::ByteArray(ref b)) => a.cmp(b),
+            (&DataType::Json(ref a), &DataType::Json(ref b)) => a.cmp(b),
             (&DataType::None, &DataType::None) => Ordering::Equal,
+            (a, b) if a.is_number() && b.is_number() => Decimal::from(a).cmp(&Decimal::from(b)),
 
-            // order Ints, Reals, Text, Timestamps, None
-            (&DataType::Int(..), _)
-            | (&DataType::UnsignedInt(..), _)
-            | (&DataType::BigInt(..), _)
-            | (&DataType::UnsignedBigInt(..), _) => Ordering::Greater,
-            (&DataType::Real(..), _) => Ordering::Greater,
-            (&DataType::Text(..), _) | (&DataType::TinyText(..), _) => Ordering::Greater,
-            (&DataType::Timestamp(..), _) => Ordering::Greater,
-            (&DataType::None, _) => Ordering::Greater,
+            // order Bools, numbers, Text, ByteArrays, Json, Dates, Times, Timestamps, None
+            _ => self.type_rank().cmp(&other.type_rank()),
         }
     }
 }
@@ -270,15 +586,24 @@ impl Hash for DataType {
                 let n: u64 = self.into();
                 n.hash(state)
             }
-            DataType::Real(i, f) => {
-                i.hash(state);
-                f.hash(state);
-            }
+            // numbers that are equal must hash the same, whatever their types
+            DataType::Real(i, 0) => i.hash(state),
+            DataType::Real(..) => Decimal::from(self).hash(state),
             DataType::Text(..) | DataType::TinyText(..) => {
                 let t: &str = self.into();
                 t.hash(state)
             }
             DataType::Timestamp(ts) => ts.hash(state),
+            DataType::Decimal(ref d) => match d.to_integer() {
+                Some(i) if i as i64 as i128 == i => (i as i64).hash(state),
+                Some(i) if i as u64 as i128 == i => (i as u64).hash(state),
+                _ => d.hash(state),
+            },
+            DataType::Date(d) => d.hash(state),
+            DataType::Time(t) => t.hash(state),
+            DataType::Bool(b) => b.hash(state),
+            DataType::ByteArray(ref bytes) => bytes.hash(state),
+            DataType::Json(ref json) => json.to_bytes().hash(state),
         }
     }
 }
@@ -372,13 +697,35 @@ impl<'a> From<&'a Literal> for DataType {
         match *l {
             Literal::Null => DataType::None,
             Literal::Integer(i) => (i as i64).into(),
+            Literal::UnsignedInteger(i) => i.into(),
             Literal::String(ref s) => s.as_str().into(),
+            Literal::Blob(ref b) => b.clone().into(),
             Literal::CurrentTimestamp => {
                 let ts = chrono::Local::now().naive_local();
                 DataType::Timestamp(ts)
             }
+            Literal::CurrentDate => DataType::Date(chrono::Local::today().naive_local()),
+            Literal::CurrentTime => {
+                let t = chrono::Local::now().time();
+                DataType::Time(
+                    i64::from(t.num_seconds_from_midnight()) * 1_000_000
+                        + i64::from(t.nanosecond() / 1_000),
+                )
+            }
             Literal::FixedPoint(ref r) => {
-                DataType::Real(i64::from(r.integral), r.fractional as i32)
+                // nom-sql keeps the digits after the decimal point as an integer
+                let fractional = i128::from(r.fractional.abs());
+                let mut scale = 1;
+                while fractional >= 10i128.pow(scale) {
+                    scale += 1;
+                }
+                let integral = i128::from(r.integral) * 10i128.pow(scale);
+                let mantissa = if r.integral < 0 || r.fractional < 0 {
+                    integral - fractional
+                } else {
+                    integral + fractional
+                };
+                Decimal::new(mantissa, scale as u8).into()
             }
             _ => unimplemented!(),
         }
@@ -397,6 +744,37 @@ impl From<NaiveDateTime> for DataType {
     }
 }
 
+impl From<NaiveDate> for DataType {
+    fn from(d: NaiveDate) -> Self {
+        DataType::Date(d)
+    }
+}
+
+impl From<Decimal> for DataType {
+    fn from(d: Decimal) -> Self {
+        DataType::Decimal(Arc::new(d))
+    }
+}
+
+impl From<bool> for DataType {
+    fn from(b: bool) -> Self {
+        DataType::Bool(b)
+    }
+}
+
+impl From<Vec<u8>> for DataType {
+    fn from(bytes: Vec<u8>) -> Self {
+        DataType::ByteArray(Arc::new(bytes))
+    }
+}
+
+impl From<serde_json::Value> for DataType {
+    fn from(json: serde_json::Value) -> Self {
+        // serde_json escapes NUL characters, so the serialized form is always a valid C string
+        DataType::Json(ArcCStr::try_from(&json.to_string()[..]).unwrap())
+    }
+}
+
 // This conversion has many unwraps, but all of them are expected to be safe,
 // because DataType variants (i.e. `Text` and `TinyText`) constructors are all
 // generated from valid UTF-8 strings, or the constructor fails (e.g. TryFrom &[u8]).
@@ -518,12 +896,32 @@ impl From<&'_ DataType> for f64 {
         match *data {
             DataType::Real(i, f) => i as f64 + f64::from(f) / FLOAT_PRECISION,
             DataType::Int(i) => f64::from(i),
+            DataType::UnsignedInt(i) => f64::from(i),
             DataType::BigInt(i) => i as f64,
+            DataType::UnsignedBigInt(i) => i as f64,
+            DataType::Decimal(ref d) => d.to_f64(),
             _ => panic!("attempted to convert a {:?} to an f64", data),
         }
     }
 }
 
+impl From<&'_ DataType> for Decimal {
+    fn from(data: &'_ DataType) -> Self {
+        match *data {
+            DataType::Decimal(ref d) => **d,
+            DataType::Int(..)
+            | DataType::BigInt(..)
+            | DataType::UnsignedInt(..)
+            | DataType::UnsignedBigInt(..) => Decimal::new(data.into(), 0),
+            // reals are stored with nine fractional digits
+            DataType::Real(i, f) => {
+                Decimal::new(i128::from(i) * FLOAT_PRECISION as i128 + i128::from(f), 9)
+            }
+            _ => panic!("attempted to convert a {:?} to a Decimal", data),
+        }
+    }
+}
+
 impl From<String> for DataType {
     fn from(s: String) -> Self {
         DataType::try_from(s.as_bytes()).unwrap()
@@ -566,7 +964,14 @@ impl TryFrom<mysql_common::value::Value> for DataType {
 
         match v {
             Value::NULL => Ok(DataType::None),
-            Value::Bytes(v) => DataType::try_from(&v[..]),
+            Value::Bytes(v) => {
+                // anything that can't be represented as text is kept as raw bytes
+                if !v.contains(&0) && std::str::from_utf8(&v).is_ok() {
+                    DataType::try_from(&v[..])
+                } else {
+                    Ok(v.into())
+                }
+            }
             Value::Int(v) => Ok(v.into()),
             Value::UInt(v) => Ok(v.into()),
             Value::Float(v) => Ok(v.into()),
@@ -581,50 +986,131 @@ impl TryFrom<mysql_common::value::Value> for DataType {
                     ),
                 ))
             }
-            Value::Time(..) => Err("`mysql_common::value::Value::time` is not supported in Noria"),
+            Value::Time(negative, days, hours, minutes, seconds, micros) => {
+                let secs = i64::from(days) * 86_400
+                    + i64::from(hours) * 3600
+                    + i64::from(minutes) * 60
+                    + i64::from(seconds);
+                let t = secs * 1_000_000 + i64::from(micros);
+                Ok(DataType::Time(if negative { -t } else { t }))
+            }
         }
     }
 }
 
-// Performs an arithmetic operation on two numeric DataTypes,
-// returning a new DataType as the result.
-macro_rules! arithmetic_operation (
-    ($op:tt, $first:ident, $second:ident) => (
+// Performs an arithmetic operation on two DataTypes, returning a new DataType as the result, or
+// `None` if either operand is not a number, or if the operation overflows or divides by zero.
+// Operations on NULL yield NULL.
+macro_rules! checked_arithmetic_operation (
+    ($op:tt, $checked:ident, $first:ident, $second:ident) => (
         match ($first, $second) {
-            (&DataType::None, _) | (_, &DataType::None) => DataType::None,
-            (&DataType::Int(a), &DataType::Int(b)) => (a $op b).into(),
-            (&DataType::UnsignedInt(a), &DataType::UnsignedInt(b)) => (a $op b).into(),
-            (&DataType::BigInt(a), &DataType::BigInt(b)) => (a $op b).into(),
-            (&DataType::UnsignedBigInt(a), &DataType::UnsignedBigInt(b)) => (a $op b).into(),
-
-            (&DataType::Int(a), &DataType::BigInt(b)) => (i64::from(a) $op b).into(),
-            (&DataType::BigInt(a), &DataType::Int(b)) => (a $op i64::from(b)).into(),
-            (&DataType::Int(a), &DataType::UnsignedBigInt(b)) => (i128::from(a) $op i128::from(b)).into(),
-            (&DataType::UnsignedBigInt(a), &DataType::Int(b)) => (i128::from(a) $op i128::from(b)).into(),
-            (&DataType::BigInt(a), &DataType::UnsignedBigInt(b)) => (i128::from(a) $op i128::from(b)).into(),
-            (&DataType::UnsignedBigInt(a), &DataType::BigInt(b)) => (i128::from(a) $op i128::from(b)).into(),
-            (&DataType::UnsignedBigInt(a), &DataType::UnsignedInt(b)) => (a $op u64::from(b)).into(),
-            (&DataType::UnsignedInt(a), &DataType::UnsignedBigInt(b)) => (u64::from(a) $op b).into(),
-
-            (first @ &DataType::Int(..), second @ &DataType::Real(..)) |
-            (first @ &DataType::BigInt(..), second @ &DataType::Real(..)) |
-            (first @ &DataType::UnsignedInt(..), second @ &DataType::Real(..)) |
-            (first @ &DataType::UnsignedBigInt(..), second @ &DataType::Real(..)) |
-            (first @ &DataType::Real(..), second @ &DataType::Int(..)) |
-            (first @ &DataType::Real(..), second @ &DataType::BigInt(..)) |
-            (first @ &DataType::Real(..), second @ &DataType::UnsignedInt(..)) |
-            (first @ &DataType::Real(..), second @ &DataType::UnsignedBigInt(..)) |
-            (first @ &DataType::Real(..), second @ &DataType::Real(..)) => {
+            (&DataType::None, _) | (_, &DataType::None) => Some(DataType::None),
+            (&DataType::Int(a), &DataType::Int(b)) => a.$checked(b).map(DataType::from),
+            (&DataType::UnsignedInt(a), &DataType::UnsignedInt(b)) => a.$checked(b).map(DataType::from),
+            (&DataType::BigInt(a), &DataType::BigInt(b)) => a.$checked(b).map(DataType::from),
+            (&DataType::UnsignedBigInt(a), &DataType::UnsignedBigInt(b)) => a.$checked(b).map(DataType::from),
+
+            (first, second) if first.is_integral() && second.is_integral() => {
+                let a: i128 = first.into();
+                let b: i128 = second.into();
+                a.$checked(b).and_then(integral)
+            }
+            (first, second) if first.is_exact() && second.is_exact() => {
+                let a: Decimal = first.into();
+                let b: Decimal = second.into();
+                a.$checked(b).map(DataType::from)
+            }
+            (first, second) if first.is_number() && second.is_number() => {
                 let a: f64 = first.into();
                 let b: f64 = second.into();
-                (a $op b).into()
+                Some(a $op b).filter(|r| r.is_finite()).map(DataType::from)
             }
-            (first, second) => panic!(
+            _ => None,
+        }
+    );
+);
+
+/// The given integer as a `BigInt`, or as an `UnsignedBigInt` if it is too large for that.
+fn integral(n: i128) -> Option<DataType> {
+    if n >= i128::from(std::i64::MIN) && n <= i128::from(std::i64::MAX) {
+        Some(DataType::BigInt(n as i64))
+    } else if n >= 0 && n <= i128::from(std::u64::MAX) {
+        Some(DataType::UnsignedBigInt(n as u64))
+    } else {
+        None
+    }
+}
+
+impl DataType {
+    fn is_integral(&self) -> bool {
+        match *self {
+            DataType::Int(..)
+            | DataType::BigInt(..)
+            | DataType::UnsignedInt(..)
+            | DataType::UnsignedBigInt(..) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether this is an integer or a decimal, whose arithmetic is exact.
+    fn is_exact(&self) -> bool {
+        match *self {
+            DataType::Decimal(..) => true,
+            _ => self.is_integral(),
+        }
+    }
+
+    fn is_number(&self) -> bool {
+        match *self {
+            DataType::Real(..) => true,
+            _ => self.is_exact(),
+        }
+    }
+
+    /// `self + other`, or `None` if either is not a number or if the sum overflows.
+    ///
+    /// Adding `NULL` yields `NULL`.
+    pub fn checked_add(&self, other: &DataType) -> Option<DataType> {
+        checked_arithmetic_operation!(+, checked_add, self, other)
+    }
+
+    /// `self - other`, or `None` if either is not a number or if the difference overflows.
+    ///
+    /// Subtracting `NULL` yields `NULL`.
+    pub fn checked_sub(&self, other: &DataType) -> Option<DataType> {
+        checked_arithmetic_operation!(-, checked_sub, self, other)
+    }
+
+    /// `self * other`, or `None` if either is not a number or if the product overflows.
+    ///
+    /// Multiplying by `NULL` yields `NULL`.
+    pub fn checked_mul(&self, other: &DataType) -> Option<DataType> {
+        checked_arithmetic_operation!(*, checked_mul, self, other)
+    }
+
+    /// `self / other`, or `None` if either is not a number, if `other` is zero, or if the
+    /// quotient overflows. Dividing two integers yields an integer.
+    ///
+    /// Dividing by `NULL` yields `NULL`.
+    pub fn checked_div(&self, other: &DataType) -> Option<DataType> {
+        checked_arithmetic_operation!(/, checked_div, self, other)
+    }
+}
+
+// Performs an arithmetic operation on two numeric DataTypes, returning a new DataType as the
+// result. Operations that overflow or divide by zero yield NULL, and operations on non-numeric
+// values panic.
+macro_rules! arithmetic_operation (
+    ($op:tt, $checked:ident, $first:ident, $second:ident) => (
+        match $first.$checked($second) {
+            Some(result) => result,
+            None if $first.is_number() && $second.is_number() => DataType::None,
+            None => panic!(
                 format!(
                     "can't {} a {:?} and {:?}",
                     stringify!($op),
-                    first,
-                    second,
+                    $first,
+                    $second,
                 )
             ),
         }
@@ -635,7 +1121,7 @@ impl<'a, 'b> Add<&'b DataType> for &'a DataType {
     type Output = DataType;
 
     fn add(self, other: &'b DataType) -> DataType {
-        arithmetic_operation!(+, self, other)
+        arithmetic_operation!(+, checked_add, self, other)
     }
 }
 
@@ -643,7 +1129,7 @@ impl<'a, 'b> Sub<&'b DataType> for &'a DataType {
     type Output = DataType;
 
     fn sub(self, other: &'b DataType) -> DataType {
-        arithmetic_operation!(-, self, other)
+        arithmetic_operation!(-, checked_sub, self, other)
     }
 }
 
@@ -651,7 +1137,7 @@ impl<'a, 'b> Mul<&'b DataType> for &'a DataType {
     type Output = DataType;
 
     fn mul(self, other: &'b DataType) -> DataType {
-        arithmetic_operation!(*, self, other)
+        arithmetic_operation!(*, checked_mul, self, other)
     }
 }
 
@@ -659,26 +1145,44 @@ impl<'a, 'b> Div<&'b DataType> for &'a DataType {
     type Output = DataType;
 
     fn div(self, other: &'b DataType) -> DataType {
-        arithmetic_operation!(/, self, other)
+        arithmetic_operation!(/, checked_div, self, other)
     }
 }
 
 /// A modification to make to an existing value.
+///
+/// Arithmetic on a `NULL` value leaves it `NULL`, while the other operations treat a `NULL` value
+/// as absent, and so replace it with the given value.
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum Operation {
     /// Add the given value to the existing one.
     Add,
     /// Subtract the given value from the existing value.
     Sub,
+    /// Multiply the existing value by the given one.
+    Multiply,
+    /// Keep the smaller of the existing value and the given one.
+    Min,
+    /// Keep the larger of the existing value and the given one.
+    Max,
+    /// Append the given string (or bytes) to the existing value.
+    Append,
 }
 
 /// A modification to make to a column in an existing row.
+///
+/// All the modifications of an update are applied atomically by the base table, so they never
+/// race with other writes to the same row.
 #[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum Modification {
     /// Set the cell to this value.
     Set(DataType),
     /// Use the given [`Operation`] to combine the existing value and this one.
     Apply(Operation, DataType),
+    /// Set the cell to `NULL`.
+    SetNull,
+    /// Set the cell to this value, but only if it is currently `NULL`.
+    SetIfNull(DataType),
     /// Leave the existing value as-is.
     None,
 }
@@ -717,17 +1221,71 @@ pub enum TableOperation {
         /// The key used to identify the row to update.
         key: Vec<DataType>,
     },
+    /// Insert the contained row, unless a row with the same key already exists.
+    InsertIfAbsent(Vec<DataType>),
+    /// Update the existing row with the given `key`, but only if the columns in `expected` hold
+    /// the given values.
+    UpdateIf {
+        /// The key used to identify the row to update.
+        key: Vec<DataType>,
+        /// The value each of these columns must have for the update to be applied.
+        expected: Vec<(usize, DataType)>,
+        /// The modifications to make to each column of the existing row.
+        set: Vec<Modification>,
+    },
 }
 
 impl TableOperation {
     #[doc(hidden)]
     pub fn row(&self) -> Option<&[DataType]> {
         match *self {
-            TableOperation::Insert(ref r) => Some(r),
+            TableOperation::Insert(ref r) | TableOperation::InsertIfAbsent(ref r) => Some(r),
             TableOperation::InsertOrUpdate { ref row, .. } => Some(row),
             _ => None,
         }
     }
+
+    /// The shard of a table split into `shards` shards by column `key_col` that this operation
+    /// must go to.
+    #[doc(hidden)]
+    pub fn shard(&self, key_col: usize, shards: usize) -> usize {
+        let key = match *self {
+            TableOperation::Insert(ref r) | TableOperation::InsertIfAbsent(ref r) => &r[key_col],
+            TableOperation::Delete { ref key } => &key[0],
+            TableOperation::Update { ref key, .. } => &key[0],
+            TableOperation::UpdateIf { ref key, .. } => &key[0],
+            TableOperation::InsertOrUpdate { ref row, .. } => &row[key_col],
+        };
+        crate::shard_by(key, shards)
+    }
+}
+
+/// The outcome of a single [`TableOperation`].
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum OperationResult {
+    /// The operation was applied.
+    Applied,
+    /// The operation was not applied, since it would have left two rows with the same values in
+    /// the contained columns, which make up the primary key or a `UNIQUE` key of the table.
+    UniqueViolation(Vec<usize>),
+    /// The operation was not applied, since its condition did not hold. That is, an
+    /// `InsertIfAbsent` found an existing row with the same key, or an `UpdateIf` found a row
+    /// whose values did not match the expected ones.
+    ConditionFailed,
+    /// The operation was not applied, since there is no row with the given key.
+    NotFound,
+    /// The operation was not applied, since its values do not fit the columns of the table.
+    Invalid,
+    /// The operation was not applied, since another operation of the same transaction could not
+    /// be applied.
+    Aborted,
+}
+
+impl OperationResult {
+    /// Whether the operation was applied.
+    pub fn is_applied(&self) -> bool {
+        *self == OperationResult::Applied
+    }
 }
 
 impl From<Vec<DataType>> for TableOperation {
@@ -752,10 +1310,19 @@ mod tests {
         assert_eq!(a_dt.unwrap(), DataType::None);
 
         // Test Value::Bytes.
-        // Can't build a CString with interior nul-terminated chars.
+        // Can't build a CString with interior nul-terminated chars, so those are kept as bytes.
         let a = Value::Bytes(vec![0; 30]);
         let a_dt = DataType::try_from(a);
-        assert!(a_dt.is_err());
+        assert!(a_dt.is_ok());
+        assert_eq!(a_dt.unwrap(), DataType::ByteArray(Arc::new(vec![0; 30])));
+
+        let a = Value::Bytes(vec![0xff, 0xfe]);
+        let a_dt = DataType::try_from(a);
+        assert!(a_dt.is_ok());
+        assert_eq!(
+            a_dt.unwrap(),
+            DataType::ByteArray(Arc::new(vec![0xff, 0xfe]))
+        );
 
         let a = Value::Bytes(vec![1; 30]);
         let a_dt = DataType::try_from(a);
@@ -799,10 +1366,219 @@ mod tests {
         assert_eq!(a_dt.unwrap(), DataType::Timestamp(ts));
 
         // Test Value::Time.
-        // noria::DataType has no `Time` representation.
-        let a = Value::Time(true, 0, 0, 0, 0, 0);
+        let a = Value::Time(true, 1, 2, 3, 4, 5);
         let a_dt = DataType::try_from(a);
-        assert!(a_dt.is_err());
+        assert!(a_dt.is_ok());
+        let a_dt = a_dt.unwrap();
+        assert_eq!(
+            a_dt,
+            DataType::Time(-((26 * 3600 + 3 * 60 + 4) * 1_000_000 + 5))
+        );
+        assert_eq!(a_dt.to_string(), "-26:03:04.000005");
+    }
+
+    #[test]
+    fn new_data_types() {
+        let dec: DataType = "12.50".parse::<Decimal>().unwrap().into();
+        let date = DataType::Date(NaiveDate::from_ymd(2020, 2, 29));
+        let time = DataType::Time((12 * 3600 + 34 * 60 + 56) * 1_000_000);
+        let json = DataType::parse_json("{ \"b\": [1, 2],  \"a\": null }").unwrap();
+        let bytes = DataType::from(vec![0u8, 0xab]);
+
+        assert_eq!(format!("{}", dec), "12.50");
+        assert_eq!(format!("{:?}", dec), "Decimal(12.50)");
+        assert_eq!(format!("{}", date), "2020-02-29");
+        assert_eq!(format!("{}", time), "12:34:56");
+        assert_eq!(format!("{}", DataType::Bool(true)), "true");
+        assert_eq!(format!("{}", bytes), "X'00AB'");
+        assert_eq!(format!("{}", json), r#"{"a":null,"b":[1,2]}"#);
+
+        // decimals keep their exact value through arithmetic
+        assert_eq!(
+            &dec + &DataType::from(1),
+            "13.5".parse::<Decimal>().unwrap().into()
+        );
+        assert_eq!(
+            &dec * &DataType::from("0.1".parse::<Decimal>().unwrap()),
+            "1.25".parse::<Decimal>().unwrap().into()
+        );
+        let f: f64 = (&dec).into();
+        assert!((f - 12.5).abs() < std::f64::EPSILON);
+
+        // documents that differ only in formatting are the same document
+        assert_eq!(
+            json,
+            DataType::parse_json(r#"{"a":null,"b":[1,2]}"#).unwrap()
+        );
+        assert!(DataType::parse_json("{").is_err());
+
+        // values of different types have a total order
+        let mut all = vec![
+            DataType::None,
+            json.clone(),
+            time.clone(),
+            date.clone(),
+            bytes.clone(),
+            dec.clone(),
+            DataType::Bool(false),
+            DataType::from(1),
+            DataType::from(1.5),
+            DataType::from("hi"),
+        ];
+        all.sort();
+        assert_eq!(
+            all,
+            vec![
+                DataType::Bool(false),
+                DataType::from(1),
+                DataType::from(1.5),
+                dec.clone(),
+                DataType::from("hi"),
+                bytes.clone(),
+                json.clone(),
+                date.clone(),
+                time.clone(),
+                DataType::None,
+            ]
+        );
+        assert_eq!(DataType::from(1).cmp(&dec), Ordering::Less);
+        assert_eq!(dec.cmp(&DataType::from(1)), Ordering::Greater);
+
+        // every type survives a serde round-trip
+        for dt in all {
+            let s = serde_json::to_string(&dt).unwrap();
+            assert_eq!(serde_json::from_str::<DataType>(&s).unwrap(), dt);
+        }
+    }
+
+    #[test]
+    fn numbers_compare_by_value() {
+        let dec = |s: &str| DataType::from(s.parse::<Decimal>().unwrap());
+        let hash = |dt: &DataType| {
+            use std::collections::hash_map::DefaultHasher;
+            let mut s = DefaultHasher::new();
+            dt.hash(&mut s);
+            s.finish()
+        };
+
+        let equal = vec![
+            (DataType::from(2), DataType::from(2.0)),
+            (DataType::from(2), dec("2.00")),
+            (DataType::from(2.5), dec("2.50")),
+            (
+                DataType::UnsignedBigInt(std::u64::MAX),
+                dec("18446744073709551615"),
+            ),
+            (DataType::BigInt(-7), dec("-7")),
+        ];
+        for (a, b) in equal {
+            assert_eq!(a, b);
+            assert_eq!(b, a);
+            assert_eq!(a.cmp(&b), Ordering::Equal);
+            assert_eq!(hash(&a), hash(&b));
+        }
+
+        assert!(DataType::from(2) < dec("2.01"));
+        assert!(dec("2.01") < DataType::from(2.5));
+        assert!(DataType::from(-0.5) < DataType::UnsignedInt(0));
+        assert!(dec("-100") < DataType::from(-99.5));
+
+        // nom-sql keeps the digits after the decimal point as an integer
+        let fixed = |integral, fractional| {
+            DataType::from(Literal::FixedPoint(nom_sql::Real {
+                integral,
+                fractional,
+            }))
+        };
+        assert_eq!(fixed(1, 25), dec("1.25"));
+        assert_eq!(fixed(-3, 5), dec("-3.5"));
+        assert_eq!(fixed(0, 0), dec("0"));
+
+        // decimal arithmetic that can't be done yields NULL
+        let huge = DataType::from(Decimal::new(std::i128::MAX, 0));
+        assert_eq!(&huge + &DataType::from(1), DataType::None);
+        assert_eq!(&huge * &dec("1.5"), DataType::None);
+        assert_eq!(&dec("1.5") / &DataType::from(0), DataType::None);
+    }
+
+    #[test]
+    fn coerce_to_column_types() {
+        let dec = |s: &str| DataType::from(s.parse::<Decimal>().unwrap());
+
+        // integers
+        assert_eq!(
+            DataType::from("42").coerce_to(&SqlType::Int(32)),
+            Some(42.into())
+        );
+        assert_eq!(DataType::from("x").coerce_to(&SqlType::Int(32)), None);
+        assert_eq!(DataType::from(1.5).coerce_to(&SqlType::Int(32)), None);
+        assert_eq!(DataType::from(300).coerce_to(&SqlType::Tinyint(4)), None);
+        assert_eq!(
+            DataType::from(-1).coerce_to(&SqlType::UnsignedBigint(64)),
+            None
+        );
+        assert_eq!(
+            DataType::from(7).coerce_to(&SqlType::Bigint(64)),
+            Some(DataType::BigInt(7))
+        );
+        assert_eq!(dec("3.00").coerce_to(&SqlType::Int(32)), Some(3.into()));
+        assert_eq!(
+            DataType::from(1).coerce_to(&SqlType::Bool),
+            Some(DataType::Bool(true))
+        );
+
+        // reals and decimals
+        assert_eq!(
+            DataType::from(2).coerce_to(&SqlType::Double),
+            Some((2.0).into())
+        );
+        assert_eq!(
+            DataType::from("1.005").coerce_to(&SqlType::Decimal(5, 2)),
+            Some(dec("1.01"))
+        );
+        assert_eq!(
+            DataType::from(1000).coerce_to(&SqlType::Decimal(5, 2)),
+            None
+        );
+
+        // text and bytes
+        assert_eq!(
+            DataType::from(42).coerce_to(&SqlType::Varchar(2)),
+            Some("42".into())
+        );
+        assert_eq!(DataType::from("abc").coerce_to(&SqlType::Varchar(2)), None);
+        assert_eq!(DataType::from(1.5).coerce_to(&SqlType::Text), None);
+        assert_eq!(
+            DataType::from("ab").coerce_to(&SqlType::Blob),
+            Some(vec![b'a', b'b'].into())
+        );
+        assert_eq!(
+            DataType::from(vec![0u8; 3]).coerce_to(&SqlType::Binary(2)),
+            None
+        );
+
+        // dates and times
+        let date = NaiveDate::from_ymd(2020, 2, 29);
+        assert_eq!(
+            DataType::from("2020-02-29").coerce_to(&SqlType::Date),
+            Some(DataType::Date(date))
+        );
+        assert_eq!(
+            DataType::from("2020-02-29 12:00:00").coerce_to(&SqlType::Timestamp),
+            Some(DataType::Timestamp(date.and_hms(12, 0, 0)))
+        );
+        assert_eq!(DataType::from("yesterday").coerce_to(&SqlType::Date), None);
+
+        // enums
+        let e = SqlType::Enum(vec![Literal::String("a".into())]);
+        assert_eq!(DataType::from("a").coerce_to(&e), Some("a".into()));
+        assert_eq!(DataType::from("b").coerce_to(&e), None);
+
+        // NULL fits anywhere
+        assert_eq!(
+            DataType::None.coerce_to(&SqlType::Int(32)),
+            Some(DataType::None)
+        );
     }
 
     #[test]
@@ -868,6 +1644,24 @@ mod tests {
         assert_eq!(&DataType::BigInt(4) / &DataType::from(2), 2.into());
     }
 
+    #[test]
+    fn checked_arithmetic() {
+        let max = DataType::from(std::i32::MAX);
+        assert_eq!(max.checked_add(&1.into()), None);
+        assert_eq!(&max + &1.into(), DataType::None);
+        assert_eq!(DataType::from(1).checked_div(&0.into()), None);
+        assert_eq!(
+            DataType::UnsignedBigInt(std::u64::MAX).checked_sub(&DataType::from(1)),
+            Some(DataType::UnsignedBigInt(std::u64::MAX - 1))
+        );
+        assert_eq!(
+            DataType::from(-1).checked_mul(&DataType::UnsignedInt(2)),
+            Some((-2).into())
+        );
+        assert_eq!(DataType::from("a").checked_add(&1.into()), None);
+        assert_eq!(DataType::None.checked_add(&1.into()), Some(DataType::None));
+    }
+
     #[test]
     #[should_panic(expected = "can't + a TinyText(\"hi\") and Int(5)")]
     fn add_invalid_types() {
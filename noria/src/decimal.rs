@@ -0,0 +1,417 @@
+use serde::de::{Deserialize, Deserializer, Error};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// The largest number of digits a `Decimal` can hold, both in total and after the decimal point.
+pub const MAX_DECIMAL_DIGITS: u8 = 38;
+
+/// Division yields this many more fractional digits than the more precise of its operands, just
+/// like MySQL's default `div_precision_increment`.
+const DIV_SCALE_INCREMENT: u8 = 4;
+
+fn pow10(exp: u8) -> i128 {
+    10i128.pow(u32::from(exp))
+}
+
+/// A lossless fixed-point decimal number, as stored in SQL `DECIMAL` columns.
+///
+/// The value is `mantissa * 10^-scale`. Two decimals that differ only in their number of trailing
+/// fractional zeros (e.g., `1.5` and `1.50`) are equal, and hash the same.
+///
+/// Arithmetic on decimals goes through `checked_add` and friends, which return `None` rather than
+/// panicking when the result does not fit.
+#[derive(Clone, Copy, Serialize)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u8,
+}
+
+impl Decimal {
+    /// Construct the decimal `mantissa * 10^-scale`.
+    ///
+    /// Panics if `scale` is greater than [`MAX_DECIMAL_DIGITS`].
+    pub fn new(mantissa: i128, scale: u8) -> Self {
+        assert!(
+            scale <= MAX_DECIMAL_DIGITS,
+            "decimals can hold at most {} fractional digits",
+            MAX_DECIMAL_DIGITS
+        );
+        Decimal { mantissa, scale }
+    }
+
+    /// The digits of this decimal, without a decimal point.
+    pub fn mantissa(&self) -> i128 {
+        self.mantissa
+    }
+
+    /// The number of digits after the decimal point.
+    pub fn scale(&self) -> u8 {
+        self.scale
+    }
+
+    /// The value of this decimal, if it is a whole number.
+    pub fn to_integer(&self) -> Option<i128> {
+        let d = self.normalize();
+        if d.scale == 0 {
+            Some(d.mantissa)
+        } else {
+            None
+        }
+    }
+
+    /// Convert this decimal into the nearest `f64`.
+    pub fn to_f64(&self) -> f64 {
+        self.mantissa as f64 / pow10(self.scale) as f64
+    }
+
+    /// This decimal with any trailing fractional zeros removed.
+    fn normalize(&self) -> Self {
+        let mut d = *self;
+        while d.scale > 0 && d.mantissa % 10 == 0 {
+            d.mantissa /= 10;
+            d.scale -= 1;
+        }
+        d
+    }
+
+    /// This decimal with exactly `scale` fractional digits, if that does not overflow.
+    fn rescale(&self, scale: u8) -> Option<Self> {
+        assert!(scale >= self.scale);
+        let mantissa = self.mantissa.checked_mul(pow10(scale - self.scale))?;
+        Some(Decimal { mantissa, scale })
+    }
+
+    /// This decimal rounded (half away from zero) or padded to exactly `scale` fractional digits.
+    pub(crate) fn round_to(&self, scale: u8) -> Option<Self> {
+        if scale >= self.scale {
+            return self.rescale(scale);
+        }
+        let div = pow10(self.scale - scale);
+        let mut mantissa = self.mantissa / div;
+        let rem = (self.mantissa % div).abs();
+        if rem >= div - rem {
+            mantissa += self.mantissa.signum();
+        }
+        Some(Decimal { mantissa, scale })
+    }
+
+    /// The number of digits before the decimal point.
+    pub(crate) fn integral_digits(&self) -> u8 {
+        let mut integral = (self.mantissa / pow10(self.scale)).unsigned_abs();
+        let mut digits = 0;
+        while integral != 0 {
+            integral /= 10;
+            digits += 1;
+        }
+        digits
+    }
+
+    /// The mantissas of this decimal and `other` at their common scale, if that does not
+    /// overflow.
+    fn aligned(&self, other: &Self) -> Option<(i128, i128, u8)> {
+        let scale = self.scale.max(other.scale);
+        Some((
+            self.rescale(scale)?.mantissa,
+            other.rescale(scale)?.mantissa,
+            scale,
+        ))
+    }
+
+    /// `self + other`, or `None` if the sum overflows.
+    pub fn checked_add(self, other: Decimal) -> Option<Decimal> {
+        let (a, b, scale) = self.aligned(&other)?;
+        Some(Decimal::new(a.checked_add(b)?, scale))
+    }
+
+    /// `self - other`, or `None` if the difference overflows.
+    pub fn checked_sub(self, other: Decimal) -> Option<Decimal> {
+        let (a, b, scale) = self.aligned(&other)?;
+        Some(Decimal::new(a.checked_sub(b)?, scale))
+    }
+
+    /// `self * other`, or `None` if the product overflows.
+    ///
+    /// The product has as many fractional digits as its operands combined, but is rounded to
+    /// [`MAX_DECIMAL_DIGITS`] fractional digits if that is more than a decimal can hold.
+    pub fn checked_mul(self, other: Decimal) -> Option<Decimal> {
+        let a = self.normalize();
+        let b = other.normalize();
+        let product = Decimal {
+            mantissa: a.mantissa.checked_mul(b.mantissa)?,
+            scale: a.scale + b.scale,
+        };
+        product.round_to(product.scale.min(MAX_DECIMAL_DIGITS))
+    }
+
+    /// `self / other`, or `None` if `other` is zero or the quotient overflows.
+    ///
+    /// The quotient has [`DIV_SCALE_INCREMENT`] more fractional digits than the more precise of its
+    /// operands, but fewer if computing that many would overflow.
+    pub fn checked_div(self, other: Decimal) -> Option<Decimal> {
+        if other.mantissa == 0 {
+            return None;
+        }
+        let a = self.normalize();
+        let b = other.normalize();
+        let scale = (self.scale.max(other.scale) + DIV_SCALE_INCREMENT).min(MAX_DECIMAL_DIGITS);
+
+        // a / b = (a.m * 10^(scale - a.s + b.s)) / b.m * 10^-scale, so each fractional digit
+        // given up shifts the dividend one digit less
+        let fewest = a.scale.saturating_sub(b.scale);
+        (fewest..=scale.max(fewest)).rev().find_map(|scale| {
+            let shift = scale + b.scale - a.scale;
+            if shift > MAX_DECIMAL_DIGITS {
+                return None;
+            }
+            let mantissa = a.mantissa.checked_mul(pow10(shift))?;
+            Some(Decimal::new(mantissa.checked_div(b.mantissa)?, scale))
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename = "Decimal")]
+        struct Raw {
+            mantissa: i128,
+            scale: u8,
+        }
+
+        let Raw { mantissa, scale } = Raw::deserialize(deserializer)?;
+        if scale > MAX_DECIMAL_DIGITS {
+            return Err(D::Error::custom(format!(
+                "decimals can hold at most {} fractional digits, not {}",
+                MAX_DECIMAL_DIGITS, scale
+            )));
+        }
+        Ok(Decimal { mantissa, scale })
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(i: i64) -> Self {
+        Decimal::new(i128::from(i), 0)
+    }
+}
+
+impl From<u64> for Decimal {
+    fn from(i: u64) -> Self {
+        Decimal::new(i128::from(i), 0)
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (negative, digits) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let (integral, fractional) = match digits.find('.') {
+            Some(i) => (&digits[..i], &digits[i + 1..]),
+            None => (digits, ""),
+        };
+        if integral.is_empty() && fractional.is_empty() {
+            return Err("not a decimal number");
+        }
+        if !integral
+            .bytes()
+            .chain(fractional.bytes())
+            .all(|b| b.is_ascii_digit())
+        {
+            return Err("not a decimal number");
+        }
+        if integral.len() + fractional.len() > usize::from(MAX_DECIMAL_DIGITS) {
+            return Err("too many digits for a decimal");
+        }
+
+        let mut mantissa: i128 = 0;
+        for b in integral.bytes().chain(fractional.bytes()) {
+            mantissa = mantissa * 10 + i128::from(b - b'0');
+        }
+        if negative {
+            mantissa = -mantissa;
+        }
+        Ok(Decimal::new(mantissa, fractional.len() as u8))
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        let abs = self.mantissa.unsigned_abs();
+        if self.scale == 0 {
+            return write!(f, "{}{}", sign, abs);
+        }
+        let div = pow10(self.scale) as u128;
+        write!(
+            f,
+            "{}{}.{:0width$}",
+            sign,
+            abs / div,
+            abs % div,
+            width = usize::from(self.scale)
+        )
+    }
+}
+
+impl fmt::Debug for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // compare the integral parts first, so that aligning the fractional parts can't overflow
+        let (ad, bd) = (pow10(self.scale), pow10(other.scale));
+        let (ai, bi) = (self.mantissa / ad, other.mantissa / bd);
+        ai.cmp(&bi).then_with(|| {
+            let scale = self.scale.max(other.scale);
+            let af = (self.mantissa % ad) * pow10(scale - self.scale);
+            let bf = (other.mantissa % bd) * pow10(scale - other.scale);
+            af.cmp(&bf)
+        })
+    }
+}
+
+impl Hash for Decimal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let d = self.normalize();
+        d.mantissa.hash(state);
+        d.scale.hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn it_parses_and_displays() {
+        assert_eq!(d("12.340").to_string(), "12.340");
+        assert_eq!(d("-0.05").to_string(), "-0.05");
+        assert_eq!(d("7").to_string(), "7");
+        assert_eq!(d(".5").to_string(), "0.5");
+        assert!("1.2.3".parse::<Decimal>().is_err());
+        assert!("abc".parse::<Decimal>().is_err());
+        assert!("-".parse::<Decimal>().is_err());
+    }
+
+    #[test]
+    fn it_compares_across_scales() {
+        use std::collections::hash_map::DefaultHasher;
+        let hash = |d: &Decimal| {
+            let mut s = DefaultHasher::new();
+            d.hash(&mut s);
+            s.finish()
+        };
+
+        assert_eq!(d("1.5"), d("1.50"));
+        assert_eq!(hash(&d("1.5")), hash(&d("1.50")));
+        assert!(d("1.5") < d("1.51"));
+        assert!(d("-1.5") < d("-1.49"));
+        assert!(d("10") > d("9.99999"));
+        assert!(d("-0.1") < d("0"));
+    }
+
+    #[test]
+    fn it_does_exact_arithmetic() {
+        assert_eq!(d("0.1").checked_add(d("0.2")), Some(d("0.3")));
+        assert_eq!(d("1.5").checked_sub(d("2.25")), Some(d("-0.75")));
+        assert_eq!(d("1.5").checked_mul(d("-2.5")), Some(d("-3.75")));
+        assert_eq!(d("1").checked_div(d("3")), Some(d("0.3333")));
+        assert_eq!(
+            d("1.00").checked_div(d("8")).unwrap().to_string(),
+            "0.125000"
+        );
+    }
+
+    #[test]
+    fn it_divides_with_fewer_digits_rather_than_overflow() {
+        // 38 fractional digits would need a shift of 74 digits
+        let tiny = Decimal::new(1, 36);
+        let q = d("2").checked_div(tiny).unwrap();
+        assert_eq!(q, Decimal::new(2 * pow10(36), 0));
+        assert!(q.scale() < MAX_DECIMAL_DIGITS);
+
+        let q = d("1").checked_div(Decimal::new(pow10(36) + 1, 36)).unwrap();
+        assert_eq!(q.scale(), 2);
+        assert_eq!(q, d("0.99"));
+        assert_eq!(Decimal::new(std::i128::MAX, 0).checked_div(d("0.1")), None);
+    }
+
+    #[test]
+    fn it_rejects_deserialized_scales_that_are_too_large() {
+        let json = |mantissa: i128, scale: u8| {
+            format!("{{\"mantissa\":{},\"scale\":{}}}", mantissa, scale)
+        };
+        let ok: Decimal = serde_json::from_str(&json(125, 2)).unwrap();
+        assert_eq!(ok, d("1.25"));
+        assert_eq!(
+            serde_json::from_str::<Decimal>(&serde_json::to_string(&ok).unwrap()).unwrap(),
+            ok
+        );
+        assert!(serde_json::from_str::<Decimal>(&json(1, MAX_DECIMAL_DIGITS + 1)).is_err());
+    }
+
+    #[test]
+    fn it_checks_for_overflow() {
+        let max = Decimal::new(std::i128::MAX, 0);
+        assert_eq!(max.checked_add(d("1")), None);
+        assert_eq!(Decimal::new(std::i128::MIN, 0).checked_sub(d("1")), None);
+        assert_eq!(max.checked_mul(d("2")), None);
+        assert_eq!(d("1").checked_div(d("0")), None);
+        assert_eq!(d("1").checked_div(d("0.00")), None);
+        assert_eq!(d("0.1").checked_add(max), None);
+    }
+
+    #[test]
+    fn it_rounds_precise_products() {
+        // 20 + 20 fractional digits is more than a decimal can hold
+        let a = d("0.00000000000000000005");
+        let p = a.checked_mul(a).unwrap();
+        assert_eq!(p.scale(), MAX_DECIMAL_DIGITS);
+        assert_eq!(p, d("0"));
+
+        let b = d("1.00000000000000000001");
+        let c = d("0.00000000000000000005");
+        let p = b.checked_mul(c).unwrap();
+        assert_eq!(p.scale(), MAX_DECIMAL_DIGITS);
+        assert_eq!(p, d("0.00000000000000000005"));
+    }
+
+    #[test]
+    fn it_rounds() {
+        assert_eq!(d("1.25").round_to(1).unwrap().to_string(), "1.3");
+        assert_eq!(d("-1.25").round_to(1).unwrap().to_string(), "-1.3");
+        assert_eq!(d("1.24").round_to(0).unwrap().to_string(), "1");
+        assert_eq!(d("1.2").round_to(3).unwrap().to_string(), "1.200");
+        assert_eq!(d("123.45").integral_digits(), 3);
+        assert_eq!(d("-0.45").integral_digits(), 0);
+    }
+}
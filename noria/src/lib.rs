@@ -204,9 +204,13 @@ use petgraph::graph::NodeIndex;
 use std::collections::HashMap;
 use tokio_tower::multiplex;
 
+mod bulk;
 mod controller;
 mod data;
+mod decimal;
+mod snapshot;
 mod table;
+mod transaction;
 mod view;
 
 #[doc(hidden)]
@@ -245,8 +249,15 @@ pub mod results {
 
 /// Noria errors.
 pub mod error {
+    pub use crate::bulk::BulkLoadError;
+    pub use crate::snapshot::SnapshotError;
     pub use crate::table::TableError;
     pub use crate::view::ViewError;
+    pub use crate::DrainError;
+    pub use crate::MoveDomainError;
+    pub use crate::RecipeError;
+    pub use crate::ReshardError;
+    pub use crate::TransactionError;
 }
 
 task_local! {
@@ -295,13 +306,23 @@ impl<T> From<T> for Tagged<T> {
     }
 }
 
+pub use crate::bulk::{BulkLoadProgress, CsvRows, DumpInsert, DumpReader};
 pub use crate::controller::{ControllerDescriptor, ControllerHandle};
-pub use crate::data::{DataType, Modification, Operation, TableOperation};
-pub use crate::table::Table;
-pub use crate::view::View;
+pub use crate::data::{DataType, Modification, Operation, OperationResult, TableOperation};
+pub use crate::decimal::{Decimal, MAX_DECIMAL_DIGITS};
+pub use crate::snapshot::Snapshot;
+pub use crate::table::{Table, WriteResult, WriteToken};
+pub use crate::transaction::Transaction;
+pub use crate::view::{Change, ChangeCursor, Changes, Delta, Subscription, View};
 
 #[doc(hidden)]
-pub use crate::table::Input;
+pub use crate::table::{coerce_operation, Input};
+
+#[doc(hidden)]
+pub use crate::snapshot::SnapshotPage;
+
+#[doc(hidden)]
+pub use crate::transaction::{TransactionTag, TransactionWrites};
 
 #[doc(hidden)]
 pub use crate::view::{ReadQuery, ReadReply, ReadReplyBatch};
@@ -328,6 +349,169 @@ pub struct ActivationResult {
     pub expressions_removed: usize,
 }
 
+/// Describes why a recipe could not be applied.
+///
+/// A rejected recipe leaves the running dataflow graph as it was. Errors returned by
+/// `ControllerHandle::extend_recipe` and `ControllerHandle::install_recipe` can be turned back
+/// into a `RecipeError` using `failure::Error::downcast_ref`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RecipeError {
+    /// Name of the offending query, if it was given one.
+    pub query: Option<String>,
+    /// The SQL text of the offending statement, if the error can be attributed to one.
+    pub sql: Option<String>,
+    /// Why the statement was rejected.
+    pub reason: String,
+    /// Line and column (both starting at 1) in the recipe text at which the error was found, if
+    /// it was found while parsing the recipe.
+    pub location: Option<(usize, usize)>,
+}
+
+impl RecipeError {
+    #[doc(hidden)]
+    pub fn new<S: Into<String>>(reason: S) -> Self {
+        RecipeError {
+            query: None,
+            sql: None,
+            reason: reason.into(),
+            location: None,
+        }
+    }
+}
+
+impl std::fmt::Display for RecipeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some((line, column)) = self.location {
+            write!(f, "line {}, column {}: ", line, column)?;
+        }
+        match (&self.query, &self.sql) {
+            (Some(ref query), Some(ref sql)) => {
+                write!(f, "query \"{}\" ({}): {}", query, sql, self.reason)
+            }
+            (Some(ref query), None) => write!(f, "query \"{}\": {}", query, self.reason),
+            (None, Some(ref sql)) => write!(f, "{}: {}", sql, self.reason),
+            (None, None) => write!(f, "{}", self.reason),
+        }
+    }
+}
+
+impl failure::Fail for RecipeError {}
+
+/// Describes why a base table or view could not be resharded.
+#[derive(Clone, Debug, Fail, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ReshardError {
+    /// There is no base table or view with the given name.
+    #[fail(display = "no table or view named '{}'", _0)]
+    NoSuchTable(String),
+
+    /// The given base table or view is not sharded, and so cannot be resharded either.
+    ///
+    /// This is the case for everything if the deployment runs without sharding, and for tables
+    /// with a compound primary key or additional unique keys otherwise.
+    #[fail(display = "'{}' is not sharded", _0)]
+    NotSharded(String),
+
+    /// Tables and views must be split across at least two shards.
+    #[fail(display = "cannot split a table or view into {} shards", _0)]
+    TooFewShards(usize),
+
+    /// The given base table has no column with the given name.
+    #[fail(display = "'{}' has no column named '{}'", _0, _1)]
+    NoSuchColumn(String, String),
+
+    /// Only base tables without a primary key can be sharded by a given column, and only by
+    /// columns that all of their unique keys include.
+    #[fail(display = "'{}' cannot be sharded by '{}'", _0, _1)]
+    CannotShardBy(String, String),
+
+    /// The queries that read from the given base table or view could not be built again with
+    /// the new sharding. Nothing was changed.
+    #[fail(display = "the queries over '{}' cannot be rebuilt", _0)]
+    CannotRebuild(String),
+
+    /// The given base table or view was resharded, but the new sharding could not be recorded,
+    /// so it is split as before if the controller restarts.
+    #[fail(display = "failed to persist the new sharding of '{}'", _0)]
+    NotPersisted(String),
+}
+
+/// Describes why a transaction could not be committed.
+#[derive(Clone, Debug, Fail, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TransactionError {
+    /// The transaction writes to a base table that no longer exists.
+    #[fail(display = "no table named '{}'", _0)]
+    NoSuchTable(String),
+
+    /// A shard of a base table the transaction writes to is on a worker that has failed. None of
+    /// the writes of the transaction were applied.
+    #[fail(display = "a shard of table '{}' is unavailable", _0)]
+    Unavailable(String),
+
+    /// A base table the transaction writes to was resharded after the handle used to write to it
+    /// was obtained. None of the writes of the transaction were applied.
+    #[fail(display = "table '{}' was resharded since it was opened", _0)]
+    StaleTable(String),
+}
+
+/// Describes why a domain could not be moved to another worker.
+#[derive(Clone, Debug, Fail, Serialize, Deserialize, PartialEq, Eq)]
+pub enum MoveDomainError {
+    /// There is no domain with the given index, or it does not have a shard with the given index.
+    #[fail(display = "no shard {} of domain {}", shard, domain)]
+    NoSuchDomain {
+        /// The domain that was asked for.
+        domain: usize,
+        /// The shard of the domain that was asked for.
+        shard: usize,
+    },
+
+    /// There is no healthy worker at the given address that is not being drained.
+    #[fail(display = "no healthy worker at {}", _0)]
+    NoSuchWorker(std::net::SocketAddr),
+
+    /// The shard was in the middle of replays, transactions, snapshots or writes passed on to
+    /// other bases, and could not hand over. Moving it again later may succeed.
+    #[fail(display = "shard {} of domain {} is busy", shard, domain)]
+    Busy {
+        /// The domain that was asked for.
+        domain: usize,
+        /// The shard of the domain that was asked for.
+        shard: usize,
+    },
+}
+
+/// Describes why a worker could not be drained.
+#[derive(Clone, Debug, Fail, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DrainError {
+    /// There is no healthy worker at the given address.
+    #[fail(display = "no healthy worker at {}", _0)]
+    NoSuchWorker(std::net::SocketAddr),
+
+    /// There is no other healthy worker that is not being drained, and so nowhere for the domains
+    /// of the given worker to go.
+    #[fail(display = "no worker to move the domains of {} to", _0)]
+    NoOtherWorker(std::net::SocketAddr),
+
+    /// Some domains on the given worker were too busy to move, or left copies behind that could
+    /// not be shut down, and are still there. The worker is still being drained, and draining it
+    /// again moves them too.
+    #[fail(display = "worker at {} still holds domains", _0)]
+    StillHoldsDomains(std::net::SocketAddr),
+}
+
+/// The state of a worker, as reported by `ControllerHandle::instances`.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum WorkerState {
+    /// The worker is up, and may be given new domains.
+    Healthy,
+    /// The domains of the worker are being moved off it, and it is not given new ones.
+    Draining,
+    /// The worker holds no domains, and is not given new ones, so it can be shut down.
+    Drained,
+    /// The worker stopped sending heartbeats, and its domains were built again elsewhere.
+    Failed,
+}
+
 #[doc(hidden)]
 #[inline]
 pub fn shard_by(dt: &DataType, shards: usize) -> usize {
@@ -343,6 +527,27 @@ pub fn shard_by(dt: &DataType, shards: usize) -> usize {
             hasher.write(s.as_bytes());
             hasher.finish() as usize % shards
         }
+        // whole decimals must end up where the equal integers do
+        DataType::Decimal(ref d) if d.to_integer().is_some() => {
+            let i = d.to_integer().unwrap();
+            if i as i64 as i128 == i {
+                i as i64 as usize % shards
+            } else {
+                i as u64 as usize % shards
+            }
+        }
+        DataType::Decimal(..) | DataType::ByteArray(..) | DataType::Json(..) => {
+            use std::hash::{Hash, Hasher};
+            let mut hasher = ahash::AHasher::new_with_keys(0x3306, 0x6033);
+            dt.hash(&mut hasher);
+            hasher.finish() as usize % shards
+        }
+        DataType::Date(d) => {
+            use chrono::Datelike;
+            d.num_days_from_ce() as usize % shards
+        }
+        DataType::Time(t) => t as usize % shards,
+        DataType::Bool(b) => b as usize % shards,
         // a bit hacky: send all NULL values to the first shard
         DataType::None => 0,
         ref x => {
@@ -0,0 +1,85 @@
+use crate::data::DataType;
+use futures_util::stream::Stream;
+use std::fmt;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// A failed snapshot.
+#[derive(Clone, Debug, Fail, Serialize, Deserialize, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// There is no base table or view with the given name.
+    #[fail(display = "no table or view named '{}'", _0)]
+    NoSuchName(String),
+
+    /// The given base table or view does not keep all of its rows in memory, and so there is no
+    /// state to take a snapshot of.
+    ///
+    /// This is the case for partially materialized views, which only hold the keys that have been
+    /// read recently.
+    #[fail(display = "'{}' is not fully materialized", _0)]
+    NotMaterialized(String),
+
+    /// The snapshot was not read for so long that it was discarded.
+    #[fail(display = "snapshot expired before it was read in full")]
+    Expired,
+
+    /// A worker holding part of the base table or view, or of the bases it is computed from,
+    /// has failed.
+    #[fail(display = "'{}' is not available", _0)]
+    Unavailable(String),
+}
+
+/// The next batch of rows of a snapshot pinned by the domains.
+#[doc(hidden)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SnapshotPage {
+    /// Identifies the snapshot to the controller.
+    pub id: u64,
+    /// The rows of this batch.
+    pub rows: Vec<Vec<DataType>>,
+    /// Whether this is the last batch of the snapshot.
+    pub done: bool,
+}
+
+/// The contents of a base table or fully materialized view at a single point in time.
+///
+/// The rows are yielded in batches, in no particular order. All shards of the table or view are
+/// copied at the same point: the rows reflect either all or none of the effects of any write, and
+/// of any transaction. To copy the shards of a view at the same point, writes to the base tables
+/// the view is computed from are held back until every shard has been copied.
+///
+/// The copies are kept by the workers until they have been read. A snapshot that is not read from
+/// for five minutes is discarded, and reading from it then fails with
+/// [`SnapshotError::Expired`].
+///
+/// Created with [`ControllerHandle::snapshot`].
+///
+/// [`ControllerHandle::snapshot`]: crate::ControllerHandle::snapshot
+pub struct Snapshot {
+    pub(crate) columns: Vec<String>,
+    pub(crate) inner:
+        Pin<Box<dyn Stream<Item = Result<Vec<Vec<DataType>>, failure::Error>> + Send>>,
+}
+
+impl fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("columns", &self.columns)
+            .finish()
+    }
+}
+
+impl Snapshot {
+    /// The names of the columns of the rows in this snapshot.
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+}
+
+impl Stream for Snapshot {
+    type Item = Result<Vec<Vec<DataType>>, failure::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
@@ -1,21 +1,23 @@
 use crate::channel::CONNECTION_FROM_BASE;
 use crate::data::*;
 use crate::internal::*;
+use crate::transaction::TransactionTag;
 use crate::LocalOrNot;
 use crate::{Tagged, Tagger};
 use async_bincode::{AsyncBincodeStream, AsyncDestination};
 use futures_util::{
-    future, future::TryFutureExt, ready, stream::futures_unordered::FuturesUnordered,
-    stream::TryStreamExt,
+    future, future::FutureExt, future::TryFutureExt, ready,
+    stream::futures_unordered::FuturesUnordered, stream::StreamExt, stream::TryStreamExt,
 };
-use nom_sql::CreateTableStatement;
+use nom_sql::{ColumnConstraint, ColumnSpecification, CreateTableStatement, SqlType};
 use petgraph::graph::NodeIndex;
 use std::collections::HashMap;
 use std::future::Future;
 use std::net::SocketAddr;
+use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
-use std::{fmt, io};
+use std::{fmt, io, mem};
 use tokio::io::AsyncWriteExt;
 use tokio_tower::multiplex;
 use tower_balance::p2c::Balance;
@@ -27,7 +29,7 @@ use vec_map::VecMap;
 
 type Transport = AsyncBincodeStream<
     tokio::net::TcpStream,
-    Tagged<()>,
+    Tagged<WriteResult>,
     Tagged<LocalOrNot<Input>>,
     AsyncDestination,
 >;
@@ -35,8 +37,8 @@ type Transport = AsyncBincodeStream<
 /// Create a new row for insertion into a [`Table`] using column names.
 ///
 /// If the schema of the given table is known, column defaults and `NOT NULL` restrictions will
-/// also be respected. In the future, this method will also check that the provided `DataType`
-/// matches the expected data type for each column.
+/// also be respected. The provided values are checked against the type of each column when the
+/// row is inserted.
 ///
 /// Values are automatically converted to `DataType` as necessary.
 ///
@@ -49,7 +51,8 @@ type Transport = AsyncBincodeStream<
 ///     "created_at" => chrono::Local::now().naive_local(),
 ///     "logins" => 0,
 ///   );
-///   users.insert(user).await
+///   users.insert(user).await?;
+///   Ok(())
 /// }
 /// ```
 #[macro_export]
@@ -91,7 +94,6 @@ macro_rules! row {
         for (coli, col) in $tbl.columns().iter().enumerate() {
             match &**col {
                 $($k => {
-                    // TODO: check row[coli] against schema.fields[coli].sql_type ?
                     row[coli] = vals[$idx].take().expect("field name appears twice -- should be caught by match");
                     if let Some(ref schema) = schema {
                         if schema.fields[coli].constraints.iter().any(|c| c == &$crate::ColumnConstraint::NotNull) {
@@ -146,13 +148,14 @@ async fn add_user(users: &mut Table) -> Result<(), TableError> {
       "not an ident" => s,
       "logins" => 0,
     );
-    users.insert(user).await
+    users.insert(user).await?;
+    Ok(())
 }
 
 /// Create an update for a given [`Table`] using column names.
 ///
-/// In the future, this method will also check that the provided `DataType`
-/// matches the expected data type for each column if the schema is known.
+/// The provided values are checked against the type of each column when the update is
+/// performed if the schema is known.
 ///
 /// Values are automatically converted to `DataType` as necessary.
 ///
@@ -163,7 +166,8 @@ async fn add_user(users: &mut Table) -> Result<(), TableError> {
 ///     "password" => "hunter3",
 ///     "logins" => noria::Modification::Apply(noria::Operation::Add, 1.into()),
 ///   );
-///   users.update(vec!["jonhoo".into()], user).await
+///   users.update(vec!["jonhoo".into()], user).await?;
+///   Ok(())
 /// }
 /// ```
 #[macro_export]
@@ -181,7 +185,6 @@ macro_rules! update {
         for (coli, col) in $tbl.columns().iter().enumerate() {
             match &**col {
                 $($k => {
-                    // TODO: check set[$idx].1 against schema.fields[coli].sql_type ?
                     set[$idx].0 = coli;
                 },)|+
                 _ => { /* column value not updated */ }
@@ -198,7 +201,8 @@ async fn update_user(users: &mut Table) -> Result<(), TableError> {
       "password" => "hunter3",
       "logins" => crate::Modification::Apply(crate::Operation::Add, 1.into()),
     );
-    users.update(vec!["jonhoo".into()], user).await
+    users.update(vec!["jonhoo".into()], user).await?;
+    Ok(())
 }
 
 #[derive(Debug)]
@@ -239,20 +243,71 @@ impl Service<()> for Endpoint {
     }
 }
 
+/// One of the connections to a shard of a table, which reports when it fails.
+struct Connection {
+    client: InnerService,
+    failed: tokio::sync::mpsc::UnboundedSender<()>,
+}
+
+impl Service<Tagged<LocalOrNot<Input>>> for Connection {
+    type Response = <InnerService as Service<Tagged<LocalOrNot<Input>>>>::Response;
+    type Error = <InnerService as Service<Tagged<LocalOrNot<Input>>>>::Error;
+    type Future = <InnerService as Service<Tagged<LocalOrNot<Input>>>>::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let r = ready!(self.client.poll_ready(cx));
+        if r.is_err() {
+            let _ = self.failed.send(());
+        }
+        Poll::Ready(r)
+    }
+
+    fn call(&mut self, req: Tagged<LocalOrNot<Input>>) -> Self::Future {
+        self.client.call(req)
+    }
+}
+
+impl tower_load::Load for Connection {
+    type Metric = <InnerService as tower_load::Load>::Metric;
+
+    fn load(&self) -> Self::Metric {
+        self.client.load()
+    }
+}
+
 fn make_table_stream(
     addr: SocketAddr,
 ) -> impl futures_util::stream::TryStream<
-    Ok = tower_discover::Change<usize, InnerService>,
+    Ok = tower_discover::Change<usize, Connection>,
     Error = tokio::io::Error,
 > {
+    let (failed, mut failures) = tokio::sync::mpsc::unbounded_channel();
+
     // TODO: use whatever comes out of https://github.com/tower-rs/tower/issues/456 instead of
     // creating _all_ the connections every time.
-    (0..crate::TABLE_POOL_SIZE)
-        .map(|i| async move {
-            let svc = Endpoint(addr).call(()).await?;
-            Ok(tower_discover::Change::Insert(i, svc))
+    let connections = (0..crate::TABLE_POOL_SIZE)
+        .map(move |i| {
+            let failed = failed.clone();
+            async move {
+                let client = Endpoint(addr).call(()).await?;
+                Ok(tower_discover::Change::Insert(
+                    i,
+                    Connection { client, failed },
+                ))
+            }
         })
-        .collect::<futures_util::stream::FuturesUnordered<_>>()
+        .collect::<futures_util::stream::FuturesUnordered<_>>();
+
+    // the shard is most likely gone once any of its connections fails, usually because it moved
+    // to another worker, so the whole pool fails rather than wait for connections that never come
+    let failure = futures_util::stream::once(async move {
+        failures.recv().await;
+        Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "table server went away",
+        ))
+    });
+    connections.chain(failure)
 }
 
 fn make_table_discover(addr: SocketAddr) -> Discover {
@@ -261,17 +316,104 @@ fn make_table_discover(addr: SocketAddr) -> Discover {
 
 // Unpin + Send bounds are needed due to https://github.com/rust-lang/rust/issues/55997
 #[cfg(not(doc))]
-type Discover = impl tower_discover::Discover<Key = usize, Service = InnerService, Error = tokio::io::Error>
+type Discover = impl tower_discover::Discover<Key = usize, Service = Connection, Error = tokio::io::Error>
     + Unpin
     + Send;
 #[cfg(doc)]
-type Discover = crate::doc_mock::Discover<InnerService>;
+type Discover = crate::doc_mock::Discover<Connection>;
 
 pub(crate) type TableRpc = Buffer<
     ConcurrencyLimit<Balance<Discover, Tagged<LocalOrNot<Input>>>>,
     Tagged<LocalOrNot<Input>>,
 >;
 
+/// Fetches a [`TableBuilder`] for a table from the controller again, or `None` if the table no
+/// longer exists.
+pub(crate) type Refetch = Arc<dyn Fn() -> Refetching + Send + Sync>;
+/// A fetch started by a [`Refetch`].
+pub(crate) type Refetching =
+    Pin<Box<dyn Future<Output = Result<Option<TableBuilder>, failure::Error>> + Send>>;
+
+/// Where the shards of a table are, as last fetched by a handle to it or any of its clones.
+struct Routing {
+    /// Counts how often the routing was fetched again.
+    version: usize,
+    shards: Vec<TableRpc>,
+    shard_addrs: Vec<SocketAddr>,
+}
+
+/// Connect to the given shards of a table, reusing the connections in `rpcs` where there are any.
+fn connect(
+    txs: &[SocketAddr],
+    rpcs: &Mutex<HashMap<(SocketAddr, usize), TableRpc>>,
+) -> Vec<TableRpc> {
+    let mut conns = Vec::with_capacity(txs.len());
+    for (shardi, &addr) in txs.iter().enumerate() {
+        use std::collections::hash_map::Entry;
+
+        // one entry per shard so that we can send sharded requests in parallel even if
+        // they happen to be targeting the same machine.
+        let mut rpcs = rpcs.lock().unwrap();
+        let s = match rpcs.entry((addr, shardi)) {
+            Entry::Occupied(e) => e.get().clone(),
+            Entry::Vacant(h) => {
+                // TODO: maybe always use the same local port?
+                let (c, w) = Buffer::pair(
+                    ConcurrencyLimit::new(
+                        Balance::from_entropy(make_table_discover(addr)),
+                        crate::PENDING_LIMIT,
+                    ),
+                    crate::BUFFER_TO_POOL,
+                );
+                use tracing_futures::Instrument;
+                tokio::spawn(w.instrument(tracing::debug_span!(
+                    "table_worker",
+                    addr = %addr,
+                    shard = shardi
+                )));
+                h.insert(c.clone());
+                c
+            }
+        };
+        conns.push(s);
+    }
+    conns
+}
+
+/// Fetch where the shards of a table are once the connections to the `failed` ones have failed, as
+/// they do when shards move to other workers, unless a clone of the handle has done so since the
+/// routing at `version`. Returns whether the routing could be fetched.
+async fn refetch_routing(
+    refetch: Refetch,
+    rpcs: Arc<Mutex<HashMap<(SocketAddr, usize), TableRpc>>>,
+    routing: Arc<Mutex<Routing>>,
+    version: usize,
+    failed: Vec<(SocketAddr, usize)>,
+) -> bool {
+    if routing.lock().unwrap().version != version {
+        return true;
+    }
+    let tb = match refetch().await {
+        Ok(Some(tb)) => tb,
+        Ok(None) | Err(_) => return false,
+    };
+
+    // shards that stayed where they were are connected to again
+    {
+        let mut rpcs = rpcs.lock().unwrap();
+        for shard in &failed {
+            rpcs.remove(shard);
+        }
+    }
+    let mut routing = routing.lock().unwrap();
+    if routing.version == version {
+        routing.version += 1;
+        routing.shards = connect(&tb.txs, &rpcs);
+        routing.shard_addrs = tb.txs;
+    }
+    true
+}
+
 /// A failed [`Table`] operation.
 #[derive(Debug, Fail)]
 pub enum TableError {
@@ -289,6 +431,24 @@ pub enum TableError {
     )]
     WrongKeyColumnCount(usize, usize),
 
+    /// A condition was given on a column that does not exist.
+    #[fail(display = "no column with index {}", _0)]
+    NoSuchColumn(usize),
+
+    /// A value was given that cannot be stored in the column it was destined for.
+    #[fail(
+        display = "column '{}' expects a value of type {}, got {:?}",
+        column, expected, got
+    )]
+    TypeMismatch {
+        /// The name of the column.
+        column: String,
+        /// The type of the column.
+        expected: SqlType,
+        /// The value that was given.
+        got: DataType,
+    },
+
     /// The underlying connection to Noria produced an error.
     #[fail(display = "{}", _0)]
     TransportError(#[cause] failure::Error),
@@ -300,11 +460,144 @@ impl From<Box<dyn std::error::Error + Send + Sync>> for TableError {
     }
 }
 
+/// Check a single value destined for the column described by `spec`, and coerce it to the
+/// column's type.
+///
+/// If `fill_default` is set, a missing value for a `NOT NULL` column is replaced by the column's
+/// default value, if it has one.
+fn coerce_value(
+    spec: &ColumnSpecification,
+    v: &mut DataType,
+    fill_default: bool,
+) -> Result<(), TableError> {
+    let mismatch = |got: &DataType| TableError::TypeMismatch {
+        column: spec.column.name.clone(),
+        expected: spec.sql_type.clone(),
+        got: got.clone(),
+    };
+
+    if v.is_none() {
+        if !spec.constraints.contains(&ColumnConstraint::NotNull) {
+            return Ok(());
+        }
+        let default = spec.constraints.iter().find_map(|c| match *c {
+            ColumnConstraint::DefaultValue(ref l) if fill_default => Some(DataType::from(l)),
+            _ => None,
+        });
+        match default {
+            Some(default) if !default.is_none() => *v = default,
+            _ => return Err(mismatch(v)),
+        }
+    }
+
+    *v = v.coerce_to(&spec.sql_type).ok_or_else(|| mismatch(v))?;
+    Ok(())
+}
+
+/// Check the values in `op` against the types of the columns in `fields` (whose key columns are
+/// `key`), and coerce them to those types.
+///
+/// This enforces column types and widths, `NOT NULL` constraints, and fills in defaults for
+/// missing `NOT NULL` values in inserted rows. Columns beyond the end of `fields` are not checked.
+#[doc(hidden)]
+pub fn coerce_operation(
+    fields: &[ColumnSpecification],
+    key: &[usize],
+    op: &mut TableOperation,
+) -> Result<(), TableError> {
+    let coerce_key = |k: &mut [DataType]| -> Result<(), TableError> {
+        for (v, &col) in k.iter_mut().zip(key) {
+            if let Some(spec) = fields.get(col) {
+                *v = v
+                    .coerce_to(&spec.sql_type)
+                    .ok_or_else(|| TableError::TypeMismatch {
+                        column: spec.column.name.clone(),
+                        expected: spec.sql_type.clone(),
+                        got: v.clone(),
+                    })?;
+            }
+        }
+        Ok(())
+    };
+    let coerce_set = |set: &mut [Modification]| -> Result<(), TableError> {
+        for (m, spec) in set.iter_mut().zip(fields) {
+            match *m {
+                Modification::Set(ref mut v) | Modification::SetIfNull(ref mut v) => {
+                    coerce_value(spec, v, false)?
+                }
+                Modification::SetNull => coerce_value(spec, &mut DataType::None, false)?,
+                Modification::Apply(_, ref mut v) => {
+                    *v = v
+                        .coerce_to(&spec.sql_type)
+                        .filter(|v| !v.is_none())
+                        .ok_or_else(|| TableError::TypeMismatch {
+                            column: spec.column.name.clone(),
+                            expected: spec.sql_type.clone(),
+                            got: v.clone(),
+                        })?
+                }
+                Modification::None => {}
+            }
+        }
+        Ok(())
+    };
+
+    match *op {
+        TableOperation::Insert(ref mut row) | TableOperation::InsertIfAbsent(ref mut row) => {
+            for (v, spec) in row.iter_mut().zip(fields) {
+                coerce_value(spec, v, true)?;
+            }
+            Ok(())
+        }
+        TableOperation::Delete { ref mut key } => coerce_key(key),
+        TableOperation::InsertOrUpdate {
+            ref mut row,
+            ref mut update,
+        } => {
+            for (v, spec) in row.iter_mut().zip(fields) {
+                coerce_value(spec, v, true)?;
+            }
+            coerce_set(update)
+        }
+        TableOperation::Update {
+            ref mut set,
+            ref mut key,
+        } => {
+            coerce_key(key)?;
+            coerce_set(set)
+        }
+        TableOperation::UpdateIf {
+            ref mut key,
+            ref mut expected,
+            ref mut set,
+        } => {
+            coerce_key(key)?;
+            for (col, v) in expected.iter_mut() {
+                if let Some(spec) = fields.get(*col) {
+                    *v = v
+                        .coerce_to(&spec.sql_type)
+                        .ok_or_else(|| TableError::TypeMismatch {
+                            column: spec.column.name.clone(),
+                            expected: spec.sql_type.clone(),
+                            got: v.clone(),
+                        })?;
+                }
+            }
+            coerce_set(set)
+        }
+    }
+}
+
 #[doc(hidden)]
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Input {
     pub dst: LocalNodeIndex,
     pub data: Vec<TableOperation>,
+    pub txn: Option<TransactionTag>,
+    /// Whether the writer wants a token for this write.
+    pub track: bool,
+    /// Whether this write is part of a bulk load, and so only inserts rows.
+    pub bulk: bool,
 }
 
 impl fmt::Debug for Input {
@@ -312,10 +605,80 @@ impl fmt::Debug for Input {
         fmt.debug_struct("Input")
             .field("dst", &self.dst)
             .field("data", &self.data)
+            .field("txn", &self.txn)
+            .field("track", &self.track)
+            .field("bulk", &self.bulk)
             .finish()
     }
 }
 
+/// Identifies a point in the sequence of writes made to one or more base tables.
+///
+/// Writes to a [`Table`] that has been asked for tokens with [`Table::set_write_tokens`], as well
+/// as committed transactions, return a token covering the write as part of their
+/// [`WriteResult`], and [`View::lookup_after`](crate::View::lookup_after) waits until a view
+/// reflects every write covered by a token before answering. Tokens from different writes can be
+/// combined with [`WriteToken::merge`] to wait for all of them at once.
+///
+/// A token holds a sequence number for each shard of each base table it covers. A base table
+/// shard numbers the writes it returns tokens for one after the other, and since the effects of
+/// the writes to a shard reach each view in the order the shard processed them, a token also
+/// covers every write that shard processed before the ones it was returned for.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriteToken {
+    writes: Vec<(NodeIndex, usize, u64)>,
+}
+
+impl WriteToken {
+    #[doc(hidden)]
+    pub fn new(base: NodeIndex, shard: usize, seq: u64) -> Self {
+        WriteToken {
+            writes: vec![(base, shard, seq)],
+        }
+    }
+
+    /// Extend this token to also cover every write covered by `other`.
+    pub fn merge(&mut self, other: WriteToken) {
+        for (base, shard, seq) in other.writes {
+            match self
+                .writes
+                .iter_mut()
+                .find(|&&mut (b, s, _)| b == base && s == shard)
+            {
+                Some(w) => w.2 = w.2.max(seq),
+                None => self.writes.push((base, shard, seq)),
+            }
+        }
+    }
+
+    /// The base table shards this token covers, along with the sequence number of the last write
+    /// to each of them that it covers.
+    #[doc(hidden)]
+    pub fn writes(&self) -> impl Iterator<Item = (NodeIndex, usize, u64)> + '_ {
+        self.writes.iter().cloned()
+    }
+}
+
+/// The outcome of a write to a [`Table`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriteResult {
+    /// A token covering the write, which can be passed to
+    /// [`View::lookup_after`](crate::View::lookup_after).
+    ///
+    /// The token is empty unless the write was made through a [`Table`] that was asked for tokens
+    /// with [`Table::set_write_tokens`].
+    pub token: WriteToken,
+    /// The outcome of each operation of the write, in the order the operations were given.
+    pub results: Vec<OperationResult>,
+}
+
+impl WriteResult {
+    /// Whether every operation of the write was applied.
+    pub fn all_applied(&self) -> bool {
+        self.results.iter().all(OperationResult::is_applied)
+    }
+}
+
 #[doc(hidden)]
 #[derive(Clone, Serialize, Deserialize)]
 pub struct TableBuilder {
@@ -329,46 +692,24 @@ pub struct TableBuilder {
     pub table_name: String,
     pub columns: Vec<String>,
     pub schema: Option<CreateTableStatement>,
+    pub column_specs: Vec<ColumnSpecification>,
 }
 
 impl TableBuilder {
+    /// Build a handle to the table, which fetches where the shards of the table are again with
+    /// `refetch`, if given, when its connections to them fail.
     pub(crate) fn build(
         self,
         rpcs: Arc<Mutex<HashMap<(SocketAddr, usize), TableRpc>>>,
+        refetch: Option<Refetch>,
     ) -> Result<Table, io::Error> {
-        let mut addrs = Vec::with_capacity(self.txs.len());
-        let mut conns = Vec::with_capacity(self.txs.len());
-        for (shardi, &addr) in self.txs.iter().enumerate() {
-            use std::collections::hash_map::Entry;
-
-            addrs.push(addr);
-
-            // one entry per shard so that we can send sharded requests in parallel even if
-            // they happen to be targeting the same machine.
-            let mut rpcs = rpcs.lock().unwrap();
-            let s = match rpcs.entry((addr, shardi)) {
-                Entry::Occupied(e) => e.get().clone(),
-                Entry::Vacant(h) => {
-                    // TODO: maybe always use the same local port?
-                    let (c, w) = Buffer::pair(
-                        ConcurrencyLimit::new(
-                            Balance::from_entropy(make_table_discover(addr)),
-                            crate::PENDING_LIMIT,
-                        ),
-                        crate::BUFFER_TO_POOL,
-                    );
-                    use tracing_futures::Instrument;
-                    tokio::spawn(w.instrument(tracing::debug_span!(
-                        "table_worker",
-                        addr = %addr,
-                        shard = shardi
-                    )));
-                    h.insert(c.clone());
-                    c
-                }
-            };
-            conns.push(s);
-        }
+        let addrs = self.txs;
+        let conns = connect(&addrs, &rpcs);
+        let routing = Arc::new(Mutex::new(Routing {
+            version: 0,
+            shards: conns.clone(),
+            shard_addrs: addrs.clone(),
+        }));
 
         let dispatch = tracing::dispatcher::get_default(|d| d.clone());
         Ok(Table {
@@ -380,10 +721,17 @@ impl TableBuilder {
             dropped: self.dropped,
             table_name: self.table_name,
             schema: self.schema,
+            column_specs: self.column_specs,
             dst_is_local: false,
+            write_tokens: false,
 
             shard_addrs: addrs,
             shards: conns,
+            version: 0,
+            routing,
+            refetch,
+            refetching: None,
+            rpcs,
 
             dispatch,
         })
@@ -396,6 +744,9 @@ impl TableBuilder {
 /// connections to the Soup workers. For this reason, `Table` is *not* `Send` or `Sync`. To get a
 /// handle that can be sent to a different thread (i.e., one with its own dedicated connections),
 /// call `Table::into_exclusive`.
+///
+/// If a write fails because the shards of the table moved to other workers, the handle fetches
+/// where they went from the controller, so that the writes after it go there.
 #[derive(Clone)]
 pub struct Table {
     ni: NodeIndex,
@@ -406,10 +757,18 @@ pub struct Table {
     dropped: VecMap<DataType>,
     table_name: String,
     schema: Option<CreateTableStatement>,
+    column_specs: Vec<ColumnSpecification>,
     dst_is_local: bool,
+    write_tokens: bool,
 
     shards: Vec<TableRpc>,
     shard_addrs: Vec<SocketAddr>,
+    /// The version of `routing` that `shards` and `shard_addrs` were taken from.
+    version: usize,
+    routing: Arc<Mutex<Routing>>,
+    refetch: Option<Refetch>,
+    refetching: Option<future::Shared<Pin<Box<dyn Future<Output = bool> + Send>>>>,
+    rpcs: Arc<Mutex<HashMap<(SocketAddr, usize), TableRpc>>>,
 
     dispatch: tracing::Dispatch,
 }
@@ -426,17 +785,142 @@ impl fmt::Debug for Table {
             .field("table_name", &self.table_name)
             .field("schema", &self.schema)
             .field("dst_is_local", &self.dst_is_local)
+            .field("write_tokens", &self.write_tokens)
             .field("shard_addrs", &self.shard_addrs)
             .finish()
     }
 }
 
 impl Table {
+    /// Check the operations in `i` against the columns of this table, and coerce the values they
+    /// carry to the column types where the schema is known.
+    pub(crate) fn validate(&self, i: &mut Input) -> Result<(), TableError> {
+        // the column specs describe the columns of the base table, dropped ones included, which
+        // is also what the rows we write hold once we have filled in the dropped columns.
+        let ncols = self.columns.len() + self.dropped.len();
+        let fields = Some(&self.column_specs[..]).filter(|fields| fields.len() == ncols);
+
+        for op in &mut i.data {
+            match op {
+                TableOperation::Insert(ref row) | TableOperation::InsertIfAbsent(ref row) => {
+                    if row.len() != ncols {
+                        return Err(TableError::WrongColumnCount(ncols, row.len()));
+                    }
+                }
+                TableOperation::Delete { ref key } => {
+                    if key.len() != self.key.len() {
+                        return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
+                    }
+                }
+                TableOperation::InsertOrUpdate {
+                    ref row,
+                    ref update,
+                } => {
+                    if row.len() != ncols {
+                        return Err(TableError::WrongColumnCount(ncols, row.len()));
+                    }
+                    if update.len() > self.columns.len() {
+                        // NOTE: < is okay to allow dropping tailing no-ops
+                        return Err(TableError::WrongColumnCount(
+                            self.columns.len(),
+                            update.len(),
+                        ));
+                    }
+                }
+                TableOperation::Update { ref set, ref key } => {
+                    if key.len() != self.key.len() {
+                        return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
+                    }
+                    if set.len() > self.columns.len() {
+                        // NOTE: < is okay to allow dropping tailing no-ops
+                        return Err(TableError::WrongColumnCount(self.columns.len(), set.len()));
+                    }
+                }
+                TableOperation::UpdateIf {
+                    ref key,
+                    ref expected,
+                    ref set,
+                } => {
+                    if key.len() != self.key.len() {
+                        return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
+                    }
+                    if let Some(&(col, _)) =
+                        expected.iter().find(|&&(col, _)| col >= self.columns.len())
+                    {
+                        return Err(TableError::NoSuchColumn(col));
+                    }
+                    if set.len() > self.columns.len() {
+                        // NOTE: < is okay to allow dropping tailing no-ops
+                        return Err(TableError::WrongColumnCount(self.columns.len(), set.len()));
+                    }
+                }
+            }
+            if let Some(fields) = fields {
+                coerce_operation(fields, &self.key, op)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn input(
+        &mut self,
+        mut i: Input,
+    ) -> impl Future<Output = Result<Tagged<WriteResult>, TableError>> + Send {
+        if let Err(e) = self.validate(&mut i) {
+            return future::Either::Left(async move { Err(e) });
+        }
+        future::Either::Right(self.submit(i))
+    }
+
+    /// Split the given operations up by the shard of this table they must go to.
+    ///
+    /// Also returns the shard each operation went to, so that the results can be put back in
+    /// order.
+    pub(crate) fn split_by_shard(
+        &self,
+        data: Vec<TableOperation>,
+    ) -> (Vec<Vec<TableOperation>>, Vec<usize>) {
+        if self.shards.len() == 1 {
+            let shard_of = vec![0; data.len()];
+            return (vec![data], shard_of);
+        }
+
+        if self.key.is_empty() {
+            unreachable!("sharded base without a key?");
+        }
+        if self.key.len() != 1 {
+            // base sharded by complex key
+            unimplemented!();
+        }
+        let key_col = self.key[0];
+
+        let mut shard_writes = vec![Vec::new(); self.shards.len()];
+        let mut shard_of = Vec::with_capacity(data.len());
+        for r in data {
+            let shard = r.shard(key_col, self.shards.len());
+            shard_writes[shard].push(r);
+            shard_of.push(shard);
+        }
+        (shard_writes, shard_of)
+    }
+
+    /// Take up where the shards of this table are if a clone of this handle has fetched that since
+    /// this one last did.
+    fn reroute(&mut self) {
+        let routing = self.routing.lock().unwrap();
+        if routing.version != self.version {
+            self.version = routing.version;
+            self.shards = routing.shards.clone();
+            self.shard_addrs = routing.shard_addrs.clone();
+        }
+    }
+
+    /// Send the (already validated) operations in `i` to the shards of this table.
     #[allow(clippy::cognitive_complexity)]
-    fn input(
+    pub(crate) fn submit(
         &mut self,
         mut i: Input,
-    ) -> impl Future<Output = Result<Tagged<()>, TableError>> + Send {
+    ) -> impl Future<Output = Result<Tagged<WriteResult>, TableError>> + Send {
         let span = if crate::trace_next_op() {
             Some(tracing::trace_span!(
                 "table-request",
@@ -446,57 +930,6 @@ impl Table {
             None
         };
 
-        // NOTE: this is really just a try block
-        let immediate_err = || {
-            let ncols = self.columns.len() + self.dropped.len();
-            for op in &i.data {
-                match op {
-                    TableOperation::Insert(ref row) => {
-                        if row.len() != ncols {
-                            return Err(TableError::WrongColumnCount(ncols, row.len()));
-                        }
-                    }
-                    TableOperation::Delete { ref key } => {
-                        if key.len() != self.key.len() {
-                            return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
-                        }
-                    }
-                    TableOperation::InsertOrUpdate {
-                        ref row,
-                        ref update,
-                    } => {
-                        if row.len() != ncols {
-                            return Err(TableError::WrongColumnCount(ncols, row.len()));
-                        }
-                        if update.len() > self.columns.len() {
-                            // NOTE: < is okay to allow dropping tailing no-ops
-                            return Err(TableError::WrongColumnCount(
-                                self.columns.len(),
-                                update.len(),
-                            ));
-                        }
-                    }
-                    TableOperation::Update { ref set, ref key } => {
-                        if key.len() != self.key.len() {
-                            return Err(TableError::WrongKeyColumnCount(self.key.len(), key.len()));
-                        }
-                        if set.len() > self.columns.len() {
-                            // NOTE: < is okay to allow dropping tailing no-ops
-                            return Err(TableError::WrongColumnCount(
-                                self.columns.len(),
-                                set.len(),
-                            ));
-                        }
-                    }
-                }
-            }
-            Ok(())
-        };
-
-        if let Err(e) = immediate_err() {
-            return future::Either::Left(async move { Err(e) });
-        }
-
         if self.shards.len() == 1 {
             let request = Tagged::from(if self.dst_is_local {
                 unsafe { LocalOrNot::for_local_transfer(i) }
@@ -506,34 +939,11 @@ impl Table {
 
             let _guard = span.as_ref().map(tracing::Span::enter);
             tracing::trace!("submit request");
-            future::Either::Right(future::Either::Left(
-                self.shards[0].call(request).map_err(TableError::from),
-            ))
+            future::Either::Left(self.shards[0].call(request).map_err(TableError::from))
         } else {
-            if self.key.is_empty() {
-                unreachable!("sharded base without a key?");
-            }
-            if self.key.len() != 1 {
-                // base sharded by complex key
-                unimplemented!();
-            }
-            let key_col = self.key[0];
-
             let _guard = span.as_ref().map(tracing::Span::enter);
             tracing::trace!("shard request");
-            let mut shard_writes = vec![Vec::new(); self.shards.len()];
-            for r in i.data.drain(..) {
-                let shard = {
-                    let key = match r {
-                        TableOperation::Insert(ref r) => &r[key_col],
-                        TableOperation::Delete { ref key } => &key[0],
-                        TableOperation::Update { ref key, .. } => &key[0],
-                        TableOperation::InsertOrUpdate { ref row, .. } => &row[key_col],
-                    };
-                    crate::shard_by(key, self.shards.len())
-                };
-                shard_writes[shard].push(r);
-            }
+            let (mut shard_writes, shard_of) = self.split_by_shard(mem::take(&mut i.data));
 
             let wait_for = FuturesUnordered::new();
             for (s, rs) in shard_writes.drain(..).enumerate() {
@@ -543,12 +953,18 @@ impl Table {
                             LocalOrNot::for_local_transfer(Input {
                                 dst: i.dst,
                                 data: rs,
+                                txn: i.txn.clone(),
+                                track: i.track,
+                                bulk: i.bulk,
                             })
                         }
                     } else {
                         LocalOrNot::new(Input {
                             dst: i.dst,
                             data: rs,
+                            txn: i.txn.clone(),
+                            track: i.track,
+                            bulk: i.bulk,
                         })
                     };
                     let request = Tagged::from(p);
@@ -562,7 +978,7 @@ impl Table {
                     let _guard = span.as_ref().map(tracing::Span::enter);
                     tracing::trace!("submit request shard");
 
-                    wait_for.push(self.shards[s].call(request));
+                    wait_for.push(self.shards[s].call(request).map_ok(move |ack| (s, ack.v)));
                 } else {
                     // poll_ready reserves a sender slot which we have to release
                     // we do that by dropping the old handle and replacing it with a clone
@@ -571,30 +987,79 @@ impl Table {
                 }
             }
 
-            future::Either::Right(future::Either::Right(
+            let nshards = self.shards.len();
+            future::Either::Right(
                 wait_for
-                    .try_for_each(|_| async { Ok(()) })
+                    .try_fold(
+                        (WriteToken::default(), vec![Vec::new(); nshards]),
+                        |(mut token, mut results), (s, r)| async move {
+                            token.merge(r.token);
+                            results[s] = r.results;
+                            Ok((token, results))
+                        },
+                    )
                     .map_err(TableError::from)
-                    .map_ok(Tagged::from),
-            ))
+                    .map_ok(move |(token, results)| {
+                        let mut results: Vec<_> = results.into_iter().map(Vec::into_iter).collect();
+                        let results = shard_of
+                            .into_iter()
+                            .map(|s| results[s].next().expect("shard sent too few results"))
+                            .collect();
+                        Tagged::from(WriteResult { token, results })
+                    }),
+            )
         }
     }
 }
 
 impl Service<Vec<TableOperation>> for Table {
     type Error = TableError;
-    type Response = <TableRpc as Service<Tagged<LocalOrNot<Input>>>>::Response;
+    type Response = Tagged<WriteResult>;
 
     #[cfg(not(doc))]
-    type Future = impl Future<Output = Result<Tagged<()>, TableError>> + Send;
+    type Future = impl Future<Output = Result<Tagged<WriteResult>, TableError>> + Send;
     #[cfg(doc)]
-    type Future = crate::doc_mock::Future<Result<Tagged<()>, TableError>>;
+    type Future = crate::doc_mock::Future<Result<Tagged<WriteResult>, TableError>>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        for s in &mut self.shards {
-            ready!(s.poll_ready(cx)).map_err(TableError::from)?;
+        let mut refetched = false;
+        loop {
+            if let Some(ref mut refetching) = self.refetching {
+                let fetched = ready!(refetching.poll_unpin(cx));
+                self.refetching = None;
+                if !fetched {
+                    return Poll::Ready(Err(TableError::TransportError(failure::err_msg(
+                        "failed to fetch where the shards of the table went",
+                    ))));
+                }
+                refetched = true;
+            }
+            self.reroute();
+
+            let mut failed = Vec::new();
+            for (i, s) in self.shards.iter_mut().enumerate() {
+                if let Err(e) = ready!(s.poll_ready(cx)) {
+                    if refetched || self.refetch.is_none() {
+                        return Poll::Ready(Err(TableError::from(e)));
+                    }
+                    failed.push((self.shard_addrs[i], i));
+                }
+            }
+            if failed.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+
+            // the shards may have moved to other workers
+            let refetch = refetch_routing(
+                self.refetch.clone().unwrap(),
+                self.rpcs.clone(),
+                self.routing.clone(),
+                self.version,
+                failed,
+            );
+            let refetching: Pin<Box<dyn Future<Output = bool> + Send>> = Box::pin(refetch);
+            self.refetching = Some(refetching.shared());
         }
-        Poll::Ready(Ok(()))
     }
 
     fn call(&mut self, ops: Vec<TableOperation>) -> Self::Future {
@@ -614,6 +1079,15 @@ impl Table {
         self.dst_is_local = true;
     }
 
+    /// Choose whether writes made through this handle return a [`WriteToken`] that covers them.
+    ///
+    /// Tokens are off by default. The effects of a write that returns a token are sent to every
+    /// shard of every node below the table, even those it does not change, so that views can
+    /// tell when they reflect the write, and so they cost a little more than other writes.
+    pub fn set_write_tokens(&mut self, on: bool) {
+        self.write_tokens = on;
+    }
+
     /// Get the list of columns in this base table.
     ///
     /// Note that this will *not* be updated if the underlying recipe changes and adds or removes
@@ -631,7 +1105,6 @@ impl Table {
     }
 
     fn inject_dropped_cols(&self, r: &mut TableOperation) {
-        use std::mem;
         let ndropped = self.dropped.len();
         if ndropped != 0 {
             // inject defaults for dropped columns
@@ -640,6 +1113,7 @@ impl Table {
             // get a handle to the underlying data vector
             let r = match *r {
                 TableOperation::Insert(ref mut row)
+                | TableOperation::InsertIfAbsent(ref mut row)
                 | TableOperation::InsertOrUpdate { ref mut row, .. } => row,
                 _ => unimplemented!("we need to shift the update/delete cols!"),
             };
@@ -716,7 +1190,7 @@ impl Table {
         }
     }
 
-    fn prep_records(&self, mut ops: Vec<TableOperation>) -> Input {
+    pub(crate) fn prep_records(&self, mut ops: Vec<TableOperation>) -> Input {
         for r in &mut ops {
             self.inject_dropped_cols(r);
         }
@@ -724,7 +1198,35 @@ impl Table {
         Input {
             dst: self.node,
             data: ops,
+            txn: None,
+            track: self.write_tokens,
+            bulk: false,
+        }
+    }
+
+    /// Expand column-modification pairs into a modification for every column of this table.
+    pub(crate) fn modifications<V>(&self, u: V) -> Result<Vec<Modification>, TableError>
+    where
+        V: IntoIterator<Item = (usize, Modification)>,
+    {
+        assert!(
+            !self.key.is_empty() && self.key_is_primary,
+            "update operations can only be applied to base nodes with key columns"
+        );
+
+        let mut set = vec![Modification::None; self.columns.len()];
+        for (coli, m) in u {
+            if coli >= self.columns.len() {
+                return Err(TableError::WrongColumnCount(self.columns.len(), coli + 1));
+            }
+            set[coli] = m;
         }
+        Ok(set)
+    }
+
+    /// The index of the base node this table writes to.
+    pub(crate) fn base(&self) -> NodeIndex {
+        self.ni
     }
 
     async fn quick_n_dirty<Request, R>(
@@ -740,7 +1242,13 @@ impl Table {
     }
 
     /// Insert a single row of data into this base table.
-    pub async fn insert<V>(&mut self, u: V) -> Result<(), TableError>
+    ///
+    /// Like all writes, this returns a [`WriteResult`] that says whether the operation was
+    /// applied. If this handle returns write tokens, its token can be passed to
+    /// [`View::lookup_after`](crate::View::lookup_after) to read the effects of the write. Inserting a row whose primary key or `UNIQUE` columns
+    /// collide with those of an existing row leaves the existing row in place, and reports an
+    /// [`OperationResult::UniqueViolation`].
+    pub async fn insert<V>(&mut self, u: V) -> Result<WriteResult, TableError>
     where
         V: Into<Vec<DataType>>,
     {
@@ -748,8 +1256,23 @@ impl Table {
             .await
     }
 
+    /// Insert a single row of data into this base table, unless a row with the same key already
+    /// exists.
+    ///
+    /// If it does, the operation is reported as [`OperationResult::ConditionFailed`].
+    pub async fn insert_if_absent<V>(&mut self, u: V) -> Result<WriteResult, TableError>
+    where
+        V: Into<Vec<DataType>>,
+    {
+        self.quick_n_dirty(vec![TableOperation::InsertIfAbsent(u.into())])
+            .await
+    }
+
     /// Perform multiple operation on this base table.
-    pub async fn perform_all<I, V>(&mut self, i: I) -> Result<(), TableError>
+    ///
+    /// The operations are applied in order, and the result of each one is reported in the same
+    /// order.
+    pub async fn perform_all<I, V>(&mut self, i: I) -> Result<WriteResult, TableError>
     where
         I: IntoIterator<Item = V>,
         V: Into<TableOperation>,
@@ -759,7 +1282,7 @@ impl Table {
     }
 
     /// Delete the row with the given key from this base table.
-    pub async fn delete<I>(&mut self, key: I) -> Result<(), TableError>
+    pub async fn delete<I>(&mut self, key: I) -> Result<WriteResult, TableError>
     where
         I: Into<Vec<DataType>>,
     {
@@ -771,27 +1294,57 @@ impl Table {
     ///
     /// `u` is a set of column-modification pairs, where for each pair `(i, m)`, the modification
     /// `m` will be applied to column `i` of the record with key `key`.
-    pub async fn update<V>(&mut self, key: Vec<DataType>, u: V) -> Result<(), TableError>
+    pub async fn update<V>(&mut self, key: Vec<DataType>, u: V) -> Result<WriteResult, TableError>
     where
         V: IntoIterator<Item = (usize, Modification)>,
     {
-        assert!(
-            !self.key.is_empty() && self.key_is_primary,
-            "update operations can only be applied to base nodes with key columns"
-        );
-
-        let mut set = vec![Modification::None; self.columns.len()];
-        for (coli, m) in u {
-            if coli >= self.columns.len() {
-                return Err(TableError::WrongColumnCount(self.columns.len(), coli + 1));
-            }
-            set[coli] = m;
-        }
-
+        let set = self.modifications(u)?;
         self.quick_n_dirty(vec![TableOperation::Update { key, set }])
             .await
     }
 
+    /// Update the row with the given key in this base table, but only if its current values
+    /// match those in `expected`.
+    ///
+    /// `expected` is a set of column-value pairs that must all hold for the modifications in `u`
+    /// (as documented in `Table::update`) to be applied. If any of them does not, the operation
+    /// is reported as [`OperationResult::ConditionFailed`] and the row is left as it is.
+    ///
+    /// ```rust,no_run
+    /// # async fn claim(jobs: &mut noria::Table) -> Result<(), noria::error::TableError> {
+    /// // jobs: (id, state, worker)
+    /// let r = jobs
+    ///     .update_if(
+    ///         vec![42.into()],
+    ///         vec![(1, "queued".into())],
+    ///         vec![(1, "running".into()), (2, "worker-3".into())],
+    ///     )
+    ///     .await?;
+    /// if r.all_applied() {
+    ///     // this worker now owns job 42
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn update_if<E, V>(
+        &mut self,
+        key: Vec<DataType>,
+        expected: E,
+        u: V,
+    ) -> Result<WriteResult, TableError>
+    where
+        E: IntoIterator<Item = (usize, DataType)>,
+        V: IntoIterator<Item = (usize, Modification)>,
+    {
+        let set = self.modifications(u)?;
+        self.quick_n_dirty(vec![TableOperation::UpdateIf {
+            key,
+            expected: expected.into_iter().collect(),
+            set,
+        }])
+        .await
+    }
+
     /// Perform a insert-or-update on this base table.
     ///
     /// If a row already exists for the key in `insert`, the existing row will instead be updated
@@ -800,23 +1353,11 @@ impl Table {
         &mut self,
         insert: Vec<DataType>,
         update: V,
-    ) -> Result<(), TableError>
+    ) -> Result<WriteResult, TableError>
     where
         V: IntoIterator<Item = (usize, Modification)>,
     {
-        assert!(
-            !self.key.is_empty() && self.key_is_primary,
-            "update operations can only be applied to base nodes with key columns"
-        );
-
-        let mut set = vec![Modification::None; self.columns.len()];
-        for (coli, m) in update {
-            if coli >= self.columns.len() {
-                return Err(TableError::WrongColumnCount(self.columns.len(), coli + 1));
-            }
-            set[coli] = m;
-        }
-
+        let set = self.modifications(update)?;
         self.quick_n_dirty(vec![TableOperation::InsertOrUpdate {
             row: insert,
             update: set,
@@ -0,0 +1,258 @@
+use crate::data::*;
+use crate::table::{Table, TableError};
+use petgraph::graph::NodeIndex;
+use std::fmt;
+
+/// Identifies the writes that belong to a single transaction as they flow through the dataflow.
+///
+/// When a transaction commits, every shard of every base table it writes to emits exactly one
+/// update carrying this tag, and so knowing the bases is enough for a reader to tell how many
+/// updates it must see before all of the transaction's writes have reached it.
+#[doc(hidden)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TransactionTag {
+    pub id: u64,
+    pub bases: Vec<NodeIndex>,
+}
+
+/// The writes of a transaction, as they are sent to the controller to be committed.
+///
+/// Holds the name and node of every base table the transaction writes to, along with the
+/// operations for each shard of that table.
+#[doc(hidden)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TransactionWrites {
+    pub tables: Vec<(String, NodeIndex, Vec<Vec<TableOperation>>)>,
+}
+
+/// Remembers where each operation of a transaction went, so that the results of the operations
+/// can be put back in the order they were added in.
+#[derive(Debug)]
+pub(crate) struct ResultOrder {
+    /// The table each operation was for, in the order they were added.
+    order: Vec<usize>,
+    /// The shard of its table that each operation for each table went to.
+    shard_of: Vec<Vec<usize>>,
+}
+
+impl ResultOrder {
+    /// Arrange the results the controller reported for each shard of each table in the order the
+    /// operations were added.
+    pub(crate) fn arrange(self, results: Vec<Vec<Vec<OperationResult>>>) -> Vec<OperationResult> {
+        let mut results: Vec<Vec<_>> = results
+            .into_iter()
+            .map(|shards| shards.into_iter().map(Vec::into_iter).collect())
+            .collect();
+        let mut shard_of: Vec<_> = self.shard_of.into_iter().map(Vec::into_iter).collect();
+        self.order
+            .into_iter()
+            .map(|t| {
+                let s = shard_of[t].next().expect("more operations than were added");
+                results[t][s].next().expect("shard sent too few results")
+            })
+            .collect()
+    }
+}
+
+/// A set of writes to one or more base tables that are applied all together or not at all, and
+/// that become visible atomically.
+///
+/// A `Transaction` is obtained through `ControllerHandle::transaction`. Operations are buffered
+/// locally until the transaction is passed to `ControllerHandle::commit`, at which point they are
+/// all checked against their tables and then sent to the controller together. The controller has
+/// every base table involved check the operations against the rows it holds, and only if all of
+/// them can be applied does it have the bases apply them. Otherwise, none of them are applied.
+/// Views never expose only some of the writes of a committed transaction: a view holds back the
+/// changes a transaction makes to its rows until every write of the transaction that can affect it
+/// has been applied.
+///
+/// Transactions provide atomic *visibility*, not isolation. Writes made concurrently by other
+/// clients may be applied between those of a transaction in views, although not at the bases,
+/// which hold back other writes while they wait to hear whether to apply a transaction.
+///
+/// ```rust,no_run
+/// # async fn checkout(db: &mut noria::ControllerHandle<noria::ZookeeperAuthority>) -> Result<(), failure::Error> {
+/// let orders = db.table("orders").await?;
+/// let lines = db.table("order_lines").await?;
+///
+/// let mut txn = db.transaction();
+/// txn.insert(&orders, vec![1.into(), "jonhoo".into()]);
+/// txn.insert(&lines, vec![1.into(), "book".into(), 2.into()]);
+/// txn.insert(&lines, vec![1.into(), "pen".into(), 5.into()]);
+/// db.commit(txn).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Transaction {
+    writes: Vec<(Table, Vec<TableOperation>)>,
+    /// The index into `writes` of the table each operation was added for, in the order they were
+    /// added.
+    order: Vec<usize>,
+}
+
+impl fmt::Debug for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Transaction")
+            .field(
+                "tables",
+                &self
+                    .writes
+                    .iter()
+                    .map(|(t, ops)| (t.table_name(), ops.len()))
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Transaction {
+    pub(crate) fn new() -> Self {
+        Transaction {
+            writes: Vec::new(),
+            order: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, table: &Table, ops: impl IntoIterator<Item = TableOperation>) {
+        let i = match self
+            .writes
+            .iter()
+            .position(|(t, _)| t.base() == table.base())
+        {
+            Some(i) => i,
+            None => {
+                self.writes.push((table.clone(), Vec::new()));
+                self.writes.len() - 1
+            }
+        };
+        for op in ops {
+            self.writes[i].1.push(op);
+            self.order.push(i);
+        }
+    }
+
+    /// Add an operation on the given base table to this transaction.
+    pub fn perform<O>(&mut self, table: &Table, op: O)
+    where
+        O: Into<TableOperation>,
+    {
+        self.add(table, Some(op.into()));
+    }
+
+    /// Add multiple operations on the given base table to this transaction.
+    pub fn perform_all<I, V>(&mut self, table: &Table, i: I)
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<TableOperation>,
+    {
+        self.add(table, i.into_iter().map(Into::into));
+    }
+
+    /// Insert a single row of data into the given base table as part of this transaction.
+    pub fn insert<V>(&mut self, table: &Table, u: V)
+    where
+        V: Into<Vec<DataType>>,
+    {
+        self.perform(table, TableOperation::Insert(u.into()));
+    }
+
+    /// Delete the row with the given key from the given base table as part of this transaction.
+    pub fn delete<I>(&mut self, table: &Table, key: I)
+    where
+        I: Into<Vec<DataType>>,
+    {
+        self.perform(table, TableOperation::Delete { key: key.into() });
+    }
+
+    /// Insert a single row of data into the given base table as part of this transaction, unless
+    /// a row with the same key already exists.
+    pub fn insert_if_absent<V>(&mut self, table: &Table, u: V)
+    where
+        V: Into<Vec<DataType>>,
+    {
+        self.perform(table, TableOperation::InsertIfAbsent(u.into()));
+    }
+
+    /// Update the row with the given key in the given base table as part of this transaction.
+    ///
+    /// See `Table::update` for the meaning of `u`.
+    pub fn update<V>(&mut self, table: &Table, key: Vec<DataType>, u: V) -> Result<(), TableError>
+    where
+        V: IntoIterator<Item = (usize, Modification)>,
+    {
+        let set = table.modifications(u)?;
+        self.perform(table, TableOperation::Update { key, set });
+        Ok(())
+    }
+
+    /// Update the row with the given key in the given base table as part of this transaction, but
+    /// only if its current values match those in `expected`.
+    ///
+    /// See `Table::update_if` for the meaning of `expected` and `u`. If the condition does not
+    /// hold, none of the operations of the transaction are applied.
+    pub fn update_if<E, V>(
+        &mut self,
+        table: &Table,
+        key: Vec<DataType>,
+        expected: E,
+        u: V,
+    ) -> Result<(), TableError>
+    where
+        E: IntoIterator<Item = (usize, DataType)>,
+        V: IntoIterator<Item = (usize, Modification)>,
+    {
+        let set = table.modifications(u)?;
+        self.perform(
+            table,
+            TableOperation::UpdateIf {
+                key,
+                expected: expected.into_iter().collect(),
+                set,
+            },
+        );
+        Ok(())
+    }
+
+    /// Perform an insert-or-update on the given base table as part of this transaction.
+    ///
+    /// See `Table::insert_or_update` for the meaning of `insert` and `update`.
+    pub fn insert_or_update<V>(
+        &mut self,
+        table: &Table,
+        insert: Vec<DataType>,
+        update: V,
+    ) -> Result<(), TableError>
+    where
+        V: IntoIterator<Item = (usize, Modification)>,
+    {
+        let update = table.modifications(update)?;
+        self.perform(
+            table,
+            TableOperation::InsertOrUpdate {
+                row: insert,
+                update,
+            },
+        );
+        Ok(())
+    }
+
+    /// Check every operation against the columns of its table, and split the operations up by
+    /// the shard of their table they must go to.
+    pub(crate) fn prepare(self) -> Result<(TransactionWrites, ResultOrder), TableError> {
+        let mut tables = Vec::with_capacity(self.writes.len());
+        let mut shard_of = Vec::with_capacity(self.writes.len());
+        for (table, ops) in self.writes {
+            let mut i = table.prep_records(ops);
+            table.validate(&mut i)?;
+            let (shards, of) = table.split_by_shard(i.data);
+            tables.push((table.table_name().to_owned(), table.base(), shards));
+            shard_of.push(of);
+        }
+
+        let order = ResultOrder {
+            order: self.order,
+            shard_of,
+        };
+        Ok((TransactionWrites { tables }, order))
+    }
+}
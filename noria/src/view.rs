@@ -1,9 +1,10 @@
 use crate::data::*;
+use crate::table::WriteToken;
 use crate::{Tagged, Tagger};
 use async_bincode::{AsyncBincodeStream, AsyncDestination};
 use futures_util::{
     future, future::TryFutureExt, ready, stream::futures_unordered::FuturesUnordered,
-    stream::StreamExt, stream::TryStreamExt,
+    stream::Stream, stream::StreamExt, stream::TryStreamExt,
 };
 use nom_sql::ColumnSpecification;
 use petgraph::graph::NodeIndex;
@@ -11,7 +12,11 @@ use std::collections::HashMap;
 use std::fmt;
 use std::future::Future;
 use std::io;
+use std::mem;
 use std::net::SocketAddr;
+use std::ops::Bound;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 use tokio_tower::multiplex;
@@ -101,6 +106,33 @@ pub enum ViewError {
     /// The given view is not yet available.
     #[fail(display = "the view is not yet available")]
     NotYetAvailable,
+    /// The subscription was dropped by the view because it was not polled for too long.
+    #[fail(display = "the subscription has expired")]
+    SubscriptionExpired,
+    /// Some of the changes a [`Changes`] stream was asked for are no longer retained, or the
+    /// change log they were in has since started over.
+    #[fail(display = "the requested changes are no longer available")]
+    ChangesTruncated,
+    /// Sharded views only support subscriptions to keys that consist of a single column.
+    #[fail(display = "sharded views only support subscriptions to single-column keys")]
+    UnsupportedSubscription,
+    /// The [`ChangeCursor`] was not created for the table it was used with.
+    #[fail(display = "the cursor does not belong to this table")]
+    CursorMismatch,
+    /// The view cannot answer a read over the given range.
+    ///
+    /// Partially materialized views only support bounded ranges over a single integral key
+    /// column that cover a limited number of keys.
+    #[fail(display = "the view does not support reads over this range")]
+    UnsupportedRange,
+    /// A non-blocking range read found keys in the range missing from a partially materialized
+    /// view. The missing keys are being backfilled, so a later read may succeed.
+    #[fail(display = "some keys in the range are not yet available")]
+    RangeMiss,
+    /// A bound of a range read includes the parameter value where the view's query excludes it,
+    /// or the other way around.
+    #[fail(display = "the range bounds do not match the comparisons in the view's query")]
+    RangeBoundMismatch,
     /// A lower-level error occurred while communicating with Soup.
     #[fail(display = "{}", _0)]
     TransportError(#[cause] failure::Error),
@@ -123,21 +155,211 @@ pub enum ReadQuery {
         keys: Vec<Vec<DataType>>,
         /// Whether to block if a partial replay is triggered
         block: bool,
+        /// Only read once the view reflects all the writes covered by this token
+        after: Option<WriteToken>,
     },
     /// Read the size of a leaf view
     Size {
         /// Where to read from
         target: (NodeIndex, usize),
     },
+    /// Read all keys within a range from a leaf view
+    Range {
+        /// Where to read from
+        target: (NodeIndex, usize),
+        /// Lower bound of the keys to read
+        lower: Bound<Vec<DataType>>,
+        /// Upper bound of the keys to read
+        upper: Bound<Vec<DataType>>,
+        /// Whether to block if a partial replay is triggered
+        block: bool,
+    },
+    /// Subscribe to changes to the given keys of a leaf view
+    Subscribe {
+        /// Where to subscribe
+        target: (NodeIndex, usize),
+        /// Keys to watch for changes
+        keys: Vec<Vec<DataType>>,
+    },
+    /// Fetch the changes seen by a subscription since it was last polled
+    Poll {
+        /// Where the subscription was made
+        target: (NodeIndex, usize),
+        /// The subscription to poll
+        subscription: u64,
+    },
+    /// Read changes from the change log of a base table
+    Changes {
+        /// Which base table shard to read from
+        target: (NodeIndex, usize),
+        /// Epoch of the change log and sequence number of the first change to read, or `None` for
+        /// the oldest change that can still be read
+        from: Option<(u64, u64)>,
+    },
 }
 
 #[doc(hidden)]
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ReadReply<D = ReadReplyBatch> {
-    /// Errors if view isn't ready yet.
+    /// Errors if view isn't ready yet, or is gone. Reads of any kind from a view that is gone are
+    /// answered with this error.
     Normal(Result<Vec<D>, ()>),
     /// Read size of view
     Size(usize),
+    /// Identifier of a new subscription
+    Subscribed(u64),
+    /// Changes seen by a subscription. Errors if the subscription has expired.
+    Deltas(Result<Vec<Delta>, ()>),
+    /// The epoch of a base table's change log, and changes made to the base table along with their
+    /// sequence numbers. Errors if the requested changes are no longer available.
+    Changes(Result<(u64, Vec<(u64, Delta)>), ()>),
+    /// The requested range cannot be read from the view.
+    UnsupportedRange,
+    /// Some of the keys in the requested range were missing, and the read did not block.
+    RangeMiss,
+    /// The bounds of the requested range disagree with the comparisons in the query.
+    RangeBoundMismatch,
+}
+
+/// A single change to the contents of a view, as seen by a [`Subscription`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Delta {
+    /// The row was added to the view.
+    Positive(Vec<DataType>),
+    /// The row was removed from the view.
+    Negative(Vec<DataType>),
+}
+
+impl Delta {
+    /// The row that was added or removed.
+    pub fn row(&self) -> &[DataType] {
+        match *self {
+            Delta::Positive(ref r) | Delta::Negative(ref r) => &r[..],
+        }
+    }
+
+    /// Whether the row was added to the view.
+    pub fn is_positive(&self) -> bool {
+        matches!(*self, Delta::Positive(_))
+    }
+}
+
+/// A stream of the changes made to a set of keys in a view.
+///
+/// Each item holds the changes that were made to the subscribed keys since the previous item, in
+/// the order they were applied to the view. The stream ends after it yields an error.
+///
+/// Created with [`View::subscribe`].
+pub struct Subscription {
+    inner: Pin<Box<dyn Stream<Item = Result<Vec<Delta>, ViewError>> + Send>>,
+}
+
+impl fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscription").finish()
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Result<Vec<Delta>, ViewError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+/// A single change to a base table, as seen by a [`Changes`] stream.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    /// The shard of the base table that the change was made to.
+    pub shard: usize,
+    /// The position of the change in the shard's change log.
+    pub seq: u64,
+    /// The row that was added to or removed from the base table.
+    pub delta: Delta,
+}
+
+/// The position of a [`Changes`] stream in the change log of each shard of a base table.
+///
+/// A cursor can be saved and later passed to [`ControllerHandle::changes`] to resume following a
+/// base table right after the last change that was seen.
+///
+/// [`ControllerHandle::changes`]: crate::ControllerHandle::changes
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeCursor(Vec<Option<(u64, u64)>>);
+
+impl ChangeCursor {
+    /// A cursor that starts at the oldest change each shard still retains.
+    pub fn oldest() -> Self {
+        Self::default()
+    }
+
+    /// The sequence number of the next change to read from the given shard.
+    ///
+    /// Returns `None` if no change from the shard has been seen yet.
+    pub fn position(&self, shard: usize) -> Option<u64> {
+        self.0.get(shard).cloned().flatten().map(|(_, seq)| seq)
+    }
+
+    fn advance(&mut self, shard: usize, epoch: u64, changes: &[Change]) {
+        if let Some(change) = changes.last() {
+            if self.0.len() <= shard {
+                self.0.resize(shard + 1, None);
+            }
+            self.0[shard] = Some((epoch, change.seq + 1));
+        }
+    }
+}
+
+/// A stream of the changes made to a base table.
+///
+/// Each item holds the rows that were added to and removed from the base table since the previous
+/// item, after any updates have been resolved into the removal of the old row and the addition of
+/// the new one. Changes from the same shard are in the order they were applied, while changes to
+/// different shards are not ordered with respect to each other. The stream ends after it yields
+/// an error.
+///
+/// Unless the base table is persisted, every shard keeps only a bounded number of its most recent
+/// changes, and starts its change log over when it is restarted. A stream that falls too far
+/// behind, or that resumes from a cursor into changes that are no longer available, fails with
+/// [`ViewError::ChangesTruncated`].
+///
+/// Created with [`ControllerHandle::changes`].
+///
+/// [`ControllerHandle::changes`]: crate::ControllerHandle::changes
+pub struct Changes {
+    // yields the changes of one shard at a time, along with the epoch of its change log
+    inner: Pin<Box<dyn Stream<Item = Result<(usize, u64, Vec<Change>), ViewError>> + Send>>,
+    cursor: ChangeCursor,
+}
+
+impl fmt::Debug for Changes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Changes")
+            .field("cursor", &self.cursor)
+            .finish()
+    }
+}
+
+impl Changes {
+    /// The position just after the last change that this stream has yielded.
+    pub fn cursor(&self) -> &ChangeCursor {
+        &self.cursor
+    }
+}
+
+impl Stream for Changes {
+    type Item = Result<Vec<Change>, ViewError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let changes = ready!(self.inner.as_mut().poll_next(cx));
+        Poll::Ready(changes.map(|changes| {
+            changes.map(|(shard, epoch, changes)| {
+                self.cursor.advance(shard, epoch, &changes);
+                changes
+            })
+        }))
+    }
 }
 
 #[doc(hidden)]
@@ -146,7 +368,11 @@ pub struct ViewBuilder {
     pub node: NodeIndex,
     pub columns: Vec<String>,
     pub schema: Option<Vec<ColumnSpecification>>,
+    /// The columns that the view is keyed on.
+    pub key: Vec<usize>,
     pub shards: Vec<SocketAddr>,
+    /// Other readers that hold the same rows as `node`, along with the addresses of their shards.
+    pub replicas: Vec<(NodeIndex, Vec<SocketAddr>)>,
 }
 
 impl ViewBuilder {
@@ -156,70 +382,98 @@ impl ViewBuilder {
         &self,
         rpcs: Arc<Mutex<HashMap<(SocketAddr, usize), ViewRpc>>>,
     ) -> Result<View, io::Error> {
-        let node = self.node;
         let columns = self.columns.clone();
-        let shards = self.shards.clone();
         let schema = self.schema.clone();
+        let key = self.key.clone();
 
-        let mut addrs = Vec::with_capacity(shards.len());
-        let mut conns = Vec::with_capacity(shards.len());
-
-        for (shardi, &addr) in shards.iter().enumerate() {
-            use std::collections::hash_map::Entry;
-
-            addrs.push(addr);
-
-            // one entry per shard so that we can send sharded requests in parallel even if
-            // they happen to be targeting the same machine.
-            let mut rpcs = rpcs.lock().unwrap();
-            let s = match rpcs.entry((addr, shardi)) {
-                Entry::Occupied(e) => e.get().clone(),
-                Entry::Vacant(h) => {
-                    // TODO: maybe always use the same local port?
-                    let (c, w) = Buffer::pair(
-                        ConcurrencyLimit::new(
-                            Balance::from_entropy(make_views_discover(addr)),
-                            crate::PENDING_LIMIT,
-                        ),
-                        crate::BUFFER_TO_POOL,
-                    );
-                    use tracing_futures::Instrument;
-                    tokio::spawn(w.instrument(tracing::debug_span!(
-                        "view_worker",
-                        addr = %addr,
-                        shard = shardi
-                    )));
-                    h.insert(c.clone());
-                    c
+        let replicas: Vec<_> = Some((self.node, &self.shards))
+            .into_iter()
+            .chain(
+                self.replicas
+                    .iter()
+                    .map(|&(node, ref shards)| (node, shards)),
+            )
+            .map(|(node, shards)| {
+                let mut conns = Vec::with_capacity(shards.len());
+                for (shardi, &addr) in shards.iter().enumerate() {
+                    use std::collections::hash_map::Entry;
+
+                    // one entry per shard so that we can send sharded requests in parallel even if
+                    // they happen to be targeting the same machine.
+                    let mut rpcs = rpcs.lock().unwrap();
+                    let s = match rpcs.entry((addr, shardi)) {
+                        Entry::Occupied(e) => e.get().clone(),
+                        Entry::Vacant(h) => {
+                            // TODO: maybe always use the same local port?
+                            let (c, w) = Buffer::pair(
+                                ConcurrencyLimit::new(
+                                    Balance::from_entropy(make_views_discover(addr)),
+                                    crate::PENDING_LIMIT,
+                                ),
+                                crate::BUFFER_TO_POOL,
+                            );
+                            use tracing_futures::Instrument;
+                            tokio::spawn(w.instrument(tracing::debug_span!(
+                                "view_worker",
+                                addr = %addr,
+                                shard = shardi
+                            )));
+                            h.insert(c.clone());
+                            c
+                        }
+                    };
+                    conns.push(s);
                 }
-            };
-            conns.push(s);
-        }
+
+                Replica {
+                    node,
+                    shards: conns,
+                    shard_addrs: shards.clone(),
+                }
+            })
+            .collect();
 
         let tracer = tracing::dispatcher::get_default(|d| d.clone());
         Ok(View {
-            node,
             schema,
             columns,
-            shard_addrs: addrs,
-            shards: conns,
+            key,
+            down: Arc::new(replicas.iter().map(|_| AtomicBool::new(false)).collect()),
+            replicas,
+            next: 0,
             tracer,
         })
     }
 }
 
+/// One copy of the reader behind a [`View`], along with connections to each of its shards.
+#[derive(Clone)]
+struct Replica {
+    node: NodeIndex,
+    shards: Vec<ViewRpc>,
+    shard_addrs: Vec<SocketAddr>,
+}
+
 /// A `View` is used to query previously defined external views.
 ///
 /// Note that if you create multiple `View` handles from a single `ControllerHandle`, they may
 /// share connections to the Soup workers.
+///
+/// Views that are replicated across several workers spread their reads over all the replicas in
+/// turn. A read that fails to reach a replica is retried on the other replicas, and the
+/// unreachable replica is skipped by later reads until all replicas have failed.
 #[derive(Clone)]
 pub struct View {
-    node: NodeIndex,
     columns: Vec<String>,
     schema: Option<Vec<ColumnSpecification>>,
+    key: Vec<usize>,
 
-    shards: Vec<ViewRpc>,
-    shard_addrs: Vec<SocketAddr>,
+    replicas: Vec<Replica>,
+    /// Which replicas could not be reached the last time they were read from. Shared among all
+    /// clones of this handle.
+    down: Arc<Vec<AtomicBool>>,
+    /// The replica that the next read goes to.
+    next: usize,
 
     tracer: tracing::Dispatch,
 }
@@ -227,9 +481,10 @@ pub struct View {
 impl fmt::Debug for View {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("View")
-            .field("node", &self.node)
+            .field("node", &self.replicas[0].node)
             .field("columns", &self.columns)
-            .field("shard_addrs", &self.shard_addrs)
+            .field("shard_addrs", &self.replicas[0].shard_addrs)
+            .field("replicas", &self.replicas.len())
             .finish()
     }
 }
@@ -247,115 +502,245 @@ impl Service<(Vec<Vec<DataType>>, bool)> for View {
     type Future = crate::doc_mock::Future<Result<Self::Response, Self::Error>>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        for s in &mut self.shards {
+        let r = self.pick();
+        if r != self.next {
+            // release any sender slots we reserved on the replica we were going to use
+            for s in &mut self.replicas[self.next].shards {
+                *s = s.clone();
+            }
+            self.next = r;
+        }
+
+        for s in &mut self.replicas[r].shards {
             ready!(s.poll_ready(cx)).map_err(ViewError::from)?;
         }
         Poll::Ready(Ok(()))
     }
 
     fn call(&mut self, (keys, block): (Vec<Vec<DataType>>, bool)) -> Self::Future {
+        self.read(keys, block, None)
+    }
+}
+
+/// The index of a replica, the reader node of that replica, and connections to its shards.
+type Attempt = (usize, NodeIndex, Vec<ViewRpc>);
+
+impl View {
+    /// The replica that the next read should go to: the next one in turn that is not known to be
+    /// down, or just the next one in turn if they all are.
+    fn pick(&self) -> usize {
+        let n = self.replicas.len();
+        (0..n)
+            .map(|i| (self.next + i) % n)
+            .find(|&r| !self.down[r].load(Ordering::Relaxed))
+            .unwrap_or(self.next)
+    }
+
+    /// Take the connections to the replica that `poll_ready` prepared for the next read, followed
+    /// by those to every other replica that is not known to be down, and move on to the next
+    /// replica for the read after that.
+    fn take_replicas(&mut self) -> Vec<Attempt> {
+        let n = self.replicas.len();
+        let first = self.next;
+        self.next = (first + 1) % n;
+
+        // the ready connections hold the sender slots that `poll_ready` reserved, so we take those
+        // and leave fresh clones behind.
+        let ready = &mut self.replicas[first];
+        let mut attempts = vec![(
+            first,
+            ready.node,
+            ready
+                .shards
+                .iter_mut()
+                .map(|s| mem::replace(s, s.clone()))
+                .collect(),
+        )];
+        for r in (1..n).map(|i| (first + i) % n) {
+            if !self.down[r].load(Ordering::Relaxed) {
+                let replica = &self.replicas[r];
+                attempts.push((r, replica.node, replica.shards.clone()));
+            }
+        }
+        attempts
+    }
+
+    fn read(
+        &mut self,
+        keys: Vec<Vec<DataType>>,
+        block: bool,
+        after: Option<WriteToken>,
+    ) -> impl Future<Output = Result<Vec<Results>, ViewError>> + Send {
         let span = if crate::trace_next_op() {
             Some(tracing::trace_span!(
                 "view-request",
                 ?keys,
-                node = self.node.index()
+                node = self.replicas[self.next].node.index()
             ))
         } else {
             None
         };
 
-        let columns = Arc::from(&self.columns[..]);
-        if self.shards.len() == 1 {
-            let request = Tagged::from(ReadQuery::Normal {
-                target: (self.node, 0),
-                keys,
-                block,
-            });
+        let columns: Arc<[String]> = Arc::from(&self.columns[..]);
+        let mut keys = Some(keys);
+        with_failover(
+            self.take_replicas(),
+            Arc::clone(&self.down),
+            move |node, shards, last| {
+                // only copy the keys if we may have to send them again
+                let keys = if last {
+                    keys.take().unwrap()
+                } else {
+                    keys.clone().unwrap()
+                };
+                read_shards(node, shards, keys, block, after.clone(), span.clone())
+            },
+        )
+        .map_ok(move |rows| {
+            rows.into_iter()
+                .map(|rows| Results::new(rows.into(), Arc::clone(&columns)))
+                .collect()
+        })
+    }
+}
 
-            let _guard = span.as_ref().map(tracing::Span::enter);
-            tracing::trace!("submit request");
+/// Send a request to each of the given replicas in turn until one of them can be reached, and
+/// keep track of which replicas could not be.
+///
+/// The connections of the first replica must already be ready.
+async fn with_failover<T, F, Fut>(
+    replicas: Vec<Attempt>,
+    down: Arc<Vec<AtomicBool>>,
+    mut request: F,
+) -> Result<T, ViewError>
+where
+    F: FnMut(NodeIndex, &mut [ViewRpc], bool) -> Fut,
+    Fut: Future<Output = Result<T, ViewError>>,
+{
+    let last = replicas.len() - 1;
+    for (i, (r, node, mut shards)) in replicas.into_iter().enumerate() {
+        let ready = if i == 0 {
+            Ok(())
+        } else {
+            ready_all(&mut shards).await
+        };
+        let res = match ready {
+            Ok(()) => request(node, &mut shards[..], i == last).await,
+            Err(e) => Err(e),
+        };
 
-            return future::Either::Left(
-                self.shards[0]
+        match res {
+            Err(ViewError::TransportError(e)) => {
+                down[r].store(true, Ordering::Relaxed);
+                if i == last {
+                    return Err(ViewError::TransportError(e));
+                }
+            }
+            res => {
+                down[r].store(false, Ordering::Relaxed);
+                return res;
+            }
+        }
+    }
+    unreachable!("views always have at least one replica");
+}
+
+async fn ready_all(shards: &mut [ViewRpc]) -> Result<(), ViewError> {
+    for shard in shards {
+        future::poll_fn(|cx| shard.poll_ready(cx)).await?;
+    }
+    Ok(())
+}
+
+/// Look up the given keys in the shards of a reader whose connections are all ready.
+fn read_shards(
+    node: NodeIndex,
+    shards: &mut [ViewRpc],
+    keys: Vec<Vec<DataType>>,
+    block: bool,
+    after: Option<WriteToken>,
+    span: Option<tracing::Span>,
+) -> impl Future<Output = Result<Vec<ReadReplyBatch>, ViewError>> + Send {
+    if shards.len() == 1 {
+        let request = Tagged::from(ReadQuery::Normal {
+            target: (node, 0),
+            keys,
+            block,
+            after,
+        });
+
+        let _guard = span.as_ref().map(tracing::Span::enter);
+        tracing::trace!("submit request");
+
+        return future::Either::Left(shards[0].call(request).map_err(ViewError::from).and_then(
+            |reply| async move {
+                match reply.v {
+                    ReadReply::Normal(Ok(rows)) => Ok(rows),
+                    ReadReply::Normal(Err(())) => Err(ViewError::NotYetAvailable),
+                    _ => unreachable!(),
+                }
+            },
+        ));
+    }
+
+    if let Some(ref span) = span {
+        span.in_scope(|| tracing::trace!("shard request"));
+    }
+    assert!(keys.iter().all(|k| k.len() == 1));
+    let mut shard_queries = vec![Vec::new(); shards.len()];
+    for key in keys {
+        let shard = crate::shard_by(&key[0], shards.len());
+        shard_queries[shard].push(key);
+    }
+
+    future::Either::Right(
+        shards
+            .iter_mut()
+            .enumerate()
+            .zip(shard_queries.into_iter())
+            .filter_map(|((shardi, shard), shard_queries)| {
+                if shard_queries.is_empty() {
+                    // poll_ready reserves a sender slot which we have to release
+                    // we do that by dropping the old handle and replacing it with a clone
+                    // https://github.com/tokio-rs/tokio/issues/898
+                    *shard = shard.clone();
+                    None
+                } else {
+                    Some(((shardi, shard), shard_queries))
+                }
+            })
+            .map(move |((shardi, shard), shard_queries)| {
+                let request = Tagged::from(ReadQuery::Normal {
+                    target: (node, shardi),
+                    keys: shard_queries,
+                    block,
+                    after: after.clone(),
+                });
+
+                let _guard = span.as_ref().map(tracing::Span::enter);
+                // make a span per shard
+                let span = if span.is_some() {
+                    Some(tracing::trace_span!("view-shard", shardi))
+                } else {
+                    None
+                };
+                let _guard = span.as_ref().map(tracing::Span::enter);
+                tracing::trace!("submit request shard");
+
+                shard
                     .call(request)
                     .map_err(ViewError::from)
-                    .and_then(move |reply| async move {
+                    .and_then(|reply| async move {
                         match reply.v {
-                            ReadReply::Normal(Ok(rows)) => Ok(rows
-                                .into_iter()
-                                .map(|rows| Results::new(rows.into(), Arc::clone(&columns)))
-                                .collect()),
+                            ReadReply::Normal(Ok(rows)) => Ok(rows),
                             ReadReply::Normal(Err(())) => Err(ViewError::NotYetAvailable),
                             _ => unreachable!(),
                         }
-                    }),
-            );
-        }
-
-        if let Some(ref span) = span {
-            span.in_scope(|| tracing::trace!("shard request"));
-        }
-        assert!(keys.iter().all(|k| k.len() == 1));
-        let mut shard_queries = vec![Vec::new(); self.shards.len()];
-        for key in keys {
-            let shard = crate::shard_by(&key[0], self.shards.len());
-            shard_queries[shard].push(key);
-        }
-
-        let node = self.node;
-        future::Either::Right(
-            self.shards
-                .iter_mut()
-                .enumerate()
-                .zip(shard_queries.into_iter())
-                .filter_map(|((shardi, shard), shard_queries)| {
-                    if shard_queries.is_empty() {
-                        // poll_ready reserves a sender slot which we have to release
-                        // we do that by dropping the old handle and replacing it with a clone
-                        // https://github.com/tokio-rs/tokio/issues/898
-                        *shard = shard.clone();
-                        None
-                    } else {
-                        Some(((shardi, shard), shard_queries))
-                    }
-                })
-                .map(move |((shardi, shard), shard_queries)| {
-                    let request = Tagged::from(ReadQuery::Normal {
-                        target: (node, shardi),
-                        keys: shard_queries,
-                        block,
-                    });
-
-                    let _guard = span.as_ref().map(tracing::Span::enter);
-                    // make a span per shard
-                    let span = if span.is_some() {
-                        Some(tracing::trace_span!("view-shard", shardi))
-                    } else {
-                        None
-                    };
-                    let _guard = span.as_ref().map(tracing::Span::enter);
-                    tracing::trace!("submit request shard");
-
-                    shard
-                        .call(request)
-                        .map_err(ViewError::from)
-                        .and_then(|reply| async move {
-                            match reply.v {
-                                ReadReply::Normal(Ok(rows)) => Ok(rows),
-                                ReadReply::Normal(Err(())) => Err(ViewError::NotYetAvailable),
-                                _ => unreachable!(),
-                            }
-                        })
-                })
-                .collect::<FuturesUnordered<_>>()
-                .try_concat()
-                .map_ok(move |rows| {
-                    rows.into_iter()
-                        .map(|rows| Results::new(rows.into(), Arc::clone(&columns)))
-                        .collect()
-                }),
-        )
-    }
+                    })
+            })
+            .collect::<FuturesUnordered<_>>()
+            .try_concat(),
+    )
 }
 
 #[allow(clippy::len_without_is_empty)]
@@ -376,28 +761,31 @@ impl View {
     pub async fn len(&mut self) -> Result<usize, ViewError> {
         future::poll_fn(|cx| self.poll_ready(cx)).await?;
 
-        let node = self.node;
-        let mut rsps = self
-            .shards
-            .iter_mut()
-            .enumerate()
-            .map(|(shardi, shard)| {
-                shard.call(Tagged::from(ReadQuery::Size {
-                    target: (node, shardi),
-                }))
-            })
-            .collect::<FuturesUnordered<_>>();
-
-        let mut nrows = 0;
-        while let Some(reply) = rsps.next().await.transpose()? {
-            if let ReadReply::Size(rows) = reply.v {
-                nrows += rows;
-            } else {
-                unreachable!();
-            }
-        }
+        let replicas = self.take_replicas();
+        with_failover(replicas, Arc::clone(&self.down), |node, shards, _| {
+            let mut rsps = shards
+                .iter_mut()
+                .enumerate()
+                .map(|(shardi, shard)| {
+                    shard.call(Tagged::from(ReadQuery::Size {
+                        target: (node, shardi),
+                    }))
+                })
+                .collect::<FuturesUnordered<_>>();
 
-        Ok(nrows)
+            async move {
+                let mut nrows = 0;
+                while let Some(reply) = rsps.next().await.transpose()? {
+                    match reply.v {
+                        ReadReply::Size(rows) => nrows += rows,
+                        ReadReply::Normal(Err(())) => return Err(ViewError::NotYetAvailable),
+                        _ => unreachable!(),
+                    }
+                }
+                Ok::<_, ViewError>(nrows)
+            }
+        })
+        .await
     }
 
     /// Retrieve the query results for the given parameter values.
@@ -423,6 +811,27 @@ impl View {
         Ok(rs.into_iter().next().unwrap())
     }
 
+    /// Retrieve the query results for the given parameter value once this view reflects all the
+    /// writes covered by `token`.
+    ///
+    /// Writes to a [`Table`](crate::Table) that returns write tokens (see
+    /// [`Table::set_write_tokens`](crate::Table::set_write_tokens)) return a [`WriteToken`] as
+    /// part of their [`WriteResult`](crate::WriteResult), and reading with that token is
+    /// guaranteed to observe the effects of the write (and of any earlier writes to the same base
+    /// table shards). The method blocks until that is the case, and until the results for the key
+    /// are available.
+    pub async fn lookup_after(
+        &mut self,
+        key: &[DataType],
+        token: &WriteToken,
+    ) -> Result<Results, ViewError> {
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+        let rs = self
+            .read(vec![Vec::from(key)], true, Some(token.clone()))
+            .await?;
+        Ok(rs.into_iter().next().unwrap())
+    }
+
     /// Retrieve the first query result for the given parameter value.
     ///
     /// The method will block if the results are not yet available only when `block` is `true`.
@@ -435,6 +844,297 @@ impl View {
         let rs = self.multi_lookup(vec![Vec::from(key)], block).await?;
         Ok(rs.into_iter().next().unwrap().into_iter().next())
     }
+
+    /// Retrieve the query results for all keys that fall between the given bounds.
+    ///
+    /// Keys are compared lexicographically against each bound, but only on as many columns as the
+    /// bound has values. A bound can therefore name just a prefix of a compound key, in which case
+    /// every key that starts with that prefix compares equal to the bound.
+    ///
+    /// Range reads are sent to every shard of the view. Fully materialized views answer them
+    /// directly from their state. Partially materialized views only support bounded ranges over a
+    /// single integral key column, which are read as if each key in the range had been looked up
+    /// individually (backfilling any missing keys). Other ranges over a partially materialized
+    /// view fail with [`ViewError::UnsupportedRange`]. If `block` is false and any key in the
+    /// range is missing, the read fails with [`ViewError::RangeMiss`] rather than returning only
+    /// part of the range.
+    ///
+    /// The rows are returned in key order, also when they come from several shards.
+    ///
+    /// SQL queries that compare a column to a placeholder with `<`, `<=`, `>` or `>=` are keyed on
+    /// their equality parameters followed by that column, and are always fully materialized. For
+    /// such queries, a bound that covers the whole key must be `Included` where the query's
+    /// comparison includes the parameter value (`<=`, `>=`) and `Excluded` where it does not (`<`,
+    /// `>`), or the read fails with [`ViewError::RangeBoundMismatch`].
+    ///
+    /// The method will block if the results are not yet available only when `block` is `true`.
+    pub async fn lookup_range(
+        &mut self,
+        lower: Bound<&[DataType]>,
+        upper: Bound<&[DataType]>,
+        block: bool,
+    ) -> Result<Results, ViewError> {
+        future::poll_fn(|cx| self.poll_ready(cx)).await?;
+
+        let span = if crate::trace_next_op() {
+            Some(tracing::trace_span!(
+                "view-range-request",
+                ?lower,
+                ?upper,
+                node = self.replicas[self.next].node.index()
+            ))
+        } else {
+            None
+        };
+        let _guard = span.as_ref().map(tracing::Span::enter);
+        tracing::trace!("submit range request");
+
+        let lower = owned_bound(lower);
+        let upper = owned_bound(upper);
+        let replicas = self.take_replicas();
+        let mut rows = with_failover(replicas, Arc::clone(&self.down), |node, shards, _| {
+            let mut rsps = shards
+                .iter_mut()
+                .enumerate()
+                .map(|(shardi, shard)| {
+                    shard.call(Tagged::from(ReadQuery::Range {
+                        target: (node, shardi),
+                        lower: lower.clone(),
+                        upper: upper.clone(),
+                        block,
+                    }))
+                })
+                .collect::<FuturesUnordered<_>>();
+
+            async move {
+                let mut rows = Vec::new();
+                while let Some(reply) = rsps.next().await.transpose()? {
+                    match reply.v {
+                        ReadReply::Normal(Ok(batches)) => {
+                            for batch in batches {
+                                rows.extend(batch);
+                            }
+                        }
+                        ReadReply::Normal(Err(())) => return Err(ViewError::NotYetAvailable),
+                        ReadReply::UnsupportedRange => return Err(ViewError::UnsupportedRange),
+                        ReadReply::RangeMiss => return Err(ViewError::RangeMiss),
+                        ReadReply::RangeBoundMismatch => return Err(ViewError::RangeBoundMismatch),
+                        _ => unreachable!(),
+                    }
+                }
+                Ok(rows)
+            }
+        })
+        .await?;
+
+        if self.replicas[0].shards.len() > 1 {
+            // each shard returns its rows in key order, but the shards' rows are interleaved.
+            // the sort is stable, so rows with the same key stay in the order the shard had them.
+            let key = &self.key;
+            rows.sort_by(|a, b| {
+                key.iter()
+                    .map(|&c| a[c].cmp(&b[c]))
+                    .find(|o| *o != std::cmp::Ordering::Equal)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+        }
+
+        Ok(Results::new(rows, Arc::from(&self.columns[..])))
+    }
+
+    /// Subscribe to changes to the given keys of this view.
+    ///
+    /// The returned [`Subscription`] yields every change made to the rows under any of the keys
+    /// from the moment this method returns. Changes that were made earlier are not included, so
+    /// to follow a key from a consistent starting point, subscribe to it *before* looking it up,
+    /// and be prepared to see changes that are already reflected in the lookup results.
+    ///
+    /// The view keeps the changes for each subscription until they are picked up by polling the
+    /// stream, and drops subscriptions that go unpolled for too long. Each shard of the view
+    /// pushes its changes to the stream as soon as they become visible to reads.
+    pub async fn subscribe(&mut self, keys: Vec<Vec<DataType>>) -> Result<Subscription, ViewError> {
+        // a subscription lives in a single replica, so it can't move to another one
+        let Replica {
+            node,
+            shards: replica,
+            ..
+        } = self.replicas[self.pick()].clone();
+        let nshards = replica.len();
+        let mut shard_keys = vec![Vec::new(); nshards];
+        if nshards == 1 {
+            shard_keys[0] = keys;
+        } else {
+            if keys.iter().any(|k| k.len() != 1) {
+                return Err(ViewError::UnsupportedSubscription);
+            }
+            for key in keys {
+                shard_keys[crate::shard_by(&key[0], nshards)].push(key);
+            }
+        }
+
+        let mut subscriptions = Vec::new();
+        for (shardi, keys) in shard_keys.into_iter().enumerate() {
+            if keys.is_empty() {
+                continue;
+            }
+
+            let mut shard = replica[shardi].clone();
+            future::poll_fn(|cx| shard.poll_ready(cx)).await?;
+            let reply = shard
+                .call(Tagged::from(ReadQuery::Subscribe {
+                    target: (node, shardi),
+                    keys,
+                }))
+                .await?;
+            match reply.v {
+                ReadReply::Subscribed(id) => subscriptions.push((shard, (node, shardi), id)),
+                ReadReply::Normal(Err(())) => return Err(ViewError::NotYetAvailable),
+                _ => unreachable!(),
+            }
+        }
+
+        // every shard holds on to our polls until it has something to report, so we just keep a
+        // poll outstanding at each shard and hand out whatever comes back first.
+        let shards = subscriptions.into_iter().map(|(shard, target, id)| {
+            futures_util::stream::unfold(Some(shard), move |shard| async move {
+                let mut shard = shard?;
+                match poll_subscription(&mut shard, target, id).await {
+                    Ok(deltas) => Some((Ok(deltas), Some(shard))),
+                    Err(e) => Some((Err(e), None)),
+                }
+            })
+            .boxed()
+        });
+        let deltas = futures_util::stream::select_all(shards)
+            .filter(|deltas| future::ready(deltas.as_ref().map_or(true, |ds| !ds.is_empty())))
+            .scan(false, |failed, deltas| {
+                // the subscription is no good once any of its shards is gone
+                if *failed {
+                    return future::ready(None);
+                }
+                *failed = deltas.is_err();
+                future::ready(Some(deltas))
+            });
+
+        Ok(Subscription {
+            inner: Box::pin(deltas),
+        })
+    }
+
+    /// Follow the change log of the base table this handle was built for, starting at `cursor`.
+    pub(crate) fn into_changes(mut self, cursor: ChangeCursor) -> Result<Changes, ViewError> {
+        // base tables are never replicated
+        let Replica { node, shards, .. } = self.replicas.swap_remove(0);
+        if cursor.0.len() > shards.len() {
+            return Err(ViewError::CursorMismatch);
+        }
+
+        // every shard holds on to our reads until it has changes for us, so we just keep a read
+        // outstanding at each shard and hand out whatever comes back first.
+        let shards = shards.into_iter().enumerate().map(|(shardi, shard)| {
+            let from = cursor.0.get(shardi).cloned().flatten();
+            futures_util::stream::unfold(Some((shard, from)), move |state| async move {
+                let (mut shard, from) = state?;
+                match read_changes(&mut shard, (node, shardi), from).await {
+                    Ok((epoch, changes)) => {
+                        let next = changes.last().map(|c| (epoch, c.seq + 1)).or(from);
+                        Some((Ok((shardi, epoch, changes)), Some((shard, next))))
+                    }
+                    Err(e) => Some((Err(e), None)),
+                }
+            })
+            .boxed()
+        });
+        let changes = futures_util::stream::select_all(shards)
+            .filter(|changes| {
+                future::ready(changes.as_ref().map_or(true, |(_, _, cs)| !cs.is_empty()))
+            })
+            .scan(false, |failed, changes| {
+                // the stream can't skip over the changes of a shard it failed to read from
+                if *failed {
+                    return future::ready(None);
+                }
+                *failed = changes.is_err();
+                future::ready(Some(changes))
+            });
+
+        Ok(Changes {
+            inner: Box::pin(changes),
+            cursor,
+        })
+    }
+
+    /// Retrieve the query results for all keys that start with the given prefix.
+    ///
+    /// This is a shorthand for a [`View::lookup_range`] where both bounds are the given prefix.
+    pub async fn lookup_prefix(
+        &mut self,
+        prefix: &[DataType],
+        block: bool,
+    ) -> Result<Results, ViewError> {
+        self.lookup_range(Bound::Included(prefix), Bound::Included(prefix), block)
+            .await
+    }
+}
+
+/// Wait for the changes one shard of a base table makes starting at the given position, and
+/// return them along with the epoch of the shard's change log.
+///
+/// The shard replies with no changes if none are made for a while.
+async fn read_changes(
+    shard: &mut ViewRpc,
+    target: (NodeIndex, usize),
+    from: Option<(u64, u64)>,
+) -> Result<(u64, Vec<Change>), ViewError> {
+    future::poll_fn(|cx| shard.poll_ready(cx)).await?;
+    let reply = shard
+        .call(Tagged::from(ReadQuery::Changes { target, from }))
+        .await?;
+    match reply.v {
+        ReadReply::Changes(Ok((epoch, cs))) => Ok((
+            epoch,
+            cs.into_iter()
+                .map(|(seq, delta)| Change {
+                    shard: target.1,
+                    seq,
+                    delta,
+                })
+                .collect(),
+        )),
+        ReadReply::Changes(Err(())) => Err(ViewError::ChangesTruncated),
+        _ => unreachable!(),
+    }
+}
+
+/// Wait for the changes seen by one shard's part of a subscription since it was last polled.
+///
+/// The shard replies with no changes if none arrive for a while, so that we know that the
+/// subscription is still alive.
+async fn poll_subscription(
+    shard: &mut ViewRpc,
+    target: (NodeIndex, usize),
+    subscription: u64,
+) -> Result<Vec<Delta>, ViewError> {
+    future::poll_fn(|cx| shard.poll_ready(cx)).await?;
+    let reply = shard
+        .call(Tagged::from(ReadQuery::Poll {
+            target,
+            subscription,
+        }))
+        .await?;
+    match reply.v {
+        ReadReply::Deltas(Ok(ds)) => Ok(ds),
+        ReadReply::Deltas(Err(())) => Err(ViewError::SubscriptionExpired),
+        _ => unreachable!(),
+    }
+}
+
+fn owned_bound(bound: Bound<&[DataType]>) -> Bound<Vec<DataType>> {
+    match bound {
+        Bound::Included(k) => Bound::Included(Vec::from(k)),
+        Bound::Excluded(k) => Bound::Excluded(Vec::from(k)),
+        Bound::Unbounded => Bound::Unbounded,
+    }
 }
 
 #[derive(Debug, Default)]
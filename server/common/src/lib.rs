@@ -21,7 +21,11 @@ impl SizeOf for DataType {
         use std::mem::size_of_val;
 
         let inner = match *self {
-            DataType::Text(ref t) => size_of_val(t) as u64 + t.to_bytes().len() as u64,
+            DataType::Text(ref t) | DataType::Json(ref t) => {
+                size_of_val(t) as u64 + t.to_bytes().len() as u64
+            }
+            DataType::Decimal(ref d) => size_of_val(&**d) as u64,
+            DataType::ByteArray(ref b) => size_of_val(&**b) as u64 + b.len() as u64,
             _ => 0u64,
         };
 
@@ -65,7 +69,7 @@ mod tests {
     #[test]
     fn data_type_mem_size() {
         use arccstr::ArcCStr;
-        use chrono::NaiveDateTime;
+        use chrono::{NaiveDate, NaiveDateTime};
         use std::convert::TryFrom;
         use std::mem::{size_of, size_of_val};
 
@@ -87,6 +91,14 @@ mod tests {
         assert_eq!(size_of_val(&time) as u64, time.size_of());
         assert_eq!(time.deep_size_of(), 16); // DataType + inline NaiveDateTime
 
+        let dec = DataType::from(noria::Decimal::new(12345, 2));
+        let bytes = DataType::from(vec![0u8, 1, 2]);
+        let date = DataType::Date(NaiveDate::from_ymd(2020, 2, 29));
+        assert_eq!(size_of_val(&dec), 16);
+        assert_eq!(dec.deep_size_of(), 16 + size_of::<noria::Decimal>() as u64);
+        assert_eq!(bytes.deep_size_of(), 16 + 24 + 3); // DataType + Vec + 3 bytes
+        assert_eq!(date.deep_size_of(), 16); // DataType + inline NaiveDate
+
         assert_eq!(size_of_val(&rec), 24);
         assert_eq!(rec.size_of(), 24 + 3 * 16);
         assert_eq!(rec.deep_size_of(), 24 + 3 * 16 + (8 + 16));
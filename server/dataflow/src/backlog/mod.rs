@@ -1,9 +1,22 @@
+use crate::node::special::RangeParameter;
 use crate::prelude::*;
 use ahash::RandomState;
 use common::SizeOf;
+use noria::WriteToken;
 use rand::prelude::*;
 use std::borrow::Cow;
-use std::sync::Arc;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::future::Future;
+use std::ops::Bound;
+use std::sync::atomic::{self, AtomicBool, AtomicUsize};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::task::{Poll, Waker};
+use std::time;
+
+/// Subscriptions that have not been polled for this long are assumed to belong to clients that
+/// have gone away, and are dropped.
+const SUBSCRIPTION_TIMEOUT: time::Duration = time::Duration::from_secs(30);
 
 /// Allocate a new end-user facing result table.
 pub(crate) fn new(cols: usize, key: &[usize]) -> (SingleReadHandle, WriteHandle) {
@@ -63,6 +76,15 @@ fn new_inner(
         _ => make!(Many),
     };
 
+    // partial state can't answer range reads anyway, so only full state keeps its keys in order
+    let index = if trigger.is_none() {
+        Some(Arc::new(RwLock::new(BTreeSet::new())))
+    } else {
+        None
+    };
+    let subscribers = Arc::new(Subscribers::default());
+    let progress = Arc::new(Mutex::new(Progress::default()));
+    let retired = Arc::new(AtomicBool::new(false));
     let w = WriteHandle {
         partial: trigger.is_some(),
         handle: w,
@@ -70,11 +92,28 @@ fn new_inner(
         cols,
         contiguous,
         mem_size: 0,
+        index: index.clone(),
+        index_added: Vec::new(),
+        index_removed: Vec::new(),
+        subscribers: Arc::clone(&subscribers),
+        evicted: HashMap::new(),
+        refilling: HashMap::new(),
+        trigger: trigger.clone(),
+        unpublished: HashMap::new(),
+        progress: Arc::clone(&progress),
+        retired: Arc::clone(&retired),
     };
     let r = SingleReadHandle {
         handle: r,
         trigger,
         key: Vec::from(key),
+        shard: 0,
+        nshards: 1,
+        range: None,
+        index,
+        subscribers,
+        progress,
+        retired,
     };
 
     (r, w)
@@ -104,6 +143,163 @@ fn key_to_double(k: Key) -> Cow<(DataType, DataType)> {
     }
 }
 
+/// The keys of a fully materialized reader, in order.
+///
+/// The writer adds keys before it swaps in the records under them, and removes keys only once the
+/// swap has removed their last record, so the index always covers every key visible to readers.
+type KeyIndex = Arc<RwLock<BTreeSet<Vec<DataType>>>>;
+
+/// Compare `key` to `bound` on only as many columns as `bound` has values.
+fn prefix_cmp(key: &[DataType], bound: &[DataType]) -> Ordering {
+    for (k, b) in key.iter().zip(bound) {
+        match k.cmp(b) {
+            Ordering::Equal => {}
+            o => return o,
+        }
+    }
+    Ordering::Equal
+}
+
+/// Check whether `key` falls between `lower` and `upper`.
+///
+/// The key is only compared against as many columns as each bound has values, so a bound that
+/// names a prefix of the key matches every key that starts with that prefix.
+fn key_in_range(key: &[DataType], lower: Bound<&[DataType]>, upper: Bound<&[DataType]>) -> bool {
+    let cmp = |bound: &[DataType]| prefix_cmp(key, bound);
+    let above = match lower {
+        Bound::Included(b) => cmp(b) != Ordering::Less,
+        Bound::Excluded(b) => cmp(b) == Ordering::Greater,
+        Bound::Unbounded => true,
+    };
+    let below = match upper {
+        Bound::Included(b) => cmp(b) != Ordering::Greater,
+        Bound::Excluded(b) => cmp(b) == Ordering::Less,
+        Bound::Unbounded => true,
+    };
+    above && below
+}
+
+/// Clients that have subscribed to changes to keys in a reader.
+#[derive(Default)]
+struct Subscribers {
+    /// The number of subscriptions, so that the writer can tell that there are none without
+    /// taking the lock.
+    count: AtomicUsize,
+    subscriptions: Mutex<Subscriptions>,
+}
+
+impl Subscribers {
+    /// Lock the subscriptions, unless there are none.
+    fn lock(&self) -> Option<MutexGuard<Subscriptions>> {
+        if self.count.load(atomic::Ordering::Acquire) == 0 {
+            None
+        } else {
+            Some(self.subscriptions.lock().unwrap())
+        }
+    }
+}
+
+#[derive(Default)]
+struct Subscriptions {
+    next: u64,
+    subs: HashMap<u64, Subscription>,
+}
+
+struct Subscription {
+    keys: HashSet<Vec<DataType>>,
+    // deltas that have been added to the backlog, but have not yet been swapped in
+    unpublished: Vec<Record>,
+    // deltas that are visible to readers, but have not yet been picked up by the client
+    published: Vec<Record>,
+    last_polled: time::Instant,
+    // the client waiting for deltas to be published, if any
+    waiting: Option<Waker>,
+}
+
+impl Subscriptions {
+    /// Make the unpublished deltas visible, wake up the clients waiting for them, and drop the
+    /// subscriptions that have expired. Returns how many subscriptions are left.
+    fn publish(&mut self) -> usize {
+        let now = time::Instant::now();
+        self.subs
+            .retain(|_, sub| now.duration_since(sub.last_polled) < SUBSCRIPTION_TIMEOUT);
+        for sub in self.subs.values_mut() {
+            sub.published.append(&mut sub.unpublished);
+            if !sub.published.is_empty() {
+                if let Some(waker) = sub.waiting.take() {
+                    waker.wake();
+                }
+            }
+        }
+        self.subs.len()
+    }
+
+    fn is_subscribed(&self, key: &[DataType]) -> bool {
+        self.subs.values().any(|sub| sub.keys.contains(key))
+    }
+
+    /// Hand a change to a key to the subscriptions to that key.
+    fn push(&mut self, key: &[DataType], r: &Record) {
+        for sub in self.subs.values_mut() {
+            if sub.keys.contains(key) {
+                sub.unpublished.push(r.clone());
+            }
+        }
+    }
+}
+
+/// The changes that turn `old` into `new`, neither of which need to be in any particular order.
+fn diff(mut old: Vec<Vec<DataType>>, new: Vec<Vec<DataType>>) -> Vec<Record> {
+    let mut changes = Vec::new();
+    for row in new {
+        match old.iter().position(|r| *r == row) {
+            Some(i) => {
+                old.swap_remove(i);
+            }
+            None => changes.push(Record::Positive(row)),
+        }
+    }
+    changes.extend(old.into_iter().map(Record::Negative));
+    changes
+}
+
+/// How far the tracked writes to the base node shards that reach a reader have been applied to
+/// it.
+#[derive(Default)]
+struct Progress {
+    /// The base node shards whose writes reach the reader.
+    sources: HashSet<(NodeIndex, usize)>,
+    /// The last tracked write from each base node shard that is visible to readers.
+    ///
+    /// A base node shard numbers its tracked writes one after the other, and every new reader is
+    /// sent one as soon as it is ready, so that it learns how far along each shard is.
+    published: HashMap<(NodeIndex, usize), u64>,
+}
+
+impl Progress {
+    fn publish<I>(&mut self, applied: I)
+    where
+        I: IntoIterator<Item = ((NodeIndex, usize), u64)>,
+    {
+        for (source, seq) in applied {
+            let published = self.published.entry(source).or_insert(seq);
+            *published = (*published).max(seq);
+        }
+    }
+
+    fn has_applied(&self, token: &WriteToken) -> bool {
+        token.writes().all(|(base, shard, seq)| {
+            if !self.sources.contains(&(base, shard)) {
+                // writes to this base shard never reach us
+                return true;
+            }
+            self.published
+                .get(&(base, shard))
+                .map_or(false, |&applied| applied >= seq)
+        })
+    }
+}
+
 pub(crate) struct WriteHandle {
     handle: multiw::Handle,
     partial: bool,
@@ -111,6 +307,31 @@ pub(crate) struct WriteHandle {
     key: Vec<usize>,
     contiguous: bool,
     mem_size: usize,
+    index: Option<KeyIndex>,
+    // keys that gained records since the last swap, and must be added to the index before it
+    index_added: Vec<Vec<DataType>>,
+    // keys that lost records since the last swap, and may have to be removed from the index after it
+    index_removed: Vec<Vec<DataType>>,
+    subscribers: Arc<Subscribers>,
+    // subscribed keys that were evicted, along with the records they held at the time, and
+    // whether a replay has been requested to refill them. the updates to these keys are lost
+    // upstream until they are refilled, so subscribers are told the difference instead.
+    evicted: HashMap<Vec<DataType>, (Vec<Vec<DataType>>, bool)>,
+    // evicted keys that the replay currently being processed refills
+    refilling: HashMap<Vec<DataType>, Vec<Vec<DataType>>>,
+    trigger: Option<Arc<dyn Fn(&mut dyn Iterator<Item = &[DataType]>) -> bool + Send + Sync>>,
+    // the last tracked write from each base node shard that has been added to the backlog, but
+    // has not yet been swapped in
+    unpublished: HashMap<(NodeIndex, usize), u64>,
+    progress: Arc<Mutex<Progress>>,
+    // set once the reader is gone, and with it this handle
+    retired: Arc<AtomicBool>,
+}
+
+impl Drop for WriteHandle {
+    fn drop(&mut self) {
+        self.retired.store(true, atomic::Ordering::Release);
+    }
 }
 
 type Key<'a> = Cow<'a, [DataType]>;
@@ -130,6 +351,9 @@ impl<'a> MutWriteHandleEntry<'a> {
             .handle
             .meta_get_and(Cow::Borrowed(&*self.key), |rs| rs.is_empty())
         {
+            if let Some((rows, _)) = self.handle.evicted.remove(&*self.key) {
+                self.handle.refilling.insert(self.key.to_vec(), rows);
+            }
             self.handle.handle.clear(self.key)
         } else {
             unreachable!("attempted to fill already-filled key");
@@ -137,6 +361,7 @@ impl<'a> MutWriteHandleEntry<'a> {
     }
 
     pub(crate) fn mark_hole(self) {
+        self.handle.evicting(&self.key);
         let size = self
             .handle
             .handle
@@ -229,7 +454,148 @@ impl WriteHandle {
     }
 
     pub(crate) fn swap(&mut self) {
+        let index = match self.index {
+            Some(ref index) if !self.index_added.is_empty() || !self.index_removed.is_empty() => {
+                Some(index)
+            }
+            _ => None,
+        };
+
+        if let Some(index) = index {
+            let mut index = index.write().unwrap();
+            index.extend(self.index_added.drain(..));
+        }
         self.handle.refresh();
+        if let Some(index) = index {
+            let mut index = index.write().unwrap();
+            for key in self.index_removed.drain(..) {
+                let gone = match self.handle.meta_get_and(Cow::Borrowed(&key[..]), |_| ()) {
+                    Some((Some(()), _)) => false,
+                    _ => true,
+                };
+                if gone {
+                    index.remove(&key);
+                }
+            }
+        }
+        if let Some(mut subscriptions) = self.subscribers.lock() {
+            let left = subscriptions.publish();
+            self.subscribers
+                .count
+                .store(left, atomic::Ordering::Release);
+        }
+        if !self.unpublished.is_empty() {
+            self.progress
+                .lock()
+                .unwrap()
+                .publish(self.unpublished.drain());
+        }
+
+        // ask for the evicted keys that subscribers still want to be replayed, now that the holes
+        // are visible
+        if let Some(ref trigger) = self.trigger {
+            let mut keys = self
+                .evicted
+                .iter_mut()
+                .filter_map(|(key, (_, requested))| {
+                    if *requested {
+                        None
+                    } else {
+                        *requested = true;
+                        Some(&key[..])
+                    }
+                })
+                .peekable();
+            if keys.peek().is_some() {
+                trigger(&mut keys);
+            }
+        }
+    }
+
+    /// Keep track of the records under `key` if subscribers want to hear about changes to it, since
+    /// it is about to be evicted.
+    fn evicting(&mut self, key: &[DataType]) {
+        let subscribed = match self.subscribers.lock() {
+            Some(subscriptions) => subscriptions.is_subscribed(key),
+            None => false,
+        };
+        if !subscribed || self.evicted.contains_key(key) {
+            return;
+        }
+        let rows = self
+            .handle
+            .meta_get_and(Cow::Borrowed(key), |rs| {
+                rs.iter().cloned().collect::<Vec<Vec<DataType>>>()
+            })
+            .and_then(|(rows, _)| rows);
+        if let Some(rows) = rows {
+            self.evicted.insert(key.to_vec(), (rows, false));
+        }
+    }
+
+    /// Set the base node shards whose writes reach this reader.
+    pub(crate) fn track_writes<I>(&mut self, sources: I)
+    where
+        I: IntoIterator<Item = (NodeIndex, usize)>,
+    {
+        self.progress.lock().unwrap().sources = sources.into_iter().collect();
+    }
+
+    /// Record that all the effects of the given write have been added to the backlog.
+    ///
+    /// Just like the effects themselves, this becomes visible to readers after the next call to
+    /// `swap()`.
+    pub(crate) fn applied(&mut self, base: NodeIndex, shard: usize, seq: u64) {
+        let unpublished = self.unpublished.entry((base, shard)).or_insert(seq);
+        *unpublished = (*unpublished).max(seq);
+    }
+
+    /// Hand a set of updates to any clients that have subscribed to their keys.
+    ///
+    /// The updates are delivered along with the next call to `swap()`, so that subscribers never
+    /// observe a change before it is visible to lookups. Only regular updates should be passed
+    /// here; replays fill in state that already existed, and so are not changes.
+    pub(crate) fn notify_subscribers(&self, rs: &Records) {
+        let mut subscriptions = match self.subscribers.lock() {
+            Some(subscriptions) => subscriptions,
+            None => return,
+        };
+
+        for r in rs.iter() {
+            let key = key_from_record(&self.key[..], self.contiguous, &r[..]);
+            // changes to evicted keys are accounted for once they are refilled
+            if !self.evicted.contains_key(&*key) {
+                subscriptions.push(&key, r);
+            }
+        }
+    }
+
+    /// Tell subscribers how the evicted keys that the given replay refills have changed since they
+    /// were evicted.
+    ///
+    /// This must be called with the records of every replay, after the keys it fills have been
+    /// marked as filled, and before the records are added.
+    pub(crate) fn notify_refilled(&mut self, rs: &Records) {
+        if self.refilling.is_empty() {
+            return;
+        }
+
+        let mut rows: HashMap<_, Vec<_>> = HashMap::new();
+        for r in rs.iter() {
+            let key = key_from_record(&self.key[..], self.contiguous, &r[..]);
+            if self.refilling.contains_key(&*key) {
+                rows.entry(key.into_owned()).or_default().push(r.to_vec());
+            }
+        }
+        let mut subscriptions = self.subscribers.lock();
+        for (key, old) in self.refilling.drain() {
+            let new = rows.remove(&key).unwrap_or_default();
+            if let Some(ref mut subscriptions) = subscriptions {
+                for r in diff(old, new) {
+                    subscriptions.push(&key, &r);
+                }
+            }
+        }
     }
 
     /// Add a new set of records to the backlog.
@@ -239,7 +605,23 @@ impl WriteHandle {
     where
         I: IntoIterator<Item = Record>,
     {
-        let mem_delta = self.handle.add(&self.key[..], self.cols, rs);
+        let mem_delta = if self.index.is_some() {
+            let key = &self.key[..];
+            let contiguous = self.contiguous;
+            let added = &mut self.index_added;
+            let removed = &mut self.index_removed;
+            let rs = rs.into_iter().inspect(|r| {
+                let k = key_from_record(key, contiguous, &r[..]).into_owned();
+                if let Record::Positive(..) = *r {
+                    added.push(k);
+                } else {
+                    removed.push(k);
+                }
+            });
+            self.handle.add(key, self.cols, rs)
+        } else {
+            self.handle.add(&self.key[..], self.cols, rs)
+        };
         if mem_delta > 0 {
             self.mem_size += mem_delta as usize;
         } else if mem_delta < 0 {
@@ -263,7 +645,15 @@ impl WriteHandle {
                 unreachable!("mem size is {}, but map is empty", self.mem_size);
             }
 
-            self.handle.empty_random_for_each(rng, n, |vs| {
+            let subscriptions = self.subscribers.lock();
+            let evicted = &mut self.evicted;
+            self.handle.empty_random_for_each(rng, n, |key, vs| {
+                if let Some(ref subscriptions) = subscriptions {
+                    if subscriptions.is_subscribed(&key) && !evicted.contains_key(&*key) {
+                        let rows = vs.iter().cloned().collect();
+                        evicted.insert(key.into_owned(), (rows, false));
+                    }
+                }
                 let size: u64 = vs.iter().map(|r| r.deep_size_of() as u64).sum();
                 bytes_to_be_freed += size;
                 n -= 1;
@@ -300,6 +690,13 @@ pub struct SingleReadHandle {
     handle: multir::Handle,
     trigger: Option<Arc<dyn Fn(&mut dyn Iterator<Item = &[DataType]>) -> bool + Send + Sync>>,
     key: Vec<usize>,
+    shard: usize,
+    nshards: usize,
+    range: Option<RangeParameter>,
+    index: Option<KeyIndex>,
+    subscribers: Arc<Subscribers>,
+    progress: Arc<Mutex<Progress>>,
+    retired: Arc<AtomicBool>,
 }
 
 impl std::fmt::Debug for SingleReadHandle {
@@ -308,6 +705,9 @@ impl std::fmt::Debug for SingleReadHandle {
             .field("handle", &self.handle)
             .field("has_trigger", &self.trigger.is_some())
             .field("key", &self.key)
+            .field("shard", &self.shard)
+            .field("nshards", &self.nshards)
+            .field("range", &self.range)
             .finish()
     }
 }
@@ -352,6 +752,170 @@ impl SingleReadHandle {
             })
     }
 
+    /// Find all entries whose keys fall between the given bounds.
+    ///
+    /// The records for each matching key are passed to `then`, and the results are returned in
+    /// key order. Keys are only compared against as many columns as each bound has values, so
+    /// bounds may name a prefix of the key.
+    ///
+    /// Partially materialized state may have holes anywhere in the range, so only fully
+    /// materialized state can answer a range read. For partial state, `Ok(None)` is returned.
+    pub fn try_find_range_and<F, T>(
+        &self,
+        lower: Bound<&[DataType]>,
+        upper: Bound<&[DataType]>,
+        mut then: F,
+    ) -> Result<Option<Vec<T>>, ()>
+    where
+        F: FnMut(&evmap::Values<Vec<DataType>, RandomState>) -> T,
+    {
+        let index = match self.index {
+            Some(ref index) => index.read().unwrap(),
+            None => return Ok(None),
+        };
+
+        // every key that starts with the lower bound sorts after it, so we can start there even
+        // if the bound is exclusive, and skip over the keys it excludes.
+        let start = match lower {
+            Bound::Included(b) | Bound::Excluded(b) => Bound::Included(Vec::from(b)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+
+        let mut found = Vec::new();
+        for key in index.range::<Vec<DataType>, _>((start, Bound::Unbounded)) {
+            let past = match upper {
+                Bound::Included(b) => prefix_cmp(key, b) == Ordering::Greater,
+                Bound::Excluded(b) => prefix_cmp(key, b) != Ordering::Less,
+                Bound::Unbounded => false,
+            };
+            if past {
+                break;
+            }
+            if !key_in_range(key, lower, upper) {
+                continue;
+            }
+
+            // the index may briefly hold keys that are not yet (or no longer) visible
+            if let (Some(t), _) = self.handle.meta_get_and(key, &mut then).ok_or(())? {
+                found.push(t);
+            }
+        }
+        Ok(Some(found))
+    }
+
+    /// Whether this handle reads from partially materialized state.
+    pub fn is_partial(&self) -> bool {
+        self.trigger.is_some()
+    }
+
+    /// Record how the query behind this reader bounds range lookups on its last key column.
+    pub(crate) fn set_range(&mut self, range: Option<RangeParameter>) {
+        self.range = range;
+    }
+
+    /// Whether the bounds of a range lookup include or exclude the parameter value as the query
+    /// does.
+    ///
+    /// For a query like `ts > ?`, a lower bound that covers the whole key must be `Excluded`.
+    /// Bounds that name only a prefix of the key leave the range column open at that end, and
+    /// agree with any query, as do bounds at ends that the query leaves open.
+    pub fn range_agrees(&self, lower: &Bound<Vec<DataType>>, upper: &Bound<Vec<DataType>>) -> bool {
+        let range = match self.range {
+            Some(range) => range,
+            None => return true,
+        };
+
+        let keylen = self.key.len();
+        let agrees =
+            |bound: &Bound<Vec<DataType>>, inclusive: Option<bool>| match (bound, inclusive) {
+                (Bound::Included(k), Some(false)) | (Bound::Excluded(k), Some(true)) => {
+                    k.len() != keylen
+                }
+                _ => true,
+            };
+        agrees(lower, range.lower_inclusive) && agrees(upper, range.upper_inclusive)
+    }
+
+    /// Record which shard of a sharded reader this handle reads from.
+    pub(crate) fn set_shard(&mut self, shard: usize, nshards: usize) {
+        self.shard = shard;
+        self.nshards = nshards;
+    }
+
+    /// Whether the given key is stored in the shard this handle reads from.
+    pub fn owns_key(&self, key: &[DataType]) -> bool {
+        self.nshards <= 1 || crate::shard_by(&key[0], self.nshards) == self.shard
+    }
+
+    /// Subscribe to changes to the given keys, and return an identifier for the subscription.
+    ///
+    /// Changes are buffered until they are picked up with `subscription_deltas`. Subscriptions
+    /// that are not polled for a while are dropped.
+    pub fn subscribe(&self, keys: Vec<Vec<DataType>>) -> u64 {
+        let mut subscriptions = self.subscribers.subscriptions.lock().unwrap();
+        let id = subscriptions.next;
+        subscriptions.next += 1;
+        subscriptions.subs.insert(
+            id,
+            Subscription {
+                keys: keys.into_iter().collect(),
+                unpublished: Vec::new(),
+                published: Vec::new(),
+                last_polled: time::Instant::now(),
+                waiting: None,
+            },
+        );
+        self.subscribers
+            .count
+            .store(subscriptions.subs.len(), atomic::Ordering::Release);
+        id
+    }
+
+    /// Wait for changes to be made to the keys of the given subscription, and take all the changes
+    /// that have been made since it was last polled.
+    ///
+    /// Resolves to `None` if there is no such subscription, or if it has been dropped.
+    pub fn subscription_deltas(
+        &self,
+        id: u64,
+    ) -> impl Future<Output = Option<Vec<Record>>> + Send + 'static {
+        let subscribers = Arc::clone(&self.subscribers);
+        futures_util::future::poll_fn(move |cx| {
+            let mut subscriptions = subscribers.subscriptions.lock().unwrap();
+            let sub = match subscriptions.subs.get_mut(&id) {
+                Some(sub) => sub,
+                None => return Poll::Ready(None),
+            };
+            sub.last_polled = time::Instant::now();
+            if sub.published.is_empty() {
+                sub.waiting = Some(cx.waker().clone());
+                Poll::Pending
+            } else {
+                Poll::Ready(Some(std::mem::take(&mut sub.published)))
+            }
+        })
+    }
+
+    /// Whether all the writes covered by the given token are visible through this handle.
+    pub fn has_applied(&self, token: &WriteToken) -> bool {
+        self.progress.lock().unwrap().has_applied(token)
+    }
+
+    /// The last tracked write from each base node shard that is visible through this handle.
+    pub(crate) fn published(&self) -> Vec<((NodeIndex, usize), u64)> {
+        let progress = self.progress.lock().unwrap();
+        progress
+            .published
+            .iter()
+            .map(|(&s, &seq)| (s, seq))
+            .collect()
+    }
+
+    /// Whether the reader this handle reads from has been removed.
+    pub fn is_retired(&self) -> bool {
+        self.retired.load(atomic::Ordering::Acquire)
+    }
+
     pub fn len(&self) -> usize {
         self.handle.len()
     }
@@ -564,4 +1128,213 @@ mod tests {
             .0
             .unwrap());
     }
+
+    #[test]
+    fn subscriptions_see_swapped_deltas() {
+        use futures_util::FutureExt;
+
+        let a = vec![1.into(), "a".into()];
+        let b = vec![2.into(), "b".into()];
+
+        let (r, mut w) = new(2, &[0]);
+        w.swap();
+        let id = r.subscribe(vec![vec![1.into()]]);
+        assert_eq!(r.subscription_deltas(id).now_or_never(), None);
+
+        let rs: Records = vec![
+            Record::Positive(a.clone()),
+            Record::Positive(b.clone()),
+            Record::Negative(a.clone()),
+        ]
+        .into();
+        w.notify_subscribers(&rs);
+        w.add(rs);
+
+        // nothing is delivered until the changes are swapped in
+        let mut waiting = r.subscription_deltas(id).boxed();
+        assert_eq!((&mut waiting).now_or_never(), None);
+        w.swap();
+        assert_eq!(
+            waiting.now_or_never(),
+            Some(Some(vec![Record::Positive(a.clone()), Record::Negative(a)]))
+        );
+        assert_eq!(r.subscription_deltas(id).now_or_never(), None);
+        assert_eq!(r.subscription_deltas(id + 1).now_or_never(), Some(None));
+    }
+
+    #[test]
+    fn subscriptions_see_changes_to_evicted_keys() {
+        use futures_util::FutureExt;
+
+        let a = vec![1.into(), "a".into()];
+        let b = vec![1.into(), "b".into()];
+        let c = vec![1.into(), "c".into()];
+        let key = vec![DataType::from(1)];
+
+        let triggered = Arc::new(Mutex::new(Vec::new()));
+        let (r, mut w) = {
+            let triggered = Arc::clone(&triggered);
+            new_partial(
+                2,
+                &[0],
+                move |keys: &mut dyn Iterator<Item = &[DataType]>| {
+                    triggered.lock().unwrap().extend(keys.map(Vec::from));
+                    true
+                },
+            )
+        };
+        w.swap();
+        w.mut_with_key(&key[..]).mark_filled();
+        w.add(vec![
+            Record::Positive(a.clone()),
+            Record::Positive(b.clone()),
+        ]);
+        w.swap();
+        let id = r.subscribe(vec![key.clone()]);
+
+        // an evicted key is replayed again right away
+        w.mut_with_key(&key[..]).mark_hole();
+        w.swap();
+        assert_eq!(*triggered.lock().unwrap(), vec![key.clone()]);
+
+        // and whatever changed while it was gone is reported once it is back
+        w.mut_with_key(&key[..]).mark_filled();
+        let rs: Records = vec![Record::Positive(a), Record::Positive(c.clone())].into();
+        w.notify_refilled(&rs);
+        w.add(rs);
+        w.swap();
+        assert_eq!(
+            r.subscription_deltas(id).now_or_never(),
+            Some(Some(vec![Record::Positive(c), Record::Negative(b)]))
+        );
+    }
+
+    #[test]
+    fn write_progress_is_visible_after_swap() {
+        let base = NodeIndex::new(1);
+        let (r, mut w) = new(2, &[0]);
+        w.track_writes(vec![(base, 0)]);
+        w.swap();
+
+        // nothing is known to be applied until the reader hears from the base shard
+        let token = WriteToken::new(base, 0, 10);
+        assert!(!r.has_applied(&WriteToken::new(base, 0, 1)));
+        // writes to base shards that do not reach the reader are never waited for
+        assert!(r.has_applied(&WriteToken::new(base, 1, 10)));
+
+        w.applied(base, 0, 10);
+        assert!(!r.has_applied(&token));
+        w.swap();
+        assert!(r.has_applied(&token));
+        assert!(r.has_applied(&WriteToken::new(base, 0, 1)));
+        assert!(!r.has_applied(&WriteToken::new(base, 0, 11)));
+    }
+
+    #[test]
+    fn range_query() {
+        use std::ops::Bound::*;
+
+        let (r, mut w) = new(2, &[0]);
+        w.add((0..10).map(|i| Record::Positive(vec![i.into(), "a".into()])));
+        w.swap();
+
+        fn count(
+            r: &SingleReadHandle,
+            lower: std::ops::Bound<&[DataType]>,
+            upper: std::ops::Bound<&[DataType]>,
+        ) -> usize {
+            r.try_find_range_and(lower, upper, |rs| rs.len())
+                .unwrap()
+                .unwrap()
+                .into_iter()
+                .sum()
+        }
+        let three = vec![DataType::from(3)];
+        let five = vec![DataType::from(5)];
+
+        assert_eq!(count(&r, Unbounded, Unbounded), 10);
+        assert_eq!(count(&r, Included(&three[..]), Unbounded), 7);
+        assert_eq!(count(&r, Excluded(&three[..]), Unbounded), 6);
+        assert_eq!(count(&r, Included(&three[..]), Included(&five[..])), 3);
+        assert_eq!(count(&r, Included(&three[..]), Excluded(&five[..])), 2);
+        assert_eq!(count(&r, Excluded(&five[..]), Excluded(&three[..])), 0);
+    }
+
+    #[test]
+    fn range_query_prefix() {
+        use std::ops::Bound::*;
+
+        let (r, mut w) = new(3, &[0, 1]);
+        w.add(vec![
+            Record::Positive(vec![1.into(), 1.into(), "a".into()]),
+            Record::Positive(vec![1.into(), 2.into(), "b".into()]),
+            Record::Positive(vec![2.into(), 1.into(), "c".into()]),
+        ]);
+        w.swap();
+
+        let prefix = vec![DataType::from(1)];
+        let found = r
+            .try_find_range_and(Included(&prefix[..]), Included(&prefix[..]), |rs| rs.len())
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.into_iter().sum::<usize>(), 2);
+    }
+
+    #[test]
+    fn range_query_after_removal() {
+        use std::ops::Bound::*;
+
+        let (r, mut w) = new(2, &[0]);
+        w.add(vec![
+            Record::Positive(vec![1.into(), "a".into()]),
+            Record::Positive(vec![1.into(), "b".into()]),
+            Record::Positive(vec![2.into(), "c".into()]),
+        ]);
+        w.swap();
+        w.add(vec![
+            Record::Negative(vec![1.into(), "a".into()]),
+            Record::Negative(vec![2.into(), "c".into()]),
+        ]);
+        w.swap();
+
+        let found = r
+            .try_find_range_and(Unbounded, Unbounded, |rs| rs.len())
+            .unwrap()
+            .unwrap();
+        assert_eq!(found, vec![1]);
+        assert_eq!(r.index.as_ref().unwrap().read().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn range_query_bound_kinds() {
+        use std::ops::Bound::*;
+
+        let (mut r, _w) = new(3, &[0, 1]);
+        r.set_range(Some(RangeParameter {
+            lower_inclusive: Some(false),
+            upper_inclusive: None,
+        }));
+
+        let full = vec![DataType::from(1), DataType::from(2)];
+        let prefix = vec![DataType::from(1)];
+        assert!(r.range_agrees(&Excluded(full.clone()), &Unbounded));
+        assert!(!r.range_agrees(&Included(full.clone()), &Unbounded));
+        assert!(r.range_agrees(&Included(prefix.clone()), &Included(prefix)));
+        // the query leaves the upper end open, so any bound goes there
+        assert!(r.range_agrees(&Excluded(full.clone()), &Included(full)));
+    }
+
+    #[test]
+    fn range_query_partial() {
+        let (r, mut w) = new_partial(1, &[0], |_: &mut dyn Iterator<Item = &[DataType]>| true);
+        w.swap();
+        assert_eq!(
+            r.try_find_range_and(
+                std::ops::Bound::Unbounded,
+                std::ops::Bound::Unbounded,
+                |rs| rs.len()
+            ),
+            Ok(None)
+        );
+    }
 }
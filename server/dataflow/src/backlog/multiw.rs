@@ -52,12 +52,18 @@ impl Handle {
         &mut self,
         rng: &mut impl rand::Rng,
         n: usize,
-        mut f: impl FnMut(&evmap::Values<Vec<DataType>, RandomState>),
+        mut f: impl FnMut(Key, &evmap::Values<Vec<DataType>, RandomState>),
     ) {
         match *self {
-            Handle::Single(ref mut h) => h.empty_random(rng, n).for_each(|r| f(r.1)),
-            Handle::Double(ref mut h) => h.empty_random(rng, n).for_each(|r| f(r.1)),
-            Handle::Many(ref mut h) => h.empty_random(rng, n).for_each(|r| f(r.1)),
+            Handle::Single(ref mut h) => h
+                .empty_random(rng, n)
+                .for_each(|(k, vs)| f(Key::Borrowed(std::slice::from_ref(k)), vs)),
+            Handle::Double(ref mut h) => h
+                .empty_random(rng, n)
+                .for_each(|(k, vs)| f(Key::Owned(vec![k.0.clone(), k.1.clone()]), vs)),
+            Handle::Many(ref mut h) => h
+                .empty_random(rng, n)
+                .for_each(|(k, vs)| f(Key::Borrowed(&k[..]), vs)),
         }
     }
 
@@ -0,0 +1,502 @@
+use crate::prelude::*;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{self, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use tempfile::{tempdir, TempDir};
+
+/// The number of changes each shard of a base table keeps in memory for clients that follow its
+/// change log. Older changes can only be read if the log is persisted.
+const CHANGE_LOG_CAPACITY: usize = 1 << 16;
+
+/// Persisted change logs remember where in the file every this many changes start, so that old
+/// changes can be found without reading the whole file.
+const INDEX_INTERVAL: u64 = 1 << 10;
+
+/// The length of the header of a persisted change log, which holds its epoch.
+const HEADER_LEN: u64 = 8;
+
+struct Inner {
+    epoch: u64,
+    // sequence number of the first change in `changes`
+    first: u64,
+    changes: VecDeque<Record>,
+    // the file the log is persisted to, and where every `INDEX_INTERVAL`th change starts in it
+    persisted: Option<(PathBuf, Vec<u64>)>,
+    next_waiter: u64,
+    waiting: HashMap<u64, Waker>,
+}
+
+impl Inner {
+    /// The sequence number the next change will be given.
+    fn next(&self) -> u64 {
+        self.first + self.changes.len() as u64
+    }
+
+    /// The sequence number of the oldest change that can still be read.
+    fn oldest(&self) -> u64 {
+        if self.persisted.is_some() {
+            0
+        } else {
+            self.first
+        }
+    }
+}
+
+/// The changes that have been applied to a single shard of a base table.
+///
+/// Every record a base emits after resolving the operations it receives is assigned the next in
+/// a sequence of monotonically increasing sequence numbers. Changes are published to the log in
+/// the batches that the base's group commit queue flushes.
+///
+/// If the base is persisted, so is its log, and the log picks up where it left off when the base's
+/// domain is restarted. Otherwise only the most recent changes are retained, and the log starts
+/// over with a new epoch when the domain is restarted, so that positions in the old log are not
+/// mistaken for positions in the new one.
+#[derive(Clone)]
+pub struct ChangeLog(Arc<Mutex<Inner>>);
+
+impl std::fmt::Debug for ChangeLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let inner = self.0.lock().unwrap();
+        f.debug_struct("ChangeLog")
+            .field("epoch", &inner.epoch)
+            .field("first", &inner.first)
+            .field("len", &inner.changes.len())
+            .finish()
+    }
+}
+
+impl ChangeLog {
+    /// The epoch of the log, which identifies this incarnation of it.
+    pub fn epoch(&self) -> u64 {
+        self.0.lock().unwrap().epoch
+    }
+
+    /// Wait for changes starting at position `from`, and read up to `limit` of them along with
+    /// their sequence numbers.
+    ///
+    /// A position is an epoch and a sequence number. If `from` is `None`, changes are read from
+    /// the oldest change that can still be read. Resolves to `None` if some of the changes
+    /// starting at `from` are no longer available, or if `from` is a position in another epoch.
+    pub fn read(&self, from: Option<(u64, u64)>, limit: usize) -> NextChanges {
+        NextChanges {
+            log: self.clone(),
+            from,
+            limit,
+            waiter: None,
+        }
+    }
+}
+
+/// A future that resolves once a [`ChangeLog`] has changes at a given position.
+///
+/// Created by [`ChangeLog::read`].
+pub struct NextChanges {
+    log: ChangeLog,
+    from: Option<(u64, u64)>,
+    limit: usize,
+    waiter: Option<u64>,
+}
+
+impl Future for NextChanges {
+    type Output = Option<Vec<(u64, Record)>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut inner = this.log.0.lock().unwrap();
+        if let Some(waiter) = this.waiter.take() {
+            inner.waiting.remove(&waiter);
+        }
+
+        let from = match this.from {
+            None => inner.oldest(),
+            Some((epoch, seq)) if epoch == inner.epoch && seq <= inner.next() => seq,
+            Some(_) => return Poll::Ready(None),
+        };
+        if from < inner.oldest() {
+            return Poll::Ready(None);
+        }
+
+        if from == inner.next() {
+            let waiter = inner.next_waiter;
+            inner.next_waiter += 1;
+            inner.waiting.insert(waiter, cx.waker().clone());
+            this.waiter = Some(waiter);
+            return Poll::Pending;
+        }
+
+        if from >= inner.first {
+            let skip = (from - inner.first) as usize;
+            return Poll::Ready(Some(
+                inner
+                    .changes
+                    .iter()
+                    .skip(skip)
+                    .take(this.limit)
+                    .cloned()
+                    .enumerate()
+                    .map(|(i, r)| (from + i as u64, r))
+                    .collect(),
+            ));
+        }
+
+        // the changes have to be read back from disk, which we don't want to hold up the writer
+        let (path, index) = inner.persisted.as_ref().expect("checked against oldest");
+        let start = from / INDEX_INTERVAL;
+        let (path, offset) = (path.clone(), index[start as usize]);
+        let end = std::cmp::min(inner.first, from + this.limit as u64);
+        drop(inner);
+
+        let changes = tokio::task::block_in_place(|| {
+            read_file(&path, offset, start * INDEX_INTERVAL, from, end)
+        });
+        Poll::Ready(changes.ok())
+    }
+}
+
+impl Drop for NextChanges {
+    fn drop(&mut self) {
+        if let Some(waiter) = self.waiter {
+            self.log.0.lock().unwrap().waiting.remove(&waiter);
+        }
+    }
+}
+
+/// Read the changes with sequence numbers in `from..end` from a persisted change log, given the
+/// offset of the change with sequence number `start`.
+fn read_file(
+    path: &Path,
+    offset: u64,
+    start: u64,
+    from: u64,
+    end: u64,
+) -> io::Result<Vec<(u64, Record)>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut file = BufReader::new(file);
+    let mut changes = Vec::with_capacity((end - from) as usize);
+    for seq in start..end {
+        let r: Record = bincode::deserialize_from(&mut file).map_err(into_io)?;
+        if seq >= from {
+            changes.push((seq, r));
+        }
+    }
+    Ok(changes)
+}
+
+fn into_io(e: bincode::Error) -> io::Error {
+    match *e {
+        bincode::ErrorKind::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+/// The end of a [`ChangeLog`] that the domain of its base appends to.
+pub(crate) struct ChangeLogWriter {
+    log: ChangeLog,
+    // changes that have been appended, but not yet published
+    pending: Vec<Record>,
+    next: u64,
+    // the file the log is persisted to, and the offset at which the next change will be written
+    file: Option<(BufWriter<File>, u64)>,
+    sync: bool,
+    // With DurabilityMode::DeleteOnExit, the log is stored in a temporary directory.
+    _directory: Option<TempDir>,
+}
+
+impl ChangeLogWriter {
+    /// Open the change log of the given base shard.
+    ///
+    /// The log is persisted alongside the base's state, and resumes from an existing log if there
+    /// is one.
+    pub(crate) fn open(name: &str, params: &PersistenceParameters) -> io::Result<Self> {
+        let (directory, path) = match params.mode {
+            DurabilityMode::MemoryOnly => return Ok(Self::in_memory()),
+            DurabilityMode::Permanent => (None, PathBuf::from(format!("{}.changes", name))),
+            DurabilityMode::DeleteOnExit => {
+                let dir = tempdir()?;
+                let path = dir.path().join(format!("{}.changes", name));
+                (Some(dir), path)
+            }
+        };
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&path)?;
+        let (epoch, next, changes, index, end) = if file.metadata()?.len() < HEADER_LEN {
+            // a new log; a log that was created without its header being written is empty too
+            let epoch = rand::random();
+            file.set_len(0)?;
+            bincode::serialize_into(&mut file, &epoch).map_err(into_io)?;
+            file.sync_all()?;
+            (epoch, 0, VecDeque::new(), Vec::new(), HEADER_LEN)
+        } else {
+            recover(&mut file)?
+        };
+        file.seek(SeekFrom::Start(end))?;
+
+        let inner = Inner {
+            epoch,
+            first: next - changes.len() as u64,
+            changes,
+            persisted: Some((path, index)),
+            next_waiter: 0,
+            waiting: HashMap::new(),
+        };
+        Ok(ChangeLogWriter {
+            log: ChangeLog(Arc::new(Mutex::new(inner))),
+            pending: Vec::new(),
+            next,
+            file: Some((BufWriter::new(file), end)),
+            sync: params.mode == DurabilityMode::Permanent,
+            _directory: directory,
+        })
+    }
+
+    /// A change log that is only kept in memory.
+    pub(crate) fn in_memory() -> Self {
+        let inner = Inner {
+            epoch: rand::random(),
+            first: 0,
+            changes: VecDeque::new(),
+            persisted: None,
+            next_waiter: 0,
+            waiting: HashMap::new(),
+        };
+        ChangeLogWriter {
+            log: ChangeLog(Arc::new(Mutex::new(inner))),
+            pending: Vec::new(),
+            next: 0,
+            file: None,
+            sync: false,
+            _directory: None,
+        }
+    }
+
+    /// The log that readers can follow.
+    pub(crate) fn log(&self) -> ChangeLog {
+        self.log.clone()
+    }
+
+    /// Append the records emitted by a base to the log.
+    ///
+    /// The records are not visible to readers until the log is published.
+    pub(crate) fn append(&mut self, rs: &Records) {
+        self.pending.extend(rs.iter().cloned());
+    }
+
+    /// Persist the records that have been appended since the last call, and make them visible to
+    /// readers.
+    pub(crate) fn publish(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut index = Vec::new();
+        if let Some((ref mut file, ref mut offset)) = self.file {
+            for (seq, r) in (self.next..).zip(&self.pending) {
+                if seq % INDEX_INTERVAL == 0 {
+                    index.push(*offset);
+                }
+                bincode::serialize_into(&mut *file, r).map_err(into_io)?;
+                *offset += bincode::serialized_size(r).map_err(into_io)?;
+            }
+            file.flush()?;
+            if self.sync {
+                file.get_ref().sync_data()?;
+            }
+        }
+        self.next += self.pending.len() as u64;
+
+        let mut inner = self.log.0.lock().unwrap();
+        if let Some((_, ref mut offsets)) = inner.persisted {
+            offsets.extend(index);
+        }
+        inner.changes.extend(self.pending.drain(..));
+        let excess = inner.changes.len().saturating_sub(CHANGE_LOG_CAPACITY);
+        if excess > 0 {
+            inner.changes.drain(..excess);
+            inner.first += excess as u64;
+        }
+        for (_, waker) in inner.waiting.drain() {
+            waker.wake();
+        }
+        Ok(())
+    }
+}
+
+/// Read back a persisted change log, and drop any change at its end that was not completely
+/// written.
+///
+/// Returns the log's epoch, the sequence number of the next change, the most recent changes, the
+/// index of the log, and the offset at which the next change should be written.
+fn recover(file: &mut File) -> io::Result<(u64, u64, VecDeque<Record>, Vec<u64>, u64)> {
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(0))?;
+    let mut reader = BufReader::new(&mut *file);
+    let epoch: u64 = bincode::deserialize_from(&mut reader).map_err(into_io)?;
+
+    let mut changes = VecDeque::new();
+    let mut index = Vec::new();
+    let mut offset = HEADER_LEN;
+    let mut next = 0;
+    while offset < len {
+        let r: Record = match bincode::deserialize_from(&mut reader) {
+            Ok(r) => r,
+            Err(_) => break,
+        };
+        if next % INDEX_INTERVAL == 0 {
+            index.push(offset);
+        }
+        offset += bincode::serialized_size(&r).map_err(into_io)?;
+        next += 1;
+
+        changes.push_back(r);
+        if changes.len() > CHANGE_LOG_CAPACITY {
+            changes.pop_front();
+        }
+    }
+    drop(reader);
+
+    file.set_len(offset)?;
+    Ok((epoch, next, changes, index, offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::FutureExt;
+
+    fn read(log: &ChangeLog, from: Option<(u64, u64)>, limit: usize) -> Option<Vec<(u64, Record)>> {
+        log.read(from, limit)
+            .now_or_never()
+            .expect("changes are available")
+    }
+
+    #[test]
+    fn it_numbers_changes() {
+        let mut w = ChangeLogWriter::in_memory();
+        let log = w.log();
+        let epoch = log.epoch();
+        assert!(log.read(None, 10).now_or_never().is_none());
+
+        w.append(
+            &vec![
+                Record::Positive(vec![1.into()]),
+                Record::Positive(vec![2.into()]),
+            ]
+            .into(),
+        );
+        w.append(&vec![Record::Negative(vec![1.into()])].into());
+        // nothing is visible until the log is published
+        assert!(log.read(None, 10).now_or_never().is_none());
+        w.publish().unwrap();
+
+        assert_eq!(
+            read(&log, None, 10),
+            Some(vec![
+                (0, Record::Positive(vec![1.into()])),
+                (1, Record::Positive(vec![2.into()])),
+                (2, Record::Negative(vec![1.into()])),
+            ])
+        );
+        assert_eq!(
+            read(&log, Some((epoch, 1)), 1),
+            Some(vec![(1, Record::Positive(vec![2.into()]))])
+        );
+        assert!(log.read(Some((epoch, 3)), 10).now_or_never().is_none());
+        assert_eq!(read(&log, Some((epoch + 1, 1)), 10), None);
+        assert_eq!(read(&log, Some((epoch, 4)), 10), None);
+    }
+
+    #[test]
+    fn it_wakes_readers() {
+        let mut w = ChangeLogWriter::in_memory();
+        let log = w.log();
+        let mut next = Box::pin(log.read(Some((log.epoch(), 0)), 10));
+        let waker = futures_util::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert!(next.as_mut().poll(&mut cx).is_pending());
+
+        w.append(&vec![Record::Positive(vec![1.into()])].into());
+        w.publish().unwrap();
+        assert_eq!(
+            next.as_mut().poll(&mut cx),
+            Poll::Ready(Some(vec![(0, Record::Positive(vec![1.into()]))]))
+        );
+        assert!(log.0.lock().unwrap().waiting.is_empty());
+    }
+
+    #[test]
+    fn it_truncates() {
+        let mut w = ChangeLogWriter::in_memory();
+        let log = w.log();
+        let epoch = log.epoch();
+        let rs: Records = (0..CHANGE_LOG_CAPACITY + 2)
+            .map(|i| Record::Positive(vec![i.into()]))
+            .collect();
+        w.append(&rs);
+        w.publish().unwrap();
+
+        assert_eq!(read(&log, Some((epoch, 0)), 1), None);
+        assert_eq!(read(&log, Some((epoch, 1)), 1), None);
+        assert_eq!(
+            read(&log, None, 1),
+            Some(vec![(2, Record::Positive(vec![2.into()]))])
+        );
+    }
+
+    #[test]
+    fn it_persists() {
+        let dir = tempdir().unwrap();
+        let name = dir.path().join("it_persists");
+        let name = name.to_str().unwrap();
+        let params = PersistenceParameters {
+            mode: DurabilityMode::Permanent,
+            ..Default::default()
+        };
+
+        let n = CHANGE_LOG_CAPACITY + INDEX_INTERVAL as usize + 1;
+        let epoch = {
+            let mut w = ChangeLogWriter::open(name, &params).unwrap();
+            let rs: Records = (0..n).map(|i| Record::Positive(vec![i.into()])).collect();
+            w.append(&rs);
+            w.publish().unwrap();
+            w.log().epoch()
+        };
+
+        let mut w = ChangeLogWriter::open(name, &params).unwrap();
+        let log = w.log();
+        assert_eq!(log.epoch(), epoch);
+
+        // old changes are read back from disk
+        assert_eq!(
+            read(&log, Some((epoch, INDEX_INTERVAL + 1)), 2),
+            Some(vec![
+                (
+                    INDEX_INTERVAL + 1,
+                    Record::Positive(vec![(INDEX_INTERVAL as usize + 1).into()])
+                ),
+                (
+                    INDEX_INTERVAL + 2,
+                    Record::Positive(vec![(INDEX_INTERVAL as usize + 2).into()])
+                ),
+            ])
+        );
+
+        // and the log continues where it left off
+        w.append(&vec![Record::Negative(vec![0.into()])].into());
+        w.publish().unwrap();
+        assert_eq!(
+            read(&log, Some((epoch, n as u64)), 10),
+            Some(vec![(n as u64, Record::Negative(vec![0.into()]))])
+        );
+    }
+}
@@ -5,6 +5,7 @@ use std::cmp;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::mem;
 use std::net::SocketAddr;
+use std::ops::{Bound, Range};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time;
@@ -16,10 +17,12 @@ use ahash::RandomState;
 use futures_util::{future::FutureExt, stream::StreamExt};
 use noria::channel::{self, TcpSender};
 pub use noria::internal::DomainIndex as Index;
+use noria::{OperationResult, TableOperation, TransactionTag, WriteToken};
 use slog::Logger;
 use stream_cancel::Valve;
 
-use crate::Readers;
+use crate::changelog::ChangeLogWriter;
+use crate::{ChangeLogs, Readers};
 use timekeeper::{RealTime, SimpleTracker, ThreadTime, Timer, TimerSet};
 use tokio;
 
@@ -45,6 +48,9 @@ pub struct Config {
 
 const BATCH_SIZE: usize = 256;
 
+/// Pinned snapshots whose next page has not been asked for in this long are discarded.
+pub const SNAPSHOT_TIMEOUT: time::Duration = time::Duration::from_secs(5 * 60);
+
 #[derive(Debug)]
 enum DomainMode {
     Forwarding,
@@ -69,6 +75,8 @@ enum TriggerEndpoint {
     Start(Vec<usize>),
     End {
         source: SourceSelection,
+        /// The domain the replays come from.
+        domain: Index,
         options: Vec<Box<dyn channel::Sender<Item = Box<Packet>> + Send>>,
     },
     Local(Vec<usize>),
@@ -133,6 +141,11 @@ pub struct DomainBuilder {
     pub persistence_parameters: PersistenceParameters,
     /// Configuration parameters for the domain.
     pub config: Config,
+    /// The domain whose base nodes this domain only keeps a backup of, if any.
+    pub backup_of: Option<Index>,
+    /// Whether this shard takes over from a copy of it on another worker, and so waits to be
+    /// given what that copy held with `MoveIn` before it handles anything else.
+    pub moving_in: bool,
 }
 
 unsafe impl Send for DomainBuilder {}
@@ -143,6 +156,7 @@ impl DomainBuilder {
         self,
         log: Logger,
         readers: Readers,
+        change_logs: ChangeLogs,
         channel_coordinator: Arc<ChannelCoordinator>,
         control_addr: SocketAddr,
         shutdown_valve: &Valve,
@@ -162,10 +176,27 @@ impl DomainBuilder {
         Domain {
             index: self.index,
             shard: self.shard,
-            _nshards: self.nshards,
+            nshards: self.nshards,
 
             persistence_parameters: self.persistence_parameters,
             nodes: self.nodes,
+            backup_of: self.backup_of,
+            backups: Vec::new(),
+            shipped: 0,
+            backup_applied: HashMap::new(),
+            held_replies: VecDeque::new(),
+            restoring: Map::default(),
+            held_inputs: Map::default(),
+            reshards: Map::default(),
+            forwarded: HashMap::new(),
+            next_forwarded: 0,
+            setup: Vec::new(),
+            moving_in: if self.moving_in {
+                Some(MovingIn::default())
+            } else {
+                None
+            },
+            moved_out: None,
             state: StateMap::default(),
             log,
             not_ready,
@@ -179,6 +210,12 @@ impl DomainBuilder {
 
             shutdown_valve: shutdown_valve.clone(),
             readers,
+            change_logs,
+            base_changes: Map::default(),
+            prepared: Map::default(),
+            pinning: Vec::new(),
+            awaiting: Vec::new(),
+            snapshots: HashMap::new(),
             control_reply_tx,
             channel_coordinator,
 
@@ -206,6 +243,129 @@ impl DomainBuilder {
     }
 }
 
+/// The operations of a transaction that a base node has agreed to apply, along with the writes to
+/// the base node that have arrived since. Those must wait until the transaction has been
+/// committed or aborted, since their outcome may depend on it.
+struct PreparedTransaction {
+    txn: TransactionTag,
+    data: Vec<TableOperation>,
+    held: VecDeque<Box<Packet>>,
+}
+
+/// A reply to a write that is held back until the backups of the domain have applied the write.
+enum HeldReply {
+    Ack(SourceChannelIdentifier, noria::WriteResult),
+    Committed(WriteToken),
+    /// The results of a write passed on by the given shard of a base with the given identifier.
+    Forwarded((Index, usize), u64, noria::WriteResult),
+}
+
+/// A base node that ships its writes to the base that takes over from it as it is resharded.
+struct Reshard {
+    /// The base that takes over.
+    to: (Index, LocalNodeIndex),
+    /// The column the base that takes over is sharded by, and into how many shards.
+    column: usize,
+    shards: usize,
+    /// Whether the base has handed over, and passes its writes on rather than applying them.
+    forward: bool,
+    /// Whether replies to the writes passed on are held back, and the ones that are.
+    hold: bool,
+    held: Vec<(SourceChannelIdentifier, noria::WriteResult)>,
+}
+
+/// What a copy of a domain shard on another worker needs besides its nodes to take over from it.
+///
+/// Partially materialized state is left behind, and is filled again as it is asked for. The copy
+/// handles the packets that set up the state and the replay paths of the nodes again, and then
+/// starts out with the rows of the rest.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MovedShard {
+    setup: Vec<Packet>,
+    /// The rows of every fully materialized node that is not a reader.
+    rows: Map<Vec<Vec<DataType>>>,
+    /// The rows of every reader, which are left out if it is partial, along with the last tracked
+    /// write from each base node shard that it had applied.
+    readers: Map<(Vec<Vec<DataType>>, Vec<((NodeIndex, usize), u64)>)>,
+    ingress_inject: Map<(usize, Vec<DataType>)>,
+    backups: Vec<Index>,
+    shipped: u64,
+    backup_applied: HashMap<Index, u64>,
+    /// The base nodes that ship their writes to bases that take over from them, along with the
+    /// base that takes over, the column and number of shards it is sharded by, and whether they
+    /// have handed over.
+    reshards: Map<((Index, LocalNodeIndex), usize, usize, bool)>,
+    next_forwarded: u64,
+}
+
+/// A shard that has handed over to a copy of it on another worker.
+struct MovedOut {
+    /// Where the copy acknowledges writes passed on to it through this shard.
+    alias: ReplicaAddr,
+    /// The packets received since the shard handed over, until it is told where the copy is.
+    held: Option<Vec<Box<Packet>>>,
+    /// Whether the shard was told to stop passing on writes from clients.
+    retiring: bool,
+    /// Whether the shard has reported that the copy applied every write it passed on.
+    retired: bool,
+}
+
+/// A shard that takes over from a copy of it on another worker.
+#[derive(Default)]
+struct MovingIn {
+    /// How many shards send to this one, once it has been told.
+    senders: Option<usize>,
+    /// How many shards have sent to this one for the last time through the copy it took over from.
+    rerouted: usize,
+    /// Packets the copy passed on before this shard was given what the copy held.
+    relayed: VecDeque<Box<Packet>>,
+    /// Packets sent straight to this shard, which must wait until everything sent through the
+    /// copy has arrived.
+    held: VecDeque<Box<Packet>>,
+}
+
+/// Who hears how a write that was passed on to the base that took over went.
+enum ForwardedFor {
+    /// The clients that sent the write, along with the operations that came from each.
+    Clients(Vec<(SourceChannelIdentifier, Range<usize>)>),
+    /// The given shard of a base that passed the write on to this one, which knows it by the
+    /// given identifier.
+    Base((Index, usize), u64),
+    /// The controller, which waits for the base to have passed on all the writes before it.
+    Controller,
+}
+
+/// A write passed on to the base that took over, waiting for the results from every shard it
+/// went to.
+struct ForwardedWrite {
+    node: LocalNodeIndex,
+    reply: ForwardedFor,
+    /// The operations of the write that went to each shard.
+    parts: HashMap<usize, Vec<usize>>,
+    results: Vec<Option<OperationResult>>,
+    token: WriteToken,
+}
+
+/// An executor that holds on to write acknowledgements rather than sending them.
+struct HoldAcks<'a> {
+    inner: &'a mut dyn Executor,
+    acks: Vec<(SourceChannelIdentifier, noria::WriteResult)>,
+}
+
+impl<'a> Executor for HoldAcks<'a> {
+    fn ack(&mut self, tag: SourceChannelIdentifier, result: noria::WriteResult) {
+        self.acks.push((tag, result));
+    }
+
+    fn create_universe(&mut self, req: HashMap<String, DataType>) {
+        self.inner.create_universe(req);
+    }
+
+    fn send(&mut self, dest: ReplicaAddr, m: Box<Packet>) {
+        self.inner.send(dest, m);
+    }
+}
+
 #[derive(Clone, Debug)]
 struct TimedPurge {
     time: time::Instant,
@@ -217,7 +377,7 @@ struct TimedPurge {
 pub struct Domain {
     index: Index,
     shard: Option<usize>,
-    _nshards: usize,
+    nshards: usize,
 
     nodes: DomainNodes,
     state: StateMap,
@@ -225,6 +385,37 @@ pub struct Domain {
 
     not_ready: HashSet<LocalNodeIndex>,
 
+    /// The domain whose base nodes this domain only keeps a backup of, if any.
+    backup_of: Option<Index>,
+    /// The domains that keep backups of the base nodes of this domain.
+    backups: Vec<Index>,
+    /// The number of the last write shipped to the backups.
+    shipped: u64,
+    /// The number of the last write each backup has confirmed that it applied.
+    backup_applied: HashMap<Index, u64>,
+    /// Replies to writes that must wait until every backup has applied the write with the given
+    /// number.
+    held_replies: VecDeque<(u64, HeldReply)>,
+    /// Rows to fill base nodes with once they are ready, copied from the bases they replace.
+    restoring: Map<Vec<Vec<DataType>>>,
+    /// Writes passed on to base nodes that take over from others before they are ready.
+    held_inputs: Map<VecDeque<Box<Packet>>>,
+
+    /// The base nodes that ship their writes to the bases that take over from them.
+    reshards: Map<Reshard>,
+    /// The writes passed on to the bases that took over, by identifier.
+    forwarded: HashMap<u64, ForwardedWrite>,
+    next_forwarded: u64,
+
+    /// The packets that set up the state and the replay paths of the nodes, which a copy of this
+    /// shard that takes over on another worker handles again.
+    setup: Vec<Packet>,
+    /// Set while this shard takes over from a copy of it on another worker.
+    moving_in: Option<MovingIn>,
+    /// Set once this shard has handed over to a copy of it on another worker, after which it
+    /// only passes on what it is sent.
+    moved_out: Option<MovedOut>,
+
     ingress_inject: Map<(usize, Vec<DataType>)>,
 
     persistence_parameters: PersistenceParameters,
@@ -243,6 +434,16 @@ pub struct Domain {
 
     shutdown_valve: Valve,
     readers: Readers,
+    change_logs: ChangeLogs,
+    base_changes: Map<ChangeLogWriter>,
+    prepared: Map<PreparedTransaction>,
+    /// Snapshots waiting for their node to apply the writes they must reflect.
+    pinning: Vec<(LocalNodeIndex, u64, WriteToken)>,
+    /// Readers the controller waits for to reflect the writes covered by the given tokens.
+    awaiting: Vec<(LocalNodeIndex, WriteToken)>,
+    /// The rows of pinned snapshots that have yet to be read, along with when they were last read
+    /// from.
+    snapshots: HashMap<u64, (time::Instant, std::vec::IntoIter<Vec<DataType>>)>,
     control_reply_tx: TcpSender<ControlReplyPacket>,
     channel_coordinator: Arc<ChannelCoordinator>,
 
@@ -386,6 +587,7 @@ impl Domain {
         if let TriggerEndpoint::End {
             source,
             ref mut options,
+            ..
         } = self.replay_paths.get_mut(&tag).unwrap().trigger
         {
             let ask_shard_by_key_i = match source {
@@ -604,6 +806,12 @@ impl Domain {
                 return;
             }
 
+            if let Some(log) = self.base_changes.get_mut(me) {
+                // record what the base made of the writes for anyone following its changes. they
+                // are published once the group commit has been processed.
+                m.as_mut().unwrap().map_data(|rs| log.append(rs));
+            }
+
             // normally, we ignore misses during regular forwarding.
             // however, we have to be a little careful in the case of joins.
             let evictions = if n.is_internal() && n.is_join() && !misses.is_empty() {
@@ -711,8 +919,11 @@ impl Domain {
         }
 
         match &**m.as_ref().unwrap() {
-            m @ &Packet::Message { .. } if m.is_empty() => {
-                // no need to deal with our children if we're not sending them anything
+            m @ &Packet::Message { write: None, .. } if m.is_empty() => {
+                // no need to deal with our children if we're not sending them anything.
+                // updates caused by a write (including all those that are part of a transaction)
+                // must be forwarded regardless, since readers downstream count them to tell which
+                // writes and transactions they have seen in full.
                 return;
             }
             &Packet::Message { .. } => {}
@@ -757,10 +968,59 @@ impl Domain {
         }
 
         match *m {
+            _ if m
+                .input()
+                .and_then(|i| self.reshards.get(i.dst))
+                .map_or(false, |r| r.forward) =>
+            {
+                // the base has handed over to the base that took over from it
+                self.forward_input(m, executor);
+            }
+            Packet::ForwardedInput { ref inner, .. } if self.not_ready.contains(&inner.dst) => {
+                // the base that takes over is not ready yet, and applies the write once it is
+                let dst = inner.dst;
+                self.held_inputs
+                    .entry(dst)
+                    .or_insert_with(VecDeque::new)
+                    .push_back(m);
+            }
+            _ if m
+                .input()
+                .map_or(false, |i| self.prepared.contains_key(i.dst)) =>
+            {
+                // the base node is waiting to hear whether to apply a transaction, and what this
+                // write does may depend on that.
+                let dst = m.input().unwrap().dst;
+                self.prepared[dst].held.push_back(m);
+            }
+            Packet::ForwardedInput { .. } => {
+                self.total_forward_time.start();
+                self.apply_forwarded(m, executor);
+                self.total_forward_time.stop();
+            }
             Packet::Message { .. } | Packet::Input { .. } => {
+                let mut ship = false;
+                if let Some(input) = m.input() {
+                    if !self.not_ready.contains(&input.dst) {
+                        self.mirror(input, executor);
+                        ship = self.ship_to_backups(input, executor);
+                    }
+                }
+
                 // WO for https://github.com/rust-lang/rfcs/issues/1403
                 self.total_forward_time.start();
-                self.dispatch(m, executor);
+                if ship {
+                    let mut held = HoldAcks {
+                        inner: &mut *executor,
+                        acks: Vec::new(),
+                    };
+                    self.dispatch(m, &mut held);
+                    for (src, result) in held.acks {
+                        self.reply_after_backups(HeldReply::Ack(src, result), executor);
+                    }
+                } else {
+                    self.dispatch(m, executor);
+                }
                 self.total_forward_time.stop();
             }
             Packet::ReplayPiece { .. } => {
@@ -772,6 +1032,14 @@ impl Domain {
                 self.handle_eviction(m, executor);
             }
             consumed => {
+                match consumed {
+                    Packet::PrepareState { .. }
+                    | Packet::SetupReplayPath { .. }
+                    | Packet::Ready { .. }
+                    | Packet::AliasReader { .. } => self.setup.push(consumed.clone()),
+                    _ => {}
+                }
+
                 match consumed {
                     // workaround #16223
                     Packet::AddNode { node, parents } => {
@@ -789,7 +1057,32 @@ impl Domain {
                         trace!(self.log, "new node incorporated"; "local" => addr.id());
                     }
                     Packet::RemoveNodes { nodes } => {
+                        // our backups only have copies of our base nodes
+                        let bases: Vec<_> = nodes
+                            .iter()
+                            .cloned()
+                            .filter(|&node| self.nodes[node].borrow().is_base())
+                            .collect();
+                        if !bases.is_empty() {
+                            let shard = self.shard.unwrap_or(0);
+                            for &backup in &self.backups {
+                                let m = Packet::RemoveNodes {
+                                    nodes: bases.clone(),
+                                };
+                                executor.send((backup, shard), Box::new(m));
+                            }
+                        }
+
                         for &node in &nodes {
+                            self.reshards.remove(node);
+                            self.held_inputs.remove(node);
+                            if self.base_changes.remove(node).is_some() {
+                                let gid = self.nodes[node].borrow().global_addr();
+                                self.change_logs
+                                    .lock()
+                                    .unwrap()
+                                    .remove(&(gid, self.shard.unwrap_or(0)));
+                            }
                             self.nodes[node].borrow_mut().remove();
                             self.state.remove(node);
                             trace!(self.log, "node removed"; "local" => node.id());
@@ -807,11 +1100,24 @@ impl Domain {
                         node,
                         field,
                         default,
+                        spec,
                     } => {
                         let mut n = self.nodes[node].borrow_mut();
                         n.add_column(&field);
                         if let Some(b) = n.get_base_mut() {
-                            b.add_column(default);
+                            b.add_column(default.clone(), spec.clone());
+
+                            // our backups must add the column at the same point among the writes
+                            let shard = self.shard.unwrap_or(0);
+                            for &backup in &self.backups {
+                                let m = Packet::AddBaseColumn {
+                                    node,
+                                    field: field.clone(),
+                                    default: default.clone(),
+                                    spec: spec.clone(),
+                                };
+                                executor.send((backup, shard), Box::new(m));
+                            }
                         } else if n.is_ingress() {
                             self.ingress_inject
                                 .entry(node)
@@ -821,15 +1127,212 @@ impl Domain {
                         } else {
                             unreachable!("node unrelated to base got AddBaseColumn");
                         }
-                        self.control_reply_tx
-                            .send(ControlReplyPacket::ack())
-                            .unwrap();
+                        if self.backup_of.is_none() {
+                            self.control_reply_tx
+                                .send(ControlReplyPacket::ack())
+                                .unwrap();
+                        }
                     }
                     Packet::DropBaseColumn { node, column } => {
                         let mut n = self.nodes[node].borrow_mut();
                         n.get_base_mut()
                             .expect("told to drop base column from non-base node")
                             .drop_column(column);
+
+                        let shard = self.shard.unwrap_or(0);
+                        for &backup in &self.backups {
+                            let m = Packet::DropBaseColumn { node, column };
+                            executor.send((backup, shard), Box::new(m));
+                        }
+                        if self.backup_of.is_none() {
+                            self.control_reply_tx
+                                .send(ControlReplyPacket::ack())
+                                .unwrap();
+                        }
+                    }
+                    Packet::UpdateBackups { backups } => {
+                        // bring new backups up to date with what the base nodes hold so far, which
+                        // they load in bulk. they receive all later writes as they come in. every
+                        // base is sent, even an empty one, so that each new backup confirms that
+                        // it has caught up.
+                        let shard = self.shard.unwrap_or(0);
+                        for &backup in backups.iter().filter(|b| !self.backups.contains(b)) {
+                            for (local, n) in self.nodes.iter() {
+                                let n = n.borrow();
+                                let (base, s) = match (n.get_base(), self.state.get(local)) {
+                                    (Some(base), Some(s)) => (base, s),
+                                    _ => continue,
+                                };
+
+                                let data = s
+                                    .cloned_records()
+                                    .into_iter()
+                                    .map(|mut row| {
+                                        base.fix(&mut row);
+                                        TableOperation::Insert(row)
+                                    })
+                                    .collect();
+                                let m = Packet::BackupInput {
+                                    seq: self.shipped,
+                                    inner: Input {
+                                        dst: local,
+                                        data,
+                                        txn: None,
+                                        track: false,
+                                        bulk: true,
+                                    },
+                                };
+                                executor.send((backup, shard), Box::new(m));
+                            }
+                        }
+
+                        // writes held back for backups that are gone now only wait for the rest
+                        self.backup_applied.retain(|b, _| backups.contains(b));
+                        self.backups = backups;
+                        self.release_replies(executor);
+                    }
+                    Packet::BackupInput { seq, inner } => {
+                        let primary = self
+                            .backup_of
+                            .expect("non-backup domain was shipped a write");
+                        let m = Packet::Input {
+                            inner: LocalOrNot::new(inner),
+                            src: None,
+                            senders: Vec::new(),
+                        };
+                        self.handle(Box::new(m), executor, false);
+
+                        let m = Packet::BackupApplied {
+                            backup: self.index,
+                            seq,
+                        };
+                        executor.send((primary, self.shard.unwrap_or(0)), Box::new(m));
+                    }
+                    Packet::BackupApplied { backup, seq } => {
+                        let applied = self.backup_applied.entry(backup).or_insert(0);
+                        *applied = cmp::max(*applied, seq);
+                        self.release_replies(executor);
+                    }
+                    Packet::RestoreBase { node, rows } => {
+                        self.restoring
+                            .entry(node)
+                            .or_insert_with(Vec::new)
+                            .extend(rows);
+                    }
+                    Packet::ShipBase {
+                        node,
+                        to,
+                        column,
+                        shards,
+                    } => {
+                        let rows = self.snapshot(node).expect("shipping rows of partial base");
+                        let last_write = self.nodes[node].borrow().get_base().unwrap().last_write();
+                        let mut parts = vec![Vec::new(); shards];
+                        for row in rows {
+                            parts[noria::shard_by(&row[column], shards)].push(row);
+                        }
+                        for (shard, rows) in parts.into_iter().enumerate() {
+                            let m = Packet::ShippedBase {
+                                node: to.1,
+                                rows,
+                                last_write,
+                            };
+                            executor.send((to.0, shard), Box::new(m));
+                        }
+
+                        // every write the base applies from now on is shipped after the rows
+                        let r = Reshard {
+                            to,
+                            column,
+                            shards,
+                            forward: false,
+                            hold: false,
+                            held: Vec::new(),
+                        };
+                        self.reshards.insert(node, r);
+                    }
+                    Packet::ShippedBase {
+                        node,
+                        rows,
+                        last_write,
+                    } => {
+                        self.nodes[node]
+                            .borrow_mut()
+                            .get_base_mut()
+                            .expect("shipped rows to non-base node")
+                            .advance_writes(last_write);
+                        self.restoring
+                            .entry(node)
+                            .or_insert_with(Vec::new)
+                            .extend(rows);
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::ForwardBase { node, hold } => {
+                        let r = self
+                            .reshards
+                            .get_mut(node)
+                            .expect("told to forward writes of base that ships none");
+                        r.hold = hold;
+                        let held = if hold {
+                            Vec::new()
+                        } else {
+                            mem::take(&mut r.held)
+                        };
+                        for (src, result) in held {
+                            executor.ack(src, result);
+                        }
+
+                        if !r.forward {
+                            r.forward = true;
+
+                            // a tracked write that goes to every shard after all the writes shipped
+                            // so far tells the controller when they have all been applied
+                            let input = Input {
+                                dst: node,
+                                data: Vec::new(),
+                                txn: None,
+                                track: true,
+                                bulk: false,
+                            };
+                            self.forward(input, Some(ForwardedFor::Controller), executor);
+                        }
+                    }
+                    Packet::ForwardedResult { id, shard, result } => {
+                        let done = {
+                            let w = self
+                                .forwarded
+                                .get_mut(&id)
+                                .expect("got results of unknown forwarded write");
+                            let ops = w.parts.remove(&shard).unwrap_or_default();
+                            for (op, r) in ops.into_iter().zip(result.results) {
+                                w.results[op] = Some(r);
+                            }
+                            w.token.merge(result.token);
+                            w.parts.is_empty()
+                        };
+                        if done {
+                            let w = self.forwarded.remove(&id).unwrap();
+                            self.reply_forwarded(w, executor);
+                        }
+                    }
+                    Packet::AwaitApplied { node, token } => {
+                        self.awaiting.push((node, token));
+                        self.release_awaiting();
+                    }
+                    Packet::AliasReader { node, aliases } => {
+                        let shard = self.shard.unwrap_or(0);
+                        let gid = self.nodes[node].borrow().global_addr();
+                        let mut readers = self.readers.lock().unwrap();
+                        let r = readers
+                            .get(&(gid, shard))
+                            .cloned()
+                            .expect("told to alias reader that has no handle");
+                        for alias in aliases {
+                            readers.insert((alias, shard), r.clone());
+                        }
+                        drop(readers);
                         self.control_reply_tx
                             .send(ControlReplyPacket::ack())
                             .unwrap();
@@ -932,7 +1435,7 @@ impl Domain {
                                         tx
                                     })
                                     .collect::<Vec<_>>();
-                                let (r_part, w_part) = backlog::new_partial(
+                                let (mut r_part, w_part) = backlog::new_partial(
                                     cols,
                                     &k[..],
                                     move |misses: &mut dyn Iterator<Item = &[DataType]>| {
@@ -964,45 +1467,45 @@ impl Domain {
                                         }
                                     },
                                 );
+                                r_part.set_shard(self.shard.unwrap_or(0), self.nshards);
 
                                 let mut n = self.nodes[node].borrow_mut();
                                 tokio::task::block_in_place(|| {
                                     n.with_reader_mut(|r| {
-                                        assert!(self
+                                        let old = self
                                             .readers
                                             .lock()
                                             .unwrap()
-                                            .insert(
-                                                (gid, *self.shard.as_ref().unwrap_or(&0)),
-                                                r_part
-                                            )
-                                            .is_none());
+                                            .insert((gid, self.shard.unwrap_or(0)), r_part);
+                                        // a copy of this shard that moved away from this worker
+                                        // leaves its retired handle behind
+                                        assert!(old.map_or(true, |r| r.is_retired()));
 
                                         // make sure Reader is actually prepared to receive state
-                                        r.set_write_handle(w_part)
+                                        r.set_write_handle(w_part, self.shard.unwrap_or(0))
                                     })
                                 })
                                 .unwrap();
                             }
                             InitialState::Global { gid, cols, key } => {
                                 use crate::backlog;
-                                let (r_part, w_part) = backlog::new(cols, &key[..]);
+                                let (mut r_part, w_part) = backlog::new(cols, &key[..]);
 
                                 let mut n = self.nodes[node].borrow_mut();
                                 tokio::task::block_in_place(|| {
                                     n.with_reader_mut(|r| {
-                                        assert!(self
+                                        r_part.set_range(r.range_parameter());
+                                        let old = self
                                             .readers
                                             .lock()
                                             .unwrap()
-                                            .insert(
-                                                (gid, *self.shard.as_ref().unwrap_or(&0)),
-                                                r_part
-                                            )
-                                            .is_none());
+                                            .insert((gid, self.shard.unwrap_or(0)), r_part);
+                                        // a copy of this shard that moved away from this worker
+                                        // leaves its retired handle behind
+                                        assert!(old.map_or(true, |r| r.is_retired()));
 
                                         // make sure Reader is actually prepared to receive state
-                                        r.set_write_handle(w_part)
+                                        r.set_write_handle(w_part, self.shard.unwrap_or(0))
                                     })
                                 })
                                 .unwrap();
@@ -1064,6 +1567,7 @@ impl Domain {
 
                                 TriggerEndpoint::End {
                                     source: selection,
+                                    domain,
                                     options,
                                 }
                             }
@@ -1102,11 +1606,12 @@ impl Domain {
                         self.nodes[node]
                             .borrow_mut()
                             .with_reader_mut(|r| {
-                                let w = r
-                                    .writer_mut()
-                                    .expect("reader replay requested for non-materialized reader");
+                                assert!(
+                                    r.writer_mut().is_some(),
+                                    "reader replay requested for non-materialized reader"
+                                );
                                 // ensure that all writes have been applied
-                                w.swap();
+                                r.swap();
                             })
                             .expect("reader replay requested for non-reader node");
 
@@ -1314,12 +1819,7 @@ impl Domain {
                                 match (n.get_base(), &params.mode) {
                                     (Some(base), &DurabilityMode::DeleteOnExit)
                                     | (Some(base), &DurabilityMode::Permanent) => {
-                                        let base_name = format!(
-                                            "{}-{}-{}",
-                                            params.log_prefix,
-                                            n.name(),
-                                            self.shard.unwrap_or(0),
-                                        );
+                                        let base_name = self.base_file_name(node);
 
                                         Box::new(PersistentState::new(
                                             base_name,
@@ -1339,20 +1839,54 @@ impl Domain {
                             // materialized
                         }
 
+                        // a base that replaces one that is gone, or a node of a copy of a shard
+                        // that moved, starts out with its rows unless it found rows of its own on
+                        // disk
+                        if let Some(rows) = self.restoring.remove(node) {
+                            match self.state.get_mut(node) {
+                                Some(s) if s.rows() == 0 => {
+                                    let mut rs = rows.into_iter().collect();
+                                    s.process_records(&mut rs, None);
+                                }
+                                _ => {
+                                    warn!(self.log, "not restoring rows of node with state";
+                                          "local" => node.id());
+                                }
+                            }
+                        }
+
+                        let base = {
+                            let n = self.nodes[node].borrow();
+                            if n.is_base() && self.backup_of.is_none() {
+                                Some(n.global_addr())
+                            } else {
+                                None
+                            }
+                        };
+                        if let Some(gid) = base {
+                            self.open_change_log(node, gid);
+                        }
+
                         if self.not_ready.remove(&node) {
                             trace!(self.log, "readying empty node"; "local" => node.id());
                         }
 
+                        // a base that takes over from another applies the writes that were passed
+                        // on to it in the meantime
+                        if let Some(held) = self.held_inputs.remove(node) {
+                            for m in held {
+                                self.handle(m, executor, false);
+                            }
+                        }
+
                         // swap replayed reader nodes to expose new state
                         {
                             let mut n = self.nodes[node].borrow_mut();
                             if n.is_reader() {
                                 n.with_reader_mut(|r| {
-                                    if let Some(ref mut state) = r.writer_mut() {
-                                        trace!(self.log, "swapping state"; "local" => node.id());
-                                        state.swap();
-                                        trace!(self.log, "state swapped"; "local" => node.id());
-                                    }
+                                    trace!(self.log, "swapping state"; "local" => node.id());
+                                    r.swap();
+                                    trace!(self.log, "state swapped"; "local" => node.id());
                                 })
                                 .unwrap();
                             }
@@ -1447,9 +1981,141 @@ impl Domain {
                             .send(ControlReplyPacket::Statistics(domain_stats, node_stats))
                             .unwrap();
                     }
+                    Packet::MoveOut { alias } => {
+                        // writes waiting to be committed go with the rest of the state
+                        for m in self.group_commit_queues.flush_all() {
+                            self.handle(m, executor, false);
+                        }
+
+                        let moved = if self.can_move_out() {
+                            let moved = self.move_out(executor);
+                            self.moved_out = Some(MovedOut {
+                                alias: (alias, 0),
+                                held: Some(Vec::new()),
+                                retiring: false,
+                                retired: false,
+                            });
+                            Some(moved)
+                        } else {
+                            None
+                        };
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::MovedOut(moved))
+                            .unwrap();
+                    }
+                    Packet::Reroute { moves } => {
+                        for (moved, to) in moves {
+                            self.reroute(moved, to, executor);
+                        }
+                    }
+                    Packet::PinSnapshot { node, id, after } => {
+                        self.pinning.push((node, id, after));
+                        self.pin_snapshots();
+                    }
+                    Packet::SnapshotPage { id, rows } => {
+                        let page = self.snapshots.get_mut(&id).map(|(last_read, pinned)| {
+                            *last_read = time::Instant::now();
+                            let page: Vec<_> = pinned.take(rows).collect();
+                            (page, pinned.as_slice().is_empty())
+                        });
+                        if let Some((_, true)) = page {
+                            self.snapshots.remove(&id);
+                        }
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::SnapshotPage(page))
+                            .unwrap();
+                    }
                     Packet::UpdateStateSize => {
                         self.update_state_sizes();
                     }
+                    Packet::PrepareTransaction { node, txn, data } => {
+                        assert!(
+                            !self.prepared.contains_key(node),
+                            "base node asked to prepare two transactions at once"
+                        );
+                        let (gid, results) = {
+                            let mut n = self.nodes[node].borrow_mut();
+                            let gid = n.global_addr();
+                            let results = n
+                                .get_base_mut()
+                                .expect("told to prepare a transaction at a non-base node")
+                                .check(node, data.clone(), &self.state);
+                            (gid, results)
+                        };
+                        if results.iter().all(OperationResult::is_applied) {
+                            let p = PreparedTransaction {
+                                txn,
+                                data,
+                                held: VecDeque::new(),
+                            };
+                            self.prepared.insert(node, p);
+                        }
+                        let shard = self.shard.unwrap_or(0);
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::Prepared(gid, shard, results))
+                            .unwrap();
+                    }
+                    Packet::CommitTransaction { node, hold } => {
+                        let PreparedTransaction { txn, data, held } = self
+                            .prepared
+                            .remove(node)
+                            .expect("told to commit a transaction that was not prepared");
+                        let m = Packet::Input {
+                            inner: LocalOrNot::new(Input {
+                                dst: node,
+                                data,
+                                txn: Some(txn.clone()),
+                                track: true,
+                                bulk: false,
+                            }),
+                            src: None,
+                            senders: Vec::new(),
+                        };
+                        self.handle(Box::new(m), executor, false);
+
+                        let token = {
+                            let n = self.nodes[node].borrow();
+                            let seq = n.get_base().unwrap().last_write();
+                            WriteToken::new(n.global_addr(), self.shard.unwrap_or(0), seq)
+                        };
+                        self.reply_after_backups(HeldReply::Committed(token), executor);
+
+                        if hold {
+                            let p = PreparedTransaction {
+                                txn,
+                                data: Vec::new(),
+                                held,
+                            };
+                            self.prepared.insert(node, p);
+                        } else {
+                            for m in held {
+                                self.handle(m, executor, false);
+                            }
+                        }
+                    }
+                    Packet::AbortTransaction { node } => {
+                        if let Some(p) = self.prepared.remove(node) {
+                            for m in p.held {
+                                self.handle(m, executor, false);
+                            }
+                        }
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
+                    Packet::UpdateWritePaths {
+                        node,
+                        paths,
+                        first_txn,
+                    } => {
+                        self.nodes[node]
+                            .borrow_mut()
+                            .with_reader_mut(|r| r.set_write_paths(paths, first_txn))
+                            .unwrap();
+                        self.control_reply_tx
+                            .send(ControlReplyPacket::ack())
+                            .unwrap();
+                    }
                     Packet::Quit => unreachable!("Quit messages are handled by event loop"),
                     Packet::Spin => {
                         // spinning as instructed
@@ -1527,11 +2193,7 @@ impl Domain {
                 for n in swap {
                     self.nodes[n]
                         .borrow_mut()
-                        .with_reader_mut(|r| {
-                            if let Some(wh) = r.writer_mut() {
-                                wh.swap();
-                            }
-                        })
+                        .with_reader_mut(|r| r.swap())
                         .unwrap();
                 }
 
@@ -2042,12 +2704,7 @@ impl Domain {
                                 }
                             } else if is_reader {
                                 // we filled a hole! swap the reader.
-                                n.with_reader_mut(|r| {
-                                    if let Some(wh) = r.writer_mut() {
-                                        wh.swap();
-                                    }
-                                })
-                                .unwrap();
+                                n.with_reader_mut(|r| r.swap()).unwrap();
                                 // and also unmark the replay request
                                 if let Some(ref mut prev) =
                                     self.reader_triggered.get_mut(segment.node)
@@ -2913,6 +3570,333 @@ impl Domain {
             .unwrap();
     }
 
+    /// Copy all the rows in the state of the given node, or `None` if it holds only some of them.
+    fn snapshot(&self, node: LocalNodeIndex) -> Option<Vec<Vec<DataType>>> {
+        let n = self.nodes[node].borrow();
+        if n.is_reader() {
+            // readers keep their state outside of the domain, and only the reader handle can
+            // iterate over it.
+            let shard = self.shard.unwrap_or(0);
+            self.readers
+                .lock()
+                .unwrap()
+                .get(&(n.global_addr(), shard))
+                .and_then(|r| {
+                    r.try_find_range_and(Bound::Unbounded, Bound::Unbounded, |rs| {
+                        rs.iter().cloned().collect::<Vec<_>>()
+                    })
+                    .ok()
+                    .and_then(|found| found)
+                })
+                .map(|found| found.into_iter().flatten().collect())
+        } else {
+            match self.state.get(node) {
+                Some(s) if !s.is_partial() => {
+                    let mut rows = s.cloned_records();
+                    if let Some(b) = n.get_base() {
+                        // rows written before columns were added are stored without them.
+                        for row in &mut rows {
+                            b.fix(row);
+                        }
+                    }
+                    Some(rows)
+                }
+                _ => None,
+            }
+        }
+    }
+
+    /// Send the given reply to the write shipped to the backups last once they have all applied
+    /// it, or right away if there are no backups.
+    fn reply_after_backups(&mut self, reply: HeldReply, executor: &mut dyn Executor) {
+        self.held_replies.push_back((self.shipped, reply));
+        self.release_replies(executor);
+    }
+
+    /// Send the held replies to the writes that every backup has applied.
+    fn release_replies(&mut self, executor: &mut dyn Executor) {
+        let backup_applied = &self.backup_applied;
+        let applied = self
+            .backups
+            .iter()
+            .map(|b| backup_applied.get(b).cloned().unwrap_or(0))
+            .min()
+            .unwrap_or(self.shipped);
+        while let Some(&(seq, _)) = self.held_replies.front() {
+            if seq > applied {
+                break;
+            }
+            match self.held_replies.pop_front().unwrap().1 {
+                HeldReply::Ack(src, result) => executor.ack(src, result),
+                HeldReply::Committed(token) => self
+                    .control_reply_tx
+                    .send(ControlReplyPacket::Committed(token))
+                    .unwrap(),
+                HeldReply::Forwarded(from, id, result) => {
+                    let m = Packet::ForwardedResult {
+                        id,
+                        shard: self.shard.unwrap_or(0),
+                        result,
+                    };
+                    executor.send(from, Box::new(m));
+                }
+            }
+        }
+    }
+
+    /// Ship a write to the backups, if there are any, and return whether it was shipped. Nobody
+    /// hears about the write until they have all applied it, so that it is not lost if we fail.
+    fn ship_to_backups(&mut self, input: &Input, executor: &mut dyn Executor) -> bool {
+        if self.backups.is_empty() {
+            return false;
+        }
+        self.shipped += 1;
+        let shard = self.shard.unwrap_or(0);
+        for &backup in &self.backups {
+            let m = Packet::BackupInput {
+                seq: self.shipped,
+                inner: input.clone(),
+            };
+            executor.send((backup, shard), Box::new(m));
+        }
+        true
+    }
+
+    /// Pass a copy of a write to a base that is being resharded on to the base that takes over
+    /// from it, if there is one.
+    fn mirror(&mut self, input: &Input, executor: &mut dyn Executor) {
+        if !self.reshards.contains_key(input.dst) {
+            return;
+        }
+        let input = Input {
+            dst: input.dst,
+            data: input.data.clone(),
+            txn: None,
+            track: false,
+            bulk: input.bulk,
+        };
+        self.forward(input, None, executor);
+    }
+
+    /// Pass a write to a base that has handed over on to the base that took over from it.
+    fn forward_input(&mut self, m: Box<Packet>, executor: &mut dyn Executor) {
+        let (input, reply) = match *m {
+            Packet::Input {
+                inner,
+                src,
+                mut senders,
+            } => {
+                let input = unsafe { inner.take() };
+                senders.extend(src.map(|src| (src, 0..input.data.len())));
+                (input, Some(ForwardedFor::Clients(senders)))
+            }
+            Packet::ForwardedInput { from, id, inner } => {
+                (inner, id.map(|id| ForwardedFor::Base(from, id)))
+            }
+            _ => unreachable!(),
+        };
+        self.forward(input, reply, executor);
+    }
+
+    /// Send the operations of a write to the base given by `input.dst` to the shards of the base
+    /// that takes over from it that they belong in. A write without operations goes to every
+    /// shard.
+    ///
+    /// If anyone is to hear how the write went, the results are collected as the shards send
+    /// them back.
+    fn forward(&mut self, input: Input, reply: Option<ForwardedFor>, executor: &mut dyn Executor) {
+        let Input {
+            dst,
+            data,
+            txn,
+            track,
+            bulk,
+        } = input;
+        let r = &self.reshards[dst];
+        let ((to, local), column, shards) = (r.to, r.column, r.shards);
+
+        let nops = data.len();
+        let mut parts: HashMap<usize, (Vec<usize>, Vec<TableOperation>)> = if nops == 0 {
+            (0..shards).map(|s| (s, Default::default())).collect()
+        } else {
+            HashMap::new()
+        };
+        for (i, op) in data.into_iter().enumerate() {
+            let part = parts.entry(op.shard(column, shards)).or_default();
+            part.0.push(i);
+            part.1.push(op);
+        }
+
+        let id = reply.map(|reply| {
+            let id = self.next_forwarded;
+            self.next_forwarded += 1;
+            let w = ForwardedWrite {
+                node: dst,
+                reply,
+                parts: parts
+                    .iter()
+                    .map(|(&s, (ops, _))| (s, ops.clone()))
+                    .collect(),
+                results: vec![None; nops],
+                token: WriteToken::default(),
+            };
+            self.forwarded.insert(id, w);
+            id
+        });
+
+        let from = (self.index, self.shard.unwrap_or(0));
+        for (shard, (_, data)) in parts {
+            let m = Packet::ForwardedInput {
+                from,
+                id,
+                inner: Input {
+                    dst: local,
+                    data,
+                    txn: txn.clone(),
+                    track,
+                    bulk,
+                },
+            };
+            executor.send((to, shard), Box::new(m));
+        }
+    }
+
+    /// Apply a write passed on by a base to this base, which took over from it, and send the
+    /// results back if the other base asked for them.
+    fn apply_forwarded(&mut self, m: Box<Packet>, executor: &mut dyn Executor) {
+        let (from, id, inner) = match *m {
+            Packet::ForwardedInput { from, id, inner } => (from, id, inner),
+            _ => unreachable!(),
+        };
+        self.mirror(&inner, executor);
+        self.ship_to_backups(&inner, executor);
+
+        // the write comes from a base rather than from a client, so the reply to it is caught
+        // here rather than sent to a client
+        let src = id.map(|_| SourceChannelIdentifier {
+            token: 0,
+            epoch: 0,
+            tag: 0,
+        });
+        let m = Packet::Input {
+            inner: LocalOrNot::new(inner),
+            src,
+            senders: Vec::new(),
+        };
+        let mut held = HoldAcks {
+            inner: &mut *executor,
+            acks: Vec::new(),
+        };
+        self.dispatch(Box::new(m), &mut held);
+        let acks = held.acks;
+        if let Some(id) = id {
+            for (_, result) in acks {
+                self.reply_after_backups(HeldReply::Forwarded(from, id, result), executor);
+            }
+        }
+    }
+
+    /// Tell whoever is waiting for a write that was passed on how it went.
+    fn reply_forwarded(&mut self, w: ForwardedWrite, executor: &mut dyn Executor) {
+        let results: Vec<_> = w
+            .results
+            .into_iter()
+            .map(|r| r.expect("missing result of forwarded operation"))
+            .collect();
+        match w.reply {
+            ForwardedFor::Clients(senders) => {
+                for (src, ops) in senders {
+                    let result = noria::WriteResult {
+                        token: w.token.clone(),
+                        results: results[ops].to_vec(),
+                    };
+                    match self.reshards.get_mut(w.node) {
+                        Some(r) if r.hold => r.held.push((src, result)),
+                        _ => executor.ack(src, result),
+                    }
+                }
+            }
+            ForwardedFor::Base(from, id) => {
+                let m = Packet::ForwardedResult {
+                    id,
+                    shard: self.shard.unwrap_or(0),
+                    result: noria::WriteResult {
+                        token: w.token,
+                        results,
+                    },
+                };
+                executor.send(from, Box::new(m));
+            }
+            ForwardedFor::Controller => {
+                self.control_reply_tx
+                    .send(ControlReplyPacket::Forwarded(w.token))
+                    .unwrap();
+            }
+        }
+    }
+
+    /// Tell the controller about the readers it waits for that reflect the writes it waits for.
+    fn release_awaiting(&mut self) {
+        if self.awaiting.is_empty() {
+            return;
+        }
+
+        let shard = self.shard.unwrap_or(0);
+        for (node, token) in mem::take(&mut self.awaiting) {
+            let applied = {
+                let gid = self.nodes[node].borrow().global_addr();
+                self.readers
+                    .lock()
+                    .unwrap()
+                    .get(&(gid, shard))
+                    .map_or(true, |r| r.has_applied(&token))
+            };
+            if applied {
+                self.control_reply_tx
+                    .send(ControlReplyPacket::ack())
+                    .unwrap();
+            } else {
+                self.awaiting.push((node, token));
+            }
+        }
+    }
+
+    /// Copy the rows of the nodes that snapshots are waiting for, once they have applied the
+    /// writes that the snapshots must reflect.
+    fn pin_snapshots(&mut self) {
+        if self.pinning.is_empty() {
+            return;
+        }
+
+        let shard = self.shard.unwrap_or(0);
+        for (node, id, after) in mem::take(&mut self.pinning) {
+            let ready = {
+                let n = self.nodes[node].borrow();
+                !n.is_reader()
+                    || self
+                        .readers
+                        .lock()
+                        .unwrap()
+                        .get(&(n.global_addr(), shard))
+                        .map_or(true, |r| r.has_applied(&after))
+            };
+            if !ready {
+                self.pinning.push((node, id, after));
+                continue;
+            }
+
+            let rows = self.snapshot(node);
+            let pinned = rows.is_some();
+            if let Some(rows) = rows {
+                self.snapshots
+                    .insert(id, (time::Instant::now(), rows.into_iter()));
+            }
+            self.control_reply_tx
+                .send(ControlReplyPacket::SnapshotPinned(pinned))
+                .unwrap();
+        }
+    }
+
     pub fn update_state_sizes(&mut self) {
         let total: u64 = self
             .nodes
@@ -2946,6 +3930,452 @@ impl Domain {
         // no response sent, as worker will read the atomic
     }
 
+    /// The name the files of the given base node are kept under.
+    ///
+    /// A base that took over from one of the same name as the table was resharded keeps its
+    /// files apart from those of the other.
+    fn base_file_name(&self, node: LocalNodeIndex) -> String {
+        let n = self.nodes[node].borrow();
+        let name = match n.get_base().map(|b| b.generation()) {
+            Some(generation) if generation > 0 => format!("{}.{}", n.name(), generation),
+            _ => n.name().to_owned(),
+        };
+        format!(
+            "{}-{}-{}",
+            self.persistence_parameters.log_prefix,
+            name,
+            self.shard.unwrap_or(0),
+        )
+    }
+
+    /// Open the change log of the given base, and make it available to the readers.
+    fn open_change_log(&mut self, node: LocalNodeIndex, gid: NodeIndex) {
+        let shard = self.shard.unwrap_or(0);
+        let name = self.base_file_name(node);
+        let log = ChangeLogWriter::open(&name, &self.persistence_parameters)
+            .expect("failed to open change log");
+        self.change_logs
+            .lock()
+            .unwrap()
+            .insert((gid, shard), log.log());
+        self.base_changes.insert(node, log);
+    }
+
+    /// Publish the changes bases have made since the last time, so that they become visible to
+    /// the clients following them.
+    fn publish_changes(&mut self) {
+        for (_, log) in self.base_changes.iter_mut() {
+            log.publish().expect("failed to persist change log");
+        }
+    }
+
+    /// Send to the given shard, which moved to the worker at `to`, there from now on, after
+    /// telling it that nothing more comes the old way.
+    fn reroute(&mut self, moved: ReplicaAddr, to: SocketAddr, executor: &mut dyn Executor) {
+        // replays are asked for over connections of their own, which must go to the copy too
+        let shard = self.shard;
+        let cc = &self.channel_coordinator;
+        for path in self.replay_paths.values_mut() {
+            if let TriggerEndpoint::End {
+                source,
+                domain,
+                ref mut options,
+            } = path.trigger
+            {
+                if domain != moved.0 {
+                    continue;
+                }
+                let i = match source {
+                    SourceSelection::SameShard if shard == Some(moved.1) => 0,
+                    SourceSelection::SameShard => continue,
+                    _ => moved.1,
+                };
+
+                // our worker may not have heard where the shard went yet
+                while cc.get_addr(&moved) != Some(to) {}
+                options[i] = tokio::task::block_in_place(|| {
+                    cc.builder_for(&moved).unwrap().build_sync().unwrap()
+                });
+            }
+        }
+
+        // the last packet from this shard that goes through the old copy
+        executor.send(moved, Box::new(Packet::Rerouted { to }));
+    }
+
+    fn process(&mut self, packet: Box<Packet>, executor: &mut dyn Executor) {
+        let packet = match *packet {
+            // passed on by the copy of this shard that moved out before this one took over
+            Packet::Relayed { .. } => match *packet {
+                Packet::Relayed { inner } => inner,
+                _ => unreachable!(),
+            },
+            _ => packet,
+        };
+        if let Packet::Rerouted { .. } = *packet {
+            // every shard that sends to this one was heard from as it took over
+            return;
+        }
+
+        // TODO: Initialize tracer here, and when flushing group commit
+        // queue.
+        if self.group_commit_queues.should_append(&packet, &self.nodes) {
+            if let Some(packet) = self.group_commit_queues.append(packet) {
+                self.handle(packet, executor, true);
+            }
+        } else {
+            if let Some(m) = self.group_commit_queues.flush_before(&packet) {
+                self.handle(m, executor, true);
+            }
+            self.handle(packet, executor, true);
+        }
+
+        while let Some(m) = self.group_commit_queues.flush_if_necessary() {
+            self.handle(m, executor, true);
+        }
+        self.publish_changes();
+        self.pin_snapshots();
+        self.release_awaiting();
+    }
+
+    /// Whether this shard can hand over to a copy of it on another worker.
+    ///
+    /// Replays, transactions, snapshots and writes passed on to other bases are all tied to this
+    /// copy of the shard, so it only hands over once none of them are in progress.
+    fn can_move_out(&self) -> bool {
+        let readers_settled = self.nodes.values().all(|n| {
+            let n = n.borrow();
+            !n.is_reader() || n.is_dropped() || !n.with_reader(|r| r.holds_back()).unwrap()
+        });
+
+        self.mode == DomainMode::Forwarding
+            && self.waiting.is_empty()
+            && self.concurrent_replays == 0
+            && self.replay_request_queue.is_empty()
+            && self.delayed_for_self.is_empty()
+            && self
+                .buffered_replay_requests
+                .values()
+                .all(|(_, keys, _)| keys.is_empty())
+            && self.prepared.is_empty()
+            && self.pinning.is_empty()
+            && self.awaiting.is_empty()
+            && self.snapshots.is_empty()
+            && self.held_replies.is_empty()
+            && self.forwarded.is_empty()
+            && self.restoring.is_empty()
+            && self.held_inputs.is_empty()
+            && self.reshards.values().all(|r| !r.hold)
+            && readers_settled
+    }
+
+    /// Hand this shard over to a copy of it on another worker, and return the nodes along with
+    /// everything else the copy needs.
+    fn move_out(&mut self, executor: &mut dyn Executor) -> (DomainNodes, Box<MovedShard>) {
+        let shard = self.shard.unwrap_or(0);
+
+        // the copy starts out without any partial state, so what was filled from it downstream
+        // must be forgotten too
+        let partial: Vec<_> = self
+            .state
+            .iter()
+            .filter(|(_, s)| s.is_partial())
+            .map(|(node, _)| node)
+            .collect();
+        for node in partial {
+            let m = Packet::Evict {
+                node: Some(node),
+                num_bytes: usize::max_value(),
+            };
+            self.handle_eviction(Box::new(m), executor);
+        }
+
+        let mut rows = Map::default();
+        let mut readers = Map::default();
+        for (node, n) in self.nodes.iter() {
+            let n = n.borrow();
+            if n.is_dropped() {
+                continue;
+            }
+            if n.is_reader() {
+                let published = self
+                    .readers
+                    .lock()
+                    .unwrap()
+                    .get(&(n.global_addr(), shard))
+                    .map(|r| r.published())
+                    .unwrap_or_default();
+                let rs = if n.with_reader(|r| r.is_partial()).unwrap() {
+                    Vec::new()
+                } else {
+                    self.snapshot(node).unwrap_or_default()
+                };
+                readers.insert(node, (rs, published));
+            } else if let Some(s) = self.state.get(node).filter(|s| !s.is_partial()) {
+                rows.insert(node, s.cloned_records());
+            }
+        }
+
+        let nodes = &self.nodes;
+        let setup = mem::take(&mut self.setup)
+            .into_iter()
+            .filter(|p| match *p {
+                Packet::PrepareState { node, .. }
+                | Packet::Ready { node, .. }
+                | Packet::AliasReader { node, .. } => !nodes[node].borrow().is_dropped(),
+                Packet::SetupReplayPath { ref path, .. } => path
+                    .iter()
+                    .all(|segment| !nodes[segment.node].borrow().is_dropped()),
+                _ => true,
+            })
+            .collect();
+
+        let reshards = mem::take(&mut self.reshards)
+            .into_iter()
+            .map(|(node, r)| (node, (r.to, r.column, r.shards, r.forward)))
+            .collect();
+
+        let moved = MovedShard {
+            setup,
+            rows,
+            readers,
+            ingress_inject: mem::take(&mut self.ingress_inject),
+            backups: mem::take(&mut self.backups),
+            shipped: self.shipped,
+            backup_applied: mem::take(&mut self.backup_applied),
+            reshards,
+            next_forwarded: self.next_forwarded,
+        };
+
+        // the copy opens the files of the base nodes again
+        self.publish_changes();
+        let mut change_logs = self.change_logs.lock().unwrap();
+        for (node, _) in mem::take(&mut self.base_changes) {
+            change_logs.remove(&(self.nodes[node].borrow().global_addr(), shard));
+        }
+        drop(change_logs);
+        self.state = StateMap::default();
+        self.timed_purges.clear();
+
+        // writes passed on from here are numbered apart from those the copy passes on
+        self.next_forwarded = 1 << 63;
+
+        // the write handles of the readers go with the nodes, which retires their read handles
+        (mem::take(&mut self.nodes), Box::new(moved))
+    }
+
+    /// Take over from the copy of this shard that moved out, with what it held.
+    fn move_in(&mut self, moved: MovedShard, executor: &mut dyn Executor) {
+        let MovedShard {
+            setup,
+            rows,
+            readers,
+            ingress_inject,
+            backups,
+            shipped,
+            backup_applied,
+            reshards,
+            next_forwarded,
+        } = moved;
+
+        self.ingress_inject = ingress_inject;
+        self.backups = backups;
+        self.shipped = shipped;
+        self.backup_applied = backup_applied;
+        self.next_forwarded = next_forwarded;
+        for (node, (to, column, shards, forward)) in reshards {
+            let r = Reshard {
+                to,
+                column,
+                shards,
+                forward,
+                hold: false,
+                held: Vec::new(),
+            };
+            self.reshards.insert(node, r);
+        }
+
+        // nodes start out with their rows as they become ready, just like bases that replace others
+        self.restoring = rows;
+        for m in setup {
+            self.handle(Box::new(m), executor, false);
+        }
+
+        for (node, (rows, published)) in readers {
+            let mut n = self.nodes[node].borrow_mut();
+            n.with_reader_mut(|r| {
+                if let Some(w) = r.writer_mut() {
+                    w.add(rows.into_iter().map(Record::Positive));
+                    for ((base, shard), seq) in published {
+                        w.applied(base, shard, seq);
+                    }
+                }
+                r.swap();
+            })
+            .unwrap();
+        }
+    }
+
+    /// Handle a packet sent to this shard after it handed over to a copy of it on another worker.
+    fn pass_on(&mut self, m: Box<Packet>, executor: &mut dyn Executor) {
+        match *m {
+            Packet::Relay { moves } => {
+                // the copies of this shard and of those that moved with it hear from this shard
+                // for the last time like they do from every other shard, and then the copy of
+                // this shard gets what arrived in the meantime
+                for (moved, to) in moves {
+                    executor.send(moved, Box::new(Packet::Rerouted { to }));
+                }
+                let held = self.moved_out.as_mut().unwrap().held.take();
+                for m in held.unwrap_or_default() {
+                    self.relay(m, executor);
+                }
+            }
+            Packet::ForwardedResult { id, .. } if self.forwarded.contains_key(&id) => {
+                // the copy applied a write that was sent here
+                self.handle(m, executor, true);
+            }
+            Packet::RetireRelay => {
+                self.moved_out.as_mut().unwrap().retiring = true;
+            }
+            Packet::Input { src: Some(_), .. } if self.moved_out.as_ref().unwrap().retiring => {
+                // the client hears nothing, and once this shard quits, its connection closes and
+                // it asks the controller where the copy is before it writes again
+            }
+            _ => match self.moved_out.as_mut().unwrap().held {
+                Some(ref mut held) => held.push(m),
+                None => self.relay(m, executor),
+            },
+        }
+    }
+
+    /// Report that the copy of this shard applied every write passed on through it, once it has
+    /// and this shard was told to stop passing on writes.
+    fn finish_retiring(&mut self) {
+        let moved = match self.moved_out {
+            Some(ref mut moved) => moved,
+            None => return,
+        };
+        if !moved.retiring || moved.retired || !self.forwarded.is_empty() {
+            return;
+        }
+
+        moved.retired = true;
+        self.control_reply_tx
+            .send(ControlReplyPacket::RelayRetired)
+            .unwrap();
+    }
+
+    /// Pass a packet on to the copy of this shard that took over from it.
+    fn relay(&mut self, m: Box<Packet>, executor: &mut dyn Executor) {
+        let inner = match *m {
+            Packet::Input {
+                inner,
+                src,
+                mut senders,
+            } => {
+                // the clients that sent the write are connected to this shard, so the copy tells
+                // it how the write went
+                let input = unsafe { inner.take() };
+                senders.extend(src.map(|src| (src, 0..input.data.len())));
+                let id = if senders.is_empty() {
+                    None
+                } else {
+                    let id = self.next_forwarded;
+                    self.next_forwarded += 1;
+                    let nops = input.data.len();
+                    let w = ForwardedWrite {
+                        node: input.dst,
+                        reply: ForwardedFor::Clients(senders),
+                        parts: Some((self.shard.unwrap_or(0), (0..nops).collect()))
+                            .into_iter()
+                            .collect(),
+                        results: vec![None; nops],
+                        token: WriteToken::default(),
+                    };
+                    self.forwarded.insert(id, w);
+                    Some(id)
+                };
+                Box::new(Packet::ForwardedInput {
+                    from: self.moved_out.as_ref().unwrap().alias,
+                    id,
+                    inner: input,
+                })
+            }
+            // other copies of shards that moved pass on what they are sent as it is
+            Packet::Relayed { inner } => inner,
+            m => Box::new(m),
+        };
+        executor.send(self.id(), Box::new(Packet::Relayed { inner }));
+    }
+
+    /// Handle a packet sent to this shard while it takes over from a copy of it on another worker.
+    ///
+    /// Packets sent straight to this shard wait until every shard that sends to it has sent its
+    /// last packet through the copy, so that packets from each arrive in the order they were sent.
+    fn take_over(&mut self, m: Box<Packet>, executor: &mut dyn Executor) {
+        let m = match *m {
+            Packet::MoveIn {
+                moved,
+                senders,
+                moves,
+            } => {
+                // the nodes of this shard may send to the shards that moved along with it, which
+                // must be sent to where they went
+                for (shard, to) in moves {
+                    while self.channel_coordinator.get_addr(&shard) != Some(to) {}
+                }
+                self.move_in(*moved, executor);
+                let relayed = {
+                    let moving = self.moving_in.as_mut().unwrap();
+                    moving.senders = Some(senders);
+                    mem::take(&mut moving.relayed)
+                };
+                for m in relayed {
+                    self.process(m, executor);
+                }
+                return self.finish_moving_in(executor);
+            }
+            Packet::Relayed { inner } => inner,
+            Packet::Rerouted { .. } => {
+                self.moving_in.as_mut().unwrap().rerouted += 1;
+                return self.finish_moving_in(executor);
+            }
+            m => {
+                self.moving_in.as_mut().unwrap().held.push_back(Box::new(m));
+                return;
+            }
+        };
+
+        let moving = self.moving_in.as_mut().unwrap();
+        if let Packet::Rerouted { .. } = *m {
+            moving.rerouted += 1;
+            self.finish_moving_in(executor);
+        } else if moving.senders.is_none() {
+            moving.relayed.push_back(m);
+        } else {
+            self.process(m, executor);
+        }
+    }
+
+    fn finish_moving_in(&mut self, executor: &mut dyn Executor) {
+        let done = self
+            .moving_in
+            .as_ref()
+            .map_or(false, |m| m.senders == Some(m.rerouted));
+        if !done {
+            return;
+        }
+
+        let moving = self.moving_in.take().unwrap();
+        self.control_reply_tx
+            .send(ControlReplyPacket::MovedIn)
+            .unwrap();
+        for m in moving.held {
+            self.process(m, executor);
+        }
+    }
+
     pub fn on_event(&mut self, executor: &mut dyn Executor, event: PollEvent) -> ProcessResult {
         if self.wait_time.is_running() {
             self.wait_time.stop();
@@ -2954,6 +4384,10 @@ impl Domain {
         //self.total_ptime.start();
         let res = match event {
             PollEvent::ResumePolling => {
+                // the clients have been sent how their writes went by the time we get here, so a
+                // shard that moved out can quit once the controller hears that it is done
+                self.finish_retiring();
+
                 // when do we need to be woken up again?
                 let now = time::Instant::now();
                 let opt1 = self
@@ -2975,13 +4409,26 @@ impl Domain {
                     }
                 });
 
-                let mut timeout = opt1.or(opt2).or(opt3);
+                let opt4 = self
+                    .snapshots
+                    .values()
+                    .map(|&(last_read, _)| {
+                        SNAPSHOT_TIMEOUT
+                            .checked_sub(now.duration_since(last_read))
+                            .unwrap_or(time::Duration::from_millis(0))
+                    })
+                    .min();
+
+                let mut timeout = opt1.or(opt2).or(opt3).or(opt4);
                 if let Some(opt2) = opt2 {
                     timeout = Some(std::cmp::min(timeout.unwrap(), opt2));
                 }
                 if let Some(opt3) = opt3 {
                     timeout = Some(std::cmp::min(timeout.unwrap(), opt3));
                 }
+                if let Some(opt4) = opt4 {
+                    timeout = Some(std::cmp::min(timeout.unwrap(), opt4));
+                }
                 ProcessResult::KeepPolling(timeout)
             }
             PollEvent::Process(packet) => {
@@ -2989,26 +4436,30 @@ impl Domain {
                     return ProcessResult::StopPolling;
                 }
 
-                // TODO: Initialize tracer here, and when flushing group commit
-                // queue.
-                if self.group_commit_queues.should_append(&packet, &self.nodes) {
-                    if let Some(packet) = self.group_commit_queues.append(packet) {
-                        self.handle(packet, executor, true);
-                    }
+                if self.moved_out.is_some() {
+                    self.pass_on(packet, executor);
+                } else if self.moving_in.is_some() {
+                    self.take_over(packet, executor);
                 } else {
-                    self.handle(packet, executor, true);
+                    self.process(packet, executor);
                 }
-
-                while let Some(m) = self.group_commit_queues.flush_if_necessary() {
-                    self.handle(m, executor, true);
-                }
-
                 ProcessResult::Processed
             }
+            // a shard that has moved out holds nothing that needs to be looked after
+            PollEvent::Timeout if self.moved_out.is_some() => ProcessResult::Processed,
             PollEvent::Timeout => {
                 while let Some(m) = self.group_commit_queues.flush_if_necessary() {
                     self.handle(m, executor, true);
                 }
+                self.publish_changes();
+                self.pin_snapshots();
+                self.release_awaiting();
+
+                // clients that stop reading a snapshot never say so
+                let now = time::Instant::now();
+                self.snapshots.retain(|_, &mut (last_read, _)| {
+                    now.duration_since(last_read) < SNAPSHOT_TIMEOUT
+                });
 
                 if !self.buffered_replay_requests.is_empty() || !self.timed_purges.is_empty() {
                     self.handle(Box::new(Packet::Spin), executor, true);
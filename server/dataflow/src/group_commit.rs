@@ -19,15 +19,31 @@ impl GroupCommitQueueSet {
     }
 
     /// Returns whether the given packet should be persisted.
+    ///
+    /// Inputs that are part of a transaction are never merged with other inputs, since every one
+    /// of them must produce its own update downstream. Neither are the batches of a bulk load,
+    /// which are large enough on their own, and are applied differently.
     pub fn should_append(&self, p: &Packet, nodes: &DomainNodes) -> bool {
-        if let Packet::Input { .. } = *p {
+        if let Packet::Input { ref inner, .. } = *p {
             assert!(nodes[p.dst()].borrow().is_base());
-            true
+            p.txn().is_none() && !unsafe { inner.deref() }.bulk
         } else {
             false
         }
     }
 
+    /// Merge any packets queued for the base the given input is destined for, so that they can be
+    /// processed before it.
+    pub fn flush_before(&mut self, p: &Packet) -> Option<Box<Packet>> {
+        if let Packet::Input { .. } = *p {
+            let node = p.dst();
+            if self.pending_packets.contains_key(node) {
+                return self.flush_internal(node);
+            }
+        }
+        None
+    }
+
     /// Find the first queue that has timed out waiting for more packets, and flush it to disk.
     pub fn flush_if_necessary(&mut self) -> Option<Box<Packet>> {
         let now = time::Instant::now();
@@ -45,6 +61,20 @@ impl GroupCommitQueueSet {
         }
     }
 
+    /// Merge the packets queued for every base, however long they have waited.
+    pub fn flush_all(&mut self) -> Vec<Box<Packet>> {
+        let nodes: Vec<_> = self
+            .pending_packets
+            .iter()
+            .filter(|(_, (_, ps))| !ps.is_empty())
+            .map(|(n, _)| n)
+            .collect();
+        nodes
+            .into_iter()
+            .filter_map(|node| self.flush_internal(node))
+            .collect()
+    }
+
     /// Merge any pending packets.
     fn flush_internal(&mut self, node: LocalNodeIndex) -> Option<Box<Packet>> {
         Self::merge_packets(&mut self.pending_packets[node].1)
@@ -93,6 +123,7 @@ impl GroupCommitQueueSet {
         let merged_dst = packets.peek().as_mut().unwrap().dst();
 
         let mut all_senders = vec![];
+        let mut merged_track = false;
         let merged_data = packets.fold(Vec::new(), |mut acc, p| {
             match *p {
                 Packet::Input {
@@ -100,14 +131,25 @@ impl GroupCommitQueueSet {
                     src,
                     senders,
                 } => {
-                    let Input { dst, data } = unsafe { inner.take() };
+                    let Input {
+                        dst,
+                        data,
+                        txn,
+                        track,
+                        bulk,
+                    } = unsafe { inner.take() };
+
+                    assert!(txn.is_none());
+                    assert!(!bulk);
+                    merged_track |= track;
 
                     assert_eq!(senders.len(), 0);
                     assert_eq!(merged_dst, dst);
+                    let ops = acc.len()..acc.len() + data.len();
                     acc.extend(data);
 
                     if let Some(src) = src {
-                        all_senders.push(src);
+                        all_senders.push((src, ops));
                     }
                 }
                 _ => unreachable!(),
@@ -119,6 +161,9 @@ impl GroupCommitQueueSet {
             inner: LocalOrNot::new(Input {
                 dst: merged_dst,
                 data: merged_data,
+                txn: None,
+                track: merged_track,
+                bulk: false,
             }),
             src: None,
             senders: all_senders,
@@ -14,6 +14,7 @@ extern crate serde_derive;
 extern crate slog;
 
 pub(crate) mod backlog;
+mod changelog;
 pub mod node;
 pub mod ops;
 pub mod payload; // it makes me _really_ sad that this has to be pub
@@ -32,9 +33,13 @@ use std::time;
 pub use crate::backlog::SingleReadHandle;
 pub type Readers =
     Arc<Mutex<HashMap<(petgraph::graph::NodeIndex, usize), backlog::SingleReadHandle>>>;
+pub use crate::changelog::{ChangeLog, NextChanges};
+pub type ChangeLogs = Arc<Mutex<HashMap<(petgraph::graph::NodeIndex, usize), ChangeLog>>>;
 pub type DomainConfig = domain::Config;
 
-pub use crate::domain::{Domain, DomainBuilder, Index, PollEvent, ProcessResult};
+pub use crate::domain::{
+    Domain, DomainBuilder, Index, MovedShard, PollEvent, ProcessResult, SNAPSHOT_TIMEOUT,
+};
 pub use crate::payload::Packet;
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
@@ -207,6 +207,17 @@ impl Node {
         DanglingDomainNode(n)
     }
 
+    /// Make a copy of this base node, without any parents or children, for a domain that keeps a
+    /// backup of the base's rows.
+    pub fn backup(&self) -> Node {
+        let b = self.get_base().expect("only base nodes can be backed up");
+        let mut n = self.mirror(NodeType::Base(b.clone()));
+        n.index = self.index;
+        n.domain = self.domain;
+        n.sharded_by = self.sharded_by;
+        n
+    }
+
     pub fn remove(&mut self) {
         self.inner = NodeType::Dropped;
     }
@@ -340,6 +351,11 @@ impl Node {
         self.fields.len() - 1
     }
 
+    /// Undoes the most recent call to `add_column`.
+    pub fn remove_added_column(&mut self) {
+        self.fields.pop();
+    }
+
     pub fn has_domain(&self) -> bool {
         self.domain.is_some()
     }
@@ -1,6 +1,7 @@
 use crate::node::NodeType;
 use crate::payload;
 use crate::prelude::*;
+use noria::{WriteResult, WriteToken};
 use slog::Logger;
 use std::collections::HashSet;
 use std::mem;
@@ -33,10 +34,22 @@ impl Node {
                 // NOTE: bases only accept BaseOperations
                 match m.take().map(|p| *p) {
                     Some(Packet::Input {
-                        inner, mut senders, ..
+                        inner,
+                        src,
+                        mut senders,
                     }) => {
-                        let Input { dst, data } = unsafe { inner.take() };
-                        let mut rs = b.process(addr, data, &*state);
+                        let Input {
+                            dst,
+                            data,
+                            txn,
+                            track,
+                            bulk,
+                        } = unsafe { inner.take() };
+                        let (mut rs, results) = if bulk {
+                            b.load(addr, data, &*state)
+                        } else {
+                            b.process(addr, data, &*state)
+                        };
 
                         // When a replay originates at a base node, we replay the data *through* that
                         // same base node because its column set may have changed. However, this replay
@@ -49,13 +62,37 @@ impl Node {
                             materialize(&mut rs, None, state.get_mut(addr));
                         }
 
+                        // Writes that a client asked for a token for are numbered, and readers keep
+                        // track of which of them they have seen all the effects of. Other writes
+                        // cost nothing extra.
+                        let write = if track {
+                            Some((gaddr, on_shard.unwrap_or(0), b.next_write()))
+                        } else {
+                            None
+                        };
+                        let token = write
+                            .map(|(base, shard, seq)| WriteToken::new(base, shard, seq))
+                            .unwrap_or_default();
+
                         // Send write-ACKs to all the clients with updates that made
-                        // it into this merged packet:
-                        senders.drain(..).for_each(|src| ex.ack(src));
+                        // it into this merged packet (or that sent this packet, if it was
+                        // not merged with others). The ACKs carry the results of each client's
+                        // operations, and the token for the write, if any, so that clients can
+                        // later wait for readers to reflect it:
+                        senders.extend(src.map(|src| (src, 0..results.len())));
+                        for (src, ops) in senders.drain(..) {
+                            let result = WriteResult {
+                                token: token.clone(),
+                                results: results[ops].to_vec(),
+                            };
+                            ex.ack(src, result);
+                        }
 
                         *m = Some(Box::new(Packet::Message {
                             link: Link::new(dst, dst),
                             data: rs,
+                            txn,
+                            write,
                         }));
                     }
                     Some(ref p) => {
@@ -1,8 +1,11 @@
 use crate::prelude::*;
-use noria::{Modification, Operation, TableOperation};
+use nom_sql::{ColumnConstraint, ColumnSpecification};
+use noria::{Modification, Operation, OperationResult, TableOperation};
 use std::borrow::Cow;
-use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem;
+use std::sync::Arc;
 use vec_map::VecMap;
 
 /// Base is used to represent the root nodes of the Noria data flow graph.
@@ -13,10 +16,19 @@ use vec_map::VecMap;
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Base {
     primary_key: Option<Vec<usize>>,
+    column_specs: Vec<ColumnSpecification>,
+    unique_keys: Vec<Vec<usize>>,
 
     defaults: Vec<DataType>,
     dropped: Vec<usize>,
     unmodified: bool,
+
+    /// The sequence number assigned to the last tracked write this base node processed.
+    last_write: u64,
+
+    /// How many times the base table was resharded, which keeps the files of a base apart from
+    /// those of the base it took over from.
+    generation: usize,
 }
 
 impl Base {
@@ -33,17 +45,53 @@ impl Base {
         self
     }
 
+    /// Builder with known column types and constraints.
+    ///
+    /// Operations whose values cannot be coerced to the types of the columns they are destined for,
+    /// or that violate `NOT NULL` constraints, are then rejected.
+    pub fn with_column_specs(mut self, column_specs: Vec<ColumnSpecification>) -> Base {
+        self.column_specs = column_specs;
+        self
+    }
+
+    /// Builder with unique keys over several columns.
+    ///
+    /// Unique keys over a single column are given as `UNIQUE` constraints in the column specs.
+    pub fn with_unique_keys(mut self, unique_keys: Vec<Vec<usize>>) -> Base {
+        self.unique_keys = unique_keys;
+        self
+    }
+
+    /// Builder for a base that takes over from one of the same name that was split into shards
+    /// differently, `generation` times over.
+    pub fn with_generation(mut self, generation: usize) -> Base {
+        self.generation = generation;
+        self
+    }
+
+    /// How many times the base table was resharded.
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
     pub fn key(&self) -> Option<&[usize]> {
         self.primary_key.as_ref().map(|cols| &cols[..])
     }
 
     /// Add a new column to this base node.
-    pub fn add_column(&mut self, default: DataType) -> usize {
+    ///
+    /// Base nodes that know the types of their columns must also be given the type of the new
+    /// column in `spec`.
+    pub fn add_column(&mut self, default: DataType, spec: Option<ColumnSpecification>) -> usize {
         assert!(
             !self.defaults.is_empty(),
             "cannot add columns to base nodes without\
              setting default values for initial columns"
         );
+        if !self.column_specs.is_empty() {
+            self.column_specs
+                .push(spec.expect("cannot add untyped columns to base nodes with typed columns"));
+        }
         self.defaults.push(default);
         self.unmodified = false;
         self.defaults.len() - 1
@@ -65,6 +113,37 @@ impl Base {
         self.dropped.push(column);
     }
 
+    /// Undoes the most recent call to `add_column`.
+    pub fn remove_added_column(&mut self) {
+        self.defaults.pop();
+        self.column_specs.truncate(self.defaults.len());
+    }
+
+    /// Undoes the most recent call to `drop_column` for `column`.
+    pub fn undrop_column(&mut self, column: usize) {
+        if let Some(i) = self.dropped.iter().rposition(|&c| c == column) {
+            self.dropped.remove(i);
+        }
+    }
+
+    /// The types and constraints that the values written to each column of this base must
+    /// satisfy, or an empty list if they are not known.
+    ///
+    /// Dropped columns are always filled in with their default values, so their constraints no
+    /// longer apply.
+    pub fn column_specs(&self) -> Cow<'_, [ColumnSpecification]> {
+        if self.dropped.is_empty() {
+            return Cow::Borrowed(&self.column_specs);
+        }
+        let mut specs = self.column_specs.clone();
+        for &col in &self.dropped {
+            if let Some(spec) = specs.get_mut(col) {
+                spec.constraints.clear();
+            }
+        }
+        Cow::Owned(specs)
+    }
+
     pub fn get_dropped(&self) -> VecMap<DataType> {
         self.dropped
             .iter()
@@ -72,6 +151,38 @@ impl Base {
             .collect()
     }
 
+    /// Assign a sequence number to the next tracked write processed by this base node.
+    ///
+    /// Only writes whose effects readers keep track of are numbered: those that clients asked for
+    /// a token for, those of transactions, and the ones that tell new readers how far along the
+    /// base is.
+    pub(crate) fn next_write(&mut self) -> u64 {
+        self.last_write += 1;
+        self.last_write
+    }
+
+    /// The sequence number assigned to the last tracked write this base node processed.
+    pub(crate) fn last_write(&self) -> u64 {
+        self.last_write
+    }
+
+    /// Number the tracked writes of this base node on from `last_write` at least, so that the
+    /// writes of a base that takes over from another are numbered after those of the other.
+    pub(crate) fn advance_writes(&mut self, last_write: u64) {
+        self.last_write = cmp::max(self.last_write, last_write);
+    }
+
+    /// The outcome each of the given operations would have if they were applied to this base now,
+    /// without applying them.
+    pub(crate) fn check(
+        &mut self,
+        us: LocalNodeIndex,
+        ops: Vec<TableOperation>,
+        state: &StateMap,
+    ) -> Vec<OperationResult> {
+        self.process(us, ops, state).1
+    }
+
     pub(crate) fn fix(&self, row: &mut Vec<DataType>) {
         if self.unmodified {
             return;
@@ -91,10 +202,15 @@ impl Clone for Base {
     fn clone(&self) -> Base {
         Base {
             primary_key: self.primary_key.clone(),
+            column_specs: self.column_specs.clone(),
+            unique_keys: self.unique_keys.clone(),
 
             defaults: self.defaults.clone(),
             dropped: self.dropped.clone(),
             unmodified: self.unmodified,
+
+            last_write: self.last_write,
+            generation: self.generation,
         }
     }
 }
@@ -103,19 +219,127 @@ impl Default for Base {
     fn default() -> Self {
         Base {
             primary_key: None,
+            column_specs: Vec::new(),
+            unique_keys: Vec::new(),
 
             defaults: Vec::new(),
             dropped: Vec::new(),
             unmodified: true,
+
+            last_write: 0,
+            generation: 0,
+        }
+    }
+}
+
+/// The rows of a keyed base that a batch of operations touches, by primary key, as they were
+/// before the batch and as the batch has left them so far.
+///
+/// The rows as the batch has left them are also indexed by the values of each of the base's
+/// unique keys, so that checking an operation against them does not take a pass over the batch.
+struct Touched<'a> {
+    rows: BTreeMap<Vec<DataType>, (Option<Cow<'a, [DataType]>>, Option<Cow<'a, [DataType]>>)>,
+    unique_keys: Vec<Vec<usize>>,
+    by_unique_key: Vec<HashMap<Vec<DataType>, Vec<Vec<DataType>>>>,
+}
+
+impl<'a> Touched<'a> {
+    fn new(unique_keys: Vec<Vec<usize>>) -> Self {
+        Touched {
+            rows: BTreeMap::new(),
+            by_unique_key: vec![HashMap::new(); unique_keys.len()],
+            unique_keys,
         }
     }
+
+    /// Add the row with primary key `key` to, or remove it from, the unique key indices.
+    fn index(
+        unique_keys: &[Vec<usize>],
+        by_unique_key: &mut [HashMap<Vec<DataType>, Vec<Vec<DataType>>>],
+        key: &[DataType],
+        row: &[DataType],
+        add: bool,
+    ) {
+        for (ucols, index) in unique_keys.iter().zip(by_unique_key) {
+            if let Some(vals) = unique_values(ucols, row) {
+                if add {
+                    index.entry(vals).or_default().push(key.to_vec());
+                } else if let Some(keys) = index.get_mut(&vals) {
+                    keys.retain(|k| k[..] != *key);
+                }
+            }
+        }
+    }
+
+    /// The current state of the row with the given primary key, taking into account the
+    /// operations of the batch applied so far.
+    fn current(
+        &mut self,
+        db: &'a dyn State,
+        key_cols: &[usize],
+        key: Vec<DataType>,
+    ) -> &Option<Cow<'a, [DataType]>> {
+        if !self.rows.contains_key(&key) {
+            let current = lookup_key(db, key_cols, &key);
+            if let Some(ref row) = current {
+                Self::index(&self.unique_keys, &mut self.by_unique_key, &key, row, true);
+            }
+            self.rows.insert(key.clone(), (current.clone(), current));
+        }
+        &self.rows[&key].1
+    }
+
+    /// Make `row` the current state of the row with the given primary key.
+    fn set(
+        &mut self,
+        db: &'a dyn State,
+        key_cols: &[usize],
+        key: Vec<DataType>,
+        row: Option<Cow<'a, [DataType]>>,
+    ) {
+        self.current(db, key_cols, key.clone());
+        let current = &mut self.rows.get_mut(&key).unwrap().1;
+        if let Some(old) = mem::replace(current, row) {
+            Self::index(
+                &self.unique_keys,
+                &mut self.by_unique_key,
+                &key,
+                &old,
+                false,
+            );
+        }
+        if let Some(ref new) = *current {
+            Self::index(&self.unique_keys, &mut self.by_unique_key, &key, new, true);
+        }
+    }
+
+    /// Whether a row the batch has left, other than those with the primary keys in `replaced`,
+    /// holds `vals` in the `i`th unique key.
+    fn holds(&self, i: usize, vals: &[DataType], replaced: &[&[DataType]]) -> bool {
+        self.by_unique_key[i]
+            .get(vals)
+            .map(|keys| keys.iter().any(|k| !replaced.contains(&&k[..])))
+            .unwrap_or(false)
+    }
+}
+
+/// The values of `row` in the columns of a unique key, or `None` if any of them is `NULL`, in
+/// which case the row collides with no other.
+fn unique_values(ucols: &[usize], row: &[DataType]) -> Option<Vec<DataType>> {
+    let vals: Vec<_> = ucols.iter().map(|&c| row[c].clone()).collect();
+    if vals.iter().any(DataType::is_none) {
+        None
+    } else {
+        Some(vals)
+    }
 }
 
 fn key_val(i: usize, col: usize, r: &TableOperation) -> &DataType {
     match *r {
-        TableOperation::Insert(ref row) => &row[col],
+        TableOperation::Insert(ref row) | TableOperation::InsertIfAbsent(ref row) => &row[col],
         TableOperation::Delete { ref key } => &key[i],
         TableOperation::Update { ref key, .. } => &key[i],
+        TableOperation::UpdateIf { ref key, .. } => &key[i],
         TableOperation::InsertOrUpdate { ref row, .. } => &row[col],
     }
 }
@@ -127,147 +351,404 @@ fn key_of<'a>(key_cols: &'a [usize], r: &'a TableOperation) -> impl Iterator<Ite
         .map(move |(i, col)| key_val(i, *col, r))
 }
 
+/// Look up the row with the given primary key in the state of a base.
+fn lookup_key<'a>(
+    db: &'a dyn State,
+    key_cols: &[usize],
+    key: &[DataType],
+) -> Option<Cow<'a, [DataType]>> {
+    match db.lookup(key_cols, &KeyType::from(key)) {
+        LookupResult::Some(rows) => {
+            match rows.len() {
+                0 => None,
+                1 => rows.into_iter().next(),
+                n => {
+                    // primary key, so better be unique!
+                    assert_eq!(n, 1, "key {:?} not unique (n = {})!", key, n);
+                    unreachable!();
+                }
+            }
+        }
+        LookupResult::Missing => unreachable!(),
+    }
+}
+
+/// Combine the existing value `old` with `v` using `op`, or return `None` if the types of the
+/// values do not allow it or if the result overflows.
+fn combine(op: Operation, old: &DataType, v: DataType) -> Option<DataType> {
+    match op {
+        Operation::Add => old.checked_add(&v),
+        Operation::Sub => old.checked_sub(&v),
+        Operation::Multiply => old.checked_mul(&v),
+        Operation::Min | Operation::Max | Operation::Append if old.is_none() => Some(v),
+        Operation::Min => Some(cmp::min(old.clone(), v)),
+        Operation::Max => Some(cmp::max(old.clone(), v)),
+        Operation::Append => match (old, &v) {
+            (old, v) if old.is_string() && v.is_string() => {
+                let old: &str = old.into();
+                let v: &str = v.into();
+                Some(DataType::from(format!("{}{}", old, v)))
+            }
+            (&DataType::ByteArray(ref old), &DataType::ByteArray(ref v)) => {
+                let mut new = Vec::with_capacity(old.len() + v.len());
+                new.extend_from_slice(old);
+                new.extend_from_slice(v);
+                Some(DataType::ByteArray(Arc::new(new)))
+            }
+            _ => None,
+        },
+    }
+}
+
 impl Base {
     pub(in crate::node) fn take(&mut self) -> Self {
         Clone::clone(self)
     }
 
-    pub(in crate::node) fn process(
-        &mut self,
-        us: LocalNodeIndex,
-        mut ops: Vec<TableOperation>,
-        state: &StateMap,
-    ) -> Records {
-        if self.primary_key.is_none() || ops.is_empty() {
-            return ops
-                .into_iter()
-                .map(|r| {
-                    if let TableOperation::Insert(mut r) = r {
-                        self.fix(&mut r);
-                        Record::Positive(r)
-                    } else {
-                        unreachable!("unkeyed base got non-insert operation {:?}", r);
+    /// The sets of columns, other than the primary key, in which no two rows of this base may
+    /// hold the same values.
+    ///
+    /// Like in SQL, rows with a `NULL` in any of the columns of a unique key never collide. Unique
+    /// keys that include a dropped column are no longer enforced.
+    pub fn unique_keys(&self) -> Vec<Vec<usize>> {
+        self.column_specs
+            .iter()
+            .enumerate()
+            .filter(|&(_, cs)| cs.constraints.contains(&ColumnConstraint::Unique))
+            .map(|(col, _)| vec![col])
+            .chain(self.unique_keys.iter().cloned())
+            .filter(|key| !key.iter().any(|col| self.dropped.contains(col)))
+            .filter(|key| self.primary_key.as_ref() != Some(key))
+            .collect()
+    }
+
+    /// Whether every unique key of this base includes `col`, so that the base can be sharded by
+    /// it and still enforce its unique keys in each shard on its own.
+    pub fn can_shard_by(&self, col: usize) -> bool {
+        self.unique_keys().iter().all(|key| key.contains(&col))
+    }
+
+    /// Find a unique key of this base that `row` would share with a row other than those with the
+    /// primary keys in `replaced`.
+    fn unique_conflict(
+        &self,
+        touched: &Touched<'_>,
+        db: &dyn State,
+        replaced: &[&[DataType]],
+        row: &[DataType],
+    ) -> Option<Vec<usize>> {
+        let key_cols = &self.primary_key.as_ref().unwrap()[..];
+        let other = |r: &[DataType]| {
+            let key: Vec<_> = key_cols.iter().map(|&c| r[c].clone()).collect();
+            !replaced.contains(&&key[..]) && !touched.rows.contains_key(&key)
+        };
+
+        touched
+            .unique_keys
+            .iter()
+            .enumerate()
+            .find(|&(i, ucols)| {
+                let vals = match unique_values(ucols, row) {
+                    Some(vals) => vals,
+                    None => return false,
+                };
+
+                // rows touched by this batch are only where the batch has left them
+                touched.holds(i, &vals, replaced)
+                    || match db.lookup(ucols, &KeyType::from(&vals[..])) {
+                        LookupResult::Some(rows) => rows.into_iter().any(|r| other(&r[..])),
+                        LookupResult::Missing => unreachable!(),
                     }
-                })
-                .collect();
+            })
+            .map(|(_, ucols)| ucols.clone())
+    }
+
+    /// Apply the modifications in `set` to `row`.
+    ///
+    /// If a modification cannot be applied to the existing value, or its outcome does not fit the
+    /// column, the operation is invalid, and `row` should be discarded.
+    fn modify(&self, row: &mut [DataType], set: Vec<Modification>) -> OperationResult {
+        for (col, m) in set.into_iter().enumerate() {
+            let new = match m {
+                Modification::Set(v) => v,
+                Modification::Apply(op, v) => match combine(op, &row[col], v) {
+                    Some(new) => new,
+                    None => return OperationResult::Invalid,
+                },
+                Modification::SetNull => DataType::None,
+                Modification::SetIfNull(v) if row[col].is_none() => v,
+                Modification::SetIfNull(_) | Modification::None => continue,
+            };
+            row[col] = match self.column_specs.get(col) {
+                Some(spec) => match new.coerce_to(&spec.sql_type) {
+                    Some(new) => new,
+                    None => return OperationResult::Invalid,
+                },
+                None => new,
+            };
         }
+        OperationResult::Applied
+    }
 
+    /// Replace the row with primary key `key` by `row`, unless doing so would violate the
+    /// primary key or a unique key of this base.
+    fn replace<'a>(
+        &self,
+        touched: &mut Touched<'a>,
+        db: &'a dyn State,
+        key: Vec<DataType>,
+        row: Vec<DataType>,
+    ) -> OperationResult {
         let key_cols = &self.primary_key.as_ref().unwrap()[..];
-        ops.sort_by(|a, b| key_of(key_cols, a).cmp(key_of(key_cols, b)));
+        let new_key: Vec<_> = key_cols.iter().map(|&c| row[c].clone()).collect();
+        if new_key != key && touched.current(db, key_cols, new_key.clone()).is_some() {
+            return OperationResult::UniqueViolation(key_cols.to_vec());
+        }
+        if let Some(ucols) = self.unique_conflict(touched, db, &[&key, &new_key], &row) {
+            return OperationResult::UniqueViolation(ucols);
+        }
 
-        // starting key
-        let mut this_key: Vec<_> = key_of(key_cols, &ops[0]).cloned().collect();
+        touched.set(db, key_cols, key, None);
+        touched.set(db, key_cols, new_key, Some(Cow::Owned(row)));
+        OperationResult::Applied
+    }
 
-        // starting record state
-        let db = state
-            .get(us)
-            .expect("base with primary key must be materialized");
+    /// Apply the given operations to this base, and produce the resulting changes to its rows,
+    /// along with the outcome of each operation.
+    pub(in crate::node) fn process(
+        &mut self,
+        us: LocalNodeIndex,
+        ops: Vec<TableOperation>,
+        state: &StateMap,
+    ) -> (Records, Vec<OperationResult>) {
+        let mut results = vec![OperationResult::Applied; ops.len()];
+        let mut ops: Vec<_> = ops.into_iter().map(Some).collect();
+        if !self.column_specs.is_empty() {
+            // clients check their writes too, but not all clients can be trusted to do so. the
+            // writes we reject are reported back to the client as invalid.
+            let specs = self.column_specs();
+            let key_cols = self.primary_key.as_ref().map(|k| &k[..]).unwrap_or(&[]);
+            for (op, result) in ops.iter_mut().zip(&mut results) {
+                let mut o = op.take().unwrap();
+                match noria::coerce_operation(&specs, key_cols, &mut o) {
+                    Ok(()) => *op = Some(o),
+                    Err(_) => *result = OperationResult::Invalid,
+                }
+            }
+        }
 
-        let get_current = |current_key: &'_ _| {
-            match db.lookup(key_cols, &KeyType::from(current_key)) {
-                LookupResult::Some(rows) => {
-                    match rows.len() {
-                        0 => None,
-                        1 => rows.into_iter().next(),
-                        n => {
-                            // primary key, so better be unique!
-                            assert_eq!(n, 1, "key {:?} not unique (n = {})!", current_key, n);
-                            unreachable!();
+        if self.primary_key.is_none() {
+            // without a primary key, rows cannot be told apart, so any existing row with the same
+            // values in a unique key is a collision.
+            let unique_keys = self.unique_keys();
+            let db = if unique_keys.is_empty() {
+                None
+            } else {
+                state.get(us)
+            };
+            // the values of each unique key held by the rows this batch has inserted so far
+            let mut inserted = vec![HashSet::new(); unique_keys.len()];
+            let mut records: Vec<Record> = Vec::with_capacity(ops.len());
+            for (op, result) in ops.into_iter().zip(&mut results) {
+                match op {
+                    Some(TableOperation::Insert(mut r)) => {
+                        self.fix(&mut r);
+                        let vals: Vec<_> = unique_keys
+                            .iter()
+                            .map(|ucols| unique_values(ucols, &r))
+                            .collect();
+                        let collision = unique_keys.iter().enumerate().find(|&(i, ucols)| {
+                            let vals = match vals[i] {
+                                Some(ref vals) => vals,
+                                None => return false,
+                            };
+                            inserted[i].contains(vals)
+                                || db
+                                    .map(|db| match db.lookup(ucols, &KeyType::from(&vals[..])) {
+                                        LookupResult::Some(rows) => !rows.is_empty(),
+                                        LookupResult::Missing => unreachable!(),
+                                    })
+                                    .unwrap_or(false)
+                        });
+                        match collision {
+                            Some((_, ucols)) => {
+                                *result = OperationResult::UniqueViolation(ucols.clone())
+                            }
+                            None => {
+                                for (inserted, vals) in inserted.iter_mut().zip(vals) {
+                                    inserted.extend(vals);
+                                }
+                                records.push(Record::Positive(r));
+                            }
                         }
                     }
+                    Some(_) => *result = OperationResult::Invalid,
+                    None => {}
                 }
-                LookupResult::Missing => unreachable!(),
             }
-        };
-        let mut current = get_current(&this_key);
-        let mut was = current.clone();
+            return (records.into(), results);
+        }
 
-        let mut results = Vec::with_capacity(ops.len());
-        for op in ops {
-            if this_key.iter().cmp(key_of(key_cols, &op)) != Ordering::Equal {
-                if current != was {
-                    if let Some(was) = was {
-                        results.push(Record::Negative(was.into_owned()));
-                    }
-                    if let Some(current) = current {
-                        results.push(Record::Positive(current.into_owned()));
-                    }
-                }
+        if ops.iter().all(Option::is_none) {
+            return (Records::default(), results);
+        }
 
-                this_key = key_of(key_cols, &op).cloned().collect();
-                current = get_current(&this_key);
-                was = current.clone();
-            }
+        let db = &**state
+            .get(us)
+            .expect("base with primary key must be materialized");
+        let key_cols = &self.primary_key.as_ref().unwrap()[..];
 
-            let update = match op {
-                TableOperation::Insert(row) => {
-                    if let Some(ref was) = was {
-                        eprintln!("base ignoring {:?} since it already has {:?}", row, was);
-                    } else {
-                        //assert!(was.is_none());
-                        current = Some(Cow::Owned(row));
-                    }
-                    continue;
+        // the operations are applied in order, and we remember the state every row they touch was
+        // in before and after, so that we only emit a single change per row.
+        let mut touched = Touched::new(self.unique_keys());
+        for (op, result) in ops.into_iter().zip(&mut results) {
+            let op = match op {
+                Some(op) => op,
+                None => continue,
+            };
+            let key: Vec<_> = key_of(key_cols, &op).cloned().collect();
+
+            let existing = touched.current(db, key_cols, key.clone()).clone();
+
+            *result = match op {
+                TableOperation::Insert(..) if existing.is_some() => {
+                    OperationResult::UniqueViolation(key_cols.to_vec())
+                }
+                TableOperation::InsertIfAbsent(..) if existing.is_some() => {
+                    OperationResult::ConditionFailed
                 }
+                TableOperation::Insert(mut row)
+                | TableOperation::InsertIfAbsent(mut row)
+                | TableOperation::InsertOrUpdate { mut row, .. }
+                    if existing.is_none() =>
+                {
+                    self.fix(&mut row);
+                    self.replace(&mut touched, db, key, row)
+                }
+                _ if existing.is_none() => OperationResult::NotFound,
                 TableOperation::Delete { .. } => {
-                    if current.is_some() {
-                        current = None;
-                    } else {
-                        // supposed to delete a non-existing row?
-                        // TODO: warn?
-                    }
-                    continue;
+                    touched.set(db, key_cols, key, None);
+                    OperationResult::Applied
                 }
-                TableOperation::Update { set, .. } => set,
-                TableOperation::InsertOrUpdate { row, update } => {
-                    if current.is_none() {
-                        current = Some(Cow::Owned(row));
-                        continue;
+                TableOperation::UpdateIf { ref expected, .. }
+                    if expected
+                        .iter()
+                        .any(|&(col, ref v)| existing.as_ref().unwrap().get(col) != Some(v)) =>
+                {
+                    OperationResult::ConditionFailed
+                }
+                TableOperation::Update { set, .. }
+                | TableOperation::InsertOrUpdate { update: set, .. }
+                | TableOperation::UpdateIf { set, .. } => {
+                    let mut row = existing.unwrap().into_owned();
+                    self.fix(&mut row);
+                    match self.modify(&mut row, set) {
+                        OperationResult::Applied => self.replace(&mut touched, db, key, row),
+                        failed => failed,
                     }
-                    update
                 }
+                TableOperation::Insert(..) | TableOperation::InsertIfAbsent(..) => unreachable!(),
             };
+        }
 
-            if current.is_none() {
-                // supposed to update a non-existing row?
-                // TODO: also warn here?
-                continue;
-            }
-
-            let mut future = current.unwrap().into_owned();
-            for (col, op) in update.into_iter().enumerate() {
-                // XXX: make sure user doesn't update primary key?
-                match op {
-                    Modification::Set(v) => future[col] = v,
-                    Modification::Apply(op, v) => {
-                        let old: i128 = future[col].clone().into();
-                        let delta: i128 = v.into();
-                        future[col] = match op {
-                            Operation::Add => (old + delta).into(),
-                            Operation::Sub => (old - delta).into(),
-                        };
-                    }
-                    Modification::None => {}
+        let mut records = Vec::new();
+        for (was, current) in touched.rows.into_iter().map(|(_, rows)| rows) {
+            if current != was {
+                if let Some(was) = was {
+                    records.push(Record::Negative(was.into_owned()));
+                }
+                if let Some(current) = current {
+                    records.push(Record::Positive(current.into_owned()));
                 }
             }
-            current = Some(Cow::Owned(future));
         }
 
-        // we may have changed things in the last iteration of the loop above
-        if current != was {
-            if let Some(was) = was {
-                results.push(Record::Negative(was.into_owned()));
-            }
-            if let Some(current) = current {
-                results.push(Record::Positive(current.into_owned()));
-            }
+        for r in &mut records {
+            self.fix(r);
         }
 
-        for r in &mut results {
-            self.fix(r);
+        (records.into(), results)
+    }
+
+    /// Insert the rows of a bulk load into this base, and produce the resulting changes to its
+    /// rows, along with the outcome of each insert.
+    ///
+    /// Unlike `process`, which applies the operations of a write one after the other so that each
+    /// sees what the ones before it did to existing rows, a bulk load only ever adds rows. Each row
+    /// is therefore checked with a single lookup per key in the base's state and in the keys of
+    /// the rows loaded before it, and nothing else about the batch is kept track of. Operations
+    /// other than inserts are invalid.
+    pub(in crate::node) fn load(
+        &mut self,
+        us: LocalNodeIndex,
+        ops: Vec<TableOperation>,
+        state: &StateMap,
+    ) -> (Records, Vec<OperationResult>) {
+        if self.primary_key.is_none() {
+            // without a primary key, a write is applied as a bulk load anyway
+            return self.process(us, ops, state);
         }
 
-        results.into()
+        let db = &**state
+            .get(us)
+            .expect("base with primary key must be materialized");
+        let specs = self.column_specs();
+        let key_cols = &self.primary_key.as_ref().unwrap()[..];
+        let unique_keys = self.unique_keys();
+
+        // the primary and unique keys of the rows loaded so far
+        let mut loaded = HashSet::with_capacity(ops.len());
+        let mut loaded_unique = vec![HashSet::new(); unique_keys.len()];
+        let mut records = Vec::with_capacity(ops.len());
+        let mut results = Vec::with_capacity(ops.len());
+        for mut op in ops {
+            if !specs.is_empty() && noria::coerce_operation(&specs, key_cols, &mut op).is_err() {
+                results.push(OperationResult::Invalid);
+                continue;
+            }
+            let mut row = match op {
+                TableOperation::Insert(row) => row,
+                _ => {
+                    results.push(OperationResult::Invalid);
+                    continue;
+                }
+            };
+            self.fix(&mut row);
+
+            let key: Vec<_> = key_cols.iter().map(|&c| row[c].clone()).collect();
+            if loaded.contains(&key) || lookup_key(db, key_cols, &key).is_some() {
+                results.push(OperationResult::UniqueViolation(key_cols.to_vec()));
+                continue;
+            }
+            // an existing row has a different primary key, so it collides on any unique key
+            let vals: Vec<_> = unique_keys
+                .iter()
+                .map(|ucols| unique_values(ucols, &row))
+                .collect();
+            let collision = unique_keys.iter().enumerate().find(|&(i, ucols)| {
+                let vals = match vals[i] {
+                    Some(ref vals) => vals,
+                    None => return false,
+                };
+                loaded_unique[i].contains(vals)
+                    || match db.lookup(ucols, &KeyType::from(&vals[..])) {
+                        LookupResult::Some(rows) => !rows.is_empty(),
+                        LookupResult::Missing => unreachable!(),
+                    }
+            });
+            if let Some((_, ucols)) = collision {
+                results.push(OperationResult::UniqueViolation(ucols.clone()));
+                continue;
+            }
+
+            for (loaded, vals) in loaded_unique.iter_mut().zip(vals) {
+                loaded.extend(vals);
+            }
+            loaded.insert(key);
+            records.push(Record::Positive(row));
+            results.push(OperationResult::Applied);
+        }
+        (records.into(), results)
     }
 
     pub(in crate::node) fn suggest_indexes(&self, n: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
@@ -307,7 +788,117 @@ mod tests {
         assert_eq!(b.unmodified, true);
     }
 
-    fn test_lots_of_changes_in_same_batch(mut state: Box<dyn State>) {
+    #[test]
+    fn it_coerces_and_rejects_by_column_type() {
+        use nom_sql::{ColumnConstraint, SqlType};
+
+        let mut b = Base::new(vec![]).with_column_specs(vec![
+            ColumnSpecification::with_constraints(
+                "id".into(),
+                SqlType::Int(32),
+                vec![ColumnConstraint::NotNull],
+            ),
+            ColumnSpecification::new("name".into(), SqlType::Varchar(3)),
+        ]);
+        let local = unsafe { LocalNodeIndex::make(0 as u32) };
+
+        let (rs, results) = b.process(
+            local,
+            vec![
+                TableOperation::Insert(vec!["1".into(), "a".into()]),
+                TableOperation::Insert(vec!["x".into(), "b".into()]),
+                TableOperation::Insert(vec![DataType::None, "c".into()]),
+                TableOperation::Insert(vec![4.into(), "toolong".into()]),
+                TableOperation::Insert(vec![5.into(), 42.into()]),
+            ],
+            &StateMap::new(),
+        );
+        assert_eq!(
+            rs,
+            vec![
+                Record::Positive(vec![1.into(), "a".into()]),
+                Record::Positive(vec![5.into(), "42".into()]),
+            ]
+            .into()
+        );
+        assert_eq!(
+            results,
+            vec![
+                OperationResult::Applied,
+                OperationResult::Invalid,
+                OperationResult::Invalid,
+                OperationResult::Invalid,
+                OperationResult::Applied,
+            ]
+        );
+    }
+
+    #[test]
+    fn it_checks_added_and_dropped_columns() {
+        use nom_sql::{ColumnConstraint, SqlType};
+
+        let mut b = Base::new(vec![DataType::None, DataType::None]).with_column_specs(vec![
+            ColumnSpecification::with_constraints(
+                "id".into(),
+                SqlType::Int(32),
+                vec![ColumnConstraint::NotNull],
+            ),
+            ColumnSpecification::new("name".into(), SqlType::Varchar(3)),
+        ]);
+        let local = unsafe { LocalNodeIndex::make(0 as u32) };
+        let mut insert = |b: &mut Base, row: Vec<DataType>| {
+            b.process(local, vec![TableOperation::Insert(row)], &StateMap::new())
+                .1
+                .remove(0)
+        };
+
+        b.add_column(
+            0.into(),
+            Some(ColumnSpecification::new("n".into(), SqlType::Int(32))),
+        );
+        assert_eq!(
+            insert(&mut b, vec![1.into(), "a".into(), "x".into()]),
+            OperationResult::Invalid
+        );
+        assert_eq!(
+            insert(&mut b, vec![1.into(), "a".into(), 2.into()]),
+            OperationResult::Applied
+        );
+
+        // a dropped column is filled in with its default, even if that violates its constraints
+        b.drop_column(0);
+        assert_eq!(
+            insert(&mut b, vec![DataType::None, "a".into(), 2.into()]),
+            OperationResult::Applied
+        );
+
+        // and undoing the changes restores the old checks
+        b.undrop_column(0);
+        b.remove_added_column();
+        assert_eq!(
+            insert(&mut b, vec![DataType::None, "a".into()]),
+            OperationResult::Invalid
+        );
+        assert_eq!(b.column_specs().len(), 2);
+    }
+
+    /// Set up `b` as a materialized base node with the given columns, and return a function that
+    /// has it process a batch of operations.
+    fn setup(
+        b: Base,
+        fields: &[&str],
+        state: Box<dyn State>,
+    ) -> impl FnMut(Vec<TableOperation>) -> (Records, Vec<OperationResult>) {
+        let mut write = setup_writes(b, fields, state);
+        move |u| write(u, false)
+    }
+
+    /// Like `setup`, but the returned function also takes whether the batch is a bulk load.
+    fn setup_writes(
+        b: Base,
+        fields: &[&str],
+        mut state: Box<dyn State>,
+    ) -> impl FnMut(Vec<TableOperation>, bool) -> (Records, Vec<OperationResult>) {
         use crate::node;
         use crate::prelude::*;
 
@@ -319,8 +910,8 @@ mod tests {
             node::NodeType::Source,
         ));
 
-        let b = Base::new(vec![]).with_key(vec![0, 2]);
-        let global = graph.add_node(Node::new("b", &["x", "y", "z"], b));
+        let unique_keys = b.unique_keys();
+        let global = graph.add_node(Node::new("b", fields, b));
         graph.add_edge(source, global, ());
         let local = unsafe { LocalNodeIndex::make(0 as u32) };
         let mut ip: IndexPair = global.into();
@@ -338,17 +929,31 @@ mod tests {
         for (_, col) in graph[global].suggest_indexes(global) {
             state.add_key(&col[..], None);
         }
+        for col in unique_keys {
+            state.add_key(&col[..], None);
+        }
 
         let mut states = StateMap::new();
         states.insert(local, state);
         let n = graph[global].take();
         let mut n = n.finalize(&graph);
 
-        let mut one = move |u: Vec<TableOperation>| {
-            let mut m = n.get_base_mut().unwrap().process(local, u, &states);
+        move |u: Vec<TableOperation>, bulk: bool| {
+            let b = n.get_base_mut().unwrap();
+            let (mut m, results) = if bulk {
+                b.load(local, u, &states)
+            } else {
+                b.process(local, u, &states)
+            };
             node::materialize(&mut m, None, states.get_mut(local));
-            m
-        };
+            (m, results)
+        }
+    }
+
+    fn test_lots_of_changes_in_same_batch(state: Box<dyn State>) {
+        let b = Base::new(vec![]).with_key(vec![0, 2]);
+        let mut process = setup(b, &["x", "y", "z"], state);
+        let mut one = move |u| process(u).0;
 
         assert_eq!(
             one(vec![
@@ -417,4 +1022,450 @@ mod tests {
 
         test_lots_of_changes_in_same_batch(Box::new(state));
     }
+
+    #[test]
+    fn it_enforces_primary_key() {
+        let b = Base::new(vec![]).with_key(vec![0]);
+        let mut one = setup(b, &["id", "name"], Box::new(MemoryState::default()));
+
+        assert_eq!(
+            one(vec![
+                TableOperation::Insert(vec![1.into(), "a".into()]),
+                TableOperation::Insert(vec![1.into(), "b".into()]),
+                TableOperation::InsertIfAbsent(vec![1.into(), "c".into()]),
+                TableOperation::InsertIfAbsent(vec![2.into(), "d".into()]),
+                TableOperation::Delete {
+                    key: vec![3.into()]
+                },
+                TableOperation::Update {
+                    key: vec![3.into()],
+                    set: vec![Modification::None, Modification::Set("e".into())],
+                },
+            ]),
+            (
+                vec![
+                    Record::Positive(vec![1.into(), "a".into()]),
+                    Record::Positive(vec![2.into(), "d".into()]),
+                ]
+                .into(),
+                vec![
+                    OperationResult::Applied,
+                    OperationResult::UniqueViolation(vec![0]),
+                    OperationResult::ConditionFailed,
+                    OperationResult::Applied,
+                    OperationResult::NotFound,
+                    OperationResult::NotFound,
+                ]
+            )
+        );
+
+        // changing the key of a row must not make it collide with another
+        assert_eq!(
+            one(vec![TableOperation::Update {
+                key: vec![2.into()],
+                set: vec![Modification::Set(1.into()), Modification::None],
+            }]),
+            (
+                Records::default(),
+                vec![OperationResult::UniqueViolation(vec![0])]
+            )
+        );
+        assert_eq!(
+            one(vec![TableOperation::Update {
+                key: vec![2.into()],
+                set: vec![Modification::Set(3.into()), Modification::None],
+            }]),
+            (
+                vec![
+                    Record::Negative(vec![2.into(), "d".into()]),
+                    Record::Positive(vec![3.into(), "d".into()]),
+                ]
+                .into(),
+                vec![OperationResult::Applied]
+            )
+        );
+    }
+
+    #[test]
+    fn it_updates_conditionally() {
+        let b = Base::new(vec![]).with_key(vec![0]);
+        let mut one = setup(b, &["id", "state"], Box::new(MemoryState::default()));
+        one(vec![TableOperation::Insert(vec![
+            1.into(),
+            "queued".into(),
+        ])]);
+
+        let claim = || TableOperation::UpdateIf {
+            key: vec![1.into()],
+            expected: vec![(1, "queued".into())],
+            set: vec![Modification::None, Modification::Set("running".into())],
+        };
+
+        // only the first of two competing claims succeeds
+        assert_eq!(
+            one(vec![claim(), claim()]),
+            (
+                vec![
+                    Record::Negative(vec![1.into(), "queued".into()]),
+                    Record::Positive(vec![1.into(), "running".into()]),
+                ]
+                .into(),
+                vec![OperationResult::Applied, OperationResult::ConditionFailed]
+            )
+        );
+        assert_eq!(
+            one(vec![claim()]),
+            (Records::default(), vec![OperationResult::ConditionFailed])
+        );
+        assert_eq!(
+            one(vec![TableOperation::UpdateIf {
+                key: vec![2.into()],
+                expected: vec![],
+                set: vec![],
+            }]),
+            (Records::default(), vec![OperationResult::NotFound])
+        );
+    }
+
+    #[test]
+    fn it_enforces_unique_keys() {
+        use nom_sql::SqlType;
+
+        let b = Base::new(vec![]).with_key(vec![0]).with_column_specs(vec![
+            ColumnSpecification::new("id".into(), SqlType::Int(32)),
+            ColumnSpecification::with_constraints(
+                "username".into(),
+                SqlType::Text,
+                vec![ColumnConstraint::Unique],
+            ),
+        ]);
+        assert_eq!(b.unique_keys(), vec![vec![1]]);
+        let mut one = setup(b, &["id", "username"], Box::new(MemoryState::default()));
+
+        assert_eq!(
+            one(vec![
+                TableOperation::Insert(vec![1.into(), "alice".into()]),
+                TableOperation::Insert(vec![2.into(), "alice".into()]),
+                TableOperation::Insert(vec![3.into(), DataType::None]),
+                TableOperation::Insert(vec![4.into(), DataType::None]),
+            ]),
+            (
+                vec![
+                    Record::Positive(vec![1.into(), "alice".into()]),
+                    Record::Positive(vec![3.into(), DataType::None]),
+                    Record::Positive(vec![4.into(), DataType::None]),
+                ]
+                .into(),
+                vec![
+                    OperationResult::Applied,
+                    OperationResult::UniqueViolation(vec![1]),
+                    OperationResult::Applied,
+                    OperationResult::Applied,
+                ]
+            )
+        );
+
+        // a row that is already materialized collides too, unless the batch moves it out of the way
+        assert_eq!(
+            one(vec![
+                TableOperation::Insert(vec![5.into(), "alice".into()]),
+                TableOperation::Update {
+                    key: vec![3.into()],
+                    set: vec![Modification::None, Modification::Set("alice".into())],
+                },
+                TableOperation::Update {
+                    key: vec![1.into()],
+                    set: vec![Modification::None, Modification::Set("bob".into())],
+                },
+                TableOperation::Insert(vec![6.into(), "alice".into()]),
+            ]),
+            (
+                vec![
+                    Record::Negative(vec![1.into(), "alice".into()]),
+                    Record::Positive(vec![1.into(), "bob".into()]),
+                    Record::Positive(vec![6.into(), "alice".into()]),
+                ]
+                .into(),
+                vec![
+                    OperationResult::UniqueViolation(vec![1]),
+                    OperationResult::UniqueViolation(vec![1]),
+                    OperationResult::Applied,
+                    OperationResult::Applied,
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn it_bulk_loads_rows() {
+        use nom_sql::SqlType;
+
+        let b = Base::new(vec![]).with_key(vec![0]).with_column_specs(vec![
+            ColumnSpecification::new("id".into(), SqlType::Int(32)),
+            ColumnSpecification::with_constraints(
+                "username".into(),
+                SqlType::Text,
+                vec![ColumnConstraint::Unique],
+            ),
+        ]);
+        let mut write = setup_writes(b, &["id", "username"], Box::new(MemoryState::default()));
+        write(
+            vec![TableOperation::Insert(vec![1.into(), "alice".into()])],
+            false,
+        );
+
+        // rows collide with those already in the base and with those loaded before them
+        assert_eq!(
+            write(
+                vec![
+                    TableOperation::Insert(vec![2.into(), "bob".into()]),
+                    TableOperation::Insert(vec![1.into(), "carol".into()]),
+                    TableOperation::Insert(vec![3.into(), "alice".into()]),
+                    TableOperation::Insert(vec![4.into(), "bob".into()]),
+                    TableOperation::Insert(vec![2.into(), "dave".into()]),
+                    TableOperation::Delete {
+                        key: vec![1.into()]
+                    },
+                    TableOperation::Insert(vec![5.into(), DataType::None]),
+                ],
+                true
+            ),
+            (
+                vec![
+                    Record::Positive(vec![2.into(), "bob".into()]),
+                    Record::Positive(vec![5.into(), DataType::None]),
+                ]
+                .into(),
+                vec![
+                    OperationResult::Applied,
+                    OperationResult::UniqueViolation(vec![0]),
+                    OperationResult::UniqueViolation(vec![1]),
+                    OperationResult::UniqueViolation(vec![1]),
+                    OperationResult::UniqueViolation(vec![0]),
+                    OperationResult::Invalid,
+                    OperationResult::Applied,
+                ]
+            )
+        );
+
+        // and the loaded rows are in the base's state
+        assert_eq!(
+            write(
+                vec![TableOperation::Insert(vec![6.into(), "bob".into()])],
+                true
+            )
+            .1,
+            vec![OperationResult::UniqueViolation(vec![1])]
+        );
+    }
+
+    #[test]
+    fn it_enforces_compound_unique_keys() {
+        use nom_sql::SqlType;
+
+        let b = Base::new(vec![])
+            .with_key(vec![0])
+            .with_column_specs(vec![
+                ColumnSpecification::new("id".into(), SqlType::Int(32)),
+                ColumnSpecification::new("org".into(), SqlType::Text),
+                ColumnSpecification::new("name".into(), SqlType::Text),
+            ])
+            .with_unique_keys(vec![vec![1, 2]]);
+        assert_eq!(b.unique_keys(), vec![vec![1, 2]]);
+        assert!(b.can_shard_by(1));
+        assert!(!b.can_shard_by(0));
+        let mut one = setup(b, &["id", "org", "name"], Box::new(MemoryState::default()));
+
+        assert_eq!(
+            one(vec![
+                TableOperation::Insert(vec![1.into(), "a".into(), "x".into()]),
+                TableOperation::Insert(vec![2.into(), "a".into(), "y".into()]),
+                TableOperation::Insert(vec![3.into(), "b".into(), "x".into()]),
+                TableOperation::Insert(vec![4.into(), "a".into(), "x".into()]),
+            ]),
+            (
+                vec![
+                    Record::Positive(vec![1.into(), "a".into(), "x".into()]),
+                    Record::Positive(vec![2.into(), "a".into(), "y".into()]),
+                    Record::Positive(vec![3.into(), "b".into(), "x".into()]),
+                ]
+                .into(),
+                vec![
+                    OperationResult::Applied,
+                    OperationResult::Applied,
+                    OperationResult::Applied,
+                    OperationResult::UniqueViolation(vec![1, 2]),
+                ]
+            )
+        );
+
+        // the values are free again once the row holding them is gone
+        assert_eq!(
+            one(vec![
+                TableOperation::Delete {
+                    key: vec![1.into()]
+                },
+                TableOperation::Insert(vec![5.into(), "a".into(), "x".into()]),
+                TableOperation::Insert(vec![6.into(), "a".into(), "x".into()]),
+            ]),
+            (
+                vec![
+                    Record::Negative(vec![1.into(), "a".into(), "x".into()]),
+                    Record::Positive(vec![5.into(), "a".into(), "x".into()]),
+                ]
+                .into(),
+                vec![
+                    OperationResult::Applied,
+                    OperationResult::Applied,
+                    OperationResult::UniqueViolation(vec![1, 2]),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn it_enforces_unique_keys_without_primary_key() {
+        use nom_sql::SqlType;
+
+        let b = Base::new(vec![]).with_column_specs(vec![
+            ColumnSpecification::with_constraints(
+                "username".into(),
+                SqlType::Text,
+                vec![ColumnConstraint::Unique],
+            ),
+            ColumnSpecification::new("name".into(), SqlType::Text),
+        ]);
+        let mut one = setup(b, &["username", "name"], Box::new(MemoryState::default()));
+
+        one(vec![TableOperation::Insert(vec![
+            "alice".into(),
+            "A".into(),
+        ])]);
+        assert_eq!(
+            one(vec![
+                TableOperation::Insert(vec!["alice".into(), "B".into()]),
+                TableOperation::Insert(vec!["bob".into(), "C".into()]),
+                TableOperation::Insert(vec!["bob".into(), "D".into()]),
+            ]),
+            (
+                vec![Record::Positive(vec!["bob".into(), "C".into()])].into(),
+                vec![
+                    OperationResult::UniqueViolation(vec![0]),
+                    OperationResult::Applied,
+                    OperationResult::UniqueViolation(vec![0]),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn it_applies_modifications_atomically() {
+        use nom_sql::SqlType;
+
+        let b = Base::new(vec![]).with_key(vec![0]).with_column_specs(vec![
+            ColumnSpecification::new("id".into(), SqlType::Int(32)),
+            ColumnSpecification::new("hits".into(), SqlType::Int(32)),
+            ColumnSpecification::new("max_seen".into(), SqlType::Bigint(64)),
+            ColumnSpecification::new("log".into(), SqlType::Text),
+            ColumnSpecification::new("first_seen".into(), SqlType::Bigint(64)),
+        ]);
+        let mut one = setup(
+            b,
+            &["id", "hits", "max_seen", "log", "first_seen"],
+            Box::new(MemoryState::default()),
+        );
+        one(vec![TableOperation::Insert(vec![
+            1.into(),
+            1.into(),
+            DataType::None,
+            "a".into(),
+            DataType::None,
+        ])]);
+
+        let seen = |at: i64, first_seen| TableOperation::Update {
+            key: vec![1.into()],
+            set: vec![
+                Modification::None,
+                Modification::Apply(Operation::Multiply, 3.into()),
+                Modification::Apply(Operation::Max, at.into()),
+                Modification::Apply(Operation::Append, "b".into()),
+                first_seen,
+            ],
+        };
+        assert_eq!(
+            one(vec![
+                seen(5, Modification::SetIfNull(5.into())),
+                seen(3, Modification::SetIfNull(3.into())),
+                TableOperation::Update {
+                    key: vec![1.into()],
+                    set: vec![
+                        Modification::None,
+                        Modification::Apply(Operation::Min, 4.into()),
+                    ],
+                },
+            ]),
+            (
+                vec![
+                    Record::Negative(vec![
+                        1.into(),
+                        1.into(),
+                        DataType::None,
+                        "a".into(),
+                        DataType::None,
+                    ]),
+                    Record::Positive(vec![1.into(), 4.into(), 5.into(), "abb".into(), 5.into(),]),
+                ]
+                .into(),
+                vec![OperationResult::Applied; 3]
+            )
+        );
+
+        // results that do not fit the column are rejected, as are operations on the wrong types
+        assert_eq!(
+            one(vec![
+                TableOperation::Update {
+                    key: vec![1.into()],
+                    set: vec![
+                        Modification::None,
+                        Modification::Apply(Operation::Multiply, std::i32::MAX.into()),
+                    ],
+                },
+                TableOperation::Update {
+                    key: vec![1.into()],
+                    set: vec![
+                        Modification::None,
+                        Modification::None,
+                        Modification::None,
+                        Modification::Apply(Operation::Add, "c".into()),
+                    ],
+                },
+                TableOperation::Update {
+                    key: vec![1.into()],
+                    set: vec![
+                        Modification::None,
+                        Modification::None,
+                        Modification::SetNull,
+                    ],
+                },
+            ]),
+            (
+                vec![
+                    Record::Negative(vec![1.into(), 4.into(), 5.into(), "abb".into(), 5.into(),]),
+                    Record::Positive(vec![
+                        1.into(),
+                        4.into(),
+                        DataType::None,
+                        "abb".into(),
+                        5.into(),
+                    ]),
+                ]
+                .into(),
+                vec![
+                    OperationResult::Invalid,
+                    OperationResult::Invalid,
+                    OperationResult::Applied,
+                ]
+            )
+        );
+    }
 }
@@ -1,5 +1,58 @@
 use crate::backlog;
 use crate::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+
+/// How many updates reach a reader for every update emitted by a shard of a base node.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WritePaths {
+    /// Updates that reach the reader shard with the same index as the emitting base shard.
+    pub same_shard: usize,
+    /// Updates that reach every other reader shard.
+    pub other_shard: usize,
+    /// The number of shards of the base node.
+    pub base_shards: usize,
+}
+
+impl WritePaths {
+    /// The number of updates that reach reader shard `reader` for each update base shard `base`
+    /// emits.
+    pub fn from_shard(&self, base: usize, reader: usize) -> usize {
+        if base == reader {
+            self.same_shard
+        } else {
+            self.other_shard
+        }
+    }
+
+    /// The number of updates that reach each reader shard when every shard of the base emits an
+    /// update, as happens for every write of a transaction.
+    pub fn from_every_shard(&self) -> usize {
+        self.same_shard + (self.base_shards - 1) * self.other_shard
+    }
+}
+
+/// How a query bounds the last key column of a reader that serves range lookups.
+///
+/// Each end is `Some(true)` if the query includes the parameter value in the range (`>=`, `<=`),
+/// `Some(false)` if it does not (`>`, `<`), and `None` if the query leaves that end open.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RangeParameter {
+    pub lower_inclusive: Option<bool>,
+    pub upper_inclusive: Option<bool>,
+}
+
+/// Changes that a reader holds back, since exposing them would reveal only some of the writes of a
+/// transaction.
+struct Held {
+    /// The transaction the changes belong to, if the reader is waiting for all of its updates.
+    txn: Option<u64>,
+    /// The base shard whose write caused the changes, if any.
+    source: Option<(NodeIndex, usize)>,
+    /// The write whose effects have all been applied once these changes are, if any.
+    applied: Option<(NodeIndex, usize, u64)>,
+    data: Records,
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct Reader {
@@ -8,6 +61,33 @@ pub struct Reader {
 
     for_node: NodeIndex,
     state: Option<Vec<usize>>,
+    range: Option<RangeParameter>,
+
+    /// The number of updates that reach this reader for each update a base emits.
+    write_paths: HashMap<NodeIndex, WritePaths>,
+    /// The first transaction committed after this reader learned of its current `write_paths`.
+    ///
+    /// Earlier transactions may have had updates lost to nodes that were not yet ready, so their
+    /// updates are exposed as they arrive.
+    first_txn: u64,
+    /// The number of updates still to arrive for each transaction that has partially arrived.
+    #[serde(skip)]
+    txn_pending: HashMap<u64, usize>,
+    /// Changes held back until the transactions they belong to or follow have arrived in full.
+    #[serde(skip)]
+    held: Vec<Held>,
+    /// The keys that the changes in `held` touch.
+    #[serde(skip)]
+    held_keys: HashSet<Vec<DataType>>,
+    /// The base shards whose writes caused the changes in `held`.
+    #[serde(skip)]
+    held_sources: HashSet<(NodeIndex, usize)>,
+    /// The number of updates still to arrive for each write that has partially arrived.
+    #[serde(skip)]
+    writes_pending: HashMap<(NodeIndex, usize, u64), usize>,
+    /// The shard of the reader this is.
+    #[serde(skip)]
+    shard: usize,
 }
 
 impl Clone for Reader {
@@ -17,6 +97,15 @@ impl Clone for Reader {
             writer: None,
             state: self.state.clone(),
             for_node: self.for_node,
+            range: self.range,
+            write_paths: self.write_paths.clone(),
+            first_txn: self.first_txn,
+            txn_pending: HashMap::new(),
+            held: Vec::new(),
+            held_keys: HashSet::new(),
+            held_sources: HashSet::new(),
+            writes_pending: HashMap::new(),
+            shard: self.shard,
         }
     }
 }
@@ -27,6 +116,15 @@ impl Reader {
             writer: None,
             state: None,
             for_node,
+            range: None,
+            write_paths: HashMap::new(),
+            first_txn: 0,
+            txn_pending: HashMap::new(),
+            held: Vec::new(),
+            held_keys: HashSet::new(),
+            held_sources: HashSet::new(),
+            writes_pending: HashMap::new(),
+            shard: 0,
         }
     }
 
@@ -50,6 +148,15 @@ impl Reader {
             writer: self.writer.take(),
             state: self.state.clone(),
             for_node: self.for_node,
+            range: self.range,
+            write_paths: self.write_paths.clone(),
+            first_txn: self.first_txn,
+            txn_pending: mem::take(&mut self.txn_pending),
+            held: mem::take(&mut self.held),
+            held_keys: mem::take(&mut self.held_keys),
+            held_sources: mem::take(&mut self.held_sources),
+            writes_pending: mem::take(&mut self.writes_pending),
+            shard: self.shard,
         }
     }
 
@@ -64,9 +171,12 @@ impl Reader {
         }
     }
 
-    pub(crate) fn set_write_handle(&mut self, wh: backlog::WriteHandle) {
+    /// Give this reader the state of the given shard of the reader to maintain.
+    pub(crate) fn set_write_handle(&mut self, wh: backlog::WriteHandle, shard: usize) {
         assert!(self.writer.is_none());
+        self.shard = shard;
         self.writer = Some(wh);
+        self.track_writes();
     }
 
     pub fn key(&self) -> Option<&[usize]> {
@@ -81,10 +191,71 @@ impl Reader {
         }
     }
 
+    /// Mark this reader as serving lookups over ranges of its last key column, bounded as given.
+    ///
+    /// Partial state cannot tell which keys in a range are missing, so such readers are always
+    /// fully materialized.
+    pub fn enable_ranges(&mut self, range: RangeParameter) {
+        self.range = Some(range);
+    }
+
+    pub fn serves_ranges(&self) -> bool {
+        self.range.is_some()
+    }
+
+    pub fn range_parameter(&self) -> Option<RangeParameter> {
+        self.range
+    }
+
+    /// The number of updates that reach this reader for each update a base emits.
+    pub fn write_paths(&self) -> &HashMap<NodeIndex, WritePaths> {
+        &self.write_paths
+    }
+
+    /// Tell this reader how many updates reach it for every update emitted by each base.
+    ///
+    /// The reader uses this to tell when it has seen all the updates of a write or a transaction.
+    /// Only transactions numbered `first_txn` or higher are waited for, since updates of earlier
+    /// ones may have been lost to nodes that did not yet reach this reader.
+    pub fn set_write_paths(&mut self, paths: HashMap<NodeIndex, WritePaths>, first_txn: u64) {
+        self.write_paths = paths;
+        self.first_txn = first_txn;
+        // writes and transactions we were waiting for may now never arrive in full
+        self.txn_pending.clear();
+        self.writes_pending.clear();
+        self.track_writes();
+        self.release();
+    }
+
+    /// Tell the reader state which base shards' writes reach it.
+    fn track_writes(&mut self) {
+        if let Some(ref mut wh) = self.writer {
+            let shard = self.shard;
+            wh.track_writes(self.write_paths.iter().flat_map(|(&base, paths)| {
+                (0..paths.base_shards)
+                    .filter(move |&s| paths.from_shard(s, shard) != 0)
+                    .map(move |s| (base, s))
+            }));
+        }
+    }
+
+    /// Expose the writes applied so far to readers.
+    pub(crate) fn swap(&mut self) {
+        if let Some(ref mut state) = self.writer {
+            state.swap();
+        }
+    }
+
     pub(crate) fn is_empty(&self) -> bool {
         self.writer.as_ref().map(|w| w.is_empty()).unwrap_or(true)
     }
 
+    /// Whether the reader is waiting for more of the updates of a transaction, or holds back
+    /// changes until they arrive.
+    pub(crate) fn holds_back(&self) -> bool {
+        !self.txn_pending.is_empty() || !self.held.is_empty()
+    }
+
     pub(crate) fn state_size(&self) -> Option<u64> {
         self.writer.as_ref().map(SizeOf::deep_size_of)
     }
@@ -97,8 +268,8 @@ impl Reader {
         if let Some(ref mut handle) = self.writer {
             let mut rng = rand::thread_rng();
             bytes_freed = handle.evict_random_keys(&mut rng, n);
-            handle.swap();
         }
+        self.swap();
         bytes_freed
     }
 
@@ -108,68 +279,212 @@ impl Reader {
             for k in keys {
                 w.mut_with_key(&k[..]).mark_hole();
             }
-            w.swap();
         }
+        self.swap();
     }
 
     pub(in crate::node) fn process(&mut self, m: &mut Option<Box<Packet>>, swap: bool) {
+        let mut txn = None;
+        let mut txn_complete = false;
+        if let Some(t) = m.as_ref().and_then(|m| m.txn()) {
+            // this is one of the updates that a transaction causes to arrive here. keep track of
+            // how many more we need to see before the transaction's writes may be exposed.
+            let expected: usize = t
+                .bases
+                .iter()
+                .filter_map(|base| self.write_paths.get(base))
+                .map(WritePaths::from_every_shard)
+                .sum();
+            if expected != 0 && t.id >= self.first_txn {
+                let remaining = self.txn_pending.entry(t.id).or_insert(expected);
+                *remaining -= 1;
+                if *remaining == 0 {
+                    self.txn_pending.remove(&t.id);
+                    txn_complete = true;
+                }
+                txn = Some(t.id);
+            }
+        }
+
+        let mut source = None;
+        let mut applied = None;
+        if let Some((base, shard, seq)) = m.as_ref().and_then(|m| m.write()) {
+            // likewise, keep track of how many more updates caused by this write we need to see
+            // before all of its effects have been applied here.
+            source = Some((base, shard));
+            let expected = self
+                .write_paths
+                .get(&base)
+                .map(|paths| paths.from_shard(shard, self.shard))
+                .unwrap_or(0);
+            let complete = match expected {
+                0 => false,
+                1 => true,
+                _ => {
+                    let remaining = self
+                        .writes_pending
+                        .entry((base, shard, seq))
+                        .or_insert(expected);
+                    *remaining -= 1;
+                    *remaining == 0
+                }
+            };
+            if complete {
+                // updates arrive in order along every path, so we have also seen all the updates
+                // of earlier writes to the same base shard, including any we lost count of.
+                self.writes_pending
+                    .retain(|&(b, s, q), _| b != base || s != shard || q > seq);
+                applied = Some((base, shard, seq));
+            }
+        }
+
         if let Some(ref mut state) = self.writer {
             let m = m.as_mut().unwrap();
-            // make sure we don't fill a partial materialization
-            // hole with incomplete (i.e., non-replay) state.
-            if m.is_regular() && state.is_partial() {
-                m.map_data(|data| {
-                    data.retain(|row| {
-                        match state.entry_from_record(&row[..]).try_find_and(|_| ()) {
-                            Ok((None, _)) => {
-                                // row would miss in partial state.
-                                // leave it blank so later lookup triggers replay.
-                                false
-                            }
-                            Err(_) => unreachable!(),
-                            _ => {
-                                // state is already present,
-                                // so we can safely keep it up to date.
-                                true
+            let key = self.state.as_ref().unwrap();
+            if m.is_regular() {
+                let data = m.take_data();
+                // changes of a transaction are held back until all of them have arrived, and so
+                // are any later changes that would otherwise overtake them: those to the same
+                // keys, and those whose writes would mark the held ones as applied.
+                let hold = txn.is_some()
+                    || !self.held.is_empty()
+                        && (source.map_or(false, |s| self.held_sources.contains(&s))
+                            || data
+                                .iter()
+                                .any(|r| self.held_keys.contains(&key_of(key, r))));
+                if hold {
+                    self.held_sources.extend(source);
+                    self.held_keys.extend(data.iter().map(|r| key_of(key, r)));
+                    self.held.push(Held {
+                        txn,
+                        source,
+                        applied,
+                        data,
+                    });
+                } else {
+                    expose(state, data, applied);
+                }
+            } else {
+                // it *can* happen that multiple readers miss (and thus request replay for) the
+                // same hole at the same time. we need to make sure that we ignore any such
+                // duplicated replay.
+                if state.is_partial() {
+                    m.map_data(|data| {
+                        data.retain(|row| {
+                            match state.entry_from_record(&row[..]).try_find_and(|_| ()) {
+                                Ok((None, _)) => {
+                                    // filling a hole with replay -- ok
+                                    true
+                                }
+                                Ok((Some(_), _)) => {
+                                    // a given key should only be replayed to once!
+                                    false
+                                }
+                                Err(_) => {
+                                    // state has not yet been swapped, which means it's new,
+                                    // which means there are no readers, which means no
+                                    // requests for replays have been issued by readers, which
+                                    // means no duplicates can be received.
+                                    true
+                                }
                             }
-                        }
+                        });
                     });
-                });
-            }
+                }
 
-            // it *can* happen that multiple readers miss (and thus request replay for) the
-            // same hole at the same time. we need to make sure that we ignore any such
-            // duplicated replay.
-            if !m.is_regular() && state.is_partial() {
-                m.map_data(|data| {
-                    data.retain(|row| {
-                        match state.entry_from_record(&row[..]).try_find_and(|_| ()) {
-                            Ok((None, _)) => {
-                                // filling a hole with replay -- ok
-                                true
-                            }
-                            Ok((Some(_), _)) => {
-                                // a given key should only be replayed to once!
-                                false
-                            }
-                            Err(_) => {
-                                // state has not yet been swapped, which means it's new,
-                                // which means there are no readers, which means no
-                                // requests for replays have been issued by readers, which
-                                // means no duplicates can be received.
-                                true
-                            }
+                // the replay already includes any held back changes to the keys it fills, since
+                // they came through the replay's path before it did.
+                if !self.held.is_empty() {
+                    let held = &mut self.held;
+                    m.map_data(|data| {
+                        let filled: HashSet<_> = data.iter().map(|r| key_of(key, r)).collect();
+                        for h in held.iter_mut() {
+                            h.data.retain(|r| !filled.contains(&key_of(key, r)));
                         }
                     });
-                });
+                }
+
+                // subscribers missed the changes to any evicted keys that this replay refills
+                m.map_data(|data| state.notify_refilled(data));
+
+                state.add(m.take_data());
             }
+        }
 
-            state.add(m.take_data());
+        if txn_complete {
+            self.release();
+        }
 
-            if swap {
-                // TODO: avoid doing the pointer swap if we didn't modify anything (inc. ts)
-                state.swap();
+        if swap {
+            // TODO: avoid doing the pointer swap if we didn't modify anything (inc. ts)
+            self.swap();
+        }
+    }
+
+    /// Expose the held back changes that no longer need to wait for a transaction, in the order
+    /// in which they arrived.
+    fn release(&mut self) {
+        let state = match self.writer {
+            Some(ref mut state) => state,
+            None => return,
+        };
+        let key = self.state.as_ref().unwrap();
+        let mut keys = HashSet::new();
+        let mut sources = HashSet::new();
+        for h in mem::take(&mut self.held) {
+            let wait = h.txn.map_or(false, |t| self.txn_pending.contains_key(&t))
+                || h.source.map_or(false, |s| sources.contains(&s))
+                || h.data.iter().any(|r| keys.contains(&key_of(key, r)));
+            if wait {
+                sources.extend(h.source);
+                keys.extend(h.data.iter().map(|r| key_of(key, r)));
+                self.held.push(h);
+            } else {
+                expose(state, h.data, h.applied);
             }
         }
+        self.held_keys = keys;
+        self.held_sources = sources;
+    }
+}
+
+/// The reader key of the given row.
+fn key_of(key: &[usize], row: &[DataType]) -> Vec<DataType> {
+    key.iter().map(|&c| row[c].clone()).collect()
+}
+
+/// Apply the regular changes in `data` to the reader state, along with the given completed write.
+fn expose(
+    state: &mut backlog::WriteHandle,
+    mut data: Records,
+    applied: Option<(NodeIndex, usize, u64)>,
+) {
+    // subscribers want to hear about every change, even to keys that are not currently
+    // materialized here.
+    state.notify_subscribers(&data);
+
+    // make sure we don't fill a partial materialization
+    // hole with incomplete (i.e., non-replay) state.
+    if state.is_partial() {
+        data.retain(|row| {
+            match state.entry_from_record(&row[..]).try_find_and(|_| ()) {
+                Ok((None, _)) => {
+                    // row would miss in partial state.
+                    // leave it blank so later lookup triggers replay.
+                    false
+                }
+                Err(_) => unreachable!(),
+                _ => {
+                    // state is already present,
+                    // so we can safely keep it up to date.
+                    true
+                }
+            }
+        });
+    }
+
+    state.add(data);
+    if let Some((base, shard, seq)) = applied {
+        state.applied(base, shard, seq);
     }
 }
@@ -88,7 +88,11 @@ impl Sharder {
         }
 
         let mut dest = Destination::Any;
-        if let Packet::ReplayPiece {
+        if m.write().is_some() {
+            // every shard downstream expects to see every update caused by a tracked write, so
+            // that it can tell which writes (and transactions) it has seen in full
+            dest = Destination::All;
+        } else if let Packet::ReplayPiece {
             context: payload::ReplayPieceContext::Regular { last: true },
             ..
         } = *m
@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use std::fmt::{self, Display};
 use std::sync;
 
+use crate::ops::project::ProjectExpression;
 use crate::prelude::*;
 pub use nom_sql::Operator;
 
@@ -38,6 +39,82 @@ impl Display for Value {
 pub enum FilterCondition {
     Comparison(Operator, Value),
     In(Vec<DataType>),
+    /// Matches if all of the nested conditions match. Each nested condition carries its own
+    /// column, so the column that an `And` is paired with is ignored.
+    And(Vec<(usize, FilterCondition)>),
+    /// Matches if any of the nested conditions match. Each nested condition carries its own
+    /// column, so the column that an `Or` is paired with is ignored.
+    Or(Vec<(usize, FilterCondition)>),
+    /// Compares the values of two expressions computed over the record. The column that an
+    /// `Expression` is paired with is ignored, and the condition never matches if either
+    /// expression is `NULL`.
+    Expression(Operator, ProjectExpression, ProjectExpression),
+}
+
+fn compare(op: &Operator, d: &DataType, v: &DataType) -> bool {
+    match *op {
+        Operator::Equal => d == v,
+        Operator::NotEqual => d != v,
+        Operator::Greater => d > v,
+        Operator::GreaterOrEqual => d >= v,
+        Operator::Less => d < v,
+        Operator::LessOrEqual => d <= v,
+        Operator::In => unreachable!(),
+        _ => unimplemented!(),
+    }
+}
+
+impl FilterCondition {
+    /// Returns true if column `col` of record `r` satisfies this condition.
+    pub fn matches(&self, r: &[DataType], col: usize) -> bool {
+        match *self {
+            FilterCondition::Comparison(ref op, ref f) => {
+                let v = match *f {
+                    Value::Constant(ref dt) => dt,
+                    Value::Column(c) => &r[c],
+                };
+                compare(op, &r[col], v)
+            }
+            FilterCondition::In(ref fs) => fs.contains(&r[col]),
+            FilterCondition::And(ref cs) => cs.iter().all(|(i, c)| c.matches(r, *i)),
+            FilterCondition::Or(ref cs) => cs.iter().any(|(i, c)| c.matches(r, *i)),
+            FilterCondition::Expression(ref op, ref left, ref right) => {
+                let (left, right) = (left.eval(r), right.eval(r));
+                !left.is_none() && !right.is_none() && compare(op, &left, &right)
+            }
+        }
+    }
+
+    /// Renders this condition on column `col` for use in node descriptions.
+    pub fn describe(&self, col: usize) -> String {
+        let nested = |cs: &[(usize, FilterCondition)], sep: &str| {
+            cs.iter()
+                .map(|(i, c)| match *c {
+                    FilterCondition::And(_) | FilterCondition::Or(_) => {
+                        format!("({})", c.describe(*i))
+                    }
+                    _ => c.describe(*i),
+                })
+                .collect::<Vec<_>>()
+                .join(sep)
+        };
+        match *self {
+            FilterCondition::Comparison(ref op, ref x) => format!("f{} {} {}", col, op, x),
+            FilterCondition::In(ref xs) => format!(
+                "f{} IN ({})",
+                col,
+                xs.iter()
+                    .map(|d| format!("{}", d))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            FilterCondition::And(ref cs) => nested(cs, " AND "),
+            FilterCondition::Or(ref cs) => nested(cs, " OR "),
+            FilterCondition::Expression(ref op, ref left, ref right) => {
+                format!("{} {} {}", left, op, right)
+            }
+        }
+    }
 }
 
 impl Filter {
@@ -81,31 +158,7 @@ impl Ingredient for Filter {
         _: &DomainNodes,
         _: &StateMap,
     ) -> ProcessingResult {
-        rs.retain(|r| {
-            self.filter.iter().all(|(i, cond)| {
-                // check if this filter matches
-                let d = &r[*i];
-                match cond {
-                    FilterCondition::Comparison(ref op, ref f) => {
-                        let v = match *f {
-                            Value::Constant(ref dt) => dt,
-                            Value::Column(c) => &r[c],
-                        };
-                        match *op {
-                            Operator::Equal => d == v,
-                            Operator::NotEqual => d != v,
-                            Operator::Greater => d > v,
-                            Operator::GreaterOrEqual => d >= v,
-                            Operator::Less => d < v,
-                            Operator::LessOrEqual => d <= v,
-                            Operator::In => unreachable!(),
-                            _ => unimplemented!(),
-                        }
-                    }
-                    FilterCondition::In(ref fs) => fs.contains(d),
-                }
-            })
-        });
+        rs.retain(|r| self.filter.iter().all(|(i, cond)| cond.matches(r, *i)));
 
         ProcessingResult {
             results: rs,
@@ -138,19 +191,7 @@ impl Ingredient for Filter {
             "σ[{}]",
             self.filter
                 .iter()
-                .filter_map(|(i, ref cond)| match *cond {
-                    FilterCondition::Comparison(ref op, ref x) => {
-                        Some(format!("f{} {} {}", i, escape(&format!("{}", op)), x))
-                    }
-                    FilterCondition::In(ref xs) => Some(format!(
-                        "f{} IN ({})",
-                        i,
-                        xs.iter()
-                            .map(|d| format!("{}", d))
-                            .collect::<Vec<_>>()
-                            .join(", ")
-                    )),
-                })
+                .map(|(i, ref cond)| escape(&cond.describe(*i)))
                 .collect::<Vec<_>>()
                 .as_slice()
                 .join(", ")
@@ -172,30 +213,7 @@ impl Ingredient for Filter {
         self.lookup(*self.src, columns, key, nodes, states)
             .and_then(|result| {
                 let f = self.filter.clone();
-                let filter = move |r: &[DataType]| {
-                    f.iter().all(|(i, ref cond)| {
-                        // check if this filter matches
-                        let d = &r[*i];
-                        match *cond {
-                            FilterCondition::Comparison(ref op, ref f) => {
-                                let v = match *f {
-                                    Value::Constant(ref dt) => dt,
-                                    Value::Column(c) => &r[c],
-                                };
-                                match *op {
-                                    Operator::Equal => d == v,
-                                    Operator::NotEqual => d != v,
-                                    Operator::Greater => d > v,
-                                    Operator::GreaterOrEqual => d >= v,
-                                    Operator::Less => d < v,
-                                    Operator::LessOrEqual => d <= v,
-                                    _ => unimplemented!(),
-                                }
-                            }
-                            FilterCondition::In(ref fs) => fs.contains(d),
-                        }
-                    })
-                };
+                let filter = move |r: &[DataType]| f.iter().all(|(i, cond)| cond.matches(r, *i));
 
                 match result {
                     Some(rs) => {
@@ -420,4 +438,94 @@ mod tests {
         left = vec![42.into(), "b".into()];
         assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
     }
+
+    #[test]
+    fn it_works_with_expressions() {
+        use crate::ops::project::ProjectExpressionBase;
+        use nom_sql::ArithmeticOperator;
+
+        // x * 2 > 10
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Expression(
+                    Operator::Greater,
+                    ProjectExpression::new(
+                        ArithmeticOperator::Multiply,
+                        ProjectExpressionBase::Column(0),
+                        ProjectExpressionBase::Literal(2.into()),
+                    ),
+                    ProjectExpression::Base(ProjectExpressionBase::Literal(10.into())),
+                ),
+            )]),
+        );
+
+        let mut left: Vec<DataType>;
+
+        left = vec![6.into(), "a".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        left = vec![5.into(), "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+    }
+
+    #[test]
+    fn it_works_with_disjunctions() {
+        // f0 = 1 OR (f0 > 5 AND f1 != "a")
+        let mut g = setup(
+            false,
+            Some(&[(
+                0,
+                FilterCondition::Or(vec![
+                    (
+                        0,
+                        FilterCondition::Comparison(Operator::Equal, Value::Constant(1.into())),
+                    ),
+                    (
+                        0,
+                        FilterCondition::And(vec![
+                            (
+                                0,
+                                FilterCondition::Comparison(
+                                    Operator::Greater,
+                                    Value::Constant(5.into()),
+                                ),
+                            ),
+                            (
+                                1,
+                                FilterCondition::Comparison(
+                                    Operator::NotEqual,
+                                    Value::Constant("a".into()),
+                                ),
+                            ),
+                        ]),
+                    ),
+                ]),
+            )]),
+        );
+
+        let mut left: Vec<DataType>;
+
+        // first disjunct matches
+        left = vec![1.into(), "a".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        // second disjunct matches
+        left = vec![6.into(), "b".into()];
+        assert_eq!(g.narrow_one_row(left.clone(), false), vec![left].into());
+
+        // neither disjunct matches (6 > 5, but "a" == "a")
+        left = vec![6.into(), "a".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+
+        // neither disjunct matches (2 != 1 and 2 <= 5)
+        left = vec![2.into(), "b".into()];
+        assert!(g.narrow_one_row(left.clone(), false).is_empty());
+
+        assert_eq!(
+            g.node().description(true),
+            "σ[f0 = 1 OR (f0 \\> 5 AND f1 != \"a\")]"
+        );
+    }
 }
@@ -2,14 +2,29 @@ use crate::ops::grouped::GroupedOperation;
 use crate::ops::grouped::GroupedOperator;
 
 use crate::prelude::*;
+use noria::Decimal;
 
 /// Supported aggregation operators.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Aggregation {
     /// Count the number of records for each group. The value for the `over` column is ignored.
     COUNT,
     /// Sum the value of the `over` column for all records of each group.
     SUM,
+    /// Average the non-NULL values of the `over` column for each group.
+    AVG,
+    /// Compute the population variance of the non-NULL values of the `over` column for each
+    /// group.
+    VARIANCE,
+    /// Compute the population standard deviation of the non-NULL values of the `over` column for
+    /// each group.
+    STDDEV,
+    /// Emit 1 if all non-NULL values of the `over` column in a group are non-zero, and 0
+    /// otherwise (i.e., SQL's `bool_and`).
+    BOOLAND,
+    /// Emit 1 if any non-NULL value of the `over` column in a group is non-zero, and 0 otherwise
+    /// (i.e., SQL's `bool_or`).
+    BOOLOR,
 }
 
 impl Aggregation {
@@ -37,6 +52,49 @@ impl Aggregation {
             },
         )
     }
+
+    /// Names of the columns that an `Aggregator` performing this operation emits between the
+    /// group columns and the aggregated value, in order to maintain the value incrementally.
+    pub fn state_columns(&self) -> &'static [&'static str] {
+        match *self {
+            Aggregation::COUNT | Aggregation::SUM => &[],
+            Aggregation::AVG => &["count", "sum"],
+            Aggregation::VARIANCE | Aggregation::STDDEV => &["count", "sum", "sum_of_squares"],
+            Aggregation::BOOLAND | Aggregation::BOOLOR => &["count", "true_count"],
+        }
+    }
+}
+
+/// The contribution of a single record to an aggregation.
+#[derive(Debug, Clone, Copy)]
+pub struct AggregateDiff {
+    /// 1 for a positive record and -1 for a negative one, or 0 if the record does not count
+    /// towards the aggregation (i.e., its value is NULL and the aggregation ignores NULLs).
+    count: i128,
+    /// The record's value (1 or 0 for boolean aggregations), negated for a negative record.
+    value: i128,
+}
+
+/// Stores an aggregated integer, which may be too large for a `BigInt` (just like MySQL, sums of
+/// `BIGINT`s that do not fit are emitted as `DECIMAL`s).
+fn to_data(v: i128) -> DataType {
+    if v >= i128::from(i64::min_value()) && v <= i128::from(i64::max_value()) {
+        DataType::BigInt(v as i64)
+    } else {
+        Decimal::new(v, 0).into()
+    }
+}
+
+/// Reads back an aggregated integer stored by `to_data`.
+fn from_data(v: &DataType) -> i128 {
+    match *v {
+        DataType::Int(n) => i128::from(n),
+        DataType::UnsignedInt(n) => i128::from(n),
+        DataType::BigInt(n) => i128::from(n),
+        DataType::UnsignedBigInt(n) => i128::from(n),
+        DataType::Decimal(ref d) if d.scale() == 0 => d.mantissa(),
+        ref x => unreachable!("aggregation state holds {:?}", x),
+    }
 }
 
 /// Aggregator implementas a Soup node that performans common aggregation operations such as counts
@@ -52,6 +110,10 @@ impl Aggregation {
 /// identifying the group, and appending the aggregated value. For example, for a sum with
 /// `self.over == 1`, a previous sum of `3`, and an incoming record with `[a, 1, x]`, the output
 /// would be `[a, x, 4]`.
+///
+/// Aggregations that cannot be updated from their previous value alone (e.g., `AVG`) also emit the
+/// intermediate state they need (see `Aggregation::state_columns`) before the aggregated value.
+/// For an average, the output record above would be `[a, x, 2, 4, 2.0]` instead.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Aggregator {
     op: Aggregation,
@@ -59,8 +121,15 @@ pub struct Aggregator {
     group: Vec<usize>,
 }
 
+impl GroupedOperator<Aggregator> {
+    /// The aggregation that this operator performs.
+    pub fn kind(&self) -> &Aggregation {
+        &self.inner.op
+    }
+}
+
 impl GroupedOperation for Aggregator {
-    type Diff = i128;
+    type Diff = AggregateDiff;
 
     fn setup(&mut self, parent: &Node) {
         assert!(
@@ -74,24 +143,38 @@ impl GroupedOperation for Aggregator {
     }
 
     fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
-        match self.op {
-            Aggregation::COUNT if pos => 1,
-            Aggregation::COUNT => -1,
-            Aggregation::SUM => {
-                let v = match r[self.over] {
-                    DataType::Int(n) => i128::from(n),
-                    DataType::UnsignedInt(n) => i128::from(n),
-                    DataType::BigInt(n) => i128::from(n),
-                    DataType::UnsignedBigInt(n) => i128::from(n),
-                    DataType::None => 0,
-                    ref x => unreachable!("tried to aggregate over {:?} on {:?}", x, r),
-                };
-                if pos {
-                    v
-                } else {
-                    0i128 - v
-                }
-            }
+        let sign = if pos { 1 } else { -1 };
+        if let Aggregation::COUNT = self.op {
+            return AggregateDiff {
+                count: sign,
+                value: 0,
+            };
+        }
+
+        let v = match r[self.over] {
+            DataType::Int(n) => Some(i128::from(n)),
+            DataType::UnsignedInt(n) => Some(i128::from(n)),
+            DataType::BigInt(n) => Some(i128::from(n)),
+            DataType::UnsignedBigInt(n) => Some(i128::from(n)),
+            DataType::None => None,
+            ref x => unreachable!("tried to aggregate over {:?} on {:?}", x, r),
+        };
+        let v = match self.op {
+            Aggregation::BOOLAND | Aggregation::BOOLOR => v.map(|v| (v != 0) as i128),
+            _ => v,
+        };
+
+        match v {
+            // SUM has always treated NULL as 0
+            None if self.op == Aggregation::SUM => AggregateDiff {
+                count: sign,
+                value: 0,
+            },
+            None => AggregateDiff { count: 0, value: 0 },
+            Some(v) => AggregateDiff {
+                count: sign,
+                value: sign * v,
+            },
         }
     }
 
@@ -100,15 +183,73 @@ impl GroupedOperation for Aggregator {
         current: Option<&DataType>,
         diffs: &mut dyn Iterator<Item = Self::Diff>,
     ) -> DataType {
-        let n = match current {
-            Some(&DataType::Int(n)) => i128::from(n),
-            Some(&DataType::UnsignedInt(n)) => i128::from(n),
-            Some(&DataType::BigInt(n)) => i128::from(n),
-            Some(&DataType::UnsignedBigInt(n)) => i128::from(n),
-            None => 0,
-            _ => unreachable!(),
+        let n = current.map_or(0, from_data);
+        match self.op {
+            Aggregation::COUNT => to_data(diffs.fold(n, |n, d| n + d.count)),
+            Aggregation::SUM => to_data(diffs.fold(n, |n, d| n + d.value)),
+            _ => unreachable!("{:?} keeps state columns", self.op),
+        }
+    }
+
+    fn state_columns(&self) -> usize {
+        self.op.state_columns().len()
+    }
+
+    fn apply_with_state(
+        &self,
+        current: Option<&[DataType]>,
+        diffs: &mut dyn Iterator<Item = Self::Diff>,
+    ) -> Vec<DataType> {
+        if self.state_columns() == 0 {
+            return vec![self.apply(current.map(|c| &c[c.len() - 1]), diffs)];
+        }
+
+        // the state columns hold the number of values in the group, their sum and, for
+        // variances, the sum of their squares
+        let mut state: Vec<i128> = match current {
+            Some(current) => current[..current.len() - 1].iter().map(from_data).collect(),
+            None => vec![0; self.state_columns()],
+        };
+        for d in diffs {
+            state[0] += d.count;
+            state[1] += d.value;
+            if let Some(squares) = state.get_mut(2) {
+                // `d.value` is negated for negative records, which `d.count` makes up for
+                *squares += d.count * d.value * d.value;
+            }
+        }
+
+        let (n, sum) = (state[0], state[1]);
+        let value = if n == 0 {
+            DataType::None
+        } else {
+            match self.op {
+                Aggregation::AVG => DataType::from(sum as f64 / n as f64),
+                Aggregation::VARIANCE | Aggregation::STDDEV => {
+                    // the exact numerator can overflow even an i128 for large values
+                    let numerator = n
+                        .checked_mul(state[2])
+                        .and_then(|a| a.checked_sub(sum.checked_mul(sum)?))
+                        .map_or_else(
+                            || n as f64 * state[2] as f64 - (sum as f64) * (sum as f64),
+                            |v| v as f64,
+                        );
+                    let variance = numerator / (n as f64 * n as f64);
+                    if self.op == Aggregation::STDDEV {
+                        DataType::from(variance.sqrt())
+                    } else {
+                        DataType::from(variance)
+                    }
+                }
+                Aggregation::BOOLAND => DataType::from((sum == n) as i32),
+                Aggregation::BOOLOR => DataType::from((sum > 0) as i32),
+                Aggregation::COUNT | Aggregation::SUM => unreachable!(),
+            }
         };
-        diffs.fold(n, |n, d| n + d).into()
+
+        let mut out: Vec<DataType> = state.into_iter().map(to_data).collect();
+        out.push(value);
+        out
     }
 
     fn description(&self, detailed: bool) -> String {
@@ -116,12 +257,22 @@ impl GroupedOperation for Aggregator {
             return String::from(match self.op {
                 Aggregation::COUNT => "+",
                 Aggregation::SUM => "𝛴",
+                Aggregation::AVG => "AVG",
+                Aggregation::VARIANCE => "VAR",
+                Aggregation::STDDEV => "STDDEV",
+                Aggregation::BOOLAND => "∧",
+                Aggregation::BOOLOR => "∨",
             });
         }
 
         let op_string = match self.op {
             Aggregation::COUNT => "|*|".into(),
             Aggregation::SUM => format!("𝛴({})", self.over),
+            Aggregation::AVG => format!("avg({})", self.over),
+            Aggregation::VARIANCE => format!("var({})", self.over),
+            Aggregation::STDDEV => format!("stddev({})", self.over),
+            Aggregation::BOOLAND => format!("∧({})", self.over),
+            Aggregation::BOOLOR => format!("∨({})", self.over),
         };
         let group_cols = self
             .group
@@ -176,6 +327,9 @@ mod tests {
 
         let s = Aggregation::SUM.over(s, 1, &[2, 0]);
         assert_eq!(s.description(true), "𝛴(1) γ[2, 0]");
+
+        let a = Aggregation::AVG.over(0.into(), 1, &[0]);
+        assert_eq!(a.description(true), "avg(1) γ[0]");
     }
 
     #[test]
@@ -412,4 +566,136 @@ mod tests {
         );
         assert_eq!(c.node().resolve(1), None);
     }
+
+    fn setup_op(op: Aggregation) -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        let fields: Vec<String> = Some("x")
+            .into_iter()
+            .chain(op.state_columns().iter().cloned())
+            .chain(Some("ys"))
+            .map(String::from)
+            .collect();
+        let fields: Vec<&str> = fields.iter().map(String::as_str).collect();
+        g.set_op(
+            "identity",
+            &fields[..],
+            op.over(s.as_global(), 1, &[0]),
+            true,
+        );
+        g
+    }
+
+    /// Returns the aggregated value that the last positive record in `rs` carries.
+    fn value(rs: Records) -> DataType {
+        let r = rs
+            .into_iter()
+            .filter(Record::is_positive)
+            .last()
+            .unwrap()
+            .extract()
+            .0;
+        r[r.len() - 1].clone()
+    }
+
+    #[test]
+    fn it_averages() {
+        let mut c = setup_op(Aggregation::AVG);
+
+        let rs = c.narrow_one_row(vec![1.into(), 1.into()], true);
+        assert_eq!(rs.len(), 1);
+        assert_eq!(value(rs), 1.0.into());
+
+        let rs = c.narrow_one_row(vec![1.into(), 2.into()], true);
+        assert_eq!(rs.len(), 2);
+        assert_eq!(value(rs), 1.5.into());
+
+        // NULLs do not count towards the average
+        let rs = c.narrow_one_row(vec![1.into(), DataType::None], true);
+        assert_eq!(rs.len(), 0);
+
+        let rs = c.narrow_one_row((vec![1.into(), 1.into()], false), true);
+        assert_eq!(value(rs), 2.0.into());
+
+        // an empty group has no average
+        let rs = c.narrow_one_row((vec![1.into(), 2.into()], false), true);
+        assert_eq!(value(rs), DataType::None);
+    }
+
+    #[test]
+    fn it_computes_variance() {
+        let mut c = setup_op(Aggregation::VARIANCE);
+        c.narrow_one(
+            vec![vec![1.into(), 2.into()], vec![1.into(), 4.into()]],
+            true,
+        );
+        let rs = c.narrow_one_row(vec![1.into(), 6.into()], true);
+        // mean 4, squared deviations 4 + 0 + 4
+        assert_eq!(value(rs), (8.0 / 3.0).into());
+
+        let mut c = setup_op(Aggregation::STDDEV);
+        c.narrow_one(
+            vec![vec![1.into(), 2.into()], vec![1.into(), 6.into()]],
+            true,
+        );
+        let rs = c.narrow_one_row((vec![1.into(), 6.into()], false), true);
+        assert_eq!(value(rs), 0.0.into());
+        let rs = c.narrow_one_row(vec![1.into(), 8.into()], true);
+        assert_eq!(value(rs), 3.0.into());
+    }
+
+    #[test]
+    fn it_sums_past_bigint() {
+        let mut c = setup_op(Aggregation::SUM);
+        let max = i64::max_value();
+        c.narrow_one_row(vec![1.into(), max.into()], true);
+        let rs = c.narrow_one_row(vec![1.into(), max.into()], true);
+        assert_eq!(value(rs), Decimal::new(2 * i128::from(max), 0).into());
+
+        // and back once the sum fits again
+        let rs = c.narrow_one_row((vec![1.into(), max.into()], false), true);
+        assert_eq!(value(rs), max.into());
+
+        // the sum of squares behind a variance overflows long before the values do
+        let mut c = setup_op(Aggregation::VARIANCE);
+        c.narrow_one(
+            vec![vec![1.into(), max.into()], vec![1.into(), max.into()]],
+            true,
+        );
+        let rs = c.narrow_one_row((vec![1.into(), max.into()], false), true);
+        assert_eq!(value(rs), 0.0.into());
+    }
+
+    #[test]
+    fn it_computes_boolean_aggregates() {
+        let mut and = setup_op(Aggregation::BOOLAND);
+        let mut or = setup_op(Aggregation::BOOLOR);
+
+        let rs = and.narrow_one_row(vec![1.into(), 1.into()], true);
+        assert_eq!(value(rs), 1.into());
+        let rs = or.narrow_one_row(vec![1.into(), 0.into()], true);
+        assert_eq!(value(rs), 0.into());
+
+        let rs = and.narrow_one_row(vec![1.into(), 0.into()], true);
+        assert_eq!(value(rs), 0.into());
+        let rs = or.narrow_one_row(vec![1.into(), 5.into()], true);
+        assert_eq!(value(rs), 1.into());
+
+        // removing the only dissenting value flips the result back
+        let rs = and.narrow_one_row((vec![1.into(), 0.into()], false), true);
+        assert_eq!(value(rs), 1.into());
+        let rs = or.narrow_one_row((vec![1.into(), 5.into()], false), true);
+        assert_eq!(value(rs), 0.into());
+    }
+
+    #[test]
+    fn it_resolves_state_columns() {
+        let c = setup_op(Aggregation::AVG);
+        assert_eq!(
+            c.node().resolve(0),
+            Some(vec![(c.narrow_base_id().as_global(), 0)])
+        );
+        assert_eq!(c.node().resolve(1), None);
+        assert_eq!(c.node().resolve(3), None);
+    }
 }
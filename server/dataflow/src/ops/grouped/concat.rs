@@ -99,6 +99,12 @@ impl GroupConcat {
                     DataType::UnsignedBigInt(ref n) => s.push_str(&n.to_string()),
                     DataType::Real(..) => s.push_str(&rec[*i].to_string()),
                     DataType::Timestamp(ref ts) => s.push_str(&ts.format("%+").to_string()),
+                    DataType::ByteArray(ref bytes) => s.push_str(&String::from_utf8_lossy(bytes)),
+                    DataType::Decimal(..)
+                    | DataType::Date(..)
+                    | DataType::Time(..)
+                    | DataType::Bool(..)
+                    | DataType::Json(..) => s.push_str(&rec[*i].to_string()),
                     DataType::None => unreachable!(),
                 },
             }
@@ -1,6 +1,6 @@
 use std::sync;
 
-use crate::ops::filter::{FilterCondition, Value};
+use crate::ops::filter::FilterCondition;
 use crate::ops::grouped::GroupedOperation;
 use crate::ops::grouped::GroupedOperator;
 pub use nom_sql::{Literal, Operator};
@@ -86,29 +86,7 @@ impl GroupedOperation for FilterAggregator {
     }
 
     fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
-        let passes_filter = self.filter.iter().all(|(i, cond)| {
-            // check if this filter matches
-            let d = &r[*i];
-            match *cond {
-                FilterCondition::Comparison(ref op, ref f) => {
-                    let v = match *f {
-                        Value::Constant(ref dt) => dt,
-                        Value::Column(c) => &r[c],
-                    };
-                    match *op {
-                        Operator::Equal => d == v,
-                        Operator::NotEqual => d != v,
-                        Operator::Greater => d > v,
-                        Operator::GreaterOrEqual => d >= v,
-                        Operator::Less => d < v,
-                        Operator::LessOrEqual => d <= v,
-                        Operator::In => unreachable!(),
-                        _ => unimplemented!(),
-                    }
-                }
-                FilterCondition::In(ref fs) => fs.contains(d),
-            }
-        });
+        let passes_filter = self.filter.iter().all(|(i, cond)| cond.matches(r, *i));
         let v = if passes_filter {
             match self.op {
                 FilterAggregation::COUNT => 1,
@@ -192,6 +170,7 @@ mod tests {
     use super::*;
 
     use crate::ops;
+    use crate::ops::filter::Value;
 
     fn setup(mat: bool) -> ops::test::MockGraph {
         let mut g = ops::test::MockGraph::new();
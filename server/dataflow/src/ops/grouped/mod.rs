@@ -59,6 +59,25 @@ pub trait GroupedOperation: fmt::Debug + Clone {
         diffs: &mut dyn Iterator<Item = Self::Diff>,
     ) -> DataType;
 
+    /// The number of columns, emitted between the group columns and the aggregated value, in
+    /// which this operation keeps intermediate state that it needs to maintain its value
+    /// incrementally (e.g., the count and sum behind an average).
+    fn state_columns(&self) -> usize {
+        0
+    }
+
+    /// Given the `current` state columns and value of a group (if any), and a number of changes
+    /// for the group (`diffs`), compute its updated state columns and value.
+    ///
+    /// Operations without state columns need not implement this, as it defers to `apply`.
+    fn apply_with_state(
+        &self,
+        current: Option<&[DataType]>,
+        diffs: &mut dyn Iterator<Item = Self::Diff>,
+    ) -> Vec<DataType> {
+        vec![self.apply(current.map(|c| &c[c.len() - 1]), diffs)]
+    }
+
     fn description(&self, detailed: bool) -> String;
     fn over_columns(&self) -> Vec<usize>;
 }
@@ -141,7 +160,7 @@ where
         // build a translation mechanism for going from output columns to input columns
         let colfix: Vec<_> = (0..self.cols)
             .filter(|col| {
-                // since the generated value goes at the end,
+                // since the state columns and the generated value go at the end,
                 // this is the n'th output value
                 // otherwise this column does not appear in output
                 self.group_by.iter().any(|c| c == col)
@@ -237,17 +256,18 @@ where
                     };
 
                     let old = rs.into_iter().next();
-                    // current value is in the last output column
+                    // current value is in the last output column, preceded by any state columns,
                     // or "" if there is no current group
                     let current = old.as_ref().map(|rows| match rows {
-                        Cow::Borrowed(rs) => Cow::Borrowed(&rs[rs.len() - 1]),
-                        Cow::Owned(rs) => Cow::Owned(rs[rs.len() - 1].clone()),
+                        Cow::Borrowed(rs) => Cow::Borrowed(&rs[out_key.len()..]),
+                        Cow::Owned(rs) => Cow::Owned(rs[out_key.len()..].to_vec()),
                     });
 
                     // new is the result of applying all diffs for the group to the current value
-                    let new = inner.apply(current.as_ref().map(|v| &**v), &mut diffs as &mut _);
+                    let new = inner
+                        .apply_with_state(current.as_ref().map(|v| &**v), &mut diffs as &mut _);
                     match current {
-                        Some(ref current) if new == **current => {
+                        Some(ref current) if new[..] == **current => {
                             // no change
                         }
                         _ => {
@@ -259,7 +279,7 @@ where
 
                             // emit positive, which is group + new.
                             let mut rec = group;
-                            rec.push(new);
+                            rec.extend(new);
                             out.push(Record::Positive(rec));
                         }
                     }
@@ -292,7 +312,7 @@ where
     }
 
     fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
-        if col == self.colfix.len() {
+        if col >= self.colfix.len() {
             return None;
         }
         Some(vec![(self.src.as_global(), self.colfix[col])])
@@ -303,7 +323,7 @@ where
     }
 
     fn parent_columns(&self, column: usize) -> Vec<(NodeIndex, Option<usize>)> {
-        if column == self.colfix.len() {
+        if column >= self.colfix.len() {
             return vec![(self.src.as_global(), None)];
         }
         vec![(self.src.as_global(), Some(self.colfix[column]))]
@@ -2,6 +2,8 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::mem;
 
+use slog::Logger;
+
 use crate::prelude::*;
 
 /// Kind of join
@@ -11,6 +13,8 @@ pub enum JoinType {
     Left,
     /// Inner join between two views
     Inner,
+    /// Full outer join between two views
+    Full,
 }
 
 /// Where to source a join column
@@ -24,7 +28,9 @@ pub enum JoinSource {
     B(usize, usize),
 }
 
-/// Join provides a left outer join between two views.
+/// Join provides an inner, left outer, or full outer join between two views.
+///
+/// Right outer joins are expressed as left outer joins with the parents swapped.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Join {
     left: IndexPair,
@@ -43,6 +49,11 @@ pub struct Join {
     in_place_right_emit: Vec<(bool, usize)>,
 
     kind: JoinType,
+
+    // The join keys of the left rows that an ongoing full replay has brought so far, which full
+    // joins use to find the right rows that nothing on the left matches once the replay ends.
+    #[serde(skip)]
+    replayed_keys: HashSet<DataType>,
 }
 
 enum Preprocessed {
@@ -121,6 +132,7 @@ impl Join {
             in_place_left_emit,
             in_place_right_emit,
             kind,
+            replayed_keys: HashSet::new(),
         }
     }
 
@@ -179,13 +191,28 @@ impl Join {
         reuse
     }
 
+    /// Whether rows from the left (if `left`) or right parent are emitted padded with NULLs when
+    /// nothing in the other parent matches them.
+    fn pads(&self, left: bool) -> bool {
+        match self.kind {
+            JoinType::Inner => false,
+            JoinType::Left => left,
+            JoinType::Full => true,
+        }
+    }
+
+    /// Generate the output row for a row from the left (if `left`) or right parent that nothing in
+    /// the other parent matches. The join column is emitted only once, so it is filled in from
+    /// `row` whichever parent that is from.
     // TODO: make non-allocating
-    fn generate_null(&self, left: &[DataType]) -> Vec<DataType> {
+    fn generate_null(&self, row: &[DataType], left: bool) -> Vec<DataType> {
         self.emit
             .iter()
             .map(|&(from_left, col)| {
-                if from_left {
-                    left[col].clone()
+                if from_left == left {
+                    row[col].clone()
+                } else if from_left && col == self.on.0 {
+                    row[self.on.1].clone()
                 } else {
                     DataType::None
                 }
@@ -209,7 +236,11 @@ impl Ingredient for Join {
 
     fn must_replay_among(&self) -> Option<HashSet<NodeIndex>> {
         match self.kind {
-            JoinType::Left => Some(Some(self.left.as_global()).into_iter().collect()),
+            // full joins find the unmatched rows of the right parent themselves at the end of a
+            // full replay from the left (see `on_input_raw`)
+            JoinType::Left | JoinType::Full => {
+                Some(Some(self.left.as_global()).into_iter().collect())
+            }
             JoinType::Inner => Some(
                 vec![self.left.as_global(), self.right.as_global()]
                     .into_iter()
@@ -285,18 +316,24 @@ impl Ingredient for Join {
             rs.sort_by(cmp);
         }
 
+        // whether the records we got, or the rows they match in the other parent, are emitted
+        // padded with NULLs when there is no match
+        let from_left = from == *self.left;
+        let pad_from = self.pads(from_left);
+        let pad_other = self.pads(!from_left);
+
         let mut ret: Vec<Record> = Vec::with_capacity(rs.len());
         let mut at = 0;
         while at != rs.len() {
-            let mut old_right_count = None;
-            let mut new_right_count = None;
+            // how many records on our side have this key, now that this batch has been applied
+            let mut from_count = None;
             let prev_join_key = rs[at][from_key].clone();
 
-            if from == *self.right && self.kind == JoinType::Left {
+            if pad_other {
                 let rc = self
                     .lookup(
-                        *self.right,
-                        &[self.on.1],
+                        from,
+                        &[from_key],
                         &KeyType::Single(&prev_join_key),
                         nodes,
                         state,
@@ -304,7 +341,7 @@ impl Ingredient for Join {
                     .unwrap();
 
                 if rc.is_none() {
-                    // we got something from right, but that row's key is not in right??
+                    // we got something from one side, but that row's key is not in that side??
                     //
                     // this *can* happen! imagine if you have two partial indices on right,
                     // one on column a and one on column b. imagine that a is the join key.
@@ -322,15 +359,14 @@ impl Ingredient for Join {
                 } else {
                     if replay_key_cols.is_some() {
                         lookups.push(Lookup {
-                            on: *self.right,
-                            cols: vec![self.on.1],
+                            on: from,
+                            cols: vec![from_key],
                             key: vec![prev_join_key.clone()],
                         });
                     }
 
                     let rc = rc.unwrap().count();
-                    old_right_count = Some(rc);
-                    new_right_count = Some(rc);
+                    from_count = Some(rc);
                 }
             }
 
@@ -374,11 +410,12 @@ impl Ingredient for Join {
 
             let start = at;
             let mut make_null = None;
-            if self.kind == JoinType::Left && from == *self.right {
-                // If records are being received from the right, we need to find the number of
-                // records that existed *before* this batch of records was processed so we know
-                // whether or not to generate +/- NULL rows.
-                if let Some(mut old_rc) = old_right_count {
+            if pad_other {
+                // If the rows in the other side are padded when unmatched, we need to find the
+                // number of records on our side that existed *before* this batch of records was
+                // processed so we know whether or not to generate +/- NULL rows.
+                if let Some(new_rc) = from_count {
+                    let mut old_rc = new_rc;
                     while at != rs.len() && rs[at][from_key] == prev_join_key {
                         if rs[at].is_positive() {
                             old_rc -= 1
@@ -388,17 +425,16 @@ impl Ingredient for Join {
                         at += 1;
                     }
 
-                    // emit null rows if necessary for left join
-                    let new_rc = new_right_count.unwrap();
+                    // emit null rows if necessary for the other side
                     if new_rc == 0 && old_rc != 0 {
-                        // all lefts for this key must emit + NULLs
+                        // all other rows for this key must emit + NULLs
                         make_null = Some(true);
                     } else if new_rc != 0 && old_rc == 0 {
-                        // all lefts for this key must emit - NULLs
+                        // all other rows for this key must emit - NULLs
                         make_null = Some(false);
                     }
                 } else {
-                    // we got a right, but missed in right; clearly, a replay is needed
+                    // we got a record, but missed on its own side; clearly, a replay is needed
                     let start = at;
                     at = rs[at..]
                         .iter()
@@ -407,7 +443,7 @@ impl Ingredient for Join {
                         .unwrap_or_else(|| rs.len());
                     misses.extend((start..at).map(|i| Miss {
                         on: from,
-                        lookup_idx: vec![self.on.1],
+                        lookup_idx: vec![from_key],
                         lookup_cols: vec![from_key],
                         replay_cols: replay_key_cols.clone(),
                         // NOTE: we're stealing data here!
@@ -426,7 +462,8 @@ impl Ingredient for Join {
                     .unwrap_or_else(|| rs.len());
             }
 
-            let mut other_rows_count = 0;
+            // where in `ret` the joined rows for the first record with this key ended up
+            let mut joined = Vec::new();
             for r in &mut rs[start..at] {
                 // put something bogus in rs (which will be discarded anyway) so we can take r.
                 let r = mem::replace(r, Record::Positive(Vec::new()));
@@ -436,9 +473,9 @@ impl Ingredient for Join {
                     // we have yet to iterate through other_rows
                     let mut other_rows = other_rows.peekable();
                     if other_rows.peek().is_none() {
-                        if self.kind == JoinType::Left && from == *self.left {
-                            // left join, got a thing from left, no rows in right == NULL
-                            ret.push((self.generate_null(&row), positive).into());
+                        if pad_from {
+                            // outer join, no rows on the other side == NULL
+                            ret.push((self.generate_null(&row, from_left), positive).into());
                         }
                         continue;
                     }
@@ -446,13 +483,13 @@ impl Ingredient for Join {
                     // we're going to pull a little trick here so that the *last* time we use
                     // `row`, we re-use its memory instead of allocating a new Vec. we do this by
                     // (ab)using .peek() to terminate the loop one iteration early.
-                    other_rows_count += 1;
                     let mut other = other_rows.next().unwrap();
                     while other_rows.peek().is_some() {
                         if let Some(false) = make_null {
-                            // we need to generate a -NULL for all these lefts
-                            ret.push((self.generate_null(&other), false).into());
+                            // we need to generate a -NULL for all these other rows
+                            ret.push((self.generate_null(&other, !from_left), false).into());
                         }
+                        joined.push(ret.len());
                         if from == *self.left {
                             ret.push(
                                 (
@@ -471,17 +508,17 @@ impl Ingredient for Join {
                             );
                         }
                         if let Some(true) = make_null {
-                            // we need to generate a +NULL for all these lefts
-                            ret.push((self.generate_null(&other), true).into());
+                            // we need to generate a +NULL for all these other rows
+                            ret.push((self.generate_null(&other, !from_left), true).into());
                         }
                         other = other_rows.next().unwrap();
-                        other_rows_count += 1;
                     }
 
                     if let Some(false) = make_null {
-                        // we need to generate a -NULL for the last left too
-                        ret.push((self.generate_null(&other), false).into());
+                        // we need to generate a -NULL for the last other row too
+                        ret.push((self.generate_null(&other, !from_left), false).into());
                     }
+                    joined.push(ret.len());
                     ret.push(
                         (
                             self.regenerate_row(row, &other, from == *self.left, false),
@@ -490,21 +527,21 @@ impl Ingredient for Join {
                             .into(),
                     );
                     if let Some(true) = make_null {
-                        // we need to generate a +NULL for the last left too
-                        ret.push((self.generate_null(&other), true).into());
+                        // we need to generate a +NULL for the last other row too
+                        ret.push((self.generate_null(&other, !from_left), true).into());
                     }
-                } else if other_rows_count == 0 {
-                    if self.kind == JoinType::Left && from == *self.left {
-                        // left join, got a thing from left, no rows in right == NULL
-                        ret.push((self.generate_null(&row), positive).into());
+                } else if joined.is_empty() {
+                    if pad_from {
+                        // outer join, no rows on the other side == NULL
+                        ret.push((self.generate_null(&row, from_left), positive).into());
                     }
                 } else {
                     // we no longer have access to `other_rows`
-                    // *but* the values are all in ret[-other_rows_count:]!
-                    let start = ret.len() - other_rows_count;
-                    let end = ret.len();
+                    // *but* the values are all in ret at the positions in `joined`! (they are not
+                    // necessarily adjacent, since NULL rows may have been emitted in between.)
+                    let last = joined[joined.len() - 1];
                     // we again use the trick above where the last row we produce reuses `row`
-                    for i in start..(end - 1) {
+                    for &i in &joined[..joined.len() - 1] {
                         if from == *self.left {
                             let r = (
                                 self.generate_row(&row, &ret[i], Preprocessed::Right),
@@ -522,7 +559,7 @@ impl Ingredient for Join {
                         }
                     }
                     let r = (
-                        self.regenerate_row(row, &ret[end - 1], from == *self.left, true),
+                        self.regenerate_row(row, &ret[last], from == *self.left, true),
                         positive,
                     )
                         .into();
@@ -538,6 +575,49 @@ impl Ingredient for Join {
         }
     }
 
+    fn on_input_raw(
+        &mut self,
+        ex: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        replay: ReplayContext,
+        nodes: &DomainNodes,
+        state: &StateMap,
+        log: &Logger,
+    ) -> RawProcessingResult {
+        // full replays only ever come from the left (see `must_replay_among`), so once one ends,
+        // we have yet to emit the rows on the right that nothing on the left matches.
+        let full_replay = match replay {
+            ReplayContext::Full { last } if self.kind == JoinType::Full && from == *self.left => {
+                Some(last)
+            }
+            _ => None,
+        };
+        if full_replay.is_some() {
+            let on = self.on.0;
+            self.replayed_keys.extend(rs.iter().map(|r| r[on].clone()));
+        }
+
+        let mut m = self.on_input(ex, from, rs, replay.key(), nodes, state);
+        if full_replay == Some(true) {
+            let replayed_keys = mem::replace(&mut self.replayed_keys, HashSet::new());
+            match state.get(*self.right) {
+                Some(right) => m.results.extend(
+                    right
+                        .cloned_records()
+                        .into_iter()
+                        .filter(|r| !replayed_keys.contains(&r[self.on.1]))
+                        .map(|r| Record::Positive(self.generate_null(&r, false))),
+                ),
+                None => error!(
+                    log,
+                    "full join cannot pad the right side without its materialized state"
+                ),
+            }
+        }
+        RawProcessingResult::Regular(m)
+    }
+
     fn suggest_indexes(&self, _this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
         vec![
             (self.left.as_global(), vec![self.on.0]),
@@ -561,6 +641,7 @@ impl Ingredient for Join {
             return String::from(match self.kind {
                 JoinType::Left => "⋉",
                 JoinType::Inner => "⋈",
+                JoinType::Full => "⟗",
             });
         }
 
@@ -577,6 +658,7 @@ impl Ingredient for Join {
         let op = match self.kind {
             JoinType::Left => "⋉",
             JoinType::Inner => "⋈",
+            JoinType::Full => "⟗",
         };
 
         format!(
@@ -590,6 +672,11 @@ impl Ingredient for Join {
         )
     }
 
+    fn requires_full_materialization(&self) -> bool {
+        // a partial replay from either side would miss the rows that only the other side has
+        self.kind == JoinType::Full
+    }
+
     fn parent_columns(&self, col: usize) -> Vec<(NodeIndex, Option<usize>)> {
         let pcol = self.emit[col];
         if (pcol.0 && pcol.1 == self.on.0) || (!pcol.0 && pcol.1 == self.on.1) {
@@ -614,6 +701,10 @@ mod tests {
     use crate::ops;
 
     fn setup() -> (ops::test::MockGraph, IndexPair, IndexPair) {
+        setup_kind(JoinType::Left)
+    }
+
+    fn setup_kind(kind: JoinType) -> (ops::test::MockGraph, IndexPair, IndexPair) {
         let mut g = ops::test::MockGraph::new();
         let l = g.add_base("left", &["l0", "l1"]);
         let r = g.add_base("right", &["r0", "r1"]);
@@ -622,7 +713,7 @@ mod tests {
         let j = Join::new(
             l.as_global(),
             r.as_global(),
-            JoinType::Left,
+            kind,
             vec![B(0, 0), L(1), R(1)],
         );
 
@@ -737,6 +828,69 @@ mod tests {
         assert_eq!(rs.len(), 0);
     }
 
+    #[test]
+    fn it_works_with_full_joins() {
+        let (mut j, l, r) = setup_kind(JoinType::Full);
+        let l_a1 = vec![1.into(), "a".into()];
+        let l_c3 = vec![3.into(), "c".into()];
+        let l_d3 = vec![3.into(), "d".into()];
+
+        let r_x1 = vec![1.into(), "x".into()];
+        let r_w3 = vec![3.into(), "w".into()];
+        let r_v3 = vec![3.into(), "v".into()];
+
+        // unmatched records from either side are padded with NULLs
+        j.seed(r, r_x1.clone());
+        let rs = j.one_row(r, r_x1.clone(), false);
+        assert_eq!(
+            rs,
+            vec![(vec![1.into(), DataType::None, "x".into()], true)].into()
+        );
+
+        j.seed(l, l_c3.clone());
+        let rs = j.one_row(l, l_c3.clone(), false);
+        assert_eq!(
+            rs,
+            vec![(vec![3.into(), "c".into(), DataType::None], true)].into()
+        );
+
+        // a match from the left should revoke the nulls on the right
+        j.seed(l, l_a1.clone());
+        let rs = j.one_row(l, l_a1.clone(), false);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), DataType::None, "x".into()], false),
+                (vec![1.into(), "a".into(), "x".into()], true),
+            ]
+            .into()
+        );
+
+        // and removing the match should bring them back
+        j.unseed(l);
+        j.seed(l, l_c3.clone());
+        let rs = j.one_row(l, (l_a1.clone(), false), false);
+        assert_eq!(
+            rs,
+            vec![
+                (vec![1.into(), "a".into(), "x".into()], false),
+                (vec![1.into(), DataType::None, "x".into()], true),
+            ]
+            .into()
+        );
+
+        // several matches disappearing at once should pad every row on the other side once
+        j.seed(l, l_d3.clone());
+        let rs = j.one(r, vec![(r_w3.clone(), false), (r_v3.clone(), false)], false);
+        assert_eq!(rs.len(), 6);
+        for &left in &["c", "d"] {
+            for &right in &["w", "v"] {
+                assert!(rs.has_negative(&[3.into(), left.into(), right.into()][..]));
+            }
+            assert!(rs.has_positive(&[3.into(), left.into(), DataType::None][..]));
+        }
+    }
+
     #[test]
     fn it_suggests_indices() {
         use std::collections::HashMap;
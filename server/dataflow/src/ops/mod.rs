@@ -10,8 +10,10 @@ pub mod grouped;
 pub mod identity;
 pub mod join;
 pub mod latest;
+pub mod paginate;
 pub mod project;
 pub mod rewrite;
+pub mod semijoin;
 pub mod topk;
 pub mod trigger;
 pub mod union;
@@ -24,12 +26,14 @@ pub enum NodeOperator {
     Concat(grouped::GroupedOperator<grouped::concat::GroupConcat>),
     FilterSum(grouped::GroupedOperator<grouped::filteraggregate::FilterAggregator>),
     Join(join::Join),
+    SemiJoin(semijoin::SemiJoin),
     Latest(latest::Latest),
     Project(project::Project),
     Union(union::Union),
     Identity(identity::Identity),
     Filter(filter::Filter),
     TopK(topk::TopK),
+    Paginate(paginate::Paginate),
     Trigger(trigger::Trigger),
     Rewrite(rewrite::Rewrite),
     Distinct(distinct::Distinct),
@@ -62,12 +66,14 @@ nodeop_from_impl!(
     grouped::GroupedOperator<grouped::filteraggregate::FilterAggregator>
 );
 nodeop_from_impl!(NodeOperator::Join, join::Join);
+nodeop_from_impl!(NodeOperator::SemiJoin, semijoin::SemiJoin);
 nodeop_from_impl!(NodeOperator::Latest, latest::Latest);
 nodeop_from_impl!(NodeOperator::Project, project::Project);
 nodeop_from_impl!(NodeOperator::Union, union::Union);
 nodeop_from_impl!(NodeOperator::Identity, identity::Identity);
 nodeop_from_impl!(NodeOperator::Filter, filter::Filter);
 nodeop_from_impl!(NodeOperator::TopK, topk::TopK);
+nodeop_from_impl!(NodeOperator::Paginate, paginate::Paginate);
 nodeop_from_impl!(NodeOperator::Trigger, trigger::Trigger);
 nodeop_from_impl!(NodeOperator::Rewrite, rewrite::Rewrite);
 nodeop_from_impl!(NodeOperator::Distinct, distinct::Distinct);
@@ -80,12 +86,14 @@ macro_rules! impl_ingredient_fn_mut {
             NodeOperator::Concat(ref mut i) => i.$fn($($arg),*),
             NodeOperator::FilterSum(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Join(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::SemiJoin(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Latest(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Project(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Union(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Identity(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Filter(ref mut i) => i.$fn($($arg),*),
             NodeOperator::TopK(ref mut i) => i.$fn($($arg),*),
+            NodeOperator::Paginate(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Trigger(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Rewrite(ref mut i) => i.$fn($($arg),*),
             NodeOperator::Distinct(ref mut i) => i.$fn($($arg),*),
@@ -101,12 +109,14 @@ macro_rules! impl_ingredient_fn_ref {
             NodeOperator::Concat(ref i) => i.$fn($($arg),*),
             NodeOperator::FilterSum(ref i) => i.$fn($($arg),*),
             NodeOperator::Join(ref i) => i.$fn($($arg),*),
+            NodeOperator::SemiJoin(ref i) => i.$fn($($arg),*),
             NodeOperator::Latest(ref i) => i.$fn($($arg),*),
             NodeOperator::Project(ref i) => i.$fn($($arg),*),
             NodeOperator::Union(ref i) => i.$fn($($arg),*),
             NodeOperator::Identity(ref i) => i.$fn($($arg),*),
             NodeOperator::Filter(ref i) => i.$fn($($arg),*),
             NodeOperator::TopK(ref i) => i.$fn($($arg),*),
+            NodeOperator::Paginate(ref i) => i.$fn($($arg),*),
             NodeOperator::Trigger(ref i) => i.$fn($($arg),*),
             NodeOperator::Rewrite(ref i) => i.$fn($($arg),*),
             NodeOperator::Distinct(ref i) => i.$fn($($arg),*),
@@ -419,7 +429,7 @@ pub mod test {
             struct Ex;
 
             impl Executor for Ex {
-                fn ack(&mut self, _: SourceChannelIdentifier) {}
+                fn ack(&mut self, _: SourceChannelIdentifier, _: noria::WriteResult) {}
                 fn create_universe(&mut self, _: HashMap<String, DataType>) {}
                 fn send(&mut self, _: ReplicaAddr, _: Box<Packet>) {}
             }
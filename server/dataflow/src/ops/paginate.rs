@@ -0,0 +1,390 @@
+use std::collections::HashMap;
+
+use crate::prelude::*;
+
+use crate::ops::topk::Order;
+use nom_sql::OrderType;
+
+/// Paginate splits the records in each group into pages of `page_size` records, and tags every
+/// record with the number of the page it falls on.
+///
+/// Records are ranked in SQL order (i.e., ascending order puts the smallest value first), with ties
+/// broken by comparing entire records so that the assignment of records to pages is
+/// deterministic. The first `offset` records in each group are assigned to negative page numbers,
+/// and the page number is emitted as an additional, last column.
+///
+/// A change to a single record can shift every record ranked after it onto a different page, so
+/// this operator ranks the group's current records once per batch, inserts and removes the updates
+/// at their ranks, and then re-emits the records after the first changed rank whose page moved.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Paginate {
+    src: IndexPair,
+
+    // some cache state
+    us: Option<IndexPair>,
+    cols: usize,
+
+    // precomputed datastructures
+    group_by: Vec<usize>,
+
+    order: Order,
+    page_size: usize,
+    offset: usize,
+}
+
+impl Paginate {
+    /// Construct a new Paginate operator.
+    ///
+    /// `src` is this operator's ancestor, `order` gives the columns (and directions) that records
+    /// are ranked by, `group_by` indicates the columns that this operator is keyed on, and
+    /// `page_size` is the number of records per page once the first `offset` records in each
+    /// group have been skipped.
+    pub fn new(
+        src: NodeIndex,
+        order: Vec<(usize, OrderType)>,
+        group_by: Vec<usize>,
+        page_size: usize,
+        offset: usize,
+    ) -> Self {
+        assert!(page_size > 0, "pages must hold at least one record");
+
+        let mut group_by = group_by;
+        group_by.sort();
+
+        Paginate {
+            src: src.into(),
+
+            us: None,
+            cols: 0,
+
+            group_by,
+            order: order.into(),
+            page_size,
+            offset,
+        }
+    }
+
+    fn page_of(&self, rank: usize) -> i64 {
+        (rank as i64 - self.offset as i64).div_euclid(self.page_size as i64)
+    }
+}
+
+impl Ingredient for Paginate {
+    fn take(&mut self) -> NodeOperator {
+        Clone::clone(self).into()
+    }
+
+    fn ancestors(&self) -> Vec<NodeIndex> {
+        vec![self.src.as_global()]
+    }
+
+    fn on_connected(&mut self, g: &Graph) {
+        let srcn = &g[self.src.as_global()];
+        self.cols = srcn.fields().len();
+    }
+
+    fn on_commit(&mut self, us: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
+        // who's our parent really?
+        self.src.remap(remap);
+
+        // who are we?
+        self.us = Some(remap[&us]);
+    }
+
+    fn on_input(
+        &mut self,
+        _: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        replay_key_cols: Option<&[usize]>,
+        _: &DomainNodes,
+        state: &StateMap,
+    ) -> ProcessingResult {
+        debug_assert_eq!(from, *self.src);
+
+        if rs.is_empty() {
+            return ProcessingResult {
+                results: rs,
+                ..Default::default()
+            };
+        }
+
+        let group_by = &self.group_by;
+
+        // Every update can move the records ranked after it, so process all records for the same
+        // group together. Grouping (rather than sorting) the batch preserves the order of a
+        // negative and a positive for the same record.
+        let mut groups: Vec<(Vec<DataType>, Vec<Record>)> = Vec::new();
+        let mut group_idx: HashMap<Vec<DataType>, usize> = HashMap::new();
+        let rs: Vec<Record> = rs.into();
+        for r in rs {
+            let grp: Vec<DataType> = group_by.iter().map(|&col| r[col].clone()).collect();
+            match group_idx.get(&grp) {
+                Some(&i) => groups[i].1.push(r),
+                None => {
+                    group_idx.insert(grp.clone(), groups.len());
+                    groups.push((grp, vec![r]));
+                }
+            }
+        }
+
+        let us = self.us.unwrap();
+        let db = state
+            .get(*us)
+            .expect("paginate operators must have their own state materialized");
+
+        let order = &self.order;
+        let rank = |a: &[DataType], b: &[DataType]| order.cmp(a, b).then_with(|| a.cmp(b));
+
+        let mut out = Vec::new();
+        let mut misses = Vec::new();
+        let mut lookups = Vec::new();
+        for (grp, rs) in groups {
+            // the group's records in rank order, along with the page we last emitted them on
+            let mut current: Vec<(Vec<DataType>, Option<DataType>)> =
+                match db.lookup(&group_by[..], &KeyType::from(&grp[..])) {
+                    LookupResult::Some(old) => {
+                        if replay_key_cols.is_some() {
+                            lookups.push(Lookup {
+                                on: *us,
+                                cols: group_by.clone(),
+                                key: grp.clone(),
+                            });
+                        }
+                        old.into_iter()
+                            .map(|r| {
+                                let mut r = r.into_owned();
+                                let page = r.pop();
+                                (r, page)
+                            })
+                            .collect()
+                    }
+                    LookupResult::Missing => {
+                        misses.extend(rs.into_iter().map(|r| Miss {
+                            on: *us,
+                            lookup_idx: group_by.clone(),
+                            lookup_cols: group_by.clone(),
+                            replay_cols: replay_key_cols.map(Vec::from),
+                            record: r.extract().0,
+                        }));
+                        continue;
+                    }
+                };
+            current.sort_by(|a, b| rank(&a.0, &b.0));
+
+            // apply the updates at their ranks; records ranked before the first rank that changed
+            // stay on their pages
+            let mut delta: HashMap<Vec<DataType>, isize> = HashMap::new();
+            let mut changed = current.len();
+            for r in rs {
+                let (r, positive) = r.extract();
+                let at = current.binary_search_by(|x| rank(&x.0, &r));
+                if positive {
+                    let i = match at {
+                        Ok(i) | Err(i) => i,
+                    };
+                    current.insert(i, (r, None));
+                    changed = changed.min(i);
+                } else if let Ok(i) = at {
+                    let (mut r, page) = current.remove(i);
+                    if let Some(page) = page {
+                        r.push(page);
+                        *delta.entry(r).or_insert(0) -= 1;
+                    }
+                    changed = changed.min(i);
+                }
+            }
+
+            // only emit the records whose page number changed
+            for (i, &mut (ref r, ref mut page)) in current.iter_mut().enumerate().skip(changed) {
+                let new_page: DataType = self.page_of(i).into();
+                if page.as_ref() == Some(&new_page) {
+                    continue;
+                }
+                if let Some(old_page) = page.take() {
+                    let mut old = r.clone();
+                    old.push(old_page);
+                    *delta.entry(old).or_insert(0) -= 1;
+                }
+                let mut new = r.clone();
+                new.push(new_page);
+                *delta.entry(new).or_insert(0) += 1;
+            }
+            for (r, count) in delta {
+                if count < 0 {
+                    out.extend((0..-count).map(|_| Record::Negative(r.clone())));
+                } else if count > 0 {
+                    out.extend((0..count).map(|_| Record::Positive(r.clone())));
+                }
+            }
+        }
+
+        ProcessingResult {
+            results: out.into(),
+            lookups,
+            misses,
+        }
+    }
+
+    fn suggest_indexes(&self, this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        vec![(this, self.group_by.clone())].into_iter().collect()
+    }
+
+    fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
+        if col == self.cols {
+            return None;
+        }
+        Some(vec![(self.src.as_global(), col)])
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from("Paginate");
+        }
+
+        let group_cols = self
+            .group_by
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "Paginate [{} per page, skip {}] γ[{}]",
+            self.page_size, self.offset, group_cols
+        )
+    }
+
+    fn parent_columns(&self, col: usize) -> Vec<(NodeIndex, Option<usize>)> {
+        if col == self.cols {
+            return vec![(self.src.as_global(), None)];
+        }
+        vec![(self.src.as_global(), Some(col))]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup(page_size: usize, offset: usize) -> (ops::test::MockGraph, IndexPair) {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y", "z"]);
+        g.set_op(
+            "paginate",
+            &["x", "y", "z", "page"],
+            Paginate::new(
+                s.as_global(),
+                vec![(2, OrderType::OrderAscending)],
+                vec![1],
+                page_size,
+                offset,
+            ),
+            true,
+        );
+        (g, s)
+    }
+
+    fn paged(r: &[DataType], page: i64) -> Vec<DataType> {
+        let mut r = r.to_vec();
+        r.push(page.into());
+        r
+    }
+
+    #[test]
+    fn it_assigns_pages() {
+        let (mut g, _) = setup(2, 0);
+        let ni = g.node().local_addr();
+
+        let r10: Vec<DataType> = vec![1.into(), "z".into(), 10.into()];
+        let r20: Vec<DataType> = vec![2.into(), "z".into(), 20.into()];
+        let r30: Vec<DataType> = vec![3.into(), "z".into(), 30.into()];
+
+        let a = g.narrow_one_row(r20.clone(), true);
+        assert_eq!(a, vec![paged(&r20, 0)].into());
+
+        let a = g.narrow_one_row(r30.clone(), true);
+        assert_eq!(a, vec![paged(&r30, 0)].into());
+
+        // 10 ranks first, which pushes 30 onto the second page
+        let a = g.narrow_one_row(r10.clone(), true);
+        assert_eq!(a.len(), 3);
+        assert!(a.iter().any(|r| r == &(paged(&r10, 0), true).into()));
+        assert!(a.iter().any(|r| r == &(paged(&r30, 0), false).into()));
+        assert!(a.iter().any(|r| r == &(paged(&r30, 1), true).into()));
+        assert_eq!(g.states[ni].rows(), 3);
+
+        // and removing it pulls 30 back
+        let a = g.narrow_one_row((r10.clone(), false), true);
+        assert_eq!(a.len(), 3);
+        assert!(a.iter().any(|r| r == &(paged(&r10, 0), false).into()));
+        assert!(a.iter().any(|r| r == &(paged(&r30, 1), false).into()));
+        assert!(a.iter().any(|r| r == &(paged(&r30, 0), true).into()));
+        assert_eq!(g.states[ni].rows(), 2);
+    }
+
+    #[test]
+    fn it_skips_offset() {
+        let (mut g, _) = setup(2, 1);
+
+        let r10: Vec<DataType> = vec![1.into(), "z".into(), 10.into()];
+        let r20: Vec<DataType> = vec![2.into(), "z".into(), 20.into()];
+        let r30: Vec<DataType> = vec![3.into(), "z".into(), 30.into()];
+
+        let a = g.narrow_one(vec![r30.clone(), r10.clone(), r20.clone()], true);
+        assert_eq!(a.len(), 3);
+        assert!(a.iter().any(|r| r == &(paged(&r10, -1), true).into()));
+        assert!(a.iter().any(|r| r == &(paged(&r20, 0), true).into()));
+        assert!(a.iter().any(|r| r == &(paged(&r30, 0), true).into()));
+    }
+
+    #[test]
+    fn it_keeps_groups_apart() {
+        let (mut g, _) = setup(1, 0);
+
+        let a1: Vec<DataType> = vec![1.into(), "a".into(), 10.into()];
+        let b1: Vec<DataType> = vec![2.into(), "b".into(), 20.into()];
+        let a2: Vec<DataType> = vec![3.into(), "a".into(), 30.into()];
+
+        let a = g.narrow_one(vec![a1.clone(), b1.clone(), a2.clone()], true);
+        assert_eq!(a.len(), 3);
+        assert!(a.iter().any(|r| r == &(paged(&a1, 0), true).into()));
+        assert!(a.iter().any(|r| r == &(paged(&b1, 0), true).into()));
+        assert!(a.iter().any(|r| r == &(paged(&a2, 1), true).into()));
+    }
+
+    #[test]
+    fn it_ignores_unchanged_updates() {
+        let (mut g, _) = setup(2, 0);
+
+        let r10: Vec<DataType> = vec![1.into(), "z".into(), 10.into()];
+        let r20: Vec<DataType> = vec![2.into(), "z".into(), 20.into()];
+
+        g.narrow_one(vec![r10.clone(), r20.clone()], true);
+        let a = g.narrow_one(
+            vec![Record::Negative(r20.clone()), Record::Positive(r20.clone())],
+            true,
+        );
+        assert!(a.is_empty());
+    }
+
+    #[test]
+    fn it_suggests_indices() {
+        let (g, _) = setup(2, 0);
+        let me = 2.into();
+        let idx = g.node().suggest_indexes(me);
+        assert_eq!(idx.len(), 1);
+        assert_eq!(*idx.iter().next().unwrap().1, vec![1]);
+    }
+
+    #[test]
+    fn it_resolves() {
+        let (g, _) = setup(2, 0);
+        assert_eq!(
+            g.node().resolve(2),
+            Some(vec![(g.narrow_base_id().as_global(), 2)])
+        );
+        assert_eq!(g.node().resolve(3), None);
+    }
+}
@@ -1,22 +1,67 @@
+use chrono::{Datelike, Timelike};
 use nom_sql::ArithmeticOperator;
 
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt;
 
+use crate::ops::filter::{FilterCondition, Value};
 use crate::prelude::*;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum ProjectExpressionBase {
     Column(usize),
     Literal(DataType),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ProjectExpression {
-    op: ArithmeticOperator,
-    left: ProjectExpressionBase,
-    right: ProjectExpressionBase,
+/// A component of a date or time that can be extracted from a timestamp.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum DatePart {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+/// An expression computed over the columns of a record.
+///
+/// The SQL front end produces all of these from the select lists of queries, with arithmetic
+/// nested to any depth.
+///
+/// Arithmetic yields `NULL` where it is not defined: when an operand is `NULL` or not a number,
+/// when dividing by zero, and when the result overflows.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ProjectExpression {
+    /// A column of the record, or a literal value.
+    Base(ProjectExpressionBase),
+    /// A binary arithmetic operation between two expressions, or `NULL` if it is not defined.
+    Arithmetic {
+        op: ArithmeticOperator,
+        left: Box<ProjectExpression>,
+        right: Box<ProjectExpression>,
+    },
+    /// `CASE WHEN .. THEN .. ELSE .. END`: evaluates to the expression of the first branch whose
+    /// conditions all match the record, or to `otherwise` if none do.
+    Case {
+        branches: Vec<(Vec<(usize, FilterCondition)>, ProjectExpression)>,
+        otherwise: Box<ProjectExpression>,
+    },
+    /// The first of the given expressions that is not `NULL`, or `NULL` if they all are.
+    /// `IFNULL(a, b)` is `COALESCE(a, b)`.
+    Coalesce(Vec<ProjectExpression>),
+    /// The given text in lower case.
+    Lower(Box<ProjectExpression>),
+    /// The given text in upper case.
+    Upper(Box<ProjectExpression>),
+    /// The concatenation of the given expressions, or `NULL` if any of them is `NULL`.
+    Concat(Vec<ProjectExpression>),
+    /// A component of the given timestamp, date or time, or `NULL` if it is none of those.
+    ///
+    /// Like in MySQL, a date is taken to be at midnight. A time has no year, month or day, and its
+    /// hours are not limited to a single day; all of its components carry its sign.
+    Extract(DatePart, Box<ProjectExpression>),
 }
 
 impl ProjectExpression {
@@ -25,7 +70,151 @@ impl ProjectExpression {
         left: ProjectExpressionBase,
         right: ProjectExpressionBase,
     ) -> ProjectExpression {
-        ProjectExpression { op, left, right }
+        ProjectExpression::Arithmetic {
+            op,
+            left: Box::new(ProjectExpression::Base(left)),
+            right: Box::new(ProjectExpression::Base(right)),
+        }
+    }
+
+    /// Replaces every column index `i` that this expression reads with `f(i)`.
+    pub fn map_columns<F: FnMut(usize) -> usize>(&mut self, f: &mut F) {
+        match *self {
+            ProjectExpression::Base(ProjectExpressionBase::Column(ref mut i)) => *i = f(*i),
+            ProjectExpression::Base(ProjectExpressionBase::Literal(_)) => (),
+            ProjectExpression::Arithmetic {
+                ref mut left,
+                ref mut right,
+                ..
+            } => {
+                left.map_columns(f);
+                right.map_columns(f);
+            }
+            ProjectExpression::Case {
+                ref mut branches,
+                ref mut otherwise,
+            } => {
+                for &mut (ref mut conds, ref mut e) in branches {
+                    map_condition_columns(conds, f);
+                    e.map_columns(f);
+                }
+                otherwise.map_columns(f);
+            }
+            ProjectExpression::Coalesce(ref mut es) | ProjectExpression::Concat(ref mut es) => {
+                for e in es {
+                    e.map_columns(f);
+                }
+            }
+            ProjectExpression::Lower(ref mut e)
+            | ProjectExpression::Upper(ref mut e)
+            | ProjectExpression::Extract(_, ref mut e) => e.map_columns(f),
+        }
+    }
+
+    /// Evaluate this expression over the given record.
+    pub fn eval(&self, record: &[DataType]) -> DataType {
+        match *self {
+            ProjectExpression::Base(ProjectExpressionBase::Column(i)) => record[i].clone(),
+            ProjectExpression::Base(ProjectExpressionBase::Literal(ref data)) => data.clone(),
+            ProjectExpression::Arithmetic {
+                ref op,
+                ref left,
+                ref right,
+            } => {
+                let left = left.eval(record);
+                let right = right.eval(record);
+                match *op {
+                    ArithmeticOperator::Add => left.checked_add(&right),
+                    ArithmeticOperator::Subtract => left.checked_sub(&right),
+                    ArithmeticOperator::Multiply => left.checked_mul(&right),
+                    ArithmeticOperator::Divide => left.checked_div(&right),
+                }
+                .unwrap_or(DataType::None)
+            }
+            ProjectExpression::Case {
+                ref branches,
+                ref otherwise,
+            } => branches
+                .iter()
+                .find(|(conds, _)| conds.iter().all(|(i, c)| c.matches(record, *i)))
+                .map(|(_, e)| e)
+                .unwrap_or(&**otherwise)
+                .eval(record),
+            ProjectExpression::Coalesce(ref es) => es
+                .iter()
+                .map(|e| e.eval(record))
+                .find(|d| !d.is_none())
+                .unwrap_or(DataType::None),
+            ProjectExpression::Lower(ref e) => match e.eval(record) {
+                ref d if d.is_string() => <&str>::from(d).to_lowercase().into(),
+                d => d,
+            },
+            ProjectExpression::Upper(ref e) => match e.eval(record) {
+                ref d if d.is_string() => <&str>::from(d).to_uppercase().into(),
+                d => d,
+            },
+            ProjectExpression::Concat(ref es) => {
+                let mut s = String::new();
+                for e in es {
+                    match e.eval(record) {
+                        DataType::None => return DataType::None,
+                        ref d if d.is_string() => s.push_str(<&str>::from(d)),
+                        d => s.push_str(&d.to_string()),
+                    }
+                }
+                s.into()
+            }
+            ProjectExpression::Extract(part, ref e) => match e.eval(record) {
+                DataType::Timestamp(ts) => DataType::from(match part {
+                    DatePart::Year => ts.year(),
+                    DatePart::Month => ts.month() as i32,
+                    DatePart::Day => ts.day() as i32,
+                    DatePart::Hour => ts.hour() as i32,
+                    DatePart::Minute => ts.minute() as i32,
+                    DatePart::Second => ts.second() as i32,
+                }),
+                DataType::Date(d) => DataType::from(match part {
+                    DatePart::Year => d.year(),
+                    DatePart::Month => d.month() as i32,
+                    DatePart::Day => d.day() as i32,
+                    DatePart::Hour | DatePart::Minute | DatePart::Second => 0,
+                }),
+                DataType::Time(micros) => {
+                    let secs = micros.abs() / 1_000_000;
+                    let value = match part {
+                        DatePart::Year | DatePart::Month | DatePart::Day => return DataType::None,
+                        DatePart::Hour => secs / 3600,
+                        DatePart::Minute => secs / 60 % 60,
+                        DatePart::Second => secs % 60,
+                    };
+                    DataType::from(value * micros.signum())
+                }
+                _ => DataType::None,
+            },
+        }
+    }
+}
+
+fn map_condition_columns<F: FnMut(usize) -> usize>(
+    conditions: &mut [(usize, FilterCondition)],
+    f: &mut F,
+) {
+    for &mut (ref mut i, ref mut c) in conditions {
+        match *c {
+            // these carry their own columns, and ignore the one that they are paired with
+            FilterCondition::And(ref mut cs) | FilterCondition::Or(ref mut cs) => {
+                map_condition_columns(cs, f)
+            }
+            FilterCondition::Expression(_, ref mut left, ref mut right) => {
+                left.map_columns(f);
+                right.map_columns(f);
+            }
+            FilterCondition::Comparison(_, Value::Column(ref mut other)) => {
+                *i = f(*i);
+                *other = f(*other);
+            }
+            FilterCondition::Comparison(..) | FilterCondition::In(_) => *i = f(*i),
+        }
     }
 }
 
@@ -38,16 +227,69 @@ impl fmt::Display for ProjectExpressionBase {
     }
 }
 
-impl fmt::Display for ProjectExpression {
+impl fmt::Display for DatePart {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let op = match self.op {
-            ArithmeticOperator::Add => "+",
-            ArithmeticOperator::Subtract => "-",
-            ArithmeticOperator::Divide => "/",
-            ArithmeticOperator::Multiply => "*",
+        let part = match *self {
+            DatePart::Year => "YEAR",
+            DatePart::Month => "MONTH",
+            DatePart::Day => "DAY",
+            DatePart::Hour => "HOUR",
+            DatePart::Minute => "MINUTE",
+            DatePart::Second => "SECOND",
         };
+        write!(f, "{}", part)
+    }
+}
 
-        write!(f, "{} {} {}", self.left, op, self.right)
+impl fmt::Display for ProjectExpression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let list = |es: &[ProjectExpression]| {
+            es.iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match *self {
+            ProjectExpression::Base(ref b) => write!(f, "{}", b),
+            ProjectExpression::Arithmetic {
+                ref op,
+                ref left,
+                ref right,
+            } => {
+                let op = match *op {
+                    ArithmeticOperator::Add => "+",
+                    ArithmeticOperator::Subtract => "-",
+                    ArithmeticOperator::Divide => "/",
+                    ArithmeticOperator::Multiply => "*",
+                };
+                let operand = |e: &ProjectExpression| match *e {
+                    ProjectExpression::Arithmetic { .. } => format!("({})", e),
+                    _ => e.to_string(),
+                };
+
+                write!(f, "{} {} {}", operand(left), op, operand(right))
+            }
+            ProjectExpression::Case {
+                ref branches,
+                ref otherwise,
+            } => {
+                write!(f, "CASE")?;
+                for (conds, e) in branches {
+                    let conds = conds
+                        .iter()
+                        .map(|(i, c)| c.describe(*i))
+                        .collect::<Vec<_>>()
+                        .join(" AND ");
+                    write!(f, " WHEN {} THEN {}", conds, e)?;
+                }
+                write!(f, " ELSE {} END", otherwise)
+            }
+            ProjectExpression::Coalesce(ref es) => write!(f, "COALESCE({})", list(es)),
+            ProjectExpression::Lower(ref e) => write!(f, "LOWER({})", e),
+            ProjectExpression::Upper(ref e) => write!(f, "UPPER({})", e),
+            ProjectExpression::Concat(ref es) => write!(f, "CONCAT({})", list(es)),
+            ProjectExpression::Extract(part, ref e) => write!(f, "EXTRACT({} FROM {})", part, e),
+        }
     }
 }
 
@@ -100,25 +342,6 @@ impl Project {
     }
 }
 
-fn eval_expression(expression: &ProjectExpression, record: &[DataType]) -> DataType {
-    let left = match expression.left {
-        ProjectExpressionBase::Column(i) => &record[i],
-        ProjectExpressionBase::Literal(ref data) => data,
-    };
-
-    let right = match expression.right {
-        ProjectExpressionBase::Column(i) => &record[i],
-        ProjectExpressionBase::Literal(ref data) => data,
-    };
-
-    match expression.op {
-        ArithmeticOperator::Add => left + right,
-        ArithmeticOperator::Subtract => left - right,
-        ArithmeticOperator::Multiply => left * right,
-        ArithmeticOperator::Divide => left / right,
-    }
-}
-
 impl Ingredient for Project {
     fn take(&mut self) -> NodeOperator {
         Clone::clone(self).into()
@@ -168,7 +391,7 @@ impl Ingredient for Project {
                         Some(emit) => Box::new(rs.map(move |r| {
                             let mut new_r = Vec::with_capacity(r.len());
                             let mut expr: Vec<DataType> = if let Some(ref e) = expressions {
-                                e.iter().map(|i| eval_expression(i, &r[..])).collect()
+                                e.iter().map(|i| i.eval(&r[..])).collect()
                             } else {
                                 vec![]
                             };
@@ -239,7 +462,7 @@ impl Ingredient for Project {
                 }
 
                 if let Some(ref e) = self.expressions {
-                    new_r.extend(e.iter().map(|i| eval_expression(i, &r[..])));
+                    new_r.extend(e.iter().map(|i| i.eval(&r[..])));
                 }
 
                 if let Some(ref a) = self.additional {
@@ -351,11 +574,11 @@ mod tests {
     }
 
     fn setup_column_arithmetic(op: ArithmeticOperator) -> ops::test::MockGraph {
-        let expression = ProjectExpression {
-            left: ProjectExpressionBase::Column(0),
-            right: ProjectExpressionBase::Column(1),
+        let expression = ProjectExpression::new(
             op,
-        };
+            ProjectExpressionBase::Column(0),
+            ProjectExpressionBase::Column(1),
+        );
 
         setup_arithmetic(expression)
     }
@@ -470,14 +693,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn it_forwards_undefined_arithmetic_as_null() {
+        let mut p = setup_column_arithmetic(ArithmeticOperator::Divide);
+        let rec = vec![10.into(), 0.into()];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec![10.into(), 0.into(), DataType::None]].into()
+        );
+
+        let mut p = setup_column_arithmetic(ArithmeticOperator::Add);
+        let rec = vec!["a".into(), 1.into()];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec!["a".into(), 1.into(), DataType::None]].into()
+        );
+
+        let mut p = setup_column_arithmetic(ArithmeticOperator::Multiply);
+        let rec = vec![std::i64::MAX.into(), 2.into()];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec![std::i64::MAX.into(), 2.into(), DataType::None]].into()
+        );
+    }
+
     #[test]
     fn it_forwards_arithmetic_w_literals() {
         let number: DataType = 40.into();
-        let expression = ProjectExpression {
-            left: ProjectExpressionBase::Column(0),
-            right: ProjectExpressionBase::Literal(number),
-            op: ArithmeticOperator::Multiply,
-        };
+        let expression = ProjectExpression::new(
+            ArithmeticOperator::Multiply,
+            ProjectExpressionBase::Column(0),
+            ProjectExpressionBase::Literal(number),
+        );
 
         let mut p = setup_arithmetic(expression);
         let rec = vec![10.into(), 0.into()];
@@ -491,11 +738,11 @@ mod tests {
     fn it_forwards_arithmetic_w_only_literals() {
         let a: DataType = 80.into();
         let b: DataType = 40.into();
-        let expression = ProjectExpression {
-            left: ProjectExpressionBase::Literal(a),
-            right: ProjectExpressionBase::Literal(b),
-            op: ArithmeticOperator::Divide,
-        };
+        let expression = ProjectExpression::new(
+            ArithmeticOperator::Divide,
+            ProjectExpressionBase::Literal(a),
+            ProjectExpressionBase::Literal(b),
+        );
 
         let mut p = setup_arithmetic(expression);
         let rec = vec![0.into(), 0.into()];
@@ -505,6 +752,147 @@ mod tests {
         );
     }
 
+    #[test]
+    fn it_forwards_nested_arithmetic() {
+        // x * (1 - y)
+        let expression = ProjectExpression::Arithmetic {
+            op: ArithmeticOperator::Multiply,
+            left: Box::new(ProjectExpression::Base(ProjectExpressionBase::Column(0))),
+            right: Box::new(ProjectExpression::new(
+                ArithmeticOperator::Subtract,
+                ProjectExpressionBase::Literal(1.into()),
+                ProjectExpressionBase::Column(1),
+            )),
+        };
+
+        let mut p = setup_arithmetic(expression);
+        assert_eq!(p.node().description(true), "π[0, 1, 0 * ((lit: 1) - 1)]");
+        let rec = vec![10.into(), 3.into()];
+        assert_eq!(
+            p.narrow_one_row(rec, false),
+            vec![vec![10.into(), 3.into(), (-20).into()]].into()
+        );
+    }
+
+    #[test]
+    fn it_forwards_case() {
+        use crate::ops::filter::Value;
+        use nom_sql::Operator;
+
+        // CASE WHEN x > 5 THEN y ELSE 0 END
+        let expression = ProjectExpression::Case {
+            branches: vec![(
+                vec![(
+                    0,
+                    FilterCondition::Comparison(Operator::Greater, Value::Constant(5.into())),
+                )],
+                ProjectExpression::Base(ProjectExpressionBase::Column(1)),
+            )],
+            otherwise: Box::new(ProjectExpression::Base(ProjectExpressionBase::Literal(
+                0.into(),
+            ))),
+        };
+
+        let mut p = setup_arithmetic(expression);
+        assert_eq!(
+            p.narrow_one_row(vec![10.into(), 3.into()], false),
+            vec![vec![10.into(), 3.into(), 3.into()]].into()
+        );
+        assert_eq!(
+            p.narrow_one_row(vec![1.into(), 3.into()], false),
+            vec![vec![1.into(), 3.into(), 0.into()]].into()
+        );
+    }
+
+    #[test]
+    fn it_forwards_coalesce() {
+        let expression = ProjectExpression::Coalesce(vec![
+            ProjectExpression::Base(ProjectExpressionBase::Column(0)),
+            ProjectExpression::Base(ProjectExpressionBase::Column(1)),
+        ]);
+
+        let mut p = setup_arithmetic(expression);
+        assert_eq!(
+            p.narrow_one_row(vec![DataType::None, 3.into()], false),
+            vec![vec![DataType::None, 3.into(), 3.into()]].into()
+        );
+        assert_eq!(
+            p.narrow_one_row(vec![1.into(), 3.into()], false),
+            vec![vec![1.into(), 3.into(), 1.into()]].into()
+        );
+    }
+
+    #[test]
+    fn it_forwards_string_functions() {
+        let expression = ProjectExpression::Concat(vec![
+            ProjectExpression::Lower(Box::new(ProjectExpression::Base(
+                ProjectExpressionBase::Column(0),
+            ))),
+            ProjectExpression::Base(ProjectExpressionBase::Literal("-".into())),
+            ProjectExpression::Upper(Box::new(ProjectExpression::Base(
+                ProjectExpressionBase::Column(1),
+            ))),
+        ]);
+
+        let mut p = setup_arithmetic(expression);
+        assert_eq!(
+            p.node().description(true),
+            "π[0, 1, CONCAT(LOWER(0), (lit: \"-\"), UPPER(1))]"
+        );
+        assert_eq!(
+            p.narrow_one_row(vec!["Hello".into(), "World".into()], false),
+            vec![vec!["Hello".into(), "World".into(), "hello-WORLD".into()]].into()
+        );
+        assert_eq!(
+            p.narrow_one_row(vec!["Hello".into(), DataType::None], false),
+            vec![vec!["Hello".into(), DataType::None, DataType::None]].into()
+        );
+    }
+
+    #[test]
+    fn it_forwards_date_extraction() {
+        use chrono::NaiveDate;
+
+        let expression = ProjectExpression::Extract(
+            DatePart::Month,
+            Box::new(ProjectExpression::Base(ProjectExpressionBase::Column(0))),
+        );
+
+        let mut p = setup_arithmetic(expression);
+        let ts: DataType = NaiveDate::from_ymd(2019, 7, 15).and_hms(12, 30, 0).into();
+        assert_eq!(
+            p.narrow_one_row(vec![ts.clone(), 0.into()], false),
+            vec![vec![ts, 0.into(), 7.into()]].into()
+        );
+    }
+
+    #[test]
+    fn it_extracts_from_dates_and_times() {
+        use chrono::NaiveDate;
+
+        let extract = |part, value: DataType| {
+            ProjectExpression::Extract(
+                part,
+                Box::new(ProjectExpression::Base(ProjectExpressionBase::Literal(
+                    value,
+                ))),
+            )
+            .eval(&[])
+        };
+        let date = DataType::Date(NaiveDate::from_ymd(2019, 7, 15));
+        assert_eq!(extract(DatePart::Year, date.clone()), 2019.into());
+        assert_eq!(extract(DatePart::Day, date.clone()), 15.into());
+        assert_eq!(extract(DatePart::Hour, date), 0.into());
+
+        // -26:03:04.5
+        let time = DataType::Time(-(((26 * 60 + 3) * 60 + 4) * 1_000_000 + 500_000));
+        assert_eq!(extract(DatePart::Hour, time.clone()), (-26).into());
+        assert_eq!(extract(DatePart::Minute, time.clone()), (-3).into());
+        assert_eq!(extract(DatePart::Second, time.clone()), (-4).into());
+        assert_eq!(extract(DatePart::Day, time), DataType::None);
+        assert_eq!(extract(DatePart::Month, "July".into()), DataType::None);
+    }
+
     fn setup_query_through(
         mut state: Box<dyn State>,
         permutation: &[usize],
@@ -617,11 +1005,11 @@ mod tests {
     #[test]
     fn it_queries_through_w_arithmetic_and_literals() {
         let additional = Some(vec![DataType::Int(42)]);
-        let expressions = Some(vec![ProjectExpression {
-            left: ProjectExpressionBase::Column(0),
-            right: ProjectExpressionBase::Column(1),
-            op: ArithmeticOperator::Add,
-        }]);
+        let expressions = Some(vec![ProjectExpression::new(
+            ArithmeticOperator::Add,
+            ProjectExpressionBase::Column(0),
+            ProjectExpressionBase::Column(1),
+        )]);
 
         let state = Box::new(MemoryState::default());
         let (p, states) = setup_query_through(state, &[1], additional, expressions);
@@ -632,11 +1020,11 @@ mod tests {
     #[test]
     fn it_queries_through_w_arithmetic_and_literals_persistent() {
         let additional = Some(vec![DataType::Int(42)]);
-        let expressions = Some(vec![ProjectExpression {
-            left: ProjectExpressionBase::Column(0),
-            right: ProjectExpressionBase::Column(1),
-            op: ArithmeticOperator::Add,
-        }]);
+        let expressions = Some(vec![ProjectExpression::new(
+            ArithmeticOperator::Add,
+            ProjectExpressionBase::Column(0),
+            ProjectExpressionBase::Column(1),
+        )]);
 
         let state = Box::new(PersistentState::new(
             String::from("it_queries_through_w_arithmetic_and_literals_persistent"),
@@ -0,0 +1,649 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::mem;
+
+use crate::prelude::*;
+
+/// Kind of semi-join
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SemiJoinType {
+    /// Emit rows from the left view that match at least one row in the right view
+    Semi,
+    /// Emit rows from the left view that match no rows in the right view
+    Anti,
+    /// Emit rows from the left view whose join column is `NOT IN` the right view's join column,
+    /// which, unlike `Anti`, never holds if the right view has a `NULL` there, and only holds for
+    /// a `NULL` on the left if the right view is empty.
+    NotIn,
+}
+
+/// SemiJoin filters the rows of its left parent by whether any row in its right parent has the same
+/// value in the join column. Just like SQL's `=`, a `NULL` never matches anything.
+///
+/// Unlike a join, a semi-join never emits more than one row for each row in its left parent, no
+/// matter how many rows in the right parent match it, and it never emits any columns from the right
+/// parent. Changes on the right only produce output when the first row for a key appears, or the
+/// last one disappears.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SemiJoin {
+    left: IndexPair,
+    right: IndexPair,
+
+    // Key column in the left and right parents respectively
+    on: (usize, usize),
+
+    // Which columns of the left parent to emit
+    emit: Vec<usize>,
+
+    kind: SemiJoinType,
+}
+
+impl SemiJoin {
+    /// Create a new instance of SemiJoin
+    ///
+    /// `left` and `right` are the left and right parents respectively. `on` is a tuple specifying
+    /// the join columns: (left_parent_column, right_parent_column), and `emit` lists the columns
+    /// of the left parent to emit.
+    pub fn new(
+        left: NodeIndex,
+        right: NodeIndex,
+        kind: SemiJoinType,
+        on: (usize, usize),
+        emit: Vec<usize>,
+    ) -> Self {
+        Self {
+            left: left.into(),
+            right: right.into(),
+            on,
+            emit,
+            kind,
+        }
+    }
+
+    fn generate_row(&self, left: &[DataType]) -> Vec<DataType> {
+        self.emit.iter().map(|&col| left[col].clone()).collect()
+    }
+
+    /// Whether rows on the left are emitted when some row on the right matches them (if `matched`)
+    /// or when none does.
+    fn emits(&self, matched: bool) -> bool {
+        match self.kind {
+            SemiJoinType::Semi => matched,
+            SemiJoinType::Anti | SemiJoinType::NotIn => !matched,
+        }
+    }
+
+    /// Whether a `NOT IN` emits a row on the left with the join key `key`, given whether some row
+    /// on the right matches it, and how many rows on the right there are in total and with a
+    /// `NULL` join key.
+    fn not_in(key: &DataType, matched: bool, rows: usize, nulls: usize) -> bool {
+        if *key == DataType::None {
+            rows == 0
+        } else {
+            !matched && nulls == 0
+        }
+    }
+
+    /// How many rows the right parent has in total, and how many of them have a `NULL` join key.
+    ///
+    /// Returns `None` if the right parent's state is not available here.
+    fn right_summary(&self, state: &StateMap) -> Option<(usize, usize)> {
+        let right = state.get(*self.right)?;
+        match right.lookup(&[self.on.1], &KeyType::Single(&DataType::None)) {
+            LookupResult::Some(nulls) => Some((right.rows(), nulls.len())),
+            LookupResult::Missing => None,
+        }
+    }
+
+    /// Processes a batch of records from the right parent of a `NOT IN`.
+    ///
+    /// A `NULL` appearing on (or disappearing from) the right, or the right becoming empty (or
+    /// non-empty), can change whether any row on the left is emitted, so those batches re-evaluate
+    /// every row on the left. Other batches only affect the rows on the left with their keys.
+    fn not_in_from_right(
+        &self,
+        rs: Vec<Record>,
+        nodes: &DomainNodes,
+        state: &StateMap,
+    ) -> Option<ProcessingResult> {
+        let (rows, nulls) = self.right_summary(state)?;
+        let sign = |r: &Record| if r.is_positive() { 1 } else { -1 };
+        let mut deltas: HashMap<DataType, isize> = HashMap::new();
+        for r in &rs {
+            *deltas.entry(r[self.on.1].clone()).or_insert(0) += sign(r);
+        }
+        let rows_delta: isize = deltas.values().sum();
+        let nulls_delta = deltas.get(&DataType::None).cloned().unwrap_or(0);
+        let old_rows = (rows as isize - rows_delta) as usize;
+        let old_nulls = (nulls as isize - nulls_delta) as usize;
+
+        if (old_rows == 0) == (rows == 0) && (old_nulls == 0) == (nulls == 0) {
+            // with a NULL on the right, nothing on the left is emitted before or after the batch
+            return if nulls == 0 {
+                None
+            } else {
+                Some(ProcessingResult::default())
+            };
+        }
+
+        let left = state.get(*self.left)?;
+        let mut ret = Vec::new();
+        for row in left.cloned_records() {
+            let key = &row[self.on.0];
+            let count = match self
+                .lookup(
+                    *self.right,
+                    &[self.on.1],
+                    &KeyType::Single(key),
+                    nodes,
+                    state,
+                )
+                .and_then(|others| others)
+            {
+                Some(others) => others.count() as isize,
+                None => continue,
+            };
+            let old_count = count - deltas.get(key).cloned().unwrap_or(0);
+            let before = Self::not_in(key, old_count > 0, old_rows, old_nulls);
+            let after = Self::not_in(key, count > 0, rows, nulls);
+            if before != after {
+                ret.push((self.generate_row(&row), after).into());
+            }
+        }
+        Some(ProcessingResult {
+            results: ret.into(),
+            ..Default::default()
+        })
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self.kind {
+            SemiJoinType::Semi => "∃",
+            SemiJoinType::Anti => "∄",
+            SemiJoinType::NotIn => "∉",
+        }
+    }
+}
+
+/// Find where the run of records with the same value in `key` as the record at `at` ends.
+fn end_of_key(rs: &[Record], at: usize, key: usize) -> usize {
+    rs[at..]
+        .iter()
+        .position(|r| r[key] != rs[at][key])
+        .map(|p| at + p)
+        .unwrap_or_else(|| rs.len())
+}
+
+impl Ingredient for SemiJoin {
+    fn take(&mut self) -> NodeOperator {
+        Clone::clone(self).into()
+    }
+
+    fn ancestors(&self) -> Vec<NodeIndex> {
+        vec![self.left.as_global(), self.right.as_global()]
+    }
+
+    fn is_join(&self) -> bool {
+        true
+    }
+
+    fn must_replay_among(&self) -> Option<HashSet<NodeIndex>> {
+        // only the left parent has the rows we emit
+        Some(Some(self.left.as_global()).into_iter().collect())
+    }
+
+    fn on_connected(&mut self, _g: &Graph) {}
+
+    fn on_commit(&mut self, _: NodeIndex, remap: &HashMap<NodeIndex, IndexPair>) {
+        self.left.remap(remap);
+        self.right.remap(remap);
+    }
+
+    fn on_input(
+        &mut self,
+        _: &mut dyn Executor,
+        from: LocalNodeIndex,
+        rs: Records,
+        replay_key_cols: Option<&[usize]>,
+        nodes: &DomainNodes,
+        state: &StateMap,
+    ) -> ProcessingResult {
+        let mut misses = Vec::new();
+        let mut lookups = Vec::new();
+
+        if rs.is_empty() {
+            return ProcessingResult {
+                results: rs,
+                ..Default::default()
+            };
+        }
+
+        let from_left = from == *self.left;
+        let (other, from_key, other_key) = if from_left {
+            (*self.right, self.on.0, self.on.1)
+        } else {
+            (*self.left, self.on.1, self.on.0)
+        };
+
+        let replay_key_cols: Option<Vec<usize>> = replay_key_cols.map(|cols| {
+            cols.iter()
+                .map(|&col| match self.emit[col] {
+                    l if from_left => l,
+                    l if l == self.on.0 => self.on.1,
+                    _ => {
+                        // we're getting a partial replay, but the replay key doesn't exist in the
+                        // parent we're getting the replay from?!
+                        unreachable!()
+                    }
+                })
+                .collect()
+        });
+
+        // process all the records with the same join key together, so that we look up each key
+        // only once, and so that we can tell how many rows on the right a key had before the batch
+        let mut rs: Vec<_> = rs.into();
+        rs.sort_by(|a: &Record, b: &Record| a[from_key].cmp(&b[from_key]));
+
+        // whether a `NOT IN` excludes everything on the left because of a NULL on the right, and
+        // whether it lets NULLs on the left through because the right is empty
+        let (right_nulls, right_empty) = match self.kind {
+            SemiJoinType::NotIn if from_left => match self.right_summary(state) {
+                Some((rows, nulls)) => (nulls != 0, rows == 0),
+                None => (false, false),
+            },
+            SemiJoinType::NotIn => {
+                if let Some(result) = self.not_in_from_right(rs.clone(), nodes, state) {
+                    return result;
+                }
+                (false, false)
+            }
+            _ => (false, false),
+        };
+
+        let mut ret: Vec<Record> = Vec::new();
+        let mut at = 0;
+        while at != rs.len() {
+            let start = at;
+            at = end_of_key(&rs, at, from_key);
+            let key = rs[start][from_key].clone();
+
+            if key == DataType::None || right_nulls {
+                // NULLs match nothing, so rows on the right with a NULL key never change the
+                // output, and only `NOT IN` treats rows on the left with a NULL key specially
+                if from_left {
+                    let emits = match self.kind {
+                        SemiJoinType::NotIn => key == DataType::None && right_empty,
+                        _ => self.emits(false),
+                    };
+                    if emits {
+                        ret.extend(
+                            rs[start..at].iter().map(|r| -> Record {
+                                (self.generate_row(r), r.is_positive()).into()
+                            }),
+                        );
+                    }
+                }
+                continue;
+            }
+
+            if from_left {
+                let matched = match self
+                    .lookup(other, &[other_key], &KeyType::Single(&key), nodes, state)
+                    .unwrap()
+                {
+                    Some(mut others) => others.next().is_some(),
+                    None => {
+                        // we missed in the other side!
+                        misses.extend((start..at).map(|i| Miss {
+                            on: other,
+                            lookup_idx: vec![other_key],
+                            lookup_cols: vec![from_key],
+                            replay_cols: replay_key_cols.clone(),
+                            // NOTE: we're stealing data here!
+                            record: mem::replace(&mut *rs[i], Vec::new()),
+                        }));
+                        continue;
+                    }
+                };
+
+                if replay_key_cols.is_some() {
+                    lookups.push(Lookup {
+                        on: other,
+                        cols: vec![other_key],
+                        key: vec![key],
+                    });
+                }
+
+                if self.emits(matched) {
+                    ret.extend(
+                        rs[start..at]
+                            .iter()
+                            .map(|r| -> Record { (self.generate_row(r), r.is_positive()).into() }),
+                    );
+                }
+                continue;
+            }
+
+            // the right parent's state already reflects this batch, so we can work out how many
+            // rows it had for this key before.
+            let new_rc = match self
+                .lookup(from, &[from_key], &KeyType::Single(&key), nodes, state)
+                .unwrap()
+            {
+                Some(ours) => ours.count(),
+                None => {
+                    // we got a record, but missed on its own side; clearly, a replay is needed
+                    misses.extend((start..at).map(|i| Miss {
+                        on: from,
+                        lookup_idx: vec![from_key],
+                        lookup_cols: vec![from_key],
+                        replay_cols: replay_key_cols.clone(),
+                        // NOTE: we're stealing data here!
+                        record: mem::replace(&mut *rs[i], Vec::new()),
+                    }));
+                    continue;
+                }
+            };
+            if replay_key_cols.is_some() {
+                lookups.push(Lookup {
+                    on: from,
+                    cols: vec![from_key],
+                    key: vec![key.clone()],
+                });
+            }
+            let old_rc = rs[start..at].iter().fold(new_rc as isize, |rc, r| {
+                if r.is_positive() {
+                    rc - 1
+                } else {
+                    rc + 1
+                }
+            });
+            if (old_rc == 0) == (new_rc == 0) {
+                // the key was matched before iff it is matched now, so nothing changes
+                continue;
+            }
+
+            let others = match self
+                .lookup(other, &[other_key], &KeyType::Single(&key), nodes, state)
+                .unwrap()
+            {
+                Some(others) => others,
+                None => {
+                    // we missed in the other side!
+                    misses.extend((start..at).map(|i| Miss {
+                        on: other,
+                        lookup_idx: vec![other_key],
+                        lookup_cols: vec![from_key],
+                        replay_cols: replay_key_cols.clone(),
+                        // NOTE: we're stealing data here!
+                        record: mem::replace(&mut *rs[i], Vec::new()),
+                    }));
+                    continue;
+                }
+            };
+            if replay_key_cols.is_some() {
+                lookups.push(Lookup {
+                    on: other,
+                    cols: vec![other_key],
+                    key: vec![key],
+                });
+            }
+
+            // the rows on the left with this key start (or stop) being emitted
+            let positive = self.emits(new_rc != 0);
+            ret.extend(others.map(|r| -> Record { (self.generate_row(&r), positive).into() }));
+        }
+
+        ProcessingResult {
+            results: ret.into(),
+            lookups,
+            misses,
+        }
+    }
+
+    fn suggest_indexes(&self, _this: NodeIndex) -> HashMap<NodeIndex, Vec<usize>> {
+        vec![
+            (self.left.as_global(), vec![self.on.0]),
+            (self.right.as_global(), vec![self.on.1]),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    fn resolve(&self, col: usize) -> Option<Vec<(NodeIndex, usize)>> {
+        Some(vec![(self.left.as_global(), self.emit[col])])
+    }
+
+    fn description(&self, detailed: bool) -> String {
+        if !detailed {
+            return String::from(self.symbol());
+        }
+
+        let emit = self
+            .emit
+            .iter()
+            .map(|col| format!("{}:{}", self.left.as_global().index(), col))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        format!(
+            "[{}] {}:{} {} {}:{}",
+            emit,
+            self.left.as_global().index(),
+            self.on.0,
+            self.symbol(),
+            self.right.as_global().index(),
+            self.on.1
+        )
+    }
+
+    fn is_selective(&self) -> bool {
+        true
+    }
+
+    fn requires_full_materialization(&self) -> bool {
+        // a partial state cannot tell whether the right parent is empty or has NULLs anywhere
+        self.kind == SemiJoinType::NotIn
+    }
+
+    fn parent_columns(&self, col: usize) -> Vec<(NodeIndex, Option<usize>)> {
+        let pcol = self.emit[col];
+        if pcol == self.on.0 {
+            // Join column comes from both parents
+            vec![
+                (self.left.as_global(), Some(self.on.0)),
+                (self.right.as_global(), Some(self.on.1)),
+            ]
+        } else {
+            vec![(self.left.as_global(), Some(pcol))]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops;
+
+    fn setup(kind: SemiJoinType) -> (ops::test::MockGraph, IndexPair, IndexPair) {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1"]);
+
+        let j = SemiJoin::new(l.as_global(), r.as_global(), kind, (0, 0), vec![0, 1]);
+
+        g.set_op("semijoin", &["j0", "j1"], j, false);
+        (g, l, r)
+    }
+
+    #[test]
+    fn it_describes() {
+        let (j, l, r) = setup(SemiJoinType::Semi);
+        assert_eq!(
+            j.node().description(true),
+            format!("[{}:0, {}:1] {}:0 ∃ {}:0", l, l, l, r)
+        );
+        let (j, l, r) = setup(SemiJoinType::Anti);
+        assert_eq!(
+            j.node().description(true),
+            format!("[{}:0, {}:1] {}:0 ∄ {}:0", l, l, l, r)
+        );
+        let (j, l, r) = setup(SemiJoinType::NotIn);
+        assert_eq!(
+            j.node().description(true),
+            format!("[{}:0, {}:1] {}:0 ∉ {}:0", l, l, l, r)
+        );
+    }
+
+    #[test]
+    fn it_works_with_semi_joins() {
+        let (mut j, l, r) = setup(SemiJoinType::Semi);
+        let l_a1 = vec![1.into(), "a".into()];
+        let l_b1 = vec![1.into(), "b".into()];
+        let l_c2 = vec![2.into(), "c".into()];
+
+        let r_x1 = vec![1.into(), "x".into()];
+        let r_y1 = vec![1.into(), "y".into()];
+
+        // nothing on the right matches yet
+        j.seed(l, l_a1.clone());
+        let rs = j.one_row(l, l_a1.clone(), false);
+        assert!(rs.is_empty());
+
+        // the first match on the right lets every left row with that key through
+        j.seed(l, l_b1.clone());
+        j.seed(r, r_x1.clone());
+        let rs = j.one_row(r, r_x1.clone(), false);
+        assert_eq!(rs.len(), 2);
+        assert!(rs.has_positive(&l_a1[..]));
+        assert!(rs.has_positive(&l_b1[..]));
+
+        // further matches don't emit left rows again
+        j.seed(r, r_y1.clone());
+        let rs = j.one_row(r, r_y1.clone(), false);
+        assert!(rs.is_empty());
+
+        // new left rows that are matched come through once
+        j.seed(l, l_c2.clone());
+        let rs = j.one_row(l, l_c2.clone(), false);
+        assert!(rs.is_empty());
+        let rs = j.one_row(l, (l_a1.clone(), false), false);
+        assert_eq!(rs, vec![(l_a1.clone(), false)].into());
+
+        // removing one of two matches changes nothing, removing the last one revokes the rows
+        j.unseed(r);
+        j.seed(r, r_y1.clone());
+        let rs = j.one_row(r, (r_x1.clone(), false), false);
+        assert!(rs.is_empty());
+        j.unseed(r);
+        let rs = j.one_row(r, (r_y1.clone(), false), false);
+        assert!(rs.has_negative(&l_b1[..]));
+    }
+
+    #[test]
+    fn it_works_with_anti_joins() {
+        let (mut j, l, r) = setup(SemiJoinType::Anti);
+        let l_a1 = vec![1.into(), "a".into()];
+        let l_c2 = vec![2.into(), "c".into()];
+
+        let r_x1 = vec![1.into(), "x".into()];
+        let r_y1 = vec![1.into(), "y".into()];
+
+        // unmatched left rows come through
+        j.seed(l, l_a1.clone());
+        let rs = j.one_row(l, l_a1.clone(), false);
+        assert_eq!(rs, vec![(l_a1.clone(), true)].into());
+
+        // and are revoked by the first match
+        j.seed(r, r_x1.clone());
+        let rs = j.one_row(r, r_x1.clone(), false);
+        assert_eq!(rs, vec![(l_a1.clone(), false)].into());
+
+        // a match and its removal in the same batch cancel out
+        j.seed(l, l_c2.clone());
+        let rs = j.one(r, vec![(r_y1.clone(), true), (r_y1.clone(), false)], false);
+        assert!(rs.is_empty());
+
+        // once the last match is gone, the left row comes back
+        j.unseed(r);
+        let rs = j.one_row(r, (r_x1.clone(), false), false);
+        assert_eq!(rs, vec![(l_a1.clone(), true)].into());
+    }
+
+    #[test]
+    fn it_ignores_nulls_in_anti_joins() {
+        let (mut j, l, r) = setup(SemiJoinType::Anti);
+        let l_n = vec![DataType::None, "n".into()];
+        let r_n = vec![DataType::None, "x".into()];
+
+        // NULLs never match, not even each other
+        j.seed(r, r_n.clone());
+        let rs = j.one_row(r, r_n.clone(), false);
+        assert!(rs.is_empty());
+        j.seed(l, l_n.clone());
+        let rs = j.one_row(l, l_n.clone(), false);
+        assert_eq!(rs, vec![(l_n.clone(), true)].into());
+    }
+
+    #[test]
+    fn it_works_with_not_in() {
+        let (mut j, l, r) = setup(SemiJoinType::NotIn);
+        let l_a1 = vec![1.into(), "a".into()];
+        let l_c3 = vec![3.into(), "c".into()];
+        let l_n = vec![DataType::None, "n".into()];
+
+        let r_x2 = vec![2.into(), "x".into()];
+        let r_n = vec![DataType::None, "y".into()];
+
+        // anything, even NULL, is NOT IN an empty right side
+        j.seed(l, l_a1.clone());
+        let rs = j.one_row(l, l_a1.clone(), false);
+        assert_eq!(rs, vec![(l_a1.clone(), true)].into());
+        j.seed(l, l_n.clone());
+        let rs = j.one_row(l, l_n.clone(), false);
+        assert_eq!(rs, vec![(l_n.clone(), true)].into());
+
+        // once the right side has rows, NULL on the left is no longer NOT IN it
+        j.seed(r, r_x2.clone());
+        let rs = j.one_row(r, r_x2.clone(), false);
+        assert_eq!(rs, vec![(l_n.clone(), false)].into());
+
+        // and a NULL on the right excludes everything
+        j.seed(r, r_n.clone());
+        let rs = j.one_row(r, r_n.clone(), false);
+        assert_eq!(rs, vec![(l_a1.clone(), false)].into());
+        j.seed(l, l_c3.clone());
+        let rs = j.one_row(l, l_c3.clone(), false);
+        assert!(rs.is_empty());
+
+        // until it goes away again
+        j.unseed(r);
+        j.seed(r, r_x2.clone());
+        let rs = j.one_row(r, (r_n.clone(), false), false);
+        assert_eq!(rs.len(), 2);
+        assert!(rs.has_positive(&l_a1[..]));
+        assert!(rs.has_positive(&l_c3[..]));
+    }
+
+    #[test]
+    fn it_suggests_indices() {
+        let me = 2.into();
+        let (g, l, r) = setup(SemiJoinType::Semi);
+        let hm: HashMap<_, _> = vec![(l.as_global(), vec![0]), (r.as_global(), vec![0])]
+            .into_iter()
+            .collect();
+        assert_eq!(g.node().suggest_indexes(me), hm);
+    }
+
+    #[test]
+    fn it_resolves() {
+        let (g, l, r) = setup(SemiJoinType::Anti);
+        assert_eq!(g.node().resolve(0), Some(vec![(l.as_global(), 0)]));
+        assert_eq!(g.node().resolve(1), Some(vec![(l.as_global(), 1)]));
+        assert_eq!(
+            g.node().parent_columns(0),
+            vec![(l.as_global(), Some(0)), (r.as_global(), Some(0))]
+        );
+    }
+}
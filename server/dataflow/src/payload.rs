@@ -2,13 +2,17 @@ use petgraph;
 use serde::{Deserialize, Serialize};
 
 use crate::domain;
+use crate::node::special::WritePaths;
 use crate::prelude::*;
+use nom_sql::ColumnSpecification;
 use noria;
 use noria::internal::LocalOrNot;
+use noria::{TableOperation, TransactionTag, WriteToken};
 
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::net::SocketAddr;
+use std::ops::Range;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ReplayPathSegment {
@@ -85,13 +89,28 @@ pub enum Packet {
     Input {
         inner: LocalOrNot<Input>,
         src: Option<SourceChannelIdentifier>,
-        senders: Vec<SourceChannelIdentifier>,
+        /// The clients whose inputs were merged into this one, along with the range of operations
+        /// in `inner` that came from each of them.
+        senders: Vec<(SourceChannelIdentifier, Range<usize>)>,
     },
 
     /// Regular data-flow update.
     Message {
         link: Link,
         data: Records,
+        /// The transaction whose writes this update carries, if any.
+        ///
+        /// Updates that belong to a transaction are forwarded along every edge of the dataflow,
+        /// even if they end up empty, so that readers can tell when they have seen all of them.
+        txn: Option<TransactionTag>,
+        /// The base node shard that emitted this update, and the sequence number it assigned to
+        /// the write that caused it, if that write is tracked.
+        ///
+        /// Only writes that a client asked for a token for, and those of transactions, are
+        /// tracked. Like updates that belong to a transaction, their updates are forwarded along
+        /// every edge even if they end up empty, so that readers can tell which writes they have
+        /// seen all the effects of.
+        write: Option<(NodeIndex, usize, u64)>,
     },
 
     /// Update that is part of a tagged data-flow replay path.
@@ -139,6 +158,7 @@ pub enum Packet {
         node: LocalNodeIndex,
         field: String,
         default: DataType,
+        spec: Option<ColumnSpecification>,
     },
 
     /// Drops an existing column from a `Base` node.
@@ -147,6 +167,92 @@ pub enum Packet {
         column: usize,
     },
 
+    /// Set the domains that keep backups of the base nodes of this domain.
+    ///
+    /// Each shard ships every write to its base nodes to the same shard of each backup, and only
+    /// acknowledges the write once every backup has applied it. Backups that were not in the
+    /// previous set are first sent all the rows the base nodes hold.
+    UpdateBackups {
+        backups: Vec<domain::Index>,
+    },
+
+    /// A write shipped to a backup, which it confirms with `BackupApplied` once it has applied it.
+    BackupInput {
+        seq: u64,
+        inner: Input,
+    },
+
+    /// Tell a domain that the given backup has applied every write it was shipped up to `seq`.
+    BackupApplied {
+        backup: domain::Index,
+        seq: u64,
+    },
+
+    /// Fill the given base node with rows of the base it replaces once it is ready.
+    RestoreBase {
+        node: LocalNodeIndex,
+        rows: Vec<Vec<DataType>>,
+    },
+
+    /// Send all the rows of the given base node to the shards of the base that takes over from
+    /// it, split into `shards` shards by `column`, and from then on pass every write the base
+    /// applies on to them as well.
+    ShipBase {
+        node: LocalNodeIndex,
+        to: (domain::Index, LocalNodeIndex),
+        column: usize,
+        shards: usize,
+    },
+
+    /// Rows of the base the given base node takes over from, along with the number of the last
+    /// tracked write that base processed. Acknowledged on the control reply channel.
+    ShippedBase {
+        node: LocalNodeIndex,
+        rows: Vec<Vec<DataType>>,
+        last_write: u64,
+    },
+
+    /// Stop applying the writes the given base node receives, and pass them on to the base it
+    /// ships its writes to instead, replying to the clients with the results that come back.
+    ///
+    /// The first time, the base reports a token covering every write it passed on so far on the
+    /// control reply channel. If `hold` is set, the replies to the writes it passes on are held
+    /// back until it is told otherwise.
+    ForwardBase {
+        node: LocalNodeIndex,
+        hold: bool,
+    },
+
+    /// A write that a base passed on to the given shard of the base that takes over from it.
+    ///
+    /// If `id` is set, the base that passed it on is sent the results with `ForwardedResult`.
+    ForwardedInput {
+        from: (domain::Index, usize),
+        id: Option<u64>,
+        inner: Input,
+    },
+
+    /// The results of a write passed on with `ForwardedInput` at the given shard.
+    ForwardedResult {
+        id: u64,
+        shard: usize,
+        result: noria::WriteResult,
+    },
+
+    /// Report on the control reply channel once the given reader reflects all the writes covered
+    /// by `token`.
+    AwaitApplied {
+        node: LocalNodeIndex,
+        token: WriteToken,
+    },
+
+    /// Serve the reads of the readers with the given global addresses from the given reader from
+    /// now on.
+    AliasReader {
+        node: LocalNodeIndex,
+        aliases: Vec<NodeIndex>,
+    },
+
     /// Update Egress node.
     UpdateEgress {
         node: LocalNodeIndex,
@@ -224,8 +330,104 @@ pub enum Packet {
     /// Argument specifies if we wish to get the full state size or just the partial nodes.
     GetStatistics,
 
+    /// Hand this shard over to a copy of it on another worker.
+    ///
+    /// If the shard is in the middle of a replay, a transaction, or anything else that it cannot
+    /// hand over, it carries on and reports `MovedOut(None)` on the control reply channel.
+    /// Otherwise it reports everything the copy needs, keeps every packet it receives from then on
+    /// until it is told to `Relay` them, and writes are acknowledged through `alias`.
+    MoveOut {
+        alias: domain::Index,
+    },
+
+    /// Take over from the copy of this shard that moved out, once `senders` shards have sent
+    /// `Rerouted`. Until then, only what the old copy passes on is handled. `moves` lists the
+    /// shards that moved along with this one, and the addresses of the workers they moved to.
+    MoveIn {
+        moved: Box<domain::MovedShard>,
+        senders: usize,
+        moves: Vec<(ReplicaAddr, SocketAddr)>,
+    },
+
+    /// Send `Rerouted` to each of the given shards, which moved to the workers at the given
+    /// addresses, and send to them there from then on.
+    Reroute {
+        moves: Vec<(ReplicaAddr, SocketAddr)>,
+    },
+
+    /// The last packet a shard sends to a shard that moved before it sends to the new copy at
+    /// `to` instead.
+    Rerouted {
+        to: SocketAddr,
+    },
+
+    /// Send `Rerouted` to each of the given shards, which moved along with this one, and then
+    /// pass the packets kept since `MoveOut` on to the copy that took over, along with any that
+    /// arrive from now on.
+    Relay {
+        moves: Vec<(ReplicaAddr, SocketAddr)>,
+    },
+
+    /// Drop the writes that clients send to this shard, which moved out, from now on, and report
+    /// `RelayRetired` on the control reply channel once the copy that took over has applied every
+    /// write passed on to it before. The shard can then be told to `Quit`.
+    RetireRelay,
+
+    /// A packet that a shard that moved out passed on to the copy that took over from it.
+    Relayed {
+        inner: Box<Packet>,
+    },
+
+    /// Once the given node has applied the writes covered by `after`, copy all the rows in its
+    /// state, and report on the control reply channel whether it could. The copy can then be
+    /// read a page at a time with `SnapshotPage`.
+    PinSnapshot {
+        node: LocalNodeIndex,
+        id: u64,
+        after: WriteToken,
+    },
+
+    /// Send up to `rows` more rows of the given pinned snapshot on the control reply channel.
+    SnapshotPage {
+        id: u64,
+        rows: usize,
+    },
+
     /// Ask domain to log its state size
     UpdateStateSize,
+
+    /// Check whether the given base node can apply the operations of a transaction, and report
+    /// the outcome of each operation on the control reply channel.
+    ///
+    /// If all of them can be applied, the base node holds on to them, and holds back any other
+    /// writes to it, until it is told to either commit or abort the transaction.
+    PrepareTransaction {
+        node: LocalNodeIndex,
+        txn: TransactionTag,
+        data: Vec<TableOperation>,
+    },
+
+    /// Apply the operations of the transaction the given base node last prepared.
+    ///
+    /// If `hold` is set, the base node keeps holding back other writes afterwards, until it is
+    /// told to abort the (now empty) transaction.
+    CommitTransaction {
+        node: LocalNodeIndex,
+        hold: bool,
+    },
+
+    /// Discard the operations of the transaction the given base node last prepared, if any.
+    AbortTransaction {
+        node: LocalNodeIndex,
+    },
+
+    /// Tell the given reader how many updates reach it for every update emitted by each base, and
+    /// which transactions it can count on receiving all the updates of.
+    UpdateWritePaths {
+        node: LocalNodeIndex,
+        paths: HashMap<NodeIndex, WritePaths>,
+        first_txn: u64,
+    },
 }
 
 impl Packet {
@@ -281,6 +483,32 @@ impl Packet {
         }
     }
 
+    /// The transaction this update is part of, if any.
+    pub(crate) fn txn(&self) -> Option<&TransactionTag> {
+        match *self {
+            Packet::Message { ref txn, .. } => txn.as_ref(),
+            Packet::Input { ref inner, .. } => unsafe { inner.deref() }.txn.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The write to a base node that this packet carries, if any.
+    pub(crate) fn input(&self) -> Option<&Input> {
+        match *self {
+            Packet::Input { ref inner, .. } => Some(unsafe { inner.deref() }),
+            Packet::ForwardedInput { ref inner, .. } => Some(inner),
+            _ => None,
+        }
+    }
+
+    /// The write that caused this update, if any.
+    pub(crate) fn write(&self) -> Option<(NodeIndex, usize, u64)> {
+        match *self {
+            Packet::Message { write, .. } => write,
+            _ => None,
+        }
+    }
+
     pub(crate) fn is_regular(&self) -> bool {
         match *self {
             Packet::Message { .. } => true,
@@ -308,9 +536,16 @@ impl Packet {
 
     pub(crate) fn clone_data(&self) -> Self {
         match *self {
-            Packet::Message { link, ref data } => Packet::Message {
+            Packet::Message {
+                link,
+                ref data,
+                ref txn,
+                write,
+            } => Packet::Message {
                 link,
                 data: data.clone(),
+                txn: txn.clone(),
+                write,
             },
             Packet::ReplayPiece {
                 link,
@@ -369,6 +604,24 @@ pub enum ControlReplyPacket {
         HashMap<petgraph::graph::NodeIndex, noria::debug::stats::NodeStats>,
     ),
     Booted(usize, SocketAddr),
+    /// The nodes of a shard that moved out and what a copy of it needs to take over, or `None` if
+    /// the shard could not move out yet
+    MovedOut(Option<(DomainNodes, Box<domain::MovedShard>)>),
+    /// A shard that moved in has heard from every shard that sends to it
+    MovedIn,
+    /// A shard that moved out no longer passes on writes, and the copy applied those it did
+    RelayRetired,
+    /// Whether a snapshot could be pinned, which it cannot if the node is not fully materialized
+    SnapshotPinned(bool),
+    /// The next rows of a pinned snapshot and whether they are the last, or `None` if the
+    /// snapshot has expired
+    SnapshotPage(Option<(Vec<Vec<DataType>>, bool)>),
+    /// The outcome of each operation of a transaction at the given shard of a base node
+    Prepared(NodeIndex, usize, Vec<noria::OperationResult>),
+    /// The token covering the writes of a committed transaction at a base node shard
+    Committed(noria::WriteToken),
+    /// The token covering the writes a base node shard passed on before it started forwarding
+    Forwarded(noria::WriteToken),
 }
 
 impl ControlReplyPacket {
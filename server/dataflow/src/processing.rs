@@ -95,7 +95,7 @@ pub(crate) enum ReplayContext<'a> {
 }
 
 impl<'a> ReplayContext<'a> {
-    fn key(&self) -> Option<&'a [usize]> {
+    pub(crate) fn key(&self) -> Option<&'a [usize]> {
         if let ReplayContext::Partial { key_cols, .. } = *self {
             Some(key_cols)
         } else {
@@ -148,6 +148,9 @@ where
     ///    𝛴    |  Sum
     ///    ⋈    |  Join
     ///    ⋉    |  Left join
+    ///    ⟗    |  Full outer join
+    ///    ∃    |  Semi-join
+    ///    ∄    |  Anti-join
     ///    ⋃    |  Union
     ///    σ    |  Filter
     ///    π    |  Projection
@@ -275,7 +278,8 @@ where
         false
     }
 
-    /// Returns true if this operator requires a full materialization
+    /// Returns true if this operator requires a full materialization (of itself or, if it is not
+    /// materialized, of any materialization that is replayed through it)
     fn requires_full_materialization(&self) -> bool {
         false
     }
@@ -7,11 +7,13 @@ use std::rc::Rc;
 use crate::column::Column;
 use crate::{FlowNode, MirNodeRef};
 use common::DataType;
+use dataflow::node::special::RangeParameter;
 use dataflow::ops;
 use dataflow::ops::filter::FilterCondition;
 use dataflow::ops::grouped::aggregate::Aggregation as AggregationKind;
 use dataflow::ops::grouped::extremum::Extremum as ExtremumKind;
 use dataflow::ops::grouped::filteraggregate::FilterAggregation as FilterAggregationKind;
+use dataflow::ops::project::ProjectExpression;
 use std::collections::HashMap;
 
 /// Helper enum to avoid having separate `make_aggregation_node` and `make_extremum_node` functions
@@ -72,6 +74,7 @@ impl MirNode {
             MirNodeType::Base {
                 ref column_specs,
                 ref keys,
+                ref unique_keys,
                 ..
             } => {
                 let new_column_specs: Vec<(ColumnSpecification, Option<usize>)> = column_specs
@@ -98,6 +101,7 @@ impl MirNode {
                 let new_inner = MirNodeType::Base {
                     column_specs: new_column_specs,
                     keys: keys.clone(),
+                    unique_keys: unique_keys.clone(),
                     adapted_over: Some(BaseNodeAdaptation {
                         over: node.clone(),
                         columns_added: added_cols.into_iter().cloned().collect(),
@@ -184,10 +188,48 @@ impl MirNode {
         }
     }
 
+    /// Captures the parts of this node that building other queries on top of it can change, so
+    /// that they can be put back with `restore_state` if those queries are abandoned.
+    pub fn save_state(&mut self) -> MirNodeState {
+        MirNodeState {
+            columns: self.columns.clone(),
+            inner_columns: self
+                .inner
+                .column_lists()
+                .into_iter()
+                .map(|c| c.clone())
+                .collect(),
+            ancestors: self.ancestors.clone(),
+            children: self.children.clone(),
+            flow_node: self.flow_node.clone(),
+        }
+    }
+
+    /// Puts back the state captured by an earlier call to `save_state`.
+    pub fn restore_state(&mut self, state: MirNodeState) {
+        self.columns = state.columns;
+        for (cols, saved) in self
+            .inner
+            .column_lists()
+            .into_iter()
+            .zip(state.inner_columns)
+        {
+            *cols = saved;
+        }
+        self.ancestors = state.ancestors;
+        self.children = state.children;
+        self.flow_node = state.flow_node;
+    }
+
     pub fn add_column(&mut self, c: Column) {
         match self.inner {
-            // the aggregation column must always be the last column
-            MirNodeType::Aggregation { .. } | MirNodeType::FilterAggregation { .. } => {
+            // the aggregation (or page number) column must always be the last column, and
+            // aggregations also keep their state columns right before it
+            MirNodeType::Aggregation { ref kind, .. } => {
+                let pos = self.columns.len() - 1 - kind.state_columns().len();
+                self.columns.insert(pos, c.clone());
+            }
+            MirNodeType::FilterAggregation { .. } | MirNodeType::Paginate { .. } => {
                 let pos = self.columns.len() - 1;
                 self.columns.insert(pos, c.clone());
             }
@@ -360,8 +402,13 @@ impl MirNode {
                     columns.push(on.clone());
                 }
             }
-            MirNodeType::Project { ref emit, .. } => {
-                for c in emit {
+            MirNodeType::Project {
+                ref emit,
+                ref expressions,
+                ..
+            } => {
+                let expression_columns = expressions.iter().flat_map(|&(_, _, ref cs)| cs);
+                for c in emit.iter().chain(expression_columns) {
                     if !columns.contains(&c) {
                         columns.push(c.clone());
                     }
@@ -396,6 +443,15 @@ pub struct BaseNodeAdaptation {
     pub columns_removed: Vec<ColumnSpecification>,
 }
 
+/// The state of a `MirNode` as captured by `MirNode::save_state`.
+pub struct MirNodeState {
+    columns: Vec<Column>,
+    inner_columns: Vec<Vec<Column>>,
+    ancestors: Vec<MirNodeRef>,
+    children: Vec<MirNodeRef>,
+    flow_node: Option<FlowNode>,
+}
+
 pub enum MirNodeType {
     /// over column, group_by columns
     Aggregation {
@@ -403,10 +459,12 @@ pub enum MirNodeType {
         group_by: Vec<Column>,
         kind: AggregationKind,
     },
-    /// column specifications, keys (non-compound), tx flag, adapted base
+    /// column specifications, keys (non-compound), unique keys over several columns, tx flag,
+    /// adapted base
     Base {
         column_specs: Vec<(ColumnSpecification, Option<usize>)>,
         keys: Vec<Column>,
+        unique_keys: Vec<Vec<Column>>,
         adapted_over: Option<BaseNodeAdaptation>,
     },
     /// over column, group_by columns
@@ -446,6 +504,26 @@ pub enum MirNodeType {
         on_right: Vec<Column>,
         project: Vec<Column>,
     },
+    /// on left column, on right column, emit columns
+    FullJoin {
+        on_left: Vec<Column>,
+        on_right: Vec<Column>,
+        project: Vec<Column>,
+    },
+    /// on left column, on right column, emit columns (all from the left)
+    SemiJoin {
+        on_left: Vec<Column>,
+        on_right: Vec<Column>,
+        project: Vec<Column>,
+    },
+    /// on left column, on right column, emit columns (all from the left), and whether to follow
+    /// `NOT IN`'s rules for `NULL`s
+    AntiJoin {
+        on_left: Vec<Column>,
+        on_right: Vec<Column>,
+        project: Vec<Column>,
+        null_aware: bool,
+    },
     /// group columns
     // currently unused
     #[allow(dead_code)]
@@ -456,6 +534,9 @@ pub enum MirNodeType {
     Project {
         emit: Vec<Column>,
         arithmetic: Vec<(String, ArithmeticExpression)>,
+        /// named expressions, each over the listed parent columns (`ProjectExpressionBase::Column`
+        /// indexes into that list)
+        expressions: Vec<(String, ProjectExpression, Vec<Column>)>,
         literals: Vec<(String, DataType)>,
     },
     /// emit columns
@@ -469,6 +550,13 @@ pub enum MirNodeType {
         k: usize,
         offset: usize,
     },
+    /// order function, group columns, records per page, records skipped before the first page
+    Paginate {
+        order: Option<Vec<(Column, OrderType)>>,
+        group_by: Vec<Column>,
+        page_size: usize,
+        offset: usize,
+    },
     // Get the distinct element sorted by a specific column
     Distinct {
         group_by: Vec<Column>,
@@ -477,10 +565,11 @@ pub enum MirNodeType {
     Reuse {
         node: MirNodeRef,
     },
-    /// leaf (reader) node, keys
+    /// leaf (reader) node, keys, how the last key column is bounded if it is read by range
     Leaf {
         node: MirNodeRef,
         keys: Vec<Column>,
+        range: Option<RangeParameter>,
     },
     /// Rewrite node
     Rewrite {
@@ -495,6 +584,46 @@ impl MirNodeType {
         format!("{:?}", self)
     }
 
+    /// The column lists that `add_column` can extend.
+    fn column_lists(&mut self) -> Vec<&mut Vec<Column>> {
+        match *self {
+            MirNodeType::Aggregation {
+                ref mut group_by, ..
+            }
+            | MirNodeType::Extremum {
+                ref mut group_by, ..
+            }
+            | MirNodeType::FilterAggregation {
+                ref mut group_by, ..
+            }
+            | MirNodeType::Distinct { ref mut group_by }
+            | MirNodeType::TopK {
+                ref mut group_by, ..
+            }
+            | MirNodeType::Paginate {
+                ref mut group_by, ..
+            } => vec![group_by],
+            MirNodeType::Join {
+                ref mut project, ..
+            }
+            | MirNodeType::LeftJoin {
+                ref mut project, ..
+            }
+            | MirNodeType::FullJoin {
+                ref mut project, ..
+            }
+            | MirNodeType::SemiJoin {
+                ref mut project, ..
+            }
+            | MirNodeType::AntiJoin {
+                ref mut project, ..
+            } => vec![project],
+            MirNodeType::Project { ref mut emit, .. } => vec![emit],
+            MirNodeType::Union { ref mut emit } => emit.iter_mut().collect(),
+            _ => vec![],
+        }
+    }
+
     fn add_column(&mut self, c: Column) {
         match *self {
             MirNodeType::Aggregation {
@@ -519,6 +648,15 @@ impl MirNodeType {
             }
             | MirNodeType::LeftJoin {
                 ref mut project, ..
+            }
+            | MirNodeType::FullJoin {
+                ref mut project, ..
+            }
+            | MirNodeType::SemiJoin {
+                ref mut project, ..
+            }
+            | MirNodeType::AntiJoin {
+                ref mut project, ..
             } => {
                 project.push(c);
             }
@@ -537,6 +675,9 @@ impl MirNodeType {
             }
             MirNodeType::TopK {
                 ref mut group_by, ..
+            }
+            | MirNodeType::Paginate {
+                ref mut group_by, ..
             } => {
                 group_by.push(c);
             }
@@ -590,12 +731,14 @@ impl MirNodeType {
             MirNodeType::Base {
                 column_specs: ref our_column_specs,
                 keys: ref our_keys,
+                unique_keys: ref our_unique_keys,
                 adapted_over: ref our_adapted_over,
             } => {
                 match *other {
                     MirNodeType::Base {
                         ref column_specs,
                         ref keys,
+                        ref unique_keys,
                         ..
                     } => {
                         // if we are instructed to adapt an earlier base node, we cannot reuse
@@ -611,7 +754,9 @@ impl MirNodeType {
                         // note that as long as we are not adapting a previous base node,
                         // we do *not* need `adapted_over` to *match*, since current reuse
                         // does not depend on how base node was created from an earlier one
-                        our_column_specs == column_specs && our_keys == keys
+                        our_column_specs == column_specs
+                            && our_keys == keys
+                            && our_unique_keys == unique_keys
                     }
                     _ => false,
                 }
@@ -692,16 +837,66 @@ impl MirNodeType {
                     _ => false,
                 }
             }
+            MirNodeType::FullJoin {
+                on_left: ref our_on_left,
+                on_right: ref our_on_right,
+                project: ref our_project,
+            } => match *other {
+                MirNodeType::FullJoin {
+                    ref on_left,
+                    ref on_right,
+                    ref project,
+                } => our_on_left == on_left && our_on_right == on_right && our_project == project,
+                _ => false,
+            },
+            MirNodeType::SemiJoin {
+                on_left: ref our_on_left,
+                on_right: ref our_on_right,
+                project: ref our_project,
+            } => match *other {
+                MirNodeType::SemiJoin {
+                    ref on_left,
+                    ref on_right,
+                    ref project,
+                } => our_on_left == on_left && our_on_right == on_right && our_project == project,
+                _ => false,
+            },
+            MirNodeType::AntiJoin {
+                on_left: ref our_on_left,
+                on_right: ref our_on_right,
+                project: ref our_project,
+                null_aware: our_null_aware,
+            } => match *other {
+                MirNodeType::AntiJoin {
+                    ref on_left,
+                    ref on_right,
+                    ref project,
+                    null_aware,
+                } => {
+                    our_on_left == on_left
+                        && our_on_right == on_right
+                        && our_project == project
+                        && our_null_aware == null_aware
+                }
+                _ => false,
+            },
             MirNodeType::Project {
                 emit: ref our_emit,
                 literals: ref our_literals,
                 arithmetic: ref our_arithmetic,
+                expressions: ref our_expressions,
             } => match *other {
                 MirNodeType::Project {
                     ref emit,
                     ref literals,
                     ref arithmetic,
-                } => our_emit == emit && our_literals == literals && our_arithmetic == arithmetic,
+                    ref expressions,
+                } => {
+                    our_emit == emit
+                        && our_literals == literals
+                        && our_arithmetic == arithmetic
+                        && our_expressions == expressions
+                }
                 _ => false,
             },
             MirNodeType::Distinct {
@@ -741,10 +936,33 @@ impl MirNodeType {
                 }
                 _ => false,
             },
+            MirNodeType::Paginate {
+                order: ref our_order,
+                group_by: ref our_group_by,
+                page_size: our_page_size,
+                offset: our_offset,
+            } => match *other {
+                MirNodeType::Paginate {
+                    ref order,
+                    ref group_by,
+                    page_size,
+                    offset,
+                } => {
+                    order == our_order
+                        && group_by == our_group_by
+                        && page_size == our_page_size
+                        && offset == our_offset
+                }
+                _ => false,
+            },
             MirNodeType::Leaf {
-                keys: ref our_keys, ..
+                keys: ref our_keys,
+                range: our_range,
+                ..
             } => match *other {
-                MirNodeType::Leaf { ref keys, .. } => keys == our_keys,
+                MirNodeType::Leaf {
+                    ref keys, range, ..
+                } => keys == our_keys && range == our_range,
                 _ => false,
             },
             MirNodeType::Union { emit: ref our_emit } => match *other {
@@ -807,6 +1025,11 @@ impl Debug for MirNodeType {
                 let op_string = match *kind {
                     AggregationKind::COUNT => format!("|*|({})", on.name.as_str()),
                     AggregationKind::SUM => format!("𝛴({})", on.name.as_str()),
+                    AggregationKind::AVG => format!("avg({})", on.name.as_str()),
+                    AggregationKind::VARIANCE => format!("var({})", on.name.as_str()),
+                    AggregationKind::STDDEV => format!("stddev({})", on.name.as_str()),
+                    AggregationKind::BOOLAND => format!("∧({})", on.name.as_str()),
+                    AggregationKind::BOOLOR => format!("∨({})", on.name.as_str()),
                 };
                 let group_cols = group_by
                     .iter()
@@ -862,19 +1085,7 @@ impl Debug for MirNodeType {
                     "σ[{}]",
                     conditions
                         .iter()
-                        .filter_map(|(i, ref cond)| match *cond {
-                            FilterCondition::Comparison(ref op, ref x) => {
-                                Some(format!("f{} {} {:?}", i, escape(&format!("{}", op)), x))
-                            }
-                            FilterCondition::In(ref xs) => Some(format!(
-                                "f{} IN ({})",
-                                i,
-                                xs.iter()
-                                    .map(|d| format!("{}", d))
-                                    .collect::<Vec<_>>()
-                                    .join(", ")
-                            )),
-                        })
+                        .map(|(i, ref cond)| escape(&cond.describe(*i)))
                         .collect::<Vec<_>>()
                         .as_slice()
                         .join(", ")
@@ -925,13 +1136,19 @@ impl Debug for MirNodeType {
                     jc
                 )
             }
-            MirNodeType::Leaf { ref keys, .. } => {
+            MirNodeType::Leaf {
+                ref keys, range, ..
+            } => {
                 let key_cols = keys
                     .iter()
                     .map(|k| k.name.clone())
                     .collect::<Vec<_>>()
                     .join(", ");
-                write!(f, "Leaf [⚷: {}]", key_cols)
+                if range.is_some() {
+                    write!(f, "Leaf [⚷: {} (range)]", key_cols)
+                } else {
+                    write!(f, "Leaf [⚷: {}]", key_cols)
+                }
             }
             MirNodeType::LeftJoin {
                 ref on_left,
@@ -955,6 +1172,74 @@ impl Debug for MirNodeType {
                     jc
                 )
             }
+            MirNodeType::FullJoin {
+                ref on_left,
+                ref on_right,
+                ref project,
+            } => {
+                let jc = on_left
+                    .iter()
+                    .zip(on_right)
+                    .map(|(l, r)| format!("{}:{}", l.name, r.name))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(
+                    f,
+                    "⟗ [{} on {}]",
+                    project
+                        .iter()
+                        .map(|c| c.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    jc
+                )
+            }
+            MirNodeType::SemiJoin {
+                ref on_left,
+                ref on_right,
+                ref project,
+            } => {
+                let jc = on_left
+                    .iter()
+                    .zip(on_right)
+                    .map(|(l, r)| format!("{}:{}", l.name, r.name))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(
+                    f,
+                    "∃ [{} on {}]",
+                    project
+                        .iter()
+                        .map(|c| c.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    jc
+                )
+            }
+            MirNodeType::AntiJoin {
+                ref on_left,
+                ref on_right,
+                ref project,
+                null_aware,
+            } => {
+                let jc = on_left
+                    .iter()
+                    .zip(on_right)
+                    .map(|(l, r)| format!("{}:{}", l.name, r.name))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(
+                    f,
+                    "{} [{} on {}]",
+                    if null_aware { "∉" } else { "∄" },
+                    project
+                        .iter()
+                        .map(|c| c.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    jc
+                )
+            }
             MirNodeType::Latest { ref group_by } => {
                 let key_cols = group_by
                     .iter()
@@ -967,9 +1252,10 @@ impl Debug for MirNodeType {
                 ref emit,
                 ref literals,
                 ref arithmetic,
+                ref expressions,
             } => write!(
                 f,
-                "π [{}{}{}]",
+                "π [{}{}{}{}]",
                 emit.iter()
                     .map(|c| c.name.as_str())
                     .collect::<Vec<_>>()
@@ -986,6 +1272,10 @@ impl Debug for MirNodeType {
                             .join(", ")
                     )
                 },
+                expressions
+                    .iter()
+                    .map(|&(ref n, ref e, _)| format!(", {}: {}", n, e))
+                    .collect::<String>(),
                 if literals.is_empty() {
                     "".into()
                 } else {
@@ -1016,6 +1306,16 @@ impl Debug for MirNodeType {
             MirNodeType::TopK {
                 ref order, ref k, ..
             } => write!(f, "TopK [k: {}, {:?}]", k, order),
+            MirNodeType::Paginate {
+                ref order,
+                ref page_size,
+                ref offset,
+                ..
+            } => write!(
+                f,
+                "Paginate [{} per page, skip {}, {:?}]",
+                page_size, offset, order
+            ),
             MirNodeType::Union { ref emit } => {
                 let cols = emit
                     .iter()
@@ -20,6 +20,11 @@ pub fn optimize_post_reuse(_q: &mut MirQuery) {
     // find_and_merge_filter_chains(q);
 }
 
+/// Whether there is a filter aggregation that can stand in for an aggregation of the given kind.
+fn has_filter_aggregation(kind: &Aggregation) -> bool {
+    matches!(*kind, Aggregation::COUNT | Aggregation::SUM)
+}
+
 fn find_and_merge_filter_aggregates(q: &mut MirQuery) -> Vec<MirNodeRef> {
     // 1. depth first search to find all the nodes, so we can process them later
 
@@ -72,17 +77,19 @@ fn find_and_merge_filter_aggregates(q: &mut MirQuery) -> Vec<MirNodeRef> {
             MirNodeType::Filter { .. } => {
                 // if the child is an aggregation and it has exactly one parent,
                 // then this is a candidate
-                if let MirNodeType::Aggregation { .. } = child.inner {
-                    if child.ancestors.len() == 1 {
+                if let MirNodeType::Aggregation { ref kind, .. } = child.inner {
+                    if child.ancestors.len() == 1 && has_filter_aggregation(kind) {
                         candidate = true;
                     }
                 }
             }
-            MirNodeType::Aggregation { ref on, .. } => {
+            MirNodeType::Aggregation {
+                ref on, ref kind, ..
+            } => {
                 // if the child is a filter and it has exactly one parent,
                 // then this is a candidate
                 if let MirNodeType::Filter { ref conditions } = child.inner {
-                    if child.ancestors.len() != 1 {
+                    if child.ancestors.len() != 1 || !has_filter_aggregation(kind) {
                         continue;
                     }
                     candidate = true;
@@ -172,6 +179,7 @@ fn find_and_merge_filter_aggregates(q: &mut MirQuery) -> Vec<MirNodeRef> {
                     match kind {
                         Aggregation::COUNT => FilterAggregation::COUNT,
                         Aggregation::SUM => FilterAggregation::SUM,
+                        _ => unreachable!(),
                     },
                 )
             } else {
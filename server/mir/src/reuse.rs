@@ -240,6 +240,7 @@ mod tests {
             MirNodeType::Base {
                 column_specs: vec![cspec("aa"), cspec("ab")],
                 keys: vec![Column::from("aa")],
+                unique_keys: vec![],
                 adapted_over: None,
             },
             vec![],
@@ -252,6 +253,7 @@ mod tests {
             MirNodeType::Base {
                 column_specs: vec![cspec("ba"), cspec("bb")],
                 keys: vec![Column::from("ba")],
+                unique_keys: vec![],
                 adapted_over: None,
             },
             vec![],
@@ -276,6 +278,7 @@ mod tests {
             MirNodeType::Leaf {
                 node: c.clone(),
                 keys: vec![Column::from("ba")],
+                range: None,
             },
             vec![],
             vec![],
@@ -324,6 +327,7 @@ mod tests {
             MirNodeType::Project {
                 emit: vec![Column::from("aa")],
                 arithmetic: vec![],
+                expressions: vec![],
                 literals: vec![],
             },
             vec![c.clone()],
@@ -3,7 +3,6 @@ use std::fmt::{self, Write};
 
 use crate::node::{MirNode, MirNodeType};
 use crate::query::MirQuery;
-use dataflow::ops::filter::FilterCondition;
 use dataflow::ops::grouped::aggregate::Aggregation as AggregationKind;
 use dataflow::ops::grouped::extremum::Extremum as ExtremumKind;
 use dataflow::ops::grouped::filteraggregate::FilterAggregation as FilterAggregationKind;
@@ -107,6 +106,11 @@ impl GraphViz for MirNodeType {
                 let op_string = match *kind {
                     AggregationKind::COUNT => format!("\\|*\\|({})", print_col(on)),
                     AggregationKind::SUM => format!("𝛴({})", print_col(on)),
+                    AggregationKind::AVG => format!("avg({})", print_col(on)),
+                    AggregationKind::VARIANCE => format!("var({})", print_col(on)),
+                    AggregationKind::STDDEV => format!("stddev({})", print_col(on)),
+                    AggregationKind::BOOLAND => format!("∧({})", print_col(on)),
+                    AggregationKind::BOOLOR => format!("∨({})", print_col(on)),
                 };
                 let group_cols = group_by
                     .iter()
@@ -181,19 +185,7 @@ impl GraphViz for MirNodeType {
                     "σ: {}",
                     conditions
                         .iter()
-                        .filter_map(|(i, ref cond)| match *cond {
-                            FilterCondition::Comparison(ref op, ref x) => {
-                                Some(format!("f{} {} {}", i, escape(&format!("{}", op)), x))
-                            }
-                            FilterCondition::In(ref xs) => Some(format!(
-                                "f{} IN ({})",
-                                i,
-                                xs.iter()
-                                    .map(|d| format!("{}", d))
-                                    .collect::<Vec<_>>()
-                                    .join(", ")
-                            )),
-                        })
+                        .map(|(i, ref cond)| escape(&cond.describe(*i)))
                         .collect::<Vec<_>>()
                         .as_slice()
                         .join(", ")
@@ -221,13 +213,18 @@ impl GraphViz for MirNodeType {
                     .join(", ");
                 write!(out, "⋈  | on: {}", jc)?;
             }
-            MirNodeType::Leaf { ref keys, .. } => {
+            MirNodeType::Leaf {
+                ref keys, range, ..
+            } => {
                 let key_cols = keys
                     .iter()
                     .map(|k| print_col(k))
                     .collect::<Vec<_>>()
                     .join(", ");
                 write!(out, "Leaf | ⚷: {}", key_cols)?;
+                if range.is_some() {
+                    write!(out, " (range)")?;
+                }
             }
             MirNodeType::LeftJoin {
                 ref on_left,
@@ -242,6 +239,47 @@ impl GraphViz for MirNodeType {
                     .join(", ");
                 write!(out, "⋉  | on: {}", jc)?;
             }
+            MirNodeType::FullJoin {
+                ref on_left,
+                ref on_right,
+                ..
+            } => {
+                let jc = on_left
+                    .iter()
+                    .zip(on_right)
+                    .map(|(l, r)| format!("{}:{}", print_col(l), print_col(r)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(out, "⟗  | on: {}", jc)?;
+            }
+            MirNodeType::SemiJoin {
+                ref on_left,
+                ref on_right,
+                ..
+            } => {
+                let jc = on_left
+                    .iter()
+                    .zip(on_right)
+                    .map(|(l, r)| format!("{}:{}", print_col(l), print_col(r)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(out, "∃  | on: {}", jc)?;
+            }
+            MirNodeType::AntiJoin {
+                ref on_left,
+                ref on_right,
+                null_aware,
+                ..
+            } => {
+                let jc = on_left
+                    .iter()
+                    .zip(on_right)
+                    .map(|(l, r)| format!("{}:{}", print_col(l), print_col(r)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let symbol = if null_aware { "∉" } else { "∄" };
+                write!(out, "{}  | on: {}", symbol, jc)?;
+            }
             MirNodeType::Latest { ref group_by } => {
                 let key_cols = group_by
                     .iter()
@@ -254,10 +292,11 @@ impl GraphViz for MirNodeType {
                 ref emit,
                 ref literals,
                 ref arithmetic,
+                ref expressions,
             } => {
                 write!(
                     out,
-                    "π: {}{}{}",
+                    "π: {}{}{}{}",
                     emit.iter()
                         .map(|c| print_col(c))
                         .collect::<Vec<_>>()
@@ -274,6 +313,10 @@ impl GraphViz for MirNodeType {
                                 .join(", ")
                         )
                     },
+                    expressions
+                        .iter()
+                        .map(|&(ref n, ref e, _)| format!(", {}: {}", n, e))
+                        .collect::<String>(),
                     if literals.is_empty() {
                         "".into()
                     } else {
@@ -316,6 +359,27 @@ impl GraphViz for MirNodeType {
                         .unwrap_or_else(|| "".into())
                 )?;
             }
+            MirNodeType::Paginate {
+                ref order,
+                ref page_size,
+                ref offset,
+                ..
+            } => {
+                write!(
+                    out,
+                    "Paginate [{} per page, skip {}; {}]",
+                    page_size,
+                    offset,
+                    order
+                        .as_ref()
+                        .map(|v| v
+                            .iter()
+                            .map(|(c, o)| format!("{}: {}", c.name.as_str(), o))
+                            .collect::<Vec<_>>()
+                            .join(", "))
+                        .unwrap_or_else(|| "".into())
+                )?;
+            }
             MirNodeType::Union { ref emit } => {
                 let cols = emit
                     .iter()
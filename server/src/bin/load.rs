@@ -0,0 +1,327 @@
+use clap::value_t_or_exit;
+use futures_util::stream::Stream;
+use noria::error::BulkLoadError;
+use noria::{BulkLoadProgress, ControllerHandle, CsvRows, DataType, DumpInsert, DumpReader};
+use std::fs::File;
+use std::io::BufReader;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::vec;
+use tokio::sync::mpsc;
+
+/// How often to report the progress of a load.
+const REPORT_EVERY: Duration = Duration::from_secs(5);
+
+/// How many rows of a CSV file, or statements of a SQL dump, to read ahead of the load.
+const READ_AHEAD: usize = 1024;
+
+fn reporter(table: &str) -> impl FnMut(&BulkLoadProgress) + '_ {
+    let start = Instant::now();
+    let mut last = start;
+    move |p| {
+        if last.elapsed() >= REPORT_EVERY {
+            last = Instant::now();
+            eprintln!(
+                "{}: {} rows loaded ({} rejected), {:.0} rows/s",
+                table,
+                p.acknowledged,
+                p.rejected,
+                p.acknowledged as f64 / start.elapsed().as_secs_f64()
+            );
+        }
+    }
+}
+
+fn done(table: &str, p: &BulkLoadProgress, start: Instant) {
+    let took = start.elapsed().as_secs_f64();
+    println!(
+        "{}: loaded {} rows ({} rejected) in {:.1}s ({:.0} rows/s)",
+        table,
+        p.acknowledged - p.rejected,
+        p.rejected,
+        took,
+        p.acknowledged as f64 / took
+    );
+}
+
+/// Read the items of `input`, which blocks, on a thread of its own, and pass them on through the
+/// returned channel, so that the load never waits for the input on the executor. Nothing is read
+/// after the first error.
+fn read_in_background<T, I>(input: I) -> mpsc::Receiver<Result<T, BulkLoadError>>
+where
+    T: Send + 'static,
+    I: Iterator<Item = Result<T, BulkLoadError>> + Send + 'static,
+{
+    let (mut tx, rx) = mpsc::channel(READ_AHEAD);
+    thread::spawn(move || {
+        for item in input {
+            let failed = item.is_err();
+            if futures_executor::block_on(tx.send(item)).is_err() || failed {
+                // the load has stopped, or cannot go on
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// The rows received from a CSV file, up to the first error, which is left in `failed`.
+struct CsvStream<'a> {
+    rows: mpsc::Receiver<Result<Vec<DataType>, BulkLoadError>>,
+    failed: &'a mut Option<BulkLoadError>,
+}
+
+impl Stream for CsvStream<'_> {
+    type Item = Vec<DataType>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.rows.poll_recv(cx) {
+            Poll::Ready(Some(Ok(row))) => Poll::Ready(Some(row)),
+            Poll::Ready(Some(Err(e))) => {
+                *self.failed = Some(e);
+                Poll::Ready(None)
+            }
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// The rows of the statements received from a SQL dump for one table, up to the first statement
+/// for another table, which is left in `next`, or up to the first error, which is left in
+/// `failed`.
+struct DumpStream<'a> {
+    statements: &'a mut mpsc::Receiver<Result<DumpInsert, BulkLoadError>>,
+    table: &'a str,
+    columns: &'a [String],
+    pending: vec::IntoIter<Vec<DataType>>,
+    next: &'a mut Option<DumpInsert>,
+    failed: &'a mut Option<BulkLoadError>,
+}
+
+impl Stream for DumpStream<'_> {
+    type Item = Vec<DataType>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if let Some(row) = this.pending.next() {
+                return Poll::Ready(Some(row));
+            }
+            let stmt = match this.statements.poll_recv(cx) {
+                Poll::Ready(Some(Ok(stmt))) => stmt,
+                Poll::Ready(Some(Err(e))) => {
+                    *this.failed = Some(e);
+                    return Poll::Ready(None);
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+            if stmt.table != this.table {
+                *this.next = Some(stmt);
+                return Poll::Ready(None);
+            }
+            match stmt.rows_for(this.columns) {
+                Ok(rows) => this.pending = rows.into_iter(),
+                Err(e) => {
+                    *this.failed = Some(e);
+                    return Poll::Ready(None);
+                }
+            }
+        }
+    }
+}
+
+async fn load_csv(
+    db: &mut ControllerHandle<noria::ZookeeperAuthority>,
+    table_name: &str,
+    rows: CsvRows<BufReader<File>>,
+    batch_size: usize,
+    in_flight: usize,
+) -> Result<(), failure::Error> {
+    let mut table = db.table(table_name).await?;
+
+    let start = Instant::now();
+    let mut failed = None;
+    let rows = CsvStream {
+        rows: read_in_background(rows),
+        failed: &mut failed,
+    };
+    let p = table
+        .bulk_load(rows, batch_size, in_flight, reporter(table_name))
+        .await?;
+    if let Some(e) = failed {
+        return Err(e.into());
+    }
+    done(table_name, &p, start);
+    Ok(())
+}
+
+async fn load_dump(
+    db: &mut ControllerHandle<noria::ZookeeperAuthority>,
+    only: Option<&str>,
+    statements: DumpReader<BufReader<File>>,
+    batch_size: usize,
+    in_flight: usize,
+) -> Result<(), failure::Error> {
+    let mut statements = read_in_background(statements);
+    let mut next = None;
+    loop {
+        let stmt = match next.take() {
+            Some(stmt) => stmt,
+            None => match statements.recv().await {
+                Some(stmt) => stmt?,
+                None => return Ok(()),
+            },
+        };
+        if only.map(|only| only != stmt.table).unwrap_or(false) {
+            continue;
+        }
+
+        let table_name = stmt.table.clone();
+        let mut table = db.table(&table_name).await?;
+        let columns = table.columns().to_vec();
+
+        // load the rows of consecutive statements for the same table as one stream, so that we
+        // keep batches in flight across statements.
+        let start = Instant::now();
+        let mut failed = None;
+        let rows = DumpStream {
+            pending: stmt.rows_for(&columns)?.into_iter(),
+            statements: &mut statements,
+            table: &table_name,
+            columns: &columns,
+            next: &mut next,
+            failed: &mut failed,
+        };
+        let p = table
+            .bulk_load(rows, batch_size, in_flight, reporter(&table_name))
+            .await?;
+        if let Some(e) = failed {
+            return Err(e.into());
+        }
+        done(&table_name, &p, start);
+    }
+}
+
+fn main() {
+    use clap::{App, Arg};
+    let matches = App::new("noria-load")
+        .version("0.0.1")
+        .about("Bulk-loads rows from CSV files or SQL dumps into Noria base tables.")
+        .arg(
+            Arg::with_name("zookeeper")
+                .short("z")
+                .long("zookeeper")
+                .takes_value(true)
+                .default_value("127.0.0.1:2181")
+                .help("Zookeeper connection info."),
+        )
+        .arg(
+            Arg::with_name("deployment")
+                .long("deployment")
+                .required(true)
+                .takes_value(true)
+                .help("Noria deployment ID."),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["csv", "sql"])
+                .help("Format of the input [default: sql for .sql files, csv otherwise]."),
+        )
+        .arg(
+            Arg::with_name("table")
+                .short("t")
+                .long("table")
+                .takes_value(true)
+                .help("Table to load into. Required for CSV; restricts SQL dumps to one table."),
+        )
+        .arg(
+            Arg::with_name("delimiter")
+                .long("delimiter")
+                .takes_value(true)
+                .default_value(",")
+                .help("Field delimiter of CSV input (use \\t for tab)."),
+        )
+        .arg(
+            Arg::with_name("header")
+                .long("header")
+                .help("Skip the first row of CSV input, which holds column names."),
+        )
+        .arg(
+            Arg::with_name("batch-size")
+                .long("batch-size")
+                .takes_value(true)
+                .default_value("10000")
+                .help("Number of rows to send to a table at a time."),
+        )
+        .arg(
+            Arg::with_name("in-flight")
+                .long("in-flight")
+                .takes_value(true)
+                .default_value("8")
+                .help("Number of batches to send before waiting for the first to be applied."),
+        )
+        .arg(
+            Arg::with_name("FILE")
+                .required(true)
+                .index(1)
+                .help("The CSV file or SQL dump to load."),
+        )
+        .get_matches();
+
+    let path = matches.value_of("FILE").unwrap();
+    let header = matches.is_present("header");
+    let sql = match matches.value_of("format") {
+        Some(format) => format == "sql",
+        None => path.ends_with(".sql"),
+    };
+    let table = matches.value_of("table");
+    let delimiter = match matches.value_of("delimiter").unwrap() {
+        "\\t" => '\t',
+        d if d.chars().count() == 1 => d.chars().next().unwrap(),
+        d => {
+            eprintln!("delimiter must be a single character, not '{}'", d);
+            std::process::exit(1);
+        }
+    };
+    let batch_size = value_t_or_exit!(matches, "batch-size", usize);
+    let in_flight = value_t_or_exit!(matches, "in-flight", usize);
+    let zk_addr = format!(
+        "{}/{}",
+        matches.value_of("zookeeper").unwrap(),
+        matches.value_of("deployment").unwrap()
+    );
+
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    let res = rt.block_on(async {
+        let input = BufReader::new(File::open(path)?);
+        let mut db = ControllerHandle::from_zk(&zk_addr).await?;
+        if sql {
+            return load_dump(
+                &mut db,
+                table,
+                DumpReader::new(input),
+                batch_size,
+                in_flight,
+            )
+            .await;
+        }
+
+        let table = table.ok_or_else(|| failure::err_msg("loading CSV requires --table"))?;
+        let mut rows = CsvRows::new(input).delimiter(delimiter);
+        if header {
+            rows = rows.skip_header();
+        }
+        load_csv(&mut db, table, rows, batch_size, in_flight).await
+    });
+
+    if let Err(e) = res {
+        eprintln!("failed to load {}: {}", path, e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,142 @@
+use futures_util::stream::TryStreamExt;
+use noria::{ControllerHandle, DataType};
+use serde_json::Value;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+/// The textual form of a value, or `None` for `NULL`.
+fn text(v: &DataType) -> Option<String> {
+    match *v {
+        DataType::None => None,
+        DataType::Text(..) | DataType::TinyText(..) => {
+            let s: &str = v.into();
+            Some(s.to_owned())
+        }
+        DataType::Timestamp(ts) => Some(ts.format("%Y-%m-%d %H:%M:%S%.f").to_string()),
+        _ => Some(v.to_string()),
+    }
+}
+
+/// Write a row as a line of CSV, in the form `noria-load` reads back.
+fn write_csv<W: Write>(out: &mut W, row: impl Iterator<Item = Option<String>>) -> io::Result<()> {
+    for (i, field) in row.enumerate() {
+        if i != 0 {
+            out.write_all(b",")?;
+        }
+        match field {
+            None => out.write_all(b"\\N")?,
+            Some(ref f)
+                if f.is_empty() || f == "\\N" || f.contains(&[',', '"', '\n', '\r'][..]) =>
+            {
+                write!(out, "\"{}\"", f.replace('"', "\"\""))?
+            }
+            Some(ref f) => out.write_all(f.as_bytes())?,
+        }
+    }
+    out.write_all(b"\n")
+}
+
+fn json(v: &DataType) -> Value {
+    match *v {
+        DataType::None => Value::Null,
+        DataType::Int(n) => n.into(),
+        DataType::UnsignedInt(n) => n.into(),
+        DataType::BigInt(n) => n.into(),
+        DataType::UnsignedBigInt(n) => n.into(),
+        DataType::Bool(b) => b.into(),
+        DataType::Real(..) => v.to_string().parse::<f64>().map(Value::from).unwrap(),
+        DataType::Json(..) => serde_json::from_str(&v.to_string()).unwrap(),
+        _ => text(v).into(),
+    }
+}
+
+fn main() {
+    use clap::{App, Arg};
+    let matches = App::new("noria-snapshot")
+        .version("0.0.1")
+        .about("Writes the current contents of a Noria base table or view to a file.")
+        .arg(
+            Arg::with_name("zookeeper")
+                .short("z")
+                .long("zookeeper")
+                .takes_value(true)
+                .default_value("127.0.0.1:2181")
+                .help("Zookeeper connection info."),
+        )
+        .arg(
+            Arg::with_name("deployment")
+                .long("deployment")
+                .required(true)
+                .takes_value(true)
+                .help("Noria deployment ID."),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["csv", "json"])
+                .default_value("csv")
+                .help("Write CSV with a header row, or one JSON object per line."),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .help("File to write to [default: stdout]."),
+        )
+        .arg(
+            Arg::with_name("NAME")
+                .required(true)
+                .index(1)
+                .help("The base table or view to take a snapshot of."),
+        )
+        .get_matches();
+
+    let name = matches.value_of("NAME").unwrap();
+    let csv = matches.value_of("format").unwrap() == "csv";
+    let zk_addr = format!(
+        "{}/{}",
+        matches.value_of("zookeeper").unwrap(),
+        matches.value_of("deployment").unwrap()
+    );
+    let mut out: Box<dyn Write> = match matches.value_of("output") {
+        Some(path) => Box::new(BufWriter::new(File::create(path).unwrap())),
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+
+    let mut rt = tokio::runtime::Runtime::new().unwrap();
+    let res: Result<usize, failure::Error> = rt.block_on(async {
+        let mut db = ControllerHandle::from_zk(&zk_addr).await?;
+        let mut snapshot = db.snapshot(name).await?;
+        let columns = snapshot.columns().to_vec();
+        if csv {
+            write_csv(&mut out, columns.iter().cloned().map(Some))?;
+        }
+
+        let mut nrows = 0;
+        while let Some(rows) = snapshot.try_next().await? {
+            nrows += rows.len();
+            for row in rows {
+                if csv {
+                    write_csv(&mut out, row.iter().map(text))?;
+                } else {
+                    let object: serde_json::Map<_, _> =
+                        columns.iter().cloned().zip(row.iter().map(json)).collect();
+                    serde_json::to_writer(&mut out, &object)?;
+                    out.write_all(b"\n")?;
+                }
+            }
+        }
+        out.flush()?;
+        Ok(nrows)
+    });
+
+    match res {
+        Ok(nrows) => eprintln!("{}: wrote {} rows", name, nrows),
+        Err(e) => {
+            eprintln!("failed to take snapshot of {}: {}", name, e);
+            std::process::exit(1);
+        }
+    }
+}
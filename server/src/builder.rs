@@ -63,6 +63,41 @@ impl Builder {
         self.config.sharding = shards;
     }
 
+    /// Keep `replicas` copies of the view called `view`, each on a different worker if there are
+    /// enough workers.
+    ///
+    /// Every replica holds all the rows of the view, so reads are spread over all of them, and
+    /// can move to another replica if the worker of one of them goes away. Takes effect for views
+    /// added by subsequent migrations.
+    pub fn set_reader_replicas(&mut self, view: &str, replicas: usize) {
+        assert_ne!(replicas, 0);
+        self.config
+            .reader_replicas
+            .insert(view.to_owned(), replicas);
+    }
+
+    /// Keep `backups` copies of the rows of every base table, each on a different worker from the
+    /// table itself if there are enough workers.
+    ///
+    /// Every write to a base table is also shipped to its backups, and is only acknowledged once
+    /// all of them have applied it. If the worker that holds the table fails, the table is rebuilt
+    /// from one of its backups, along with all the views that depend on it. Takes effect for base
+    /// tables added by subsequent migrations.
+    pub fn set_base_backups(&mut self, backups: usize) {
+        self.config.base_backups = backups;
+    }
+
+    /// Check how evenly the work is spread over the workers every `every`, and if some worker
+    /// does much more of it than another, move one of its domains to the other worker.
+    ///
+    /// How much work a domain does is judged by the size of its state and by the time it spends
+    /// processing updates. Domains are moved as with `ControllerHandle::move_domain`, so existing
+    /// `View` handles for the views they hold must be fetched again. Rebalancing is disabled by
+    /// default.
+    pub fn set_rebalance_interval(&mut self, every: time::Duration) {
+        self.config.rebalance_every = Some(every);
+    }
+
     /// Set how many workers this worker should wait for before becoming a controller. More workers
     /// can join later, but they won't be assigned any of the initial domains.
     pub fn set_quorum(&mut self, quorum: usize) {
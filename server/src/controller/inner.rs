@@ -2,11 +2,14 @@ use crate::controller::domain_handle::{DomainHandle, DomainShardHandle};
 use crate::controller::migrate::materialization::Materializations;
 use crate::controller::recipe::Schema;
 use crate::controller::schema;
-use crate::controller::{ControllerState, Migration, Recipe};
+use crate::controller::{ControllerState, Migration, Recipe, ShardingOverride};
 use crate::controller::{Worker, WorkerIdentifier};
 use crate::coordination::{CoordinationMessage, CoordinationPayload, DomainDescriptor};
 use dataflow::prelude::*;
-use dataflow::{node, payload::ControlReplyPacket, prelude::Packet, DomainBuilder, DomainConfig};
+use dataflow::SNAPSHOT_TIMEOUT;
+use dataflow::{
+    node, payload::ControlReplyPacket, prelude::Packet, DomainBuilder, DomainConfig, MovedShard,
+};
 use futures_util::stream::StreamExt;
 use hyper::{self, Method, StatusCode};
 use nom_sql::ColumnSpecification;
@@ -14,7 +17,9 @@ use noria::builders::*;
 use noria::channel::tcp::{SendError, TcpSender};
 use noria::consensus::{Authority, Epoch, STATE_KEY};
 use noria::debug::stats::{DomainStats, GraphStats, NodeStats};
-use noria::ActivationResult;
+use noria::error::{DrainError, MoveDomainError, ReshardError, SnapshotError, TransactionError};
+use noria::{ActivationResult, Input, RecipeError, SnapshotPage, TableOperation, WorkerState};
+use noria::{OperationResult, TransactionTag, TransactionWrites, WriteToken};
 use petgraph::visit::Bfs;
 use slog::Logger;
 use std::collections::{BTreeMap, HashMap, HashSet};
@@ -22,7 +27,15 @@ use std::mem;
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use std::{cell, io, time};
+use std::{cell, cmp, io, time};
+
+/// The number of rows of a snapshot handed to the client at a time.
+const SNAPSHOT_PAGE_ROWS: usize = 10_000;
+
+/// How much more of the work the busiest worker must do than the least busy one before a domain
+/// is moved between them, where the work of a worker is its share of all state plus its share of
+/// all processing time.
+const REBALANCE_THRESHOLD: f64 = 0.2;
 
 /// `Controller` is the core component of the alternate Soup implementation.
 ///
@@ -38,6 +51,18 @@ pub(super) struct ControllerInner {
 
     pub(super) domain_config: DomainConfig,
 
+    /// How many replicas to keep of the readers for the views with the given names.
+    pub(super) reader_replicas: HashMap<String, usize>,
+
+    /// How many backups to keep of the base nodes of each domain.
+    base_backups: usize,
+    /// The domains that keep backups of the base nodes of each domain that has any.
+    pub(super) backups: HashMap<DomainIndex, Vec<DomainHandle>>,
+    /// Backups of lost domains, kept until the bases they back up are added again.
+    retired_backups: HashMap<DomainIndex, DomainHandle>,
+    /// Where to get the rows of base nodes that are about to be added again, by name.
+    restoring: HashMap<String, Restore>,
+
     /// Parameters for persistence code.
     pub(super) persistence: PersistenceParameters,
     pub(super) materializations: Materializations,
@@ -60,16 +85,65 @@ pub(super) struct ControllerInner {
 
     pending_recovery: Option<(Vec<String>, usize)>,
 
+    /// Identifier handed to the next transaction that is committed.
+    ///
+    /// This starts at the time the controller was created, so that transactions committed before
+    /// and after a controller failover don't share identifiers.
+    pub(super) next_transaction: u64,
+
+    /// Snapshots pinned by domains that clients have yet to read in full.
+    snapshots: HashMap<u64, PinnedSnapshot>,
+    next_snapshot: u64,
+
     quorum: usize,
     heartbeat_every: Duration,
     healthcheck_every: Duration,
     last_checked_workers: Instant,
 
+    /// How often to even out the work of the workers, if at all.
+    rebalance_every: Option<Duration>,
+    last_rebalance: Instant,
+
+    /// Old copies of shards that moved to other workers, which could not be retired yet.
+    relays: Vec<DomainShardHandle>,
+    /// While nodes are being added to take over from others, the workers that hold the shards of
+    /// the nodes they take over from, so that they can be placed alongside them.
+    placement: HashMap<NodeIndex, HashMap<usize, WorkerIdentifier>>,
+
+    /// How the nodes with the given names were split into shards with `reshard`.
+    pub(super) shardings: HashMap<String, ShardingOverride>,
+    /// Base nodes that pass the writes they receive on to the base that took over from them.
+    forwarders: HashMap<NodeIndex, NodeIndex>,
+    /// The global addresses of the readers that a reader serves the reads of besides its own.
+    reader_aliases: HashMap<NodeIndex, Vec<NodeIndex>>,
+
     log: slog::Logger,
 
     pub(in crate::controller) replies: DomainReplies,
 }
 
+/// Where the rows come from that a base node is filled with when it is added in place of a base
+/// of the same name.
+enum Restore {
+    /// The given node in a backup of the old base, kept in `retired_backups`.
+    Backup(DomainIndex, LocalNodeIndex),
+    /// The given base node, which is still running, and ships its rows and the writes it applies
+    /// to the new base until it is told to pass writes on instead.
+    Ship(NodeIndex),
+}
+
+/// A snapshot whose rows the shards of a domain hold on to until a client has read them.
+struct PinnedSnapshot {
+    /// The name of the base table or view the snapshot is of.
+    name: String,
+    domain: DomainIndex,
+    /// The shard whose rows the client reads next.
+    shard: usize,
+    /// The columns clients see, if some of the node's columns have been dropped.
+    keep: Option<Vec<usize>>,
+    last_read: Instant,
+}
+
 pub(in crate::controller) struct DomainReplies(
     tokio::sync::mpsc::UnboundedReceiver<ControlReplyPacket>,
 );
@@ -89,7 +163,11 @@ impl DomainReplies {
     }
 
     pub(in crate::controller) async fn wait_for_acks(&mut self, d: &DomainHandle) {
-        for r in self.read_n_domain_replies(d.shards()).await {
+        self.wait_for_n_acks(d.shards()).await
+    }
+
+    async fn wait_for_n_acks(&mut self, n: usize) {
+        for r in self.read_n_domain_replies(n).await {
             match r {
                 ControlReplyPacket::Ack(_) => {}
                 r => unreachable!("got unexpected non-ack control reply: {:?}", r),
@@ -97,6 +175,42 @@ impl DomainReplies {
         }
     }
 
+    async fn wait_for_prepared(
+        &mut self,
+        n: usize,
+    ) -> Vec<(NodeIndex, usize, Vec<OperationResult>)> {
+        let mut prepared = Vec::with_capacity(n);
+        for r in self.read_n_domain_replies(n).await {
+            match r {
+                ControlReplyPacket::Prepared(ni, shard, rs) => prepared.push((ni, shard, rs)),
+                r => unreachable!("got unexpected non-prepared control reply: {:?}", r),
+            }
+        }
+        prepared
+    }
+
+    async fn wait_for_commits(&mut self, n: usize) -> Vec<WriteToken> {
+        let mut tokens = Vec::with_capacity(n);
+        for r in self.read_n_domain_replies(n).await {
+            match r {
+                ControlReplyPacket::Committed(token) => tokens.push(token),
+                r => unreachable!("got unexpected non-commit control reply: {:?}", r),
+            }
+        }
+        tokens
+    }
+
+    async fn wait_for_forwarded(&mut self, n: usize) -> WriteToken {
+        let mut token = WriteToken::default();
+        for r in self.read_n_domain_replies(n).await {
+            match r {
+                ControlReplyPacket::Forwarded(t) => token.merge(t),
+                r => unreachable!("got unexpected non-forwarded control reply: {:?}", r),
+            }
+        }
+        token
+    }
+
     async fn wait_for_statistics(
         &mut self,
         d: &DomainHandle,
@@ -110,6 +224,51 @@ impl DomainReplies {
         }
         stats
     }
+
+    async fn wait_for_pinned(&mut self, n: usize) -> Vec<bool> {
+        let mut pinned = Vec::with_capacity(n);
+        for r in self.read_n_domain_replies(n).await {
+            match r {
+                ControlReplyPacket::SnapshotPinned(p) => pinned.push(p),
+                r => unreachable!("got unexpected non-pinned control reply: {:?}", r),
+            }
+        }
+        pinned
+    }
+
+    async fn wait_for_snapshot_page(&mut self) -> Option<(Vec<Vec<DataType>>, bool)> {
+        match self.read_n_domain_replies(1).await.pop().unwrap() {
+            ControlReplyPacket::SnapshotPage(page) => page,
+            r => unreachable!("got unexpected non-page control reply: {:?}", r),
+        }
+    }
+
+    async fn wait_for_moved_out(&mut self) -> Option<(Map<cell::RefCell<Node>>, Box<MovedShard>)> {
+        match self.read_n_domain_replies(1).await.pop().unwrap() {
+            ControlReplyPacket::MovedOut(moved) => moved,
+            r => unreachable!("got unexpected non-moved control reply: {:?}", r),
+        }
+    }
+
+    async fn wait_for_moved_in(&mut self) {
+        loop {
+            match self.read_n_domain_replies(1).await.pop().unwrap() {
+                // the copy acknowledges setting up its nodes and replay paths again
+                ControlReplyPacket::Ack(_) => {}
+                ControlReplyPacket::MovedIn => return,
+                r => unreachable!("got unexpected non-moved control reply: {:?}", r),
+            }
+        }
+    }
+
+    async fn wait_for_relays_retired(&mut self, n: usize) {
+        for r in self.read_n_domain_replies(n).await {
+            match r {
+                ControlReplyPacket::RelayRetired => {}
+                r => unreachable!("got unexpected non-retired control reply: {:?}", r),
+            }
+        }
+    }
 }
 
 pub(super) fn graphviz(
@@ -222,9 +381,39 @@ impl ControllerInner {
             (Method::GET, "/flush_partial") => {
                 Ok(Ok(json::to_string(&self.flush_partial()).unwrap()))
             }
+            (Method::POST, "/snapshot") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| Ok(json::to_string(&self.snapshot(args)).unwrap())),
+            (Method::POST, "/snapshot_page") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| Ok(json::to_string(&self.snapshot_page(args)).unwrap())),
+            (Method::POST, "/reshard") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|(name, column, shards): (String, Option<String>, usize)| {
+                    let r = self.reshard(authority, &name, column.as_deref(), shards);
+                    Ok(json::to_string(&r).unwrap())
+                }),
+            (Method::POST, "/commit_transaction") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| Ok(json::to_string(&self.commit_transaction(args)).unwrap())),
             (Method::POST, "/inputs") => Ok(Ok(json::to_string(&self.inputs()).unwrap())),
             (Method::POST, "/outputs") => Ok(Ok(json::to_string(&self.outputs()).unwrap())),
-            (Method::GET, "/instances") => Ok(Ok(json::to_string(&self.get_instances()).unwrap())),
+            (Method::GET, "/instances") | (Method::POST, "/instances") => {
+                Ok(Ok(json::to_string(&self.get_instances()).unwrap()))
+            }
+            (Method::POST, "/drain") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| Ok(json::to_string(&self.drain(args)).unwrap())),
+            (Method::POST, "/cancel_drain") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| Ok(json::to_string(&self.cancel_drain(args)).unwrap())),
+            (Method::POST, "/move_domain") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(
+                    |(domain, shard, worker): (DomainIndex, usize, WorkerIdentifier)| {
+                        Ok(json::to_string(&self.move_domain(domain, shard, worker)).unwrap())
+                    },
+                ),
             (Method::GET, "/nodes") => {
                 // TODO(malte): this is a pretty yucky hack, but hyper doesn't provide easy access
                 // to individual query variables unfortunately. We'll probably want to factor this
@@ -265,18 +454,15 @@ impl ControllerInner {
             (Method::POST, "/view_builder") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
                 .map(|args| Ok(json::to_string(&self.view_builder(args)).unwrap())),
+            (Method::POST, "/changes_builder") => json::from_slice(&body)
+                .map_err(|_| StatusCode::BAD_REQUEST)
+                .map(|args| Ok(json::to_string(&self.changes_builder(args)).unwrap())),
             (Method::POST, "/extend_recipe") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
-                .map(|args| {
-                    self.extend_recipe(authority, args)
-                        .map(|r| json::to_string(&r).unwrap())
-                }),
+                .map(|args| Ok(json::to_string(&self.extend_recipe(authority, args)).unwrap())),
             (Method::POST, "/install_recipe") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
-                .map(|args| {
-                    self.install_recipe(authority, args)
-                        .map(|r| json::to_string(&r).unwrap())
-                }),
+                .map(|args| Ok(json::to_string(&self.install_recipe(authority, args)).unwrap())),
             (Method::POST, "/set_security_config") => json::from_slice(&body)
                 .map_err(|_| StatusCode::BAD_REQUEST)
                 .map(|args| {
@@ -362,25 +548,46 @@ impl ControllerInner {
             let mut failed = Vec::new();
             for (addr, ws) in self.workers.iter_mut() {
                 if ws.healthy && ws.last_heartbeat.elapsed() > self.heartbeat_every * 3 {
-                    error!(self.log, "worker at {:?} has failed!", addr);
                     ws.healthy = false;
                     failed.push(addr.clone());
                 }
             }
-            self.handle_failed_workers(failed);
+
+            // drained workers have nothing left to recover
+            let (drained, failed): (Vec<_>, Vec<_>) = failed
+                .into_iter()
+                .partition(|wi| self.workers[wi].draining && !self.holds_domains(wi));
+            for addr in drained {
+                info!(self.log, "drained worker at {:?} has left", addr);
+            }
+            for addr in &failed {
+                error!(self.log, "worker at {:?} has failed!", addr);
+            }
+            if !failed.is_empty() {
+                self.handle_failed_workers(failed);
+            }
         }
     }
 
     fn handle_failed_workers(&mut self, failed: Vec<WorkerIdentifier>) {
         // first, translate from the affected workers to affected data-flow nodes
         let mut affected_nodes = Vec::new();
-        for wi in failed {
+        for wi in &failed {
             info!(self.log, "handling failure of worker {:?}", wi);
-            affected_nodes.extend(self.get_failed_nodes(&wi));
+            affected_nodes.extend(self.get_failed_nodes(wi));
         }
 
-        // then, figure out which queries are affected (and thus must be removed and added again in
-        // a migration)
+        // find backups to restore lost base nodes from, since the bases will be added again
+        self.recover_backups(&failed);
+
+        // then, remove the affected queries and add them again in a migration, which fills the
+        // bases before it rebuilds everything downstream of them
+        self.rebuild_queries(affected_nodes);
+        self.finish_restore();
+    }
+
+    /// Remove the queries that use any of the given nodes, and then add them again.
+    fn rebuild_queries(&mut self, affected_nodes: Vec<NodeIndex>) {
         let affected_queries = self.recipe.queries_for_nodes(affected_nodes);
         let (recovery, mut original) = self.recipe.make_recovery(affected_queries);
 
@@ -400,6 +607,255 @@ impl ControllerInner {
             .expect("failed to activate original recipe");
     }
 
+    /// Deal with the backups affected by the failure of the given workers.
+    ///
+    /// Backups that were on a failed worker are replaced. For domains that were on a failed
+    /// worker, a surviving backup is set aside to restore their base nodes from once they are
+    /// added again.
+    fn recover_backups(&mut self, failed: &[WorkerIdentifier]) {
+        let on_failed = |d: &DomainHandle| failed.iter().any(|wi| d.assigned_to_worker(wi));
+        let log = self.log.clone();
+
+        let mut primaries: Vec<_> = self.backups.keys().cloned().collect();
+        primaries.sort();
+        for primary in primaries {
+            let mut backups = self.backups.remove(&primary).unwrap();
+            let nbackups = backups.len();
+            backups.retain(|d| !on_failed(d));
+            let lost = nbackups - backups.len();
+
+            if !on_failed(&self.domains[&primary]) {
+                if lost != 0 {
+                    warn!(log, "replacing lost backups";
+                          "domain" => primary.index(),
+                          "lost" => lost);
+                }
+                self.replace_backups(primary, backups, lost, &log);
+                continue;
+            }
+
+            // the bases of the domain are lost, but one of their backups may not be
+            let bases: Vec<_> = self.domain_nodes[&primary]
+                .iter()
+                .cloned()
+                .filter(|&ni| self.ingredients[ni].is_base())
+                .collect();
+
+            // any shards of the domain that are still around must stop shipping writes to the
+            // backups, so that what the backups hold stays put until it is restored.
+            let domain = self.domains.get_mut(&primary).unwrap();
+            for shard in 0..domain.shards() {
+                let update = Packet::UpdateBackups {
+                    backups: Vec::new(),
+                };
+                drop(domain.send_to_healthy_shard(shard, Box::new(update), &self.workers));
+            }
+
+            // a single backup is enough to restore from. the others are of no use once the bases
+            // are added again, and those will get backups of their own then.
+            let mut backups = backups.into_iter();
+            match backups.next() {
+                Some(d) => {
+                    info!(log, "restoring bases from backup";
+                          "domain" => primary.index(),
+                          "backup" => d.index().index());
+                    for ni in bases {
+                        let n = &self.ingredients[ni];
+                        let restore = Restore::Backup(d.index(), n.local_addr());
+                        self.restoring.insert(n.name().to_owned(), restore);
+                    }
+                    self.retired_backups.insert(d.index(), d);
+                }
+                None if !bases.is_empty() => {
+                    error!(log, "lost base nodes along with all of their backups";
+                           "domain" => primary.index());
+                }
+                None => {}
+            }
+            for mut d in backups {
+                drop(d.send_to_healthy(Box::new(Packet::Quit), &self.workers));
+            }
+        }
+    }
+
+    /// Make `backups` the backups of the given domain, and add `lost` more to make up for the
+    /// backups it had besides those.
+    fn replace_backups(
+        &mut self,
+        primary: DomainIndex,
+        backups: Vec<DomainHandle>,
+        lost: usize,
+        log: &Logger,
+    ) {
+        if lost != 0 {
+            let update = Packet::UpdateBackups {
+                backups: backups.iter().map(DomainHandle::index).collect(),
+            };
+            self.domains
+                .get_mut(&primary)
+                .unwrap()
+                .send_to_healthy(Box::new(update), &self.workers)
+                .unwrap();
+        }
+        self.backups.insert(primary, backups);
+        for _ in 0..lost {
+            self.add_backup(primary, log);
+        }
+    }
+
+    /// Have the given new base nodes start out with the rows of the bases of the same name that
+    /// they replace, if any.
+    ///
+    /// The rows are sent before the bases are ready, so that they are there before any write or
+    /// any replay to the nodes downstream of the bases.
+    pub(in crate::controller) fn restore_bases(&mut self, new: &HashSet<NodeIndex>, log: &Logger) {
+        if self.restoring.is_empty() {
+            return;
+        }
+
+        let mut bases: Vec<_> = new
+            .iter()
+            .cloned()
+            .filter(|&ni| self.ingredients[ni].is_base() && !self.ingredients[ni].is_dropped())
+            .collect();
+        bases.sort();
+        for ni in bases {
+            let restore = match self.restoring.remove(self.ingredients[ni].name()) {
+                Some(restore) => restore,
+                None => continue,
+            };
+            info!(log, "restoring rows of base node"; "node" => ni.index());
+
+            let (backup, node) = match restore {
+                Restore::Ship(old) => {
+                    self.ship_base(old, ni);
+                    continue;
+                }
+                Restore::Backup(backup, node) => (backup, node),
+            };
+
+            // have the backup pin a copy of the rows, and pass them on a page at a time
+            let id = self.next_snapshot;
+            self.next_snapshot += 1;
+            let d = self.retired_backups.get_mut(&backup).unwrap();
+            let shards = d.shards();
+            let mut asked = 0;
+            for shard in 0..shards {
+                let m = Box::new(Packet::PinSnapshot {
+                    node,
+                    id,
+                    after: WriteToken::default(),
+                });
+                if d.send_to_healthy_shard(shard, m, &self.workers).is_ok() {
+                    asked += 1;
+                }
+            }
+            let pinned = futures_executor::block_on(self.replies.wait_for_pinned(asked));
+            if asked != shards || !pinned.into_iter().all(|p| p) {
+                error!(log, "could not copy rows out of backup";
+                       "node" => ni.index(),
+                       "backup" => backup.index());
+                continue;
+            }
+
+            for shard in 0..shards {
+                loop {
+                    let m = Box::new(Packet::SnapshotPage {
+                        id,
+                        rows: SNAPSHOT_PAGE_ROWS,
+                    });
+                    let d = self.retired_backups.get_mut(&backup).unwrap();
+                    if d.send_to_healthy_shard(shard, m, &self.workers).is_err() {
+                        error!(log, "lost backup while restoring from it";
+                               "node" => ni.index(),
+                               "backup" => backup.index());
+                        break;
+                    }
+                    match futures_executor::block_on(self.replies.wait_for_snapshot_page()) {
+                        Some((rows, done)) => {
+                            self.send_restored_rows(ni, rows);
+                            if done {
+                                break;
+                            }
+                        }
+                        None => {
+                            error!(log, "rows copied out of backup expired";
+                                   "node" => ni.index(),
+                                   "backup" => backup.index());
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Have the shards of the base node `from` send their rows to the shards of the new base node
+    /// `to` that they belong in, along with every write they apply from then on.
+    fn ship_base(&mut self, from: NodeIndex, to: NodeIndex) {
+        let (column, shards) = match self.ingredients[to].sharded_by() {
+            Sharding::ByColumn(column, shards) => (column, shards),
+            _ => (0, 1),
+        };
+        let m = Packet::ShipBase {
+            node: self.ingredients[from].local_addr(),
+            to: (
+                self.ingredients[to].domain(),
+                self.ingredients[to].local_addr(),
+            ),
+            column,
+            shards,
+        };
+        let d = self
+            .domains
+            .get_mut(&self.ingredients[from].domain())
+            .unwrap();
+        d.send_to_healthy(Box::new(m), &self.workers).unwrap();
+
+        // every shard of the new base hears from every shard of the old one
+        let n = d.shards() * shards;
+        futures_executor::block_on(self.replies.wait_for_n_acks(n));
+    }
+
+    /// Send rows to the shards of the given new base node that they belong in.
+    fn send_restored_rows(&mut self, ni: NodeIndex, rows: Vec<Vec<DataType>>) {
+        let n = &self.ingredients[ni];
+        let local = n.local_addr();
+        let sharding = n.sharded_by();
+        let domain = self.domains.get_mut(&n.domain()).unwrap();
+
+        // the base may be sharded differently than the one the rows came from
+        let mut shards = vec![Vec::new(); domain.shards()];
+        for row in rows {
+            let shard = match sharding {
+                Sharding::ByColumn(col, n) => noria::shard_by(&row[col], n),
+                _ => 0,
+            };
+            shards[shard].push(row);
+        }
+
+        for (shard, rows) in shards.into_iter().enumerate() {
+            if rows.is_empty() {
+                continue;
+            }
+            let m = Box::new(Packet::RestoreBase { node: local, rows });
+            domain
+                .send_to_healthy_shard(shard, m, &self.workers)
+                .unwrap();
+        }
+    }
+
+    /// Give up on restoring base nodes that were not added again, and stop the backups that were
+    /// kept around to restore from.
+    fn finish_restore(&mut self) {
+        for (name, _) in self.restoring.drain() {
+            error!(self.log, "base node to restore is gone"; "name" => &name);
+        }
+        for (_, mut d) in self.retired_backups.drain() {
+            drop(d.send_to_healthy(Box::new(Packet::Quit), &self.workers));
+        }
+    }
+
     pub(super) fn handle_heartbeat(&mut self, msg: CoordinationMessage) -> Result<(), io::Error> {
         match self.workers.get_mut(&msg.source) {
             None => crit!(
@@ -413,6 +869,16 @@ impl ControllerInner {
         }
 
         self.check_worker_liveness();
+
+        if let Some(every) = self.rebalance_every {
+            if self.last_rebalance.elapsed() > every
+                && self.pending_recovery.is_none()
+                && self.workers.len() >= self.quorum
+            {
+                self.rebalance();
+                self.last_rebalance = Instant::now();
+            }
+        }
         Ok(())
     }
 
@@ -455,6 +921,11 @@ impl ControllerInner {
             materializations,
             sharding: state.config.sharding,
             domain_config: state.config.domain_config,
+            reader_replicas: state.config.reader_replicas,
+            base_backups: state.config.base_backups,
+            backups: Default::default(),
+            retired_backups: Default::default(),
+            restoring: Default::default(),
             persistence: state.config.persistence,
             heartbeat_every: state.config.heartbeat_every,
             healthcheck_every: state.config.healthcheck_every,
@@ -473,7 +944,20 @@ impl ControllerInner {
             workers: HashMap::default(),
 
             pending_recovery,
+            next_transaction: time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0),
+            snapshots: HashMap::default(),
+            next_snapshot: 0,
             last_checked_workers: Instant::now(),
+            rebalance_every: state.config.rebalance_every,
+            last_rebalance: Instant::now(),
+            relays: Vec::new(),
+            placement: HashMap::default(),
+            shardings: state.shardings,
+            forwarders: HashMap::default(),
+            reader_aliases: HashMap::default(),
 
             replies: DomainReplies(drx),
         }
@@ -507,21 +991,62 @@ impl ControllerInner {
         log: &Logger,
         nodes: Vec<(NodeIndex, bool)>,
     ) -> DomainHandle {
+        // replicas of a reader only add to the read capacity of the view if they are on different
+        // workers, so we keep them apart where we can.
+        let avoid: HashSet<_> = nodes
+            .iter()
+            .filter(|&&(ni, _)| self.ingredients[ni].is_reader())
+            .flat_map(|&(ni, _)| self.replica_workers(ni))
+            .collect();
+
+        // the shards of a node that takes over from another go where those of the other were, as
+        // long as there are as many of them.
+        let pin: HashMap<usize, WorkerIdentifier> = nodes
+            .iter()
+            .filter_map(|&(ni, _)| self.placement.get(&ni))
+            .filter(|shards| shards.len() == num_shards.unwrap_or(1))
+            .flat_map(|shards| shards.iter().map(|(&shard, &worker)| (shard, worker)))
+            .collect();
+        let nodes = nodes
+            .into_iter()
+            .map(|(ni, _)| {
+                let node = self.ingredients.node_weight_mut(ni).unwrap().take();
+                node.finalize(&self.ingredients)
+            })
+            .map(|nd| (nd.local_addr(), cell::RefCell::new(nd)))
+            .collect();
+
+        let persistence = self.persistence.clone();
+        self.boot_domain(idx, num_shards, log, nodes, persistence, &avoid, &pin, None)
+    }
+
+    /// Start the shards of a domain with the given nodes on the workers, preferring workers other
+    /// than those in `avoid`, and putting the shards in `pin` on the given workers if they can
+    /// take them.
+    #[allow(clippy::too_many_arguments)]
+    fn boot_domain(
+        &mut self,
+        idx: DomainIndex,
+        num_shards: Option<usize>,
+        log: &Logger,
+        nodes: Map<cell::RefCell<Node>>,
+        persistence: PersistenceParameters,
+        avoid: &HashSet<WorkerIdentifier>,
+        pin: &HashMap<usize, WorkerIdentifier>,
+        backup_of: Option<DomainIndex>,
+    ) -> DomainHandle {
+        let spread = self
+            .workers
+            .iter()
+            .any(|(wi, w)| w.healthy && !w.draining && !avoid.contains(wi));
+
         // TODO: can we just redirect all domain traffic through the worker's connection?
         let mut assignments = Vec::new();
-        let mut nodes = Some(
-            nodes
-                .into_iter()
-                .map(|(ni, _)| {
-                    let node = self.ingredients.node_weight_mut(ni).unwrap().take();
-                    node.finalize(&self.ingredients)
-                })
-                .map(|nd| (nd.local_addr(), cell::RefCell::new(nd)))
-                .collect(),
-        );
+        let mut nodes = Some(nodes);
 
         // TODO(malte): simple round-robin placement for the moment
-        let mut wi = self.workers.iter_mut();
+        let candidates: Vec<_> = self.workers.keys().cloned().collect();
+        let mut wi = candidates.iter().cycle();
 
         // Send `AssignDomain` to each shard of the given domain
         for i in 0..num_shards.unwrap_or(1) {
@@ -537,18 +1062,30 @@ impl ControllerInner {
                 nshards: num_shards.unwrap_or(1),
                 config: self.domain_config.clone(),
                 nodes,
-                persistence_parameters: self.persistence.clone(),
+                persistence_parameters: persistence.clone(),
+                backup_of,
+                moving_in: false,
             };
 
-            let (identifier, w) = loop {
-                if let Some((i, w)) = wi.next() {
-                    if w.healthy {
-                        break (*i, w);
-                    }
-                } else {
-                    wi = self.workers.iter_mut();
+            let pinned = pin.get(&i).filter(|&worker| {
+                self.workers
+                    .get(worker)
+                    .map(|w| w.healthy && !w.draining)
+                    .unwrap_or(false)
+            });
+            let identifier = match pinned {
+                Some(&worker) => worker,
+                _ => {
+                    let workers = &self.workers;
+                    *wi.by_ref()
+                        .find(|&c| {
+                            let w = &workers[c];
+                            w.healthy && !w.draining && !(spread && avoid.contains(c))
+                        })
+                        .unwrap()
                 }
             };
+            let w = self.workers.get_mut(&identifier).unwrap();
 
             // send domain to worker
             info!(
@@ -637,6 +1174,137 @@ impl ControllerInner {
         }
     }
 
+    /// Keep backups of the given new base nodes, as many as the controller was configured to keep.
+    pub(in crate::controller) fn add_base_backups(
+        &mut self,
+        new: &HashSet<NodeIndex>,
+        log: &Logger,
+    ) {
+        if self.base_backups == 0 {
+            return;
+        }
+
+        let mut bases: BTreeMap<DomainIndex, Vec<NodeIndex>> = BTreeMap::new();
+        for &ni in new {
+            if self.ingredients[ni].is_base() {
+                let domain = self.ingredients[ni].domain();
+                bases.entry(domain).or_insert_with(Vec::new).push(ni);
+            }
+        }
+
+        for (domain, mut bases) in bases {
+            let backups = match self.backups.get_mut(&domain) {
+                Some(backups) => backups,
+                None => {
+                    for _ in 0..self.base_backups {
+                        self.add_backup(domain, log);
+                    }
+                    continue;
+                }
+            };
+
+            // the domain is already backed up, so its backups just need copies of the new bases
+            bases.sort();
+            for ni in bases {
+                let node = self.ingredients[ni].backup();
+                let local = node.local_addr();
+                let index = self.materializations.indices(ni);
+                for d in backups.iter_mut() {
+                    let add = Packet::AddNode {
+                        node: node.clone(),
+                        parents: Vec::new(),
+                    };
+                    d.send_to_healthy(Box::new(add), &self.workers).unwrap();
+                    let ready = Packet::Ready {
+                        node: local,
+                        purge: false,
+                        index: index.clone(),
+                    };
+                    d.send_to_healthy(Box::new(ready), &self.workers).unwrap();
+                    futures_executor::block_on(self.replies.wait_for_acks(d));
+                }
+            }
+        }
+    }
+
+    /// Start another backup of the base nodes of the domain `primary`, and have the domain ship
+    /// all of its writes to it.
+    ///
+    /// The backup is placed on workers that hold neither the domain nor any of its other backups
+    /// if possible.
+    fn add_backup(&mut self, primary: DomainIndex, log: &Logger) {
+        let bases: Vec<_> = self.domain_nodes[&primary]
+            .iter()
+            .cloned()
+            .filter(|&ni| self.ingredients[ni].is_base())
+            .collect();
+        if bases.is_empty() {
+            return;
+        }
+
+        let idx = DomainIndex::from(self.ndomains);
+        self.ndomains += 1;
+        info!(
+            log,
+            "backing up domain {} in domain {}",
+            primary.index(),
+            idx.index()
+        );
+
+        let avoid: HashSet<_> = self
+            .backups
+            .get(&primary)
+            .into_iter()
+            .flatten()
+            .chain(Some(&self.domains[&primary]))
+            .flat_map(|d| d.shards.iter().map(|s| s.worker))
+            .collect();
+        let nodes = bases
+            .iter()
+            .map(|&ni| {
+                let n = self.ingredients[ni].backup();
+                (n.local_addr(), cell::RefCell::new(n))
+            })
+            .collect();
+        // a backup may end up on the same machine as what it backs up, so it must not share files
+        // with it.
+        let mut persistence = self.persistence.clone();
+        persistence.log_prefix = format!("{}-backup{}", persistence.log_prefix, idx.index());
+
+        let num_shards = self.ingredients[bases[0]].sharded_by().shards();
+        let backup_of = Some(primary);
+        let mut d = self.boot_domain(
+            idx,
+            num_shards,
+            log,
+            nodes,
+            persistence,
+            &avoid,
+            &HashMap::new(),
+            backup_of,
+        );
+        for &ni in &bases {
+            let ready = Packet::Ready {
+                node: self.ingredients[ni].local_addr(),
+                purge: false,
+                index: self.materializations.indices(ni),
+            };
+            d.send_to_healthy(Box::new(ready), &self.workers).unwrap();
+            futures_executor::block_on(self.replies.wait_for_acks(&d));
+        }
+
+        let backups = self.backups.entry(primary).or_insert_with(Vec::new);
+        backups.push(d);
+        let update = Packet::UpdateBackups {
+            backups: backups.iter().map(DomainHandle::index).collect(),
+        };
+        self.domains
+            .get_mut(&primary)
+            .unwrap()
+            .send_to_healthy(Box::new(update), &self.workers)
+            .unwrap();
+    }
+
     /// Set the `Logger` to use for internal log messages.
     ///
     /// By default, all log messages are discarded.
@@ -674,9 +1342,34 @@ impl ControllerInner {
     where
         F: FnOnce(&mut Migration) -> T,
     {
+        let mut m = self.start_migration();
+        let r = f(&mut m);
+        m.commit();
+        r
+    }
+
+    /// Perform a new query schema migration that may fail.
+    ///
+    /// The migration is only committed if `f` succeeds. If it fails, the migration is aborted and
+    /// the nodes it added are removed from the graph again.
+    pub(crate) fn migrate_or_abort<F, T, E>(&mut self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Migration) -> Result<T, E>,
+    {
+        let mut m = self.start_migration();
+        let r = f(&mut m);
+        if r.is_ok() {
+            m.commit();
+        } else {
+            m.abort();
+        }
+        r
+    }
+
+    fn start_migration(&mut self) -> Migration {
         info!(self.log, "starting migration");
         let miglog = self.log.new(o!());
-        let mut m = Migration {
+        Migration {
             mainline: self,
             added: Default::default(),
             columns: Default::default(),
@@ -684,10 +1377,7 @@ impl ControllerInner {
             context: Default::default(),
             start: time::Instant::now(),
             log: miglog,
-        };
-        let r = f(&mut m);
-        m.commit();
-        r
+        }
     }
 
     #[cfg(test)]
@@ -702,6 +1392,7 @@ impl ControllerInner {
     fn inputs(&self) -> BTreeMap<String, NodeIndex> {
         self.ingredients
             .neighbors_directed(self.source, petgraph::EdgeDirection::Outgoing)
+            .filter(|n| !self.forwarders.contains_key(n) && !self.ingredients[*n].is_dropped())
             .map(|n| {
                 let base = &self.ingredients[n];
                 assert!(base.is_base());
@@ -731,11 +1422,17 @@ impl ControllerInner {
     }
 
     fn find_view_for(&self, node: NodeIndex, name: &str) -> Option<NodeIndex> {
+        self.find_views_for(node, name).into_iter().next()
+    }
+
+    /// Find all the replicas of the reader called `name` for the given node.
+    fn find_views_for(&self, node: NodeIndex, name: &str) -> Vec<NodeIndex> {
         // reader should be a child of the given node. however, due to sharding, it may not be an
         // *immediate* child. furthermore, once we go beyond depth 1, we may accidentally hit an
         // *unrelated* reader node. to account for this, readers keep track of what node they are
         // "for", and we simply search for the appropriate reader by that metric. since we know
         // that the reader must be relatively close, a BFS search is the way to go.
+        let mut readers = Vec::new();
         let mut bfs = Bfs::new(&self.ingredients, node);
         while let Some(child) = bfs.next(&self.ingredients) {
             if self.ingredients[child]
@@ -743,10 +1440,30 @@ impl ControllerInner {
                 .unwrap_or(false)
                 && self.ingredients[child].name() == name
             {
-                return Some(child);
+                readers.push(child);
             }
         }
-        None
+        readers
+    }
+
+    /// The workers that host the other replicas of the given reader.
+    fn replica_workers(&self, reader: NodeIndex) -> Vec<WorkerIdentifier> {
+        let n = &self.ingredients[reader];
+        let is_for = n.with_reader(|r| r.is_for()).unwrap();
+        self.find_views_for(is_for, n.name())
+            .into_iter()
+            .filter(|&r| r != reader && self.ingredients[r].has_domain())
+            .filter_map(|r| self.domains.get(&self.ingredients[r].domain()))
+            .flat_map(|d| (0..d.shards()).map(move |i| d.assignment(i)))
+            .collect()
+    }
+
+    /// The addresses that the shards of the given reader serve reads on.
+    fn reader_addrs(&self, reader: NodeIndex) -> Vec<SocketAddr> {
+        let domain = &self.domains[&self.ingredients[reader].domain()];
+        (0..domain.shards())
+            .map(|i| self.read_addrs[&domain.assignment(i)])
+            .collect()
     }
 
     /// Obtain a `ViewBuilder` that can be sent to a client and then used to query a given
@@ -767,19 +1484,23 @@ impl ControllerInner {
             None => name,
             Some(alias) => alias,
         };
-        self.find_view_for(node, name).map(|r| {
-            let domain = self.ingredients[r].domain();
+        let mut readers = self.find_views_for(node, name).into_iter();
+        readers.next().map(|r| {
             let columns = self.ingredients[r].fields().to_vec();
             let schema = self.view_schema(r);
-            let shards = (0..self.domains[&domain].shards())
-                .map(|i| self.read_addrs[&self.domains[&domain].assignment(i)])
-                .collect();
+            let key = self.ingredients[r]
+                .with_reader(|r| r.key().map(Vec::from))
+                .ok()
+                .flatten()
+                .unwrap_or_default();
 
             ViewBuilder {
                 node: r,
                 columns,
                 schema,
-                shards,
+                key,
+                shards: self.reader_addrs(r),
+                replicas: readers.map(|r| (r, self.reader_addrs(r))).collect(),
             }
         })
     }
@@ -858,6 +1579,36 @@ impl ControllerInner {
             table_name: node.name().to_owned(),
             columns,
             schema,
+            column_specs: base_operator.column_specs().into_owned(),
+        })
+    }
+
+    /// Obtain a ViewBuilder that can be used to follow the change log of the given base node.
+    ///
+    /// Every shard of a base keeps its change log on the worker that hosts it, where it is served
+    /// alongside that worker's views.
+    fn changes_builder(&self, base: &str) -> Option<ViewBuilder> {
+        let ni = match self.recipe.node_addr_for(base) {
+            Ok(ni) => ni,
+            Err(_) => *self.inputs().get(base)?,
+        };
+        let node = &self.ingredients[ni];
+        if !node.is_base() {
+            return None;
+        }
+
+        let domain = node.domain();
+        let shards = (0..self.domains[&domain].shards())
+            .map(|i| self.read_addrs[&self.domains[&domain].assignment(i)])
+            .collect();
+
+        Some(ViewBuilder {
+            node: ni,
+            columns: node.fields().to_vec(),
+            schema: None,
+            key: Vec::new(),
+            shards,
+            replicas: Vec::new(),
         })
     }
 
@@ -871,6 +1622,7 @@ impl ControllerInner {
         let domains = self
             .domains
             .iter_mut()
+            .filter(|(_, s)| (0..s.shards()).all(|i| workers[&s.assignment(i)].healthy))
             .flat_map(|(&di, s)| {
                 trace!(log, "requesting stats from domain"; "di" => di.index());
                 s.send_to_healthy(Box::new(Packet::GetStatistics), workers)
@@ -885,13 +1637,1072 @@ impl ControllerInner {
         GraphStats { domains }
     }
 
-    fn get_instances(&self) -> Vec<(WorkerIdentifier, bool, Duration)> {
+    /// Find the base table or reader node called `name`.
+    fn snapshot_node(&self, name: &str) -> Option<NodeIndex> {
+        let node = match self.recipe.node_addr_for(name) {
+            Ok(ni) => ni,
+            Err(_) => match self.inputs().get(name) {
+                Some(&ni) => ni,
+                None => *self.outputs().get(name)?,
+            },
+        };
+        if self.ingredients[node].is_base() {
+            return Some(node);
+        }
+
+        let name = match self.recipe.resolve_alias(name) {
+            None => name,
+            Some(alias) => alias,
+        };
+        self.find_view_for(node, name)
+    }
+
+    /// Have every shard of the base table or fully materialized view called `name` pin a copy of
+    /// its rows, and return its columns along with the first page of rows.
+    ///
+    /// The shards of a view are copied at the same point: the bases upstream of the view hold back
+    /// writes while an empty transaction is committed through all of them, and each shard is
+    /// copied once it has applied that transaction. The shards of a base table need no such care,
+    /// since transactions are committed to all of them before the next request is handled.
+    ///
+    /// The copies stay with the domains, which hand them to the client a page at a time through
+    /// `snapshot_page`.
+    fn snapshot(&mut self, name: &str) -> Result<(Vec<String>, SnapshotPage), SnapshotError> {
+        let now = Instant::now();
+        self.snapshots
+            .retain(|_, s| now.duration_since(s.last_read) < SNAPSHOT_TIMEOUT);
+
+        let ni = self
+            .snapshot_node(name)
+            .ok_or_else(|| SnapshotError::NoSuchName(name.to_owned()))?;
+        let domain = self.ingredients[ni].domain();
+        let local = self.ingredients[ni].local_addr();
+        let id = self.next_snapshot;
+        self.next_snapshot += 1;
+
+        trace!(self.log, "taking snapshot"; "node" => ni.index());
+        let bases: Vec<_> = self.ingredients[ni]
+            .with_reader(|r| r.write_paths().keys().cloned().collect())
+            .unwrap_or_default();
+        let (after, held) = self.hold_bases(&bases)?;
+
+        let d = self.domains.get_mut(&domain).unwrap();
+        let shards = d.shards();
+        let mut asked = 0;
+        for shard in 0..shards {
+            let m = Box::new(Packet::PinSnapshot {
+                node: local,
+                id,
+                after: after.clone(),
+            });
+            if d.send_to_healthy_shard(shard, m, &self.workers).is_ok() {
+                asked += 1;
+            }
+        }
+        let pinned = futures_executor::block_on(self.replies.wait_for_pinned(asked));
+        self.release_bases(&held);
+
+        // copies pinned by the other shards are discarded by the domains once they expire
+        if asked != shards {
+            return Err(SnapshotError::Unavailable(name.to_owned()));
+        }
+        if !pinned.into_iter().all(|p| p) {
+            return Err(SnapshotError::NotMaterialized(name.to_owned()));
+        }
+
+        // clients never see the columns that have been dropped from a base table
+        let node = &self.ingredients[ni];
+        let dropped = node.get_base().map(|b| b.get_dropped()).unwrap_or_default();
+        let keep: Vec<_> = (0..node.fields().len())
+            .filter(|col| !dropped.contains_key(*col))
+            .collect();
+        let columns = keep.iter().map(|&col| node.fields()[col].clone()).collect();
+        let keep = if dropped.is_empty() { None } else { Some(keep) };
+
+        let s = PinnedSnapshot {
+            name: name.to_owned(),
+            domain,
+            shard: 0,
+            keep,
+            last_read: now,
+        };
+        self.snapshots.insert(id, s);
+        Ok((columns, self.snapshot_page(id)?))
+    }
+
+    /// Hand out the next page of rows of a snapshot, reading the shards one after the other.
+    fn snapshot_page(&mut self, id: u64) -> Result<SnapshotPage, SnapshotError> {
+        let now = Instant::now();
+        self.snapshots
+            .retain(|_, s| now.duration_since(s.last_read) < SNAPSHOT_TIMEOUT);
+
+        let s = self.snapshots.get_mut(&id).ok_or(SnapshotError::Expired)?;
+        s.last_read = now;
+        let d = self.domains.get_mut(&s.domain).unwrap();
+        let (mut rows, done) = loop {
+            let m = Box::new(Packet::SnapshotPage {
+                id,
+                rows: SNAPSHOT_PAGE_ROWS,
+            });
+            if d.send_to_healthy_shard(s.shard, m, &self.workers).is_err() {
+                let name = s.name.clone();
+                self.snapshots.remove(&id);
+                return Err(SnapshotError::Unavailable(name));
+            }
+            let (rows, shard_done) =
+                match futures_executor::block_on(self.replies.wait_for_snapshot_page()) {
+                    Some(page) => page,
+                    None => {
+                        self.snapshots.remove(&id);
+                        return Err(SnapshotError::Expired);
+                    }
+                };
+            if shard_done {
+                s.shard += 1;
+            }
+            let done = s.shard == d.shards();
+            if !rows.is_empty() || done {
+                break (rows, done);
+            }
+        };
+
+        if let Some(ref keep) = s.keep {
+            for row in &mut rows {
+                *row = keep.iter().map(|&col| row[col].clone()).collect();
+            }
+        }
+        if done {
+            self.snapshots.remove(&id);
+        }
+        Ok(SnapshotPage { id, rows, done })
+    }
+
+    /// Have every shard of the given bases hold back writes until `release_bases` is called,
+    /// once they have committed an empty transaction.
+    ///
+    /// Returns a token that covers the transaction, along with the held base shards.
+    fn hold_bases(
+        &mut self,
+        bases: &[NodeIndex],
+    ) -> Result<(WriteToken, Vec<(NodeIndex, usize)>), SnapshotError> {
+        if bases.is_empty() {
+            return Ok((WriteToken::default(), Vec::new()));
+        }
+
+        let txn = TransactionTag {
+            id: self.next_transaction,
+            bases: bases.to_vec(),
+        };
+        self.next_transaction = self.next_transaction.wrapping_add(1);
+
+        let mut held = Vec::new();
+        let mut unavailable = None;
+        'prepare: for &ni in bases {
+            let n = &self.ingredients[ni];
+            let d = self.domains.get_mut(&n.domain()).unwrap();
+            for shard in 0..d.shards() {
+                let m = Box::new(Packet::PrepareTransaction {
+                    node: n.local_addr(),
+                    txn: txn.clone(),
+                    data: Vec::new(),
+                });
+                if d.send_to_healthy_shard(shard, m, &self.workers).is_err() {
+                    unavailable = Some(n.name().to_owned());
+                    break 'prepare;
+                }
+                held.push((ni, shard));
+            }
+        }
+        futures_executor::block_on(self.replies.wait_for_prepared(held.len()));
+
+        let mut told = 0;
+        if unavailable.is_none() {
+            for &(ni, shard) in &held {
+                let n = &self.ingredients[ni];
+                let m = Box::new(Packet::CommitTransaction {
+                    node: n.local_addr(),
+                    hold: true,
+                });
+                let d = self.domains.get_mut(&n.domain()).unwrap();
+                match d.send_to_healthy_shard(shard, m, &self.workers) {
+                    Ok(()) => told += 1,
+                    Err(_) => unavailable = Some(n.name().to_owned()),
+                }
+            }
+        }
+        let mut token = WriteToken::default();
+        for t in futures_executor::block_on(self.replies.wait_for_commits(told)) {
+            token.merge(t);
+        }
+
+        match unavailable {
+            Some(name) => {
+                self.release_bases(&held);
+                Err(SnapshotError::Unavailable(name))
+            }
+            None => Ok((token, held)),
+        }
+    }
+
+    /// Let base shards held by `hold_bases` process writes again.
+    fn release_bases(&mut self, held: &[(NodeIndex, usize)]) {
+        let mut told = 0;
+        for &(ni, shard) in held {
+            let n = &self.ingredients[ni];
+            let m = Box::new(Packet::AbortTransaction {
+                node: n.local_addr(),
+            });
+            let d = self.domains.get_mut(&n.domain()).unwrap();
+            if d.send_to_healthy_shard(shard, m, &self.workers).is_ok() {
+                told += 1;
+            }
+        }
+        futures_executor::block_on(self.replies.wait_for_n_acks(told));
+    }
+
+    /// Split the base table or query called `name`, and everything downstream of it, across
+    /// `shards` shards. A base table without a primary key is sharded by `column` if one is given.
+    ///
+    /// The nodes that take over are built next to the ones they replace, which keep serving
+    /// reads and writes in the meantime. A base ships its rows to the new base, followed by every
+    /// write it applies, and once everything downstream of the new base is filled, it passes the
+    /// writes it receives on instead. The new readers then serve the reads sent to the old ones
+    /// wherever they have the same key and their shards are on the same workers, and the old
+    /// nodes are removed, all but the old base, which keeps passing on the writes of handles
+    /// opened before. Replies to the writes it passes on are held back until the switch is done,
+    /// so that no client sees a write the readers it reads from do not reflect yet.
+    fn reshard<A: Authority + 'static>(
+        &mut self,
+        authority: &Arc<A>,
+        name: &str,
+        column: Option<&str>,
+        shards: usize,
+    ) -> Result<(), ReshardError> {
+        let no_such = || ReshardError::NoSuchTable(name.to_owned());
+        let base = self.inputs().get(name).cloned();
+        let ni = match base {
+            Some(ni) => ni,
+            None => self.recipe.node_addr_for(name).map_err(|_| no_such())?,
+        };
+        let is_base = base.is_some();
+        let n = &self.ingredients[ni];
+        if n.is_dropped() {
+            return Err(no_such());
+        }
+        let current = match n.sharded_by() {
+            Sharding::ByColumn(column, shards) => (column, shards),
+            _ => return Err(ReshardError::NotSharded(name.to_owned())),
+        };
+        if shards < 2 {
+            return Err(ReshardError::TooFewShards(shards));
+        }
+        if let Some(column) = column {
+            let c =
+                n.fields().iter().position(|f| f == column).ok_or_else(|| {
+                    ReshardError::NoSuchColumn(name.to_owned(), column.to_owned())
+                })?;
+            match n.get_base() {
+                Some(b) if b.key().is_none() && b.can_shard_by(c) => {}
+                _ => {
+                    return Err(ReshardError::CannotShardBy(
+                        name.to_owned(),
+                        column.to_owned(),
+                    ))
+                }
+            }
+        }
+        let unchanged = match self.shardings.get(name) {
+            Some(o) => o.column.as_deref() == column && o.shards == shards,
+            None => column.map_or(true, |c| n.fields()[current.0] == c) && current.1 == shards,
+        };
+        if unchanged {
+            return Ok(());
+        }
+
+        info!(self.log, "resharding node";
+              "name" => name,
+              "node" => ni.index(),
+              "shards" => shards);
+        let previous = self.shardings.get(name).cloned();
+        let generation = match n.get_base() {
+            Some(b) => b.generation() + 1,
+            None => previous.as_ref().map_or(0, |o| o.generation),
+        };
+        let sharding = ShardingOverride {
+            column: column.map(str::to_owned),
+            shards,
+            generation,
+        };
+        self.shardings.insert(name.to_owned(), sharding);
+
+        // everything downstream of the node is built again, and for a base, over a new base
+        let old_nodes: Vec<_> = self
+            .downstream_of(vec![ni])
+            .into_iter()
+            .filter(|&n| !self.ingredients[n].is_dropped())
+            .filter(|&n| !is_base || n != ni)
+            .collect();
+        let old_readers = self.readers_by_name(&old_nodes);
+        let required: Vec<_> = old_nodes
+            .iter()
+            .cloned()
+            .filter(|&n| self.recipe.sql_inc().is_leaf_address(n))
+            .collect();
+        let affected: HashSet<_> = old_nodes.iter().cloned().collect();
+        if is_base {
+            self.restoring.insert(name.to_owned(), Restore::Ship(ni));
+        }
+
+        let mut recipe = mem::replace(&mut self.recipe, Recipe::blank(None));
+        let r = self.migrate_or_abort(|mig| {
+            let replace = if is_base {
+                let n = &mig.mainline.ingredients[ni];
+                let fields: Vec<_> = n.fields().to_vec();
+                let b = n.get_base().unwrap().clone();
+                Some((ni, mig.add_base(name, fields, b)))
+            } else {
+                None
+            };
+            let rebuilt = recipe
+                .rebuild_nodes(&affected, replace, &required, mig)
+                .ok_or_else(|| ReshardError::CannotRebuild(name.to_owned()))?;
+            let root = match replace {
+                Some((_, new)) => new,
+                None => rebuilt[&ni],
+            };
+
+            // the new readers go on the workers of the readers they take over from
+            let pairs = mig.mainline.reader_pairs(&old_readers, root);
+            for (old, new) in pairs {
+                let d = &mig.mainline.domains[&mig.mainline.ingredients[old].domain()];
+                let workers = (0..d.shards()).map(|s| (s, d.assignment(s))).collect();
+                mig.mainline.placement.insert(new, workers);
+            }
+            Ok(root)
+        });
+        self.recipe = recipe;
+        self.placement.clear();
+        let root = match r {
+            Ok(root) => root,
+            Err(e) => {
+                self.restoring.remove(name);
+                match previous {
+                    Some(o) => self.shardings.insert(name.to_owned(), o),
+                    None => self.shardings.remove(name),
+                };
+                return Err(e);
+            }
+        };
+        self.finish_restore();
+
+        // stop applying writes at the old base, and find out which writes the new one got from it
+        let token = if is_base {
+            let n = &self.ingredients[ni];
+            let m = Packet::ForwardBase {
+                node: n.local_addr(),
+                hold: true,
+            };
+            let d = self.domains.get_mut(&n.domain()).unwrap();
+            d.send_to_healthy(Box::new(m), &self.workers).unwrap();
+            let n = d.shards();
+            Some(futures_executor::block_on(
+                self.replies.wait_for_forwarded(n),
+            ))
+        } else {
+            None
+        };
+
+        let aliased: Vec<_> = self
+            .reader_pairs(&old_readers, root)
+            .into_iter()
+            .filter(|&(old, new)| {
+                let key = |ni: NodeIndex| {
+                    self.ingredients[ni]
+                        .with_reader(|r| r.key().map(<[usize]>::to_vec))
+                        .unwrap()
+                };
+                let o = &self.domains[&self.ingredients[old].domain()];
+                let n = &self.domains[&self.ingredients[new].domain()];
+                key(old) == key(new)
+                    && o.shards() == n.shards()
+                    && (0..o.shards()).all(|s| o.assignment(s) == n.assignment(s))
+            })
+            .collect();
+
+        // readers only take over once they reflect every write the old base applied
+        if let Some(token) = token {
+            let mut told = 0;
+            for &(_, new) in &aliased {
+                let n = &self.ingredients[new];
+                let m = Packet::AwaitApplied {
+                    node: n.local_addr(),
+                    token: token.clone(),
+                };
+                let d = self.domains.get_mut(&n.domain()).unwrap();
+                d.send_to_healthy(Box::new(m), &self.workers).unwrap();
+                told += d.shards();
+            }
+            futures_executor::block_on(self.replies.wait_for_n_acks(told));
+        }
+
+        // handles to the old readers, or to the readers those took over from, read from the new
+        // readers from now on
+        let mut told = 0;
+        for &(old, new) in &aliased {
+            let mut aliases = self.reader_aliases.remove(&old).unwrap_or_default();
+            aliases.push(old);
+            let n = &self.ingredients[new];
+            let m = Packet::AliasReader {
+                node: n.local_addr(),
+                aliases: aliases.clone(),
+            };
+            let d = self.domains.get_mut(&n.domain()).unwrap();
+            d.send_to_healthy(Box::new(m), &self.workers).unwrap();
+            told += d.shards();
+            self.reader_aliases.insert(new, aliases);
+        }
+        futures_executor::block_on(self.replies.wait_for_n_acks(told));
+
+        if is_base {
+            let n = &self.ingredients[ni];
+            let m = Packet::ForwardBase {
+                node: n.local_addr(),
+                hold: false,
+            };
+            self.domains
+                .get_mut(&n.domain())
+                .unwrap()
+                .send_to_healthy(Box::new(m), &self.workers)
+                .unwrap();
+            self.forwarders.insert(ni, root);
+        }
+
+        // the old nodes go away leaves first
+        let mut removals = Vec::with_capacity(old_nodes.len());
+        let mut topo = petgraph::visit::Topo::new(&self.ingredients);
+        while let Some(node) = topo.next(&self.ingredients) {
+            if affected.contains(&node) {
+                removals.push(node);
+            }
+        }
+        removals.reverse();
+        for &node in &removals {
+            self.reader_aliases.remove(&node);
+            let parents: Vec<_> = self
+                .ingredients
+                .neighbors_directed(node, petgraph::EdgeDirection::Incoming)
+                .collect();
+            for parent in parents {
+                let edge = self.ingredients.find_edge(parent, node).unwrap();
+                self.ingredients.remove_edge(edge);
+            }
+        }
+        self.remove_nodes(&removals).unwrap();
+
+        if authority
+            .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
+                None => unreachable!(),
+                Some(ref state) if state.epoch > self.epoch => Err(()),
+                Some(mut state) => {
+                    state.shardings = self.shardings.clone();
+                    Ok(state)
+                }
+            })
+            .is_err()
+        {
+            return Err(ReshardError::NotPersisted(name.to_owned()));
+        }
+        Ok(())
+    }
+
+    /// The readers among the given nodes, by the name of the view they are for.
+    fn readers_by_name(&self, nodes: &[NodeIndex]) -> HashMap<String, Vec<NodeIndex>> {
+        let mut readers: HashMap<String, Vec<NodeIndex>> = HashMap::new();
+        for &ni in nodes {
+            let n = &self.ingredients[ni];
+            if n.is_reader() && !n.is_dropped() {
+                readers.entry(n.name().to_owned()).or_default().push(ni);
+            }
+        }
+        for replicas in readers.values_mut() {
+            replicas.sort();
+        }
+        readers
+    }
+
+    /// Pair each of the given readers with the reader of the same view, or replica of it,
+    /// downstream of `root` that takes over from it.
+    fn reader_pairs(
+        &self,
+        old: &HashMap<String, Vec<NodeIndex>>,
+        root: NodeIndex,
+    ) -> Vec<(NodeIndex, NodeIndex)> {
+        let new = self.readers_by_name(&self.downstream_of(vec![root]));
+        let mut pairs: Vec<_> = old
+            .iter()
+            .filter_map(|(name, old)| new.get(name).map(|new| old.iter().zip(new)))
+            .flatten()
+            .map(|(&old, &new)| (old, new))
+            .collect();
+        pairs.sort();
+        pairs
+    }
+
+    /// Move the given shard of a domain to `worker`, as with `move_shards`.
+    fn move_domain(
+        &mut self,
+        domain: DomainIndex,
+        shard: usize,
+        worker: WorkerIdentifier,
+    ) -> Result<(), MoveDomainError> {
+        let nodes: Vec<_> = match self.domains.get(&domain) {
+            Some(d) if shard < d.shards() => self.domain_nodes[&domain]
+                .iter()
+                .cloned()
+                .filter(|&ni| !self.ingredients[ni].is_dropped())
+                .collect(),
+            _ => Vec::new(),
+        };
+        match self.workers.get(&worker) {
+            Some(w) if w.healthy && !w.draining => {}
+            _ => return Err(MoveDomainError::NoSuchWorker(worker)),
+        }
+        if nodes.is_empty() {
+            return Err(MoveDomainError::NoSuchDomain {
+                domain: domain.index(),
+                shard,
+            });
+        }
+        if self.domains[&domain].assignment(shard) == worker {
+            return Ok(());
+        }
+
+        if self.move_shards(vec![((domain, shard), worker)]).is_empty() {
+            Ok(())
+        } else {
+            Err(MoveDomainError::Busy {
+                domain: domain.index(),
+                shard,
+            })
+        }
+    }
+
+    /// Move each of the given domain shards to the given worker, and return the ones that could
+    /// not be moved because they were busy, or their worker failed.
+    ///
+    /// A copy of each shard is started on its new worker and given everything the shard held
+    /// except for partially materialized state, which is filled again as it is asked for. The
+    /// shards that send to the shards are then told to send to the copies instead, all at once.
+    /// The old copies pass on what is still sent to them until the new copies have taken over,
+    /// and are then retired with `retire_relays`, so that handles to their base tables fetch
+    /// where the shards went, while views of their readers must be fetched again.
+    fn move_shards(
+        &mut self,
+        moves: Vec<((DomainIndex, usize), WorkerIdentifier)>,
+    ) -> Vec<(DomainIndex, usize)> {
+        let mut left = Vec::new();
+        let mut moving = Vec::new();
+        for ((domain, shard), worker) in moves {
+            info!(self.log, "moving domain";
+                  "domain" => domain.index(),
+                  "shard" => shard,
+                  "worker" => ?worker);
+
+            // the copy sends the results of writes passed on through the shard to it under an
+            // identifier of its own, since the copy takes over the identifier of the shard
+            let alias = DomainIndex::from(self.ndomains);
+            self.ndomains += 1;
+            let m = Box::new(Packet::MoveOut { alias });
+            if self
+                .domains
+                .get_mut(&domain)
+                .unwrap()
+                .send_to_healthy_shard(shard, m, &self.workers)
+                .is_err()
+            {
+                self.ndomains -= 1;
+                left.push((domain, shard));
+                continue;
+            }
+            let (nodes, moved) = match futures_executor::block_on(self.replies.wait_for_moved_out())
+            {
+                Some(moved) => moved,
+                None => {
+                    // the shard carries on as if it had never been asked to move, and has not
+                    // used the alias for anything, so only the alias needs to be given back
+                    warn!(self.log, "domain is too busy to move";
+                              "domain" => domain.index(),
+                              "shard" => shard);
+                    self.ndomains -= 1;
+                    left.push((domain, shard));
+                    continue;
+                }
+            };
+
+            let nshards = self.domains[&domain].shards();
+            let builder = DomainBuilder {
+                index: domain,
+                shard: if nshards > 1 { Some(shard) } else { None },
+                nshards,
+                config: self.domain_config.clone(),
+                nodes,
+                persistence_parameters: self.persistence.clone(),
+                backup_of: None,
+                moving_in: true,
+            };
+            let w = self.workers.get_mut(&worker).unwrap();
+            let src = w.sender.local_addr().unwrap();
+            w.sender
+                .send(CoordinationMessage {
+                    epoch: self.epoch,
+                    source: src,
+                    payload: CoordinationPayload::AssignDomain(builder),
+                })
+                .unwrap();
+            let to = match futures_executor::block_on(self.replies.read_n_domain_replies(1)).pop() {
+                Some(ControlReplyPacket::Booted(_, addr)) => addr,
+                crp => unreachable!("got unexpected control reply packet: {:?}", crp),
+            };
+            moving.push(((domain, shard), worker, alias, to, moved));
+        }
+        if moving.is_empty() {
+            return left;
+        }
+
+        // the old copies of the shards now go by their aliases, and the new copies by the
+        // identifiers of the shards
+        let mut relays = Vec::with_capacity(moving.len());
+        let mut announce = Vec::with_capacity(2 * moving.len());
+        for &((domain, shard), worker, alias, to, _) in &moving {
+            let old = self.channel_coordinator.get_addr(&(domain, shard)).unwrap();
+            self.channel_coordinator.insert_remote((domain, shard), to);
+            self.channel_coordinator.insert_remote((alias, 0), old);
+            let tx = self
+                .channel_coordinator
+                .builder_for(&(domain, shard))
+                .unwrap()
+                .build_sync()
+                .unwrap();
+            relays.push(mem::replace(
+                &mut self.domains.get_mut(&domain).unwrap().shards[shard],
+                DomainShardHandle { worker, tx },
+            ));
+            announce.push(DomainDescriptor::new(domain, shard, to));
+            announce.push(DomainDescriptor::new(alias, 0, old));
+        }
+        for endpoint in self.workers.values_mut() {
+            for &dd in &announce {
+                endpoint
+                    .sender
+                    .send(CoordinationMessage {
+                        epoch: self.epoch,
+                        source: endpoint.sender.local_addr().unwrap(),
+                        payload: CoordinationPayload::DomainBooted(dd),
+                    })
+                    .unwrap();
+            }
+        }
+
+        // every other shard sends what it has yet to send to the shards that moved through their
+        // old copies, followed by a last packet that tells each new copy it has heard everything
+        // from it that way. the old copies do the same, and then pass on what they were sent.
+        let moves: Vec<_> = moving.iter().map(|m| (m.0, m.3)).collect();
+        let moved: HashSet<_> = moves.iter().map(|&(ri, _)| ri).collect();
+        let mut senders = 0;
+        for d in self
+            .domains
+            .values_mut()
+            .chain(self.backups.values_mut().flatten())
+        {
+            for i in 0..d.shards() {
+                if moved.contains(&(d.index(), i)) {
+                    continue;
+                }
+                let m = Box::new(Packet::Reroute {
+                    moves: moves.clone(),
+                });
+                if d.send_to_healthy_shard(i, m, &self.workers).is_ok() {
+                    senders += 1;
+                }
+            }
+        }
+        for mut relay in relays {
+            let m = Box::new(Packet::Relay {
+                moves: moves.clone(),
+            });
+            if relay.tx.send(m).is_ok() {
+                senders += 1;
+            }
+            self.relays.push(relay);
+        }
+
+        for ((domain, shard), _, _, _, moved) in moving {
+            let m = Box::new(Packet::MoveIn {
+                moved,
+                senders,
+                moves: moves.clone(),
+            });
+            self.domains
+                .get_mut(&domain)
+                .unwrap()
+                .send_to_healthy_shard(shard, m, &self.workers)
+                .unwrap();
+        }
+        for _ in 0..moves.len() {
+            futures_executor::block_on(self.replies.wait_for_moved_in());
+        }
+
+        // nothing but clients sends to the old copies any more
+        self.retire_relays();
+        left
+    }
+
+    /// Retire the old copies of shards that moved to other workers.
+    ///
+    /// Each old copy stops passing on writes from clients, and quits once the copy that took over
+    /// has applied those it did pass on. The clients that are still connected to it find their
+    /// connections closed, and fetch where the shard went before they write again. Old copies on
+    /// failed workers are gone already, and those that cannot be reached are kept to be retired
+    /// the next time around.
+    fn retire_relays(&mut self) {
+        let mut retiring = Vec::new();
+        for mut relay in mem::take(&mut self.relays) {
+            if !self.workers.get(&relay.worker).map_or(false, |w| w.healthy) {
+                continue;
+            }
+            if relay.tx.send(Box::new(Packet::RetireRelay)).is_ok() {
+                retiring.push(relay);
+            } else {
+                self.relays.push(relay);
+            }
+        }
+        if retiring.is_empty() {
+            return;
+        }
+
+        futures_executor::block_on(self.replies.wait_for_relays_retired(retiring.len()));
+        for mut relay in retiring {
+            // the old copy has sent every client how its writes went before it replied
+            drop(relay.tx.send(Box::new(Packet::Quit)));
+        }
+    }
+
+    /// Move one domain shard from the busiest worker to the least busy one, if the work is spread
+    /// unevenly enough.
+    fn rebalance(&mut self) {
+        let healthy: Vec<_> = self
+            .workers
+            .iter()
+            .filter(|(_, w)| w.healthy && !w.draining)
+            .map(|(&wi, _)| wi)
+            .collect();
+        if healthy.len() < 2 {
+            return;
+        }
+
+        // the work of a domain shard is its share of all state plus its share of all processing
+        let stats = self.get_statistics();
+        let size = |nodes: &HashMap<NodeIndex, NodeStats>| -> u64 {
+            nodes.values().map(|n| n.mem_size).sum()
+        };
+        let total_size: u64 = stats.values().map(|(_, nodes)| size(nodes)).sum();
+        let total_time: u64 = stats.values().map(|(d, _)| d.total_ptime).sum();
+        let share = |part: u64, total: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                part as f64 / total as f64
+            }
+        };
+
+        let mut load: HashMap<WorkerIdentifier, f64> =
+            healthy.iter().map(|&wi| (wi, 0.0)).collect();
+        let mut candidates = Vec::new();
+        for (&(di, shard), (d, nodes)) in stats.iter() {
+            let worker = self.domains[&di].assignment(shard);
+            let work = share(size(nodes), total_size) + share(d.total_ptime, total_time);
+            if let Some(l) = load.get_mut(&worker) {
+                *l += work;
+                candidates.push((di, shard, worker, work));
+            }
+        }
+
+        let by_load = |a: &(&WorkerIdentifier, &f64), b: &(&WorkerIdentifier, &f64)| {
+            a.1.partial_cmp(b.1).unwrap_or(cmp::Ordering::Equal)
+        };
+        let (&busiest, &max) = load.iter().max_by(by_load).unwrap();
+        let (&idlest, &min) = load.iter().min_by(by_load).unwrap();
+        let gap = max - min;
+        if gap < REBALANCE_THRESHOLD {
+            return;
+        }
+
+        // moving a domain that does less work than the gap narrows it, and the closer its work is
+        // to half the gap, the more so.
+        let pick = candidates
+            .into_iter()
+            .filter(|&(_, _, worker, work)| worker == busiest && work > 0.0 && work < gap)
+            .min_by(|a, b| {
+                let (a, b) = ((a.3 - gap / 2.0).abs(), (b.3 - gap / 2.0).abs());
+                a.partial_cmp(&b).unwrap_or(cmp::Ordering::Equal)
+            });
+        if let Some((di, shard, _, work)) = pick {
+            info!(self.log, "rebalancing workers";
+                  "from" => ?busiest,
+                  "to" => ?idlest,
+                  "domain" => di.index(),
+                  "shard" => shard,
+                  "work" => work);
+            if let Err(e) = self.move_domain(di, shard, idlest) {
+                warn!(self.log, "failed to move domain: {}", e);
+            }
+        }
+    }
+
+    fn get_instances(&self) -> Vec<(WorkerIdentifier, WorkerState, Duration)> {
         self.workers
             .iter()
-            .map(|(&id, ref status)| (id, status.healthy, status.last_heartbeat.elapsed()))
+            .map(|(&id, ref status)| {
+                let state = if status.draining && !self.holds_domains(&id) {
+                    WorkerState::Drained
+                } else if !status.healthy {
+                    WorkerState::Failed
+                } else if status.draining {
+                    WorkerState::Draining
+                } else {
+                    WorkerState::Healthy
+                };
+                (id, state, status.last_heartbeat.elapsed())
+            })
             .collect()
     }
 
+    /// Whether the given worker holds any domain shard that has nodes, that is a backup, or that
+    /// is the old copy of a shard that moved off it and has yet to be retired.
+    fn holds_domains(&self, worker: &WorkerIdentifier) -> bool {
+        let live = self.domains.iter().any(|(di, d)| {
+            d.assigned_to_worker(worker)
+                && self.domain_nodes[di]
+                    .iter()
+                    .any(|&ni| !self.ingredients[ni].is_dropped())
+        });
+        live || self
+            .backups
+            .values()
+            .flatten()
+            .any(|d| d.assigned_to_worker(worker))
+            || self.relays.iter().any(|r| r.worker == *worker)
+    }
+
+    /// Move all domain shards off the given worker, and stop placing new ones on it.
+    ///
+    /// Backups on the worker are replaced by new ones elsewhere, and the domain shards on it are
+    /// moved all at once, each to whichever other worker holds the fewest domain shards. Their old
+    /// copies on the worker are then retired. Shards that are too busy to move, and old copies
+    /// that cannot be retired yet, stay behind, and draining the worker again deals with them too.
+    fn drain(&mut self, worker: WorkerIdentifier) -> Result<(), DrainError> {
+        match self.workers.get(&worker) {
+            Some(w) if w.healthy => {}
+            _ => return Err(DrainError::NoSuchWorker(worker)),
+        }
+        if !self
+            .workers
+            .iter()
+            .any(|(&wi, w)| wi != worker && w.healthy && !w.draining)
+        {
+            return Err(DrainError::NoOtherWorker(worker));
+        }
+
+        info!(self.log, "draining worker"; "worker" => ?worker);
+        self.workers.get_mut(&worker).unwrap().draining = true;
+        let log = self.log.clone();
+
+        let mut primaries: Vec<_> = self.backups.keys().cloned().collect();
+        primaries.sort();
+        for primary in primaries {
+            let (leaving, backups): (Vec<_>, Vec<_>) = self
+                .backups
+                .remove(&primary)
+                .unwrap()
+                .into_iter()
+                .partition(|d| d.assigned_to_worker(&worker));
+            let lost = leaving.len();
+            self.replace_backups(primary, backups, lost, &log);
+            for mut d in leaving {
+                d.send_to_healthy(Box::new(Packet::Quit), &self.workers)
+                    .unwrap();
+            }
+        }
+
+        // every shard goes to whichever other worker holds the fewest shards at that point
+        let mut held: HashMap<_, usize> = self
+            .workers
+            .iter()
+            .filter(|(_, w)| w.healthy && !w.draining)
+            .map(|(&wi, _)| (wi, 0))
+            .collect();
+        for d in self.domains.values() {
+            for shard in 0..d.shards() {
+                if let Some(n) = held.get_mut(&d.assignment(shard)) {
+                    *n += 1;
+                }
+            }
+        }
+        let mut moves = Vec::new();
+        for (&di, d) in &self.domains {
+            if self.domain_nodes[&di]
+                .iter()
+                .all(|&ni| self.ingredients[ni].is_dropped())
+            {
+                continue;
+            }
+            for shard in 0..d.shards() {
+                if d.assignment(shard) == worker {
+                    let (&to, n) = held.iter_mut().min_by_key(|(_, n)| **n).unwrap();
+                    *n += 1;
+                    moves.push(((di, shard), to));
+                }
+            }
+        }
+        moves.sort_by_key(|&(ri, _)| ri);
+
+        let left = self.move_shards(moves);
+        // old copies left behind by earlier moves are retired as well
+        self.retire_relays();
+        let relays = self.relays.iter().filter(|r| r.worker == worker).count();
+        if !left.is_empty() || relays > 0 {
+            warn!(log, "draining worker still holds domains";
+                  "worker" => ?worker,
+                  "shards" => left.len(),
+                  "relays" => relays);
+            return Err(DrainError::StillHoldsDomains(worker));
+        }
+        info!(log, "worker drained"; "worker" => ?worker);
+        Ok(())
+    }
+
+    /// Stop draining the given worker, so that it may be given new domain shards again.
+    ///
+    /// Shards that were already moved off the worker stay where they are.
+    fn cancel_drain(&mut self, worker: WorkerIdentifier) -> Result<(), DrainError> {
+        match self.workers.get_mut(&worker) {
+            Some(w) if w.healthy => {
+                info!(self.log, "no longer draining worker"; "worker" => ?worker);
+                w.draining = false;
+                Ok(())
+            }
+            _ => Err(DrainError::NoSuchWorker(worker)),
+        }
+    }
+
+    /// Apply all the writes of a transaction, or none of them.
+    ///
+    /// Every shard of every base the transaction writes to first checks its operations against
+    /// the rows it holds, and holds back any other writes until told what to do with the
+    /// transaction. Only if every operation can be applied are the bases told to apply them;
+    /// otherwise they are all discarded, and the operations that could have been applied are
+    /// reported as `Aborted`. Transactions are committed one at a time, so bases never wait on one
+    /// another.
+    ///
+    /// If a worker fails after every base shard has prepared, the shards on the remaining workers
+    /// still apply their writes.
+    fn commit_transaction(
+        &mut self,
+        writes: TransactionWrites,
+    ) -> Result<(WriteToken, Vec<Vec<Vec<OperationResult>>>), TransactionError> {
+        let mut targets = Vec::with_capacity(writes.tables.len());
+        for (name, ni, ops) in writes.tables {
+            let n = match self.ingredients.node_weight(ni) {
+                Some(n) if n.is_base() && !n.is_dropped() => n,
+                _ => return Err(TransactionError::NoSuchTable(name)),
+            };
+            if ops.len() != self.domains[&n.domain()].shards() || self.forwarders.contains_key(&ni)
+            {
+                return Err(TransactionError::StaleTable(name));
+            }
+            targets.push((name, ni, n.domain(), n.local_addr(), ops));
+        }
+
+        let txn = TransactionTag {
+            id: self.next_transaction,
+            bases: targets.iter().map(|&(_, ni, ..)| ni).collect(),
+        };
+        self.next_transaction = self.next_transaction.wrapping_add(1);
+
+        let mut results: Vec<Vec<Vec<OperationResult>>> = targets
+            .iter()
+            .map(|(.., ops)| vec![Vec::new(); ops.len()])
+            .collect();
+
+        // have every base shard check its operations
+        let mut sent = Vec::new();
+        let mut unavailable = None;
+        'prepare: for (t, (name, _, domain, node, ops)) in targets.iter_mut().enumerate() {
+            let domain = self.domains.get_mut(domain).unwrap();
+            for (shard, data) in ops.drain(..).enumerate() {
+                let m = Box::new(Packet::PrepareTransaction {
+                    node: *node,
+                    txn: txn.clone(),
+                    data,
+                });
+                if domain
+                    .send_to_healthy_shard(shard, m, &self.workers)
+                    .is_err()
+                {
+                    unavailable = Some(name.clone());
+                    break 'prepare;
+                }
+                sent.push((t, shard));
+            }
+        }
+
+        let prepared = futures_executor::block_on(self.replies.wait_for_prepared(sent.len()));
+        for (ni, shard, rs) in prepared {
+            let t = targets.iter().position(|&(_, n, ..)| n == ni).unwrap();
+            results[t][shard] = rs;
+        }
+        let commit = unavailable.is_none()
+            && results
+                .iter()
+                .flatten()
+                .flatten()
+                .all(OperationResult::is_applied);
+
+        // then tell them all what to do with the transaction
+        let mut token = WriteToken::default();
+        let mut told = 0;
+        for &(t, shard) in &sent {
+            let (_, _, domain, node, _) = targets[t];
+            let m = if commit {
+                Packet::CommitTransaction { node, hold: false }
+            } else {
+                Packet::AbortTransaction { node }
+            };
+            let domain = self.domains.get_mut(&domain).unwrap();
+            match domain.send_to_healthy_shard(shard, Box::new(m), &self.workers) {
+                Ok(()) => told += 1,
+                Err(_) => unavailable = Some(targets[t].0.clone()),
+            }
+        }
+        if commit {
+            for t in futures_executor::block_on(self.replies.wait_for_commits(told)) {
+                token.merge(t);
+            }
+        } else {
+            futures_executor::block_on(self.replies.wait_for_n_acks(told));
+            for r in results.iter_mut().flatten().flatten() {
+                if r.is_applied() {
+                    *r = OperationResult::Aborted;
+                }
+            }
+        }
+
+        match unavailable {
+            Some(name) => Err(TransactionError::Unavailable(name)),
+            None => Ok((token, results)),
+        }
+    }
+
     fn flush_partial(&mut self) -> u64 {
         // get statistics for current domain sizes
         // and evict all state from partial nodes
@@ -1006,11 +2817,8 @@ impl ControllerInner {
         Ok(())
     }
 
-    fn apply_recipe(&mut self, mut new: Recipe) -> Result<ActivationResult, String> {
-        let r = self.migrate(|mig| {
-            new.activate(mig)
-                .map_err(|e| format!("failed to activate recipe: {}", e))
-        });
+    fn apply_recipe(&mut self, mut new: Recipe) -> Result<ActivationResult, RecipeError> {
+        let r = self.migrate_or_abort(|mig| new.activate(mig));
 
         match r {
             Ok(ref ra) => {
@@ -1031,7 +2839,7 @@ impl ControllerInner {
                 topo_removals.reverse();
 
                 for leaf in topo_removals {
-                    self.remove_leaf(leaf)?;
+                    self.remove_leaf(leaf).map_err(RecipeError::new)?;
                 }
 
                 // now remove bases
@@ -1052,15 +2860,30 @@ impl ControllerInner {
                     );
                     // now drop the (orphaned) base
                     self.remove_nodes(vec![base].as_slice()).unwrap();
+
+                    // along with the bases it took over from, which only passed writes on to it
+                    let mut taken_over = vec![base];
+                    while let Some(to) = taken_over.pop() {
+                        let from: Vec<_> = self
+                            .forwarders
+                            .iter()
+                            .filter(|&(_, &t)| t == to)
+                            .map(|(&from, _)| from)
+                            .collect();
+                        for &from in &from {
+                            self.forwarders.remove(&from);
+                        }
+                        self.remove_nodes(&from).unwrap();
+                        taken_over.extend(from);
+                    }
                 }
 
                 self.recipe = new;
             }
             Err(ref e) => {
                 crit!(self.log, "failed to apply recipe: {}", e);
-                // TODO(malte): a little yucky, since we don't really need the blank recipe
-                let recipe = mem::replace(&mut self.recipe, Recipe::blank(None));
-                self.recipe = recipe.revert();
+                // the migration was aborted, so go back to the recipe that is still running
+                self.recipe = new.revert();
             }
         }
 
@@ -1071,12 +2894,13 @@ impl ControllerInner {
         &mut self,
         authority: &Arc<A>,
         add_txt: String,
-    ) -> Result<ActivationResult, String> {
+    ) -> Result<ActivationResult, RecipeError> {
         // needed because self.apply_recipe needs to mutate self.recipe, so can't have it borrowed
         let new = mem::replace(&mut self.recipe, Recipe::blank(None));
         match new.extend(&add_txt) {
             Ok(new) => {
-                let activation_result = self.apply_recipe(new);
+                let activation_result = self.apply_recipe(new)?;
+                self.forget_removed_shardings();
                 if authority
                     .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
                         None => unreachable!(),
@@ -1084,21 +2908,22 @@ impl ControllerInner {
                         Some(mut state) => {
                             state.recipe_version = self.recipe.version();
                             state.recipes.push(add_txt.clone());
+                            state.shardings = self.shardings.clone();
                             Ok(state)
                         }
                     })
                     .is_err()
                 {
-                    return Err("Failed to persist recipe extension".to_owned());
+                    return Err(RecipeError::new("failed to persist recipe extension"));
                 }
 
-                activation_result
+                Ok(activation_result)
             }
             Err((old, e)) => {
                 // need to restore the old recipe
-                crit!(self.log, "failed to extend recipe: {:?}", e);
+                crit!(self.log, "failed to extend recipe: {}", e);
                 self.recipe = old;
-                Err("failed to extend recipe".to_owned())
+                Err(e)
             }
         }
     }
@@ -1107,12 +2932,13 @@ impl ControllerInner {
         &mut self,
         authority: &Arc<A>,
         r_txt: String,
-    ) -> Result<ActivationResult, String> {
+    ) -> Result<ActivationResult, RecipeError> {
         match Recipe::from_str(&r_txt, Some(self.log.clone())) {
             Ok(r) => {
                 let old = mem::replace(&mut self.recipe, Recipe::blank(None));
                 let new = old.replace(r).unwrap();
-                let activation_result = self.apply_recipe(new);
+                let activation_result = self.apply_recipe(new)?;
+                self.forget_removed_shardings();
                 if authority
                     .read_modify_write(STATE_KEY, |state: Option<ControllerState>| match state {
                         None => unreachable!(),
@@ -1120,29 +2946,43 @@ impl ControllerInner {
                         Some(mut state) => {
                             state.recipe_version = self.recipe.version();
                             state.recipes = vec![r_txt.clone()];
+                            state.shardings = self.shardings.clone();
                             Ok(state)
                         }
                     })
                     .is_err()
                 {
-                    return Err("Failed to persist recipe installation".to_owned());
+                    return Err(RecipeError::new("failed to persist recipe installation"));
                 }
-                activation_result
+                Ok(activation_result)
             }
             Err(e) => {
-                crit!(self.log, "failed to parse recipe: {:?}", e);
-                Err("failed to parse recipe".to_owned())
+                crit!(self.log, "failed to parse recipe: {}", e);
+                Err(e)
             }
         }
     }
 
+    /// Forget how tables and queries that are gone were resharded, so that they are split as
+    /// usual if they are added again.
+    fn forget_removed_shardings(&mut self) {
+        let live: HashSet<_> = self
+            .ingredients
+            .node_indices()
+            .filter(|&ni| !self.ingredients[ni].is_dropped())
+            .map(|ni| self.ingredients[ni].name().to_owned())
+            .collect();
+        self.shardings.retain(|name, _| live.contains(name));
+    }
+
     fn graphviz(&self, detailed: bool) -> String {
         graphviz(&self.ingredients, detailed, &self.materializations)
     }
 
-    fn remove_leaf(&mut self, mut leaf: NodeIndex) -> Result<(), String> {
+    fn remove_leaf(&mut self, leaf: NodeIndex) -> Result<(), String> {
         let mut removals = vec![];
         let start = leaf;
+        let mut leaves = vec![leaf];
         assert!(!self.ingredients[leaf].is_source());
 
         info!(
@@ -1159,15 +2999,6 @@ impl ControllerInner {
             // This query leaf node has children -- typically, these are readers, but they can also
             // include egress nodes or other, dependent queries. We need to find the actual reader,
             // and remove that.
-            if nchildren != 1 {
-                crit!(
-                    self.log,
-                    "cannot remove node {}, as it still has multiple children",
-                    leaf.index()
-                );
-                unreachable!();
-            }
-
             let mut readers = Vec::new();
             let mut bfs = Bfs::new(&self.ingredients, leaf);
             while let Some(child) = bfs.next(&self.ingredients) {
@@ -1177,28 +3008,37 @@ impl ControllerInner {
                 }
             }
 
-            // nodes can have only one reader attached
-            assert_eq!(readers.len(), 1);
-            let reader = readers[0];
+            // nodes can have only one reader attached, although that reader may have several
+            // replicas, each of which may hang off a (re)sharding node of its own.
+            if readers.is_empty() || nchildren > readers.len() {
+                crit!(
+                    self.log,
+                    "cannot remove node {}, as it still has multiple children",
+                    leaf.index()
+                );
+                unreachable!();
+            }
             debug!(
                 self.log,
                 "Removing query leaf \"{}\"", self.ingredients[leaf].name();
                 "node" => leaf.index(),
-                "really" => reader.index(),
+                "really" => ?readers,
             );
-            removals.push(reader);
-            leaf = reader;
+            removals.extend(readers.iter().cloned());
+            leaves = readers;
         }
 
         // `node` now does not have any children any more
-        assert_eq!(
-            self.ingredients
-                .neighbors_directed(leaf, petgraph::EdgeDirection::Outgoing)
-                .count(),
-            0
-        );
+        for &leaf in &leaves {
+            assert_eq!(
+                self.ingredients
+                    .neighbors_directed(leaf, petgraph::EdgeDirection::Outgoing)
+                    .count(),
+                0
+            );
+        }
 
-        let mut nodes = vec![leaf];
+        let mut nodes = leaves;
         while let Some(node) = nodes.pop() {
             let mut parents = self
                 .ingredients
@@ -1275,13 +3115,15 @@ impl ControllerInner {
     }
 
     fn get_failed_nodes(&self, lost_worker: &WorkerIdentifier) -> Vec<NodeIndex> {
-        // Find nodes directly impacted by worker failure.
-        let mut nodes: Vec<NodeIndex> = self.nodes_on_worker(Some(lost_worker));
+        // Find nodes directly impacted by worker failure, and any other downstream nodes.
+        self.downstream_of(self.nodes_on_worker(Some(lost_worker)))
+    }
 
-        // Add any other downstream nodes.
-        let mut failed_nodes = Vec::new();
+    /// The given nodes along with all the nodes downstream of them.
+    fn downstream_of(&self, mut nodes: Vec<NodeIndex>) -> Vec<NodeIndex> {
+        let mut downstream = Vec::new();
         while let Some(node) = nodes.pop() {
-            failed_nodes.push(node);
+            downstream.push(node);
             for child in self
                 .ingredients
                 .neighbors_directed(node, petgraph::EdgeDirection::Outgoing)
@@ -1291,7 +3133,7 @@ impl ControllerInner {
                 }
             }
         }
-        failed_nodes
+        downstream
     }
 
     /// List data-flow nodes, on a specific worker if `worker` specified.
@@ -1328,12 +3170,21 @@ impl ControllerInner {
 
 impl Drop for ControllerInner {
     fn drop(&mut self) {
-        for d in self.domains.values_mut() {
+        for d in self
+            .domains
+            .values_mut()
+            .chain(self.backups.values_mut().flatten())
+        {
             // XXX: this is a terrible ugly hack to ensure that all workers exit
             for _ in 0..100 {
                 // don't unwrap, because given domain may already have terminated
                 drop(d.send_to_healthy(Box::new(Packet::Quit), &self.workers));
             }
         }
+        for r in &mut self.relays {
+            if self.workers.get(&r.worker).map_or(false, |w| w.healthy) {
+                drop(r.tx.send(Box::new(Packet::Quit)));
+            }
+        }
     }
 }
@@ -79,7 +79,7 @@ pub fn assign(log: &Logger, graph: &mut Graph, topo_list: &[NodeIndex], ndomains
                         let p = &graph[pni];
                         if p.is_source() || p.is_sharder() || p.is_shard_merger() {
                         } else if p.is_base() {
-                            if p.has_domain() {
+                            if p.has_domain() && same_shards(p, n) {
                                 friendly_base = Some(p);
                                 break 'search;
                             }
@@ -140,7 +140,7 @@ pub fn assign(log: &Logger, graph: &mut Graph, topo_list: &[NodeIndex], ndomains
                     // the key may move to a different column, so we can't actually check for
                     // ByColumn equality. this'll do for now.
                     assert_eq!(p.sharded_by().is_none(), n.sharded_by().is_none());
-                    if p.has_domain() {
+                    if p.has_domain() && same_shards(p, n) {
                         assignment = Some(p.domain().index())
                     }
                 }
@@ -169,7 +169,7 @@ pub fn assign(log: &Logger, graph: &mut Graph, topo_list: &[NodeIndex], ndomains
                         if !s.has_domain() {
                             continue;
                         }
-                        if s.sharded_by().is_none() != n.sharded_by().is_none() {
+                        if !same_shards(s, n) {
                             continue;
                         }
                         let candidate = s.domain().index();
@@ -198,3 +198,8 @@ pub fn assign(log: &Logger, graph: &mut Graph, topo_list: &[NodeIndex], ndomains
         graph[node].add_to(assignment.into());
     }
 }
+
+/// Whether two nodes are split into the same number of shards, and so can share a domain.
+fn same_shards(a: &Node, b: &Node) -> bool {
+    a.sharded_by().shards() == b.sharded_by().shards()
+}
@@ -168,6 +168,16 @@ impl Materializations {
                 indices.insert(ni, (vec![0], true));
             }
 
+            if let Some(b) = n.get_base() {
+                // bases look up rows by their unique keys to enforce them
+                for cols in b.unique_keys() {
+                    lookup_obligations
+                        .entry(ni)
+                        .or_insert_with(HashSet::new)
+                        .insert(cols);
+                }
+            }
+
             for (ni, (cols, lookup)) in indices {
                 trace!(self.log, "new indexing obligation";
                        "node" => ni.index(),
@@ -335,6 +345,12 @@ impl Materializations {
                 able = false;
             }
 
+            // readers that serve range lookups can't tell which keys in a range are missing
+            if let Ok(true) = graph[ni].with_reader(|r| r.serves_ranges()) {
+                warn!(self.log, "full because reader serves ranges"; "node" => ni.index());
+                able = false;
+            }
+
             // we are already fully materialized, so can't be made partial
             if !new.contains(&ni)
                 && self.added.get(&ni).map(|i| i.len()).unwrap_or(0)
@@ -356,6 +372,13 @@ impl Materializations {
                     able = false;
                 }
 
+                // operators that require full materialization need to see all of our state too
+                if graph[child].is_internal() && graph[child].requires_full_materialization() {
+                    warn!(self.log, "full because descendant requires full"; "node" => ni.index(), "child" => child.index());
+                    stack.clear();
+                    able = false;
+                }
+
                 if self.have.contains_key(&child) {
                     // materialized child -- don't need to keep walking along this path
                     if !self.partial.contains(&child) {
@@ -394,6 +417,15 @@ impl Materializations {
                             able = false;
                             break 'attempt;
                         }
+                        if !self.have.contains_key(&pni)
+                            && graph[pni].is_internal()
+                            && graph[pni].requires_full_materialization()
+                        {
+                            warn!(self.log, "full because replays pass through a node that requires full";
+                                  "node" => ni.index(), "through" => pni.index());
+                            able = false;
+                            break 'attempt;
+                        }
                         let index: Vec<_> = cols.into_iter().map(Option::unwrap).collect();
                         if let Some(m) = self.have.get(&pni) {
                             if !m.contains(&index) {
@@ -452,6 +484,11 @@ impl Materializations {
         assert!(replay_obligations.is_empty());
     }
 
+    /// The indices that the state of the given node is kept in.
+    pub(in crate::controller) fn indices(&self, index: NodeIndex) -> Indices {
+        self.have.get(&index).cloned().unwrap_or_default()
+    }
+
     /// Retrieves the materialization status of a given node, or None
     /// if the node isn't materialized.
     pub(in crate::controller) fn get_status(
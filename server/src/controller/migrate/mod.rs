@@ -23,6 +23,9 @@
 use crate::controller::ControllerInner;
 use dataflow::prelude::*;
 use dataflow::{node, prelude::Packet};
+use nom_sql::ColumnSpecification;
+use noria::internal::LocalOrNot;
+use noria::Input;
 use std::collections::{HashMap, HashSet};
 use std::time::Instant;
 
@@ -34,10 +37,11 @@ mod augmentation;
 pub(crate) mod materialization;
 mod routing;
 mod sharding;
+mod transactions;
 
 #[derive(Clone)]
 pub(super) enum ColumnChange {
-    Add(String, DataType),
+    Add(String, DataType, Option<ColumnSpecification>),
     Drop(usize),
 }
 
@@ -50,7 +54,8 @@ pub struct Migration<'a> {
     pub(super) mainline: &'a mut ControllerInner,
     pub(super) added: HashSet<NodeIndex>,
     pub(super) columns: Vec<(NodeIndex, ColumnChange)>,
-    pub(super) readers: HashMap<NodeIndex, NodeIndex>,
+    /// The reader of each maintained node, followed by any replicas of it.
+    pub(super) readers: HashMap<NodeIndex, Vec<NodeIndex>>,
 
     pub(super) start: Instant,
     pub(super) log: slog::Logger,
@@ -111,11 +116,18 @@ impl<'a> Migration<'a> {
         S2: ToString,
         FS: IntoIterator<Item = S2>,
     {
+        // a base that was resharded keeps its rows in files of its own
+        let name = name.to_string();
+        let b = match self.mainline.shardings.get(&name) {
+            Some(o) => b.with_generation(o.generation),
+            None => b,
+        };
+
         // add to the graph
         let ni = self
             .mainline
             .ingredients
-            .add_node(node::Node::new(name.to_string(), fields, b));
+            .add_node(node::Node::new(name, fields, b));
         info!(self.log,
               "adding new base";
               "node" => ni.index(),
@@ -184,11 +196,33 @@ impl<'a> Migration<'a> {
         node: NodeIndex,
         field: S,
         default: DataType,
+    ) -> usize {
+        self.add_column_with_spec(node, field.to_string(), default, None)
+    }
+
+    /// Add a new column of a known type to a base node.
+    ///
+    /// Writes to the new column are checked against `spec` from then on.
+    pub(super) fn add_typed_column(
+        &mut self,
+        node: NodeIndex,
+        spec: ColumnSpecification,
+        default: DataType,
+    ) -> usize {
+        let field = spec.column.name.clone();
+        self.add_column_with_spec(node, field, default, Some(spec))
+    }
+
+    fn add_column_with_spec(
+        &mut self,
+        node: NodeIndex,
+        field: String,
+        default: DataType,
+        spec: Option<ColumnSpecification>,
     ) -> usize {
         // not allowed to add columns to new nodes
         assert!(!self.added.contains(&node));
 
-        let field = field.to_string();
         let base = &mut self.mainline.ingredients[node];
         assert!(base.is_base());
 
@@ -197,12 +231,16 @@ impl<'a> Migration<'a> {
         let col_i1 = base.add_column(&field);
         // we can't rely on DerefMut, since it disallows mutating Taken nodes
         {
-            let col_i2 = base.get_base_mut().unwrap().add_column(default.clone());
+            let col_i2 = base
+                .get_base_mut()
+                .unwrap()
+                .add_column(default.clone(), spec.clone());
             assert_eq!(col_i1, col_i2);
         }
 
         // also eventually propagate to domain clone
-        self.columns.push((node, ColumnChange::Add(field, default)));
+        self.columns
+            .push((node, ColumnChange::Add(field, default, spec)));
 
         col_i1
     }
@@ -233,20 +271,44 @@ impl<'a> Migration<'a> {
     fn ensure_reader_for(&mut self, n: NodeIndex, name: Option<String>) {
         use std::collections::hash_map::Entry;
         if let Entry::Vacant(e) = self.readers.entry(n) {
-            // make a reader
-            let r = node::special::Reader::new(n);
-            let mut r = if let Some(name) = name {
-                self.mainline.ingredients[n].named_mirror(r, name)
-            } else {
-                self.mainline.ingredients[n].mirror(r)
-            };
-            if r.name().starts_with("SHALLOW_") {
-                r.purge = true;
+            // named views can be replicated. every replica is a reader of its own, which ends up
+            // in a domain of its own, and all of them are fed by the same egress from `n`'s domain.
+            let replicas = name
+                .as_ref()
+                .and_then(|name| self.mainline.reader_replicas.get(name))
+                .cloned()
+                .unwrap_or(1);
+
+            let mut readers = Vec::with_capacity(replicas);
+            for _ in 0..replicas {
+                // make a reader
+                let r = node::special::Reader::new(n);
+                let mut r = if let Some(ref name) = name {
+                    self.mainline.ingredients[n].named_mirror(r, name.clone())
+                } else {
+                    self.mainline.ingredients[n].mirror(r)
+                };
+                if r.name().starts_with("SHALLOW_") {
+                    r.purge = true;
+                }
+                let r = self.mainline.ingredients.add_node(r);
+                self.mainline.ingredients.add_edge(n, r, ());
+                self.added.insert(r);
+                readers.push(r);
             }
-            let r = self.mainline.ingredients.add_node(r);
-            self.mainline.ingredients.add_edge(n, r, ());
-            self.added.insert(r);
-            e.insert(r);
+            e.insert(readers);
+        }
+    }
+
+    /// Apply `f` to the reader of `n` and to all of its replicas.
+    fn with_readers_mut<F>(&mut self, n: NodeIndex, mut f: F)
+    where
+        F: FnMut(&mut node::special::Reader),
+    {
+        for &ri in &self.readers[&n] {
+            self.mainline.ingredients[ri]
+                .with_reader_mut(&mut f)
+                .unwrap();
         }
     }
 
@@ -255,13 +317,8 @@ impl<'a> Migration<'a> {
     /// To query into the maintained state, use `ControllerInner::get_getter`.
     pub fn maintain_anonymous(&mut self, n: NodeIndex, key: &[usize]) -> NodeIndex {
         self.ensure_reader_for(n, None);
-        let ri = self.readers[&n];
-
-        self.mainline.ingredients[ri]
-            .with_reader_mut(|r| r.set_key(key))
-            .unwrap();
-
-        ri
+        self.with_readers_mut(n, |r| r.set_key(key));
+        self.readers[&n][0]
     }
 
     /// Set up the given node such that its output can be efficiently queried.
@@ -269,12 +326,54 @@ impl<'a> Migration<'a> {
     /// To query into the maintained state, use `ControllerInner::get_getter`.
     pub fn maintain(&mut self, name: String, n: NodeIndex, key: &[usize]) {
         self.ensure_reader_for(n, Some(name));
+        self.with_readers_mut(n, |r| r.set_key(key));
+    }
 
-        let ri = self.readers[&n];
+    /// Set up the given node such that its output can be queried by ranges over the last column
+    /// of `key`, bounded as the query's `range` says.
+    ///
+    /// The resulting reader is always fully materialized.
+    pub fn maintain_for_ranges(
+        &mut self,
+        name: String,
+        n: NodeIndex,
+        key: &[usize],
+        range: node::special::RangeParameter,
+    ) {
+        self.maintain(name, n, key);
+        self.with_readers_mut(n, |r| r.enable_ranges(range));
+    }
+
+    /// Discard the changes introduced by this `Migration`.
+    ///
+    /// All nodes added by the migration are removed from the graph again, and columns added to or
+    /// dropped from existing base nodes are restored, which leaves the running graph as it was
+    /// before the migration started.
+    pub(super) fn abort(self) {
+        info!(self.log, "aborting migration"; "#nodes" => self.added.len());
+
+        // column changes have only been applied to the controller's copy of the bases so far, so
+        // undoing them there, most recent first, leaves the bases as they were.
+        for (ni, change) in self.columns.into_iter().rev() {
+            let base = &mut self.mainline.ingredients[ni];
+            match change {
+                ColumnChange::Add(..) => {
+                    base.remove_added_column();
+                    base.get_base_mut().unwrap().remove_added_column();
+                }
+                ColumnChange::Drop(column) => {
+                    base.get_base_mut().unwrap().undrop_column(column);
+                }
+            }
+        }
 
-        self.mainline.ingredients[ri]
-            .with_reader_mut(|r| r.set_key(key))
-            .unwrap();
+        // new nodes always have the highest indices in the graph, so removing them from the top
+        // down never moves an existing node to a different index.
+        let mut added: Vec<_> = self.added.into_iter().collect();
+        added.sort();
+        for ni in added.into_iter().rev() {
+            self.mainline.ingredients.remove_node(ni);
+        }
     }
 
     /// Commit the changes introduced by this `Migration` to the master `Soup`.
@@ -294,8 +393,14 @@ impl<'a> Migration<'a> {
 
         // Shard the graph as desired
         let mut swapped0 = if let Some(shards) = mainline.sharding {
-            let (t, swapped) =
-                sharding::shard(&log, &mut mainline.ingredients, &mut new, &topo, shards);
+            let (t, swapped) = sharding::shard(
+                &log,
+                &mut mainline.ingredients,
+                &mut new,
+                &topo,
+                shards,
+                &mainline.shardings,
+            );
             topo = t;
 
             swapped
@@ -450,6 +555,16 @@ impl<'a> Migration<'a> {
             sharding::validate(&log, &mainline.ingredients, &topo, shards)
         };
 
+        // Let readers know how to tell when they have seen all of a transaction's updates. no
+        // transaction commits during a migration, so readers need only wait for the ones that
+        // commit after it.
+        let changed_paths = transactions::inform_readers(
+            &mut mainline.ingredients,
+            mainline.source,
+            &new,
+            mainline.next_transaction,
+        );
+
         // at this point, we've hooked up the graph such that, for any given domain, the graph
         // looks like this:
         //
@@ -542,10 +657,11 @@ impl<'a> Migration<'a> {
             for ni in inform {
                 let n = &mainline.ingredients[ni];
                 let m = match change.clone() {
-                    ColumnChange::Add(field, default) => Box::new(Packet::AddBaseColumn {
+                    ColumnChange::Add(field, default, spec) => Box::new(Packet::AddBaseColumn {
                         node: n.local_addr(),
                         field,
                         default,
+                        spec,
                     }),
                     ColumnChange::Drop(column) => Box::new(Packet::DropBaseColumn {
                         node: n.local_addr(),
@@ -560,6 +676,9 @@ impl<'a> Migration<'a> {
             }
         }
 
+        // Give new bases that replace lost or retired ones their rows
+        mainline.restore_bases(&new, &log);
+
         // Set up inter-domain connections
         // NOTE: once we do this, we are making existing domains block on new domains!
         info!(log, "bringing up inter-domain connections");
@@ -581,6 +700,48 @@ impl<'a> Migration<'a> {
             &mut mainline.replies,
         );
 
+        // Tell existing readers whose paths changed about their new paths
+        for &ni in &changed_paths {
+            let n = &mainline.ingredients[ni];
+            let m = Box::new(Packet::UpdateWritePaths {
+                node: n.local_addr(),
+                paths: n.with_reader(|r| r.write_paths().clone()).unwrap(),
+                first_txn: mainline.next_transaction,
+            });
+            let domain = mainline.domains.get_mut(&n.domain()).unwrap();
+            domain.send_to_healthy(m, &mainline.workers).unwrap();
+            futures_executor::block_on(mainline.replies.wait_for_acks(&domain));
+        }
+
+        // Send a tracked write through every base that reaches a new reader, or one whose paths
+        // changed. Once such a reader has seen all of the write's updates, it knows that it
+        // reflects every earlier write to the base, and so which write tokens it can honor.
+        let mut bases = HashSet::new();
+        for &ni in new.iter().chain(&changed_paths) {
+            if let Ok(paths) = mainline.ingredients[ni].with_reader(|r| r.write_paths()) {
+                bases.extend(paths.keys().cloned());
+            }
+        }
+        for base in bases {
+            let n = &mainline.ingredients[base];
+            let m = Box::new(Packet::Input {
+                inner: LocalOrNot::new(Input {
+                    dst: n.local_addr(),
+                    data: Vec::new(),
+                    txn: None,
+                    track: true,
+                    bulk: false,
+                }),
+                src: None,
+                senders: Vec::new(),
+            });
+            let domain = mainline.domains.get_mut(&n.domain()).unwrap();
+            domain.send_to_healthy(m, &mainline.workers).unwrap();
+        }
+
+        // Back up new base nodes
+        mainline.add_base_backups(&new, &log);
+
         warn!(log, "migration completed"; "ms" => start.elapsed().as_millis());
     }
 }
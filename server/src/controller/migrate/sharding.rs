@@ -1,3 +1,4 @@
+use crate::controller::ShardingOverride;
 use dataflow::node;
 use dataflow::ops;
 use dataflow::prelude::*;
@@ -12,7 +13,8 @@ pub fn shard(
     graph: &mut Graph,
     new: &mut HashSet<NodeIndex>,
     topo_list: &[NodeIndex],
-    sharding_factor: usize,
+    default_sharding_factor: usize,
+    overrides: &HashMap<String, ShardingOverride>,
 ) -> (Vec<NodeIndex>, HashMap<(NodeIndex, NodeIndex), NodeIndex>) {
     // we must keep track of changes we make to the parent of a node, since this remapping must be
     // communicated to the nodes so they know the true identifier of their parent in the graph.
@@ -21,11 +23,34 @@ pub fn shard(
     // we want to shard every node by its "input" index. if the index required from a parent
     // doesn't match the current sharding key, we need to do a shuffle (i.e., a Union + Sharder).
     'nodes: for &node in topo_list {
+        let sharding_factor = shards_for(graph, node, default_sharding_factor, overrides);
         let mut input_shardings: HashMap<_, _> = graph
             .neighbors_directed(node, petgraph::EdgeDirection::Incoming)
             .map(|ni| (ni, graph[ni].sharded_by()))
             .collect();
 
+        // a base table without a primary key that was resharded by a given column is sharded
+        // by that column, as long as that lets it enforce its unique keys
+        if graph[node].is_base() {
+            let column = overrides
+                .get(graph[node].name())
+                .and_then(|o| o.column.as_ref())
+                .and_then(|c| graph[node].fields().iter().position(|f| f == c));
+            if let Some(column) = column {
+                let base = graph[node].get_base().unwrap();
+                if base.key().is_none() && base.can_shard_by(column) {
+                    info!(log, "sharding base node by requested column";
+                          "node" => ?node,
+                          "column" => column);
+                    graph
+                        .node_weight_mut(node)
+                        .unwrap()
+                        .shard_by(Sharding::ByColumn(column, sharding_factor));
+                    continue;
+                }
+            }
+        }
+
         let mut need_sharding = if graph[node].is_internal() || graph[node].is_base() {
             // suggest_indexes is okay because `node` *must* be new, and therefore will return
             // global node indices.
@@ -176,6 +201,13 @@ pub fn shard(
                     // ok to continue since standard shard_by is None
                     continue;
                 }
+                None if !graph[node].get_base().unwrap().can_shard_by(want_sharding) => {
+                    // a shard would only see some of the rows that may collide with the ones it
+                    // receives, and so could not enforce the base's unique keys.
+                    warn!(log, "not sharding base node whose unique keys lack the column";
+                          "node" => ?node, "column" => want_sharding);
+                    continue;
+                }
                 None => {
                     // base nodes -- what do we shard them by?
                     warn!(log, "sharding base node"; "node" => ?node, "column" => want_sharding);
@@ -412,7 +444,7 @@ pub fn shard(
 
             // and that its children must be sharded somehow (otherwise what is the sharder doing?)
             let col = graph[n].with_sharder(|s| s.sharded_by()).unwrap();
-            let by = Sharding::ByColumn(col, sharding_factor);
+            let by = Sharding::ByColumn(col, sharded_to(graph, n, default_sharding_factor));
 
             // we can only push sharding above newly created nodes that are not already sharded.
             if !new.contains(&p) || graph[p].sharded_by() != Sharding::None {
@@ -432,6 +464,15 @@ pub fn shard(
                     }
                 }
 
+                // nor bases that must enforce unique keys across the rows of several shards
+                if !graph[p].get_base().unwrap().can_shard_by(col) {
+                    trace!(
+                        log,
+                        "no, parent is weird (has unique keys without the column)"
+                    );
+                    continue;
+                }
+
                 // if the base has other children, sharding it may have other effects
                 if graph
                     .neighbors_directed(p, petgraph::EdgeDirection::Outgoing)
@@ -508,7 +549,8 @@ pub fn shard(
                     // TODO: we *could* insert a de-shard here
                     continue 'sharders;
                 }
-                let csharding = Sharding::ByColumn(col.unwrap(), sharding_factor);
+                let csharding =
+                    Sharding::ByColumn(col.unwrap(), sharded_to(graph, c, default_sharding_factor));
 
                 if csharding == by {
                     // sharding by the same key, which is now unnecessary.
@@ -618,11 +660,51 @@ pub fn shard(
         }
         topo_list.push(node);
     }
-    validate(log, graph, &topo_list, sharding_factor);
+    validate(log, graph, &topo_list, default_sharding_factor);
 
     (topo_list, swaps)
 }
 
+/// The number of shards to split `node` into, if it is sharded.
+///
+/// Nodes that were resharded keep the number of shards they were given. Other readers use the
+/// default sharding factor, and other nodes the number of shards of their inputs, so that the
+/// nodes below a resharded node are not shuffled back to the default number of shards.
+fn shards_for(
+    graph: &Graph,
+    node: NodeIndex,
+    sharding_factor: usize,
+    overrides: &HashMap<String, ShardingOverride>,
+) -> usize {
+    let n = &graph[node];
+    if n.is_base() || n.is_internal() || n.is_reader() {
+        if let Some(o) = overrides.get(n.name()) {
+            return o.shards;
+        }
+    }
+    if n.is_reader() {
+        return sharding_factor;
+    }
+
+    let mut inputs = graph
+        .neighbors_directed(node, petgraph::EdgeDirection::Incoming)
+        .filter(|&ni| !graph[ni].is_source())
+        .filter_map(|ni| graph[ni].sharded_by().shards());
+    match inputs.next() {
+        Some(shards) if inputs.all(|s| s == shards) => shards,
+        _ => sharding_factor,
+    }
+}
+
+/// The number of shards the given sharder splits its output into, which is that of its children.
+fn sharded_to(graph: &Graph, sharder: NodeIndex, sharding_factor: usize) -> usize {
+    graph
+        .neighbors_directed(sharder, petgraph::EdgeDirection::Outgoing)
+        .filter_map(|c| graph[c].sharded_by().shards())
+        .next()
+        .unwrap_or(sharding_factor)
+}
+
 /// Modify the graph such that the path between `src` and `dst` shuffles the input such that the
 /// records received by `dst` are sharded by sharding `to`.
 fn reshard(
@@ -747,11 +829,8 @@ pub fn validate(log: &Logger, graph: &Graph, topo_list: &[NodeIndex], sharding_f
             if in_node.is_sharder() {
                 // ancestor is a sharder, so its output sharding must match ours
                 in_node.with_sharder(|s| {
-                    let in_sharding = remap(
-                        n,
-                        in_ni,
-                        Sharding::ByColumn(s.sharded_by(), sharding_factor),
-                    );
+                    let shards = n.sharded_by().shards().unwrap_or(sharding_factor);
+                    let in_sharding = remap(n, in_ni, Sharding::ByColumn(s.sharded_by(), shards));
                     if in_sharding != n.sharded_by() {
                         crit!(
                             log,
@@ -1,150 +1,117 @@
-use controller::domain_handle::DomainHandle;
-use dataflow::payload::{EgressForBase, IngressFromBase};
+//! Bookkeeping that lets readers expose the writes of a transaction atomically.
+//!
+//! Every shard of every base a transaction writes to receives exactly one input for that
+//! transaction, and the update each of them emits is forwarded along every edge of the dataflow,
+//! even if it ends up empty, and sharders send it to every shard below them. A reader shard
+//! therefore receives a fixed number of updates for every update a given base shard emits, and
+//! once it knows those numbers, it can tell when it has seen all the updates of a transaction.
+//! The updates of writes that clients asked for a token for are forwarded the same way, so the
+//! same numbers also tell a reader when it has seen all the effects of such a write.
+
+use dataflow::node::special::WritePaths;
 use dataflow::prelude::*;
 use petgraph;
-use slog::Logger;
-use std::borrow::Borrow;
 use std::collections::{HashMap, HashSet};
 
-fn count_base_ingress(
+/// The number of updates each shard of `ni` receives for every update emitted by a shard of
+/// `base`: first for the shard of `ni` with the same index as the emitting shard, and then for
+/// each of the other shards of `ni`.
+fn count_paths(
     graph: &Graph,
-    source: NodeIndex,
-    nodes: &[NodeIndex],
-    has_path: &HashSet<(NodeIndex, NodeIndex)>,
-) -> IngressFromBase {
-    let ingress_nodes: Vec<_> = nodes
-        .into_iter()
-        .filter(|&&ni| graph[ni].borrow().is_ingress())
-        .filter(|&&ni| graph[ni].borrow().is_transactional())
-        .collect();
-
-    graph
-        .neighbors_directed(source, petgraph::EdgeDirection::Outgoing)
-        .map(|base| {
-            let num_paths = ingress_nodes
-                .iter()
-                .filter(|&&ingress| has_path.contains(&(base, *ingress)))
-                .map(|&&ingress| {
-                    if graph[ingress].is_shard_merger() {
-                        let egress = graph
-                            .neighbors_directed(ingress, petgraph::EdgeDirection::Incoming)
-                            .next()
-                            .unwrap();
+    base: NodeIndex,
+    ni: NodeIndex,
+    memo: &mut HashMap<NodeIndex, (usize, usize)>,
+) -> (usize, usize) {
+    if ni == base {
+        // a shard of a sharded base only emits updates into its own shard of the dataflow
+        return match graph[base].sharded_by().shards() {
+            Some(_) => (1, 0),
+            None => (1, 1),
+        };
+    }
+    if let Some(&n) = memo.get(&ni) {
+        return n;
+    }
 
-                        if graph[egress].domain() == graph[base].domain() {
-                            1
-                        } else {
-                            graph[egress].sharded_by().shards()
-                        }
-                    } else {
-                        1
+    let n = &graph[ni];
+    let paths = if n.is_source() || n.is_base() || n.is_dropped() {
+        (0, 0)
+    } else {
+        graph
+            .neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+            .map(|parent| {
+                let (same, other) = count_paths(graph, base, parent, memo);
+                match graph[parent].sharded_by().shards() {
+                    // a shard merger receives the update from every shard of its parent
+                    Some(shards) if n.sharded_by().is_none() => {
+                        let all = same + (shards - 1) * other;
+                        (all, all)
                     }
-                })
-                .sum();
+                    // a sharder sends every shard below it a copy of each update caused by a
+                    // write, and otherwise every shard only hears from its counterpart upstream
+                    _ => (same, other),
+                }
+            })
+            .fold((0, 0), |(same, other), (s, o)| (same + s, other + o))
+    };
 
-            (base, num_paths)
-        })
-        .collect()
+    memo.insert(ni, paths);
+    paths
 }
 
-fn base_egress_map(
-    graph: &Graph,
+/// Tell every reader how many updates reach it for every update emitted by each of the bases in
+/// the graph, and that only transactions numbered `first_txn` or higher are to be waited for.
+///
+/// Rebuilt queries, resharded nodes and new replicas of readers can change the paths to existing
+/// readers, so this recomputes the paths of all of them. It returns the readers that are not in
+/// `new` and whose paths changed; their domains must be told about the new paths.
+pub(super) fn inform_readers(
+    graph: &mut Graph,
     source: NodeIndex,
-    nodes: &[NodeIndex],
-    has_path: &HashSet<(NodeIndex, NodeIndex)>,
-) -> EgressForBase {
-    let output_nodes: Vec<_> = nodes
-        .into_iter()
-        .filter(|&&ni| graph[ni].is_output())
-        //.filter(|&ni| graph[ni].is_transactional())
+    new: &HashSet<NodeIndex>,
+    first_txn: u64,
+) -> Vec<NodeIndex> {
+    let readers: Vec<_> = graph
+        .node_indices()
+        .filter(|&ni| graph[ni].is_reader())
         .collect();
 
-    graph
+    let mut paths: HashMap<_, HashMap<_, _>> = HashMap::new();
+    let bases: Vec<_> = graph
         .neighbors_directed(source, petgraph::EdgeDirection::Outgoing)
-        .map(|base| {
-            let outs = output_nodes
-                .iter()
-                .filter(|&&out| has_path.contains(&(base, *out)))
-                .map(|&&out| *graph[out].local_addr())
-                .collect();
-            (base, outs)
-        })
-        .collect()
-}
-
-fn has_path(graph: &Graph, source: NodeIndex) -> HashSet<(NodeIndex, NodeIndex)> {
-    use petgraph::visit::Bfs;
-    let mut has_path = HashSet::new();
-    for b in graph.neighbors_directed(source, petgraph::EdgeDirection::Outgoing) {
-        let mut bfs = Bfs::new(&graph, b);
-        while let Some(nx) = bfs.next(&graph) {
-            has_path.insert((b, nx));
+        .collect();
+    for base in bases {
+        let mut memo = HashMap::new();
+        for &reader in &readers {
+            let (same_shard, other_shard) = count_paths(graph, base, reader, &mut memo);
+            if same_shard != 0 || other_shard != 0 {
+                paths.entry(reader).or_default().insert(
+                    base,
+                    WritePaths {
+                        same_shard,
+                        other_shard,
+                        base_shards: graph[base].sharded_by().shards().unwrap_or(1),
+                    },
+                );
+            }
         }
     }
 
-    has_path
-}
-
-pub fn analyze_changes(
-    graph: &Graph,
-    source: NodeIndex,
-    domain_new_nodes: HashMap<DomainIndex, Vec<NodeIndex>>,
-) -> HashMap<DomainIndex, (IngressFromBase, EgressForBase)> {
-    let has_path = has_path(graph, source);
-    domain_new_nodes
-        .into_iter()
-        .map(|(domain, nodes): (_, Vec<NodeIndex>)| {
-            (
-                domain,
-                (
-                    count_base_ingress(graph, source, &nodes[..], &has_path),
-                    base_egress_map(graph, source, &nodes[..], &has_path),
-                ),
-            )
-        })
-        .collect()
-}
-
-pub fn merge_deps(
-    graph: &Graph,
-    old: &mut HashMap<DomainIndex, (IngressFromBase, EgressForBase)>,
-    new: HashMap<DomainIndex, (IngressFromBase, EgressForBase)>,
-) {
-    for (di, (new_ingress, new_egress)) in new {
-        let entry = old.entry(di).or_insert((HashMap::new(), HashMap::new()));
-        let old_ingress = &mut entry.0;
-        let old_egress = &mut entry.1;
-
-        for (base, v) in new_egress {
-            let e = old_egress.entry(base).or_insert_with(Vec::new);
-            e.extend(v);
-        }
-
-        for (base, v) in new_ingress {
-            let e = old_ingress.entry(base).or_insert(0);
-            *e += v;
-
-            // Domains containing a base node will get a single copy of each packet sent to it.
-            if graph[base].domain() == di {
-                *e = 1;
+    let mut changed = Vec::new();
+    for reader in readers {
+        let paths = paths.remove(&reader).unwrap_or_default();
+        let fresh = new.contains(&reader);
+        let same = graph[reader]
+            .with_reader(|r| *r.write_paths() == paths)
+            .unwrap();
+        if fresh || !same {
+            graph[reader]
+                .with_reader_mut(|r| r.set_write_paths(paths, first_txn))
+                .unwrap();
+            if !fresh {
+                changed.push(reader);
             }
         }
     }
-}
-
-pub fn finalize(
-    deps: HashMap<DomainIndex, (IngressFromBase, EgressForBase)>,
-    log: &Logger,
-    txs: &mut HashMap<DomainIndex, DomainHandle>,
-    at: i64,
-) {
-    for (domain, (ingress_from_base, egress_for_base)) in deps {
-        trace!(log, "notifying domain of migration completion"; "domain" => domain.index());
-        let ctx = txs.get_mut(&domain).unwrap();
-        let _ = ctx.send(box Packet::CompleteMigration {
-            at,
-            ingress_from_base,
-            egress_for_base,
-        });
-    }
+    changed
 }
@@ -5,10 +5,12 @@ use std::collections::HashMap;
 
 use crate::controller::Migration;
 use common::DataType;
+use dataflow::node::special::RangeParameter;
 use dataflow::ops::filter::FilterCondition;
 use dataflow::ops::join::{Join, JoinType};
 use dataflow::ops::latest::Latest;
 use dataflow::ops::project::{Project, ProjectExpression, ProjectExpressionBase};
+use dataflow::ops::semijoin::{SemiJoin, SemiJoinType};
 use dataflow::{node, ops};
 use mir::node::{GroupedNodeType, MirNode, MirNodeType};
 use mir::query::{MirQuery, QueryFlowParts};
@@ -104,9 +106,12 @@ fn mir_node_to_flow_parts(
                 MirNodeType::Base {
                     ref mut column_specs,
                     ref keys,
+                    ref unique_keys,
                     ref adapted_over,
                 } => match *adapted_over {
-                    None => make_base_node(&name, column_specs.as_mut_slice(), keys, mig),
+                    None => {
+                        make_base_node(&name, column_specs.as_mut_slice(), keys, unique_keys, mig)
+                    }
                     Some(ref bna) => adapt_base_node(
                         bna.over.clone(),
                         mig,
@@ -212,10 +217,12 @@ fn mir_node_to_flow_parts(
                     let parent = mir_node.ancestors[0].clone();
                     make_latest_node(&name, parent, mir_node.columns.as_slice(), group_by, mig)
                 }
-                MirNodeType::Leaf { ref keys, .. } => {
+                MirNodeType::Leaf {
+                    ref keys, range, ..
+                } => {
                     assert_eq!(mir_node.ancestors.len(), 1);
                     let parent = mir_node.ancestors[0].clone();
-                    materialize_leaf_node(&parent, name, keys, mig);
+                    materialize_leaf_node(&parent, name, keys, range, mig);
                     // TODO(malte): below is yucky, but required to satisfy the type system:
                     // each match arm must return a `FlowNode`, so we use the parent's one
                     // here.
@@ -245,10 +252,76 @@ fn mir_node_to_flow_parts(
                         mig,
                     )
                 }
+                MirNodeType::FullJoin {
+                    ref on_left,
+                    ref on_right,
+                    ref project,
+                } => {
+                    assert_eq!(mir_node.ancestors.len(), 2);
+                    let left = mir_node.ancestors[0].clone();
+                    let right = mir_node.ancestors[1].clone();
+                    make_join_node(
+                        &name,
+                        left,
+                        right,
+                        mir_node.columns.as_slice(),
+                        on_left,
+                        on_right,
+                        project,
+                        JoinType::Full,
+                        mig,
+                    )
+                }
+                MirNodeType::SemiJoin {
+                    ref on_left,
+                    ref on_right,
+                    ref project,
+                } => {
+                    assert_eq!(mir_node.ancestors.len(), 2);
+                    let left = mir_node.ancestors[0].clone();
+                    let right = mir_node.ancestors[1].clone();
+                    make_semi_join_node(
+                        &name,
+                        left,
+                        right,
+                        mir_node.columns.as_slice(),
+                        on_left,
+                        on_right,
+                        project,
+                        SemiJoinType::Semi,
+                        mig,
+                    )
+                }
+                MirNodeType::AntiJoin {
+                    ref on_left,
+                    ref on_right,
+                    ref project,
+                    null_aware,
+                } => {
+                    assert_eq!(mir_node.ancestors.len(), 2);
+                    let left = mir_node.ancestors[0].clone();
+                    let right = mir_node.ancestors[1].clone();
+                    make_semi_join_node(
+                        &name,
+                        left,
+                        right,
+                        mir_node.columns.as_slice(),
+                        on_left,
+                        on_right,
+                        project,
+                        if null_aware {
+                            SemiJoinType::NotIn
+                        } else {
+                            SemiJoinType::Anti
+                        },
+                        mig,
+                    )
+                }
                 MirNodeType::Project {
                     ref emit,
                     ref literals,
                     ref arithmetic,
+                    ref expressions,
                 } => {
                     assert_eq!(mir_node.ancestors.len(), 1);
                     let parent = mir_node.ancestors[0].clone();
@@ -258,6 +331,7 @@ fn mir_node_to_flow_parts(
                         mir_node.columns.as_slice(),
                         emit,
                         arithmetic,
+                        expressions,
                         literals,
                         mig,
                         table_mapping,
@@ -311,6 +385,25 @@ fn mir_node_to_flow_parts(
                         mig,
                     )
                 }
+                MirNodeType::Paginate {
+                    ref order,
+                    ref group_by,
+                    ref page_size,
+                    ref offset,
+                } => {
+                    assert_eq!(mir_node.ancestors.len(), 1);
+                    let parent = mir_node.ancestors[0].clone();
+                    make_paginate_node(
+                        &name,
+                        parent,
+                        mir_node.columns.as_slice(),
+                        order,
+                        group_by,
+                        *page_size,
+                        *offset,
+                        mig,
+                    )
+                }
                 MirNodeType::Rewrite {
                     ref value,
                     ref column,
@@ -370,7 +463,7 @@ fn adapt_base_node(
             None => DataType::None,
             Some(dv) => dv,
         };
-        let column_id = mig.add_column(na, &a.column.name, default_value);
+        let column_id = mig.add_typed_column(na, a.clone(), default_value);
 
         // store the new column ID in the column specs for this node
         for &mut (ref cs, ref mut cid) in column_specs.iter_mut() {
@@ -404,6 +497,7 @@ fn make_base_node(
     name: &str,
     column_specs: &mut [(ColumnSpecification, Option<usize>)],
     pkey_columns: &[Column],
+    unique_keys: &[Vec<Column>],
     mig: &mut Migration,
 ) -> FlowNode {
     // remember the absolute base column ID for potential later removal
@@ -418,7 +512,7 @@ fn make_base_node(
     let column_names = column_names(columns.as_slice());
 
     // note that this defaults to a "None" (= NULL) default value for columns that do not have one
-    // specified; we don't currently handle a "NOT NULL" SQL constraint for defaults
+    // specified; "NOT NULL" SQL constraints are enforced by the base itself using the column specs
     let default_values = column_specs
         .iter()
         .map(|&(ref cs, _)| {
@@ -431,21 +525,27 @@ fn make_base_node(
         })
         .collect::<Vec<DataType>>();
 
-    let base = if !pkey_columns.is_empty() {
-        let pkey_column_ids = pkey_columns
-            .iter()
-            .map(|pkc| {
-                //assert_eq!(pkc.table.as_ref().unwrap(), name);
+    let column_ids = |key: &[Column]| -> Vec<usize> {
+        key.iter()
+            .map(|kc| {
+                //assert_eq!(kc.table.as_ref().unwrap(), name);
                 column_specs
                     .iter()
-                    .position(|&(ref cs, _)| Column::from(&cs.column) == *pkc)
+                    .position(|&(ref cs, _)| Column::from(&cs.column) == *kc)
                     .unwrap()
             })
-            .collect();
-        node::special::Base::new(default_values).with_key(pkey_column_ids)
+            .collect()
+    };
+
+    let specs = column_specs.iter().map(|(cs, _)| cs.clone()).collect();
+    let base = if !pkey_columns.is_empty() {
+        node::special::Base::new(default_values).with_key(column_ids(pkey_columns))
     } else {
         node::special::Base::new(default_values)
     };
+    let base = base
+        .with_column_specs(specs)
+        .with_unique_keys(unique_keys.iter().map(|k| column_ids(k)).collect());
 
     FlowNode::New(mig.add_base(name, column_names.as_slice(), base))
 }
@@ -734,12 +834,61 @@ fn make_join_node(
     let j = match kind {
         JoinType::Inner => Join::new(left_na, right_na, JoinType::Inner, join_config),
         JoinType::Left => Join::new(left_na, right_na, JoinType::Left, join_config),
+        JoinType::Full => Join::new(left_na, right_na, JoinType::Full, join_config),
     };
     let n = mig.add_ingredient(String::from(name), column_names.as_slice(), j);
 
     FlowNode::New(n)
 }
 
+fn make_semi_join_node(
+    name: &str,
+    left: MirNodeRef,
+    right: MirNodeRef,
+    columns: &[Column],
+    on_left: &[Column],
+    on_right: &[Column],
+    proj_cols: &[Column],
+    kind: SemiJoinType,
+    mig: &mut Migration,
+) -> FlowNode {
+    let column_names = column_names(columns);
+
+    assert_eq!(on_left.len(), 1, "no support for multiple column joins");
+    assert_eq!(on_right.len(), 1, "no support for multiple column joins");
+
+    let left_join_col_id = left.borrow().column_id_for_column(&on_left[0], None);
+    let right_join_col_id = right.borrow().column_id_for_column(&on_right[0], None);
+    let emit = proj_cols
+        .iter()
+        .map(|c| {
+            left.borrow()
+                .columns
+                .iter()
+                .position(|lc| lc == c)
+                .unwrap_or_else(|| {
+                    panic!(
+                        "semi-joins can only emit columns from their left parent, not {:#?}",
+                        c
+                    )
+                })
+        })
+        .collect();
+
+    let left_na = left.borrow().flow_node_addr().unwrap();
+    let right_na = right.borrow().flow_node_addr().unwrap();
+    let j = SemiJoin::new(
+        left_na,
+        right_na,
+        kind,
+        (left_join_col_id, right_join_col_id),
+        emit,
+    );
+    let n = mig.add_ingredient(String::from(name), column_names.as_slice(), j);
+
+    FlowNode::New(n)
+}
+
 fn make_latest_node(
     name: &str,
     parent: MirNodeRef,
@@ -787,6 +936,7 @@ fn make_project_node(
     columns: &[Column],
     emit: &[Column],
     arithmetic: &[(String, ArithmeticExpression)],
+    expressions: &[(String, ProjectExpression, Vec<Column>)],
     literals: &[(String, DataType)],
     mig: &mut Migration,
     table_mapping: Option<&HashMap<(String, Option<String>), String>>,
@@ -810,6 +960,15 @@ fn make_project_node(
                 generate_projection_base(&parent, &e.right),
             )
         })
+        .chain(expressions.iter().map(|&(_, ref e, ref columns)| {
+            let column_ids: Vec<_> = columns
+                .iter()
+                .map(|c| parent.borrow().column_id_for_column(c, table_mapping))
+                .collect();
+            let mut e = e.clone();
+            e.map_columns(&mut |i| column_ids[i]);
+            e
+        }))
         .collect();
 
     let n = mig.add_ingredient(
@@ -914,10 +1073,57 @@ fn make_topk_node(
     FlowNode::New(na)
 }
 
+fn make_paginate_node(
+    name: &str,
+    parent: MirNodeRef,
+    columns: &[Column],
+    order: &Option<Vec<(Column, OrderType)>>,
+    group_by: &[Column],
+    page_size: usize,
+    offset: usize,
+    mig: &mut Migration,
+) -> FlowNode {
+    let parent_na = parent.borrow().flow_node_addr().unwrap();
+    let column_names = column_names(columns);
+
+    assert!(
+        !group_by.is_empty(),
+        "need bogokey for Paginate without group columns"
+    );
+
+    let group_by_indx = group_by
+        .iter()
+        .map(|c| parent.borrow().column_id_for_column(c, None))
+        .collect::<Vec<_>>();
+
+    // unlike TopK, Paginate ranks records in SQL order, so there is no need to reverse it here
+    let cmp_rows = match *order {
+        Some(ref o) => o
+            .iter()
+            .map(|&(ref c, ref order_type)| {
+                (
+                    parent.borrow().column_id_for_column(c, None),
+                    order_type.clone(),
+                )
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+
+    // make the new operator and record its metadata
+    let na = mig.add_ingredient(
+        String::from(name),
+        column_names.as_slice(),
+        ops::paginate::Paginate::new(parent_na, cmp_rows, group_by_indx, page_size, offset),
+    );
+    FlowNode::New(na)
+}
+
 fn materialize_leaf_node(
     parent: &MirNodeRef,
     name: String,
     key_cols: &[Column],
+    range: Option<RangeParameter>,
     mig: &mut Migration,
 ) {
     let na = parent.borrow().flow_node_addr().unwrap();
@@ -934,7 +1140,11 @@ fn materialize_leaf_node(
             .iter()
             .map(|c| parent.borrow().column_id_for_column(c, None))
             .collect();
-        mig.maintain(name, na, &key_cols[..]);
+        if let Some(range) = range {
+            mig.maintain_for_ranges(name, na, &key_cols[..], range);
+        } else {
+            mig.maintain(name, na, &key_cols[..]);
+        }
     } else {
         // if no key specified, default to the first column
         mig.maintain(name, na, &[0]);
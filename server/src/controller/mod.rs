@@ -17,6 +17,7 @@ use hyper::{self, StatusCode};
 use noria::channel::TcpSender;
 use noria::consensus::{Authority, Epoch, STATE_KEY};
 use noria::ControllerDescriptor;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
@@ -41,10 +42,30 @@ pub(crate) struct ControllerState {
 
     recipe_version: usize,
     recipes: Vec<String>,
+
+    /// How the nodes with the given names were split into shards with
+    /// `ControllerHandle::reshard`.
+    #[serde(default)]
+    shardings: HashMap<String, ShardingOverride>,
+}
+
+/// How a node is split into shards in place of how the migration that adds it would split it.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct ShardingOverride {
+    /// The column to shard a base table without a primary key by.
+    pub(crate) column: Option<String>,
+    pub(crate) shards: usize,
+    /// How many times a base table was resharded, which keeps the files of its shards apart from
+    /// those of the shards it took over from.
+    #[serde(default)]
+    pub(crate) generation: usize,
 }
 
 struct Worker {
     healthy: bool,
+    /// Whether the domains of this worker are being moved off it, and it should not be given new
+    /// ones.
+    draining: bool,
     last_heartbeat: time::Instant,
     sender: TcpSender<CoordinationMessage>,
 }
@@ -53,6 +74,7 @@ impl Worker {
     fn new(sender: TcpSender<CoordinationMessage>) -> Self {
         Worker {
             healthy: true,
+            draining: false,
             last_heartbeat: time::Instant::now(),
             sender,
         }
@@ -262,6 +284,7 @@ fn instance_campaign<A: Authority + 'static>(
                         epoch,
                         recipe_version: 0,
                         recipes: vec![],
+                        shardings: HashMap::new(),
                     }),
                     Some(ref state) if state.epoch > epoch => Err(()),
                     Some(mut state) => {
@@ -1,18 +1,21 @@
 use crate::controller::security::SecurityConfig;
-use crate::controller::sql::SqlIncorporator;
+use crate::controller::sql::{
+    expand_unparseable, restore_expressions, take_extensions, Extensions, SqlIncorporator,
+};
 use crate::controller::Migration;
 use crate::ReuseConfigType;
 use dataflow::ops::trigger::Trigger;
 use dataflow::ops::trigger::TriggerEvent;
 use dataflow::prelude::DataType;
+use mir::query::QueryFlowParts;
 use nom_sql::parser as sql_parser;
 use nom_sql::SqlQuery;
-use noria::ActivationResult;
+use noria::{ActivationResult, RecipeError};
 use petgraph::graph::NodeIndex;
 
 use nom_sql::CreateTableStatement;
 use slog;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::str;
 use std::vec::Vec;
 
@@ -22,8 +25,9 @@ type QueryID = u64;
 #[derive(Clone, Debug)]
 // crate viz for tests
 pub(crate) struct Recipe {
-    /// SQL queries represented in the recipe. Value tuple is (name, query, public).
-    expressions: HashMap<QueryID, (Option<String>, SqlQuery, bool)>,
+    /// SQL queries represented in the recipe. Value tuple is (name, query, extensions, public),
+    /// where the extensions are what the SQL front end took out of the query (see `Extensions`).
+    expressions: HashMap<QueryID, (Option<String>, SqlQuery, Extensions, bool)>,
     /// Addition order for the recipe expressions
     expression_order: Vec<QueryID>,
     /// Named read/write expression aliases, mapping to queries in `expressions`.
@@ -61,12 +65,13 @@ pub(super) enum Schema {
     View(Vec<String>),
 }
 
-fn hash_query(q: &SqlQuery) -> QueryID {
+fn hash_query(q: &SqlQuery, ext: &Extensions) -> QueryID {
     use std::collections::hash_map::DefaultHasher;
     use std::hash::{Hash, Hasher};
 
     let mut h = DefaultHasher::new();
     q.hash(&mut h);
+    ext.hash(&mut h);
     h.finish()
 }
 
@@ -123,6 +128,37 @@ fn query_exprs(input: &str) -> nom::IResult<&str, Vec<(bool, Option<&str>, SqlQu
     nom::multi::many1(query_expr)(input)
 }
 
+/// Adds `q` to the graph through `inc`, turning any error raised while doing so into a
+/// `RecipeError` that names the offending query.
+fn add_query_to(
+    inc: &mut SqlIncorporator,
+    q: SqlQuery,
+    ext: Extensions,
+    name: Option<String>,
+    is_leaf: bool,
+    mig: &mut Migration,
+) -> Result<QueryFlowParts, RecipeError> {
+    let sql = q.to_string();
+    inc.add_parsed_query(q, ext, name.clone(), is_leaf, mig)
+        .map_err(|reason| RecipeError {
+            query: name,
+            sql: Some(sql),
+            reason,
+            location: None,
+        })
+}
+
+/// Returns the line and column in the recipe text of byte `offset` into a statement, given the
+/// offset, line and column in the recipe text at which each of the statement's lines starts.
+fn locate(starts: &[(usize, usize, usize)], offset: usize) -> (usize, usize) {
+    let &(start, line, column) = starts
+        .iter()
+        .take_while(|&&(start, _, _)| start <= offset)
+        .last()
+        .unwrap_or(&starts[0]);
+    (line, column + offset.saturating_sub(start))
+}
+
 #[allow(unused)]
 impl Recipe {
     /// Return security groups in the recipe
@@ -187,7 +223,7 @@ impl Recipe {
 
     pub(in crate::controller) fn resolve_alias(&self, alias: &str) -> Option<&str> {
         self.aliases.get(alias).map(|ref qid| {
-            let (ref internal_qn, _, _) = self.expressions[qid];
+            let (ref internal_qn, _, _, _) = self.expressions[qid];
             internal_qn.as_ref().unwrap().as_str()
         })
     }
@@ -238,36 +274,30 @@ impl Recipe {
     /// Note that the recipe is not backed by a Soup data-flow graph until `activate` is called on
     /// it.
     // crate viz for tests
-    pub(crate) fn from_str(recipe_text: &str, log: Option<slog::Logger>) -> Result<Recipe, String> {
-        // remove comment lines
-        let lines: Vec<String> = recipe_text
-            .lines()
-            .map(str::trim)
-            .filter(|l| !l.is_empty() && !l.starts_with('#') && !l.starts_with("--"))
-            .map(String::from)
-            .collect();
-        let cleaned_recipe_text = lines.join("\n");
-
+    pub(crate) fn from_str(
+        recipe_text: &str,
+        log: Option<slog::Logger>,
+    ) -> Result<Recipe, RecipeError> {
         // parse and compute differences to current recipe
-        let parsed_queries = Recipe::parse(&cleaned_recipe_text)?;
+        let parsed_queries = Recipe::parse(recipe_text)?;
 
-        Ok(Recipe::from_queries(parsed_queries, log))
+        Recipe::from_queries(parsed_queries, log)
     }
 
     /// Creates a recipe from a set of pre-parsed `SqlQuery` structures.
     /// Note that the recipe is not backed by a Soup data-flow graph until `activate` is called on
     /// it.
     fn from_queries(
-        qs: Vec<(Option<String>, SqlQuery, bool)>,
+        qs: Vec<(Option<String>, SqlQuery, Extensions, bool)>,
         log: Option<slog::Logger>,
-    ) -> Recipe {
+    ) -> Result<Recipe, RecipeError> {
         let mut aliases = HashMap::default();
         let mut expression_order = Vec::new();
         let mut duplicates = 0;
         let expressions = qs
             .into_iter()
-            .map(|(n, q, is_leaf)| {
-                let qid = hash_query(&q);
+            .map(|(n, q, ext, is_leaf)| {
+                let qid = hash_query(&q, &ext);
                 if !expression_order.contains(&qid) {
                     expression_order.push(qid);
                 } else {
@@ -276,17 +306,21 @@ impl Recipe {
                 match n {
                     None => (),
                     Some(ref name) => {
-                        assert!(
-                            !aliases.contains_key(name) || aliases[name] == qid,
-                            "Query name exists but existing query is different: {}",
-                            name
-                        );
+                        if aliases.contains_key(name) && aliases[name] != qid {
+                            return Err(RecipeError {
+                                query: Some(name.clone()),
+                                sql: Some(q.to_string()),
+                                reason: "query name exists but existing query is different"
+                                    .to_owned(),
+                                location: None,
+                            });
+                        }
                         aliases.insert(name.clone(), qid);
                     }
                 }
-                (qid, (n, q, is_leaf))
+                Ok((qid, (n, q, ext, is_leaf)))
             })
-            .collect::<HashMap<_, _>>();
+            .collect::<Result<HashMap<_, _>, _>>()?;
 
         let inc = match log {
             None => SqlIncorporator::default(),
@@ -300,7 +334,7 @@ impl Recipe {
 
         debug!(log, "{} duplicate queries", duplicates; "version" => 0);
 
-        Recipe {
+        Ok(Recipe {
             expressions,
             expression_order,
             aliases,
@@ -309,7 +343,7 @@ impl Recipe {
             prior: None,
             inc: Some(inc),
             log,
-        }
+        })
     }
 
     /// Creates a new security universe
@@ -340,7 +374,7 @@ impl Recipe {
         }
 
         for expr in self.expressions.values() {
-            let (n, q, is_leaf) = expr.clone();
+            let (n, q, ext, is_leaf) = expr.clone();
 
             // add the universe-specific query
             // don't use query name to avoid conflict with global queries
@@ -365,7 +399,7 @@ impl Recipe {
                 .inc
                 .as_mut()
                 .unwrap()
-                .add_parsed_query(q, new_name, is_leaf, mig)?;
+                .add_parsed_query(q, ext, new_name, is_leaf, mig)?;
 
             // If the user provided us with a query name, use that.
             // If not, use the name internally used by the QFP.
@@ -383,8 +417,25 @@ impl Recipe {
     /// Activate the recipe by migrating the Soup data-flow graph wrapped in `mig` to the recipe.
     /// This causes all necessary changes to said graph to be applied; however, it is the caller's
     /// responsibility to call `mig.commit()` afterwards.
+    ///
+    /// If activation fails, the incorporator is rolled back to its state from before the call,
+    /// and the caller should abort `mig` rather than commit it.
     // crate viz for tests
-    pub(crate) fn activate(&mut self, mig: &mut Migration) -> Result<ActivationResult, String> {
+    pub(crate) fn activate(
+        &mut self,
+        mig: &mut Migration,
+    ) -> Result<ActivationResult, RecipeError> {
+        let sp = self.inc.as_mut().unwrap().savepoint();
+        let r = self.activate_queries(mig);
+        let inc = self.inc.as_mut().unwrap();
+        match r {
+            Ok(_) => inc.release(sp),
+            Err(_) => inc.rollback(sp),
+        }
+        r
+    }
+
+    fn activate_queries(&mut self, mig: &mut Migration) -> Result<ActivationResult, RecipeError> {
         debug!(self.log, "{} queries, {} of which are named",
                                  self.expressions.len(),
                                  self.aliases.len(); "version" => self.version);
@@ -408,24 +459,62 @@ impl Recipe {
         // tagged with the new version. If this recipe was just created, there is no need to
         // upgrade the schema version, as the SqlIncorporator's version will still be at zero.
         if self.version > 0 {
-            self.inc.as_mut().unwrap().upgrade_schema(self.version);
+            self.inc
+                .as_mut()
+                .unwrap()
+                .upgrade_schema(self.version)
+                .map_err(RecipeError::new)?;
+        }
+
+        // make sure that everything we are asked to remove can be removed before changing
+        // anything, so that we don't add queries only to fail on a removal
+        for qid in &removed {
+            let (ref n, ref q, _, _) = self.prior.as_ref().unwrap().expressions[qid];
+            let inc = self.inc.as_ref().unwrap();
+            let error = |reason: String| RecipeError {
+                query: n.clone(),
+                sql: Some(q.to_string()),
+                reason,
+                location: None,
+            };
+            match q {
+                SqlQuery::CreateTable(ref ctq) => {
+                    if inc.get_query_address(&ctq.table.name).is_none() {
+                        crit!(
+                            self.log,
+                            "failed to remove base {} whose address could not be resolved",
+                            ctq.table.name
+                        );
+                        return Err(error(
+                            "address of removed base could not be resolved".to_owned(),
+                        ));
+                    }
+                }
+                _ => {
+                    let name = n
+                        .as_ref()
+                        .ok_or_else(|| error("unnamed queries cannot be removed".to_owned()))?;
+                    inc.check_removal(name, mig).map_err(error)?;
+                }
+            }
         }
 
         // create nodes to enforce security configuration
-        if self.security_config.is_some() {
+        if let Some(ref config) = self.security_config {
             info!(
                 self.log,
                 "Found a security configuration, bootstrapping groups..."
             );
-            let config = self.security_config.take().unwrap();
             for group in config.groups.values() {
                 info!(
                     self.log,
                     "Creating membership view for group {}",
                     group.name()
                 );
-                let qfp = self.inc.as_mut().unwrap().add_parsed_query(
+                let qfp = add_query_to(
+                    self.inc.as_mut().unwrap(),
                     group.membership(),
+                    Extensions::default(),
                     Some(group.name()),
                     true,
                     mig,
@@ -444,22 +533,16 @@ impl Recipe {
 
                 result.new_nodes.insert(group.name(), qfp.query_leaf);
             }
-
-            self.security_config = Some(config);
         }
 
         // add new queries to the Soup graph carried by `mig`, and reflect state in the
         // incorporator in `inc`. `NodeIndex`es for new nodes are collected in `new_nodes` to be
         // returned to the caller (who may use them to obtain mutators and getters)
         for qid in added {
-            let (n, q, is_leaf) = self.expressions[&qid].clone();
+            let (n, q, ext, is_leaf) = self.expressions[&qid].clone();
 
             // add the query
-            let qfp = self
-                .inc
-                .as_mut()
-                .unwrap()
-                .add_parsed_query(q, n.clone(), is_leaf, mig)?;
+            let qfp = add_query_to(self.inc.as_mut().unwrap(), q, ext, n.clone(), is_leaf, mig)?;
 
             // If the user provided us with a query name, use that.
             // If not, use the name internally used by the QFP.
@@ -471,37 +554,33 @@ impl Recipe {
             result.new_nodes.insert(query_name, qfp.query_leaf);
         }
 
-        result.removed_leaves = removed
-            .iter()
-            .filter_map(|qid| {
-                let (ref n, ref q, _) = self.prior.as_ref().unwrap().expressions[qid];
-                match q {
-                    SqlQuery::CreateTable(ref ctq) => {
-                        // a base may have many dependent queries, including ones that also lost
-                        // nodes; the code handling `removed_leaves` therefore needs to take care
-                        // not to remove bases while they still have children, or to try removing
-                        // them twice.
-                        self.inc.as_mut().unwrap().remove_base(&ctq.table.name);
-                        match self.prior.as_ref().unwrap().node_addr_for(&ctq.table.name) {
-                            Ok(ni) => Some(ni),
-                            Err(e) => {
-                                crit!(
-                                    self.log,
-                                    "failed to remove base {} whose  address could not be resolved",
-                                    ctq.table.name
-                                );
-                                unimplemented!()
-                            }
-                        }
+        for qid in removed {
+            let (ref n, ref q, _, _) = self.prior.as_ref().unwrap().expressions[&qid];
+            let inc = self.inc.as_mut().unwrap();
+            let error = |reason: String| RecipeError {
+                query: n.clone(),
+                sql: Some(q.to_string()),
+                reason,
+                location: None,
+            };
+            match q {
+                SqlQuery::CreateTable(ref ctq) => {
+                    // a base may have many dependent queries, including ones that also lost
+                    // nodes; the code handling `removed_leaves` therefore needs to take care
+                    // not to remove bases while they still have children, or to try removing
+                    // them twice.
+                    let ni = inc.get_query_address(&ctq.table.name).unwrap();
+                    inc.remove_base(&ctq.table.name).map_err(error)?;
+                    result.removed_leaves.push(ni);
+                }
+                _ => {
+                    let name = n.as_ref().unwrap();
+                    if let Some(ni) = inc.remove_query(name, mig).map_err(error)? {
+                        result.removed_leaves.push(ni);
                     }
-                    _ => self
-                        .inc
-                        .as_mut()
-                        .unwrap()
-                        .remove_query(n.as_ref().unwrap(), mig),
                 }
-            })
-            .collect();
+            }
+        }
 
         Ok(result)
     }
@@ -532,7 +611,7 @@ impl Recipe {
     pub(crate) fn expressions(&self) -> Vec<(Option<&String>, &SqlQuery)> {
         self.expressions
             .values()
-            .map(|&(ref n, ref q, _)| (n.as_ref(), q))
+            .map(|&(ref n, ref q, _, _)| (n.as_ref(), q))
             .collect()
     }
 
@@ -541,7 +620,7 @@ impl Recipe {
     /// recipe; use `replace` if removal of unused expressions is desired.
     /// Consumes `self` and returns a replacement recipe.
     // crate viz for tests
-    pub(crate) fn extend(mut self, additions: &str) -> Result<Recipe, (Recipe, String)> {
+    pub(crate) fn extend(mut self, additions: &str) -> Result<Recipe, (Recipe, RecipeError)> {
         // parse and compute differences to current recipe
         let add_rp = match Recipe::from_str(additions, None) {
             Ok(rp) => rp,
@@ -549,6 +628,18 @@ impl Recipe {
         };
         let (added, _) = add_rp.compute_delta(&self);
 
+        for (n, qid) in &add_rp.aliases {
+            if self.aliases.contains_key(n) && self.aliases[n] != *qid {
+                let e = RecipeError {
+                    query: Some(n.clone()),
+                    sql: Some(add_rp.expressions[qid].1.to_string()),
+                    reason: "query name exists but existing query is different".to_owned(),
+                    location: None,
+                };
+                return Err((self, e));
+            }
+        }
+
         // move the incorporator state from the old recipe to the new one
         let prior_inc = self.inc.take();
 
@@ -572,13 +663,6 @@ impl Recipe {
             new.expression_order.push(qid);
         }
 
-        for (n, qid) in &add_rp.aliases {
-            assert!(
-                !new.aliases.contains_key(n) || new.aliases[n] == *qid,
-                "Query name exists but existing query is different: {}",
-                n
-            );
-        }
         new.aliases.extend(add_rp.aliases);
 
         // return new recipe as replacement for self
@@ -601,68 +685,102 @@ impl Recipe {
         self.inc = Some(new_inc);
     }
 
-    fn parse(recipe_text: &str) -> Result<Vec<(Option<String>, SqlQuery, bool)>, String> {
-        let lines: Vec<&str> = recipe_text
+    fn parse(
+        recipe_text: &str,
+    ) -> Result<Vec<(Option<String>, SqlQuery, Extensions, bool)>, RecipeError> {
+        // remove comment lines, and remember where the remaining ones are
+        let lines: Vec<(usize, usize, &str)> = recipe_text
             .lines()
-            .filter(|l| !l.is_empty() && !l.starts_with('#'))
-            .map(|l| {
+            .enumerate()
+            .map(|(i, l)| {
                 // remove inline comments, too
-                match l.find('#') {
-                    None => l.trim(),
-                    Some(pos) => &l[0..pos - 1].trim(),
-                }
+                let l = match l.find('#') {
+                    None => l,
+                    Some(pos) => &l[0..pos],
+                };
+                let trimmed = l.trim_start();
+                (i + 1, l.len() - trimmed.len() + 1, trimmed.trim_end())
             })
+            .filter(|&(_, _, l)| !l.is_empty() && !l.starts_with("--"))
             .collect();
+
+        // each query string is kept along with the offset, line and column at which each of its
+        // lines starts
         let mut query_strings = Vec::new();
         let mut q = String::new();
+        let mut starts = Vec::new();
 
         let linecount = lines.len();
-        let mut i = 1;
-        for l in lines {
-            if !l.ends_with(';') && i < linecount {
-                q.push_str(l);
+        for (i, (line, column, l)) in lines.into_iter().enumerate() {
+            starts.push((q.len(), line, column));
+            q.push_str(l);
+            if !l.ends_with(';') && i + 1 < linecount {
                 q.push_str(" ");
             } else {
                 // either line ends with semicolor, or it does not and this is the last line
                 // in both cases, we're at the end of the query
-                q.push_str(l);
-                query_strings.push(q);
+                query_strings.push((q, starts));
                 q = String::new();
+                starts = Vec::new();
             }
-            i += 1;
         }
 
-        let parsed_queries = query_strings.iter().fold(
-            Vec::new(),
-            |mut acc: Vec<Result<(bool, Option<&str>, SqlQuery), String>>, q| {
-                match query_exprs(q) {
-                    Result::Err(e) => {
-                        // we got a parse error
-                        acc.push(Err(format!("Query \"{}\", parse error: {}", q, e)));
-                    }
-                    Result::Ok((remainder, parsed)) => {
-                        // should have consumed all input
-                        assert!(
-                            remainder.is_empty(),
-                            format!(
-                                "failed to parse the complete recipe; left with: {}",
-                                remainder
+        let mut parsed_queries = Vec::new();
+        for (q, starts) in &query_strings {
+            let error = |offset: usize, reason: String| RecipeError {
+                query: None,
+                sql: Some(q.clone()),
+                reason,
+                location: Some(locate(starts, offset)),
+            };
+            let (expanded, expressions) =
+                expand_unparseable(q).map_err(|e| error(0, format!("parse error: {}", e)))?;
+            // offsets into `expanded` only line up with the recipe text if nothing was expanded
+            let offset = |rest: &str| {
+                if expressions.is_empty() {
+                    expanded.len() - rest.len()
+                } else {
+                    0
+                }
+            };
+            match query_exprs(&expanded) {
+                Result::Err(e) => {
+                    // we got a parse error
+                    let at = match e {
+                        nom::Err::Error((rest, _)) | nom::Err::Failure((rest, _)) => offset(rest),
+                        nom::Err::Incomplete(_) => 0,
+                    };
+                    return Err(error(at, format!("parse error: {}", e)));
+                }
+                Result::Ok((remainder, parsed)) => {
+                    // should have consumed all input
+                    if !remainder.is_empty() {
+                        return Err(RecipeError {
+                            sql: Some(remainder.to_owned()),
+                            ..error(
+                                offset(remainder),
+                                "failed to parse the complete recipe".to_owned(),
                             )
-                        );
-                        acc.extend(parsed.into_iter().map(|p| Ok(p)).collect::<Vec<_>>());
+                        });
+                    }
+                    let mut restored = 0;
+                    for (public, name, mut parsed) in parsed {
+                        restored += restore_expressions(&mut parsed, &expressions)
+                            .map_err(|e| error(0, e))?;
+                        let ext = take_extensions(&mut parsed).map_err(|e| error(0, e))?;
+                        parsed_queries.push((name.map(String::from), parsed, ext, public));
+                    }
+                    if restored != expressions.len() {
+                        return Err(error(
+                            0,
+                            "unsupported expression outside of a select list".to_owned(),
+                        ));
                     }
                 }
-                acc
-            },
-        );
+            }
+        }
 
-        Ok(parsed_queries
-            .into_iter()
-            .map(|pr| {
-                let pr = pr.unwrap();
-                (pr.1.map(String::from), pr.2, pr.0)
-            })
-            .collect::<Vec<_>>())
+        Ok(parsed_queries)
     }
 
     /// Returns the predecessor from which this `Recipe` was migrated to.
@@ -724,12 +842,46 @@ impl Recipe {
     /// Reverts to prior version of recipe
     pub(super) fn revert(self) -> Recipe {
         if let Some(prior) = self.prior {
-            *prior
+            let mut prior = *prior;
+            // the incorporator and security configuration moved to this recipe when it was derived
+            // from `prior`, so hand them back
+            if prior.inc.is_none() {
+                prior.inc = self.inc;
+            }
+            if prior.security_config.is_none() {
+                prior.security_config = self.security_config;
+            }
+            prior
         } else {
             Recipe::blank(Some(self.log))
         }
     }
 
+    /// Builds the nodes at the addresses in `affected` again, along with the queries over the
+    /// base in `replace` (see `SqlIncorporator::rebuild_nodes`), and returns the address of the
+    /// node that takes over from each of them.
+    ///
+    /// Returns `None`, and leaves the recipe as it was, if any of the nodes in `required` could
+    /// not be built again.
+    pub(super) fn rebuild_nodes(
+        &mut self,
+        affected: &HashSet<NodeIndex>,
+        replace: Option<(NodeIndex, NodeIndex)>,
+        required: &[NodeIndex],
+        mig: &mut Migration,
+    ) -> Option<HashMap<NodeIndex, NodeIndex>> {
+        let inc = self.inc.as_mut().unwrap();
+        let sp = inc.savepoint();
+        let rebuilt = inc.rebuild_nodes(affected, replace, mig);
+        if required.iter().all(|ni| rebuilt.contains_key(ni)) {
+            inc.release(sp);
+            Some(rebuilt)
+        } else {
+            inc.rollback(sp);
+            None
+        }
+    }
+
     pub(super) fn queries_for_nodes(&self, nodes: Vec<NodeIndex>) -> Vec<String> {
         nodes
             .iter()
@@ -776,11 +928,14 @@ mod tests {
         let q0 = sql_parser::parse_query("SELECT a FROM b;").unwrap();
         let q1 = sql_parser::parse_query("SELECT a, c FROM b WHERE x = 42;").unwrap();
 
-        let q0_id = hash_query(&q0);
-        let q1_id = hash_query(&q1);
+        let q0_id = hash_query(&q0, &Extensions::default());
+        let q1_id = hash_query(&q1, &Extensions::default());
 
-        let pq_a = vec![(None, q0.clone(), true), (None, q1.clone(), true)];
-        let r1 = Recipe::from_queries(pq_a, None);
+        let pq_a = vec![
+            (None, q0.clone(), Extensions::default(), true),
+            (None, q1.clone(), Extensions::default(), true),
+        ];
+        let r1 = Recipe::from_queries(pq_a, None).unwrap();
 
         // delta from empty recipe
         let (added, removed) = r1.compute_delta(&r0);
@@ -796,9 +951,12 @@ mod tests {
 
         // bring on a new query set
         let q2 = sql_parser::parse_query("SELECT c FROM b;").unwrap();
-        let q2_id = hash_query(&q2);
-        let pq_b = vec![(None, q0, true), (None, q2.clone(), true)];
-        let r2 = Recipe::from_queries(pq_b, None);
+        let q2_id = hash_query(&q2, &Extensions::default());
+        let pq_b = vec![
+            (None, q0, Extensions::default(), true),
+            (None, q2.clone(), Extensions::default(), true),
+        ];
+        let r2 = Recipe::from_queries(pq_b, None).unwrap();
 
         // delta should show addition and removal
         let (added, removed) = r2.compute_delta(&r1);
@@ -848,7 +1006,6 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "Query name exists but existing query is different")]
     fn it_avoids_spurious_aliasing() {
         let r0 = Recipe::blank(None);
 
@@ -859,11 +1016,36 @@ mod tests {
         assert_eq!(r1.expressions.len(), 2);
 
         let r2_txt = "q_0: SELECT a, c FROM b WHERE x = 21;\nq_1: SELECT c FROM b;";
-        // we expect this to panic, since both q_0 and q_1 already exist with a different
+        // we expect this to fail, since both q_0 and q_1 already exist with a different
         // definition
-        let r2 = r1.extend(r2_txt).unwrap();
-        assert_eq!(r2.version, 2);
-        assert_eq!(r2.expressions.len(), 4);
+        let (r1, e) = r1.extend(r2_txt).unwrap_err();
+        assert_eq!(
+            e.reason,
+            "query name exists but existing query is different"
+        );
+        assert!(e.query == Some("q_0".into()) || e.query == Some("q_1".into()));
+        // the original recipe is handed back unchanged
+        assert_eq!(r1.version, 1);
+        assert_eq!(r1.expressions.len(), 2);
+    }
+
+    #[test]
+    fn it_reports_parse_errors() {
+        let r1_txt = "q_0: SELECT a FROM b;\nq_1: SELEKT x FROM y;";
+        let e = Recipe::from_str(r1_txt, None).unwrap_err();
+        assert_eq!(e.query, None);
+        assert_eq!(e.sql, Some("q_1: SELEKT x FROM y;".to_owned()));
+        assert!(e.reason.starts_with("parse error"));
+        assert_eq!(e.location.map(|(line, _)| line), Some(2));
+    }
+
+    #[test]
+    fn it_locates_errors_in_statements_spanning_lines() {
+        let r1_txt = "# comment\nq_0: SELECT a FROM b;\n\n  q_1: SELECT x\n    FROM y WHERE;";
+        let e = Recipe::from_str(r1_txt, None).unwrap_err();
+        let (line, column) = e.location.unwrap();
+        assert!(line == 4 || line == 5);
+        assert!(column >= 3);
     }
 
     #[test]
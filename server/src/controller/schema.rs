@@ -24,6 +24,14 @@ fn to_sql_type(d: &DataType) -> Option<SqlType> {
         // type), so caller must handle appropriately.
         DataType::None => None,
         DataType::Timestamp(_) => Some(SqlType::Timestamp),
+        DataType::Decimal(ref d) => Some(SqlType::Decimal(noria::MAX_DECIMAL_DIGITS, d.scale())),
+        DataType::Date(_) => Some(SqlType::Date),
+        // nom-sql has no SqlType for `TIME`, so times are described by their text form, which is
+        // no longer than `-838:59:59.000000` for any time that MySQL can hold
+        DataType::Time(_) => Some(SqlType::Varchar(17)),
+        DataType::Bool(_) => Some(SqlType::Bool),
+        DataType::ByteArray(_) => Some(SqlType::Blob),
+        DataType::Json(_) => Some(SqlType::Text),
     }
 }
 
@@ -52,7 +60,23 @@ fn type_for_internal_column(
                 to_sql_type(&emits.1[off])
             }
         }
-        ops::NodeOperator::Sum(_) | ops::NodeOperator::FilterSum(_) => {
+        ops::NodeOperator::Sum(ref o) => {
+            use dataflow::ops::grouped::aggregate::Aggregation;
+
+            // computed column is always emitted last, after any state columns
+            if column_index < node.fields().len() - 1 {
+                // state columns hold counts and (integral) sums
+                return Some(SqlType::Bigint(64));
+            }
+            match *o.kind() {
+                Aggregation::COUNT | Aggregation::SUM => Some(SqlType::Bigint(64)),
+                Aggregation::AVG | Aggregation::VARIANCE | Aggregation::STDDEV => {
+                    Some(SqlType::Real)
+                }
+                Aggregation::BOOLAND | Aggregation::BOOLOR => Some(SqlType::Bool),
+            }
+        }
+        ops::NodeOperator::FilterSum(_) => {
             // computed column is always emitted last
             if column_index == node.fields().len() - 1 {
                 // counts and sums always produce integral columns
@@ -80,7 +104,7 @@ fn type_for_internal_column(
                 unreachable!();
             }
         }
-        ops::NodeOperator::Join(_) => {
+        ops::NodeOperator::Join(_) | ops::NodeOperator::SemiJoin(_) => {
             // join doesn't "generate" columns, but they may come from one of the other
             // ancestors; so keep iterating to try the other paths
             None
@@ -0,0 +1,1024 @@
+use super::expand_unparseable;
+use super::lexer::RESERVED_PREFIX;
+use super::lexer::{check_reserved, is_identifier, join, tokenize, Token, TokenKind};
+use super::passes::subqueries::scalar_subquery_join;
+use dataflow::ops::project::DatePart;
+use nom_sql::parser::parse_query;
+use nom_sql::{ArithmeticOperator, Column, Literal, Operator};
+use nom_sql::{ConditionBase, ConditionExpression, FieldDefinitionExpression};
+use nom_sql::{JoinRightSide, SelectSpecification, SelectStatement, SqlQuery};
+
+use noria::Decimal;
+use std::fmt;
+
+/// Functions that nom-sql cannot parse, but which may be used in the select list of a query.
+const FUNCTIONS: &[&str] = &["coalesce", "ifnull", "lower", "upper", "concat", "extract"];
+
+/// Words that cannot be used as column names in an expression without quoting them.
+const KEYWORDS: &[&str] = &[
+    "case", "when", "then", "else", "end", "and", "or", "not", "is", "null", "from",
+];
+
+/// An expression in the select list of a query that nom-sql cannot parse: `CASE`, `COALESCE`,
+/// `IFNULL`, `LOWER`, `UPPER`, `CONCAT` and `EXTRACT`, along with arithmetic over them.
+///
+/// nom-sql's AST has no place for these, so the SQL front end parses them itself and stores each
+/// one in the select list as a column without a table whose name is the expression's SQL text
+/// (see `from_column`). nom-sql never produces such names, since it only accepts plain
+/// identifiers as column names.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Expression {
+    Column(Column),
+    Literal(Literal),
+    /// A number with a decimal point, which nom-sql's `Literal` cannot hold exactly.
+    Decimal(Decimal),
+    Arithmetic(ArithmeticOperator, Box<Expression>, Box<Expression>),
+    /// `CASE WHEN .. THEN .. [ELSE ..] END`; without an `ELSE`, the expression is `NULL` when no
+    /// branch matches.
+    Case(Vec<(Condition, Expression)>, Option<Box<Expression>>),
+    /// `COALESCE(..)` and `IFNULL(..)`.
+    Coalesce(Vec<Expression>),
+    Lower(Box<Expression>),
+    Upper(Box<Expression>),
+    Concat(Vec<Expression>),
+    Extract(DatePart, Box<Expression>),
+}
+
+/// What a placeholder that `expand_expressions` put in a select list stands for.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Extracted {
+    /// An expression column (see `Expression::into_column`).
+    Expression(Column),
+    /// A scalar subquery, parsed on its own, whose result is projected under the given name.
+    Subquery(String, SelectStatement),
+}
+
+/// A condition in a `WHEN` branch of a `CASE` expression.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Condition {
+    Comparison(Operator, Expression, Expression),
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+}
+
+impl Expression {
+    /// Returns the expression stored in `column`, if it is an expression column.
+    pub(crate) fn from_column(column: &Column) -> Option<Expression> {
+        if column.function.is_some() || column.table.is_some() || is_identifier(&column.name) {
+            return None;
+        }
+        parse(&column.name).ok()
+    }
+
+    /// Returns a column that stores this expression under the output name `alias`.
+    pub(crate) fn into_column(self, alias: String) -> Column {
+        Column {
+            name: self.to_string(),
+            alias: Some(alias),
+            table: None,
+            function: None,
+        }
+    }
+
+    /// Calls `f` on every column that this expression refers to.
+    pub(crate) fn for_each_column<F: FnMut(&mut Column)>(&mut self, f: &mut F) {
+        match *self {
+            Expression::Column(ref mut c) => f(c),
+            Expression::Literal(_) | Expression::Decimal(_) => (),
+            Expression::Arithmetic(_, ref mut left, ref mut right) => {
+                left.for_each_column(f);
+                right.for_each_column(f);
+            }
+            Expression::Case(ref mut branches, ref mut otherwise) => {
+                for &mut (ref mut condition, ref mut e) in branches {
+                    condition.for_each_column(f);
+                    e.for_each_column(f);
+                }
+                if let Some(ref mut e) = *otherwise {
+                    e.for_each_column(f);
+                }
+            }
+            Expression::Coalesce(ref mut es) | Expression::Concat(ref mut es) => {
+                for e in es {
+                    e.for_each_column(f);
+                }
+            }
+            Expression::Lower(ref mut e)
+            | Expression::Upper(ref mut e)
+            | Expression::Extract(_, ref mut e) => e.for_each_column(f),
+        }
+    }
+}
+
+impl Condition {
+    fn for_each_column<F: FnMut(&mut Column)>(&mut self, f: &mut F) {
+        match *self {
+            Condition::Comparison(_, ref mut left, ref mut right) => {
+                left.for_each_column(f);
+                right.for_each_column(f);
+            }
+            Condition::And(ref mut cs) | Condition::Or(ref mut cs) => {
+                for c in cs {
+                    c.for_each_column(f);
+                }
+            }
+        }
+    }
+}
+
+/// Calls `f` on every column that the expression stored in `column` refers to, and stores the
+/// rewritten expression back into `column`. Returns false if `column` is not an expression
+/// column.
+pub(crate) fn rewrite_columns<F: FnMut(&mut Column)>(column: &mut Column, mut f: F) -> bool {
+    match Expression::from_column(column) {
+        Some(mut e) => {
+            e.for_each_column(&mut f);
+            column.name = e.to_string();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Returns true if the select list item in `tokens` contains an expression that nom-sql cannot
+/// parse. Expressions inside the arguments of other functions, such as the `CASE` in
+/// `SUM(CASE WHEN .. END)`, are left to nom-sql.
+pub(crate) fn is_extended(tokens: &[Token]) -> bool {
+    let significant: Vec<_> = tokens.iter().filter(|t| t.is_significant()).collect();
+    let is_function = |t: &Token| FUNCTIONS.iter().any(|f| t.is_keyword(f));
+    // for each open parenthesis, whether it holds the arguments of a function that nom-sql parses
+    let mut opaque = Vec::new();
+    for (i, t) in significant.iter().enumerate() {
+        let next_is_paren = significant.get(i + 1).map_or(false, |n| n.is_symbol("("));
+        if t.is_symbol("(") {
+            let call = i > 0
+                && significant[i - 1].kind == TokenKind::Word
+                && !is_function(significant[i - 1])
+                && !KEYWORDS.iter().any(|kw| significant[i - 1].is_keyword(kw));
+            opaque.push(call);
+        } else if t.is_symbol(")") {
+            opaque.pop();
+        } else if !opaque.contains(&true)
+            && (t.is_keyword("case") || (is_function(t) && next_is_paren))
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// Takes the expressions that nom-sql cannot parse (see `is_extended`), and the scalar subqueries,
+/// out of the select lists in `sql`, and puts a placeholder column named `__noria_expr_<n>` in
+/// place of each one. Returns the rewritten SQL along with what each placeholder stands for, which
+/// `restore_expressions` puts back once the rewritten SQL has been parsed.
+pub(crate) fn expand_expressions(sql: &str) -> Result<(String, Vec<Extracted>), String> {
+    let tokens = tokenize(sql)?;
+    check_reserved(&tokens)?;
+
+    let mut items = Vec::new();
+    for select in (0..tokens.len()).filter(|&i| tokens[i].is_keyword("select")) {
+        items.extend(
+            select_items(&tokens, select + 1)
+                .into_iter()
+                .filter(|&(start, end)| {
+                    // other expressions that contain subqueries are left to nom-sql
+                    let item = &tokens[start..end];
+                    is_scalar_subquery(split_alias(item).0)
+                        || (is_extended(item) && !item.iter().any(|t| t.is_keyword("select")))
+                }),
+        );
+    }
+    items.sort();
+
+    let mut expanded = String::with_capacity(sql.len());
+    let mut extracted = Vec::with_capacity(items.len());
+    let mut subqueries = 0;
+    let mut copied = 0;
+    for (start, end) in items {
+        if start < copied {
+            // nested in a scalar subquery, which takes care of it when it is parsed
+            continue;
+        }
+        let (expression, alias) = split_alias(&tokens[start..end]);
+        let placeholder = format!("{}expr_{}", RESERVED_PREFIX, extracted.len());
+        if is_scalar_subquery(expression) {
+            let name = alias.map_or_else(|| format!("scalar{}", subqueries), String::from);
+            subqueries += 1;
+            extracted.push(Extracted::Subquery(name, parse_subquery(expression)?));
+        } else {
+            // like MySQL, name the column after its SQL text if it has no alias
+            let alias = alias.map_or_else(|| join(expression).trim().to_owned(), String::from);
+            extracted.push(Extracted::Expression(
+                parse_tokens(expression)?.into_column(alias),
+            ));
+        }
+        expanded.push_str(&join(&tokens[copied..start]));
+        expanded.push_str(&placeholder);
+        copied = end;
+    }
+    expanded.push_str(&join(&tokens[copied..]));
+    Ok((expanded, extracted))
+}
+
+/// Returns true if `tokens` are a parenthesized subquery and nothing else.
+fn is_scalar_subquery(tokens: &[Token]) -> bool {
+    let significant: Vec<_> = tokens.iter().filter(|t| t.is_significant()).collect();
+    if significant.len() < 3
+        || !significant[0].is_symbol("(")
+        || !significant[1].is_keyword("select")
+    {
+        return false;
+    }
+    let mut depth = 0;
+    for (i, t) in significant.iter().enumerate() {
+        if t.is_symbol("(") {
+            depth += 1;
+        } else if t.is_symbol(")") {
+            depth -= 1;
+            if depth == 0 {
+                return i == significant.len() - 1;
+            }
+        }
+    }
+    false
+}
+
+/// Parses the parenthesized subquery in `tokens` (see `is_scalar_subquery`) on its own.
+fn parse_subquery(tokens: &[Token]) -> Result<SelectStatement, String> {
+    let significant: Vec<_> = (0..tokens.len())
+        .filter(|&i| tokens[i].is_significant())
+        .collect();
+    let sql = join(&tokens[significant[0] + 1..significant[significant.len() - 1]]);
+    let (expanded, extracted) = expand_unparseable(&sql)?;
+    let mut q = parse_query(&expanded).map_err(String::from)?;
+    if restore_expressions(&mut q, &extracted)? != extracted.len() {
+        return Err(format!(
+            "unsupported expression in subquery: {}",
+            sql.trim()
+        ));
+    }
+    match q {
+        SqlQuery::Select(s) => Ok(s),
+        _ => Err(format!("not a subquery: {}", sql.trim())),
+    }
+}
+
+/// Returns the (start, end) positions of the items in the select list that starts at `from`.
+/// Neither includes the whitespace around an item.
+fn select_items(tokens: &[Token], from: usize) -> Vec<(usize, usize)> {
+    let mut items = Vec::new();
+    let mut start = None;
+    let mut end = from;
+    let mut depth = 0;
+    for (i, t) in tokens.iter().enumerate().skip(from) {
+        if depth == 0 {
+            let ends_list = t.is_symbol(";")
+                || t.is_symbol(")")
+                || ["from", "where", "group", "order", "limit", "union"]
+                    .iter()
+                    .any(|kw| t.is_keyword(kw));
+            if ends_list || t.is_symbol(",") {
+                if let Some(start) = start.take() {
+                    items.push((start, end));
+                }
+                if ends_list {
+                    return items;
+                }
+                continue;
+            }
+            if start.is_none() && (t.is_keyword("distinct") || t.is_keyword("all")) {
+                continue;
+            }
+        }
+        if t.is_symbol("(") {
+            depth += 1;
+        } else if t.is_symbol(")") {
+            depth -= 1;
+        }
+        if t.is_significant() {
+            start = start.or(Some(i));
+            end = i + 1;
+        }
+    }
+    if let Some(start) = start {
+        items.push((start, end));
+    }
+    items
+}
+
+/// Splits a select list item into its expression and its alias, if it has one.
+fn split_alias<'a, 'b>(item: &'b [Token<'a>]) -> (&'b [Token<'a>], Option<&'a str>) {
+    let significant: Vec<_> = (0..item.len())
+        .filter(|&i| item[i].is_significant())
+        .collect();
+    if significant.len() < 3 {
+        return (item, None);
+    }
+    let last = &item[significant[significant.len() - 1]];
+    let before = significant[significant.len() - 2];
+    let alias = match last.kind {
+        TokenKind::Word if KEYWORDS.iter().any(|kw| last.is_keyword(kw)) => None,
+        TokenKind::Word | TokenKind::QuotedIdentifier => last.identifier(),
+        _ => None,
+    };
+    match alias {
+        Some(alias) if item[before].is_keyword("as") => (&item[..before], Some(alias)),
+        Some(alias) if item[before].is_symbol(")") || item[before].is_keyword("end") => {
+            (&item[..=before], Some(alias))
+        }
+        _ => (item, None),
+    }
+}
+
+/// Puts what `expand_expressions` took out of the select lists in a query back in place of their
+/// placeholders. Expressions go back as they were, while each scalar subquery becomes a left join
+/// against the subquery (see `scalar_subquery_join`) whose result is selected in its place. Returns
+/// the number of placeholders that were replaced.
+pub(crate) fn restore_expressions(
+    query: &mut SqlQuery,
+    extracted: &[Extracted],
+) -> Result<usize, String> {
+    let mut restored = 0;
+    match *query {
+        SqlQuery::Select(ref mut s) => restore_select(s, extracted, &mut restored)?,
+        SqlQuery::CompoundSelect(ref mut cs) => {
+            for &mut (_, ref mut s) in &mut cs.selects {
+                restore_select(s, extracted, &mut restored)?;
+            }
+        }
+        SqlQuery::CreateView(ref mut v) => match *v.definition {
+            SelectSpecification::Simple(ref mut s) => restore_select(s, extracted, &mut restored)?,
+            SelectSpecification::Compound(ref mut cs) => {
+                for &mut (_, ref mut s) in &mut cs.selects {
+                    restore_select(s, extracted, &mut restored)?;
+                }
+            }
+        },
+        _ => (),
+    }
+    Ok(restored)
+}
+
+fn restore_select(
+    s: &mut SelectStatement,
+    extracted: &[Extracted],
+    restored: &mut usize,
+) -> Result<(), String> {
+    let prefix = format!("{}expr_", RESERVED_PREFIX);
+    let mut subqueries = Vec::new();
+    for (i, field) in s.fields.iter_mut().enumerate() {
+        if let FieldDefinitionExpression::Col(ref mut c) = *field {
+            if c.table.is_some() || c.function.is_some() || !c.name.starts_with(&prefix) {
+                continue;
+            }
+            match c.name[prefix.len()..]
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| extracted.get(i))
+            {
+                Some(Extracted::Expression(e)) => *c = e.clone(),
+                Some(Extracted::Subquery(name, sq)) => subqueries.push((i, name, sq)),
+                None => return Err(format!("unknown expression placeholder: {}", c.name)),
+            }
+            *restored += 1;
+        }
+    }
+    for j in &mut s.join {
+        restore_join(&mut j.right, extracted, restored)?;
+    }
+    if let Some(ref mut ce) = s.where_clause {
+        restore_condition(ce, extracted, restored)?;
+    }
+    for (i, name, sq) in subqueries {
+        let (column, join) = scalar_subquery_join(s, name, sq.clone())?;
+        s.fields[i] = FieldDefinitionExpression::Col(column);
+        s.join.push(join);
+    }
+    Ok(())
+}
+
+fn restore_join(
+    right: &mut JoinRightSide,
+    extracted: &[Extracted],
+    restored: &mut usize,
+) -> Result<(), String> {
+    match *right {
+        JoinRightSide::NestedSelect(ref mut s, _) => restore_select(s, extracted, restored),
+        JoinRightSide::NestedJoin(ref mut j) => restore_join(&mut j.right, extracted, restored),
+        JoinRightSide::Table(_) | JoinRightSide::Tables(_) => Ok(()),
+    }
+}
+
+fn restore_condition(
+    ce: &mut ConditionExpression,
+    extracted: &[Extracted],
+    restored: &mut usize,
+) -> Result<(), String> {
+    match *ce {
+        ConditionExpression::ComparisonOp(ref mut tree)
+        | ConditionExpression::LogicalOp(ref mut tree) => {
+            restore_condition(&mut tree.left, extracted, restored)?;
+            restore_condition(&mut tree.right, extracted, restored)
+        }
+        ConditionExpression::NegationOp(ref mut inner)
+        | ConditionExpression::Bracketed(ref mut inner) => {
+            restore_condition(inner, extracted, restored)
+        }
+        ConditionExpression::Base(ConditionBase::NestedSelect(ref mut s)) => {
+            restore_select(s, extracted, restored)
+        }
+        ConditionExpression::Base(_) | ConditionExpression::Arithmetic(_) => Ok(()),
+    }
+}
+
+/// Parses the expression in `sql`.
+pub(crate) fn parse(sql: &str) -> Result<Expression, String> {
+    parse_tokens(&tokenize(sql)?)
+}
+
+/// Parses the expression in `tokens`, which must not contain anything else.
+pub(crate) fn parse_tokens(tokens: &[Token]) -> Result<Expression, String> {
+    let mut parser = Parser {
+        tokens: tokens
+            .iter()
+            .cloned()
+            .filter(Token::is_significant)
+            .collect(),
+        pos: 0,
+    };
+    let e = parser.expression()?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(e),
+        Some(t) => Err(format!("unexpected {} in expression", t)),
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token<'a>, String> {
+        let t = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| "unexpected end of expression".to_owned())?;
+        self.pos += 1;
+        Ok(t)
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.peek().map_or(false, |t| t.is_keyword(keyword));
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        let found = self.peek().map_or(false, |t| t.is_symbol(symbol));
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), String> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.expected(keyword))
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), String> {
+        if self.eat_symbol(symbol) {
+            Ok(())
+        } else {
+            Err(self.expected(symbol))
+        }
+    }
+
+    fn expected(&self, what: &str) -> String {
+        match self.peek() {
+            Some(t) => format!("expected {} but found {} in expression", what, t),
+            None => format!("expected {} at end of expression", what),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expression, String> {
+        let mut e = self.term()?;
+        loop {
+            let op = if self.eat_symbol("+") {
+                ArithmeticOperator::Add
+            } else if self.eat_symbol("-") {
+                ArithmeticOperator::Subtract
+            } else {
+                return Ok(e);
+            };
+            e = Expression::Arithmetic(op, Box::new(e), Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Expression, String> {
+        let mut e = self.primary()?;
+        loop {
+            let op = if self.eat_symbol("*") {
+                ArithmeticOperator::Multiply
+            } else if self.eat_symbol("/") {
+                ArithmeticOperator::Divide
+            } else {
+                return Ok(e);
+            };
+            e = Expression::Arithmetic(op, Box::new(e), Box::new(self.primary()?));
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expression, String> {
+        let t = self.next()?;
+        match t.kind {
+            TokenKind::Symbol if t.text == "(" => {
+                let e = self.expression()?;
+                self.expect_symbol(")")?;
+                Ok(e)
+            }
+            TokenKind::Symbol if t.text == "-" => match self.next()? {
+                ref n if n.kind == TokenKind::Number => number(n.text, true),
+                n => Err(format!("unexpected {} after - in expression", n)),
+            },
+            TokenKind::Number => number(t.text, false),
+            TokenKind::String => Ok(Expression::Literal(Literal::String(
+                t.string_value().unwrap_or_default(),
+            ))),
+            TokenKind::Word if t.is_keyword("null") => Ok(Expression::Literal(Literal::Null)),
+            TokenKind::Word if t.is_keyword("case") => self.case(),
+            TokenKind::Word if self.eat_symbol("(") => self.function(t.text),
+            TokenKind::Word | TokenKind::QuotedIdentifier => self.column(t),
+            _ => Err(format!("unexpected {} in expression", t)),
+        }
+    }
+
+    fn column(&mut self, first: Token<'a>) -> Result<Expression, String> {
+        let identifier = |t: &Token| match t.kind {
+            TokenKind::Word if KEYWORDS.iter().any(|kw| t.is_keyword(kw)) => {
+                Err(format!("unexpected {} in expression", t))
+            }
+            _ => t
+                .identifier()
+                .map(String::from)
+                .ok_or_else(|| format!("expected a column name but found {}", t)),
+        };
+        let first = identifier(&first)?;
+        let (table, name) = if self.eat_symbol(".") {
+            (Some(first), identifier(&self.next()?)?)
+        } else {
+            (None, first)
+        };
+        Ok(Expression::Column(Column {
+            name,
+            alias: None,
+            table,
+            function: None,
+        }))
+    }
+
+    /// Parses the arguments of a call to `name`, whose opening parenthesis has been consumed.
+    fn function(&mut self, name: &str) -> Result<Expression, String> {
+        let lower = name.to_ascii_lowercase();
+        let e = match &*lower {
+            "extract" => {
+                let part = self.next()?;
+                let part = match &*part.text.to_ascii_lowercase() {
+                    "year" => DatePart::Year,
+                    "month" => DatePart::Month,
+                    "day" => DatePart::Day,
+                    "hour" => DatePart::Hour,
+                    "minute" => DatePart::Minute,
+                    "second" => DatePart::Second,
+                    _ => return Err(format!("unsupported date part in EXTRACT: {}", part)),
+                };
+                self.expect_keyword("from")?;
+                Expression::Extract(part, Box::new(self.expression()?))
+            }
+            "lower" => Expression::Lower(Box::new(self.expression()?)),
+            "upper" => Expression::Upper(Box::new(self.expression()?)),
+            "coalesce" | "ifnull" | "concat" => {
+                let mut args = vec![self.expression()?];
+                while self.eat_symbol(",") {
+                    args.push(self.expression()?);
+                }
+                match &*lower {
+                    "concat" => Expression::Concat(args),
+                    "ifnull" if args.len() != 2 => {
+                        return Err("IFNULL takes exactly two arguments".to_owned());
+                    }
+                    _ => Expression::Coalesce(args),
+                }
+            }
+            _ => return Err(format!("unsupported function in expression: {}", name)),
+        };
+        self.expect_symbol(")")?;
+        Ok(e)
+    }
+
+    fn case(&mut self) -> Result<Expression, String> {
+        // `CASE x WHEN v THEN ..` compares `x` with each `v` in turn
+        let operand = if self.peek().map_or(false, |t| t.is_keyword("when")) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        let mut branches = Vec::new();
+        while self.eat_keyword("when") {
+            let condition = match operand {
+                Some(ref x) => {
+                    Condition::Comparison(Operator::Equal, x.clone(), self.expression()?)
+                }
+                None => self.condition()?,
+            };
+            self.expect_keyword("then")?;
+            branches.push((condition, self.expression()?));
+        }
+        if branches.is_empty() {
+            return Err(self.expected("WHEN"));
+        }
+        let otherwise = if self.eat_keyword("else") {
+            Some(Box::new(self.expression()?))
+        } else {
+            None
+        };
+        self.expect_keyword("end")?;
+        Ok(Expression::Case(branches, otherwise))
+    }
+
+    fn condition(&mut self) -> Result<Condition, String> {
+        let mut cs = vec![self.conjunction()?];
+        while self.eat_keyword("or") {
+            cs.push(self.conjunction()?);
+        }
+        Ok(if cs.len() == 1 {
+            cs.pop().unwrap()
+        } else {
+            Condition::Or(cs)
+        })
+    }
+
+    fn conjunction(&mut self) -> Result<Condition, String> {
+        let mut cs = vec![self.comparison()?];
+        while self.eat_keyword("and") {
+            cs.push(self.comparison()?);
+        }
+        Ok(if cs.len() == 1 {
+            cs.pop().unwrap()
+        } else {
+            Condition::And(cs)
+        })
+    }
+
+    fn comparison(&mut self) -> Result<Condition, String> {
+        // a parenthesis either encloses a condition or starts an operand, as in `(a + 1) > b`
+        if self.peek().map_or(false, |t| t.is_symbol("(")) {
+            let start = self.pos;
+            self.pos += 1;
+            if let Ok(c) = self.condition() {
+                if self.eat_symbol(")") {
+                    return Ok(c);
+                }
+            }
+            self.pos = start;
+        }
+
+        let left = self.expression()?;
+        if self.eat_keyword("is") {
+            let op = if self.eat_keyword("not") {
+                Operator::NotEqual
+            } else {
+                Operator::Equal
+            };
+            self.expect_keyword("null")?;
+            return Ok(Condition::Comparison(
+                op,
+                left,
+                Expression::Literal(Literal::Null),
+            ));
+        }
+        let op = match self.next()? {
+            ref t if t.kind == TokenKind::Symbol => match t.text {
+                "=" => Operator::Equal,
+                "!=" | "<>" => Operator::NotEqual,
+                "<" => Operator::Less,
+                "<=" => Operator::LessOrEqual,
+                ">" => Operator::Greater,
+                ">=" => Operator::GreaterOrEqual,
+                _ => return Err(format!("unsupported comparison in CASE: {}", t)),
+            },
+            t => return Err(format!("unsupported comparison in CASE: {}", t)),
+        };
+        Ok(Condition::Comparison(op, left, self.expression()?))
+    }
+}
+
+/// Parses a numeric literal. Numbers with a decimal point are kept as exact decimals.
+fn number(text: &str, negative: bool) -> Result<Expression, String> {
+    let out_of_range = || format!("numeric literal out of range: {}", text);
+    if text.contains('.') {
+        let d: Decimal = text.parse().map_err(|_| out_of_range())?;
+        Ok(Expression::Decimal(if negative {
+            Decimal::new(-d.mantissa(), d.scale())
+        } else {
+            d
+        }))
+    } else {
+        let i: i64 = text.parse().map_err(|_| out_of_range())?;
+        Ok(Expression::Literal(Literal::Integer(if negative {
+            -i
+        } else {
+            i
+        })))
+    }
+}
+
+fn quote(identifier: &str) -> String {
+    if is_identifier(identifier)
+        && !KEYWORDS
+            .iter()
+            .any(|kw| identifier.eq_ignore_ascii_case(kw))
+    {
+        identifier.to_owned()
+    } else {
+        format!("`{}`", identifier)
+    }
+}
+
+fn list(es: &[Expression]) -> String {
+    es.iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expression::Column(ref c) => {
+                if let Some(ref table) = c.table {
+                    write!(f, "{}.", quote(table))?;
+                }
+                write!(f, "{}", quote(&c.name))
+            }
+            Expression::Decimal(ref d) => write!(f, "{}", d),
+            Expression::Literal(Literal::String(ref s)) => {
+                write!(f, "'{}'", s.replace('\\', "\\\\").replace('\'', "''"))
+            }
+            Expression::Literal(ref l) => write!(f, "{}", l.to_string()),
+            Expression::Arithmetic(ref op, ref left, ref right) => {
+                let op = match *op {
+                    ArithmeticOperator::Add => "+",
+                    ArithmeticOperator::Subtract => "-",
+                    ArithmeticOperator::Multiply => "*",
+                    ArithmeticOperator::Divide => "/",
+                };
+                write!(f, "({} {} {})", left, op, right)
+            }
+            Expression::Case(ref branches, ref otherwise) => {
+                write!(f, "CASE")?;
+                for &(ref condition, ref e) in branches {
+                    write!(f, " WHEN {} THEN {}", condition, e)?;
+                }
+                if let Some(ref e) = *otherwise {
+                    write!(f, " ELSE {}", e)?;
+                }
+                write!(f, " END")
+            }
+            Expression::Coalesce(ref es) => write!(f, "COALESCE({})", list(es)),
+            Expression::Lower(ref e) => write!(f, "LOWER({})", e),
+            Expression::Upper(ref e) => write!(f, "UPPER({})", e),
+            Expression::Concat(ref es) => write!(f, "CONCAT({})", list(es)),
+            Expression::Extract(part, ref e) => write!(f, "EXTRACT({} FROM {})", part, e),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let nested = |cs: &[Condition], sep: &str| {
+            cs.iter()
+                .map(|c| format!("({})", c))
+                .collect::<Vec<_>>()
+                .join(sep)
+        };
+        match *self {
+            Condition::Comparison(ref op, ref left, Expression::Literal(Literal::Null))
+                if *op == Operator::Equal || *op == Operator::NotEqual =>
+            {
+                let not = if *op == Operator::NotEqual {
+                    "NOT "
+                } else {
+                    ""
+                };
+                write!(f, "{} IS {}NULL", left, not)
+            }
+            Condition::Comparison(ref op, ref left, ref right) => {
+                write!(f, "{} {} {}", left, op, right)
+            }
+            Condition::And(ref cs) => write!(f, "{}", nested(cs, " AND ")),
+            Condition::Or(ref cs) => write!(f, "{}", nested(cs, " OR ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(table: &str, name: &str) -> Expression {
+        Expression::Column(Column {
+            name: name.to_owned(),
+            alias: None,
+            table: Some(table.to_owned()),
+            function: None,
+        })
+    }
+
+    #[test]
+    fn it_parses_expressions() {
+        assert_eq!(
+            parse("COALESCE(t.a, LOWER(t.b), 'none')").unwrap(),
+            Expression::Coalesce(vec![
+                column("t", "a"),
+                Expression::Lower(Box::new(column("t", "b"))),
+                Expression::Literal(Literal::String("none".to_owned())),
+            ])
+        );
+        assert_eq!(
+            parse("case when t.a > 1 and t.b is null then t.a * 2 end").unwrap(),
+            Expression::Case(
+                vec![(
+                    Condition::And(vec![
+                        Condition::Comparison(
+                            Operator::Greater,
+                            column("t", "a"),
+                            Expression::Literal(Literal::Integer(1)),
+                        ),
+                        Condition::Comparison(
+                            Operator::Equal,
+                            column("t", "b"),
+                            Expression::Literal(Literal::Null),
+                        ),
+                    ]),
+                    Expression::Arithmetic(
+                        ArithmeticOperator::Multiply,
+                        Box::new(column("t", "a")),
+                        Box::new(Expression::Literal(Literal::Integer(2))),
+                    ),
+                )],
+                None,
+            )
+        );
+        assert_eq!(
+            parse("t.a * -0.05").unwrap(),
+            Expression::Arithmetic(
+                ArithmeticOperator::Multiply,
+                Box::new(column("t", "a")),
+                Box::new(Expression::Decimal(Decimal::new(-5, 2))),
+            )
+        );
+        assert!(parse("LOWER(t.a").is_err());
+        assert!(parse("SUBSTR(t.a, 1)").is_err());
+        assert!(parse("CASE END").is_err());
+    }
+
+    #[test]
+    fn it_leaves_nested_expressions_to_nom_sql() {
+        let extended = |sql| is_extended(&tokenize(sql).unwrap());
+        assert!(extended("CASE WHEN a THEN 1 END AS x"));
+        assert!(extended("(LOWER(a))"));
+        assert!(!extended("SUM(CASE WHEN a = 1 THEN b ELSE 0 END) AS s"));
+        assert!(!extended("lower"));
+    }
+
+    #[test]
+    fn it_round_trips_through_columns() {
+        for sql in &[
+            "CASE t.kind WHEN 'it''s' THEN CONCAT(t.a, '\\\\') ELSE -1.5 END",
+            "EXTRACT(YEAR FROM `t`.`end`) + (t.a - 2) / 3",
+            "CASE WHEN ((t.a < 1) OR (t.b IS NOT NULL)) THEN IFNULL(t.c, 0) END",
+        ] {
+            let e = parse(sql).unwrap();
+            let c = e.clone().into_column("x".to_owned());
+            assert!(!is_identifier(&c.name));
+            assert_eq!(Expression::from_column(&c), Some(e));
+        }
+    }
+
+    #[test]
+    fn it_expands_and_restores_select_list_expressions() {
+        use nom_sql::parser::parse_query;
+
+        let (expanded, extracted) = expand_expressions(
+            "SELECT id, CASE WHEN a > 1 THEN 'big' ELSE 'small' END AS size, LOWER(name) \
+             FROM t WHERE x IN (SELECT COALESCE(y, 0) AS y FROM u);",
+        )
+        .unwrap();
+        assert_eq!(
+            expanded,
+            "SELECT id, __noria_expr_0, __noria_expr_1 \
+             FROM t WHERE x IN (SELECT __noria_expr_2 FROM u);"
+        );
+        let columns: Vec<_> = extracted
+            .iter()
+            .map(|e| match *e {
+                Extracted::Expression(ref c) => c.clone(),
+                Extracted::Subquery(..) => unreachable!(),
+            })
+            .collect();
+        let aliases: Vec<_> = columns.iter().map(|c| c.alias.clone().unwrap()).collect();
+        assert_eq!(aliases, vec!["size", "LOWER(name)", "y"]);
+
+        let mut q = parse_query(&expanded).unwrap();
+        assert_eq!(restore_expressions(&mut q, &extracted), Ok(3));
+        match q {
+            SqlQuery::Select(ref s) => {
+                assert_eq!(
+                    s.fields[1],
+                    FieldDefinitionExpression::Col(columns[0].clone())
+                )
+            }
+            _ => unreachable!(),
+        }
+
+        assert!(expand_expressions("SELECT LOWER(a FROM t").is_err());
+        assert!(expand_expressions("SELECT UPPER(__noria_expr_0) FROM t").is_err());
+    }
+
+    #[test]
+    fn it_restores_scalar_subqueries_as_left_joins() {
+        use nom_sql::parser::parse_query;
+
+        let (expanded, extracted) = expand_expressions(
+            "SELECT u.id, (SELECT COUNT(*) FROM posts WHERE posts.author = u.id) AS n, \
+             (SELECT MAX(t.x) FROM t WHERE t.y = u.y) \
+             FROM users u WHERE u.id = ?;",
+        )
+        .unwrap();
+        assert_eq!(
+            expanded,
+            "SELECT u.id, __noria_expr_0, __noria_expr_1 FROM users u WHERE u.id = ?;"
+        );
+        let mut q = parse_query(&expanded).unwrap();
+        assert_eq!(restore_expressions(&mut q, &extracted), Ok(2));
+        let expected = parse_query(
+            "SELECT u.id, __noria_scalar_n.n AS n, \
+             __noria_scalar_scalar0.scalar0 AS scalar0 FROM users u \
+             LEFT JOIN (SELECT posts.author, count(*) AS n FROM posts GROUP BY posts.author) \
+             AS __noria_scalar_n ON (u.id = __noria_scalar_n.author) \
+             LEFT JOIN (SELECT t.y, max(t.x) AS scalar0 FROM t GROUP BY t.y) \
+             AS __noria_scalar_scalar0 ON (u.y = __noria_scalar_scalar0.y) \
+             WHERE u.id = ?;",
+        )
+        .unwrap();
+        assert_eq!(q, expected);
+
+        // expressions in a scalar subquery are restored along with it
+        let (expanded, extracted) =
+            expand_expressions("SELECT (SELECT UPPER(t.x) FROM t WHERE t.y = u.y) AS x FROM u")
+                .unwrap();
+        assert_eq!(extracted.len(), 1);
+        let mut q = parse_query(&expanded).unwrap();
+        assert_eq!(restore_expressions(&mut q, &extracted), Ok(1));
+        match q {
+            SqlQuery::Select(ref s) => match s.join[0].right {
+                JoinRightSide::NestedSelect(ref sq, _) => assert_eq!(
+                    sq.fields[1],
+                    FieldDefinitionExpression::Col(
+                        parse("UPPER(t.x)").unwrap().into_column("x".to_owned())
+                    )
+                ),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+
+        // the subquery must be correlated with the outer query
+        let (expanded, extracted) =
+            expand_expressions("SELECT (SELECT MAX(x) FROM t) AS m FROM u").unwrap();
+        let mut q = parse_query(&expanded).unwrap();
+        assert!(restore_expressions(&mut q, &extracted).is_err());
+    }
+
+    #[test]
+    fn it_rewrites_columns() {
+        let mut c = parse("UPPER(a)")
+            .unwrap()
+            .into_column("UPPER(a)".to_owned());
+        assert!(rewrite_columns(&mut c, |c| c.table = Some("t".to_owned())));
+        assert_eq!(c.name, "UPPER(t.a)");
+        assert!(!rewrite_columns(&mut Column::from("t.a"), |_| ()));
+    }
+}
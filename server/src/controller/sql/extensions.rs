@@ -0,0 +1,273 @@
+use super::{disguised_aggregate, PAGE_PARAMETER};
+use dataflow::ops::grouped::aggregate::Aggregation;
+use nom_sql::{
+    Column, ConditionBase, ConditionExpression, ConditionTree, FieldDefinitionExpression,
+    FunctionArguments, FunctionExpression, JoinOperator, JoinRightSide, Literal, Operator,
+    SelectSpecification, SelectStatement, SqlQuery,
+};
+
+/// What the SQL front end took out of a query because nom-sql's AST has no place for it.
+///
+/// `expand_unparseable` stands in for these with placeholders that nom-sql can parse, and
+/// `take_extensions` takes the placeholders out of the parsed query again. The planner reads the
+/// extensions next to the query rather than from it, so a query that was parsed without the front
+/// end (such as a security policy) is always planned as written, even if it happens to look like
+/// one of the placeholders.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Extensions {
+    /// Those of each SELECT in the query, in order. A compound SELECT has one for each of its
+    /// SELECTs, and queries that are not SELECTs have none.
+    selects: Vec<SelectExtensions>,
+}
+
+impl Extensions {
+    /// Returns the extensions of the `i`th SELECT in the query.
+    pub(crate) fn select(&self, i: usize) -> SelectExtensions {
+        self.selects.get(i).cloned().unwrap_or_default()
+    }
+
+    /// Returns the extensions of the `i`th SELECT in the query for changing.
+    pub(crate) fn select_mut(&mut self, i: usize) -> &mut SelectExtensions {
+        if self.selects.len() <= i {
+            self.selects.resize(i + 1, SelectExtensions::default());
+        }
+        &mut self.selects[i]
+    }
+}
+
+/// What the SQL front end took out of a single SELECT.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SelectExtensions {
+    /// Whether the query has an `OFFSET ?` clause, which makes it serve one page of `LIMIT`
+    /// results at a time.
+    pub(crate) paginated: bool,
+    /// The aggregation that each `GROUP_CONCAT` column, by name, computes instead, if
+    /// `expand_aggregates` disguised a call to another aggregate function as that column.
+    pub(crate) aggregates: Vec<(String, Aggregation)>,
+    /// The kind of each outer join that nom-sql cannot parse, by the position of its clause among
+    /// the query's joins (see `expand_joins`).
+    outer_joins: Vec<(usize, OuterJoin)>,
+    /// Those of the SELECTs nested in this one that have any, by the nested statement.
+    subqueries: Vec<(SelectStatement, SelectExtensions)>,
+}
+
+/// An outer join that nom-sql cannot parse.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OuterJoin {
+    /// `RIGHT [OUTER] JOIN`.
+    Right,
+    /// `FULL [OUTER] JOIN`.
+    Full,
+}
+
+impl SelectExtensions {
+    /// Returns the kind of outer join that the `i`th join clause of the query is, if it is one that
+    /// nom-sql cannot parse.
+    pub(crate) fn outer_join(&self, i: usize) -> Option<OuterJoin> {
+        self.outer_joins
+            .iter()
+            .find(|&&(j, _)| j == i)
+            .map(|&(_, kind)| kind)
+    }
+
+    /// Returns the extensions of `subquery`, a SELECT nested in this one, as those of a query.
+    pub(crate) fn subquery(&self, subquery: &SelectStatement) -> Extensions {
+        Extensions {
+            selects: self
+                .subqueries
+                .iter()
+                .find(|&&(ref sq, _)| sq == subquery)
+                .map(|&(_, ref ext)| ext.clone())
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// Notes that `subquery`, a SELECT nested in this one, has been rewritten into `rewritten`,
+    /// which keeps its extensions.
+    pub(crate) fn rewrite_subquery(
+        &mut self,
+        subquery: &SelectStatement,
+        rewritten: &SelectStatement,
+    ) {
+        for &mut (ref mut sq, _) in &mut self.subqueries {
+            if sq == subquery {
+                *sq = rewritten.clone();
+            }
+        }
+    }
+}
+
+/// Takes the placeholders that `expand_unparseable` left in `query`, which was parsed from its
+/// output, out of the query, and returns what they stand for.
+pub(crate) fn take_extensions(query: &mut SqlQuery) -> Result<Extensions, String> {
+    let selects = match *query {
+        SqlQuery::Select(ref mut s) => vec![take_select(s)?],
+        SqlQuery::CompoundSelect(ref mut cs) => cs
+            .selects
+            .iter_mut()
+            .map(|&mut (_, ref mut s)| take_select(s))
+            .collect::<Result<_, _>>()?,
+        SqlQuery::CreateView(ref mut v) => match *v.definition {
+            SelectSpecification::Simple(ref mut s) => vec![take_select(s)?],
+            SelectSpecification::Compound(ref mut cs) => cs
+                .selects
+                .iter_mut()
+                .map(|&mut (_, ref mut s)| take_select(s))
+                .collect::<Result<_, _>>()?,
+        },
+        _ => Vec::new(),
+    };
+    Ok(Extensions { selects })
+}
+
+fn take_select(s: &mut SelectStatement) -> Result<SelectExtensions, String> {
+    let mut ext = SelectExtensions::default();
+    if let Some(ce) = s.where_clause.take() {
+        let (rest, paginated) = take_page_parameter(ce);
+        s.where_clause = rest;
+        ext.paginated = paginated;
+    }
+    for field in &mut s.fields {
+        if let FieldDefinitionExpression::Col(ref mut c) = *field {
+            if let Some(agg) = take_aggregate(c) {
+                ext.aggregates.push((c.name.clone(), agg));
+            }
+        }
+    }
+
+    for (i, j) in s.join.iter_mut().enumerate() {
+        // `expand_joins` leaves no inner or cross joins other than those standing in for outer
+        // joins
+        let kind = match j.operator {
+            JoinOperator::InnerJoin => Some(OuterJoin::Right),
+            JoinOperator::CrossJoin => Some(OuterJoin::Full),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            ext.outer_joins.push((i, kind));
+            j.operator = JoinOperator::Join;
+        }
+        take_join(&mut j.right, &mut ext.subqueries)?;
+    }
+    if let Some(ref mut ce) = s.where_clause {
+        take_condition(ce, &mut ext.subqueries)?;
+    }
+    Ok(ext)
+}
+
+/// Takes the placeholders out of `s`, a SELECT nested in another, and adds its extensions to
+/// `subqueries` if it has any.
+fn take_nested(
+    s: &mut SelectStatement,
+    subqueries: &mut Vec<(SelectStatement, SelectExtensions)>,
+) -> Result<(), String> {
+    let ext = take_select(s)?;
+    if ext.paginated {
+        return Err("OFFSET ? can only page through the results of the outermost query".to_owned());
+    }
+    if ext != SelectExtensions::default() {
+        subqueries.push((s.clone(), ext));
+    }
+    Ok(())
+}
+
+fn take_join(
+    right: &mut JoinRightSide,
+    subqueries: &mut Vec<(SelectStatement, SelectExtensions)>,
+) -> Result<(), String> {
+    match *right {
+        JoinRightSide::NestedSelect(ref mut s, _) => take_nested(s, subqueries),
+        JoinRightSide::NestedJoin(ref mut j) => take_join(&mut j.right, subqueries),
+        JoinRightSide::Table(_) | JoinRightSide::Tables(_) => Ok(()),
+    }
+}
+
+fn take_condition(
+    ce: &mut ConditionExpression,
+    subqueries: &mut Vec<(SelectStatement, SelectExtensions)>,
+) -> Result<(), String> {
+    match *ce {
+        ConditionExpression::ComparisonOp(ref mut tree)
+        | ConditionExpression::LogicalOp(ref mut tree) => {
+            take_condition(&mut tree.left, subqueries)?;
+            take_condition(&mut tree.right, subqueries)
+        }
+        ConditionExpression::NegationOp(ref mut inner)
+        | ConditionExpression::Bracketed(ref mut inner) => take_condition(inner, subqueries),
+        ConditionExpression::Base(ConditionBase::NestedSelect(ref mut s)) => {
+            take_nested(s, subqueries)
+        }
+        ConditionExpression::Base(_) | ConditionExpression::Arithmetic(_) => Ok(()),
+    }
+}
+
+/// Turns `c` back into a plain `GROUP_CONCAT` if `expand_aggregates` disguised a call to another
+/// aggregate function as it, and returns the aggregation that the function computes. Unless the
+/// column has an alias, it is named after the function, like MySQL would.
+fn take_aggregate(c: &mut Column) -> Option<Aggregation> {
+    let (function, agg) = match c.function {
+        Some(ref mut f) => match **f {
+            FunctionExpression::GroupConcat(ref args, ref mut separator) => {
+                let (function, agg) = disguised_aggregate(separator)?;
+                // the default separator, which nothing reads
+                *separator = ",".to_owned();
+                (format!("{}({})", function, args), agg)
+            }
+            _ => return None,
+        },
+        None => return None,
+    };
+    if c.alias.is_none() {
+        c.name = function;
+    }
+    Some(agg)
+}
+
+/// Removes the `PAGE_PARAMETER = ?` comparison that `expand_page_parameters` conjoined with a
+/// query's `WHERE` clause, returning the remaining condition and whether the comparison was found.
+fn take_page_parameter(ce: ConditionExpression) -> (Option<ConditionExpression>, bool) {
+    match ce {
+        ConditionExpression::ComparisonOp(ref ct) if ct.operator == Operator::Equal => {
+            let is_page = match (&*ct.left, &*ct.right) {
+                (
+                    ConditionExpression::Base(ConditionBase::Field(ref c)),
+                    ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)),
+                ) => c.table.is_none() && c.function.is_none() && c.name == PAGE_PARAMETER,
+                _ => false,
+            };
+            if is_page {
+                (None, true)
+            } else {
+                (Some(ce.clone()), false)
+            }
+        }
+        ConditionExpression::LogicalOp(ConditionTree {
+            operator: Operator::And,
+            left,
+            right,
+        }) => {
+            let (left, left_page) = take_page_parameter(*left);
+            let (right, right_page) = take_page_parameter(*right);
+            let found = left_page || right_page;
+            let rest = match (left, right) {
+                (Some(left), Some(right)) => Some(ConditionExpression::LogicalOp(ConditionTree {
+                    operator: Operator::And,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                })),
+                (rest, None) | (None, rest) => rest,
+            };
+            if !found {
+                return (rest, false);
+            }
+            // the rest of the condition was bracketed when the comparison was added
+            let rest = rest.map(|ce| match ce {
+                ConditionExpression::Bracketed(inner) => *inner,
+                ce => ce,
+            });
+            (rest, true)
+        }
+        ce => (Some(ce), false),
+    }
+}
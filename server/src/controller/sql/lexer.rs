@@ -0,0 +1,247 @@
+use std::fmt;
+
+/// Prefix of the identifiers that the SQL front end generates while rewriting queries that
+/// nom-sql cannot parse. Queries may not use identifiers with this prefix themselves.
+pub(crate) const RESERVED_PREFIX: &str = "__noria_";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TokenKind {
+    /// A keyword or an unquoted identifier.
+    Word,
+    /// An identifier quoted with backticks or square brackets.
+    QuotedIdentifier,
+    /// A string literal in single or double quotes.
+    String,
+    /// An unsigned integer or decimal number.
+    Number,
+    /// An operator or a punctuation character, e.g. `(`, `,`, `?` or `<=`.
+    Symbol,
+    /// Whitespace or a comment.
+    Space,
+}
+
+/// A token of SQL text, which borrows its text from the SQL that it was read from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Token<'a> {
+    pub(crate) kind: TokenKind,
+    pub(crate) text: &'a str,
+}
+
+impl<'a> Token<'a> {
+    /// Returns true if this token is the given keyword (in any case).
+    pub(crate) fn is_keyword(&self, keyword: &str) -> bool {
+        self.kind == TokenKind::Word && self.text.eq_ignore_ascii_case(keyword)
+    }
+
+    /// Returns true if this token is the given operator or punctuation.
+    pub(crate) fn is_symbol(&self, symbol: &str) -> bool {
+        self.kind == TokenKind::Symbol && self.text == symbol
+    }
+
+    /// Returns the identifier that this token names, without any quotes, if it is a word or a
+    /// quoted identifier.
+    pub(crate) fn identifier(&self) -> Option<&'a str> {
+        match self.kind {
+            TokenKind::Word => Some(self.text),
+            TokenKind::QuotedIdentifier => Some(&self.text[1..self.text.len() - 1]),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of this token if it is a string literal, with escape sequences
+    /// interpreted the same way that nom-sql interprets them.
+    pub(crate) fn string_value(&self) -> Option<String> {
+        if self.kind != TokenKind::String {
+            return None;
+        }
+        let quote = self.text.chars().next()?;
+        let mut value = String::with_capacity(self.text.len());
+        let mut chars = self.text[1..self.text.len() - 1].chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('b') => value.push('\x7f'),
+                    Some('r') => value.push('\r'),
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('0') => value.push('\0'),
+                    Some('Z') => value.push('\x1a'),
+                    Some(c) => value.push(c),
+                    None => (),
+                },
+                c if c == quote => {
+                    // a doubled quote stands for the quote itself
+                    chars.next();
+                    value.push(c);
+                }
+                c => value.push(c),
+            }
+        }
+        Some(value)
+    }
+
+    /// Returns true if this token is not whitespace or a comment.
+    pub(crate) fn is_significant(&self) -> bool {
+        self.kind != TokenKind::Space
+    }
+}
+
+impl<'a> fmt::Display for Token<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '@'
+}
+
+/// Returns true if `s` can be used as an identifier without quoting it.
+pub(crate) fn is_identifier(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(is_identifier_char)
+}
+
+/// Splits `sql` into tokens. String literals, quoted identifiers and comments each become a
+/// single token, so that whatever they contain is never mistaken for a keyword. Concatenating the
+/// text of all the tokens gives back `sql`.
+pub(crate) fn tokenize(sql: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = sql;
+    while let Some(c) = rest.chars().next() {
+        let (kind, len) = match c {
+            c if c.is_whitespace() => (
+                TokenKind::Space,
+                rest.find(|c: char| !c.is_whitespace())
+                    .unwrap_or_else(|| rest.len()),
+            ),
+            '-' if rest.starts_with("--") => (
+                TokenKind::Space,
+                rest.find('\n').unwrap_or_else(|| rest.len()),
+            ),
+            '/' if rest.starts_with("/*") => match rest[2..].find("*/") {
+                Some(end) => (TokenKind::Space, end + 4),
+                None => return Err("unterminated comment".to_owned()),
+            },
+            '\'' | '"' => (TokenKind::String, quoted_len(rest, c, true)?),
+            '`' => (TokenKind::QuotedIdentifier, quoted_len(rest, '`', false)?),
+            '[' if rest[1..].starts_with(is_identifier_char) => {
+                (TokenKind::QuotedIdentifier, quoted_len(rest, ']', false)?)
+            }
+            c if c.is_ascii_digit() => {
+                let digits = |s: &str| {
+                    s.find(|c: char| !c.is_ascii_digit())
+                        .unwrap_or_else(|| s.len())
+                };
+                let mut len = digits(rest);
+                if rest[len..].starts_with('.')
+                    && rest[len + 1..].starts_with(|c: char| c.is_ascii_digit())
+                {
+                    len += 1 + digits(&rest[len + 1..]);
+                }
+                (TokenKind::Number, len)
+            }
+            c if is_identifier_char(c) => (
+                TokenKind::Word,
+                rest.find(|c: char| !is_identifier_char(c))
+                    .unwrap_or_else(|| rest.len()),
+            ),
+            _ => {
+                let len = ["<=", ">=", "<>", "!=", "||"]
+                    .iter()
+                    .find(|op| rest.starts_with(*op))
+                    .map_or(c.len_utf8(), |op| op.len());
+                (TokenKind::Symbol, len)
+            }
+        };
+        tokens.push(Token {
+            kind,
+            text: &rest[..len],
+        });
+        rest = &rest[len..];
+    }
+    Ok(tokens)
+}
+
+/// Returns the length of the quoted token at the start of `s`, which ends with `close`.
+fn quoted_len(s: &str, close: char, escapes: bool) -> Result<usize, String> {
+    let mut chars = s.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        if escapes && c == '\\' {
+            chars.next();
+        } else if c == close {
+            // a doubled quote stands for the quote itself
+            if escapes && s[i + 1..].starts_with(close) {
+                chars.next();
+            } else {
+                return Ok(i + 1);
+            }
+        }
+    }
+    Err(format!("unterminated quoted text: {}", s))
+}
+
+/// Returns an error if any identifier in `tokens` starts with `RESERVED_PREFIX`.
+pub(crate) fn check_reserved(tokens: &[Token]) -> Result<(), String> {
+    match tokens.iter().filter_map(Token::identifier).find(|id| {
+        id.len() >= RESERVED_PREFIX.len()
+            && id[..RESERVED_PREFIX.len()].eq_ignore_ascii_case(RESERVED_PREFIX)
+    }) {
+        Some(id) => Err(format!(
+            "identifiers starting with {} are reserved: {}",
+            RESERVED_PREFIX, id
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Concatenates the text of `tokens`.
+pub(crate) fn join(tokens: &[Token]) -> String {
+    tokens.iter().map(|t| t.text).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(sql: &str) -> Vec<(TokenKind, &str)> {
+        tokenize(sql)
+            .unwrap()
+            .into_iter()
+            .filter(Token::is_significant)
+            .map(|t| (t.kind, t.text))
+            .collect()
+    }
+
+    #[test]
+    fn it_tokenizes_quoted_text() {
+        assert_eq!(
+            kinds("SELECT `from`, 'it''s (not) a \\'select' FROM t;"),
+            vec![
+                (TokenKind::Word, "SELECT"),
+                (TokenKind::QuotedIdentifier, "`from`"),
+                (TokenKind::Symbol, ","),
+                (TokenKind::String, "'it''s (not) a \\'select'"),
+                (TokenKind::Word, "FROM"),
+                (TokenKind::Word, "t"),
+                (TokenKind::Symbol, ";"),
+            ]
+        );
+        let sql = "SELECT a -- OFFSET ?\nFROM t WHERE b <= 1.5 /* ) */";
+        assert_eq!(join(&tokenize(sql).unwrap()), sql);
+        assert!(tokenize("SELECT 'a FROM t").is_err());
+    }
+
+    #[test]
+    fn it_reads_string_values() {
+        let tokens = tokenize(r#"'it''s' "a\"b\n""#).unwrap();
+        assert_eq!(tokens[0].string_value(), Some("it's".to_owned()));
+        assert_eq!(tokens[2].string_value(), Some("a\"b\n".to_owned()));
+    }
+
+    #[test]
+    fn it_rejects_reserved_identifiers() {
+        assert!(check_reserved(&tokenize("SELECT __noria_x FROM t").unwrap()).is_err());
+        assert!(check_reserved(&tokenize("SELECT `__NORIA_x` FROM t").unwrap()).is_err());
+        assert!(check_reserved(&tokenize("SELECT '__noria_x' FROM t").unwrap()).is_ok());
+    }
+}
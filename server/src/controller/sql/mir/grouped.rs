@@ -9,10 +9,15 @@ use nom_sql::{
 use std::collections::{HashMap, HashSet};
 use std::ops::Deref;
 
-fn target_columns_from_computed_column(computed_col: &nom_sql::Column) -> Column {
+/// Returns the column that `computed_col` aggregates over, which must belong to a table.
+fn target_columns_from_computed_column(computed_col: &nom_sql::Column) -> Result<Column, String> {
     use nom_sql::FunctionExpression::*;
 
-    match *computed_col.function.as_ref().unwrap().deref() {
+    let function = computed_col
+        .function
+        .as_ref()
+        .ok_or_else(|| format!("{} is not a computed column", computed_col.name))?;
+    let over = match *function.deref() {
         Avg(FunctionArguments::Column(ref col), _)
         | Count(FunctionArguments::Column(ref col), _)
         | Count(
@@ -35,10 +40,17 @@ fn target_columns_from_computed_column(computed_col: &nom_sql::Column) -> Column
         | Sum(FunctionArguments::Column(ref col), _) => Column::from(col),
         CountStar => {
             // see comment re COUNT(*) rewriting in make_aggregation_node
-            panic!("COUNT(*) should have been rewritten earlier!")
+            return Err("COUNT(*) should have been rewritten earlier".to_owned());
         }
-        _ => unreachable!(),
+        ref f => return Err(format!("unsupported aggregation {}", f)),
+    };
+    if over.table.is_none() {
+        return Err(format!(
+            "aggregated column {} does not belong to a table",
+            over.name
+        ));
     }
+    Ok(over)
 }
 
 // Move predicates above grouped_by nodes
@@ -50,7 +62,7 @@ pub(super) fn make_predicates_above_grouped<'a>(
     node_count: usize,
     column_to_predicates: &HashMap<Column, Vec<&'a ConditionExpression>>,
     prev_node: &mut Option<MirNodeRef>,
-) -> (Vec<&'a ConditionExpression>, Vec<MirNodeRef>) {
+) -> Result<(Vec<&'a ConditionExpression>, Vec<MirNodeRef>), String> {
     let mut created_predicates = Vec::new();
     let mut predicates_above_group_by_nodes = Vec::new();
     let mut node_count = node_count;
@@ -62,13 +74,16 @@ pub(super) fn make_predicates_above_grouped<'a>(
                 // whenever we have a column getting aggregated (i.e. an over column
                 // rather than a group by column) we won't be able to filter on it
                 // later, so any filters involving it need to get moved above
-                let over_col = target_columns_from_computed_column(ccol);
+                let over_col = target_columns_from_computed_column(ccol)?;
                 let over_table = over_col.table.as_ref().unwrap().as_str();
 
                 if column_to_predicates.contains_key(&over_col) {
                     let parent = match *prev_node {
                         Some(ref p) => p.clone(),
-                        None => node_for_rel[over_table].clone(),
+                        None => node_for_rel
+                            .get(over_table)
+                            .cloned()
+                            .ok_or_else(|| format!("unknown table {}", over_table))?,
                     };
 
                     let new_mpns = mir_converter.predicates_above_group_by(
@@ -77,7 +92,7 @@ pub(super) fn make_predicates_above_grouped<'a>(
                         over_col,
                         parent,
                         &mut created_predicates,
-                    );
+                    )?;
 
                     node_count += predicates_above_group_by_nodes.len();
                     *prev_node = Some(new_mpns.last().unwrap().clone());
@@ -87,7 +102,7 @@ pub(super) fn make_predicates_above_grouped<'a>(
         }
     }
 
-    (created_predicates, predicates_above_group_by_nodes)
+    Ok((created_predicates, predicates_above_group_by_nodes))
 }
 
 pub(super) fn make_grouped(
@@ -98,7 +113,7 @@ pub(super) fn make_grouped(
     node_count: usize,
     prev_node: &mut Option<MirNodeRef>,
     is_reconcile: bool,
-) -> Vec<MirNodeRef> {
+) -> Result<Vec<MirNodeRef>, String> {
     let mut func_nodes: Vec<MirNodeRef> = Vec::new();
     let mut node_count = node_count;
 
@@ -109,7 +124,12 @@ pub(super) fn make_grouped(
                 .edges
                 .values()
                 .filter(|e| match **e {
-                    QueryGraphEdge::Join(_) | QueryGraphEdge::LeftJoin(_) => false,
+                    QueryGraphEdge::Join(_)
+                    | QueryGraphEdge::LeftJoin(_)
+                    | QueryGraphEdge::FullJoin(_)
+                    | QueryGraphEdge::SemiJoin(_)
+                    | QueryGraphEdge::AntiJoin(_)
+                    | QueryGraphEdge::NotIn(_) => false,
                     QueryGraphEdge::GroupBy(_) => true,
                 })
                 .collect();
@@ -148,7 +168,9 @@ pub(super) fn make_grouped(
                                 nom_sql::Column::from(colname.as_ref()),
                             ))
                         }
-                        _ => unimplemented!(),
+                        ref f => {
+                            return Err(format!("cannot reconcile {} across universes", f));
+                        }
                     };
 
                     nom_sql::Column {
@@ -162,13 +184,16 @@ pub(super) fn make_grouped(
                 };
 
                 // We must also push parameter columns through the group by
-                let over_col = target_columns_from_computed_column(&computed_col);
+                let over_col = target_columns_from_computed_column(&computed_col)?;
                 let over_table = over_col.table.as_ref().unwrap().as_str();
 
                 let parent_node = match *prev_node {
                     // If no explicit parent node is specified, we extract
                     // the base node from the "over" column's specification
-                    None => node_for_rel[over_table].clone(),
+                    None => node_for_rel
+                        .get(over_table)
+                        .cloned()
+                        .ok_or_else(|| format!("unknown table {}", over_table))?,
                     // We have an explicit parent node (likely a projection
                     // helper), so use that
                     Some(ref node) => node.clone(),
@@ -244,7 +269,7 @@ pub(super) fn make_grouped(
                         // output, we make one up a group column by adding an extra
                         // projection node
                         let proj_name = format!("{}_prj_hlpr", name);
-                        let fn_col = target_columns_from_computed_column(&computed_col);
+                        let fn_col = target_columns_from_computed_column(&computed_col)?;
 
                         let proj =
                             mir_converter.make_projection_helper(&proj_name, parent_node, &fn_col);
@@ -267,12 +292,18 @@ pub(super) fn make_grouped(
                     (parent_node, group_cols)
                 };
 
+                let aggregate = qg
+                    .aggregates
+                    .iter()
+                    .find(|&&(ref col, _)| *col == computed_col.name)
+                    .map(|&(_, ref agg)| agg.clone());
                 let nodes: Vec<MirNodeRef> = mir_converter.make_function_node(
                     name,
                     &Column::from(computed_col),
+                    aggregate,
                     group_cols.iter().collect(),
                     parent_node,
-                );
+                )?;
 
                 *prev_node = Some(nodes.last().unwrap().clone());
                 node_count += nodes.len();
@@ -281,5 +312,5 @@ pub(super) fn make_grouped(
         }
     }
 
-    func_nodes
+    Ok(func_nodes)
 }
@@ -1,10 +1,16 @@
 use crate::controller::sql::mir::SqlToMirConverter;
 use crate::controller::sql::query_graph::{JoinRef, QueryGraph, QueryGraphEdge};
 use dataflow::ops::join::JoinType;
+use dataflow::ops::semijoin::SemiJoinType;
 use mir::MirNodeRef;
 use nom_sql::ConditionTree;
 use std::collections::{HashMap, HashSet};
 
+enum JoinKind {
+    Join(JoinType),
+    SemiJoin(SemiJoinType),
+}
+
 struct JoinChain {
     tables: HashSet<String>,
     last_node: MirNodeRef,
@@ -36,7 +42,7 @@ pub(super) fn make_joins(
     qg: &QueryGraph,
     node_for_rel: &HashMap<&str, MirNodeRef>,
     node_count: usize,
-) -> Vec<MirNodeRef> {
+) -> Result<Vec<MirNodeRef>, String> {
     let mut join_nodes: Vec<MirNodeRef> = Vec::new();
     let mut join_chains = Vec::new();
     let mut node_count = node_count;
@@ -46,13 +52,36 @@ pub(super) fn make_joins(
         let (left_chain, right_chain) =
             pick_join_chains(&jref.src, &jref.dst, &mut join_chains, node_for_rel);
 
-        let jn = mir_converter.make_join_node(
-            &format!("{}_n{}", name, node_count),
-            jp,
-            left_chain.last_node.clone(),
-            right_chain.last_node.clone(),
-            join_type,
-        );
+        // full joins scan their right parent's state when they are first populated, so the right
+        // side must be materialized even if it is a filter or projection we could query through
+        let right_node = if let JoinKind::Join(JoinType::Full) = join_type {
+            let id = mir_converter.make_identity_node(
+                &format!("{}_n{}", name, node_count),
+                right_chain.last_node.clone(),
+            );
+            node_count += 1;
+            join_nodes.push(id.clone());
+            id
+        } else {
+            right_chain.last_node.clone()
+        };
+
+        let jn = match join_type {
+            JoinKind::Join(kind) => mir_converter.make_join_node(
+                &format!("{}_n{}", name, node_count),
+                jp,
+                left_chain.last_node.clone(),
+                right_node,
+                kind,
+            )?,
+            JoinKind::SemiJoin(kind) => mir_converter.make_semi_join_node(
+                &format!("{}_n{}", name, node_count),
+                jp,
+                left_chain.last_node.clone(),
+                right_node,
+                kind,
+            )?,
+        };
 
         // merge node chains
         let new_chain = left_chain.merge_chain(right_chain, jn.clone());
@@ -63,13 +92,23 @@ pub(super) fn make_joins(
         join_nodes.push(jn);
     }
 
-    join_nodes
+    Ok(join_nodes)
 }
 
-fn from_join_ref<'a>(jref: &JoinRef, qg: &'a QueryGraph) -> (JoinType, &'a ConditionTree) {
+fn from_join_ref<'a>(jref: &JoinRef, qg: &'a QueryGraph) -> (JoinKind, &'a ConditionTree) {
     match qg.edges[&(jref.src.clone(), jref.dst.clone())] {
-        QueryGraphEdge::Join(ref jps) => (JoinType::Inner, &jps[jref.index]),
-        QueryGraphEdge::LeftJoin(ref jps) => (JoinType::Left, &jps[jref.index]),
+        QueryGraphEdge::Join(ref jps) => (JoinKind::Join(JoinType::Inner), &jps[jref.index]),
+        QueryGraphEdge::LeftJoin(ref jps) => (JoinKind::Join(JoinType::Left), &jps[jref.index]),
+        QueryGraphEdge::FullJoin(ref jps) => (JoinKind::Join(JoinType::Full), &jps[jref.index]),
+        QueryGraphEdge::SemiJoin(ref jps) => {
+            (JoinKind::SemiJoin(SemiJoinType::Semi), &jps[jref.index])
+        }
+        QueryGraphEdge::AntiJoin(ref jps) => {
+            (JoinKind::SemiJoin(SemiJoinType::Anti), &jps[jref.index])
+        }
+        QueryGraphEdge::NotIn(ref jps) => {
+            (JoinKind::SemiJoin(SemiJoinType::NotIn), &jps[jref.index])
+        }
         QueryGraphEdge::GroupBy(_) => unreachable!(),
     }
 }
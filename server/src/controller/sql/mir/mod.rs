@@ -5,12 +5,17 @@ use noria::DataType;
 use petgraph::graph::NodeIndex;
 // TODO(malte): remove if possible
 use dataflow::ops::filter::FilterCondition;
+use dataflow::ops::grouped::aggregate::Aggregation;
 use dataflow::ops::join::JoinType;
+use dataflow::ops::project::{ProjectExpression, ProjectExpressionBase};
+use dataflow::ops::semijoin::SemiJoinType;
 
+use crate::controller::sql::expression::{Condition, Expression};
 use crate::controller::sql::query_graph::{OutputColumn, QueryGraph};
 use crate::controller::sql::query_signature::Signature;
+use crate::controller::sql::{restore, PAGE_COLUMN};
 use nom_sql::{
-    ArithmeticExpression, CaseWhenExpression, ColumnOrLiteral, ColumnSpecification,
+    ArithmeticBase, ArithmeticExpression, CaseWhenExpression, ColumnOrLiteral, ColumnSpecification,
     CompoundSelectOperator, ConditionBase, ConditionExpression, ConditionTree, Literal, Operator,
     SqlQuery, TableKey,
 };
@@ -30,6 +35,18 @@ mod join;
 mod rewrite;
 mod security;
 
+/// Returns the columns that the join predicate `jp` equates on its left and right.
+fn equi_join_columns(jp: &ConditionTree) -> Result<(Column, Column), String> {
+    let column = |ce: &ConditionExpression| match *ce {
+        ConditionExpression::Base(ConditionBase::Field(ref f)) => Ok(Column::from(f)),
+        _ => Err(format!("joins must compare columns, but got {}", jp)),
+    };
+    if jp.operator != Operator::Equal && jp.operator != Operator::In {
+        return Err(format!("only equi-joins are supported, but got {}", jp));
+    }
+    Ok((column(&*jp.left)?, column(&*jp.right)?))
+}
+
 fn sanitize_leaf_column(c: &mut Column, view_name: &str) {
     c.table = Some(view_name.to_string());
     c.function = None;
@@ -52,6 +69,13 @@ fn predicate_columns(ce: &ConditionExpression) -> HashSet<Column> {
         Bracketed(ref ce) => {
             cols.extend(predicate_columns(&ce));
         }
+        Arithmetic(ref ae) => {
+            for b in &[&ae.left, &ae.right] {
+                if let ArithmeticBase::Column(ref c) = **b {
+                    cols.insert(Column::from(c));
+                }
+            }
+        }
         NegationOp(_) => unreachable!("negations should have been eliminated"),
         _ => (),
     }
@@ -89,12 +113,95 @@ fn value_columns_needed_for_predicates(
                 },
                 oc.clone(),
             )),
-            OutputColumn::Data(_) => None,
+            OutputColumn::Data(_) | OutputColumn::Expression(_) => None,
         })
         .filter(|(c, _)| pred_columns.contains(c))
         .collect()
 }
 
+/// Converts a select list expression into a `ProjectExpression` over the columns that it refers
+/// to, which are added to `columns`.
+fn project_expression(e: &Expression, columns: &mut Vec<Column>) -> ProjectExpression {
+    match *e {
+        Expression::Column(ref c) => {
+            let c = Column::from(c);
+            let i = columns.iter().position(|pc| *pc == c).unwrap_or_else(|| {
+                columns.push(c);
+                columns.len() - 1
+            });
+            ProjectExpression::Base(ProjectExpressionBase::Column(i))
+        }
+        Expression::Literal(ref l) => {
+            ProjectExpression::Base(ProjectExpressionBase::Literal(DataType::from(l)))
+        }
+        Expression::Decimal(d) => {
+            ProjectExpression::Base(ProjectExpressionBase::Literal(DataType::from(d)))
+        }
+        Expression::Arithmetic(ref op, ref left, ref right) => ProjectExpression::Arithmetic {
+            op: op.clone(),
+            left: Box::new(project_expression(left, columns)),
+            right: Box::new(project_expression(right, columns)),
+        },
+        Expression::Case(ref branches, ref otherwise) => ProjectExpression::Case {
+            branches: branches
+                .iter()
+                .map(|&(ref c, ref e)| {
+                    let condition = project_condition(c, columns);
+                    (vec![condition], project_expression(e, columns))
+                })
+                .collect(),
+            otherwise: Box::new(match *otherwise {
+                Some(ref e) => project_expression(e, columns),
+                None => ProjectExpression::Base(ProjectExpressionBase::Literal(DataType::None)),
+            }),
+        },
+        Expression::Coalesce(ref es) => {
+            ProjectExpression::Coalesce(es.iter().map(|e| project_expression(e, columns)).collect())
+        }
+        Expression::Concat(ref es) => {
+            ProjectExpression::Concat(es.iter().map(|e| project_expression(e, columns)).collect())
+        }
+        Expression::Lower(ref e) => {
+            ProjectExpression::Lower(Box::new(project_expression(e, columns)))
+        }
+        Expression::Upper(ref e) => {
+            ProjectExpression::Upper(Box::new(project_expression(e, columns)))
+        }
+        Expression::Extract(part, ref e) => {
+            ProjectExpression::Extract(part, Box::new(project_expression(e, columns)))
+        }
+    }
+}
+
+/// Converts a `CASE` condition into a filter condition. The column that each condition is paired
+/// with is ignored, since they all compare expressions.
+fn project_condition(c: &Condition, columns: &mut Vec<Column>) -> (usize, FilterCondition) {
+    let condition = match *c {
+        Condition::Comparison(ref op, ref left, ref right) => FilterCondition::Expression(
+            op.clone(),
+            project_expression(left, columns),
+            project_expression(right, columns),
+        ),
+        Condition::And(ref cs) => {
+            FilterCondition::And(cs.iter().map(|c| project_condition(c, columns)).collect())
+        }
+        Condition::Or(ref cs) => {
+            FilterCondition::Or(cs.iter().map(|c| project_condition(c, columns)).collect())
+        }
+    };
+    (0, condition)
+}
+
+/// A change to a `SqlToMirConverter`, along with what it replaced, as recorded while the
+/// converter keeps a journal.
+#[derive(Clone, Debug)]
+enum Undo {
+    BaseSchemas(String, Option<Vec<(usize, Vec<ColumnSpecification>)>>),
+    Current(String, Option<usize>),
+    Node((String, usize), Option<MirNodeRef>),
+    SchemaVersion(usize),
+}
+
 #[derive(Clone, Debug)]
 pub(super) struct SqlToMirConverter {
     base_schemas: HashMap<String, Vec<(usize, Vec<ColumnSpecification>)>>,
@@ -105,6 +212,9 @@ pub(super) struct SqlToMirConverter {
 
     /// Universe in which the conversion is happening
     universe: Universe,
+
+    /// Changes made since `start_journal`, so that `rollback` can undo them
+    journal: Option<Vec<Undo>>,
 }
 
 impl Default for SqlToMirConverter {
@@ -116,6 +226,7 @@ impl Default for SqlToMirConverter {
             nodes: HashMap::default(),
             schema_version: 0,
             universe: Universe::default(),
+            journal: None,
         }
     }
 }
@@ -141,6 +252,75 @@ impl SqlToMirConverter {
         self.universe = Universe::default();
     }
 
+    /// Starts recording the changes made to the converter, so that they can be undone with
+    /// `rollback`.
+    pub(super) fn start_journal(&mut self) {
+        self.journal = Some(Vec::new());
+    }
+
+    /// Stops recording changes and keeps the ones made since `start_journal`.
+    pub(super) fn release_journal(&mut self) {
+        self.journal = None;
+    }
+
+    /// Undoes all changes made since `start_journal`, and stops recording.
+    pub(super) fn rollback(&mut self) {
+        let journal = match self.journal.take() {
+            Some(journal) => journal,
+            None => return,
+        };
+        for undo in journal.into_iter().rev() {
+            match undo {
+                Undo::BaseSchemas(name, prior) => restore(&mut self.base_schemas, name, prior),
+                Undo::Current(name, prior) => restore(&mut self.current, name, prior),
+                Undo::Node(id, prior) => restore(&mut self.nodes, id, prior),
+                Undo::SchemaVersion(v) => self.schema_version = v,
+            }
+        }
+    }
+
+    /// All MIR nodes that the converter knows about.
+    pub(super) fn nodes(&self) -> impl Iterator<Item = &MirNodeRef> {
+        self.nodes.values()
+    }
+
+    fn record(&mut self, undo: Undo) {
+        if let Some(ref mut journal) = self.journal {
+            journal.push(undo);
+        }
+    }
+
+    /// Registers `node` under `id`, unless a node is registered there already.
+    fn register_node(&mut self, id: (String, usize), node: &MirNodeRef) {
+        if !self.nodes.contains_key(&id) {
+            self.nodes.insert(id.clone(), node.clone());
+            self.record(Undo::Node(id, None));
+        }
+    }
+
+    fn unregister_node(&mut self, id: (String, usize)) -> Option<MirNodeRef> {
+        let prior = self.nodes.remove(&id);
+        if prior.is_some() {
+            self.record(Undo::Node(id, prior.clone()));
+        }
+        prior
+    }
+
+    /// Makes the current schema version the one in which `name` is looked up.
+    fn set_current(&mut self, name: &str) {
+        let prior = self.current.insert(String::from(name), self.schema_version);
+        self.record(Undo::Current(String::from(name), prior));
+    }
+
+    fn add_base_schema(&mut self, name: &str, columns: Vec<ColumnSpecification>) {
+        let prior = self.base_schemas.get(name).cloned();
+        self.base_schemas
+            .entry(String::from(name))
+            .or_default()
+            .push((self.schema_version, columns));
+        self.record(Undo::BaseSchemas(String::from(name), prior));
+    }
+
     fn get_view(&self, view_name: &str) -> Result<MirNodeRef, String> {
         self.current
             .get(view_name)
@@ -156,43 +336,92 @@ impl SqlToMirConverter {
 
     pub fn add_nodes(&mut self, nodes: Vec<MirNodeRef>) {
         for node in nodes {
-            let node_id = (String::from(node.borrow().name()), self.schema_version);
-            self.nodes.entry(node_id).or_insert_with(|| node.clone());
-            self.current
-                .insert(String::from(node.borrow().name()), self.schema_version);
+            let name = String::from(node.borrow().name());
+            self.register_node((name.clone(), self.schema_version), &node);
+            self.set_current(&name);
         }
     }
 
-    fn logical_op_to_conditions(
+    /// Converts an arbitrary boolean condition expression into filter conditions. Conjunctions
+    /// are flattened into separate conditions, while disjunctions become a single
+    /// `FilterCondition::Or` over the conditions for each of their sides.
+    fn to_filter_conditions(
         &self,
-        ct: &ConditionTree,
+        ce: &ConditionExpression,
         columns: &mut Vec<Column>,
         n: &MirNodeRef,
-    ) -> Vec<(usize, FilterCondition)> {
-        match ct.operator {
-            Operator::And => {
-                let mut left_filter = match ct.left.as_ref() {
-                    ConditionExpression::LogicalOp(ref ct2) => {
-                        self.logical_op_to_conditions(ct2, columns, n)
-                    }
-                    ConditionExpression::ComparisonOp(ref ct2) => {
-                        self.to_conditions(ct2, columns, n)
-                    }
-                    _ => unimplemented!(),
-                };
-                let mut right_filter = match ct.right.as_ref() {
-                    ConditionExpression::LogicalOp(ref ct2) => {
-                        self.logical_op_to_conditions(ct2, columns, n)
+    ) -> Result<Vec<(usize, FilterCondition)>, String> {
+        match *ce {
+            ConditionExpression::LogicalOp(ref ct) => {
+                let mut left = self.to_filter_conditions(&ct.left, columns, n)?;
+                let mut right = self.to_filter_conditions(&ct.right, columns, n)?;
+                match ct.operator {
+                    Operator::And => {
+                        left.append(&mut right);
+                        Ok(left)
                     }
-                    ConditionExpression::ComparisonOp(ref ct2) => {
-                        self.to_conditions(ct2, columns, n)
+                    Operator::Or => {
+                        // a disjunct with several conditions must match all of them
+                        let conjunction = |mut cs: Vec<(usize, FilterCondition)>| {
+                            if cs.len() == 1 {
+                                cs.pop().unwrap()
+                            } else {
+                                (cs[0].0, FilterCondition::And(cs))
+                            }
+                        };
+                        let l = conjunction(left);
+                        let r = conjunction(right);
+                        Ok(vec![(l.0, FilterCondition::Or(vec![l, r]))])
                     }
-                    _ => unimplemented!(),
-                };
-                left_filter.append(&mut right_filter);
-                left_filter
+                    ref op => Err(format!("unsupported logical operator {}", op)),
+                }
+            }
+            ConditionExpression::ComparisonOp(ref ct) => self.to_conditions(ct, columns, n),
+            ConditionExpression::Bracketed(ref inner) => {
+                self.to_filter_conditions(inner, columns, n)
             }
-            _ => unimplemented!(),
+            ConditionExpression::NegationOp(_) => {
+                Err("negated condition could not be rewritten".to_owned())
+            }
+            ConditionExpression::Base(_) => Err("condition is not a comparison".to_owned()),
+            ConditionExpression::Arithmetic(_) => {
+                Err("arithmetic expression used as a condition".to_owned())
+            }
+        }
+    }
+
+    /// Converts an operand of a comparison into an expression over the columns of `n`.
+    fn to_expression(
+        &self,
+        ce: &ConditionExpression,
+        n: &MirNodeRef,
+    ) -> Result<ProjectExpression, String> {
+        let base = |b: &ArithmeticBase| match *b {
+            ArithmeticBase::Column(ref c) => {
+                let id = n.borrow().column_id_for_column(&Column::from(c), None);
+                ProjectExpression::Base(ProjectExpressionBase::Column(id))
+            }
+            ArithmeticBase::Scalar(ref l) => {
+                ProjectExpression::Base(ProjectExpressionBase::Literal(DataType::from(l)))
+            }
+        };
+        match *ce {
+            ConditionExpression::Base(ConditionBase::Field(ref f)) => {
+                Ok(base(&ArithmeticBase::Column(f.clone())))
+            }
+            ConditionExpression::Base(ConditionBase::Literal(ref l)) => {
+                Ok(base(&ArithmeticBase::Scalar(l.clone())))
+            }
+            ConditionExpression::Arithmetic(ref ae) => Ok(ProjectExpression::Arithmetic {
+                op: ae.op.clone(),
+                left: Box::new(base(&ae.left)),
+                right: Box::new(base(&ae.right)),
+            }),
+            ConditionExpression::Bracketed(ref inner) => self.to_expression(inner, n),
+            ConditionExpression::Base(ConditionBase::NestedSelect(_)) => {
+                Err("nested SELECT in comparisons is not supported".to_owned())
+            }
+            ref ce => Err(format!("unsupported operand in comparison: {:?}", ce)),
         }
     }
 
@@ -203,13 +432,30 @@ impl SqlToMirConverter {
         ct: &ConditionTree,
         columns: &mut Vec<Column>,
         n: &MirNodeRef,
-    ) -> Vec<(usize, FilterCondition)> {
+    ) -> Result<Vec<(usize, FilterCondition)>, String> {
         use std::cmp::max;
 
+        // comparisons involving arithmetic are evaluated as expressions over the whole record
+        if let (ConditionExpression::Arithmetic(_), _) | (_, ConditionExpression::Arithmetic(_)) =
+            (ct.left.as_ref(), ct.right.as_ref())
+        {
+            let left = self.to_expression(&ct.left, n)?;
+            let right = self.to_expression(&ct.right, n)?;
+            return Ok(vec![(
+                0,
+                FilterCondition::Expression(ct.operator.clone(), left, right),
+            )]);
+        }
+
         // TODO(malte): we only support one level of condition nesting at this point :(
         let l = match *ct.left.as_ref() {
             ConditionExpression::Base(ConditionBase::Field(ref f)) => f.clone(),
-            _ => unimplemented!(),
+            ref ce => {
+                return Err(format!(
+                    "left-hand side of comparison must be a column, not {:?}",
+                    ce
+                ));
+            }
         };
         use dataflow::ops::filter;
         let f = match *ct.right.as_ref() {
@@ -241,7 +487,15 @@ impl SqlToMirConverter {
                 let fi = columns.iter().rposition(|c| *c.name == f.name).unwrap();
                 FilterCondition::Comparison(ct.operator.clone(), filter::Value::Column(fi))
             }
-            _ => unimplemented!(),
+            ConditionExpression::Base(ConditionBase::NestedSelect(_)) => {
+                return Err("nested SELECT in comparisons is not supported".to_owned());
+            }
+            ref ce => {
+                return Err(format!(
+                    "unsupported right-hand side of comparison: {:?}",
+                    ce
+                ));
+            }
         };
 
         let absolute_column_ids: Vec<usize> = columns
@@ -274,7 +528,7 @@ impl SqlToMirConverter {
             }
         }
 
-        filters
+        Ok(filters)
     }
 
     pub(super) fn add_leaf_below(
@@ -305,6 +559,7 @@ impl SqlToMirConverter {
                     emit: columns.clone(),
                     literals: vec![],
                     arithmetic: vec![],
+                    expressions: vec![],
                 },
                 vec![parent.clone()],
                 vec![],
@@ -335,15 +590,18 @@ impl SqlToMirConverter {
             MirNodeType::Leaf {
                 node: parent.clone(),
                 keys: Vec::from(params),
+                range: None,
             },
             vec![n],
             vec![],
         );
 
         // always register leaves
-        self.current.insert(String::from(name), self.schema_version);
-        self.nodes
+        self.set_current(name);
+        let prior = self
+            .nodes
             .insert((String::from(name), self.schema_version), new_leaf.clone());
+        self.record(Undo::Node((String::from(name), self.schema_version), prior));
 
         // wrap in a (very short) query to return
         MirQuery {
@@ -361,7 +619,7 @@ impl SqlToMirConverter {
         order: &Option<OrderClause>,
         limit: &Option<LimitClause>,
         has_leaf: bool,
-    ) -> MirQuery {
+    ) -> Result<MirQuery, String> {
         let union_name = if !has_leaf && limit.is_none() {
             String::from(name)
         } else {
@@ -371,13 +629,16 @@ impl SqlToMirConverter {
             CompoundSelectOperator::Union => self.make_union_node(
                 &union_name,
                 &sqs.iter().map(|mq| mq.leaf.clone()).collect::<Vec<_>>()[..],
-            ),
-            _ => unimplemented!(),
+            )?,
+            op => {
+                return Err(format!(
+                    "{} is not supported in compound SELECT queries",
+                    op
+                ))
+            }
         };
         let node_id = (union_name, self.schema_version);
-        self.nodes
-            .entry(node_id)
-            .or_insert_with(|| final_node.clone());
+        self.register_node(node_id, &final_node);
 
         // we use these columns for intermediate nodes
         let columns: Vec<Column> = final_node.borrow().columns().to_vec();
@@ -402,12 +663,10 @@ impl SqlToMirConverter {
                 final_node,
                 topk_columns,
                 order,
-                limit.as_ref().unwrap(),
+                limit.as_ref().unwrap().limit as usize,
             );
             let node_id = (topk_name, self.schema_version);
-            self.nodes
-                .entry(node_id)
-                .or_insert_with(|| topk_node.clone());
+            self.register_node(node_id, &topk_node);
             final_node = topk_node;
         }
 
@@ -419,6 +678,7 @@ impl SqlToMirConverter {
                 MirNodeType::Leaf {
                     node: final_node.clone(),
                     keys: vec![],
+                    range: None,
                 },
                 vec![final_node.clone()],
                 vec![],
@@ -428,21 +688,19 @@ impl SqlToMirConverter {
             final_node
         };
 
-        self.current
-            .insert(String::from(leaf_node.borrow().name()), self.schema_version);
+        let leaf_name = String::from(leaf_node.borrow().name());
+        self.set_current(&leaf_name);
         let node_id = (String::from(name), self.schema_version);
-        self.nodes
-            .entry(node_id)
-            .or_insert_with(|| leaf_node.clone());
+        self.register_node(node_id, &leaf_node);
 
-        MirQuery {
+        Ok(MirQuery {
             name: String::from(name),
             roots: sqs.iter().fold(Vec::new(), |mut acc, mq| {
                 acc.extend(mq.roots.iter().cloned());
                 acc
             }),
             leaf: leaf_node,
-        }
+        })
     }
 
     // pub(super) viz for tests
@@ -463,63 +721,85 @@ impl SqlToMirConverter {
         }
     }
 
-    pub(super) fn named_base_to_mir(&mut self, name: &str, query: &SqlQuery) -> MirQuery {
+    pub(super) fn named_base_to_mir(
+        &mut self,
+        name: &str,
+        query: &SqlQuery,
+    ) -> Result<MirQuery, String> {
         match *query {
             SqlQuery::CreateTable(ref ctq) => {
-                assert_eq!(name, ctq.table.name);
-                let n = self.make_base_node(&name, &ctq.fields, ctq.keys.as_ref());
+                if name != ctq.table.name {
+                    return Err(format!(
+                        "table {} cannot be added under the name {}",
+                        ctq.table.name, name
+                    ));
+                }
+                let n = self.make_base_node(&name, &ctq.fields, ctq.keys.as_ref())?;
                 let node_id = (String::from(name), self.schema_version);
-                use std::collections::hash_map::Entry;
-                if let Entry::Vacant(e) = self.nodes.entry(node_id) {
-                    self.current.insert(String::from(name), self.schema_version);
-                    e.insert(n.clone());
+                if !self.nodes.contains_key(&node_id) {
+                    self.set_current(name);
+                    self.register_node(node_id, &n);
                 }
-                MirQuery::singleton(name, n)
+                Ok(MirQuery::singleton(name, n))
             }
-            _ => panic!("expected CREATE TABLE query!"),
+            ref q => Err(format!("expected CREATE TABLE query, got {}", q)),
         }
     }
 
-    pub(super) fn remove_query(&mut self, name: &str, mq: &MirQuery) {
-        use std::collections::VecDeque;
-
+    /// Checks that `remove_query` can remove the query called `name`, whose MIR is `mq`.
+    pub(super) fn check_removal(&self, name: &str, mq: &MirQuery) -> Result<(), String> {
         let v = self
             .current
-            .remove(name)
-            .unwrap_or_else(|| panic!("no query named \"{}\"?", name));
+            .get(name)
+            .ok_or_else(|| format!("no query named \"{}\"", name))?;
+        match self.nodes.get(&(name.to_owned(), *v)) {
+            Some(leaf) if leaf.borrow().name == mq.leaf.borrow().name => Ok(()),
+            Some(_) => Err(format!(
+                "query \"{}\" does not end in the expected leaf node",
+                name
+            )),
+            None => Err(format!("query \"{}\" has no leaf node at v{}", name, v)),
+        }
+    }
 
-        let nodeid = (name.to_owned(), v);
-        let leaf_mn = self.nodes.remove(&nodeid).unwrap();
+    pub(super) fn remove_query(&mut self, name: &str, mq: &MirQuery) -> Result<(), String> {
+        use std::collections::VecDeque;
 
-        assert_eq!(leaf_mn.borrow().name, mq.leaf.borrow().name);
+        self.check_removal(name, mq)?;
+        let v = self.current[name];
+        let prior = self.current.remove(name);
+        self.record(Undo::Current(name.to_owned(), prior));
+        let leaf_mn = self.unregister_node((name.to_owned(), v)).unwrap();
 
         // traverse the MIR query backwards, removing any nodes that we still have registered.
         let mut q = VecDeque::new();
         q.push_back(leaf_mn);
 
-        while !q.is_empty() {
-            let mnr = q.pop_front().unwrap();
-            let n = mnr.borrow_mut();
+        while let Some(mnr) = q.pop_front() {
+            let n = mnr.borrow();
             q.extend(n.ancestors.clone());
             // node may not be registered, so don't bother checking return
             match n.inner {
                 MirNodeType::Reuse { .. } | MirNodeType::Base { .. } => (),
                 _ => {
-                    self.nodes.remove(&(n.name.to_owned(), v));
+                    self.unregister_node((n.name.to_owned(), v));
                 }
             }
         }
+        Ok(())
     }
 
-    pub(super) fn remove_base(&mut self, name: &str, mq: &MirQuery) {
+    pub(super) fn remove_base(&mut self, name: &str, mq: &MirQuery) -> Result<(), String> {
         info!(self.log, "Removing base {} from SqlTomirconverter", name);
-        self.remove_query(name, mq);
-        if self.base_schemas.remove(name).is_none() {
-            warn!(
+        self.remove_query(name, mq)?;
+        match self.base_schemas.remove(name) {
+            Some(prior) => self.record(Undo::BaseSchemas(name.to_owned(), Some(prior))),
+            None => warn!(
                 self.log,
                 "Attempted to remove non-existant base node {} from SqlToMirconverter", name
-            );
+            ),
         }
+        Ok(())
     }
 
     pub(super) fn named_query_to_mir(
@@ -547,7 +827,7 @@ impl SqlToMirConverter {
             // only add the node if we don't have it registered at this schema version already. If
             // we don't do this, we end up adding the node again for every re-use of it, with
             // increasingly deeper chains of nested `MirNode::Reuse` structures.
-            self.nodes.entry(node_id).or_insert_with(|| mn.clone());
+            self.register_node(node_id, &mn);
 
             if mn.borrow().ancestors().is_empty() {
                 // root
@@ -558,15 +838,16 @@ impl SqlToMirConverter {
                 leaves.push(mn);
             }
         }
-        assert_eq!(
-            leaves.len(),
-            1,
-            "expected just one leaf! leaves: {:?}",
-            leaves
-        );
+        if leaves.len() != 1 {
+            return Err(format!(
+                "expected query {} to have one leaf, but it has {}",
+                name,
+                leaves.len()
+            ));
+        }
         let leaf = leaves.into_iter().next().unwrap();
-        self.current
-            .insert(String::from(leaf.borrow().name()), self.schema_version);
+        let leaf_name = String::from(leaf.borrow().name());
+        self.set_current(&leaf_name);
 
         Ok((
             sec,
@@ -580,9 +861,16 @@ impl SqlToMirConverter {
         ))
     }
 
-    pub(super) fn upgrade_schema(&mut self, new_version: usize) {
-        assert!(new_version > self.schema_version);
+    pub(super) fn upgrade_schema(&mut self, new_version: usize) -> Result<(), String> {
+        if new_version <= self.schema_version {
+            return Err(format!(
+                "cannot go back from schema version {} to {}",
+                self.schema_version, new_version
+            ));
+        }
+        self.record(Undo::SchemaVersion(self.schema_version));
         self.schema_version = new_version;
+        Ok(())
     }
 
     fn make_base_node(
@@ -590,7 +878,7 @@ impl SqlToMirConverter {
         name: &str,
         cols: &[ColumnSpecification],
         keys: Option<&Vec<TableKey>>,
-    ) -> MirNodeRef {
+    ) -> Result<MirNodeRef, String> {
         // have we seen a base of this name before?
         if self.base_schemas.contains_key(name) {
             let mut existing_schemas: Vec<(usize, Vec<ColumnSpecification>)> =
@@ -612,7 +900,7 @@ impl SqlToMirConverter {
                         existing_sv
                     );
                     let existing_node = self.nodes[&(String::from(name), existing_sv)].clone();
-                    return MirNode::reuse(existing_node, self.schema_version);
+                    return Ok(MirNode::reuse(existing_node, self.schema_version));
                 } else {
                     // match, but schema is different, so we'll need to either:
                     //  1) reuse the existing node, but add an upgrader for any changes in the
@@ -672,26 +960,23 @@ impl SqlToMirConverter {
                                 columns
                                     .iter()
                                     .position(|cc| cc == *removed)
-                                    .unwrap_or_else(|| {
-                                        panic!(
-                                            "couldn't find column \"{:#?}\", \
-                                             which we're removing",
-                                            removed
+                                    .ok_or_else(|| {
+                                        format!(
+                                            "couldn't find column {}, which we're removing from {}",
+                                            removed.column.name, name
                                         )
-                                    });
+                                    })?;
                             columns.remove(pos);
                         }
-                        assert_eq!(
-                            columns.len(),
-                            existing_node.borrow().columns().len() + columns_added.len()
-                                - columns_removed.len()
-                        );
 
                         // remember the schema for this version
-                        let base_schemas = self.base_schemas.entry(String::from(name)).or_default();
-                        base_schemas.push((self.schema_version, columns.clone()));
+                        self.add_base_schema(name, columns);
 
-                        return MirNode::adapt_base(existing_node, columns_added, columns_removed);
+                        return Ok(MirNode::adapt_base(
+                            existing_node,
+                            columns_added,
+                            columns_removed,
+                        ));
                     } else {
                         info!(self.log, "base table has complex schema change");
                         break;
@@ -701,9 +986,15 @@ impl SqlToMirConverter {
         }
 
         // all columns on a base must have the base as their table
-        assert!(cols
+        if let Some(c) = cols
             .iter()
-            .all(|c| c.column.table == Some(String::from(name))));
+            .find(|c| c.column.table != Some(String::from(name)))
+        {
+            return Err(format!(
+                "column {} does not belong to table {}",
+                c.column.name, name
+            ));
+        }
 
         // primary keys can either be specified directly (at the end of CREATE TABLE), or inline
         // with the definition of a field (i.e., as a ColumnConstraint).
@@ -719,14 +1010,36 @@ impl SqlToMirConverter {
                 })
                 .collect(),
         };
-        assert!(primary_keys.len() <= 1);
+        if primary_keys.len() > 1 {
+            return Err(format!("table {} has more than one primary key", name));
+        }
+
+        // unique keys over a single column have been turned into constraints on that column by
+        // an earlier rewrite pass, so only those over several columns are left to pass on.
+        let mut unique_keys = Vec::new();
+        for k in keys.into_iter().flatten() {
+            if let TableKey::UniqueKey(_, ref key_cols) = *k {
+                if key_cols.len() < 2 {
+                    continue;
+                }
+                if let Some(c) = key_cols
+                    .iter()
+                    .find(|c| !cols.iter().any(|cs| cs.column.name == c.name))
+                {
+                    return Err(format!(
+                        "unique key of table {} refers to unknown column {}",
+                        name, c.name
+                    ));
+                }
+                unique_keys.push(key_cols.iter().map(Column::from).collect());
+            }
+        }
 
         // remember the schema for this version
-        let base_schemas = self.base_schemas.entry(String::from(name)).or_default();
-        base_schemas.push((self.schema_version, cols.to_vec()));
+        self.add_base_schema(name, cols.to_vec());
 
         // make node
-        if !primary_keys.is_empty() {
+        Ok(if !primary_keys.is_empty() {
             match **primary_keys.iter().next().unwrap() {
                 TableKey::PrimaryKey(ref key_cols) => {
                     debug!(
@@ -746,6 +1059,7 @@ impl SqlToMirConverter {
                         MirNodeType::Base {
                             column_specs: cols.iter().map(|cs| (cs.clone(), None)).collect(),
                             keys: key_cols.iter().map(Column::from).collect(),
+                            unique_keys,
                             adapted_over: None,
                         },
                         vec![],
@@ -762,17 +1076,20 @@ impl SqlToMirConverter {
                 MirNodeType::Base {
                     column_specs: cols.iter().map(|cs| (cs.clone(), None)).collect(),
                     keys: vec![],
+                    unique_keys,
                     adapted_over: None,
                 },
                 vec![],
                 vec![],
             )
-        }
+        })
     }
 
-    fn make_union_node(&self, name: &str, ancestors: &[MirNodeRef]) -> MirNodeRef {
+    fn make_union_node(&self, name: &str, ancestors: &[MirNodeRef]) -> Result<MirNodeRef, String> {
         let mut emit: Vec<Vec<Column>> = Vec::new();
-        assert!(ancestors.len() > 1, "union must have more than 1 ancestors");
+        if ancestors.len() < 2 {
+            return Err("union must have more than 1 ancestors".to_owned());
+        }
 
         let ucols: Vec<Column> = ancestors.first().unwrap().borrow().columns().to_vec();
         let num_ucols = ucols.len();
@@ -790,18 +1107,16 @@ impl SqlToMirConverter {
             {
                 selected_cols.insert(c.name.clone());
             } else {
-                panic!(
+                return Err(format!(
                     "column with name '{}' not found all union ancestors: all ancestors' \
                      output columns must have the same names",
                     c.name
-                );
+                ));
             }
         }
-        assert_eq!(
-            num_ucols,
-            selected_cols.len(),
-            "union drops ancestor columns"
-        );
+        if num_ucols != selected_cols.len() {
+            return Err("union drops ancestor columns".to_owned());
+        }
 
         for ancestor in ancestors.iter() {
             let mut acols: Vec<Column> = Vec::new();
@@ -815,21 +1130,18 @@ impl SqlToMirConverter {
             emit.push(acols.clone());
         }
 
-        assert!(
-            emit.iter().all(|e| e.len() == selected_cols.len()),
-            "all ancestors columns must have the same size, but got emit: {:?}, selected: {:?}",
-            emit,
-            selected_cols
-        );
+        if emit.iter().any(|e| e.len() != selected_cols.len()) {
+            return Err("all ancestors of a union must have the same number of columns".to_owned());
+        }
 
-        MirNode::new(
+        Ok(MirNode::new(
             name,
             self.schema_version,
             emit.first().unwrap().clone(),
             MirNodeType::Union { emit },
             ancestors.to_vec(),
             vec![],
-        )
+        ))
     }
 
     // Creates union node for universe creation - returns the resulting node ref and a universe table mapping
@@ -837,12 +1149,17 @@ impl SqlToMirConverter {
         &self,
         name: &str,
         ancestors: &[MirNodeRef],
-    ) -> (
-        MirNodeRef,
-        Option<HashMap<(String, Option<String>), String>>,
-    ) {
+    ) -> Result<
+        (
+            MirNodeRef,
+            Option<HashMap<(String, Option<String>), String>>,
+        ),
+        String,
+    > {
         let mut emit: Vec<Vec<Column>> = Vec::new();
-        assert!(ancestors.len() > 1, "union must have more than 1 ancestors");
+        if ancestors.len() < 2 {
+            return Err("union must have more than 1 ancestors".to_owned());
+        }
 
         let ucols: Vec<Column> = ancestors.first().unwrap().borrow().columns().to_vec();
         let num_ucols = ucols.len();
@@ -879,11 +1196,9 @@ impl SqlToMirConverter {
             }
         }
 
-        assert_eq!(
-            num_ucols,
-            selected_cols.len(),
-            "union drops ancestor columns"
-        );
+        if num_ucols != selected_cols.len() {
+            return Err("union drops ancestor columns".to_owned());
+        }
 
         let mut table_mapping = HashMap::new();
 
@@ -921,14 +1236,11 @@ impl SqlToMirConverter {
             emit.push(acols.clone());
         }
 
-        assert!(
-            emit.iter().all(|e| e.len() == selected_cols.len()),
-            "all ancestors columns must have the same size, but got emit: {:?}, selected: {:?}",
-            emit,
-            selected_cols
-        );
+        if emit.iter().any(|e| e.len() != selected_cols.len()) {
+            return Err("all ancestors of a union must have the same number of columns".to_owned());
+        }
 
-        (
+        Ok((
             MirNode::new(
                 name,
                 self.schema_version,
@@ -938,63 +1250,52 @@ impl SqlToMirConverter {
                 vec![],
             ),
             Some(table_mapping),
-        )
+        ))
     }
 
-    fn make_union_from_same_base(
+    fn make_filter_node(
         &self,
         name: &str,
-        ancestors: Vec<MirNodeRef>,
-        columns: Vec<Column>,
-    ) -> MirNodeRef {
-        assert!(ancestors.len() > 1, "union must have more than 1 ancestors");
-        trace!(self.log, "Added union node wiht columns {:?}", columns);
-        let emit = ancestors.iter().map(|_| columns.clone()).collect();
-
-        MirNode::new(
-            name,
-            self.schema_version,
-            columns,
-            MirNodeType::Union { emit },
-            ancestors.clone(),
-            vec![],
-        )
-    }
-
-    fn make_filter_node(&self, name: &str, parent: MirNodeRef, cond: &ConditionTree) -> MirNodeRef {
+        parent: MirNodeRef,
+        cond: &ConditionExpression,
+    ) -> Result<MirNodeRef, String> {
         let mut fields = parent.borrow().columns().to_vec();
 
-        let filter = self.to_conditions(cond, &mut fields, &parent);
+        let filter = self.to_filter_conditions(cond, &mut fields, &parent)?;
         trace!(
             self.log,
             "Added filter node {} with condition {:?}",
             name,
             filter
         );
-        MirNode::new(
+        Ok(MirNode::new(
             name,
             self.schema_version,
             fields,
             MirNodeType::Filter { conditions: filter },
             vec![parent.clone()],
             vec![],
-        )
+        ))
     }
 
+    /// Makes the nodes that compute `func_col`, which computes `aggregate` instead of its function
+    /// if it stands in for an aggregate function that nom-sql cannot parse.
     fn make_function_node(
         &self,
         name: &str,
         func_col: &Column,
+        aggregate: Option<Aggregation>,
         group_cols: Vec<&Column>,
         parent: MirNodeRef,
-    ) -> Vec<MirNodeRef> {
-        use dataflow::ops::grouped::aggregate::Aggregation;
+    ) -> Result<Vec<MirNodeRef>, String> {
         use dataflow::ops::grouped::extremum::Extremum;
         use dataflow::ops::grouped::filteraggregate::FilterAggregation;
         use nom_sql::FunctionArguments;
         use nom_sql::FunctionExpression::*;
 
         let mut out_nodes = Vec::new();
+        let mut visible_cols = group_cols.clone();
+        visible_cols.push(func_col);
 
         let mknode = |over: &Column,
                       over_else: Option<Literal>,
@@ -1002,21 +1303,53 @@ impl SqlToMirConverter {
                       distinct: bool,
                       cond: Option<&ConditionExpression>| {
             if distinct {
-                let new_name = name.to_owned() + "_distinct";
-                let mut dist_col = Vec::new();
-                dist_col.push(over);
-                dist_col.extend(group_cols.clone());
-                let node = self.make_distinct_node(&new_name, parent, dist_col.clone());
-                out_nodes.push(node.clone());
+                // count how often each value of the `over` column occurs in each group, and only
+                // aggregate over the values that occur at all. unlike a plain distinct, this keeps
+                // a value around until its last occurrence goes away.
+                let one = Column::new(None, &format!("{}_one", name));
+                let mut dist_cols = group_cols.clone();
+                dist_cols.push(over);
+                let prj = self.make_project_node(
+                    &format!("{}_distinct_prj", name),
+                    parent,
+                    dist_cols.clone(),
+                    vec![],
+                    vec![],
+                    vec![(one.name.clone(), DataType::from(1))],
+                    false,
+                );
+                out_nodes.push(prj.clone());
+
+                let mut occurrences = Column::new(None, &format!("{}_occurrences", name));
+                occurrences.function = Some(Box::new(Count(
+                    FunctionArguments::Column(nom_sql::Column::from(one.name.as_str())),
+                    false,
+                )));
+                let cnt = self.make_grouped_node(
+                    &format!("{}_distinct_cnt", name),
+                    &occurrences,
+                    (prj, &one, None),
+                    dist_cols,
+                    GroupedNodeType::Aggregation(Aggregation::COUNT),
+                    None,
+                )?;
+                out_nodes.push(cnt.clone());
+
+                let present = self.make_occurrence_filter_node(
+                    &format!("{}_distinct", name),
+                    cnt,
+                    &occurrences,
+                );
+                out_nodes.push(present.clone());
                 out_nodes.push(self.make_grouped_node(
                     name,
                     &func_col,
-                    (node, &over, over_else),
+                    (present, &over, over_else),
                     group_cols,
                     t,
                     cond,
-                ));
-                out_nodes
+                )?);
+                Ok(out_nodes)
             } else {
                 out_nodes.push(self.make_grouped_node(
                     name,
@@ -1025,13 +1358,13 @@ impl SqlToMirConverter {
                     group_cols,
                     t,
                     cond,
-                ));
-                out_nodes
+                )?);
+                Ok(out_nodes)
             }
         };
 
         let func = func_col.function.as_ref().unwrap();
-        match *func.deref() {
+        let mut nodes = match *func.deref() {
             Sum(FunctionArguments::Column(ref col), distinct) => mknode(
                 &Column::from(col),
                 None,
@@ -1074,6 +1407,13 @@ impl SqlToMirConverter {
                 distinct,
                 None,
             ),
+            Avg(FunctionArguments::Column(ref col), distinct) => mknode(
+                &Column::from(col),
+                None,
+                GroupedNodeType::Aggregation(Aggregation::AVG),
+                distinct,
+                None,
+            ),
             CountStar => {
                 // XXX(malte): there is no "over" column, but our aggregation operators' API
                 // requires one to be specified, so we earlier rewrote it to use the last parent
@@ -1081,7 +1421,7 @@ impl SqlToMirConverter {
                 // faithful to COUNT(*) semantics, because COUNT(*) is supposed to count all
                 // rows including those with NULL values, and we don't have a mechanism to do that
                 // (but we also don't have a NULL value, so maybe we're okay).
-                panic!("COUNT(*) should have been rewritten earlier!")
+                Err("COUNT(*) should have been rewritten earlier!".to_owned())
             }
             Count(
                 FunctionArguments::Conditional(CaseWhenExpression {
@@ -1125,15 +1465,36 @@ impl SqlToMirConverter {
                 false,
                 None,
             ),
-            GroupConcat(FunctionArguments::Column(ref col), ref separator) => mknode(
-                &Column::from(col),
-                None,
-                GroupedNodeType::GroupConcat(separator.clone()),
+            GroupConcat(FunctionArguments::Column(ref col), ref separator) => {
+                // aggregates that nom-sql cannot parse arrive disguised as a GROUP_CONCAT
+                let t = match aggregate {
+                    Some(agg) => GroupedNodeType::Aggregation(agg),
+                    None => GroupedNodeType::GroupConcat(separator.clone()),
+                };
+                mknode(&Column::from(col), None, t, false, None)
+            }
+            ref f => Err(format!("unsupported aggregate function {:?}", f)),
+        }?;
+
+        // the state columns that an aggregation keeps are internal to it, so only its group
+        // columns and value are visible to the rest of the query
+        let agg = nodes.last().unwrap().clone();
+        let has_state = match agg.borrow().inner {
+            MirNodeType::Aggregation { ref kind, .. } => !kind.state_columns().is_empty(),
+            _ => false,
+        };
+        if has_state {
+            nodes.push(self.make_project_node(
+                &format!("{}_state_prj", name),
+                agg,
+                visible_cols,
+                vec![],
+                vec![],
+                vec![],
                 false,
-                None,
-            ),
-            _ => unimplemented!(),
+            ));
         }
+        Ok(nodes)
     }
 
     fn make_grouped_node(
@@ -1144,7 +1505,7 @@ impl SqlToMirConverter {
         group_by: Vec<&Column>,
         node_type: GroupedNodeType,
         condition: Option<&ConditionExpression>,
-    ) -> MirNodeRef {
+    ) -> Result<MirNodeRef, String> {
         let parent_node = over.0;
 
         // Resolve column IDs in parent
@@ -1152,15 +1513,22 @@ impl SqlToMirConverter {
         let else_val = over.2;
 
         // The function node's set of output columns is the group columns plus the function
-        // column
+        // column, preceded by any state columns that the aggregation keeps
         let mut combined_columns = group_by
             .iter()
             .map(|c| (*c).clone())
             .collect::<Vec<Column>>();
+        if let GroupedNodeType::Aggregation(ref agg) = node_type {
+            combined_columns.extend(
+                agg.state_columns()
+                    .iter()
+                    .map(|s| Column::new(None, &format!("{}_{}", computed_col.name, s))),
+            );
+        }
         combined_columns.push(computed_col.clone());
 
         // make the new operator
-        match node_type {
+        Ok(match node_type {
             GroupedNodeType::Aggregation(agg) => MirNode::new(
                 name,
                 self.schema_version,
@@ -1186,20 +1554,10 @@ impl SqlToMirConverter {
                 vec![],
             ),
             GroupedNodeType::FilterAggregation(filter_agg) => {
-                use nom_sql::ConditionExpression::*;
-
-                let cond = condition.expect("Filter aggregation must have condition!");
+                let cond = condition
+                    .ok_or_else(|| "filter aggregation must have a condition".to_owned())?;
                 let mut fields = parent_node.borrow().columns().to_vec();
-                let filter = match *cond {
-                    LogicalOp(ref ct) => {
-                        self.logical_op_to_conditions(ct, &mut fields, &parent_node)
-                    }
-                    ComparisonOp(ref ct) => self.to_conditions(ct, &mut fields, &parent_node),
-                    Bracketed(_) => unimplemented!(),
-                    NegationOp(_) => unreachable!("negation should have been removed earlier"),
-                    Base(_) => unreachable!("dangling base predicate"),
-                    Arithmetic(_) => unimplemented!(),
-                };
+                let filter = self.to_filter_conditions(cond, &mut fields, &parent_node)?;
                 MirNode::new(
                     name,
                     self.schema_version,
@@ -1226,7 +1584,19 @@ impl SqlToMirConverter {
                 vec![parent_node.clone()],
                 vec![],
             ),
-        }
+        })
+    }
+
+    fn make_identity_node(&self, name: &str, parent: MirNodeRef) -> MirNodeRef {
+        let columns = parent.borrow().columns().to_vec();
+        MirNode::new(
+            name,
+            self.schema_version,
+            columns,
+            MirNodeType::Identity,
+            vec![parent.clone()],
+            vec![],
+        )
     }
 
     fn make_join_node(
@@ -1236,7 +1606,7 @@ impl SqlToMirConverter {
         left_node: MirNodeRef,
         right_node: MirNodeRef,
         kind: JoinType,
-    ) -> MirNodeRef {
+    ) -> Result<MirNodeRef, String> {
         // TODO(malte): this is where we overproject join columns in order to increase reuse
         // opportunities. Technically, we need to only project those columns here that the query
         // actually needs; at a minimum, we could start with just the join colums, relying on the
@@ -1253,16 +1623,7 @@ impl SqlToMirConverter {
         let mut left_join_columns = Vec::new();
         let mut right_join_columns = Vec::new();
 
-        // equi-join only
-        assert!(jp.operator == Operator::Equal || jp.operator == Operator::In);
-        let mut l_col = match *jp.left {
-            ConditionExpression::Base(ConditionBase::Field(ref f)) => Column::from(f),
-            _ => unimplemented!(),
-        };
-        let r_col = match *jp.right {
-            ConditionExpression::Base(ConditionBase::Field(ref f)) => Column::from(f),
-            _ => unimplemented!(),
-        };
+        let (mut l_col, r_col) = equi_join_columns(jp)?;
 
         // don't duplicate the join column in the output, but instead add aliases to the columns
         // that represent it going forward (viz., the left-side join column)
@@ -1292,7 +1653,6 @@ impl SqlToMirConverter {
         left_join_columns.push(l_col);
         right_join_columns.push(r_col);
 
-        assert_eq!(left_join_columns.len(), right_join_columns.len());
         let inner = match kind {
             JoinType::Inner => MirNodeType::Join {
                 on_left: left_join_columns,
@@ -1304,16 +1664,66 @@ impl SqlToMirConverter {
                 on_right: right_join_columns,
                 project: fields.clone(),
             },
+            JoinType::Full => MirNodeType::FullJoin {
+                on_left: left_join_columns,
+                on_right: right_join_columns,
+                project: fields.clone(),
+            },
         };
         trace!(self.log, "Added join node {:?}", inner);
-        MirNode::new(
+        Ok(MirNode::new(
             name,
             self.schema_version,
             fields,
             inner,
             vec![left_node.clone(), right_node.clone()],
             vec![],
-        )
+        ))
+    }
+
+    fn make_semi_join_node(
+        &self,
+        name: &str,
+        jp: &ConditionTree,
+        left_node: MirNodeRef,
+        right_node: MirNodeRef,
+        kind: SemiJoinType,
+    ) -> Result<MirNodeRef, String> {
+        // semi- and anti-joins only ever emit the columns on the left
+        let fields = left_node.borrow().columns().to_vec();
+
+        let (l_col, r_col) = equi_join_columns(jp)?;
+
+        let on_left = vec![l_col];
+        let on_right = vec![r_col];
+        let inner = match kind {
+            SemiJoinType::Semi => MirNodeType::SemiJoin {
+                on_left,
+                on_right,
+                project: fields.clone(),
+            },
+            SemiJoinType::Anti => MirNodeType::AntiJoin {
+                on_left,
+                on_right,
+                project: fields.clone(),
+                null_aware: false,
+            },
+            SemiJoinType::NotIn => MirNodeType::AntiJoin {
+                on_left,
+                on_right,
+                project: fields.clone(),
+                null_aware: true,
+            },
+        };
+        trace!(self.log, "Added semi-join node {:?}", inner);
+        Ok(MirNode::new(
+            name,
+            self.schema_version,
+            fields,
+            inner,
+            vec![left_node.clone(), right_node.clone()],
+            vec![],
+        ))
     }
 
     fn make_projection_helper(
@@ -1327,6 +1737,7 @@ impl SqlToMirConverter {
             parent,
             vec![fn_col],
             vec![],
+            vec![],
             vec![(String::from("grp"), DataType::from(0 as i32))],
             false,
         )
@@ -1338,6 +1749,7 @@ impl SqlToMirConverter {
         parent_node: MirNodeRef,
         proj_cols: Vec<&Column>,
         arithmetic: Vec<(String, ArithmeticExpression)>,
+        expressions: Vec<(String, ProjectExpression, Vec<Column>)>,
         literals: Vec<(String, DataType)>,
         is_leaf: bool,
     ) -> MirNodeRef {
@@ -1346,6 +1758,7 @@ impl SqlToMirConverter {
         let names: Vec<String> = arithmetic
             .iter()
             .map(|&(ref n, _)| n.clone())
+            .chain(expressions.iter().map(|&(ref n, _, _)| n.clone()))
             .chain(literals.iter().map(|&(ref n, _)| n.clone()))
             .collect();
 
@@ -1380,41 +1793,60 @@ impl SqlToMirConverter {
                 emit: emit_cols,
                 literals,
                 arithmetic,
+                expressions,
             },
             vec![parent_node.clone()],
             vec![],
         )
     }
 
-    fn make_distinct_node(
+    fn make_topk_node(
         &self,
         name: &str,
         parent: MirNodeRef,
         group_by: Vec<&Column>,
+        order: &Option<OrderClause>,
+        k: usize,
     ) -> MirNodeRef {
         let combined_columns = parent.borrow().columns().to_vec();
 
+        let order = match *order {
+            Some(ref o) => Some(
+                o.columns
+                    .iter()
+                    .map(|(c, o)| (Column::from(c), o.clone()))
+                    .collect(),
+            ),
+            None => None,
+        };
+
         // make the new operator and record its metadata
         MirNode::new(
             name,
             self.schema_version,
             combined_columns,
-            MirNodeType::Distinct {
+            MirNodeType::TopK {
+                order,
                 group_by: group_by.into_iter().cloned().collect(),
+                k,
+                offset: 0,
             },
             vec![parent.clone()],
             vec![],
         )
     }
-    fn make_topk_node(
+
+    fn make_paginate_node(
         &self,
         name: &str,
         parent: MirNodeRef,
         group_by: Vec<&Column>,
         order: &Option<OrderClause>,
-        limit: &LimitClause,
+        page_size: usize,
+        offset: usize,
     ) -> MirNodeRef {
-        let combined_columns = parent.borrow().columns().to_vec();
+        let mut combined_columns = parent.borrow().columns().to_vec();
+        combined_columns.push(Column::new(None, PAGE_COLUMN));
 
         let order = match *order {
             Some(ref o) => Some(
@@ -1426,18 +1858,70 @@ impl SqlToMirConverter {
             None => None,
         };
 
-        assert_eq!(limit.offset, 0); // Non-zero offset not supported
-
         // make the new operator and record its metadata
         MirNode::new(
             name,
             self.schema_version,
             combined_columns,
-            MirNodeType::TopK {
+            MirNodeType::Paginate {
                 order,
                 group_by: group_by.into_iter().cloned().collect(),
-                k: limit.limit as usize,
-                offset: 0,
+                page_size,
+                offset,
+            },
+            vec![parent.clone()],
+            vec![],
+        )
+    }
+
+    /// Keeps only the records that a `Paginate` parent has assigned to the given page.
+    fn make_page_filter_node(&self, name: &str, parent: MirNodeRef, page: i64) -> MirNodeRef {
+        use dataflow::ops::filter;
+
+        let fields = parent.borrow().columns().to_vec();
+        let page_col = parent
+            .borrow()
+            .column_id_for_column(&Column::new(None, PAGE_COLUMN), None);
+        MirNode::new(
+            name,
+            self.schema_version,
+            fields,
+            MirNodeType::Filter {
+                conditions: vec![(
+                    page_col,
+                    FilterCondition::Comparison(
+                        Operator::Equal,
+                        filter::Value::Constant(DataType::from(page)),
+                    ),
+                )],
+            },
+            vec![parent.clone()],
+            vec![],
+        )
+    }
+
+    fn make_occurrence_filter_node(
+        &self,
+        name: &str,
+        parent: MirNodeRef,
+        occurrences: &Column,
+    ) -> MirNodeRef {
+        use dataflow::ops::filter;
+
+        let fields = parent.borrow().columns().to_vec();
+        let occurrences = parent.borrow().column_id_for_column(occurrences, None);
+        MirNode::new(
+            name,
+            self.schema_version,
+            fields,
+            MirNodeType::Filter {
+                conditions: vec![(
+                    occurrences,
+                    FilterCondition::Comparison(
+                        Operator::NotEqual,
+                        filter::Value::Constant(DataType::from(0)),
+                    ),
+                )],
             },
             vec![parent.clone()],
             vec![],
@@ -1450,71 +1934,53 @@ impl SqlToMirConverter {
         parent: MirNodeRef,
         ce: &ConditionExpression,
         nc: usize,
-    ) -> Vec<MirNodeRef> {
+    ) -> Result<Vec<MirNodeRef>, String> {
         use nom_sql::ConditionExpression::*;
 
         let mut pred_nodes: Vec<MirNodeRef> = Vec::new();
-        let output_cols = parent.borrow().columns().to_vec();
         match *ce {
             LogicalOp(ref ct) => {
-                let (left, right);
                 match ct.operator {
                     Operator::And => {
-                        left = self.make_predicate_nodes(name, parent.clone(), &*ct.left, nc);
+                        let left =
+                            self.make_predicate_nodes(name, parent.clone(), &*ct.left, nc)?;
 
-                        right = self.make_predicate_nodes(
+                        let right = self.make_predicate_nodes(
                             name,
                             left.last().unwrap().clone(),
                             &*ct.right,
                             nc + left.len(),
-                        );
+                        )?;
 
-                        pred_nodes.extend(left.clone());
-                        pred_nodes.extend(right.clone());
+                        pred_nodes.extend(left);
+                        pred_nodes.extend(right);
                     }
                     Operator::Or => {
-                        left = self.make_predicate_nodes(name, parent.clone(), &*ct.left, nc);
-
-                        right = self.make_predicate_nodes(
-                            name,
-                            parent.clone(),
-                            &*ct.right,
-                            nc + left.len(),
-                        );
-
-                        debug!(self.log, "Creating union node for `or` predicate");
-
-                        let last_left = left.last().unwrap().clone();
-                        let last_right = right.last().unwrap().clone();
-                        let union = self.make_union_from_same_base(
-                            &format!("{}_un", name),
-                            vec![last_left, last_right],
-                            output_cols,
-                        );
+                        // a disjunction is evaluated by a single filter, so that records that
+                        // satisfy both sides are only emitted once
+                        let f = self.make_filter_node(&format!("{}_f{}", name, nc), parent, ce)?;
 
-                        pred_nodes.extend(left.clone());
-                        pred_nodes.extend(right.clone());
-                        pred_nodes.push(union);
+                        pred_nodes.push(f);
                     }
-                    _ => unreachable!("LogicalOp operator is {:?}", ct.operator),
+                    ref op => return Err(format!("unsupported logical operator {}", op)),
                 }
             }
-            ComparisonOp(ref ct) => {
+            ComparisonOp(_) => {
                 // currently, we only support filter-like
                 // comparison operations, no nested-selections
-                let f = self.make_filter_node(&format!("{}_f{}", name, nc), parent, ct);
+                let f = self.make_filter_node(&format!("{}_f{}", name, nc), parent, ce)?;
 
                 pred_nodes.push(f);
             }
             Bracketed(ref inner) => {
-                pred_nodes.extend(self.make_predicate_nodes(name, parent, &*inner, nc));
+                pred_nodes.extend(self.make_predicate_nodes(name, parent, &*inner, nc)?);
             }
-            NegationOp(_) => unreachable!("negation should have been removed earlier"),
-            Base(_) => unreachable!("dangling base predicate"),
-            Arithmetic(_) => unimplemented!(),
+            NegationOp(_) => return Err("negated condition could not be rewritten".to_owned()),
+            Base(_) => return Err("condition is not a comparison".to_owned()),
+            Arithmetic(_) => return Err("arithmetic expression used as a condition".to_owned()),
         }
 
-        pred_nodes
+        Ok(pred_nodes)
     }
 
     fn predicates_above_group_by<'a>(
@@ -1524,7 +1990,7 @@ impl SqlToMirConverter {
         over_col: Column,
         parent: MirNodeRef,
         created_predicates: &mut Vec<&'a ConditionExpression>,
-    ) -> Vec<MirNodeRef> {
+    ) -> Result<Vec<MirNodeRef>, String> {
         let mut predicates_above_group_by_nodes = Vec::new();
         let mut prev_node = parent.clone();
 
@@ -1536,7 +2002,7 @@ impl SqlToMirConverter {
                     prev_node.clone(),
                     ce,
                     0,
-                );
+                )?;
                 assert!(!mpns.is_empty());
                 prev_node = mpns.last().unwrap().clone();
                 predicates_above_group_by_nodes.extend(mpns);
@@ -1544,7 +2010,7 @@ impl SqlToMirConverter {
             }
         }
 
-        predicates_above_group_by_nodes
+        Ok(predicates_above_group_by_nodes)
     }
 
     fn make_value_project_node(
@@ -1567,6 +2033,7 @@ impl SqlToMirConverter {
                         }
                         OutputColumn::Data(_) => None,
                         OutputColumn::Literal(_) => None,
+                        OutputColumn::Expression(_) => None,
                     })
                     .collect();
             let projected_literals: Vec<(String, DataType)> = arith_and_lit_columns_needed
@@ -1574,6 +2041,7 @@ impl SqlToMirConverter {
                 .filter_map(|&(_, ref oc)| match oc {
                     OutputColumn::Arithmetic(_) => None,
                     OutputColumn::Data(_) => None,
+                    OutputColumn::Expression(_) => None,
                     OutputColumn::Literal(ref lc) => {
                         Some((lc.name.clone(), DataType::from(&lc.value)))
                     }
@@ -1592,6 +2060,7 @@ impl SqlToMirConverter {
                 parent.clone(),
                 passthru_cols.iter().collect(),
                 projected_arithmetic,
+                vec![],
                 projected_literals,
                 false,
             );
@@ -1666,15 +2135,18 @@ impl SqlToMirConverter {
                 qg,
                 &node_for_rel,
                 new_node_count,
-            );
+            )?;
 
             new_node_count += join_nodes.len();
 
             let mut prev_node = match join_nodes.last() {
                 Some(n) => Some(n.clone()),
+                None if base_nodes.len() == 1 => Some(base_nodes.last().unwrap().clone()),
                 None => {
-                    assert_eq!(base_nodes.len(), 1);
-                    Some(base_nodes.last().unwrap().clone())
+                    return Err(format!(
+                        "query {} selects from several tables without joining them",
+                        name
+                    ));
                 }
             };
 
@@ -1719,7 +2191,7 @@ impl SqlToMirConverter {
                     new_node_count,
                     &column_to_predicates,
                     &mut prev_node,
-                );
+                )?;
 
             new_node_count += predicates_above_group_by_nodes.len();
 
@@ -1784,7 +2256,7 @@ impl SqlToMirConverter {
                     new_node_count,
                     &mut prev_node,
                     false,
-                );
+                )?;
 
                 new_node_count += func_nodes.len();
 
@@ -1827,7 +2299,7 @@ impl SqlToMirConverter {
                                 parent,
                                 p,
                                 0,
-                            );
+                            )?;
 
                             assert!(!fns.is_empty());
                             new_node_count += fns.len();
@@ -1856,7 +2328,9 @@ impl SqlToMirConverter {
                     }
 
                     let parent = match prev_node {
-                        None => unimplemented!(),
+                        None => {
+                            return Err(format!("no input for global predicate {}", p));
+                        }
                         Some(pn) => pn,
                     };
 
@@ -1871,7 +2345,7 @@ impl SqlToMirConverter {
                         parent,
                         p,
                         0,
-                    );
+                    )?;
 
                     assert!(!fns.is_empty());
                     new_node_count += fns.len();
@@ -1882,10 +2356,14 @@ impl SqlToMirConverter {
                 // 6. Get the final node
                 let mut final_node: MirNodeRef = if prev_node.is_some() {
                     prev_node.unwrap().clone()
-                } else {
+                } else if sorted_rels.len() == 1 {
                     // no join, filter, or function node --> base node is parent
-                    assert_eq!(sorted_rels.len(), 1);
                     node_for_rel[sorted_rels.last().unwrap()].clone()
+                } else {
+                    return Err(format!(
+                        "query {} selects from several tables without joining them",
+                        name
+                    ));
                 };
 
                 // 7. Potentially insert TopK node below the final node
@@ -1894,7 +2372,14 @@ impl SqlToMirConverter {
                 // queries (due to security universes or due to compound select queries) that do
                 // not all have the bogokey!
                 if let Some(ref limit) = st.limit {
-                    let group_by = if qg.parameters().is_empty() {
+                    // the page number is computed by the Paginate node, so it cannot be grouped on
+                    let group_params: Vec<Column> = qg
+                        .parameters()
+                        .into_iter()
+                        .filter(|&c| qg.page_parameter.as_ref() != Some(c))
+                        .map(Column::from)
+                        .collect();
+                    let group_by = if group_params.is_empty() {
                         // need to add another projection to introduce a bogokey to group by
                         let cols: Vec<_> = final_node.borrow().columns().to_vec();
                         let table =
@@ -1904,6 +2389,7 @@ impl SqlToMirConverter {
                             final_node.clone(),
                             cols.iter().collect(),
                             vec![],
+                            vec![],
                             vec![("bogokey".into(), DataType::from(0 as i32))],
                             false,
                         );
@@ -1913,19 +2399,68 @@ impl SqlToMirConverter {
 
                         vec![Column::new(None, "bogokey")]
                     } else {
-                        qg.parameters().into_iter().map(Column::from).collect()
+                        group_params
                     };
 
-                    let topk_node = self.make_topk_node(
-                        &format!("q_{:x}_n{}{}", qg.signature().hash, new_node_count, uformat),
-                        final_node,
-                        group_by.iter().collect(),
-                        &st.order,
-                        limit,
-                    );
-                    func_nodes.push(topk_node.clone());
-                    final_node = topk_node;
-                    new_node_count += 1;
+                    if qg.page_parameter.is_some() {
+                        // `OFFSET ?`: number the pages of results, and key the reader on the page
+                        let paginate_node = self.make_paginate_node(
+                            &format!("q_{:x}_n{}{}", qg.signature().hash, new_node_count, uformat),
+                            final_node,
+                            group_by.iter().collect(),
+                            &st.order,
+                            limit.limit as usize,
+                            0,
+                        );
+                        func_nodes.push(paginate_node.clone());
+                        final_node = paginate_node;
+                        new_node_count += 1;
+                    } else {
+                        let topk_node = self.make_topk_node(
+                            &format!("q_{:x}_n{}{}", qg.signature().hash, new_node_count, uformat),
+                            final_node,
+                            group_by.iter().collect(),
+                            &st.order,
+                            (limit.limit + limit.offset) as usize,
+                        );
+                        func_nodes.push(topk_node.clone());
+                        final_node = topk_node;
+                        new_node_count += 1;
+
+                        if limit.offset > 0 {
+                            // the TopK keeps the first `offset + limit` records; number them, and
+                            // keep only those that come after the offset
+                            let paginate_node = self.make_paginate_node(
+                                &format!(
+                                    "q_{:x}_n{}{}",
+                                    qg.signature().hash,
+                                    new_node_count,
+                                    uformat
+                                ),
+                                final_node,
+                                group_by.iter().collect(),
+                                &st.order,
+                                limit.limit as usize,
+                                limit.offset as usize,
+                            );
+                            func_nodes.push(paginate_node.clone());
+                            new_node_count += 1;
+
+                            let filter_node = self.make_page_filter_node(
+                                &format!(
+                                    "q_{:x}_n{}{}",
+                                    qg.signature().hash,
+                                    new_node_count,
+                                    uformat
+                                ),
+                                paginate_node,
+                                0,
+                            );
+                            func_nodes.push(filter_node.clone());
+                            final_node = filter_node;
+                            new_node_count += 1;
+                        }
+                    }
                 }
 
                 // we're now done with the query, so remember all the nodes we've added so far
@@ -1948,7 +2483,7 @@ impl SqlToMirConverter {
                     &ancestors,
                     new_node_count,
                     sec_round,
-                );
+                )?;
 
                 if sec_round {
                     table_mapping = tables;
@@ -1971,6 +2506,7 @@ impl SqlToMirConverter {
                         OutputColumn::Arithmetic(_) => None,
                         OutputColumn::Data(ref c) => Some(Column::from(c)),
                         OutputColumn::Literal(_) => None,
+                        OutputColumn::Expression(_) => None,
                     })
                     .collect()
             } else {
@@ -2007,6 +2543,19 @@ impl SqlToMirConverter {
                     }
                     OutputColumn::Data(_) => None,
                     OutputColumn::Literal(_) => None,
+                    OutputColumn::Expression(_) => None,
+                })
+                .collect();
+            let projected_expressions: Vec<(String, ProjectExpression, Vec<Column>)> = qg
+                .columns
+                .iter()
+                .filter_map(|oc| match *oc {
+                    OutputColumn::Expression(ref ec) => {
+                        let mut columns = Vec::new();
+                        let e = project_expression(&ec.expression, &mut columns);
+                        Some((ec.name.clone(), e, columns))
+                    }
+                    _ => None,
                 })
                 .collect();
             let mut projected_literals: Vec<(String, DataType)> = qg
@@ -2015,6 +2564,7 @@ impl SqlToMirConverter {
                 .filter_map(|oc| match *oc {
                     OutputColumn::Arithmetic(_) => None,
                     OutputColumn::Data(_) => None,
+                    OutputColumn::Expression(_) => None,
                     OutputColumn::Literal(ref lc) => {
                         if !already_computed.contains(oc) {
                             Some((lc.name.clone(), DataType::from(&lc.value)))
@@ -2048,6 +2598,7 @@ impl SqlToMirConverter {
                 final_node,
                 projected_columns.iter().collect(),
                 projected_arithmetic,
+                projected_expressions,
                 projected_literals,
                 !has_leaf,
             );
@@ -2081,6 +2632,11 @@ impl SqlToMirConverter {
                     MirNodeType::Leaf {
                         node: leaf_project_node.clone(),
                         keys: query_params,
+                        range: if has_bogokey {
+                            None
+                        } else {
+                            qg.range_parameter.as_ref().map(|&(_, range)| range)
+                        },
                     },
                     vec![leaf_project_node.clone()],
                     vec![],
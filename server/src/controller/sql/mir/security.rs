@@ -14,11 +14,14 @@ pub trait SecurityBoundary {
         ancestors: &[MirNodeRef],
         node_count: usize,
         sec: bool,
-    ) -> (
-        Vec<MirNodeRef>,
-        Option<HashMap<(String, Option<String>), String>>,
+    ) -> Result<
+        (
+            Vec<MirNodeRef>,
+            Option<HashMap<(String, Option<String>), String>>,
+            String,
+        ),
         String,
-    );
+    >;
 
     fn make_security_boundary(
         &self,
@@ -36,11 +39,14 @@ impl SecurityBoundary for SqlToMirConverter {
         ancestors: &[MirNodeRef],
         node_count: usize,
         sec: bool,
-    ) -> (
-        Vec<MirNodeRef>,
-        Option<HashMap<(String, Option<String>), String>>,
+    ) -> Result<
+        (
+            Vec<MirNodeRef>,
+            Option<HashMap<(String, Option<String>), String>>,
+            String,
+        ),
         String,
-    ) {
+    > {
         use crate::controller::sql::mir::grouped::make_grouped;
 
         let mut nodes_added = Vec::new();
@@ -54,11 +60,12 @@ impl SecurityBoundary for SqlToMirConverter {
         // First, union the results from all ancestors
         let (union, mapping) = if !sec {
             (
-                Some(self.make_union_node(&format!("{}_n{}", name, node_count), &ancestors)),
+                Some(self.make_union_node(&format!("{}_n{}", name, node_count), &ancestors)?),
                 None,
             )
         } else {
-            let (u, m) = self.make_union_node_sec(&format!("{}_n{}", name, node_count), &ancestors);
+            let (u, m) =
+                self.make_union_node_sec(&format!("{}_n{}", name, node_count), &ancestors)?;
             (Some(u), m)
         };
 
@@ -79,14 +86,12 @@ impl SecurityBoundary for SqlToMirConverter {
                     node_count,
                     &mut Some(node.clone()),
                     true,
-                );
+                )?;
 
                 nodes_added.extend(grouped);
-                (nodes_added, mapping, n)
-            }
-            None => {
-                panic!("union not computed correctly");
+                Ok((nodes_added, mapping, n))
             }
+            None => Err("union not computed correctly".to_owned()),
         }
     }
 
@@ -203,8 +208,7 @@ fn make_security_nodes(
             let qgn = qg
                 .relations
                 .get(*rel)
-                .expect("relation should have a query graph node.");
-            assert!(*rel != "computed_collumns");
+                .ok_or_else(|| format!("policy relation {} has no query graph node", rel))?;
 
             // Skip empty predicates
             if qgn.predicates.is_empty() {
@@ -214,17 +218,16 @@ fn make_security_nodes(
             for pred in &qgn.predicates {
                 let new_nodes = mir_converter.make_predicate_nodes(
                     &format!("sp_{:x}_n{:x}", qg.signature().hash, node_count),
-                    prev_node.expect("empty previous node"),
+                    prev_node.unwrap(),
                     pred,
                     0,
-                );
+                )?;
 
                 prev_node = Some(
                     new_nodes
-                        .iter()
                         .last()
-                        .expect("no new nodes were created")
-                        .clone(),
+                        .cloned()
+                        .ok_or_else(|| format!("no nodes were created for predicate {}", pred))?,
                 );
                 filter_nodes.extend(new_nodes);
             }
@@ -239,7 +242,7 @@ fn make_security_nodes(
             qg,
             &local_node_for_rel,
             node_count,
-        );
+        )?;
 
         node_count += join_nodes.len();
 
@@ -265,13 +268,12 @@ fn make_security_nodes(
             .chain(rewrite_nodes.into_iter())
             .collect();
 
-        assert!(
-            !policy_nodes.is_empty(),
-            "no nodes where created for policy"
-        );
-
-        security_nodes.extend(policy_nodes.clone());
-        last_policy_nodes.push(policy_nodes.last().unwrap().clone())
+        let last = policy_nodes
+            .last()
+            .cloned()
+            .ok_or_else(|| format!("no nodes were created for policy on {}", table))?;
+        security_nodes.extend(policy_nodes);
+        last_policy_nodes.push(last)
     }
 
     Ok((last_policy_nodes, security_nodes))
@@ -1,3 +1,6 @@
+mod expression;
+mod extensions;
+mod lexer;
 mod mir;
 mod passes;
 mod query_graph;
@@ -6,6 +9,11 @@ mod query_utils;
 mod reuse;
 pub(super) mod security;
 
+pub(crate) use self::expression::restore_expressions;
+use self::expression::{expand_expressions, Extracted};
+use self::extensions::SelectExtensions;
+pub(crate) use self::extensions::{take_extensions, Extensions};
+use self::lexer::{join, tokenize, Token};
 use self::mir::SqlToMirConverter;
 use self::query_graph::{to_query_graph, QueryGraph};
 use self::query_signature::Signature;
@@ -13,10 +21,13 @@ use self::reuse::ReuseConfig;
 use super::mir_to_flow::mir_query_to_flow_parts;
 use crate::controller::Migration;
 use crate::ReuseConfigType;
+use ::mir::node::MirNodeState;
+use ::mir::node::MirNodeType;
 use ::mir::query::{MirQuery, QueryFlowParts};
 use ::mir::reuse as mir_reuse;
 use ::mir::Column;
-use ::mir::MirNodeRef;
+use ::mir::{FlowNode, MirNodeRef};
+use dataflow::ops::grouped::aggregate::Aggregation;
 use dataflow::prelude::DataType;
 use nom_sql::parser as sql_parser;
 use nom_sql::{ArithmeticBase, CreateTableStatement, SqlQuery};
@@ -24,12 +35,370 @@ use nom_sql::{CompoundSelectOperator, CompoundSelectStatement, SelectStatement};
 use petgraph::graph::NodeIndex;
 
 use slog;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::rc::Rc;
 use std::str;
 use std::vec::Vec;
 
 type UniverseId = (DataType, Option<DataType>);
 
+/// Column that `expand_page_parameters` compares with the placeholder of an `OFFSET ?`, since
+/// nom-sql only accepts literal offsets. `take_extensions` takes the comparison out of the parsed
+/// query again, and marks the query as paginated: its reader is keyed on a page number.
+const PAGE_PARAMETER: &str = "__noria_page";
+
+/// Name of the page number column that paginated queries are keyed on.
+const PAGE_COLUMN: &str = "page";
+
+/// Aggregate functions that nom-sql cannot parse, along with the aggregation that each of them
+/// computes. `expand_aggregates` disguises a call to one of them as a `GROUP_CONCAT` whose
+/// separator names the function (see `aggregate_separator`), and `take_extensions` records the
+/// aggregation next to the parsed query.
+const DISGUISED_AGGREGATES: &[(&str, Aggregation)] = &[
+    ("variance", Aggregation::VARIANCE),
+    ("var_pop", Aggregation::VARIANCE),
+    ("stddev", Aggregation::STDDEV),
+    ("stddev_pop", Aggregation::STDDEV),
+    ("bool_and", Aggregation::BOOLAND),
+    ("bool_or", Aggregation::BOOLOR),
+];
+
+/// Returns the function that a `GROUP_CONCAT` with the given separator stands in for, along with
+/// the aggregation that it computes, if it was produced by `expand_aggregates`.
+fn disguised_aggregate(separator: &str) -> Option<(&'static str, Aggregation)> {
+    if !separator.starts_with(AGGREGATE_SEPARATOR_PREFIX) {
+        return None;
+    }
+    DISGUISED_AGGREGATES
+        .iter()
+        .find(|&&(f, _)| aggregate_separator(f) == separator)
+        .map(|&(f, ref agg)| (f, agg.clone()))
+}
+
+/// Prefix of the `GROUP_CONCAT` separators that `expand_aggregates` uses, which queries cannot
+/// use themselves. nom-sql only accepts alphanumeric separators.
+const AGGREGATE_SEPARATOR_PREFIX: &str = "NoriaAggregate";
+
+/// Returns the separator that `expand_aggregates` disguises a call to `function` with.
+fn aggregate_separator(function: &str) -> String {
+    format!(
+        "{}{}",
+        AGGREGATE_SEPARATOR_PREFIX,
+        function.replace('_', "")
+    )
+}
+
+/// Column that `expand_subqueries` compares with `IN` in place of an `EXISTS`.
+const EXISTS_MARKER: &str = "__noria_exists";
+
+/// Prefix of the aliases of the joins that scalar subqueries become when they are restored into a
+/// parsed query (see `restore_expressions`).
+const SCALAR_SUBQUERY_PREFIX: &str = "__noria_scalar_";
+
+/// Rewrites SQL that nom-sql cannot parse into equivalent SQL that it can: `OFFSET ?` (see
+/// `expand_page_parameters`), the aggregate functions in `DISGUISED_AGGREGATES` (see
+/// `expand_aggregates`), right and full outer joins (see `expand_joins`), the subqueries that
+/// `expand_subqueries` deals with, and the select list expressions and scalar subqueries that
+/// `expand_expressions` takes out. Those are returned along with the rewritten SQL, and must be put
+/// back into each query parsed from it with `restore_expressions`; the other rewrites leave
+/// placeholders that `take_extensions` then takes out of the query.
+pub(crate) fn expand_unparseable(sql: &str) -> Result<(String, Vec<Extracted>), String> {
+    let (sql, expressions) = expand_expressions(sql)?;
+    let sql = expand_aggregates(&expand_page_parameters(&expand_joins(
+        &expand_subqueries(&sql)?,
+    )?)?)?;
+    Ok((sql, expressions))
+}
+
+/// Splits `sql` into tokens, and returns them along with the indices of the significant ones and
+/// the nesting depth of each of those. Parentheses count as being outside of the parentheses that
+/// they open or close.
+fn tokenize_nested(sql: &str) -> Result<(Vec<Token>, Vec<usize>, Vec<i32>), String> {
+    let tokens = tokenize(sql)?;
+    let significant: Vec<usize> = (0..tokens.len())
+        .filter(|&i| tokens[i].is_significant())
+        .collect();
+    let mut depths = Vec::with_capacity(significant.len());
+    let mut depth = 0;
+    for &i in &significant {
+        if tokens[i].is_symbol(")") {
+            depth -= 1;
+        }
+        depths.push(depth);
+        if tokens[i].is_symbol("(") {
+            depth += 1;
+        }
+    }
+    if depth != 0 {
+        return Err("unbalanced parentheses".to_owned());
+    }
+    Ok((tokens, significant, depths))
+}
+
+/// Concatenates the text of `tokens`, leaving out the tokens whose indices are in `removed`, and
+/// putting the text in `insertions` before the tokens at its indices.
+fn rewrite(
+    tokens: &[Token],
+    insertions: &HashMap<usize, String>,
+    removed: &HashSet<usize>,
+) -> String {
+    let mut rewritten = String::new();
+    for (i, t) in tokens.iter().enumerate() {
+        if let Some(text) = insertions.get(&i) {
+            rewritten.push_str(text);
+        }
+        if !removed.contains(&i) {
+            rewritten.push_str(t.text);
+        }
+    }
+    rewritten
+}
+
+/// Returns true if the `k`th significant token opens a subquery.
+fn opens_subquery(tokens: &[Token], significant: &[usize], k: usize) -> bool {
+    let token = |k: usize| significant.get(k).map(|&i| &tokens[i]);
+    token(k).map_or(false, |t| t.is_symbol("("))
+        && token(k + 1).map_or(false, |t| t.is_keyword("select"))
+}
+
+/// Rewrites the subqueries in `sql` that nom-sql cannot parse into forms that it can:
+///
+///  - `EXISTS (SELECT ...)` becomes an `IN` comparison of the `EXISTS_MARKER` column with the
+///    subquery.
+///  - `x NOT IN (SELECT ...)` becomes `NOT x IN (SELECT ...)`.
+///
+/// Only the syntax changes here; the subqueries are decorrelated into joins once they have been
+/// parsed (see `passes::subqueries`). Scalar subqueries in select lists are taken out by
+/// `expand_expressions` instead.
+fn expand_subqueries(sql: &str) -> Result<String, String> {
+    expand_negated_subqueries(&expand_exists(sql)?)
+}
+
+fn expand_exists(sql: &str) -> Result<String, String> {
+    let (tokens, significant, _) = tokenize_nested(sql)?;
+    let mut insertions = HashMap::new();
+    let mut removed = HashSet::new();
+    for (k, &i) in significant.iter().enumerate() {
+        if tokens[i].is_keyword("exists") && opens_subquery(&tokens, &significant, k + 1) {
+            insertions.insert(i, format!("{} IN", EXISTS_MARKER));
+            removed.insert(i);
+        }
+    }
+    Ok(rewrite(&tokens, &insertions, &removed))
+}
+
+fn expand_negated_subqueries(sql: &str) -> Result<String, String> {
+    let (tokens, significant, _) = tokenize_nested(sql)?;
+    let is_column = |k: usize| tokens[significant[k]].identifier().is_some();
+    let mut insertions: HashMap<usize, String> = HashMap::new();
+    let mut removed = HashSet::new();
+    for k in 1..significant.len() {
+        let negated_in = tokens[significant[k]].is_keyword("not")
+            && significant
+                .get(k + 1)
+                .map_or(false, |&i| tokens[i].is_keyword("in"))
+            && opens_subquery(&tokens, &significant, k + 2);
+        if !negated_in || !is_column(k - 1) {
+            continue;
+        }
+
+        // the operand is the (possibly qualified) column that precedes the NOT
+        let mut operand = k - 1;
+        while operand >= 2
+            && tokens[significant[operand - 1]].is_symbol(".")
+            && is_column(operand - 2)
+        {
+            operand -= 2;
+        }
+        insertions
+            .entry(significant[operand])
+            .or_default()
+            .push_str("NOT ");
+        removed.extend(significant[k]..significant[k + 1]);
+    }
+    Ok(rewrite(&tokens, &insertions, &removed))
+}
+
+/// Rewrites `RIGHT [OUTER] JOIN` and `FULL [OUTER] JOIN` in `sql` into `INNER JOIN` and
+/// `CROSS JOIN` respectively, which `take_extensions` takes back out of the parsed query and
+/// records as right and full outer joins. Both of those (and `STRAIGHT_JOIN`) are plain inner
+/// joins in MySQL, so any that `sql` contains to begin with become `JOIN`s.
+fn expand_joins(sql: &str) -> Result<String, String> {
+    let tokens = tokenize(sql)?;
+    let significant: Vec<usize> = (0..tokens.len())
+        .filter(|&i| tokens[i].is_significant())
+        .collect();
+    let keyword = |k: usize, kw: &str| {
+        significant
+            .get(k)
+            .map_or(false, |&i| tokens[i].is_keyword(kw))
+    };
+
+    // the operator each join starts at, along with how many significant tokens it spans
+    let mut operators = HashMap::new();
+    let mut k = 0;
+    while k < significant.len() {
+        let (replacement, len) =
+            if (keyword(k, "cross") || keyword(k, "inner")) && keyword(k + 1, "join") {
+                ("join", 2)
+            } else if keyword(k, "straight_join") {
+                ("join", 1)
+            } else if keyword(k, "right") || keyword(k, "full") {
+                let len = if keyword(k + 1, "outer") { 3 } else { 2 };
+                if !keyword(k + len - 1, "join") {
+                    k += 1;
+                    continue;
+                }
+                if keyword(k, "right") {
+                    ("inner join", len)
+                } else {
+                    ("cross join", len)
+                }
+            } else {
+                k += 1;
+                continue;
+            };
+        operators.insert(significant[k], (replacement, significant[k + len - 1]));
+        k += len;
+    }
+
+    let mut expanded = String::with_capacity(sql.len());
+    let mut i = 0;
+    while i < tokens.len() {
+        match operators.get(&i) {
+            Some(&(replacement, last)) => {
+                expanded.push_str(replacement);
+                i = last + 1;
+            }
+            None => {
+                expanded.push_str(tokens[i].text);
+                i += 1;
+            }
+        }
+    }
+    Ok(expanded)
+}
+
+/// Rewrites every call to one of the `DISGUISED_AGGREGATES` in `sql` into a `GROUP_CONCAT` over
+/// the same arguments, using a separator that names the function (see `disguised_aggregate`).
+/// Those separators are reserved, so queries cannot use them in a `GROUP_CONCAT` of their own.
+fn expand_aggregates(sql: &str) -> Result<String, String> {
+    let tokens = tokenize(sql)?;
+    let next_significant = |from: usize| (from..tokens.len()).find(|&i| tokens[i].is_significant());
+
+    let mut expanded = String::with_capacity(sql.len());
+    let mut i = 0;
+    while i < tokens.len() {
+        let t = &tokens[i];
+        if t.is_keyword("separator") {
+            let reserved = next_significant(i + 1)
+                .and_then(|j| tokens[j].string_value())
+                .map_or(false, |sep| sep.starts_with(AGGREGATE_SEPARATOR_PREFIX));
+            if reserved {
+                return Err(format!(
+                    "GROUP_CONCAT separators starting with {} are reserved",
+                    AGGREGATE_SEPARATOR_PREFIX
+                ));
+            }
+        }
+
+        let function = DISGUISED_AGGREGATES
+            .iter()
+            .map(|&(f, _)| f)
+            .find(|f| t.is_keyword(f));
+        let open = next_significant(i + 1).filter(|&j| tokens[j].is_symbol("("));
+        let (function, open) = match (function, open) {
+            (Some(function), Some(open)) => (function, open),
+            _ => {
+                expanded.push_str(t.text);
+                i += 1;
+                continue;
+            }
+        };
+
+        let mut depth = 0;
+        let close = (open + 1..tokens.len())
+            .find(|&j| {
+                if tokens[j].is_symbol("(") {
+                    depth += 1;
+                } else if tokens[j].is_symbol(")") {
+                    if depth == 0 {
+                        return true;
+                    }
+                    depth -= 1;
+                }
+                false
+            })
+            .ok_or_else(|| format!("unterminated call to {}", function))?;
+        // nom-sql does not accept any space between SEPARATOR and the separator
+        expanded.push_str(&format!(
+            "group_concat({} separator'{}')",
+            join(&tokens[open + 1..close]),
+            aggregate_separator(function)
+        ));
+        i = close + 1;
+    }
+    Ok(expanded)
+}
+
+/// Rewrites every `OFFSET ?` in `sql` into a comparison of the `PAGE_PARAMETER` column with a
+/// placeholder, which is conjoined with the rest of the query's `WHERE` clause until
+/// `take_extensions` takes it out again.
+fn expand_page_parameters(sql: &str) -> Result<String, String> {
+    let (tokens, significant, depths) = tokenize_nested(sql)?;
+    let mut insertions: HashMap<usize, String> = HashMap::new();
+    let mut removed = HashSet::new();
+    for k in 0..significant.len() {
+        let is_page = tokens[significant[k]].is_keyword("offset")
+            && significant
+                .get(k + 1)
+                .map_or(false, |&i| tokens[i].is_symbol("?"));
+        if !is_page {
+            continue;
+        }
+        if depths[k] != 0 {
+            return Err("OFFSET ? is only supported in the outermost query".to_owned());
+        }
+
+        // the keywords of the statement that the OFFSET belongs to
+        let start = (0..k)
+            .rev()
+            .find(|&j| depths[j] == 0 && tokens[significant[j]].is_symbol(";"))
+            .map_or(0, |j| j + 1);
+        let keyword = |kws: &[&str], from: usize| {
+            (from..k).find(|&j| {
+                depths[j] == 0 && kws.iter().any(|kw| tokens[significant[j]].is_keyword(kw))
+            })
+        };
+        if keyword(&["union"], start).is_some() {
+            return Err("OFFSET ? is not supported on compound SELECT queries".to_owned());
+        }
+        let select = keyword(&["select"], start)
+            .ok_or_else(|| "OFFSET ? outside of a SELECT query".to_owned())?;
+        let condition_end = keyword(&["group", "order", "limit"], select)
+            .ok_or_else(|| "OFFSET ? without a LIMIT".to_owned())?;
+        let page = format!("{} = ?", PAGE_PARAMETER);
+        match keyword(&["where"], select).filter(|&w| w < condition_end) {
+            Some(w) => {
+                insertions.entry(significant[w + 1]).or_default().push('(');
+                insertions
+                    .entry(significant[condition_end])
+                    .or_default()
+                    .push_str(&format!(") AND {} ", page));
+            }
+            None => {
+                insertions
+                    .entry(significant[condition_end])
+                    .or_default()
+                    .push_str(&format!("WHERE {} ", page));
+            }
+        }
+        removed.extend(significant[k]..=significant[k + 1]);
+    }
+    Ok(rewrite(&tokens, &insertions, &removed))
+}
+
 #[derive(Clone, Debug)]
 enum QueryGraphReuse {
     ExactMatch(MirNodeRef),
@@ -39,6 +408,38 @@ enum QueryGraphReuse {
     None,
 }
 
+/// A change to a `SqlIncorporator`, along with what it replaced, as recorded while a savepoint
+/// is held.
+#[derive(Clone, Debug)]
+enum Undo {
+    BaseMirQuery(String, Option<MirQuery>),
+    BaseSchema(String, Option<CreateTableStatement>),
+    LeafAddress(String, Option<NodeIndex>),
+    MirQuery((u64, UniverseId), Option<MirQuery>),
+    NamedQuery(String, Option<u64>),
+    QueryGraph(u64, Option<QueryGraph>),
+    SchemaVersion(usize),
+    ViewSchema(String, Option<Vec<String>>),
+}
+
+/// Puts `prior`, the value that `key` had in `map` before it was changed, back into `map`.
+fn restore<K: Eq + Hash, V>(map: &mut HashMap<K, V>, key: K, prior: Option<V>) {
+    match prior {
+        Some(v) => map.insert(key, v),
+        None => map.remove(&key),
+    };
+}
+
+/// The state of a `SqlIncorporator` at the time `SqlIncorporator::savepoint` was called, which
+/// `SqlIncorporator::rollback` returns it to.
+///
+/// The incorporator itself records how it changes from there on; the savepoint holds the state of
+/// the MIR nodes that existed, since new queries can change those by building on top of them.
+pub(super) struct Savepoint {
+    num_queries: usize,
+    mir_nodes: Vec<(MirNodeRef, MirNodeState)>,
+}
+
 /// Long-lived struct that holds information about the SQL queries that have been incorporated into
 /// the Soup graph `grap`.
 /// The incorporator shares the lifetime of the flow graph it is associated with.
@@ -65,6 +466,9 @@ pub(crate) struct SqlIncorporator {
     /// Active universes mapped to the group they belong to.
     /// If an user universe, mapped to None.
     universes: HashMap<Option<DataType>, Vec<UniverseId>>,
+
+    /// Changes made since the last `savepoint`, so that `rollback` can undo them
+    journal: Option<Vec<Undo>>,
 }
 
 impl Default for SqlIncorporator {
@@ -87,6 +491,7 @@ impl Default for SqlIncorporator {
 
             reuse_type: ReuseConfigType::Finkelstein,
             universes: HashMap::default(),
+            journal: None,
         }
     }
 }
@@ -142,14 +547,93 @@ impl SqlIncorporator {
     pub(super) fn add_parsed_query(
         &mut self,
         query: SqlQuery,
+        ext: Extensions,
         name: Option<String>,
         is_leaf: bool,
         mig: &mut Migration,
     ) -> Result<QueryFlowParts, String> {
         match name {
-            None => self.nodes_for_query(query, is_leaf, mig),
-            Some(n) => self.nodes_for_named_query(query, n, is_leaf, mig),
+            None => self.nodes_for_query(query, ext, is_leaf, mig),
+            Some(n) => self.nodes_for_named_query(query, ext, n, is_leaf, mig),
+        }
+    }
+
+    /// Starts recording changes to the incorporator, so that they can be undone by passing the
+    /// returned savepoint to `rollback`, or kept by passing it to `release`.
+    pub(super) fn savepoint(&mut self) -> Savepoint {
+        let mir_nodes = self
+            .all_mir_nodes()
+            .into_iter()
+            .map(|n| {
+                let state = n.borrow_mut().save_state();
+                (n, state)
+            })
+            .collect();
+
+        self.journal = Some(Vec::new());
+        self.mir_converter.start_journal();
+        Savepoint {
+            num_queries: self.num_queries,
+            mir_nodes,
+        }
+    }
+
+    /// Keeps the changes made since `sp` was taken.
+    pub(super) fn release(&mut self, sp: Savepoint) {
+        drop(sp);
+        self.journal = None;
+        self.mir_converter.release_journal();
+    }
+
+    /// Undoes the changes made since `sp` was taken.
+    pub(super) fn rollback(&mut self, sp: Savepoint) {
+        for undo in self.journal.take().unwrap_or_default().into_iter().rev() {
+            match undo {
+                Undo::BaseMirQuery(k, prior) => restore(&mut self.base_mir_queries, k, prior),
+                Undo::BaseSchema(k, prior) => restore(&mut self.base_schemas, k, prior),
+                Undo::LeafAddress(k, prior) => restore(&mut self.leaf_addresses, k, prior),
+                Undo::MirQuery(k, prior) => restore(&mut self.mir_queries, k, prior),
+                Undo::NamedQuery(k, prior) => restore(&mut self.named_queries, k, prior),
+                Undo::QueryGraph(k, prior) => restore(&mut self.query_graphs, k, prior),
+                Undo::SchemaVersion(v) => self.schema_version = v,
+                Undo::ViewSchema(k, prior) => restore(&mut self.view_schemas, k, prior),
+            }
+        }
+        self.mir_converter.rollback();
+        self.num_queries = sp.num_queries;
+        for (node, state) in sp.mir_nodes {
+            node.borrow_mut().restore_state(state);
+        }
+    }
+
+    fn record(&mut self, undo: Undo) {
+        if let Some(ref mut journal) = self.journal {
+            journal.push(undo);
+        }
+    }
+
+    /// All MIR nodes of all queries, each listed once.
+    fn all_mir_nodes(&self) -> Vec<MirNodeRef> {
+        let mut seen = HashSet::new();
+        let mut nodes = Vec::new();
+        let mut stack: Vec<_> = self
+            .mir_converter
+            .nodes()
+            .chain(
+                self.mir_queries
+                    .values()
+                    .chain(self.base_mir_queries.values())
+                    .flat_map(|mq| mq.roots.iter().chain(Some(&mq.leaf))),
+            )
+            .cloned()
+            .collect();
+        while let Some(n) = stack.pop() {
+            if seen.insert(Rc::as_ptr(&n)) {
+                stack.extend(n.borrow().children().iter().cloned());
+                nodes.push(n);
+            }
         }
+        nodes
     }
 
     pub(super) fn get_base_schema(&self, name: &str) -> Option<CreateTableStatement> {
@@ -185,25 +669,131 @@ impl SqlIncorporator {
             .collect()
     }
 
+    /// Builds the nodes at the addresses in `affected` again, so that the new nodes can take over
+    /// from them, and returns the address of the node that takes over from each of them.
+    ///
+    /// If `replace` holds the address of a base node along with that of a base node added to
+    /// take over from it, the queries over the old base are built again over the new one. Nodes
+    /// that the queries they are part of cannot be built again for are left as they are.
+    pub(super) fn rebuild_nodes(
+        &mut self,
+        affected: &HashSet<NodeIndex>,
+        replace: Option<(NodeIndex, NodeIndex)>,
+        mig: &mut Migration,
+    ) -> HashMap<NodeIndex, NodeIndex> {
+        let nodes = self.all_mir_nodes();
+
+        // point the MIR nodes of the old base at the new one, and forget where every other
+        // affected node was built, so that lowering the queries again builds it anew
+        let mut cleared = Vec::new();
+        for n in &nodes {
+            let mut node = n.borrow_mut();
+            let addr = match node.flow_node {
+                Some(ref f) => f.address(),
+                None => continue,
+            };
+            match replace {
+                Some((old, new)) if addr == old => {
+                    if let MirNodeType::Base { .. } = node.inner {
+                        node.flow_node = Some(FlowNode::Existing(new));
+                    }
+                }
+                _ if affected.contains(&addr) => {
+                    cleared.push((n.clone(), node.flow_node.take().unwrap()));
+                }
+                _ => {}
+            }
+        }
+
+        // a query can only be lowered once the nodes it reuses from other queries are
+        // lowered, so keep going over the queries until no more can be lowered
+        let unbuilt = |n: &MirNodeRef| n.borrow().flow_node.is_none();
+        let mut keys: Vec<_> = self.mir_queries.keys().cloned().collect();
+        keys.sort_by(|a, b| self.mir_queries[a].name.cmp(&self.mir_queries[b].name));
+        loop {
+            let mut progress = false;
+            for k in &keys {
+                let mut mq = self.mir_queries[k].clone();
+                let mut reachable = Vec::new();
+                let mut seen = HashSet::new();
+                let mut stack = mq.roots.clone();
+                while let Some(n) = stack.pop() {
+                    if seen.insert(Rc::as_ptr(&n)) {
+                        stack.extend(n.borrow().children().iter().cloned());
+                        reachable.push(n);
+                    }
+                }
+                if !reachable.iter().any(|n| unbuilt(n)) {
+                    continue;
+                }
+                let ready =
+                    reachable
+                        .iter()
+                        .filter(|n| unbuilt(n))
+                        .all(|n| match n.borrow().inner {
+                            MirNodeType::Reuse { ref node } => !unbuilt(node),
+                            _ => true,
+                        });
+                if ready {
+                    debug!(self.log, "rebuilding query"; "name" => &mq.name);
+                    mir_query_to_flow_parts(&mut mq, mig, None);
+                    progress = true;
+                }
+            }
+            if !progress {
+                break;
+            }
+        }
+
+        let mut rebuilt = HashMap::new();
+        for (n, old) in cleared {
+            let mut node = n.borrow_mut();
+            match node.flow_node {
+                Some(ref new) => {
+                    rebuilt.insert(old.address(), new.address());
+                }
+                None => {
+                    warn!(self.log, "could not rebuild node"; "node" => node.versioned_name());
+                    node.flow_node = Some(old);
+                }
+            }
+        }
+
+        let moved: Vec<_> = self
+            .leaf_addresses
+            .iter()
+            .filter_map(|(name, &ni)| {
+                let new = match replace {
+                    Some((old, new)) if old == ni => Some(new),
+                    _ => rebuilt.get(&ni).cloned(),
+                };
+                new.map(|new| (name.clone(), ni, new))
+            })
+            .collect();
+        for (name, old, new) in moved {
+            self.leaf_addresses.insert(name.clone(), new);
+            self.record(Undo::LeafAddress(name, Some(old)));
+        }
+        rebuilt
+    }
+
     fn consider_query_graph(
         &mut self,
         query_name: &str,
         universe: UniverseId,
         st: &SelectStatement,
-    ) -> (QueryGraph, QueryGraphReuse) {
+        ext: &SelectExtensions,
+    ) -> Result<(QueryGraph, QueryGraphReuse), String> {
         debug!(self.log, "Making QG for \"{}\"", query_name);
         trace!(self.log, "Query \"{}\": {:#?}", query_name, st);
 
-        let mut qg = match to_query_graph(st) {
-            Ok(qg) => qg,
-            Err(e) => panic!(e),
-        };
+        let mut qg = to_query_graph(st, ext)?;
 
         trace!(self.log, "QG for \"{}\": {:#?}", query_name, qg);
 
         // if reuse is disabled, we're done
         if self.reuse_type == ReuseConfigType::NoReuse {
-            return (qg, QueryGraphReuse::None);
+            return Ok((qg, QueryGraphReuse::None));
         }
 
         // Do we already have this exact query or a subset of it in the same universe?
@@ -238,9 +828,13 @@ impl SqlIncorporator {
                         existing_qg,
                     );
 
-                    return (qg, QueryGraphReuse::ExactMatch(mir_query.leaf.clone()));
+                    return Ok((qg, QueryGraphReuse::ExactMatch(mir_query.leaf.clone())));
                 } else if existing_qg.signature() == qg.signature()
                     && existing_qg.parameters() != qg.parameters()
+                    && existing_qg.range_parameter.is_none()
+                    && qg.range_parameter.is_none()
+                    && existing_qg.limit.is_none()
+                    && qg.limit.is_none()
                 {
                     use self::query_graph::OutputColumn;
 
@@ -282,6 +876,7 @@ impl SqlIncorporator {
                             !is_function
                         }
                         OutputColumn::Data(ref dc) => dc.function.is_none(),
+                        OutputColumn::Expression(_) => true,
                     });
 
                     if predicates_match && no_grouped_columns {
@@ -331,10 +926,10 @@ impl SqlIncorporator {
                                     Some(project_columns)
                                 }
                             };
-                            return (
+                            return Ok((
                                 qg,
                                 QueryGraphReuse::ReaderOntoExisting(mn, project_columns, params),
-                            );
+                            ));
                         }
                     }
                 }
@@ -372,12 +967,12 @@ impl SqlIncorporator {
                 mir_queries.extend(mqs);
             }
 
-            return (qg, QueryGraphReuse::ExtendExisting(mir_queries));
+            return Ok((qg, QueryGraphReuse::ExtendExisting(mir_queries)));
         } else {
             info!(self.log, "No reuse opportunity, adding fresh query");
         }
 
-        (qg, QueryGraphReuse::None)
+        Ok((qg, QueryGraphReuse::None))
     }
 
     fn add_leaf_to_existing_query(
@@ -413,9 +1008,14 @@ impl SqlIncorporator {
         query_name: &str,
         query: &SqlQuery,
         mut mig: &mut Migration,
-    ) -> QueryFlowParts {
+    ) -> Result<QueryFlowParts, String> {
+        let ctq = match *query {
+            SqlQuery::CreateTable(ref ctq) => ctq,
+            ref q => return Err(format!("not a base table definition: {}", q)),
+        };
+
         // first, compute the MIR representation of the SQL query
-        let mut mir = self.mir_converter.named_base_to_mir(query_name, query);
+        let mut mir = self.mir_converter.named_base_to_mir(query_name, query)?;
 
         trace!(self.log, "Base node MIR: {:#?}", mir);
 
@@ -428,33 +1028,42 @@ impl SqlIncorporator {
         // on base table schema change, we will overwrite the existing schema here.
         // TODO(malte): this means that requests for this will always return the *latest* schema
         // for a base.
-        if let SqlQuery::CreateTable(ref ctq) = query {
-            self.base_schemas.insert(query_name.to_owned(), ctq.clone());
-        } else {
-            unimplemented!();
-        }
+        let prior = self.base_schemas.insert(query_name.to_owned(), ctq.clone());
+        self.record(Undo::BaseSchema(query_name.to_owned(), prior));
 
         self.register_query(query_name, None, &mir, mig.universe());
 
-        qfp
+        Ok(qfp)
     }
 
     fn add_compound_query(
         &mut self,
         query_name: &str,
         query: &CompoundSelectStatement,
+        ext: &Extensions,
         is_leaf: bool,
         mut mig: &mut Migration,
     ) -> Result<QueryFlowParts, String> {
+        if query.limit.as_ref().map_or(false, |l| l.offset != 0) {
+            return Err(String::from(
+                "OFFSET is not supported on compound SELECT queries",
+            ));
+        }
+
         let subqueries: Result<Vec<_>, String> = query
             .selects
             .iter()
             .enumerate()
             .map(|(i, sq)| {
-                Ok(self
-                    .add_select_query(&format!("{}_csq_{}", query_name, i), &sq.1, false, mig)?
+                let name = format!("{}_csq_{}", query_name, i);
+                self.add_select_query(&name, &sq.1, &ext.select(i), false, mig)?
                     .1
-                    .unwrap())
+                    .ok_or_else(|| {
+                        format!(
+                            "subquery {} of compound query {} reuses an existing query",
+                            i, query_name
+                        )
+                    })
             })
             .collect();
 
@@ -465,7 +1074,7 @@ impl SqlIncorporator {
             &query.order,
             &query.limit,
             is_leaf,
-        );
+        )?;
 
         let qfp = mir_query_to_flow_parts(&mut combined_mir_query, &mut mig, None);
 
@@ -480,10 +1089,11 @@ impl SqlIncorporator {
         &mut self,
         query_name: &str,
         sq: &SelectStatement,
+        ext: &SelectExtensions,
         is_leaf: bool,
         mig: &mut Migration,
     ) -> Result<(QueryFlowParts, Option<MirQuery>), String> {
-        let (qg, reuse) = self.consider_query_graph(&query_name, mig.universe(), sq);
+        let (qg, reuse) = self.consider_query_graph(&query_name, mig.universe(), sq, ext)?;
         Ok(match reuse {
             QueryGraphReuse::ExactMatch(mn) => {
                 let flow_node = mn.borrow().flow_node.as_ref().unwrap().address();
@@ -552,7 +1162,9 @@ impl SqlIncorporator {
                     mir = mir.make_universe_naming_consistent(x, base_name);
                 }
                 None => {
-                    panic!("Missing table mapping when reconciling universe table names!");
+                    return Err(String::from(
+                        "missing table mapping when reconciling universe table names",
+                    ));
                 }
             }
         }
@@ -566,70 +1178,80 @@ impl SqlIncorporator {
         Ok((qfp, mir))
     }
 
-    pub(super) fn remove_query(&mut self, query_name: &str, mig: &Migration) -> Option<NodeIndex> {
-        let nodeid = self
-            .leaf_addresses
-            .remove(query_name)
-            .expect("tried to remove unknown query");
-
+    /// Checks that `query_name` names a query that `remove_query` can remove, without changing
+    /// anything.
+    pub(super) fn check_removal(&self, query_name: &str, mig: &Migration) -> Result<(), String> {
+        if !self.leaf_addresses.contains_key(query_name) {
+            return Err(format!("tried to remove unknown query \"{}\"", query_name));
+        }
         let qg_hash = self
             .named_queries
-            .remove(query_name)
-            .unwrap_or_else(|| panic!("missing query hash for named query \"{}\"", query_name));
-        let mir = &self.mir_queries[&(qg_hash, mig.universe())];
-
-        // traverse self.leaf__addresses
-        if self
-            .leaf_addresses
-            .values()
-            .find(|&id| *id == nodeid)
-            .is_none()
-        {
-            // ok to remove
-
-            // remove local state for query
-
-            // traverse and remove MIR nodes
-            // TODO(malte): implement this
-            self.mir_converter.remove_query(query_name, mir);
-
-            // clean up local state
-            self.mir_queries.remove(&(qg_hash, mig.universe())).unwrap();
-            self.query_graphs.remove(&qg_hash).unwrap();
-            self.view_schemas.remove(query_name).unwrap();
+            .get(query_name)
+            .ok_or_else(|| format!("missing query hash for named query \"{}\"", query_name))?;
+        let key = (*qg_hash, mig.universe());
+        let mir = self
+            .mir_queries
+            .get(&key)
+            .ok_or_else(|| format!("missing MIR for named query \"{}\"", query_name))?;
+        if !self.query_graphs.contains_key(qg_hash) || !self.view_schemas.contains_key(query_name) {
+            return Err(format!("missing state for named query \"{}\"", query_name));
+        }
+        self.mir_converter.check_removal(query_name, mir)
+    }
 
-            // trigger reader node removal
-            Some(nodeid)
+    /// Removes the query named `query_name`, returning the address of its leaf node if no other
+    /// query uses it, in which case the caller should remove the node from the graph.
+    pub(super) fn remove_query(
+        &mut self,
+        query_name: &str,
+        mig: &Migration,
+    ) -> Result<Option<NodeIndex>, String> {
+        self.check_removal(query_name, mig)?;
+
+        let nodeid = self.leaf_addresses.remove(query_name).unwrap();
+        self.record(Undo::LeafAddress(query_name.to_owned(), Some(nodeid)));
+        let qg_hash = self.named_queries.remove(query_name).unwrap();
+        self.record(Undo::NamedQuery(query_name.to_owned(), Some(qg_hash)));
+
+        // traverse and remove MIR nodes
+        let key = (qg_hash, mig.universe());
+        self.mir_converter
+            .remove_query(query_name, &self.mir_queries[&key])?;
+        let mir = self.mir_queries.remove(&key);
+        self.record(Undo::MirQuery(key, mir));
+
+        // clean up local state
+        let qg = self.query_graphs.remove(&qg_hash);
+        self.record(Undo::QueryGraph(qg_hash, qg));
+        let schema = self.view_schemas.remove(query_name);
+        self.record(Undo::ViewSchema(query_name.to_owned(), schema));
+
+        if self.leaf_addresses.values().any(|id| *id == nodeid) {
+            // more than one query uses this leaf; don't remove node yet!
+            Ok(None)
         } else {
-            // more than one query uses this leaf
-            // don't remove node yet!
-
-            // TODO(malte): implement this
-            self.mir_converter.remove_query(query_name, mir);
-
-            // clean up state for this query
-            self.mir_queries.remove(&(qg_hash, mig.universe())).unwrap();
-            self.query_graphs.remove(&qg_hash).unwrap();
-            self.view_schemas.remove(query_name).unwrap();
-
-            None
+            // ok to remove; trigger reader node removal
+            Ok(Some(nodeid))
         }
     }
 
-    pub(super) fn remove_base(&mut self, name: &str) {
+    pub(super) fn remove_base(&mut self, name: &str) -> Result<(), String> {
         info!(self.log, "Removing base {} from SqlIncorporator", name);
-        if self.base_schemas.remove(name).is_none() {
+        let mir = self
+            .base_mir_queries
+            .get(name)
+            .ok_or_else(|| format!("tried to remove unknown base {}", name))?;
+        self.mir_converter.remove_base(name, mir)?;
+
+        let prior = self.base_schemas.remove(name);
+        if prior.is_none() {
             warn!(
                 self.log,
                 "Attempted to remove non-existant base node {} from SqlIncorporator", name
             );
         }
-
-        let mir = self
-            .base_mir_queries
-            .get(name)
-            .unwrap_or_else(|| panic!("tried to remove unknown base {}", name));
-        self.mir_converter.remove_base(name, mir)
+        self.record(Undo::BaseSchema(name.to_owned(), prior));
+        Ok(())
     }
 
     fn register_query(
@@ -651,7 +1273,8 @@ impl SqlIncorporator {
 
         // TODO(malte): get rid of duplication and figure out where to track this state
         debug!(self.log, "registering query \"{}\"", query_name);
-        self.view_schemas.insert(String::from(query_name), fields);
+        let prior = self.view_schemas.insert(String::from(query_name), fields);
+        self.record(Undo::ViewSchema(query_name.to_owned(), prior));
 
         // We made a new query, so store the query graph and the corresponding leaf MIR node.
         // TODO(malte): we currently store nothing if there is no QG (e.g., for compound queries).
@@ -659,13 +1282,19 @@ impl SqlIncorporator {
         match qg {
             Some(qg) => {
                 let qg_hash = qg.signature().hash;
-                self.query_graphs.insert(qg_hash, qg);
-                self.mir_queries.insert((qg_hash, universe), mir.clone());
-                self.named_queries.insert(query_name.to_owned(), qg_hash);
+                let prior = self.query_graphs.insert(qg_hash, qg);
+                self.record(Undo::QueryGraph(qg_hash, prior));
+                let key = (qg_hash, universe);
+                let prior = self.mir_queries.insert(key.clone(), mir.clone());
+                self.record(Undo::MirQuery(key, prior));
+                let prior = self.named_queries.insert(query_name.to_owned(), qg_hash);
+                self.record(Undo::NamedQuery(query_name.to_owned(), prior));
             }
             None => {
-                self.base_mir_queries
+                let prior = self
+                    .base_mir_queries
                     .insert(query_name.to_owned(), mir.clone());
+                self.record(Undo::BaseMirQuery(query_name.to_owned(), prior));
             }
         }
     }
@@ -730,7 +1359,9 @@ impl SqlIncorporator {
                         post_reuse_opt_mir.make_universe_naming_consistent(x, base_name);
                 }
                 None => {
-                    panic!("Missing table mapping when reconciling universe table names!");
+                    return Err(String::from(
+                        "missing table mapping when reconciling universe table names",
+                    ));
                 }
             }
         }
@@ -758,6 +1389,7 @@ impl SqlIncorporator {
     fn nodes_for_query(
         &mut self,
         q: SqlQuery,
+        ext: Extensions,
         is_leaf: bool,
         mig: &mut Migration,
     ) -> Result<QueryFlowParts, String> {
@@ -765,13 +1397,22 @@ impl SqlIncorporator {
             SqlQuery::CreateTable(ref ctq) => ctq.table.name.clone(),
             SqlQuery::CreateView(ref cvq) => cvq.name.clone(),
             SqlQuery::Select(_) | SqlQuery::CompoundSelect(_) => format!("q_{}", self.num_queries),
-            _ => panic!("only CREATE TABLE and SELECT queries can be added to the graph!"),
+            _ => {
+                return Err(
+                    "only CREATE TABLE and SELECT queries can be added to the graph".to_owned(),
+                );
+            }
         };
-        self.nodes_for_named_query(q, name, is_leaf, mig)
+        self.nodes_for_named_query(q, ext, name, is_leaf, mig)
     }
 
     /// Runs some standard rewrite passes on the query.
-    fn rewrite_query(&mut self, q: SqlQuery, mig: &mut Migration) -> Result<SqlQuery, String> {
+    fn rewrite_query(
+        &mut self,
+        q: SqlQuery,
+        ext: &mut Extensions,
+        mig: &mut Migration,
+    ) -> Result<SqlQuery, String> {
         // TODO: make this not take &mut self
 
         use passes::alias_removal::AliasRemoval;
@@ -780,7 +1421,7 @@ impl SqlIncorporator {
         use passes::key_def_coalescing::KeyDefinitionCoalescing;
         use passes::negation_removal::NegationRemoval;
         use passes::star_expansion::StarExpansion;
-        use passes::subqueries::SubQueries;
+        use passes::subqueries::{SubQueries, SubqueryDecorrelation};
         use query_utils::ReferredTables;
 
         // need to increment here so that each subquery has a unique name.
@@ -788,9 +1429,12 @@ impl SqlIncorporator {
         // so we will end up incrementing this for every subquery.
         self.num_queries += 1;
 
-        // flattens out the query by replacing subqueries for references
-        // to existing views in the graph
-        let mut fq = q.clone();
+        // turns subqueries in WHERE conjuncts and in the select list into joins, and then
+        // flattens out the query by replacing subqueries for references to existing views in the
+        // graph
+        let prefix = format!("q_{}", self.num_queries);
+        let mut fq = q.decorrelate_subqueries(&prefix, ext.select_mut(0))?;
+        let ext = ext.select(0);
         for sq in fq.extract_subqueries() {
             use self::passes::subqueries::{
                 field_with_table_name, query_from_condition_base, Subquery,
@@ -798,11 +1442,15 @@ impl SqlIncorporator {
             use nom_sql::{JoinRightSide, Table};
             match sq {
                 Subquery::InComparison(cond_base) => {
-                    let (sq, column) = query_from_condition_base(&cond_base);
+                    let (sq, column) = query_from_condition_base(&cond_base)?;
+                    let sq_ext = match sq {
+                        SqlQuery::Select(ref st) => ext.subquery(st),
+                        _ => Extensions::default(),
+                    };
 
                     let qfp = self
-                        .add_parsed_query(sq, None, false, mig)
-                        .expect("failed to add subquery");
+                        .add_parsed_query(sq, sq_ext, None, false, mig)
+                        .map_err(|e| format!("failed to add subquery: {}", e))?;
                     *cond_base = field_with_table_name(qfp.name.clone(), column);
                 }
                 Subquery::InJoin(join_right_side) => {
@@ -811,17 +1459,18 @@ impl SqlIncorporator {
                             let qfp = self
                                 .add_parsed_query(
                                     SqlQuery::Select((**ns).clone()),
+                                    ext.subquery(ns),
                                     alias.clone(),
                                     false,
                                     mig,
                                 )
-                                .expect("failed to add subquery in join");
+                                .map_err(|e| format!("failed to add subquery in join: {}", e))?;
                             JoinRightSide::Table(Table {
                                 name: qfp.name.clone(),
                                 alias: None,
                             })
                         }
-                        _ => unreachable!(),
+                        ref rhs => return Err(format!("unexpected subquery in join: {}", rhs)),
                     }
                 }
             }
@@ -853,18 +1502,18 @@ impl SqlIncorporator {
 
         // Run some standard rewrite passes on the query. This makes the later work easier,
         // as we no longer have to consider complications like aliases.
-        Ok(fq
-            .expand_table_aliases(mig.context())
-            .remove_negation()
+        fq.expand_table_aliases(mig.context())?
+            .remove_negation()?
             .coalesce_key_definitions()
-            .expand_stars(&self.view_schemas)
-            .expand_implied_tables(&self.view_schemas)
-            .rewrite_count_star(&self.view_schemas))
+            .expand_stars(&self.view_schemas)?
+            .expand_implied_tables(&self.view_schemas)?
+            .rewrite_count_star(&self.view_schemas)
     }
 
     fn nodes_for_named_query(
         &mut self,
         q: SqlQuery,
+        ext: Extensions,
         query_name: String,
         is_leaf: bool,
         mig: &mut Migration,
@@ -878,18 +1527,26 @@ impl SqlIncorporator {
                 SelectSpecification::Compound(csq) => {
                     return self.nodes_for_named_query(
                         SqlQuery::CompoundSelect(csq),
+                        ext,
                         name,
                         is_leaf,
                         mig,
                     );
                 }
                 SelectSpecification::Simple(sq) => {
-                    return self.nodes_for_named_query(SqlQuery::Select(sq), name, is_leaf, mig);
+                    return self.nodes_for_named_query(
+                        SqlQuery::Select(sq),
+                        ext,
+                        name,
+                        is_leaf,
+                        mig,
+                    );
                 }
             }
         };
 
-        let q = self.rewrite_query(q, mig)?;
+        let mut ext = ext;
+        let q = self.rewrite_query(q, &mut ext, mig)?;
 
         // TODO(larat): extend existing should handle policy nodes
         // if this is a selection, we compute its `QueryGraph` and consider the existing ones we
@@ -899,31 +1556,42 @@ impl SqlIncorporator {
                 // NOTE(malte): We can't currently reuse complete compound select queries, since
                 // our reuse logic operates on `SqlQuery` structures. Their subqueries do get
                 // reused, however.
-                self.add_compound_query(&query_name, &csq, is_leaf, mig)
-                    .unwrap()
+                self.add_compound_query(&query_name, &csq, &ext, is_leaf, mig)?
             }
-            SqlQuery::Select(sq) => self.add_select_query(&query_name, &sq, is_leaf, mig)?.0,
-            ref q @ SqlQuery::CreateTable { .. } => self.add_base_via_mir(&query_name, &q, mig),
-            q => panic!("unhandled query type in recipe: {:?}", q),
+            SqlQuery::Select(sq) => {
+                self.add_select_query(&query_name, &sq, &ext.select(0), is_leaf, mig)?
+                    .0
+            }
+            ref q @ SqlQuery::CreateTable { .. } => self.add_base_via_mir(&query_name, &q, mig)?,
+            q => return Err(format!("unhandled query type in recipe: {}", q)),
         };
 
         // record info about query
-        self.leaf_addresses
-            .insert(String::from(query_name.as_str()), qfp.query_leaf);
+        let prior = self
+            .leaf_addresses
+            .insert(query_name.clone(), qfp.query_leaf);
+        self.record(Undo::LeafAddress(query_name, prior));
 
         Ok(qfp)
     }
 
     /// Upgrades the schema version that any nodes created for queries will be tagged with.
     /// `new_version` must be strictly greater than the current version in `self.schema_version`.
-    pub(super) fn upgrade_schema(&mut self, new_version: usize) {
-        assert!(new_version > self.schema_version);
+    pub(super) fn upgrade_schema(&mut self, new_version: usize) -> Result<(), String> {
+        if new_version <= self.schema_version {
+            return Err(format!(
+                "cannot move schema version from {} back to {}",
+                self.schema_version, new_version
+            ));
+        }
         info!(
             self.log,
             "Schema version advanced from {} to {}", self.schema_version, new_version
         );
+        self.mir_converter.upgrade_schema(new_version)?;
+        self.record(Undo::SchemaVersion(self.schema_version));
         self.schema_version = new_version;
-        self.mir_converter.upgrade_schema(new_version);
+        Ok(())
     }
 }
 
@@ -959,24 +1627,32 @@ impl<'a> ToFlowParts for &'a str {
         mig: &mut Migration,
     ) -> Result<QueryFlowParts, String> {
         // try parsing the incoming SQL
-        let parsed_query = sql_parser::parse_query(self);
+        let (expanded, expressions) = expand_unparseable(self)?;
+        let mut q = sql_parser::parse_query(&expanded).map_err(String::from)?;
+        if restore_expressions(&mut q, &expressions)? != expressions.len() {
+            return Err(format!("unsupported expression in query: {}", self));
+        }
+        let ext = take_extensions(&mut q)?;
 
         // if ok, manufacture a node for the query structure we got
-        match parsed_query {
-            Ok(q) => inc.add_parsed_query(q, name, true, mig),
-            Err(e) => Err(String::from(e)),
-        }
+        inc.add_parsed_query(q, ext, name, true, mig)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{SqlIncorporator, ToFlowParts};
+    use super::{
+        aggregate_separator, disguised_aggregate, expand_aggregates, expand_page_parameters,
+        expand_unparseable, restore_expressions, take_extensions, to_query_graph, Extensions,
+        SqlIncorporator, ToFlowParts,
+    };
     use crate::controller::Migration;
     use crate::integration;
+    use dataflow::ops::grouped::aggregate::Aggregation;
     use dataflow::prelude::*;
     use nom_sql::{
-        CaseWhenExpression, Column, ColumnOrLiteral, FunctionArguments, FunctionExpression, Literal,
+        CaseWhenExpression, Column, ColumnOrLiteral, FunctionArguments, FunctionExpression,
+        Literal, SqlQuery,
     };
 
     /// Helper to grab a reference to a named view.
@@ -1153,6 +1829,40 @@ mod tests {
         .await;
     }
 
+    #[tokio::test(threaded_scheduler)]
+    async fn it_incorporates_disjunctive_selection() {
+        // set up graph
+        let mut g = integration::start_simple("it_incorporates_disjunctive_selection").await;
+        g.migrate(|mig| {
+            let mut inc = SqlIncorporator::default();
+            assert!(inc
+                .add_query("CREATE TABLE users (id int, name varchar(40));", None, mig)
+                .is_ok());
+
+            let res = inc.add_query(
+                "SELECT users.name FROM users WHERE users.id = 42 OR users.name = 'bob';",
+                None,
+                mig,
+            );
+            assert!(res.is_ok());
+
+            let qid = query_id_hash(
+                &["users"],
+                &[&Column::from("users.id"), &Column::from("users.name")],
+                &[&Column::from("users.name")],
+            );
+            // a single filter node evaluates both sides of the disjunction
+            let filter = get_node(&inc, mig, &format!("q_{:x}_n0_p0_f0", qid));
+            assert_eq!(filter.fields(), &["id", "name"]);
+            assert_eq!(filter.description(true), "σ[f0 = 42 OR f1 = \"bob\"]");
+            // leaf view node
+            let edge = get_node(&inc, mig, &res.unwrap().name);
+            assert_eq!(edge.fields(), &["name", "bogokey"]);
+            assert_eq!(edge.description(true), "π[1, lit: 0]");
+        })
+        .await;
+    }
+
     #[tokio::test(threaded_scheduler)]
     async fn it_incorporates_aggregation() {
         // set up graph
@@ -1266,6 +1976,326 @@ mod tests {
         .await;
     }
 
+    #[tokio::test(threaded_scheduler)]
+    async fn it_keys_on_range_parameter_last() {
+        // set up graph
+        let mut g = integration::start_simple("it_keys_on_range_parameter_last").await;
+        g.migrate(|mig| {
+            let mut inc = SqlIncorporator::default();
+            assert!(inc
+                .add_query(
+                    "CREATE TABLE users (id int, name varchar(40), age int);",
+                    None,
+                    mig
+                )
+                .is_ok());
+
+            // Add a new query with a range parameter and an equality parameter
+            let res = inc.add_query(
+                "SELECT id, name FROM users WHERE users.age > ? AND users.name = ?;",
+                None,
+                mig,
+            );
+            assert!(res.is_ok());
+            let qfp = res.unwrap();
+            assert_eq!(
+                get_node(&inc, mig, &qfp.name).fields(),
+                &["id", "name", "age"]
+            );
+            // the range column is keyed on last, and the reader serves range lookups
+            let n = get_reader(&inc, mig, &qfp.name);
+            n.with_reader(|r| {
+                assert_eq!(r.key().unwrap(), &[1, 2]);
+                assert_eq!(
+                    r.range_parameter(),
+                    Some(dataflow::node::special::RangeParameter {
+                        lower_inclusive: Some(false),
+                        upper_inclusive: None,
+                    })
+                );
+            })
+            .unwrap();
+        })
+        .await;
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn it_keys_on_page_parameter_last() {
+        // set up graph
+        let mut g = integration::start_simple("it_keys_on_page_parameter_last").await;
+        g.migrate(|mig| {
+            let mut inc = SqlIncorporator::default();
+            assert!(inc
+                .add_query(
+                    "CREATE TABLE users (id int, name varchar(40), age int);",
+                    None,
+                    mig
+                )
+                .is_ok());
+
+            // Add a new query that is paginated by an `OFFSET ?`
+            let res = inc.add_query(
+                "SELECT id, name FROM users WHERE users.age = ? ORDER BY id LIMIT 10 OFFSET ?;",
+                None,
+                mig,
+            );
+            assert!(res.is_ok());
+            let qfp = res.unwrap();
+            assert_eq!(
+                get_node(&inc, mig, &qfp.name).fields(),
+                &["id", "name", "age", "page"]
+            );
+            // the page number is keyed on last
+            let n = get_reader(&inc, mig, &qfp.name);
+            n.with_reader(|r| assert_eq!(r.key().unwrap(), &[2, 3]))
+                .unwrap();
+        })
+        .await;
+    }
+
+    #[test]
+    fn it_expands_page_parameters() {
+        assert_eq!(
+            expand_page_parameters("SELECT a FROM t LIMIT 5 offset  ?;").unwrap(),
+            "SELECT a FROM t WHERE __noria_page = ? LIMIT 5 ;"
+        );
+        assert_eq!(
+            expand_page_parameters(
+                "SELECT a FROM t WHERE b = ? OR c = 1 ORDER BY a LIMIT 5 OFFSET ?"
+            )
+            .unwrap(),
+            "SELECT a FROM t WHERE (b = ? OR c = 1 ) AND __noria_page = ? ORDER BY a LIMIT 5 "
+        );
+        // literal offsets, columns that merely end in "offset", and quoted text are left alone
+        let sql = "SELECT t.tz_offset FROM t WHERE id = ? AND s = 'OFFSET ?' \
+                   LIMIT 5 OFFSET 18446744073709551615;";
+        assert_eq!(expand_page_parameters(sql).unwrap(), sql);
+        // the placeholder can only page through the results of the outermost query
+        assert!(expand_page_parameters(
+            "SELECT a FROM t JOIN (SELECT b FROM u LIMIT 5 OFFSET ?) AS v ON (t.a = v.b);"
+        )
+        .is_err());
+        assert!(expand_page_parameters(
+            "SELECT a FROM t UNION SELECT a FROM u ORDER BY a LIMIT 5 OFFSET ?;"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn it_pages_only_on_offset_placeholders() {
+        use nom_sql::parser::parse_query;
+
+        let sql = expand_page_parameters("SELECT t.a FROM t WHERE t.b = ? LIMIT 5 OFFSET ?;");
+        let mut q = parse_query(&sql.unwrap()).unwrap();
+        let ext = take_extensions(&mut q).unwrap();
+        assert_eq!(
+            q,
+            parse_query("SELECT t.a FROM t WHERE t.b = ? LIMIT 5;").unwrap()
+        );
+        assert!(ext.select(0).paginated);
+
+        // a query that was parsed without the SQL front end is planned as written, even if it
+        // compares a column named like the placeholder with a parameter
+        let st = match parse_query("SELECT t.a FROM t WHERE t.__noria_page = ? LIMIT 5;") {
+            Ok(SqlQuery::Select(st)) => st,
+            q => panic!("unexpected query {:?}", q),
+        };
+        let qg = to_query_graph(&st, &Extensions::default().select(0)).unwrap();
+        assert_eq!(qg.page_parameter, None);
+        assert_eq!(qg.parameters(), vec![&Column::from("t.__noria_page")]);
+    }
+
+    #[test]
+    fn it_expands_aggregates() {
+        assert_eq!(
+            expand_aggregates("SELECT STDDEV(t.a), bool_or (b) AS any_b FROM t;").unwrap(),
+            "SELECT group_concat(t.a separator'NoriaAggregatestddev'), \
+             group_concat(b separator'NoriaAggregateboolor') AS any_b FROM t;"
+        );
+        // functions that merely end in the name of an aggregate, and quoted text, are left alone
+        assert_eq!(
+            expand_aggregates("SELECT my_variance(a), stddev_pop(a) FROM t WHERE b = 'stddev(a)';")
+                .unwrap(),
+            "SELECT my_variance(a), group_concat(a separator'NoriaAggregatestddevpop') \
+             FROM t WHERE b = 'stddev(a)';"
+        );
+        assert!(expand_aggregates(
+            "SELECT group_concat(a separator'NoriaAggregatevariance') FROM t;"
+        )
+        .is_err());
+        assert_eq!(
+            disguised_aggregate(&aggregate_separator("var_pop")),
+            Some(("var_pop", Aggregation::VARIANCE))
+        );
+        assert_eq!(disguised_aggregate(","), None);
+    }
+
+    #[test]
+    fn it_records_disguised_aggregates_next_to_the_query() {
+        use nom_sql::parser::parse_query;
+
+        let (sql, extracted) = expand_unparseable(
+            "SELECT u.id, STDDEV(t.a), bool_or(t.b) AS any_b, \
+             (SELECT VARIANCE(p.x) FROM p WHERE p.u = u.id) AS v \
+             FROM t JOIN u ON (t.u = u.id) GROUP BY u.id;",
+        )
+        .unwrap();
+        let mut q = parse_query(&sql).unwrap();
+        assert_eq!(restore_expressions(&mut q, &extracted), Ok(1));
+        let ext = take_extensions(&mut q).unwrap().select(0);
+        assert_eq!(
+            ext.aggregates,
+            vec![
+                ("stddev(t.a)".to_owned(), Aggregation::STDDEV),
+                ("any_b".to_owned(), Aggregation::BOOLOR),
+            ]
+        );
+        let st = match q {
+            SqlQuery::Select(st) => st,
+            q => panic!("unexpected query {:?}", q),
+        };
+        match st.fields[1] {
+            nom_sql::FieldDefinitionExpression::Col(ref c) => {
+                assert_eq!(c.name, "stddev(t.a)");
+                assert_eq!(
+                    c.function,
+                    Some(Box::new(nom_sql::FunctionExpression::GroupConcat(
+                        nom_sql::FunctionArguments::Column(nom_sql::Column::from("t.a")),
+                        ",".to_owned()
+                    )))
+                );
+            }
+            ref f => panic!("unexpected field {:?}", f),
+        }
+
+        // the scalar subquery keeps its own
+        let sq = match st.join[1].right {
+            nom_sql::JoinRightSide::NestedSelect(ref sq, _) => sq,
+            ref r => panic!("unexpected join {:?}", r),
+        };
+        assert_eq!(
+            ext.subquery(sq).select(0).aggregates,
+            vec![("v".to_owned(), Aggregation::VARIANCE)]
+        );
+
+        let select = |q: SqlQuery| match q {
+            SqlQuery::Select(st) => st,
+            q => panic!("unexpected query {:?}", q),
+        };
+        let sql = "SELECT t.c, group_concat(t.a separator'NoriaAggregatestddev') AS s \
+                   FROM t GROUP BY t.c;";
+        let mut q = parse_query(sql).unwrap();
+        let ext = take_extensions(&mut q).unwrap().select(0);
+        assert_eq!(
+            to_query_graph(&select(q), &ext).unwrap().aggregates,
+            vec![("s".to_owned(), Aggregation::STDDEV)]
+        );
+        // a query that was parsed without the SQL front end is planned as written
+        let st = select(parse_query(sql).unwrap());
+        let qg = to_query_graph(&st, &Extensions::default().select(0)).unwrap();
+        assert!(qg.aggregates.is_empty());
+    }
+
+    #[test]
+    fn it_expands_subqueries() {
+        assert_eq!(
+            expand_subqueries(
+                "SELECT id FROM posts WHERE NOT EXISTS (SELECT 1 FROM reads) \
+                 AND author NOT IN (SELECT uid FROM banned) AND posts.x NOT IN (SELECT y FROM t)"
+            )
+            .unwrap(),
+            "SELECT id FROM posts WHERE NOT __noria_exists IN (SELECT 1 FROM reads) \
+             AND NOT author IN (SELECT uid FROM banned) AND NOT posts.x IN (SELECT y FROM t)"
+        );
+        // EXISTS that does not precede a subquery is left alone, and so is quoted text
+        assert_eq!(
+            expand_subqueries("DROP TABLE IF EXISTS posts;").unwrap(),
+            "DROP TABLE IF EXISTS posts;"
+        );
+        let quoted = "SELECT id FROM posts \
+                      WHERE title = 'not in (select' AND body = 'exists (select 1)'";
+        assert_eq!(expand_subqueries(quoted).unwrap(), quoted);
+        assert!(expand_subqueries("SELECT (SELECT x FROM t FROM u").is_err());
+    }
+
+    #[test]
+    fn it_expands_joins() {
+        assert_eq!(
+            expand_joins(
+                "SELECT * FROM a RIGHT OUTER JOIN b ON (a.x = b.x) full\njoin c ON (b.y = c.y)"
+            )
+            .unwrap(),
+            "SELECT * FROM a inner join b ON (a.x = b.x) cross join c ON (b.y = c.y)"
+        );
+        // joins that MySQL treats as inner joins become plain joins
+        assert_eq!(
+            expand_joins(
+                "SELECT * FROM a CROSS  JOIN b ON (a.x = b.x) INNER JOIN c ON (b.y = c.y) \
+                 STRAIGHT_JOIN d ON (c.z = d.z)"
+            )
+            .unwrap(),
+            "SELECT * FROM a join b ON (a.x = b.x) join c ON (b.y = c.y) join d ON (c.z = d.z)"
+        );
+        // columns named like join keywords, and quoted text, are left alone
+        let sql = "SELECT a.right, full FROM a LEFT JOIN b ON (a.x = b.x) WHERE a.y = 'right join'";
+        assert_eq!(expand_joins(sql).unwrap(), sql);
+    }
+
+    #[test]
+    fn it_plans_outer_joins_only_from_the_front_end() {
+        use super::query_graph::QueryGraphEdge;
+        use nom_sql::parser::parse_query;
+
+        let kinds = |q: SqlQuery, ext: &Extensions| {
+            let st = match q {
+                SqlQuery::Select(st) => st,
+                q => panic!("unexpected query {:?}", q),
+            };
+            let qg = to_query_graph(&st, &ext.select(0)).unwrap();
+            let mut kinds: Vec<_> = qg
+                .edges
+                .iter()
+                .map(|(&(ref l, ref r), e)| {
+                    let kind = match *e {
+                        QueryGraphEdge::Join(_) => "join",
+                        QueryGraphEdge::LeftJoin(_) => "left",
+                        QueryGraphEdge::FullJoin(_) => "full",
+                        ref e => panic!("unexpected edge {:?}", e),
+                    };
+                    (l.clone(), r.clone(), kind)
+                })
+                .collect();
+            kinds.sort();
+            kinds
+        };
+        let front_end = |sql: &str| {
+            let (expanded, _) = expand_unparseable(sql).unwrap();
+            let mut q = parse_query(&expanded).unwrap();
+            let ext = take_extensions(&mut q).unwrap();
+            kinds(q, &ext)
+        };
+        let edge = |l: &str, r: &str, kind| (l.to_owned(), r.to_owned(), kind);
+
+        assert_eq!(
+            front_end(
+                "SELECT a.x, b.y, c.z FROM a RIGHT JOIN b ON (a.x = b.x) \
+                 FULL OUTER JOIN c ON (b.y = c.y);"
+            ),
+            vec![edge("b", "a", "left"), edge("b", "c", "full")]
+        );
+
+        // inner and cross joins are inner joins, whether or not the query went through the front
+        // end; security policies, for one, do not
+        let sql = "SELECT a.x, b.y, c.z FROM a INNER JOIN b ON (a.x = b.x) \
+                   CROSS JOIN c ON (b.y = c.y);";
+        let inner = vec![edge("a", "b", "join"), edge("b", "c", "join")];
+        assert_eq!(front_end(sql), inner);
+        assert_eq!(
+            kinds(parse_query(sql).unwrap(), &Extensions::default()),
+            inner
+        );
+    }
+
     #[tokio::test(threaded_scheduler)]
     async fn it_reuses_identical_query() {
         // set up graph
@@ -1311,6 +2341,32 @@ mod tests {
         .await;
     }
 
+    #[tokio::test(threaded_scheduler)]
+    async fn it_rolls_back_to_savepoint() {
+        // set up graph
+        let mut g = integration::start_simple("it_rolls_back_to_savepoint").await;
+        g.migrate(|mig| {
+            let mut inc = SqlIncorporator::default();
+            assert!(inc
+                .add_query("CREATE TABLE users (id int, name varchar(40));", None, mig)
+                .is_ok());
+
+            let sp = inc.savepoint();
+            let q = "SELECT id, name FROM users WHERE users.id = ?;";
+            assert!(inc.add_query(q, Some("byid".into()), mig).is_ok());
+            assert!(inc.get_query_address("byid").is_some());
+            inc.rollback(sp);
+
+            // the query is gone, and adding it again builds it from scratch rather than reusing
+            // the nodes of the abandoned one
+            assert!(inc.get_query_address("byid").is_none());
+            assert!(inc.get_view_schema("byid").is_none());
+            let qfp = inc.add_query(q, Some("byid".into()), mig).unwrap();
+            assert!(!qfp.new_nodes.is_empty());
+        })
+        .await;
+    }
+
     #[tokio::test(threaded_scheduler)]
     async fn it_reuses_with_different_parameter() {
         // set up graph
@@ -1408,6 +2464,7 @@ mod tests {
             let res = inc.add_parsed_query(
                 sql_parser::parse_query("SELECT COUNT(uid) AS vc FROM votes GROUP BY aid;")
                     .unwrap(),
+                Extensions::default(),
                 Some("votecount".into()),
                 false,
                 mig,
@@ -1421,6 +2478,7 @@ mod tests {
                     "SELECT COUNT(uid) AS vc FROM votes WHERE vc > 5 GROUP BY aid;",
                 )
                 .unwrap(),
+                Extensions::default(),
                 Some("highvotes".into()),
                 true,
                 mig,
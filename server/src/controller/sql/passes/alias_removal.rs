@@ -4,11 +4,13 @@ use nom_sql::{
 };
 
 use std::collections::HashMap;
+use std::mem;
 
+use crate::controller::sql::expression;
 use dataflow::prelude::DataType;
 
 pub trait AliasRemoval {
-    fn expand_table_aliases(self, context: &HashMap<String, DataType>) -> SqlQuery;
+    fn expand_table_aliases(self, context: &HashMap<String, DataType>) -> Result<SqlQuery, String>;
 }
 
 fn rewrite_conditional(
@@ -67,7 +69,7 @@ fn rewrite_conditional(
 }
 
 impl AliasRemoval for SqlQuery {
-    fn expand_table_aliases(self, context: &HashMap<String, DataType>) -> SqlQuery {
+    fn expand_table_aliases(self, context: &HashMap<String, DataType>) -> Result<SqlQuery, String> {
         let mut table_aliases = HashMap::new();
 
         match self {
@@ -120,7 +122,9 @@ impl AliasRemoval for SqlQuery {
                                     }
                                 }
                             }
-                            JoinRightSide::NestedJoin(_) => unimplemented!(),
+                            JoinRightSide::NestedJoin(_) => {
+                                return Err("nested joins are not supported".to_owned());
+                            }
                             _ => (),
                         }
                     }
@@ -140,6 +144,12 @@ impl AliasRemoval for SqlQuery {
                                     Some(t.clone())
                                 };
                                 col.function = None;
+                            } else {
+                                expression::rewrite_columns(&mut col, |c| {
+                                    if let Some(t) = c.table.take() {
+                                        c.table = Some(table_aliases.get(&t).cloned().unwrap_or(t));
+                                    }
+                                });
                             }
                             FieldDefinitionExpression::Col(col)
                         }
@@ -154,40 +164,37 @@ impl AliasRemoval for SqlQuery {
                     })
                     .collect();
                 // Remove them from join clauses
-                sq.join = sq
-                    .join
-                    .into_iter()
-                    .map(|mut jc| {
-                        jc.right = match jc.right {
-                            JoinRightSide::Table(t) => {
-                                if table_aliases.contains_key(&t.name) {
-                                    JoinRightSide::Table(nom_sql::Table::from(
-                                        table_aliases[&t.name].as_ref(),
-                                    ))
-                                } else {
-                                    JoinRightSide::Table(t)
-                                }
-                            }
-                            _ => unimplemented!(),
-                        };
-                        jc.constraint = match jc.constraint {
+                let unalias = |t: nom_sql::Table| match table_aliases.get(&t.name) {
+                    Some(name) => nom_sql::Table::from(name.as_ref()),
+                    None => t,
+                };
+                for jc in &mut sq.join {
+                    jc.right = match mem::replace(&mut jc.right, JoinRightSide::Tables(vec![])) {
+                        JoinRightSide::Table(t) => JoinRightSide::Table(unalias(t)),
+                        JoinRightSide::Tables(ts) => {
+                            JoinRightSide::Tables(ts.into_iter().map(&unalias).collect())
+                        }
+                        rhs => {
+                            return Err(format!("unsupported right-hand side of join: {}", rhs));
+                        }
+                    };
+                    jc.constraint =
+                        match mem::replace(&mut jc.constraint, JoinConstraint::Using(vec![])) {
                             JoinConstraint::On(cond) => {
                                 JoinConstraint::On(rewrite_conditional(&table_aliases, cond))
                             }
                             c @ JoinConstraint::Using(..) => c,
                         };
-                        jc
-                    })
-                    .collect();
+                }
                 // Remove them from conditions
                 sq.where_clause = match sq.where_clause {
                     None => None,
                     Some(wc) => Some(rewrite_conditional(&table_aliases, wc)),
                 };
-                SqlQuery::Select(sq)
+                Ok(SqlQuery::Select(sq))
             }
             // nothing to do for other query types, as they cannot have aliases
-            x => x,
+            x => Ok(x),
         }
     }
 }
@@ -219,7 +226,7 @@ mod tests {
         };
         let mut context = HashMap::new();
         context.insert(String::from("id"), "global".into());
-        let res = SqlQuery::Select(q).expand_table_aliases(&context);
+        let res = SqlQuery::Select(q).expand_table_aliases(&context).unwrap();
         // Table alias removed in field list
         match res {
             SqlQuery::Select(tq) => {
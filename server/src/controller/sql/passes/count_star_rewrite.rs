@@ -1,12 +1,15 @@
 use nom_sql::{
-    Column, ConditionBase, ConditionExpression, ConditionTree, FieldDefinitionExpression,
-    FunctionArguments, SqlQuery, Table,
+    ArithmeticBase, Column, ConditionBase, ConditionExpression, ConditionTree,
+    FieldDefinitionExpression, FunctionArguments, SqlQuery, Table,
 };
 
 use std::collections::HashMap;
 
 pub trait CountStarRewrite {
-    fn rewrite_count_star(self, write_schemas: &HashMap<String, Vec<String>>) -> SqlQuery;
+    fn rewrite_count_star(
+        self,
+        write_schemas: &HashMap<String, Vec<String>>,
+    ) -> Result<SqlQuery, String>;
 }
 
 fn extract_condition_columns(ce: &ConditionExpression) -> Vec<Column> {
@@ -25,11 +28,16 @@ fn extract_condition_columns(ce: &ConditionExpression) -> Vec<Column> {
             ..
         }) => {
             let mut cols = vec![];
-            if let ConditionExpression::Base(ConditionBase::Field(ref f)) = **left {
-                cols.push(f.clone());
-            }
-            if let ConditionExpression::Base(ConditionBase::Field(ref f)) = **right {
-                cols.push(f.clone());
+            for side in &[left, right] {
+                match ***side {
+                    ConditionExpression::Base(ConditionBase::Field(ref f)) => {
+                        cols.push(f.clone());
+                    }
+                    ConditionExpression::Arithmetic(_) => {
+                        cols.extend(extract_condition_columns(side));
+                    }
+                    _ => (),
+                }
             }
 
             cols
@@ -37,42 +45,56 @@ fn extract_condition_columns(ce: &ConditionExpression) -> Vec<Column> {
         ConditionExpression::NegationOp(ref inner) => extract_condition_columns(inner),
         ConditionExpression::Bracketed(ref inner) => extract_condition_columns(inner),
         ConditionExpression::Base(_) => unreachable!(),
-        ConditionExpression::Arithmetic(_) => unimplemented!(),
+        ConditionExpression::Arithmetic(ref ae) => [&ae.left, &ae.right]
+            .iter()
+            .filter_map(|b| match **b {
+                ArithmeticBase::Column(ref c) => Some(c.clone()),
+                ArithmeticBase::Scalar(_) => None,
+            })
+            .collect(),
     }
 }
 
 impl CountStarRewrite for SqlQuery {
-    fn rewrite_count_star(self, write_schemas: &HashMap<String, Vec<String>>) -> SqlQuery {
+    fn rewrite_count_star(
+        self,
+        write_schemas: &HashMap<String, Vec<String>>,
+    ) -> Result<SqlQuery, String> {
         use nom_sql::FunctionExpression::*;
 
-        let rewrite_count_star =
-            |c: &mut Column, tables: &Vec<Table>, avoid_columns: &Vec<Column>| {
-                assert!(!tables.is_empty());
-                if c.function
-                    .as_ref()
-                    .map(|v| **v == CountStar)
-                    .unwrap_or(false)
-                {
-                    let bogo_table = &tables[0];
-                    let mut schema_iter = write_schemas.get(&bogo_table.name).unwrap().iter();
-                    let mut bogo_column = schema_iter.next().unwrap();
-                    while avoid_columns.iter().any(|c| c.name == *bogo_column) {
-                        bogo_column = schema_iter
-                            .next()
-                            .expect("ran out of columns trying to pick a bogo column for COUNT(*)");
-                    }
+        let rewrite_count_star = |c: &mut Column,
+                                  tables: &Vec<Table>,
+                                  avoid_columns: &Vec<Column>|
+         -> Result<(), String> {
+            if c.function
+                .as_ref()
+                .map(|v| **v == CountStar)
+                .unwrap_or(false)
+            {
+                let bogo_table = tables
+                    .first()
+                    .ok_or_else(|| "COUNT(*) requires a table to count".to_owned())?;
+                let bogo_column = write_schemas
+                    .get(&bogo_table.name)
+                    .ok_or_else(|| format!("unknown table \"{}\"", bogo_table.name))?
+                    .iter()
+                    .find(|bc| !avoid_columns.iter().any(|c| c.name == **bc))
+                    .ok_or_else(|| {
+                        "ran out of columns trying to pick a bogo column for COUNT(*)".to_owned()
+                    })?;
 
-                    c.function = Some(Box::new(Count(
-                        FunctionArguments::Column(Column {
-                            name: bogo_column.clone(),
-                            alias: None,
-                            table: Some(bogo_table.name.clone()),
-                            function: None,
-                        }),
-                        false,
-                    )));
-                }
-            };
+                c.function = Some(Box::new(Count(
+                    FunctionArguments::Column(Column {
+                        name: bogo_column.clone(),
+                        alias: None,
+                        table: Some(bogo_table.name.clone()),
+                        function: None,
+                    }),
+                    false,
+                )));
+            }
+            Ok(())
+        };
 
         let err = "Must apply StarExpansion pass before CountStarRewrite"; // for wrapping
         match self {
@@ -88,19 +110,19 @@ impl CountStarRewrite for SqlQuery {
                 }
                 for field in sq.fields.iter_mut() {
                     match *field {
-                        FieldDefinitionExpression::All => panic!(err),
-                        FieldDefinitionExpression::AllInTable(_) => panic!(err),
+                        FieldDefinitionExpression::All => return Err(err.to_owned()),
+                        FieldDefinitionExpression::AllInTable(_) => return Err(err.to_owned()),
                         FieldDefinitionExpression::Value(_) => (),
                         FieldDefinitionExpression::Col(ref mut c) => {
-                            rewrite_count_star(c, &tables, &avoid_cols)
+                            rewrite_count_star(c, &tables, &avoid_cols)?
                         }
                     }
                 }
                 // TODO: also expand function columns within WHERE clause
-                SqlQuery::Select(sq)
+                Ok(SqlQuery::Select(sq))
             }
             // nothing to do for other query types, as they cannot have aliases
-            x => x,
+            x => Ok(x),
         }
     }
 }
@@ -126,7 +148,7 @@ mod tests {
             vec!["id".into(), "name".into(), "age".into()],
         );
 
-        let res = q.rewrite_count_star(&schema);
+        let res = q.rewrite_count_star(&schema).unwrap();
         match res {
             SqlQuery::Select(tq) => {
                 assert_eq!(
@@ -162,7 +184,7 @@ mod tests {
             vec!["id".into(), "name".into(), "age".into()],
         );
 
-        let res = q.rewrite_count_star(&schema);
+        let res = q.rewrite_count_star(&schema).unwrap();
         match res {
             SqlQuery::Select(tq) => {
                 assert_eq!(
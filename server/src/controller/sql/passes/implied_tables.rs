@@ -4,36 +4,50 @@ use nom_sql::{
     JoinRightSide, SelectStatement, SqlQuery, Table,
 };
 
+use crate::controller::sql::expression;
 use std::collections::HashMap;
 
 pub trait ImpliedTableExpansion {
-    fn expand_implied_tables(self, write_schemas: &HashMap<String, Vec<String>>) -> SqlQuery;
+    fn expand_implied_tables(
+        self,
+        write_schemas: &HashMap<String, Vec<String>>,
+    ) -> Result<SqlQuery, String>;
 }
 
 fn rewrite_conditional<F>(
     expand_columns: &F,
     ce: ConditionExpression,
     avail_tables: &[Table],
-) -> ConditionExpression
+) -> Result<ConditionExpression, String>
 where
-    F: Fn(Column, &[Table]) -> Column,
+    F: Fn(Column, &[Table]) -> Result<Column, String>,
 {
     use nom_sql::ConditionBase::*;
     use nom_sql::ConditionExpression::*;
 
-    let translate_ct_arm = |bce: Box<ConditionExpression>| -> Box<ConditionExpression> {
-        let new_ce = match *bce {
-            Base(Field(f)) => Base(Field(expand_columns(f, avail_tables))),
-            Base(b) => Base(b),
-            x => rewrite_conditional(expand_columns, x, avail_tables),
+    let translate_ct_arm =
+        |bce: Box<ConditionExpression>| -> Result<Box<ConditionExpression>, String> {
+            let new_ce = match *bce {
+                Base(Field(f)) => Base(Field(expand_columns(f, avail_tables)?)),
+                Base(b) => Base(b),
+                Arithmetic(mut ae) => {
+                    if let ArithmeticBase::Column(ref mut c) = ae.left {
+                        *c = expand_columns(c.clone(), avail_tables)?;
+                    }
+                    if let ArithmeticBase::Column(ref mut c) = ae.right {
+                        *c = expand_columns(c.clone(), avail_tables)?;
+                    }
+                    Arithmetic(ae)
+                }
+                x => rewrite_conditional(expand_columns, x, avail_tables)?,
+            };
+            Ok(Box::new(new_ce))
         };
-        Box::new(new_ce)
-    };
 
-    match ce {
+    Ok(match ce {
         ComparisonOp(ct) => {
-            let l = translate_ct_arm(ct.left);
-            let r = translate_ct_arm(ct.right);
+            let l = translate_ct_arm(ct.left)?;
+            let r = translate_ct_arm(ct.right)?;
             let rewritten_ct = ConditionTree {
                 operator: ct.operator,
                 left: l,
@@ -47,83 +61,75 @@ where
             right,
         }) => LogicalOp(ConditionTree {
             operator,
-            left: Box::new(rewrite_conditional(expand_columns, *left, avail_tables)),
-            right: Box::new(rewrite_conditional(expand_columns, *right, avail_tables)),
+            left: Box::new(rewrite_conditional(expand_columns, *left, avail_tables)?),
+            right: Box::new(rewrite_conditional(expand_columns, *right, avail_tables)?),
         }),
+        Bracketed(inner) => Bracketed(Box::new(rewrite_conditional(
+            expand_columns,
+            *inner,
+            avail_tables,
+        )?)),
         x => x,
-    }
+    })
 }
 
 // Sets the table for the `Column` in `f`to `table`. This is mostly useful for CREATE TABLE
 // and INSERT queries and deliberately leaves function specifications unaffected, since
 // they can refer to remote tables and `set_table` should not be used for queries that have
 // computed columns.
-fn set_table(mut f: Column, table: &Table) -> Column {
+fn set_table(mut f: Column, table: &Table) -> Result<Column, String> {
     f.table = match f.table {
         None => match f.function {
-            Some(ref mut f) => panic!(
-                "set_table({}) invoked on computed column {:?}",
-                table.name, f
-            ),
+            Some(ref f) => {
+                return Err(format!(
+                    "computed column {:?} cannot be used in a query on {}",
+                    f, table.name
+                ));
+            }
             None => Some(table.name.clone()),
         },
         Some(x) => Some(x),
     };
-    f
+    Ok(f)
 }
 
 fn rewrite_selection(
     mut sq: SelectStatement,
     write_schemas: &HashMap<String, Vec<String>>,
-) -> SelectStatement {
+) -> Result<SelectStatement, String> {
     use nom_sql::FunctionExpression::*;
     use nom_sql::{GroupByClause, OrderClause};
 
     // Tries to find a table with a matching column in the `tables_in_query` (information
     // passed as `write_schemas`; this is not something the parser or the expansion pass can
-    // know on their own). Panics if no match is found or the match is ambiguous.
-    let find_table = |f: &Column, tables_in_query: &[Table]| -> Option<String> {
-        let mut matches = write_schemas
-            .iter()
-            .filter(|&(t, _)| {
-                if !tables_in_query.is_empty() {
-                    for qt in tables_in_query {
-                        if qt.name == *t {
-                            return true;
-                        }
-                    }
-                    false
-                } else {
-                    // preserve all tables if there are no tables in the query
-                    true
-                }
-            })
-            .filter_map(|(t, ws)| {
-                let num_matching = ws.iter().filter(|c| **c == f.name).count();
-                assert!(num_matching <= 1);
-                if num_matching == 1 {
-                    Some((*t).clone())
-                } else {
-                    None
+    // know on their own). Returns `None` if no match is found, and picks one of the tables if
+    // the match is ambiguous.
+    let find_table = |f: &Column, tables_in_query: &[Table]| -> Result<Option<String>, String> {
+        let mut matches = Vec::new();
+        for (t, ws) in write_schemas {
+            // preserve all tables if there are no tables in the query
+            if !tables_in_query.is_empty() && !tables_in_query.iter().any(|qt| qt.name == *t) {
+                continue;
+            }
+            match ws.iter().filter(|c| **c == f.name).count() {
+                0 => (),
+                1 => matches.push(t.clone()),
+                _ => {
+                    return Err(format!("column {} appears more than once in {}", f.name, t));
                 }
-            })
-            .collect::<Vec<String>>();
+            }
+        }
         if matches.len() > 1 {
             println!(
                 "Ambiguous column {} exists in tables: {} -- picking a random one",
                 f.name,
                 matches.as_slice().join(", ")
             );
-            Some(matches.pop().unwrap())
-        } else if matches.is_empty() {
-            // This might be an alias for a computed column, which has no
-            // implied table. So, we allow it to pass and our code should
-            // crash in the future if this is not the case.
-            None
-        } else {
-            // exactly one match
-            Some(matches.pop().unwrap())
         }
+        // If there is no match, this might be an alias for a computed column, which has no
+        // implied table. So, we allow it to pass and our code should fail later on if this is
+        // not the case.
+        Ok(matches.pop())
     };
 
     let err = "Must apply StarExpansion pass before ImpliedTableExpansion"; // for wrapping
@@ -131,7 +137,7 @@ fn rewrite_selection(
     // Traverses a query and calls `find_table` on any column that has no explicit table set,
     // including computed columns. Should not be used for CREATE TABLE and INSERT queries,
     // which can use the simpler `set_table`.
-    let expand_columns = |mut f: Column, tables_in_query: &[Table]| -> Column {
+    let expand_columns = |mut f: Column, tables_in_query: &[Table]| -> Result<Column, String> {
         f.table = match f.table {
             None => {
                 match f.function {
@@ -161,19 +167,19 @@ fn rewrite_selection(
                             | Max(FunctionArguments::Column(ref mut fe))
                             | GroupConcat(FunctionArguments::Column(ref mut fe), _) => {
                                 if fe.table.is_none() {
-                                    fe.table = find_table(fe, tables_in_query);
+                                    fe.table = find_table(fe, tables_in_query)?;
                                 }
                             }
                             _ => {}
                         }
                         None
                     }
-                    None => find_table(&f, tables_in_query),
+                    None => find_table(&f, tables_in_query)?,
                 }
             }
             Some(x) => Some(x),
         };
-        f
+        Ok(f)
     };
 
     let mut tables: Vec<Table> = sq.tables.clone();
@@ -182,26 +188,41 @@ fn rewrite_selection(
         match jc.right {
             JoinRightSide::Table(ref join_table) => tables.push(join_table.clone()),
             JoinRightSide::Tables(ref join_tables) => tables.extend(join_tables.clone()),
-            _ => unimplemented!(),
+            ref rhs => return Err(format!("unsupported right-hand side of join: {}", rhs)),
         }
     }
     // Expand within field list
     for field in sq.fields.iter_mut() {
         match *field {
-            FieldDefinitionExpression::All => panic!(err),
-            FieldDefinitionExpression::AllInTable(_) => panic!(err),
+            FieldDefinitionExpression::All => return Err(err.to_owned()),
+            FieldDefinitionExpression::AllInTable(_) => return Err(err.to_owned()),
             FieldDefinitionExpression::Value(FieldValueExpression::Literal(_)) => (),
             FieldDefinitionExpression::Value(FieldValueExpression::Arithmetic(ref mut e)) => {
                 if let ArithmeticBase::Column(ref mut c) = e.left {
-                    *c = expand_columns(c.clone(), &tables);
+                    *c = expand_columns(c.clone(), &tables)?;
                 }
 
                 if let ArithmeticBase::Column(ref mut c) = e.right {
-                    *c = expand_columns(c.clone(), &tables);
+                    *c = expand_columns(c.clone(), &tables)?;
                 }
             }
             FieldDefinitionExpression::Col(ref mut f) => {
-                *f = expand_columns(f.clone(), &tables);
+                let mut failed = None;
+                let is_expression = expression::rewrite_columns(f, |c| {
+                    if c.table.is_none() {
+                        match find_table(c, &tables) {
+                            Ok(t) => c.table = t,
+                            Err(e) => failed = Some(e),
+                        }
+                    }
+                });
+                if let Some(e) = failed {
+                    return Err(e);
+                }
+                if is_expression {
+                    continue;
+                }
+                *f = expand_columns(f.clone(), &tables)?;
                 // also need to expand any conditionals in the column, e.g. for filtered aggregations
                 match f.function {
                     Some(ref mut f) => match **f {
@@ -220,7 +241,7 @@ fn rewrite_selection(
                             _,
                         ) => {
                             *condition =
-                                rewrite_conditional(&expand_columns, condition.clone(), &tables);
+                                rewrite_conditional(&expand_columns, condition.clone(), &tables)?;
                         }
                         _ => {}
                     },
@@ -232,7 +253,7 @@ fn rewrite_selection(
     // Expand within WHERE clause
     sq.where_clause = match sq.where_clause {
         None => None,
-        Some(wc) => Some(rewrite_conditional(&expand_columns, wc, &tables)),
+        Some(wc) => Some(rewrite_conditional(&expand_columns, wc, &tables)?),
     };
     // Expand within GROUP BY clause
     sq.group_by = match sq.group_by {
@@ -242,10 +263,10 @@ fn rewrite_selection(
                 .columns
                 .into_iter()
                 .map(|f| expand_columns(f, &tables))
-                .collect(),
+                .collect::<Result<_, _>>()?,
             having: match gbc.having {
                 None => None,
-                Some(hc) => Some(rewrite_conditional(&expand_columns, hc, &tables)),
+                Some(hc) => Some(rewrite_conditional(&expand_columns, hc, &tables)?),
             },
         }),
     };
@@ -256,36 +277,45 @@ fn rewrite_selection(
             columns: oc
                 .columns
                 .into_iter()
-                .map(|(f, o)| (expand_columns(f, &tables), o))
-                .collect(),
+                .map(|(f, o)| Ok((expand_columns(f, &tables)?, o)))
+                .collect::<Result<_, String>>()?,
         }),
     };
 
-    sq
+    Ok(sq)
 }
 
 impl ImpliedTableExpansion for SqlQuery {
-    fn expand_implied_tables(self, write_schemas: &HashMap<String, Vec<String>>) -> SqlQuery {
+    fn expand_implied_tables(
+        self,
+        write_schemas: &HashMap<String, Vec<String>>,
+    ) -> Result<SqlQuery, String> {
         match self {
-            SqlQuery::CreateTable(..) => self,
+            SqlQuery::CreateTable(..) => Ok(self),
             SqlQuery::CompoundSelect(mut csq) => {
                 csq.selects = csq
                     .selects
                     .into_iter()
-                    .map(|(op, sq)| (op, rewrite_selection(sq, write_schemas)))
-                    .collect();
-                SqlQuery::CompoundSelect(csq)
+                    .map(|(op, sq)| Ok((op, rewrite_selection(sq, write_schemas)?)))
+                    .collect::<Result<_, String>>()?;
+                Ok(SqlQuery::CompoundSelect(csq))
             }
-            SqlQuery::Select(sq) => SqlQuery::Select(rewrite_selection(sq, write_schemas)),
+            SqlQuery::Select(sq) => Ok(SqlQuery::Select(rewrite_selection(sq, write_schemas)?)),
             SqlQuery::Insert(mut iq) => {
                 let table = iq.table.clone();
                 // Expand within field list
-                iq.fields = iq
-                    .fields
-                    .map(|fields| fields.into_iter().map(|c| set_table(c, &table)).collect());
-                SqlQuery::Insert(iq)
+                iq.fields = match iq.fields {
+                    Some(fields) => Some(
+                        fields
+                            .into_iter()
+                            .map(|c| set_table(c, &table))
+                            .collect::<Result<_, _>>()?,
+                    ),
+                    None => None,
+                };
+                Ok(SqlQuery::Insert(iq))
             }
-            _ => unreachable!(),
+            q => Err(format!("cannot expand implied tables in query: {}", q)),
         }
     }
 }
@@ -330,7 +360,7 @@ mod tests {
             vec!["id".into(), "title".into(), "text".into(), "author".into()],
         );
 
-        let res = SqlQuery::Select(q).expand_implied_tables(&schema);
+        let res = SqlQuery::Select(q).expand_implied_tables(&schema).unwrap();
         match res {
             SqlQuery::Select(tq) => {
                 assert_eq!(
@@ -353,4 +383,26 @@ mod tests {
             _ => panic!(),
         }
     }
+
+    #[test]
+    fn it_reports_unexpanded_stars_and_duplicate_columns() {
+        use nom_sql::SelectStatement;
+
+        let mut schema = HashMap::new();
+        schema.insert("users".into(), vec!["id".into(), "id".into()]);
+
+        let q = SelectStatement {
+            tables: vec![Table::from("users")],
+            fields: vec![FieldDefinitionExpression::All],
+            ..Default::default()
+        };
+        assert!(SqlQuery::Select(q).expand_implied_tables(&schema).is_err());
+
+        let q = SelectStatement {
+            tables: vec![Table::from("users")],
+            fields: vec![FieldDefinitionExpression::Col(Column::from("id"))],
+            ..Default::default()
+        };
+        assert!(SqlQuery::Select(q).expand_implied_tables(&schema).is_err());
+    }
 }
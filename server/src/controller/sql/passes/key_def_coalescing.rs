@@ -30,6 +30,27 @@ impl KeyDefinitionCoalescing for SqlQuery {
                         }
                     }
                 }
+
+                // unique keys over a single column become constraints on that column; those over
+                // several columns are passed to the base as they are.
+                if let Some(ref ks) = ctq.keys {
+                    for k in ks {
+                        if let TableKey::UniqueKey(_, ref cols) = *k {
+                            if cols.len() != 1 {
+                                continue;
+                            }
+                            let cs = ctq
+                                .fields
+                                .iter_mut()
+                                .find(|cs| cs.column.name == cols[0].name);
+                            if let Some(cs) = cs {
+                                if !cs.constraints.contains(&ColumnConstraint::Unique) {
+                                    cs.constraints.push(ColumnConstraint::Unique);
+                                }
+                            }
+                        }
+                    }
+                }
                 SqlQuery::CreateTable(ctq)
             }
             x => x,
@@ -88,4 +109,43 @@ mod tests {
             _ => panic!(),
         }
     }
+
+    #[test]
+    fn it_coalesces_unique_keys() {
+        use nom_sql::CreateTableStatement;
+
+        // CREATE TABLE t (id int, name text, UNIQUE KEY (name))
+        // -->
+        // CREATE TABLE t (id int, name text UNIQUE, UNIQUE KEY (name))
+        let q = CreateTableStatement {
+            table: Table::from("t"),
+            fields: vec![
+                ColumnSpecification::new(Column::from("t.id"), SqlType::Int(32)),
+                ColumnSpecification::new(Column::from("t.name"), SqlType::Text),
+            ],
+            keys: Some(vec![TableKey::UniqueKey(
+                None,
+                vec![Column::from("t.name")],
+            )]),
+        };
+
+        let res = SqlQuery::CreateTable(q).coalesce_key_definitions();
+        match res {
+            SqlQuery::CreateTable(ctq) => {
+                assert_eq!(
+                    ctq.fields,
+                    vec![
+                        ColumnSpecification::new(Column::from("t.id"), SqlType::Int(32)),
+                        ColumnSpecification::with_constraints(
+                            Column::from("t.name"),
+                            SqlType::Text,
+                            vec![ColumnConstraint::Unique],
+                        ),
+                    ]
+                );
+            }
+            // if we get anything other than a CreateTable back, something really weird is up
+            _ => panic!(),
+        }
+    }
 }
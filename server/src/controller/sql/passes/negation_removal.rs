@@ -5,10 +5,15 @@ use nom_sql::{
 use std::mem;
 
 pub trait NegationRemoval {
-    fn remove_negation(self) -> SqlQuery;
+    fn remove_negation(self) -> Result<SqlQuery, String>;
 }
 
-fn normalize_condition_expr(ce: &mut ConditionExpression, negate: bool) {
+/// Pushes all negations in `ce` down into its comparisons, negating the whole condition first if
+/// `negate` is set.
+///
+/// Returns `Ok(true)` if the condition turned out to hold for every row (as `NOT (a IN ())` does),
+/// in which case the caller should drop it, since there is no way to express that as a comparison.
+fn normalize_condition_expr(ce: &mut ConditionExpression, negate: bool) -> Result<bool, String> {
     match *ce {
         ConditionExpression::LogicalOp(ConditionTree {
             ref mut operator,
@@ -19,12 +24,47 @@ fn normalize_condition_expr(ce: &mut ConditionExpression, negate: bool) {
                 *operator = match *operator {
                     Operator::And => Operator::Or,
                     Operator::Or => Operator::And,
-                    _ => unreachable!(),
+                    ref op => return Err(format!("unsupported logical operator {}", op)),
                 };
             }
 
-            normalize_condition_expr(left, negate);
-            normalize_condition_expr(right, negate);
+            let left_true = normalize_condition_expr(left, negate)?;
+            let right_true = normalize_condition_expr(right, negate)?;
+            if *operator == Operator::Or || (left_true && right_true) {
+                return Ok(left_true || right_true);
+            } else if left_true {
+                *ce = mem::replace(&mut **right, placeholder());
+            } else if right_true {
+                *ce = mem::replace(&mut **left, placeholder());
+            }
+        }
+        ConditionExpression::ComparisonOp(ref ct) if negate && ct.operator == Operator::In => {
+            // NOT (a IN (x, y)) => a != x AND a != y
+            let values = match *ct.right {
+                ConditionExpression::Base(ConditionBase::LiteralList(ref ll)) => ll.clone(),
+                _ => return Err("NOT IN is only supported over a list of values".to_owned()),
+            };
+            let not_equal = |v: Literal| {
+                ConditionExpression::ComparisonOp(ConditionTree {
+                    operator: Operator::NotEqual,
+                    left: ct.left.clone(),
+                    right: Box::new(ConditionExpression::Base(ConditionBase::Literal(v))),
+                })
+            };
+            let mut values = values.into_iter();
+            let first = match values.next() {
+                Some(v) => not_equal(v),
+                // nothing is in an empty list
+                None => return Ok(true),
+            };
+            let expanded = values.fold(first, |l, v| {
+                ConditionExpression::LogicalOp(ConditionTree {
+                    operator: Operator::And,
+                    left: Box::new(l),
+                    right: Box::new(not_equal(v)),
+                })
+            });
+            *ce = expanded;
         }
         ConditionExpression::ComparisonOp(ConditionTree {
             ref mut operator,
@@ -39,47 +79,51 @@ fn normalize_condition_expr(ce: &mut ConditionExpression, negate: bool) {
                     Operator::GreaterOrEqual => Operator::Less,
                     Operator::Less => Operator::GreaterOrEqual,
                     Operator::LessOrEqual => Operator::Greater,
-                    _ => unreachable!(),
+                    ref op => return Err(format!("cannot negate comparison operator {}", op)),
                 };
             }
 
-            normalize_condition_expr(left, false);
-            normalize_condition_expr(right, false);
+            // operands of a comparison are values, so there is nothing to drop in them
+            normalize_condition_expr(left, false)?;
+            normalize_condition_expr(right, false)?;
         }
-        ConditionExpression::NegationOp(_) => {
-            let inner = if let ConditionExpression::NegationOp(ref mut inner) = *ce {
-                mem::replace(
-                    &mut **inner,
-                    ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)),
-                )
-            } else {
-                unreachable!()
-            };
+        ConditionExpression::NegationOp(ref mut inner) => {
+            let inner = mem::replace(&mut **inner, placeholder());
             *ce = inner;
-            normalize_condition_expr(ce, !negate);
+            return normalize_condition_expr(ce, !negate);
         }
         ConditionExpression::Bracketed(ref mut inner) => {
-            normalize_condition_expr(inner, negate);
+            return normalize_condition_expr(inner, negate);
         }
-        ConditionExpression::Base(_) => {}
-        ConditionExpression::Arithmetic(_) => unimplemented!(),
+        ConditionExpression::Base(_) | ConditionExpression::Arithmetic(_) => {}
     }
+    Ok(false)
+}
+
+fn placeholder() -> ConditionExpression {
+    ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder))
 }
 
 impl NegationRemoval for SqlQuery {
-    fn remove_negation(mut self) -> SqlQuery {
+    fn remove_negation(mut self) -> Result<SqlQuery, String> {
         if let SqlQuery::Select(ref mut s) = self {
-            if let Some(ref mut w) = s.where_clause {
-                normalize_condition_expr(w, false);
+            let always = match s.where_clause {
+                Some(ref mut w) => normalize_condition_expr(w, false)?,
+                None => false,
+            };
+            if always {
+                s.where_clause = None;
             }
 
             for j in s.join.iter_mut() {
                 if let JoinConstraint::On(ref mut ce) = j.constraint {
-                    normalize_condition_expr(ce, false);
+                    if normalize_condition_expr(ce, false)? {
+                        return Err("join condition holds for every pair of rows".to_owned());
+                    }
                 }
             }
         }
-        self
+        Ok(self)
     }
 }
 
@@ -119,7 +163,89 @@ mod tests {
             })),
         });
 
-        normalize_condition_expr(&mut expr, false);
+        assert_eq!(normalize_condition_expr(&mut expr, false), Ok(false));
         assert_eq!(expr, target);
     }
+
+    #[test]
+    fn it_expands_negated_in_lists() {
+        let mut expr = ConditionExpression::NegationOp(Box::new(
+            ConditionExpression::ComparisonOp(ConditionTree {
+                operator: Operator::In,
+                left: Box::new(ConditionExpression::Base(ConditionBase::Field("a".into()))),
+                right: Box::new(ConditionExpression::Base(ConditionBase::LiteralList(vec![
+                    Literal::Integer(1),
+                    Literal::Integer(2),
+                ]))),
+            }),
+        ));
+
+        let target = ConditionExpression::LogicalOp(ConditionTree {
+            operator: Operator::And,
+            left: Box::new(ConditionExpression::ComparisonOp(ConditionTree {
+                operator: Operator::NotEqual,
+                left: Box::new(ConditionExpression::Base(ConditionBase::Field("a".into()))),
+                right: Box::new(ConditionExpression::Base(ConditionBase::Literal(
+                    Literal::Integer(1),
+                ))),
+            })),
+            right: Box::new(ConditionExpression::ComparisonOp(ConditionTree {
+                operator: Operator::NotEqual,
+                left: Box::new(ConditionExpression::Base(ConditionBase::Field("a".into()))),
+                right: Box::new(ConditionExpression::Base(ConditionBase::Literal(
+                    Literal::Integer(2),
+                ))),
+            })),
+        });
+
+        assert_eq!(normalize_condition_expr(&mut expr, false), Ok(false));
+        assert_eq!(expr, target);
+    }
+
+    #[test]
+    fn it_drops_negated_empty_in_lists() {
+        let b_is_one = ConditionExpression::ComparisonOp(ConditionTree {
+            operator: Operator::Equal,
+            left: Box::new(ConditionExpression::Base(ConditionBase::Field("b".into()))),
+            right: Box::new(ConditionExpression::Base(ConditionBase::Literal(
+                Literal::Integer(1),
+            ))),
+        });
+        let not_in_empty = ConditionExpression::NegationOp(Box::new(
+            ConditionExpression::ComparisonOp(ConditionTree {
+                operator: Operator::In,
+                left: Box::new(ConditionExpression::Base(ConditionBase::Field("a".into()))),
+                right: Box::new(ConditionExpression::Base(ConditionBase::LiteralList(
+                    vec![],
+                ))),
+            }),
+        ));
+
+        let mut expr = ConditionExpression::LogicalOp(ConditionTree {
+            operator: Operator::And,
+            left: Box::new(not_in_empty.clone()),
+            right: Box::new(b_is_one.clone()),
+        });
+        assert_eq!(normalize_condition_expr(&mut expr, false), Ok(false));
+        assert_eq!(expr, b_is_one);
+
+        let mut expr = ConditionExpression::LogicalOp(ConditionTree {
+            operator: Operator::Or,
+            left: Box::new(not_in_empty),
+            right: Box::new(b_is_one),
+        });
+        assert_eq!(normalize_condition_expr(&mut expr, false), Ok(true));
+    }
+
+    #[test]
+    fn it_rejects_unsupported_negations() {
+        let mut expr = ConditionExpression::NegationOp(Box::new(
+            ConditionExpression::ComparisonOp(ConditionTree {
+                operator: Operator::In,
+                left: Box::new(ConditionExpression::Base(ConditionBase::Field("a".into()))),
+                right: Box::new(ConditionExpression::Base(ConditionBase::Field("b".into()))),
+            }),
+        ));
+        assert!(normalize_condition_expr(&mut expr, false).is_err());
+    }
 }
@@ -4,50 +4,46 @@ use std::collections::HashMap;
 use std::mem;
 
 pub trait StarExpansion {
-    fn expand_stars(self, write_schemas: &HashMap<String, Vec<String>>) -> SqlQuery;
+    fn expand_stars(self, write_schemas: &HashMap<String, Vec<String>>)
+        -> Result<SqlQuery, String>;
 }
 
 impl StarExpansion for SqlQuery {
-    fn expand_stars(mut self, write_schemas: &HashMap<String, Vec<String>>) -> SqlQuery {
-        let expand_table = |table_name: String| {
-            write_schemas
-                .get(&table_name)
-                .unwrap_or_else(|| panic!("table name `{}` does not exist", table_name))
-                .clone()
-                .into_iter()
-                .map(move |f| {
+    fn expand_stars(
+        mut self,
+        write_schemas: &HashMap<String, Vec<String>>,
+    ) -> Result<SqlQuery, String> {
+        let expand_table = |table_name: &str| -> Result<Vec<FieldDefinitionExpression>, String> {
+            let fields = write_schemas
+                .get(table_name)
+                .ok_or_else(|| format!("table name `{}` does not exist", table_name))?;
+            Ok(fields
+                .iter()
+                .map(|f| {
                     FieldDefinitionExpression::Col(Column::from(
                         format!("{}.{}", table_name, f).as_ref(),
                     ))
                 })
+                .collect())
         };
 
         if let SqlQuery::Select(ref mut sq) = self {
             let old_fields = mem::replace(&mut sq.fields, vec![]);
-            sq.fields = old_fields
-                .into_iter()
-                .flat_map(|field| match field {
+            for field in old_fields {
+                match field {
                     FieldDefinitionExpression::All => {
-                        let v: Vec<_> = sq
-                            .tables
-                            .iter()
-                            .map(|t| t.name.clone())
-                            .flat_map(&expand_table)
-                            .collect();
-                        v.into_iter()
+                        for t in &sq.tables {
+                            sq.fields.extend(expand_table(&t.name)?);
+                        }
                     }
                     FieldDefinitionExpression::AllInTable(t) => {
-                        let v: Vec<_> = expand_table(t).collect();
-                        v.into_iter()
+                        sq.fields.extend(expand_table(&t)?);
                     }
-                    e @ FieldDefinitionExpression::Value(_) => vec![e].into_iter(),
-                    FieldDefinitionExpression::Col(c) => {
-                        vec![FieldDefinitionExpression::Col(c)].into_iter()
-                    }
-                })
-                .collect();
+                    f => sq.fields.push(f),
+                }
+            }
         }
-        self
+        Ok(self)
     }
 }
 
@@ -74,7 +70,7 @@ mod tests {
         let mut schema = HashMap::new();
         schema.insert("PaperTag".into(), vec!["paper_id".into(), "tag_id".into()]);
 
-        let res = SqlQuery::Select(q).expand_stars(&schema);
+        let res = SqlQuery::Select(q).expand_stars(&schema).unwrap();
         // * selector has been expanded to field list
         match res {
             SqlQuery::Select(tq) => {
@@ -105,7 +101,7 @@ mod tests {
         schema.insert("PaperTag".into(), vec!["paper_id".into(), "tag_id".into()]);
         schema.insert("Users".into(), vec!["uid".into(), "name".into()]);
 
-        let res = SqlQuery::Select(q).expand_stars(&schema);
+        let res = SqlQuery::Select(q).expand_stars(&schema).unwrap();
         // * selector has been expanded to field list
         match res {
             SqlQuery::Select(tq) => {
@@ -141,7 +137,7 @@ mod tests {
         schema.insert("PaperTag".into(), vec!["paper_id".into(), "tag_id".into()]);
         schema.insert("Users".into(), vec!["uid".into(), "name".into()]);
 
-        let res = SqlQuery::Select(q).expand_stars(&schema);
+        let res = SqlQuery::Select(q).expand_stars(&schema).unwrap();
         // * selector has been expanded to field list
         match res {
             SqlQuery::Select(tq) => {
@@ -161,4 +157,18 @@ mod tests {
             _ => panic!(),
         }
     }
+
+    #[test]
+    fn it_reports_stars_over_unknown_tables() {
+        // SELECT Users.* FROM PaperTag
+        let q = SelectStatement {
+            tables: vec![Table::from("PaperTag")],
+            fields: vec![FieldDefinitionExpression::AllInTable("Users".into())],
+            ..Default::default()
+        };
+        let mut schema = HashMap::new();
+        schema.insert("PaperTag".into(), vec!["paper_id".into(), "tag_id".into()]);
+
+        assert!(SqlQuery::Select(q).expand_stars(&schema).is_err());
+    }
 }
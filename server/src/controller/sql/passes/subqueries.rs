@@ -1,5 +1,14 @@
+use crate::controller::sql::extensions::SelectExtensions;
+use crate::controller::sql::{EXISTS_MARKER, SCALAR_SUBQUERY_PREFIX};
+use dataflow::ops::semijoin::SemiJoinType;
 use nom_sql::ConditionExpression::*;
-use nom_sql::{Column, ConditionBase, ConditionExpression, JoinRightSide, SqlQuery};
+use nom_sql::{
+    ArithmeticBase, Column, ConditionBase, ConditionExpression, ConditionTree,
+    FieldDefinitionExpression, GroupByClause, JoinClause, JoinConstraint, JoinOperator,
+    JoinRightSide, Operator, SelectStatement, SqlQuery,
+};
+use std::collections::HashSet;
+use std::mem;
 
 #[derive(Debug, PartialEq)]
 pub enum Subquery<'a> {
@@ -11,6 +20,43 @@ pub trait SubQueries {
     fn extract_subqueries(&mut self) -> Vec<Subquery>;
 }
 
+/// Rewrites correlated subqueries, as well as `IN` and `EXISTS` subqueries, into joins against
+/// decorrelated versions of those subqueries, which then become views of their own when the
+/// subqueries are extracted.
+///
+/// A subquery in a `WHERE` conjunct becomes a semi-join against the subquery, or an anti-join if
+/// the subquery is negated, which follows the rules of `NOT IN` for `NULL`s unless it comes from
+/// `NOT EXISTS`. All of these are written as inner joins whose subquery alias marks them (see
+/// `decorrelated_join`). Scalar subqueries in the select list are already left joins by the time
+/// the query is parsed (see `scalar_subquery_join`), and are only given names unique to the query.
+///
+/// `ext` holds the extensions of the query, which follow the subqueries that are rewritten.
+pub trait SubqueryDecorrelation {
+    fn decorrelate_subqueries(
+        self,
+        prefix: &str,
+        ext: &mut SelectExtensions,
+    ) -> Result<SqlQuery, String>;
+}
+
+/// Whether `table` is a subquery that `decorrelate_subqueries` turned into the right side of a
+/// semi-join or an anti-join.
+pub fn decorrelated_join(table: &str) -> Option<SemiJoinType> {
+    let numbered = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let kinds = [
+        ("semi", SemiJoinType::Semi),
+        ("anti", SemiJoinType::Anti),
+        ("notin", SemiJoinType::NotIn),
+    ];
+    match table.split('_').collect::<Vec<_>>()[..] {
+        ["q", n, sq] if numbered(n) => kinds
+            .iter()
+            .find(|(kind, _)| sq.starts_with(kind) && numbered(&sq[kind.len()..]))
+            .map(|&(_, kind)| kind),
+        _ => None,
+    }
+}
+
 fn extract_subqueries_from_condition(ce: &mut ConditionExpression) -> Vec<Subquery> {
     use nom_sql::ConditionBase::NestedSelect;
     match *ce {
@@ -26,7 +72,8 @@ fn extract_subqueries_from_condition(ce: &mut ConditionExpression) -> Vec<Subque
             NestedSelect(_) => vec![Subquery::InComparison(cb)],
             _ => vec![],
         },
-        Arithmetic(_) => unimplemented!(),
+        // arithmetic expressions only ever contain columns and literals
+        Arithmetic(_) => vec![],
     }
 }
 
@@ -39,27 +86,356 @@ pub fn field_with_table_name(name: String, column: Column) -> ConditionBase {
     })
 }
 
-pub fn query_from_condition_base(cond: &ConditionBase) -> (SqlQuery, Column) {
+/// Returns the query that `cond` nests, along with the column that it selects first.
+pub fn query_from_condition_base(cond: &ConditionBase) -> Result<(SqlQuery, Column), String> {
     use nom_sql::ConditionBase::NestedSelect;
     use nom_sql::FieldDefinitionExpression;
-    let (sq, column);
     match *cond {
-        NestedSelect(ref bst) => {
-            sq = SqlQuery::Select(*bst.clone());
-            column = bst
-                .fields
-                .iter()
-                .map(|fe| match *fe {
-                    FieldDefinitionExpression::Col(ref c) => c.clone(),
-                    _ => unreachable!(),
-                })
-                .nth(0)
-                .unwrap();
+        NestedSelect(ref bst) => match bst.fields.first() {
+            Some(FieldDefinitionExpression::Col(ref c)) => {
+                Ok((SqlQuery::Select(*bst.clone()), c.clone()))
+            }
+            _ => Err("subqueries must select a column first".to_owned()),
+        },
+        ref cb => Err(format!("{:?} is not a subquery", cb)),
+    }
+}
+
+/// Names by which the tables of `st` can be referred to.
+fn table_names(st: &SelectStatement) -> HashSet<String> {
+    let name = |t: &nom_sql::Table| t.alias.clone().unwrap_or_else(|| t.name.clone());
+    let mut names: HashSet<String> = st.tables.iter().map(name).collect();
+    for jc in &st.join {
+        match jc.right {
+            JoinRightSide::Table(ref t) => {
+                names.insert(name(t));
+            }
+            JoinRightSide::Tables(ref ts) => names.extend(ts.iter().map(name)),
+            JoinRightSide::NestedSelect(_, Some(ref alias)) => {
+                names.insert(alias.clone());
+            }
+            _ => (),
+        }
+    }
+    names
+}
+
+fn split_conjunctions(ce: ConditionExpression, conjuncts: &mut Vec<ConditionExpression>) {
+    match ce {
+        LogicalOp(ConditionTree {
+            operator: Operator::And,
+            left,
+            right,
+        }) => {
+            split_conjunctions(*left, conjuncts);
+            split_conjunctions(*right, conjuncts);
+        }
+        Bracketed(inner) => match *inner {
+            inner @ LogicalOp(ConditionTree {
+                operator: Operator::And,
+                ..
+            }) => split_conjunctions(inner, conjuncts),
+            inner => conjuncts.push(Bracketed(Box::new(inner))),
+        },
+        ce => conjuncts.push(ce),
+    }
+}
+
+fn conjoin(conjuncts: Vec<ConditionExpression>) -> Option<ConditionExpression> {
+    conjuncts.into_iter().fold(None, |acc, ce| match acc {
+        None => Some(ce),
+        Some(acc) => Some(LogicalOp(ConditionTree {
+            operator: Operator::And,
+            left: Box::new(acc),
+            right: Box::new(ce),
+        })),
+    })
+}
+
+fn equality(left: Column, right: Column) -> ConditionExpression {
+    ComparisonOp(ConditionTree {
+        operator: Operator::Equal,
+        left: Box::new(Base(ConditionBase::Field(left))),
+        right: Box::new(Base(ConditionBase::Field(right))),
+    })
+}
+
+fn mentions_tables(ce: &ConditionExpression, tables: &HashSet<String>) -> bool {
+    let is_in = |c: &Column| c.table.as_ref().map_or(false, |t| tables.contains(t));
+    match *ce {
+        ComparisonOp(ref ct) | LogicalOp(ref ct) => {
+            mentions_tables(&ct.left, tables) || mentions_tables(&ct.right, tables)
+        }
+        NegationOp(ref inner) | Bracketed(ref inner) => mentions_tables(inner, tables),
+        Base(ConditionBase::Field(ref c)) => is_in(c),
+        Base(_) => false,
+        Arithmetic(ref ae) => [&ae.left, &ae.right].iter().any(|b| match **b {
+            ArithmeticBase::Column(ref c) => is_in(c),
+            ArithmeticBase::Scalar(_) => false,
+        }),
+    }
+}
+
+/// Removes the equality comparisons with columns of the `outer` tables from the `WHERE` clause of
+/// `sq`, and returns the pairs of outer and inner columns that they compared.
+fn decorrelate(
+    sq: &mut SelectStatement,
+    outer: &HashSet<String>,
+) -> Result<Vec<(Column, Column)>, String> {
+    let outer: HashSet<String> = outer.difference(&table_names(sq)).cloned().collect();
+    let is_outer = |c: &Column| c.table.as_ref().map_or(false, |t| outer.contains(t));
+
+    let mut conjuncts = Vec::new();
+    if let Some(ce) = sq.where_clause.take() {
+        split_conjunctions(ce, &mut conjuncts);
+    }
+    let mut pairs = Vec::new();
+    let mut rest = Vec::new();
+    for ce in conjuncts {
+        if let ComparisonOp(ConditionTree {
+            operator: Operator::Equal,
+            ref left,
+            ref right,
+        }) = ce
+        {
+            if let (&Base(ConditionBase::Field(ref l)), &Base(ConditionBase::Field(ref r))) =
+                (left.as_ref(), right.as_ref())
+            {
+                if is_outer(l) && !is_outer(r) {
+                    pairs.push((l.clone(), r.clone()));
+                    continue;
+                } else if is_outer(r) && !is_outer(l) {
+                    pairs.push((r.clone(), l.clone()));
+                    continue;
+                }
+            }
+        }
+        if mentions_tables(&ce, &outer) {
+            return Err(
+                "correlated subqueries may only compare outer columns for equality".to_owned(),
+            );
+        }
+        rest.push(ce);
+    }
+    sq.where_clause = conjoin(rest);
+    Ok(pairs)
+}
+
+/// Returns whether `ce` is a (possibly negated) `IN` comparison with a subquery, along with the
+/// column compared and the subquery.
+fn subquery_comparison(ce: &ConditionExpression) -> Option<(bool, &Column, &SelectStatement)> {
+    match *ce {
+        ComparisonOp(ConditionTree {
+            operator: Operator::In,
+            ref left,
+            ref right,
+        }) => match (left.as_ref(), right.as_ref()) {
+            (&Base(ConditionBase::Field(ref c)), &Base(ConditionBase::NestedSelect(ref sq))) => {
+                Some((false, c, sq))
+            }
+            _ => None,
+        },
+        NegationOp(ref inner) => {
+            subquery_comparison(inner).map(|(negated, c, sq)| (!negated, c, sq))
+        }
+        Bracketed(ref inner) => subquery_comparison(inner),
+        _ => None,
+    }
+}
+
+fn single_pair(mut pairs: Vec<(Column, Column)>) -> Result<(Column, Column), String> {
+    if pairs.len() != 1 {
+        return Err(
+            "subqueries must be correlated with the outer query on exactly one column".to_owned(),
+        );
+    }
+    Ok(pairs.pop().unwrap())
+}
+
+/// Returns a left join against `sq`, a scalar subquery in the select list of `st` whose result is
+/// projected as `name`, along with the column that projects it in place of the subquery.
+///
+/// The subquery is decorrelated into the join condition, and grouped by the column that it is
+/// correlated on if it aggregates, so that it is `NULL` rather than (for `COUNT`) zero for outer
+/// rows that nothing matches. Its alias starts with `SCALAR_SUBQUERY_PREFIX` until
+/// `decorrelate_subqueries` gives it a name that is unique to the query.
+pub fn scalar_subquery_join(
+    st: &SelectStatement,
+    name: &str,
+    mut sq: SelectStatement,
+) -> Result<(Column, JoinClause), String> {
+    let mut field = match sq.fields[..] {
+        [FieldDefinitionExpression::Col(ref c)] => c.clone(),
+        _ => return Err("scalar subqueries must select exactly one column".to_owned()),
+    };
+
+    let (outer_col, mut inner_col) = single_pair(decorrelate(&mut sq, &table_names(st))?)?;
+    if field.function.is_some() {
+        // aggregate over the rows that match each outer row
+        if sq.group_by.is_some() {
+            return Err("scalar subqueries cannot be grouped".to_owned());
         }
-        _ => unreachable!(),
+        sq.group_by = Some(GroupByClause {
+            columns: vec![inner_col.clone()],
+            having: None,
+        });
+        field.name = name.to_owned();
+    }
+    field.alias = Some(name.to_owned());
+    inner_col.alias = None;
+
+    let alias = format!("{}{}", SCALAR_SUBQUERY_PREFIX, name);
+    let constraint = JoinConstraint::On(equality(
+        outer_col,
+        Column {
+            name: inner_col.name.clone(),
+            alias: None,
+            table: Some(alias.clone()),
+            function: None,
+        },
+    ));
+    sq.fields = vec![
+        FieldDefinitionExpression::Col(inner_col),
+        FieldDefinitionExpression::Col(field),
+    ];
+    let column = Column {
+        name: name.to_owned(),
+        alias: Some(name.to_owned()),
+        table: Some(alias.clone()),
+        function: None,
     };
+    let join = JoinClause {
+        operator: JoinOperator::LeftJoin,
+        right: JoinRightSide::NestedSelect(Box::new(sq), Some(alias)),
+        constraint,
+    };
+    Ok((column, join))
+}
+
+impl SubqueryDecorrelation for SqlQuery {
+    fn decorrelate_subqueries(
+        self,
+        prefix: &str,
+        ext: &mut SelectExtensions,
+    ) -> Result<SqlQuery, String> {
+        let mut st = match self {
+            SqlQuery::Select(st) => st,
+            q => return Ok(q),
+        };
+        let mut subqueries = 0;
+        let mut next_alias = |kind: &str| {
+            subqueries += 1;
+            format!("{}_{}{}", prefix, kind, subqueries - 1)
+        };
+
+        // scalar subqueries became joins when the query was parsed (see `scalar_subquery_join`),
+        // and only need names that are unique to this query
+        for jc in &mut st.join {
+            let alias = match jc.right {
+                JoinRightSide::NestedSelect(_, Some(ref mut alias))
+                    if alias.starts_with(SCALAR_SUBQUERY_PREFIX) =>
+                {
+                    alias
+                }
+                _ => continue,
+            };
+            let old = mem::replace(alias, next_alias("sq"));
+            let new = alias.clone();
+            let mut rename = |c: &mut Column| {
+                if c.table.as_ref() == Some(&old) {
+                    c.table = Some(new.clone());
+                }
+            };
+            for f in &mut st.fields {
+                if let FieldDefinitionExpression::Col(ref mut c) = *f {
+                    rename(c);
+                }
+            }
+            if let JoinConstraint::On(ComparisonOp(ref mut ct)) = jc.constraint {
+                if let Base(ConditionBase::Field(ref mut c)) = *ct.left {
+                    rename(c);
+                }
+                if let Base(ConditionBase::Field(ref mut c)) = *ct.right {
+                    rename(c);
+                }
+            }
+        }
+
+        let outer = table_names(&st);
+        let mut conjuncts = Vec::new();
+        if let Some(ce) = st.where_clause.take() {
+            split_conjunctions(ce, &mut conjuncts);
+        }
+        let mut rest = Vec::new();
+        for ce in conjuncts {
+            let found = subquery_comparison(&ce)
+                .map(|(negated, column, sq)| (negated, column.clone(), Box::new(sq.clone())));
+            let (negated, column, mut sq) = match found {
+                Some(found) => found,
+                None => {
+                    rest.push(ce);
+                    continue;
+                }
+            };
+            if sq.group_by.is_some() || sq.limit.is_some() {
+                return Err("subqueries in WHERE cannot be grouped or limited".to_owned());
+            }
+            let original = sq.clone();
 
-    (sq, column)
+            let mut pairs = decorrelate(&mut sq, &outer)?;
+            let exists = column.name == EXISTS_MARKER && column.table.is_none();
+            if !exists {
+                // `x IN (SELECT c ...)` also correlates x with c
+                let mut inner_col = match sq.fields[..] {
+                    [FieldDefinitionExpression::Col(ref c)] if c.function.is_none() => c.clone(),
+                    _ => return Err("IN subqueries must select exactly one column".to_owned()),
+                };
+                inner_col.alias = None;
+                let mut column = column;
+                if column.table.is_none() {
+                    if st.tables.len() + st.join.len() != 1 {
+                        return Err(format!(
+                            "column \"{}\" compared with a subquery must name its table",
+                            column.name
+                        ));
+                    }
+                    let t = &st.tables[0];
+                    column.table = Some(t.alias.clone().unwrap_or_else(|| t.name.clone()));
+                }
+                pairs.push((column, inner_col));
+            }
+            let (outer_col, inner_col) = single_pair(pairs)?;
+
+            // the subquery only has to produce its join column, since semi- and anti-joins only
+            // check whether anything in it matches each outer row
+            let alias = next_alias(match (negated, exists) {
+                (false, _) => "semi",
+                (true, true) => "anti",
+                (true, false) => "notin",
+            });
+            sq.distinct = false;
+            sq.order = None;
+            sq.fields = vec![FieldDefinitionExpression::Col(inner_col.clone())];
+            ext.rewrite_subquery(&original, &sq);
+
+            st.join.push(JoinClause {
+                operator: JoinOperator::Join,
+                right: JoinRightSide::NestedSelect(sq, Some(alias.clone())),
+                constraint: JoinConstraint::On(equality(
+                    outer_col,
+                    Column {
+                        name: inner_col.name,
+                        alias: None,
+                        table: Some(alias),
+                        function: None,
+                    },
+                )),
+            });
+        }
+        st.where_clause = conjoin(rest);
+
+        Ok(SqlQuery::Select(st))
+    }
 }
 
 impl SubQueries for SqlQuery {
@@ -126,6 +502,89 @@ mod tests {
         assert_eq!(res, vec![Subquery::InComparison(&mut expected)]);
     }
 
+    fn decorrelated(sql: &str) -> SqlQuery {
+        use crate::controller::sql::{expand_unparseable, restore_expressions};
+        use nom_sql::parser::parse_query;
+
+        let (expanded, extracted) = expand_unparseable(sql).unwrap();
+        let mut q = parse_query(&expanded).unwrap();
+        restore_expressions(&mut q, &extracted).unwrap();
+        q.decorrelate_subqueries("q_1", &mut Default::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn it_decorrelates_exists() {
+        use nom_sql::parser::parse_query;
+
+        let q = decorrelated(
+            "SELECT posts.id FROM posts \
+             WHERE NOT EXISTS (SELECT * FROM reads WHERE reads.post = posts.id) \
+             AND posts.author = ?",
+        );
+        let expected = parse_query(
+            "SELECT posts.id FROM posts \
+             JOIN (SELECT reads.post FROM reads) AS q_1_anti0 \
+             ON (posts.id = q_1_anti0.post) \
+             WHERE posts.author = ?",
+        )
+        .unwrap();
+        assert_eq!(q, expected);
+        assert_eq!(decorrelated_join("q_1_anti0"), Some(SemiJoinType::Anti));
+    }
+
+    #[test]
+    fn it_decorrelates_in() {
+        use nom_sql::parser::parse_query;
+
+        let q = decorrelated(
+            "SELECT id FROM posts WHERE author IN (SELECT uid FROM banned WHERE banned.x = 1)",
+        );
+        let expected = parse_query(
+            "SELECT id FROM posts \
+             JOIN (SELECT uid FROM banned WHERE banned.x = 1) AS q_1_semi0 \
+             ON (posts.author = q_1_semi0.uid)",
+        )
+        .unwrap();
+        assert_eq!(q, expected);
+        assert_eq!(decorrelated_join("q_1_semi0"), Some(SemiJoinType::Semi));
+    }
+
+    #[test]
+    fn it_decorrelates_not_in() {
+        use nom_sql::parser::parse_query;
+
+        let q = decorrelated("SELECT id FROM posts WHERE author NOT IN (SELECT uid FROM banned)");
+        let expected = parse_query(
+            "SELECT id FROM posts \
+             JOIN (SELECT uid FROM banned) AS q_1_notin0 \
+             ON (posts.author = q_1_notin0.uid)",
+        )
+        .unwrap();
+        assert_eq!(q, expected);
+        assert_eq!(decorrelated_join("q_1_notin0"), Some(SemiJoinType::NotIn));
+        assert_eq!(decorrelated_join("q_1_notin"), None);
+    }
+
+    #[test]
+    fn it_decorrelates_scalar_subqueries() {
+        use nom_sql::parser::parse_query;
+
+        let q = decorrelated(
+            "SELECT users.id, (SELECT COUNT(*) FROM posts WHERE posts.author = users.id) AS n \
+             FROM users",
+        );
+        let expected = parse_query(
+            "SELECT users.id, q_1_sq0.n AS n FROM users \
+             LEFT JOIN (SELECT posts.author, count(*) AS n FROM posts \
+                        GROUP BY posts.author) AS q_1_sq0 \
+             ON (users.id = q_1_sq0.author)",
+        )
+        .unwrap();
+        assert_eq!(q, expected);
+        assert_eq!(decorrelated_join("q_1_sq0"), None);
+    }
+
     #[test]
     fn it_does_nothing_for_flat_queries() {
         // select userid from role where type=1
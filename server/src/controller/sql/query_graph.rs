@@ -2,9 +2,17 @@ use nom_sql::SelectStatement;
 use nom_sql::{
     ArithmeticBase, ArithmeticExpression, Column, ConditionBase, ConditionExpression,
     ConditionTree, FieldDefinitionExpression, FieldValueExpression, JoinConstraint, JoinOperator,
-    JoinRightSide, Literal, Operator, Table,
+    JoinRightSide, LimitClause, Literal, Operator, Table,
 };
 
+use super::expression::Expression;
+use super::extensions::{OuterJoin, SelectExtensions};
+use super::passes::subqueries::decorrelated_join;
+use super::PAGE_COLUMN;
+use dataflow::node::special::RangeParameter;
+use dataflow::ops::grouped::aggregate::Aggregation;
+use dataflow::ops::semijoin::SemiJoinType;
+
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
@@ -25,11 +33,20 @@ pub struct ArithmeticColumn {
     pub expression: ArithmeticExpression,
 }
 
+/// A select list expression that nom-sql cannot parse (see `expression::Expression`).
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ExpressionColumn {
+    pub name: String,
+    pub table: Option<String>,
+    pub expression: Expression,
+}
+
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum OutputColumn {
     Data(Column),
     Arithmetic(ArithmeticColumn),
     Literal(LiteralColumn),
+    Expression(ExpressionColumn),
 }
 
 impl Ord for OutputColumn {
@@ -49,6 +66,11 @@ impl Ord for OutputColumn {
                 ref name,
                 ref table,
                 ..
+            })
+            | OutputColumn::Expression(ExpressionColumn {
+                ref name,
+                ref table,
+                ..
             }) => match *other {
                 OutputColumn::Arithmetic(ArithmeticColumn {
                     name: ref other_name,
@@ -64,6 +86,11 @@ impl Ord for OutputColumn {
                     name: ref other_name,
                     table: ref other_table,
                     ..
+                })
+                | OutputColumn::Expression(ExpressionColumn {
+                    name: ref other_name,
+                    table: ref other_table,
+                    ..
                 }) => {
                     if table.is_some() && other_table.is_some() {
                         match table.cmp(&other_table) {
@@ -96,6 +123,11 @@ impl PartialOrd for OutputColumn {
                 ref name,
                 ref table,
                 ..
+            })
+            | OutputColumn::Expression(ExpressionColumn {
+                ref name,
+                ref table,
+                ..
             }) => match *other {
                 OutputColumn::Arithmetic(ArithmeticColumn {
                     name: ref other_name,
@@ -111,6 +143,11 @@ impl PartialOrd for OutputColumn {
                     name: ref other_name,
                     table: ref other_table,
                     ..
+                })
+                | OutputColumn::Expression(ExpressionColumn {
+                    name: ref other_name,
+                    table: ref other_table,
+                    ..
                 }) => {
                     if table.is_some() && other_table.is_some() {
                         match table.cmp(&other_table) {
@@ -147,6 +184,10 @@ pub struct QueryGraphNode {
 pub enum QueryGraphEdge {
     Join(Vec<ConditionTree>),
     LeftJoin(Vec<ConditionTree>),
+    FullJoin(Vec<ConditionTree>),
+    SemiJoin(Vec<ConditionTree>),
+    AntiJoin(Vec<ConditionTree>),
+    NotIn(Vec<ConditionTree>),
     GroupBy(Vec<Column>),
 }
 
@@ -164,6 +205,18 @@ pub struct QueryGraph {
     pub join_order: Vec<JoinRef>,
     /// Global predicates (not associated with a particular relation)
     pub global_predicates: Vec<ConditionExpression>,
+    /// Column compared against a placeholder with `<`, `<=`, `>` or `>=`, if any, along with
+    /// which of its bounds the query sets and whether they are inclusive. It is keyed on after
+    /// all equality parameters, and the reader for the query serves range lookups on it.
+    pub range_parameter: Option<(Column, RangeParameter)>,
+    /// Page number column that the reader is keyed on (after all other parameters) if the query
+    /// has an `OFFSET ?` clause.
+    pub page_parameter: Option<Column>,
+    /// The query's LIMIT and OFFSET, if any.
+    pub limit: Option<LimitClause>,
+    /// Aggregation that each `GROUP_CONCAT` computed column, by name, computes instead, if the
+    /// query called an aggregate function that nom-sql cannot parse (see `expand_aggregates`).
+    pub aggregates: Vec<(String, Aggregation)>,
 }
 
 impl QueryGraph {
@@ -174,18 +227,27 @@ impl QueryGraph {
             columns: Vec::new(),
             join_order: Vec::new(),
             global_predicates: Vec::new(),
+            range_parameter: None,
+            page_parameter: None,
+            limit: None,
+            aggregates: Vec::new(),
         }
     }
 
     /// Returns the set of columns on which this query is parameterized. They can come from
-    /// multiple tables involved in the query.
+    /// multiple tables involved in the query. The range or page parameter, if any, always comes
+    /// last.
     pub fn parameters<'a>(&'a self) -> Vec<&'a Column> {
-        self.relations
-            .values()
-            .fold(Vec::new(), |mut acc: Vec<&'a Column>, qgn| {
-                acc.extend(qgn.parameters.iter());
-                acc
-            })
+        let mut params =
+            self.relations
+                .values()
+                .fold(Vec::new(), |mut acc: Vec<&'a Column>, qgn| {
+                    acc.extend(qgn.parameters.iter());
+                    acc
+                });
+        params.extend(self.range_parameter.iter().map(|&(ref c, _)| c));
+        params.extend(self.page_parameter.iter());
+        params
     }
 
     pub fn exact_hash(&self) -> u64 {
@@ -215,6 +277,10 @@ impl Hash for QueryGraph {
         self.columns.hash(state);
         self.join_order.hash(state);
         self.global_predicates.hash(state);
+        self.range_parameter.hash(state);
+        self.page_parameter.hash(state);
+        self.limit.hash(state);
+        self.aggregates.hash(state);
     }
 }
 
@@ -255,8 +321,8 @@ fn classify_conditionals(
     local: &mut HashMap<String, Vec<ConditionExpression>>,
     join: &mut Vec<ConditionTree>,
     global: &mut Vec<ConditionExpression>,
-    params: &mut Vec<Column>,
-) {
+    params: &mut Vec<(Column, Operator)>,
+) -> Result<(), String> {
     // Handling OR and AND expressions requires some care as there are some corner cases.
     //    a) we don't support OR expressions with predicates with placeholder parameters,
     //       because these expressions are meaningless in the Soup context.
@@ -287,7 +353,7 @@ fn classify_conditionals(
                 &mut new_join,
                 &mut new_global,
                 &mut new_params,
-            );
+            )?;
             classify_conditionals(
                 ct.right.as_ref(),
                 tables,
@@ -295,30 +361,26 @@ fn classify_conditionals(
                 &mut new_join,
                 &mut new_global,
                 &mut new_params,
-            );
+            )?;
 
             match ct.operator {
                 Operator::And => {
                     //
                     for (t, ces) in new_local {
-                        // conjunction, check if either side had a local predicate
-                        assert!(
-                            ces.len() <= 2,
-                            "can only combine two or fewer ConditionExpression's"
-                        );
-                        if ces.len() == 2 {
-                            let new_ce = ConditionExpression::LogicalOp(ConditionTree {
+                        // conjunction, combine all local predicates that either side had on
+                        // this table
+                        let mut ces = ces.into_iter();
+                        let first = ces.next().unwrap();
+                        let new_ce = ces.fold(first, |l, r| {
+                            ConditionExpression::LogicalOp(ConditionTree {
                                 operator: Operator::And,
-                                left: Box::new(ces.first().unwrap().clone()),
-                                right: Box::new(ces.last().unwrap().clone()),
-                            });
-
-                            let e = local.entry(t.to_string()).or_default();
-                            e.push(new_ce);
-                        } else {
-                            let e = local.entry(t.to_string()).or_default();
-                            e.extend(ces);
-                        }
+                                left: Box::new(l),
+                                right: Box::new(r),
+                            })
+                        });
+
+                        let e = local.entry(t.to_string()).or_default();
+                        e.push(new_ce);
                     }
 
                     // one side of the AND might be a global predicate, so we need to keep
@@ -326,18 +388,22 @@ fn classify_conditionals(
                     global.extend(new_global);
                 }
                 Operator::Or => {
-                    assert!(
-                        new_join.is_empty(),
-                        "can't handle OR expressions between join predicates"
-                    );
-                    assert!(
-                        new_params.is_empty(),
-                        "can't handle OR expressions between query parameter predicates"
-                    );
-                    if new_local.keys().len() == 1 && new_global.is_empty() {
+                    if !new_join.is_empty() {
+                        return Err(String::from(
+                            "can't handle OR expressions between join predicates",
+                        ));
+                    }
+                    if !new_params.is_empty() {
+                        return Err(String::from(
+                            "can't handle OR expressions between query parameter predicates",
+                        ));
+                    }
+                    let single_table = new_local.keys().len() == 1
+                        && new_local.values().all(|ces| ces.len() == 2)
+                        && new_global.is_empty();
+                    if single_table {
                         // OR over a single table => local predicate
                         let (t, ces) = new_local.into_iter().next().unwrap();
-                        assert_eq!(ces.len(), 2, "should combine only 2 ConditionExpressions");
                         let new_ce = ConditionExpression::LogicalOp(ConditionTree {
                             operator: Operator::Or,
                             left: Box::new(ces.first().unwrap().clone()),
@@ -357,6 +423,31 @@ fn classify_conditionals(
             join.extend(new_join);
             params.extend(new_params);
         }
+        ConditionExpression::ComparisonOp(ref ct)
+            if is_arithmetic(&ct.left) || is_arithmetic(&ct.right) =>
+        {
+            // comparison involving an arithmetic expression; this is a local predicate if all the
+            // columns it mentions come from the same table, and a global predicate otherwise
+            if let ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)) =
+                *ct.right.as_ref()
+            {
+                return Err(format!(
+                    "query parameters cannot be compared to arithmetic expressions: {}",
+                    ce
+                ));
+            }
+            let mut tables_mentioned: Vec<_> = operand_columns(&ct.left)
+                .into_iter()
+                .chain(operand_columns(&ct.right))
+                .map(|c| c.table.as_ref())
+                .collect();
+            tables_mentioned.sort();
+            tables_mentioned.dedup();
+            match tables_mentioned.as_slice() {
+                [Some(t)] => local.entry(t.to_string()).or_default().push(ce.clone()),
+                _ => global.push(ce.clone()),
+            }
+        }
         ConditionExpression::ComparisonOp(ref ct) => {
             // atomic selection predicate
             if let ConditionExpression::Base(ref l) = *ct.left.as_ref() {
@@ -387,8 +478,10 @@ fn classify_conditionals(
                                         }
                                         join.push(join_ct);
                                     } else {
-                                        // non-equi-join?
-                                        unimplemented!();
+                                        return Err(format!(
+                                            "non-equi-joins are not supported: {}",
+                                            ce
+                                        ));
                                     }
                                 } else {
                                     // not a comma join, just an ordinary comparison with a
@@ -398,17 +491,21 @@ fn classify_conditionals(
                                     global.push(ce.clone());
                                 }
                             } else {
-                                panic!("left hand side of comparison must be field");
+                                return Err(format!(
+                                    "left hand side of comparison must be field: {}",
+                                    ce
+                                ));
                             }
                         }
                         // right-hand side is a placeholder, so this must be a query parameter
                         ConditionBase::Literal(Literal::Placeholder) => {
                             if let ConditionBase::Field(ref lf) = *l {
-                                params.push(lf.clone());
+                                params.push((lf.clone(), ct.operator.clone()));
                             }
                         }
-                        // right-hand side is a non-placeholder literal, so this is a predicate
-                        ConditionBase::Literal(_) => {
+                        // right-hand side is a non-placeholder literal or list of literals, so
+                        // this is a predicate
+                        ConditionBase::Literal(_) | ConditionBase::LiteralList(_) => {
                             if let ConditionBase::Field(ref lf) = *l {
                                 // we assume that implied table names have previously been expanded
                                 // and thus all non-computed columns carry table names
@@ -422,85 +519,122 @@ fn classify_conditionals(
                                 }
                             }
                         }
-                        ConditionBase::LiteralList(_) => (),
-                        ConditionBase::NestedSelect(_) => unimplemented!(),
+                        ConditionBase::NestedSelect(_) => {
+                            return Err("nested SELECT in comparisons is not supported".to_owned());
+                        }
                     }
                 };
             };
         }
         ConditionExpression::Bracketed(ref inner) => {
-            let mut new_params = Vec::new();
-            let mut new_join = Vec::new();
-            let mut new_local = HashMap::new();
-            classify_conditionals(
-                inner.as_ref(),
-                tables,
-                &mut new_local,
-                &mut new_join,
-                global,
-                &mut new_params,
-            );
-            join.extend(new_join);
-            params.extend(new_params);
+            classify_conditionals(inner.as_ref(), tables, local, join, global, params)?;
         }
         ConditionExpression::Base(_) => {
             // don't expect to see a base here: we ought to exit when classifying its
             // parent selection predicate
-            panic!("encountered unexpected standalone base of condition expression");
+            return Err("condition is not a comparison".to_owned());
         }
         ConditionExpression::NegationOp(_) => {
-            panic!("negation should have been removed earlier");
+            return Err("negated condition could not be rewritten".to_owned());
+        }
+        ConditionExpression::Arithmetic(_) => {
+            // don't expect to see arithmetic here: it is only ever an operand of a comparison
+            return Err("arithmetic expression used as a condition".to_owned());
+        }
+    }
+
+    Ok(())
+}
+
+fn is_arithmetic(ce: &ConditionExpression) -> bool {
+    match *ce {
+        ConditionExpression::Arithmetic(_) => true,
+        _ => false,
+    }
+}
+
+/// Returns the columns mentioned by an operand of a comparison.
+fn operand_columns(ce: &ConditionExpression) -> Vec<&Column> {
+    let mut cols = Vec::new();
+    match *ce {
+        ConditionExpression::Base(ConditionBase::Field(ref c)) => cols.push(c),
+        ConditionExpression::Arithmetic(ref ae) => {
+            if let ArithmeticBase::Column(ref c) = ae.left {
+                cols.push(c);
+            }
+            if let ArithmeticBase::Column(ref c) = ae.right {
+                cols.push(c);
+            }
         }
-        ConditionExpression::Arithmetic(_) => unimplemented!(),
+        _ => (),
     }
+    cols
 }
 
 #[allow(clippy::cognitive_complexity)]
-pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
+pub fn to_query_graph(st: &SelectStatement, ext: &SelectExtensions) -> Result<QueryGraph, String> {
     let mut qg = QueryGraph::new();
 
     // a handy closure for making new relation nodes
-    let new_node =
-        |rel: String, preds: Vec<ConditionExpression>, st: &SelectStatement| -> QueryGraphNode {
-            QueryGraphNode {
-                rel_name: rel.clone(),
-                predicates: preds,
-                columns: st
-                    .fields
-                    .iter()
-                    .filter_map(|field| match *field {
-                        // unreachable because SQL rewrite passes will have expanded these already
-                        FieldDefinitionExpression::All => unreachable!(),
-                        FieldDefinitionExpression::AllInTable(_) => unreachable!(),
-                        // No need to do anything for literals and arithmetic expressions here, as they
-                        // aren't associated with a relation (and thus have no QGN)
-                        FieldDefinitionExpression::Value(_) => None,
-                        FieldDefinitionExpression::Col(ref c) => {
-                            match c.table.as_ref() {
+    let new_node = |rel: String,
+                    preds: Vec<ConditionExpression>,
+                    st: &SelectStatement|
+     -> Result<QueryGraphNode, String> {
+        let mut columns: Vec<Column> = Vec::new();
+        for field in &st.fields {
+            match *field {
+                // the SQL rewrite passes will have expanded these already
+                FieldDefinitionExpression::All | FieldDefinitionExpression::AllInTable(_) => {
+                    return Err("stars should have been expanded by now".to_owned());
+                }
+                // No need to do anything for literals and arithmetic expressions here, as they
+                // aren't associated with a relation (and thus have no QGN)
+                FieldDefinitionExpression::Value(_) => (),
+                FieldDefinitionExpression::Col(ref c) => {
+                    match c.table.as_ref() {
+                        None => {
+                            match c.function {
+                                // XXX(malte): don't drop aggregation columns
+                                Some(_) => (),
+                                // expressions are computed from the columns that they
+                                // refer to, which are added below
+                                None if Expression::from_column(c).is_some() => (),
                                 None => {
-                                    match c.function {
-                                        // XXX(malte): don't drop aggregation columns
-                                        Some(_) => None,
-                                        None => panic!(
-                                            "No table name set for column {} on {}",
-                                            c.name, rel
-                                        ),
-                                    }
-                                }
-                                Some(t) => {
-                                    if *t == rel {
-                                        Some(c.clone())
-                                    } else {
-                                        None
-                                    }
+                                    return Err(format!(
+                                        "column {} does not belong to any table in the query",
+                                        c.name
+                                    ));
                                 }
                             }
                         }
-                    })
-                    .collect(),
-                parameters: Vec::new(),
+                        Some(t) => {
+                            if *t == rel {
+                                columns.push(c.clone());
+                            }
+                        }
+                    }
+                }
             }
-        };
+        }
+        // the columns that expressions refer to must be carried along to the final projection
+        for field in &st.fields {
+            if let FieldDefinitionExpression::Col(ref c) = *field {
+                if let Some(mut e) = Expression::from_column(c) {
+                    e.for_each_column(&mut |c: &mut Column| {
+                        if c.table.as_ref() == Some(&rel) && !columns.contains(c) {
+                            columns.push(c.clone());
+                        }
+                    });
+                }
+            }
+        }
+        Ok(QueryGraphNode {
+            rel_name: rel.clone(),
+            predicates: preds,
+            columns,
+            parameters: Vec::new(),
+        })
+    };
 
     // 1. Add any relations mentioned in the query to the query graph.
     // This is needed so that we don't end up with an empty query graph when there are no
@@ -508,7 +642,7 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
     for table in &st.tables {
         qg.relations.insert(
             table.name.clone(),
-            new_node(table.name.clone(), Vec::new(), st),
+            new_node(table.name.clone(), Vec::new(), st)?,
         );
     }
     for jc in &st.join {
@@ -517,11 +651,11 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                 if !qg.relations.contains_key(&table.name) {
                     qg.relations.insert(
                         table.name.clone(),
-                        new_node(table.name.clone(), Vec::new(), st),
+                        new_node(table.name.clone(), Vec::new(), st)?,
                     );
                 }
             }
-            _ => unimplemented!(),
+            _ => return Err("joins on nested SELECTs are not supported".to_owned()),
         }
     }
 
@@ -534,13 +668,19 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
     };
     // 2a. Explicit joins
     // The table specified in the query is available for USING joins.
-    let prev_table = Some(st.tables.last().as_ref().unwrap().name.clone());
-    for jc in &st.join {
+    let prev_table = Some(
+        st.tables
+            .last()
+            .ok_or_else(|| "query does not select from any table".to_owned())?
+            .name
+            .clone(),
+    );
+    for (i, jc) in st.join.iter().enumerate() {
         match jc.right {
             JoinRightSide::Table(ref table) => {
                 // will be defined by join constraint
-                let left_table;
-                let right_table;
+                let mut left_table;
+                let mut right_table;
 
                 let join_pred = match jc.constraint {
                     JoinConstraint::On(ref cond) => {
@@ -560,7 +700,12 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                                     if tables_mentioned[1] != table.name {
                                         // tables are in the wrong order in join predicate, swap
                                         tables_mentioned.swap(0, 1);
-                                        assert_eq!(tables_mentioned[1], table.name);
+                                        if tables_mentioned[1] != table.name {
+                                            return Err(format!(
+                                                "join condition does not refer to joined table \"{}\"",
+                                                table.name
+                                            ));
+                                        }
                                     }
                                     left_table = tables_mentioned.remove(0);
                                     right_table = tables_mentioned.remove(0);
@@ -569,7 +714,8 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                                     left_table = tables_mentioned.remove(0);
                                     right_table = left_table.clone();
                                 } else {
-                                    unreachable!("more than 2 tables mentioned in join condition!");
+                                    return Err("join conditions must refer to one or two tables"
+                                        .to_owned());
                                 };
 
                                 // the condition tree might specify tables in opposite order to
@@ -578,11 +724,19 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                                 // conditions for now.
                                 let l = match *ct.left.as_ref() {
                                     ConditionExpression::Base(ConditionBase::Field(ref f)) => f,
-                                    _ => unimplemented!(),
+                                    _ => {
+                                        return Err(
+                                            "join conditions must compare columns".to_owned()
+                                        );
+                                    }
                                 };
                                 let r = match *ct.right.as_ref() {
                                     ConditionExpression::Base(ConditionBase::Field(ref f)) => f,
-                                    _ => unimplemented!(),
+                                    _ => {
+                                        return Err(
+                                            "join conditions must compare columns".to_owned()
+                                        );
+                                    }
                                 };
                                 if *l.table.as_ref().unwrap() == right_table
                                     && *r.table.as_ref().unwrap() == left_table
@@ -596,11 +750,15 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                                     ct.clone()
                                 }
                             }
-                            _ => panic!("join condition is not a comparison!"),
+                            _ => {
+                                return Err("join condition is not a single comparison".to_owned());
+                            }
                         }
                     }
                     JoinConstraint::Using(ref cols) => {
-                        assert_eq!(cols.len(), 1);
+                        if cols.len() != 1 {
+                            return Err("USING joins must name exactly one column".to_owned());
+                        }
                         let col = cols.iter().next().unwrap();
 
                         left_table = prev_table.as_ref().unwrap().clone();
@@ -615,18 +773,58 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                 };
 
                 // add edge for join
-                let mut _e = qg
-                    .edges
-                    .entry((left_table.clone(), right_table.clone()))
-                    .or_insert_with(|| match jc.operator {
-                        JoinOperator::LeftJoin => QueryGraphEdge::LeftJoin(vec![join_pred]),
-                        JoinOperator::Join | JoinOperator::InnerJoin => {
-                            QueryGraphEdge::Join(vec![join_pred])
+                let edge = match ext.outer_join(i) {
+                    // nom-sql cannot parse RIGHT and FULL OUTER JOINs, so the SQL front end
+                    // records them next to the query (see `expand_joins`)
+                    Some(OuterJoin::Right) => {
+                        // a right join is a left join with the tables swapped
+                        let join_pred = ConditionTree {
+                            operator: join_pred.operator,
+                            left: join_pred.right,
+                            right: join_pred.left,
+                        };
+                        std::mem::swap(&mut left_table, &mut right_table);
+                        QueryGraphEdge::LeftJoin(vec![join_pred])
+                    }
+                    Some(OuterJoin::Full) => QueryGraphEdge::FullJoin(vec![join_pred]),
+                    None => match jc.operator {
+                        JoinOperator::LeftJoin | JoinOperator::LeftOuterJoin => {
+                            QueryGraphEdge::LeftJoin(vec![join_pred])
                         }
-                        _ => unimplemented!(),
-                    });
+                        // joins against decorrelated `IN` and `EXISTS` subqueries only filter the
+                        // rows on the left
+                        JoinOperator::Join
+                        | JoinOperator::InnerJoin
+                        | JoinOperator::CrossJoin
+                        | JoinOperator::StraightJoin => match decorrelated_join(&table.name) {
+                            Some(SemiJoinType::Semi) => QueryGraphEdge::SemiJoin(vec![join_pred]),
+                            Some(SemiJoinType::Anti) => QueryGraphEdge::AntiJoin(vec![join_pred]),
+                            Some(SemiJoinType::NotIn) => QueryGraphEdge::NotIn(vec![join_pred]),
+                            None => QueryGraphEdge::Join(vec![join_pred]),
+                        },
+                    },
+                };
+                qg.edges
+                    .entry((left_table.clone(), right_table.clone()))
+                    .or_insert(edge);
+            }
+            _ => return Err("joins on nested SELECTs are not supported".to_owned()),
+        }
+    }
+
+    // A query with `OFFSET ?` serves one page of `LIMIT` results at a time, and its reader is keyed
+    // on the page number in addition to the query's other parameters.
+    if ext.paginated {
+        match st.limit {
+            Some(ref limit) if limit.limit > 0 => {
+                qg.page_parameter = Some(Column {
+                    name: String::from(PAGE_COLUMN),
+                    alias: None,
+                    table: None,
+                    function: None,
+                });
             }
-            _ => unimplemented!(),
+            _ => return Err(String::from("OFFSET ? requires a non-zero LIMIT")),
         }
     }
 
@@ -642,7 +840,7 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
             &mut join_predicates,
             &mut global_predicates,
             &mut query_parameters,
-        );
+        )?;
 
         for (_, ces) in local_predicates.iter_mut() {
             *ces = split_conjunctions(ces.clone());
@@ -653,10 +851,10 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
             if !qg.relations.contains_key(&rel) {
                 // can't have predicates on tables that do not appear in the FROM part of the
                 // statement
-                panic!(
-                    "predicate(s) {:?} on relation {} that is not in query graph",
-                    preds, rel
-                );
+                return Err(format!(
+                    "predicate on table \"{}\", which does not appear in the query",
+                    rel
+                ));
             } else {
                 qg.relations.get_mut(&rel).unwrap().predicates.extend(preds);
             }
@@ -667,22 +865,35 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
             // We have a ConditionExpression, but both sides of it are ConditionBase of type Field
             if let ConditionExpression::Base(ConditionBase::Field(ref l)) = *jp.left.as_ref() {
                 if let ConditionExpression::Base(ConditionBase::Field(ref r)) = *jp.right.as_ref() {
+                    let (lt, rt) = match (l.table.clone(), r.table.clone()) {
+                        (Some(lt), Some(rt)) => (lt, rt),
+                        _ => {
+                            return Err(format!(
+                                "join condition {} refers to a column without a table",
+                                jp
+                            ));
+                        }
+                    };
                     // If tables aren't already in the relations, add them.
-                    qg.relations
-                        .entry(l.table.clone().unwrap())
-                        .or_insert_with(|| new_node(l.table.clone().unwrap(), Vec::new(), st));
-
-                    qg.relations
-                        .entry(r.table.clone().unwrap())
-                        .or_insert_with(|| new_node(r.table.clone().unwrap(), Vec::new(), st));
+                    for t in &[&lt, &rt] {
+                        if !qg.relations.contains_key(*t) {
+                            let n = new_node((*t).clone(), Vec::new(), st)?;
+                            qg.relations.insert((*t).clone(), n);
+                        }
+                    }
 
                     let e = qg
                         .edges
-                        .entry((l.table.clone().unwrap(), r.table.clone().unwrap()))
+                        .entry((lt, rt))
                         .or_insert_with(|| QueryGraphEdge::Join(vec![]));
                     match *e {
                         QueryGraphEdge::Join(ref mut preds) => preds.push(jp.clone()),
-                        _ => panic!("Expected join edge for join condition {:#?}", jp),
+                        _ => {
+                            return Err(format!(
+                                "join condition {} is between grouped relations",
+                                jp
+                            ));
+                        }
                     };
                 }
             }
@@ -692,49 +903,144 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
         //    node for this query. Such columns will be carried all the way through the operators
         //    implementing the query (unlike in a traditional query plan, where the predicates on
         //    parameters might be evaluated sooner).
-        for column in query_parameters.into_iter() {
-            match column.table {
-                None => panic!("each parameter's column must have an associated table!"),
-                Some(ref table) => {
-                    let rel = qg.relations.get_mut(table).unwrap();
-                    if !rel.columns.contains(&column) {
-                        rel.columns.push(column.clone());
+        //    Parameters compared by `<`, `<=`, `>` or `>=` become the query's range parameter
+        //    instead; the reader keys on it last so that lookups can scan a range of its values.
+        for (column, operator) in query_parameters.into_iter() {
+            let table = match column.table {
+                None => {
+                    return Err(format!(
+                        "parameter column \"{}\" is not associated with a table",
+                        column.name
+                    ));
+                }
+                Some(ref table) => table.clone(),
+            };
+            let rel = match qg.relations.get_mut(&table) {
+                Some(rel) => rel,
+                None => {
+                    return Err(format!(
+                        "parameter on table \"{}\", which does not appear in the query",
+                        table
+                    ));
+                }
+            };
+            if !rel.columns.contains(&column) {
+                rel.columns.push(column.clone());
+            }
+            match operator {
+                Operator::Less
+                | Operator::LessOrEqual
+                | Operator::Greater
+                | Operator::GreaterOrEqual => {
+                    let (ref rc, ref mut range) = *qg
+                        .range_parameter
+                        .get_or_insert_with(|| (column.clone(), RangeParameter::default()));
+                    if *rc != column {
+                        return Err(format!(
+                            "only one column can be compared to range parameters, \
+                             but found both {} and {}",
+                            rc, column
+                        ));
+                    }
+                    let (end, inclusive) = match operator {
+                        Operator::Greater => (&mut range.lower_inclusive, false),
+                        Operator::GreaterOrEqual => (&mut range.lower_inclusive, true),
+                        Operator::Less => (&mut range.upper_inclusive, false),
+                        _ => (&mut range.upper_inclusive, true),
+                    };
+                    if end.is_some() {
+                        return Err(format!(
+                            "column {} is bounded by more than one parameter on the same side",
+                            column
+                        ));
                     }
-                    // the parameter column is included in the projected columns of the output, but
-                    // we also separately register it as a parameter so that we can set keys
+                    *end = Some(inclusive);
+                }
+                _ => {
+                    // the parameter column is included in the projected columns of the output,
+                    // but we also separately register it as a parameter so that we can set keys
                     // correctly on the leaf view
                     rel.parameters.push(column.clone());
                 }
             }
         }
 
+        if let Some((ref rc, _)) = qg.range_parameter {
+            if qg.relations.values().any(|rel| rel.parameters.contains(rc)) {
+                return Err(format!(
+                    "column {} cannot be both an equality and a range parameter",
+                    rc
+                ));
+            }
+            // readers with range keys are never partial, and cannot evaluate per-key LIMITs or
+            // aggregations over a range of keys
+            if st.limit.is_some() {
+                return Err(String::from(
+                    "LIMIT is not supported in queries with range parameters",
+                ));
+            }
+            let is_aggregate = |c: &Column| c.function.is_some();
+            let has_aggregates = st.group_by.is_some()
+                || st.fields.iter().any(|f| match *f {
+                    FieldDefinitionExpression::Col(ref c) => is_aggregate(c),
+                    FieldDefinitionExpression::Value(FieldValueExpression::Arithmetic(ref a)) => {
+                        [&a.left, &a.right].iter().any(|b| match **b {
+                            ArithmeticBase::Column(ref c) => is_aggregate(c),
+                            _ => false,
+                        })
+                    }
+                    _ => false,
+                });
+            if has_aggregates {
+                return Err(String::from(
+                    "aggregations are not supported in queries with range parameters",
+                ));
+            }
+        }
+
         // 4. Add global predicates
         qg.global_predicates = global_predicates;
     }
 
+    if let Some(ref limit) = st.limit {
+        if limit.limit == 0 && limit.offset != 0 {
+            return Err(String::from("LIMIT 0 cannot be combined with an OFFSET"));
+        }
+        qg.limit = Some(limit.clone());
+    }
+    qg.aggregates = ext.aggregates.clone();
+
     // Adds a computed column to the query graph if the given column has a function:
-    let add_computed_column = |query_graph: &mut QueryGraph, column: &Column| {
-        match column.function {
-            None => (), // we've already dealt with this column as part of some relation
-            Some(_) => {
-                // add a special node representing the computed columns; if it already
-                // exists, add another computed column to it
-                let n = query_graph
-                    .relations
-                    .entry(String::from("computed_columns"))
-                    .or_insert_with(|| new_node(String::from("computed_columns"), vec![], st));
-
-                n.columns.push(column.clone());
+    let add_computed_column =
+        |query_graph: &mut QueryGraph, column: &Column| -> Result<(), String> {
+            match column.function {
+                None => (), // we've already dealt with this column as part of some relation
+                Some(_) => {
+                    // add a special node representing the computed columns; if it already
+                    // exists, add another computed column to it
+                    if !query_graph.relations.contains_key("computed_columns") {
+                        let n = new_node(String::from("computed_columns"), vec![], st)?;
+                        query_graph
+                            .relations
+                            .insert(String::from("computed_columns"), n);
+                    }
+                    query_graph
+                        .relations
+                        .get_mut("computed_columns")
+                        .unwrap()
+                        .columns
+                        .push(column.clone());
+                }
             }
-        }
-    };
+            Ok(())
+        };
 
     // 4. Add query graph nodes for any computed columns, which won't be represented in the
     //    nodes corresponding to individual relations.
     for field in st.fields.iter() {
         match *field {
             FieldDefinitionExpression::All | FieldDefinitionExpression::AllInTable(_) => {
-                panic!("Stars should have been expanded by now!")
+                return Err("stars should have been expanded by now".to_owned());
             }
             FieldDefinitionExpression::Value(FieldValueExpression::Literal(ref l)) => {
                 qg.columns.push(OutputColumn::Literal(LiteralColumn {
@@ -748,11 +1054,11 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
             }
             FieldDefinitionExpression::Value(FieldValueExpression::Arithmetic(ref a)) => {
                 if let ArithmeticBase::Column(ref c) = a.left {
-                    add_computed_column(&mut qg, c);
+                    add_computed_column(&mut qg, c)?;
                 }
 
                 if let ArithmeticBase::Column(ref c) = a.right {
-                    add_computed_column(&mut qg, c);
+                    add_computed_column(&mut qg, c)?;
                 }
 
                 qg.columns.push(OutputColumn::Arithmetic(ArithmeticColumn {
@@ -761,10 +1067,17 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                     expression: a.clone(),
                 }));
             }
-            FieldDefinitionExpression::Col(ref c) => {
-                add_computed_column(&mut qg, c);
-                qg.columns.push(OutputColumn::Data(c.clone()));
-            }
+            FieldDefinitionExpression::Col(ref c) => match Expression::from_column(c) {
+                Some(expression) => qg.columns.push(OutputColumn::Expression(ExpressionColumn {
+                    name: c.alias.clone().unwrap_or_else(|| c.name.clone()),
+                    table: None,
+                    expression,
+                })),
+                None => {
+                    add_computed_column(&mut qg, c)?;
+                    qg.columns.push(OutputColumn::Data(c.clone()));
+                }
+            },
         }
     }
 
@@ -773,12 +1086,12 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
         Some(ref clause) => {
             for column in &clause.columns {
                 // add an edge for each relation whose columns appear in the GROUP BY clause
+                let table = column.table.clone().ok_or_else(|| {
+                    format!("GROUP BY column {} does not belong to a table", column.name)
+                })?;
                 let e = qg
                     .edges
-                    .entry((
-                        String::from("computed_columns"),
-                        column.table.as_ref().unwrap().clone(),
-                    ))
+                    .entry((String::from("computed_columns"), table))
                     .or_insert_with(|| QueryGraphEdge::GroupBy(vec![]));
                 match *e {
                     QueryGraphEdge::GroupBy(ref mut cols) => cols.push(column.clone()),
@@ -813,7 +1126,11 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                         })
                         .collect::<Vec<_>>(),
                 ),
-                QueryGraphEdge::LeftJoin(ref jps) => qg.join_order.extend(
+                QueryGraphEdge::LeftJoin(ref jps)
+                | QueryGraphEdge::FullJoin(ref jps)
+                | QueryGraphEdge::SemiJoin(ref jps)
+                | QueryGraphEdge::AntiJoin(ref jps)
+                | QueryGraphEdge::NotIn(ref jps) => qg.join_order.extend(
                     jps.iter()
                         .enumerate()
                         .map(|(idx, _)| JoinRef {
@@ -117,7 +117,11 @@ impl Signature for QueryGraph {
         for e in self.edges.values() {
             match *e {
                 QueryGraphEdge::Join(ref join_predicates)
-                | QueryGraphEdge::LeftJoin(ref join_predicates) => {
+                | QueryGraphEdge::LeftJoin(ref join_predicates)
+                | QueryGraphEdge::FullJoin(ref join_predicates)
+                | QueryGraphEdge::SemiJoin(ref join_predicates)
+                | QueryGraphEdge::AntiJoin(ref join_predicates)
+                | QueryGraphEdge::NotIn(ref join_predicates) => {
                     for p in join_predicates {
                         for c in &p.contained_columns() {
                             attrs_vec.push(c);
@@ -216,15 +220,15 @@ mod tests {
         let qc = parse_query("SELECT b.c3 FROM a, b WHERE a.c1 = b.c1 AND b.c4 = 21;").unwrap();
 
         let qga = match qa {
-            SqlQuery::Select(ref q) => to_query_graph(q).unwrap(),
+            SqlQuery::Select(ref q) => to_query_graph(q, &Default::default()).unwrap(),
             _ => panic!(),
         };
         let qgb = match qb {
-            SqlQuery::Select(ref q) => to_query_graph(q).unwrap(),
+            SqlQuery::Select(ref q) => to_query_graph(q, &Default::default()).unwrap(),
             _ => panic!(),
         };
         let qgc = match qc {
-            SqlQuery::Select(ref q) => to_query_graph(q).unwrap(),
+            SqlQuery::Select(ref q) => to_query_graph(q, &Default::default()).unwrap(),
             _ => panic!(),
         };
 
@@ -254,19 +258,19 @@ mod tests {
         let qd = parse_query("SELECT b.c3 FROM a, b WHERE a.c1 = 21 AND b.c4 = a.c2;").unwrap();
 
         let qga = match qa {
-            SqlQuery::Select(ref q) => to_query_graph(q).unwrap(),
+            SqlQuery::Select(ref q) => to_query_graph(q, &Default::default()).unwrap(),
             _ => panic!(),
         };
         let qgb = match qb {
-            SqlQuery::Select(ref q) => to_query_graph(q).unwrap(),
+            SqlQuery::Select(ref q) => to_query_graph(q, &Default::default()).unwrap(),
             _ => panic!(),
         };
         let qgc = match qc {
-            SqlQuery::Select(ref q) => to_query_graph(q).unwrap(),
+            SqlQuery::Select(ref q) => to_query_graph(q, &Default::default()).unwrap(),
             _ => panic!(),
         };
         let qgd = match qd {
-            SqlQuery::Select(ref q) => to_query_graph(q).unwrap(),
+            SqlQuery::Select(ref q) => to_query_graph(q, &Default::default()).unwrap(),
             _ => panic!(),
         };
 
@@ -1,4 +1,4 @@
-use nom_sql::{ConditionBase, ConditionExpression, SqlQuery, Table};
+use nom_sql::{ArithmeticBase, Column, ConditionBase, ConditionExpression, SqlQuery, Table};
 
 pub trait ReferredTables {
     fn referred_tables(&self) -> Vec<Table>;
@@ -18,7 +18,10 @@ impl ReferredTables for SqlQuery {
                         acc
                     })
             }
-            _ => unreachable!(),
+            SqlQuery::Update(ref uq) => vec![uq.table.clone()],
+            SqlQuery::Delete(ref dq) => vec![dq.table.clone()],
+            SqlQuery::DropTable(ref dtq) => dtq.tables.clone(),
+            SqlQuery::CreateView(_) | SqlQuery::Set(_) => vec![],
         }
     }
 }
@@ -47,7 +50,23 @@ impl ReferredTables for ConditionExpression {
                     }
                 }
             }
-            _ => unimplemented!(),
+            ConditionExpression::Arithmetic(ref ae) => {
+                for b in &[&ae.left, &ae.right] {
+                    if let ArithmeticBase::Column(Column {
+                        table: Some(ref t), ..
+                    }) = **b
+                    {
+                        let t = Table::from(t.as_ref());
+                        if !tables.contains(&t) {
+                            tables.push(t);
+                        }
+                    }
+                }
+            }
+            ConditionExpression::NegationOp(ref inner)
+            | ConditionExpression::Bracketed(ref inner) => return inner.referred_tables(),
+            // literals and subqueries do not refer to the tables of the enclosing query
+            ConditionExpression::Base(_) => (),
         }
         tables
     }
@@ -154,6 +154,34 @@ impl Finkelstein {
                         _ => return None,
                     }
                 }
+                QueryGraphEdge::FullJoin(_) => {
+                    match *new_qge {
+                        QueryGraphEdge::FullJoin(_) => {}
+                        // If there is no matching FullJoin edge, we cannot reuse
+                        _ => return None,
+                    }
+                }
+                QueryGraphEdge::SemiJoin(_) => {
+                    match *new_qge {
+                        QueryGraphEdge::SemiJoin(_) => {}
+                        // If there is no matching SemiJoin edge, we cannot reuse
+                        _ => return None,
+                    }
+                }
+                QueryGraphEdge::AntiJoin(_) => {
+                    match *new_qge {
+                        QueryGraphEdge::AntiJoin(_) => {}
+                        // If there is no matching AntiJoin edge, we cannot reuse
+                        _ => return None,
+                    }
+                }
+                QueryGraphEdge::NotIn(_) => {
+                    match *new_qge {
+                        QueryGraphEdge::NotIn(_) => {}
+                        // If there is no matching NotIn edge, we cannot reuse
+                        _ => return None,
+                    }
+                }
             }
         }
 
@@ -116,7 +116,12 @@ fn chains_to_order(chains: Vec<JoinChain>, order: &mut Vec<JoinRef>) {
 
 fn from_join_ref<'a>(jref: &JoinRef, qg: &'a QueryGraph) -> &'a ConditionTree {
     match qg.edges[&(jref.src.clone(), jref.dst.clone())] {
-        QueryGraphEdge::Join(ref jps) | QueryGraphEdge::LeftJoin(ref jps) => &jps[jref.index],
+        QueryGraphEdge::Join(ref jps)
+        | QueryGraphEdge::LeftJoin(ref jps)
+        | QueryGraphEdge::FullJoin(ref jps)
+        | QueryGraphEdge::SemiJoin(ref jps)
+        | QueryGraphEdge::AntiJoin(ref jps)
+        | QueryGraphEdge::NotIn(ref jps) => &jps[jref.index],
         QueryGraphEdge::GroupBy(_) => unreachable!(),
     }
 }
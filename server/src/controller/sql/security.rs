@@ -1,6 +1,7 @@
 use crate::controller::security::SecurityConfig;
+use crate::controller::sql::extensions::SelectExtensions;
 use crate::controller::sql::query_graph::{to_query_graph, QueryGraph};
-use crate::controller::sql::{QueryFlowParts, SqlIncorporator};
+use crate::controller::sql::{Extensions, QueryFlowParts, SqlIncorporator};
 use crate::controller::Migration;
 use dataflow::prelude::DataType;
 use nom_sql::parser as sql_parser;
@@ -52,7 +53,7 @@ pub(in crate::controller) trait Multiverse {
         name: String,
         fields: &mut Vec<String>,
         mig: &mut Migration,
-    ) -> QueryFlowParts;
+    ) -> Result<QueryFlowParts, String>;
 }
 
 impl Multiverse for SqlIncorporator {
@@ -95,7 +96,7 @@ impl Multiverse for SqlIncorporator {
             (uc_name, config.get_group_policies(group_name.to_string()))
         };
 
-        let base = self.add_base(uc_name.clone(), &mut fields, mig);
+        let base = self.add_base(uc_name.clone(), &mut fields, mig)?;
         qfps.push(base);
 
         // Then, we need to transform policies' predicates into QueryGraphs.
@@ -107,7 +108,7 @@ impl Multiverse for SqlIncorporator {
         for policy in universe_policies {
             if !policy.is_row_policy() {
                 let qfp = self
-                    .add_parsed_query(policy.predicate(), None, false, mig)
+                    .add_parsed_query(policy.predicate(), Extensions::default(), None, false, mig)
                     .unwrap();
                 let rewrite_view = qfp.name.clone();
                 let rw_pol = RewritePolicy {
@@ -127,7 +128,8 @@ impl Multiverse for SqlIncorporator {
             }
 
             trace!(self.log, "Adding row policy {:?}", policy.name());
-            let predicate = self.rewrite_query(policy.predicate(), mig)?;
+            let predicate =
+                self.rewrite_query(policy.predicate(), &mut Extensions::default(), mig)?;
             let st = match predicate {
                 SqlQuery::Select(ref st) => st,
                 _ => unreachable!(),
@@ -137,10 +139,7 @@ impl Multiverse for SqlIncorporator {
             // represented as a query graph. This will change for more complex policies eg. column
             // replacement and aggregation permission.
 
-            let qg = match to_query_graph(st) {
-                Ok(qg) => qg,
-                Err(e) => panic!(e),
-            };
+            let qg = to_query_graph(st, &SelectExtensions::default())?;
 
             let e = row_policies_qg
                 .entry(policy.table().clone())
@@ -163,7 +162,7 @@ impl Multiverse for SqlIncorporator {
         name: String,
         fields: &mut Vec<String>,
         mig: &mut Migration,
-    ) -> QueryFlowParts {
+    ) -> Result<QueryFlowParts, String> {
         // Unfortunately, we can't add the base directly to the graph, because we needd
         // it to be recorded in the MIR level, so other queries can reference it.
 
@@ -179,9 +178,8 @@ impl Multiverse for SqlIncorporator {
         s.push_str("\n");
         s.push_str(") ENGINE=MyISAM DEFAULT CHARSET=utf8;");
 
-        let parsed_query = sql_parser::parse_query(&s).unwrap();
+        let parsed_query = sql_parser::parse_query(&s)?;
 
-        self.add_parsed_query(parsed_query, Some(name), false, mig)
-            .unwrap()
+        self.add_parsed_query(parsed_query, Extensions::default(), Some(name), false, mig)
     }
 }
@@ -7,6 +7,7 @@ use dataflow::ops::identity::Identity;
 use dataflow::ops::join::JoinSource::*;
 use dataflow::ops::join::{Join, JoinSource, JoinType};
 use dataflow::ops::project::Project;
+use dataflow::ops::semijoin::{SemiJoin, SemiJoinType};
 use dataflow::ops::union::Union;
 use dataflow::{DurabilityMode, PersistenceParameters};
 use noria::consensus::LocalAuthority;
@@ -153,6 +154,114 @@ async fn it_works_basic() {
     //assert_eq!(cq.lookup(&[id.clone()], true).await, Ok(vec![vec![1.into(), 6.into()]]));
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn it_streams_view_changes() {
+    use futures_util::StreamExt;
+    use noria::Delta;
+
+    let mut g = start_simple("it_streams_view_changes").await;
+    let sql = "
+        CREATE TABLE Post (id int, author int, title varchar(255), PRIMARY KEY(id));
+        QUERY PostsByAuthor: SELECT id, title FROM Post WHERE author = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut post = g.table("Post").await.unwrap();
+    let mut posts = g.view("PostsByAuthor").await.unwrap();
+    let mut changes = posts.subscribe(vec![vec![1.into()]]).await.unwrap();
+
+    post.insert(vec![1.into(), 1.into(), "a".into()])
+        .await
+        .unwrap();
+    post.insert(vec![2.into(), 2.into(), "b".into()])
+        .await
+        .unwrap();
+    post.insert(vec![3.into(), 1.into(), "c".into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    // changes to other keys are not delivered
+    let mut deltas = Vec::new();
+    while deltas.len() < 2 {
+        deltas.extend(changes.next().await.unwrap().unwrap());
+    }
+    assert_eq!(
+        deltas,
+        vec![
+            Delta::Positive(vec![1.into(), "a".into(), 1.into()]),
+            Delta::Positive(vec![3.into(), "c".into(), 1.into()]),
+        ]
+    );
+
+    post.delete(vec![1.into()]).await.unwrap();
+    post.delete(vec![2.into()]).await.unwrap();
+    sleep().await;
+
+    let deltas = changes.next().await.unwrap().unwrap();
+    assert_eq!(
+        deltas,
+        vec![Delta::Negative(vec![1.into(), "a".into(), 1.into()])]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_streams_base_changes() {
+    use futures_util::StreamExt;
+    use noria::{ChangeCursor, Delta, Modification};
+
+    let mut g = start_simple_unsharded("it_streams_base_changes").await;
+    let sql = "CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut car = g.table("Car").await.unwrap();
+    car.insert(vec![1.into(), "Volvo".into()]).await.unwrap();
+    car.insert(vec![2.into(), "Saab".into()]).await.unwrap();
+    car.update(
+        vec![1.into()],
+        vec![(1, Modification::Set("Volkswagen".into()))],
+    )
+    .await
+    .unwrap();
+    sleep().await;
+
+    let mut changes = g.changes("Car", ChangeCursor::oldest()).await.unwrap();
+    let mut seen = Vec::new();
+    while seen.len() < 4 {
+        seen.extend(changes.next().await.unwrap().unwrap());
+    }
+    assert!(seen.iter().all(|c| c.shard == 0));
+    assert_eq!(
+        seen.iter().map(|c| c.seq).collect::<Vec<_>>(),
+        vec![0, 1, 2, 3]
+    );
+    assert_eq!(
+        seen.into_iter().map(|c| c.delta).collect::<Vec<_>>(),
+        vec![
+            Delta::Positive(vec![1.into(), "Volvo".into()]),
+            Delta::Positive(vec![2.into(), "Saab".into()]),
+            Delta::Negative(vec![1.into(), "Volvo".into()]),
+            Delta::Positive(vec![1.into(), "Volkswagen".into()]),
+        ]
+    );
+    assert_eq!(changes.cursor().position(0), Some(4));
+
+    // a new stream can pick up where the old one left off
+    let cursor = changes.cursor().clone();
+    drop(changes);
+    car.delete(vec![2.into()]).await.unwrap();
+    sleep().await;
+
+    let mut changes = g.changes("Car", cursor).await.unwrap();
+    let seen = changes.next().await.unwrap().unwrap();
+    assert_eq!(seen.len(), 1);
+    assert_eq!(seen[0].seq, 4);
+    assert_eq!(
+        seen[0].delta,
+        Delta::Negative(vec![2.into(), "Saab".into()])
+    );
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn it_completes() {
     let mut builder = Builder::default();
@@ -409,6 +518,88 @@ async fn base_mutation() {
     );
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn base_mutation_without_read_modify_write() {
+    use noria::{Modification, Operation, TableOperation};
+
+    let mut g = start_simple("base_mutation_without_read_modify_write").await;
+    let sql = "
+        CREATE TABLE Counter (id int, hits int, last_seen bigint, tags text, PRIMARY KEY(id));
+        QUERY CounterById: SELECT Counter.id, hits, last_seen, tags FROM Counter WHERE Counter.id = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut counter = g.table("Counter").await.unwrap();
+    counter.set_write_tokens(true);
+    let mut read = g.view("CounterById").await.unwrap();
+    counter
+        .insert(vec![1.into(), 0.into(), DataType::None, DataType::None])
+        .await
+        .unwrap();
+
+    // a counter capped at 3, along with the largest timestamp it has seen
+    let mut token = noria::WriteToken::default();
+    for at in &[20, 50, 10, 40, 30] {
+        let r = counter
+            .perform_all(vec![
+                TableOperation::Update {
+                    key: vec![1.into()],
+                    set: vec![
+                        Modification::None,
+                        Modification::Apply(Operation::Add, 1.into()),
+                        Modification::Apply(Operation::Max, (*at).into()),
+                        Modification::SetIfNull("first".into()),
+                    ],
+                },
+                TableOperation::Update {
+                    key: vec![1.into()],
+                    set: vec![
+                        Modification::None,
+                        Modification::Apply(Operation::Min, 3.into()),
+                    ],
+                },
+            ])
+            .await
+            .unwrap();
+        assert!(r.all_applied());
+        token.merge(r.token);
+    }
+    assert_eq!(
+        read.lookup_after(&[1.into()], &token).await.unwrap(),
+        vec![vec![1.into(), 3.into(), 50.into(), "first".into()]]
+    );
+
+    let r = counter
+        .update(
+            vec![1.into()],
+            vec![
+                (1, Modification::Apply(Operation::Multiply, 2.into())),
+                (3, Modification::Apply(Operation::Append, ",second".into())),
+            ],
+        )
+        .await
+        .unwrap();
+    assert!(r.all_applied());
+    assert_eq!(
+        read.lookup_after(&[1.into()], &r.token).await.unwrap(),
+        vec![vec![1.into(), 6.into(), 50.into(), "first,second".into()]]
+    );
+
+    let r = counter
+        .update(vec![1.into()], vec![(2, Modification::SetNull)])
+        .await
+        .unwrap();
+    assert_eq!(
+        read.lookup_after(&[1.into()], &r.token).await.unwrap(),
+        vec![vec![
+            1.into(),
+            6.into(),
+            DataType::None,
+            "first,second".into()
+        ]]
+    );
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn shared_interdomain_ancestor() {
     // set up graph
@@ -575,6 +766,63 @@ async fn it_works_w_partial_mat() {
     assert_eq!(cq.len().await.unwrap(), 1);
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_semi_and_anti_joins() {
+    let mut g = start_simple("it_works_with_semi_and_anti_joins").await;
+    g.migrate(|mig| {
+        let post = mig.add_base("post", &["id", "author"], Base::default());
+        let seen = mig.add_base(
+            "seen",
+            &["reader", "post"],
+            Base::default().with_key(vec![0, 1]),
+        );
+
+        // both readers are partial on a column that is not the join column, so upqueries go to
+        // the left parent and look up their matches in the right
+        let read = SemiJoin::new(post, seen, SemiJoinType::Semi, (0, 1), vec![0, 1]);
+        let read = mig.add_ingredient("read", &["id", "author"], read);
+        mig.maintain_anonymous(read, &[1]);
+        let unread = SemiJoin::new(post, seen, SemiJoinType::Anti, (0, 1), vec![0, 1]);
+        let unread = mig.add_ingredient("unread", &["id", "author"], unread);
+        mig.maintain_anonymous(unread, &[1]);
+    })
+    .await;
+
+    let mut post = g.table("post").await.unwrap();
+    let mut seen = g.table("seen").await.unwrap();
+    let mut read = g.view("read").await.unwrap();
+    let mut unread = g.view("unread").await.unwrap();
+
+    post.insert(vec![1.into(), 10.into()]).await.unwrap();
+    post.insert(vec![2.into(), 10.into()]).await.unwrap();
+    seen.insert(vec![100.into(), 1.into()]).await.unwrap();
+    seen.insert(vec![101.into(), 1.into()]).await.unwrap();
+    sleep().await;
+
+    // a post that was seen twice is still only read once
+    let rs = read.lookup(&[10.into()], true).await.unwrap();
+    assert_eq!(rs, vec![vec![1.into(), 10.into()]]);
+    let rs = unread.lookup(&[10.into()], true).await.unwrap();
+    assert_eq!(rs, vec![vec![2.into(), 10.into()]]);
+
+    // the first reader of a post moves it over, and the last one moves it back
+    seen.insert(vec![100.into(), 2.into()]).await.unwrap();
+    seen.delete(vec![100.into(), 1.into()]).await.unwrap();
+    sleep().await;
+
+    let rs = read.lookup(&[10.into()], true).await.unwrap();
+    assert_eq!(rs.len(), 2);
+    assert!(unread.lookup(&[10.into()], true).await.unwrap().is_empty());
+
+    seen.delete(vec![101.into(), 1.into()]).await.unwrap();
+    sleep().await;
+
+    let rs = read.lookup(&[10.into()], true).await.unwrap();
+    assert_eq!(rs, vec![vec![2.into(), 10.into()]]);
+    let rs = unread.lookup(&[10.into()], true).await.unwrap();
+    assert_eq!(rs, vec![vec![1.into(), 10.into()]]);
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn it_works_w_partial_mat_below_empty() {
     // set up graph with all nodes added in a single migration. The base tables are therefore empty
@@ -622,6 +870,196 @@ async fn it_works_w_partial_mat_below_empty() {
     assert_eq!(cq.len().await.unwrap(), 1);
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_range_lookups() {
+    use std::ops::Bound;
+
+    let mut g = start_simple("it_works_with_range_lookups").await;
+    let _ = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["a", "b"], Base::default());
+            let b = mig.add_base("b", &["a", "b"], Base::default());
+            let mut emits = HashMap::new();
+            emits.insert(a, vec![0, 1]);
+            emits.insert(b, vec![0, 1]);
+            let u = Union::new(emits);
+            let c = mig.add_ingredient("c", &["a", "b"], u);
+            mig.maintain_anonymous(c, &[0]);
+            (a, b, c)
+        })
+        .await;
+
+    let mut muta = g.table("a").await.unwrap();
+    for i in 1..=5 {
+        muta.insert(vec![i.into(), (i * 10).into()]).await.unwrap();
+    }
+    sleep().await;
+
+    let mut cq = g.view("c").await.unwrap();
+    let key = |i: i32| vec![DataType::from(i)];
+
+    // a non-blocking read of a range with missing keys reports a miss rather than partial results
+    match cq
+        .lookup_range(
+            Bound::Included(&key(2)[..]),
+            Bound::Excluded(&key(5)[..]),
+            false,
+        )
+        .await
+    {
+        Err(noria::error::ViewError::RangeMiss) => {}
+        Err(e) => panic!("expected a range miss, got {:?}", e),
+        Ok(_) => panic!("expected a range miss"),
+    }
+
+    // bounded integral ranges over partial state are read key-by-key, triggering backfills
+    let mut res: Vec<Vec<DataType>> = cq
+        .lookup_range(
+            Bound::Included(&key(2)[..]),
+            Bound::Excluded(&key(5)[..]),
+            true,
+        )
+        .await
+        .unwrap()
+        .into();
+    res.sort();
+    assert_eq!(
+        res,
+        vec![
+            vec![2.into(), 20.into()],
+            vec![3.into(), 30.into()],
+            vec![4.into(), 40.into()],
+        ]
+    );
+    assert_eq!(cq.len().await.unwrap(), 3);
+
+    // but partial state cannot answer unbounded ranges
+    assert!(cq
+        .lookup_range(Bound::Included(&key(2)[..]), Bound::Unbounded, true)
+        .await
+        .is_err());
+
+    // fully materialized state can answer any range
+    let mut b = Builder::default();
+    b.disable_partial();
+    b.set_sharding(Some(DEFAULT_SHARDING));
+    b.set_persistence(get_persistence_params("it_works_with_range_lookups_full"));
+    let mut g = b.start_local().await.unwrap().0;
+    let _ = g
+        .migrate(|mig| {
+            let a = mig.add_base("a", &["a", "b"], Base::default());
+            mig.maintain_anonymous(a, &[0]);
+            a
+        })
+        .await;
+
+    let mut muta = g.table("a").await.unwrap();
+    for i in 1..=5 {
+        muta.insert(vec![i.into(), (i * 10).into()]).await.unwrap();
+    }
+    sleep().await;
+
+    // rows from different shards come back in key order
+    let mut aq = g.view("a").await.unwrap();
+    let res: Vec<Vec<DataType>> = aq
+        .lookup_range(Bound::Excluded(&key(3)[..]), Bound::Unbounded, true)
+        .await
+        .unwrap()
+        .into();
+    assert_eq!(
+        res,
+        vec![vec![4.into(), 40.into()], vec![5.into(), 50.into()]]
+    );
+
+    let res = aq.lookup_prefix(&key(1), true).await.unwrap();
+    assert_eq!(res, vec![vec![1.into(), 10.into()]]);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_range_parameters() {
+    use std::ops::Bound;
+
+    let mut g = start_simple("it_works_with_range_parameters").await;
+    let sql = "
+        CREATE TABLE Event (id int, user int, ts int);
+        QUERY EventsSince: SELECT Event.id FROM Event WHERE Event.user = ? AND Event.ts >= ?;
+        QUERY EventsBetween: SELECT Event.id FROM Event WHERE Event.ts >= ? AND Event.ts <= ?;
+        QUERY EventsAfter: SELECT Event.id FROM Event WHERE Event.ts > ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut event = g.table("Event").await.unwrap();
+    for i in 1..=6 {
+        event
+            .insert(vec![i.into(), (i % 2).into(), (i * 10).into()])
+            .await
+            .unwrap();
+    }
+    sleep().await;
+
+    // equality parameters come first in the key, followed by the range parameter
+    let mut since = g.view("EventsSince").await.unwrap();
+    let lower = vec![DataType::from(1), DataType::from(20)];
+    let upper = vec![DataType::from(1)];
+    let mut res: Vec<Vec<DataType>> = since
+        .lookup_range(
+            Bound::Included(&lower[..]),
+            Bound::Included(&upper[..]),
+            true,
+        )
+        .await
+        .unwrap()
+        .into();
+    res.sort();
+    assert_eq!(
+        res,
+        vec![
+            vec![3.into(), 1.into(), 30.into()],
+            vec![5.into(), 1.into(), 50.into()]
+        ]
+    );
+
+    let mut between = g.view("EventsBetween").await.unwrap();
+    let lower = vec![DataType::from(20)];
+    let upper = vec![DataType::from(40)];
+    let mut res: Vec<Vec<DataType>> = between
+        .lookup_range(
+            Bound::Included(&lower[..]),
+            Bound::Excluded(&upper[..]),
+            true,
+        )
+        .await
+        .unwrap()
+        .into();
+    res.sort();
+    assert_eq!(
+        res,
+        vec![vec![2.into(), 20.into()], vec![3.into(), 30.into()]]
+    );
+
+    // the query's `>` excludes the parameter value, so the lookup can't ask to include it
+    let mut after = g.view("EventsAfter").await.unwrap();
+    let lower = vec![DataType::from(40)];
+    match after
+        .lookup_range(Bound::Included(&lower[..]), Bound::Unbounded, true)
+        .await
+    {
+        Err(noria::error::ViewError::RangeBoundMismatch) => {}
+        Err(e) => panic!("expected a bound mismatch, got {:?}", e),
+        Ok(_) => panic!("expected a bound mismatch"),
+    }
+    let mut res: Vec<Vec<DataType>> = after
+        .lookup_range(Bound::Excluded(&lower[..]), Bound::Unbounded, true)
+        .await
+        .unwrap()
+        .into();
+    res.sort();
+    assert_eq!(
+        res,
+        vec![vec![5.into(), 50.into()], vec![6.into(), 60.into()]]
+    );
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn it_works_deletion() {
     // set up graph
@@ -641,65 +1079,1014 @@ async fn it_works_deletion() {
         })
         .await;
 
-    let mut cq = g.view("c").await.unwrap();
-    let mut muta = g.table("a").await.unwrap();
-    let mut mutb = g.table("b").await.unwrap();
+    let mut cq = g.view("c").await.unwrap();
+    let mut muta = g.table("a").await.unwrap();
+    let mut mutb = g.table("b").await.unwrap();
+
+    // send a value on a
+    muta.insert(vec![1.into(), 2.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        cq.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 2.into()]]
+    );
+
+    // send a value on b
+    mutb.insert(vec![0.into(), 1.into(), 4.into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    let res = cq.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(res.len(), 2);
+    assert!(res.contains(&vec![1.into(), 2.into()]));
+    assert!(res.contains(&vec![1.into(), 4.into()]));
+
+    // delete first value
+    muta.delete(vec![2.into()]).await.unwrap();
+    sleep().await;
+    assert_eq!(
+        cq.lookup(&[1.into()], true).await.unwrap(),
+        vec![vec![1.into(), 4.into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_sql_recipe() {
+    let mut g = start_simple("it_works_with_sql_recipe").await;
+    let sql = "
+        CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
+        QUERY CountCars: SELECT COUNT(*) FROM Car WHERE brand = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut getter = g.view("CountCars").await.unwrap();
+
+    assert_eq!(mutator.table_name(), "Car");
+    assert_eq!(mutator.columns(), &["id", "brand"]);
+
+    let brands = vec!["Volvo", "Volvo", "Volkswagen"];
+    for (i, &brand) in brands.iter().enumerate() {
+        mutator.insert(vec![i.into(), brand.into()]).await.unwrap();
+    }
+
+    // Let writes propagate:
+    sleep().await;
+
+    // Retrieve the result of the count query:
+    let result = getter.lookup(&["Volvo".into()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0][0], 2.into());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_type_checks_table_writes() {
+    use noria::error::TableError;
+
+    let mut g = start_simple("it_type_checks_table_writes").await;
+    let sql = "
+        CREATE TABLE Car (id int, brand varchar(10) NOT NULL, price decimal(6,2), PRIMARY KEY(id));
+        QUERY CarsByBrand: SELECT id, price FROM Car WHERE brand = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut mutator = g.table("Car").await.unwrap();
+    let mut getter = g.view("CarsByBrand").await.unwrap();
+
+    // values are coerced to the column types
+    mutator
+        .insert(vec!["1".into(), "Volvo".into(), 19999.into()])
+        .await
+        .unwrap();
+
+    // but not if they don't fit
+    match mutator
+        .insert(vec!["two".into(), "Volvo".into(), DataType::None])
+        .await
+    {
+        Err(TableError::TypeMismatch { column, got, .. }) => {
+            assert_eq!(column, "id");
+            assert_eq!(got, "two".into());
+        }
+        r => panic!("expected a type mismatch, got {:?}", r),
+    }
+    match mutator
+        .insert(vec![2.into(), "Lamborghini".into(), DataType::None])
+        .await
+    {
+        Err(TableError::TypeMismatch { column, .. }) => assert_eq!(column, "brand"),
+        r => panic!("expected a type mismatch, got {:?}", r),
+    }
+    match mutator
+        .insert(vec![2.into(), DataType::None, DataType::None])
+        .await
+    {
+        Err(TableError::TypeMismatch { column, .. }) => assert_eq!(column, "brand"),
+        r => panic!("expected a type mismatch, got {:?}", r),
+    }
+
+    sleep().await;
+
+    let price: DataType = "19999.00".parse::<noria::Decimal>().unwrap().into();
+    assert_eq!(
+        getter.lookup(&["Volvo".into()], true).await.unwrap(),
+        vec![vec![1.into(), price]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_makes_transactions_visible_atomically() {
+    use noria::{Modification, OperationResult};
+
+    let mut g = start_simple("it_makes_transactions_visible_atomically").await;
+    let sql = "
+        CREATE TABLE Orders (id int, nlines int, PRIMARY KEY(id));
+        CREATE TABLE OrderLine (order_id int, item int);
+        QUERY OrderLines: SELECT Orders.nlines, OrderLine.item \
+            FROM Orders JOIN OrderLine ON (Orders.id = OrderLine.order_id) \
+            WHERE Orders.id = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let orders = g.table("Orders").await.unwrap();
+    let lines = g.table("OrderLine").await.unwrap();
+    let mut getter = g.view("OrderLines").await.unwrap();
+
+    // an order and its first line show up together
+    let mut txn = g.transaction();
+    txn.insert(&orders, vec![1.into(), 1.into()]);
+    txn.insert(&lines, vec![1.into(), 1.into()]);
+    let token = g.commit(txn).await.unwrap().token;
+
+    assert_eq!(
+        getter.lookup_after(&[1.into()], &token).await.unwrap(),
+        vec![vec![1.into(), 1.into()]]
+    );
+
+    // a transaction with an invalid write is rejected as a whole
+    let mut txn = g.transaction();
+    txn.update(
+        &orders,
+        vec![1.into()],
+        vec![(1, Modification::Set(2.into()))],
+    )
+    .unwrap();
+    txn.insert(&lines, vec![1.into()]);
+    assert!(g.commit(txn).await.is_err());
+
+    // as is one with a write that does not apply to the rows of its table
+    let mut txn = g.transaction();
+    txn.insert(&lines, vec![1.into(), 2.into()]);
+    txn.update_if(
+        &orders,
+        vec![1.into()],
+        vec![(1, 5.into())],
+        vec![(1, Modification::Set(2.into()))],
+    )
+    .unwrap();
+    let res = g.commit(txn).await.unwrap();
+    assert_eq!(
+        res.results,
+        vec![OperationResult::Aborted, OperationResult::ConditionFailed]
+    );
+    assert_eq!(
+        getter.lookup_after(&[1.into()], &res.token).await.unwrap(),
+        vec![vec![1.into(), 1.into()]]
+    );
+
+    // readers never see an order whose line count disagrees with its lines
+    let mut token = noria::WriteToken::default();
+    for n in 2..=20 {
+        let mut txn = g.transaction();
+        txn.update(
+            &orders,
+            vec![1.into()],
+            vec![(1, Modification::Set(n.into()))],
+        )
+        .unwrap();
+        txn.insert(&lines, vec![1.into(), n.into()]);
+        token.merge(g.commit(txn).await.unwrap().token);
+
+        let res = getter.lookup(&[1.into()], true).await.unwrap();
+        let nlines: DataType = res.len().into();
+        assert!(res.iter().all(|r| r["nlines"] == nlines), "{:?}", res);
+    }
+
+    let res = getter.lookup_after(&[1.into()], &token).await.unwrap();
+    assert_eq!(res.len(), 20);
+    assert!(res.iter().all(|r| r["nlines"] == 20.into()));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_reads_its_own_writes() {
+    let mut g = start_simple("it_reads_its_own_writes").await;
+    let sql = "
+        CREATE TABLE Article (id int, title varchar(255), PRIMARY KEY(id));
+        CREATE TABLE Vote (article_id int, user int);
+        QUERY ArticleWithVoteCount: SELECT Article.id, title, VoteCount.votes AS votes \
+            FROM Article \
+            LEFT JOIN (SELECT Vote.article_id, COUNT(user) AS votes \
+                       FROM Vote GROUP BY Vote.article_id) AS VoteCount \
+            ON (Article.id = VoteCount.article_id) WHERE Article.id = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut article = g.table("Article").await.unwrap();
+    article.set_write_tokens(true);
+    let mut vote = g.table("Vote").await.unwrap();
+    vote.set_write_tokens(true);
+    let mut awvc = g.view("ArticleWithVoteCount").await.unwrap();
+
+    let token = article
+        .insert(vec![1.into(), "Hello world".into()])
+        .await
+        .unwrap()
+        .token;
+    assert_eq!(
+        awvc.lookup_after(&[1.into()], &token).await.unwrap(),
+        vec![vec![1.into(), "Hello world".into(), DataType::None]]
+    );
+
+    // every read sees the vote written right before it, without waiting for it to propagate
+    for n in 1..=20 {
+        let token = vote.insert(vec![1.into(), n.into()]).await.unwrap().token;
+        let res = awvc.lookup_after(&[1.into()], &token).await.unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0]["votes"], n.into());
+    }
+
+    // a token can cover writes to several tables
+    let mut token = article
+        .update(
+            vec![1.into()],
+            vec![(1, noria::Modification::Set("Goodbye world".into()))],
+        )
+        .await
+        .unwrap()
+        .token;
+    token.merge(vote.insert(vec![1.into(), 21.into()]).await.unwrap().token);
+    assert_eq!(
+        awvc.lookup_after(&[1.into()], &token).await.unwrap(),
+        vec![vec![1.into(), "Goodbye world".into(), 21.into()]]
+    );
+
+    // writes only carry tokens when asked to
+    vote.set_write_tokens(false);
+    let r = vote.insert(vec![1.into(), 22.into()]).await.unwrap();
+    assert_eq!(r.token, noria::WriteToken::default());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_applies_conditional_writes() {
+    use noria::{OperationResult, TableOperation};
+
+    let mut g = start_simple("it_applies_conditional_writes").await;
+    let sql = "
+        CREATE TABLE Job (id int, state varchar(16), worker int, PRIMARY KEY(id));
+        CREATE TABLE Account (id int, username varchar(64) UNIQUE, PRIMARY KEY(id));
+        CREATE TABLE Member (id int, org int, name varchar(64), PRIMARY KEY(id), \
+            UNIQUE KEY member_name (org, name));
+        QUERY JobById: SELECT Job.id, state, worker FROM Job WHERE Job.id = ?;
+        QUERY AccountByName: SELECT Account.id, username FROM Account WHERE username = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut job = g.table("Job").await.unwrap();
+    job.set_write_tokens(true);
+    let mut account = g.table("Account").await.unwrap();
+    account.set_write_tokens(true);
+    let mut member = g.table("Member").await.unwrap();
+    let mut job_by_id = g.view("JobById").await.unwrap();
+    let mut account_by_name = g.view("AccountByName").await.unwrap();
+
+    // jobs are enqueued once
+    let r = job
+        .insert(vec![1.into(), "queued".into(), DataType::None])
+        .await
+        .unwrap();
+    assert!(r.all_applied());
+    let r = job
+        .insert(vec![1.into(), "queued".into(), DataType::None])
+        .await
+        .unwrap();
+    assert_eq!(r.results, vec![OperationResult::UniqueViolation(vec![0])]);
+    let r = job
+        .perform_all(vec![
+            TableOperation::InsertIfAbsent(vec![1.into(), "queued".into(), DataType::None]),
+            TableOperation::InsertIfAbsent(vec![2.into(), "queued".into(), DataType::None]),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(
+        r.results,
+        vec![OperationResult::ConditionFailed, OperationResult::Applied]
+    );
+
+    // and claimed by only one worker
+    let mut claimed = Vec::new();
+    for worker in 1..=2 {
+        let r = job
+            .update_if(
+                vec![1.into()],
+                vec![(1, "queued".into())],
+                vec![(1, "running".into()), (2, worker.into())],
+            )
+            .await
+            .unwrap();
+        if r.all_applied() {
+            claimed.push((worker, r.token));
+        } else {
+            assert_eq!(r.results, vec![OperationResult::ConditionFailed]);
+        }
+    }
+    assert_eq!(claimed.len(), 1);
+    let (worker, token) = claimed.pop().unwrap();
+    assert_eq!(worker, 1);
+    assert_eq!(
+        job_by_id.lookup_after(&[1.into()], &token).await.unwrap(),
+        vec![vec![1.into(), "running".into(), 1.into()]]
+    );
+    let r = job
+        .update_if(vec![3.into()], vec![], vec![(1, "running".into())])
+        .await
+        .unwrap();
+    assert_eq!(r.results, vec![OperationResult::NotFound]);
+
+    // usernames are registered once
+    let r = account
+        .perform_all(vec![
+            vec![1.into(), "alice".into()],
+            vec![2.into(), "alice".into()],
+            vec![3.into(), "bob".into()],
+        ])
+        .await
+        .unwrap();
+    assert_eq!(
+        r.results,
+        vec![
+            OperationResult::Applied,
+            OperationResult::UniqueViolation(vec![1]),
+            OperationResult::Applied,
+        ]
+    );
+    let r = account
+        .update(
+            vec![3.into()],
+            vec![(1, noria::Modification::Set("alice".into()))],
+        )
+        .await
+        .unwrap();
+    assert_eq!(r.results, vec![OperationResult::UniqueViolation(vec![1])]);
+    assert_eq!(
+        account_by_name
+            .lookup_after(&["alice".into()], &r.token)
+            .await
+            .unwrap(),
+        vec![vec![1.into(), "alice".into()]]
+    );
+
+    // and names once per organization
+    let r = member
+        .perform_all(vec![
+            vec![1.into(), 1.into(), "alice".into()],
+            vec![2.into(), 2.into(), "alice".into()],
+            vec![3.into(), 1.into(), "alice".into()],
+        ])
+        .await
+        .unwrap();
+    assert_eq!(
+        r.results,
+        vec![
+            OperationResult::Applied,
+            OperationResult::Applied,
+            OperationResult::UniqueViolation(vec![1, 2]),
+        ]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_bulk_loads_rows() {
+    use futures_util::stream;
+    use noria::{CsvRows, DumpReader};
+
+    let mut g = start_simple("it_bulk_loads_rows").await;
+    let sql = "
+        CREATE TABLE Item (id int, name varchar(32), stock int NOT NULL DEFAULT 0, PRIMARY KEY(id));
+        QUERY ItemById: SELECT Item.id, name, stock FROM Item WHERE Item.id = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut item = g.table("Item").await.unwrap();
+    item.set_write_tokens(true);
+    let mut read = g.view("ItemById").await.unwrap();
+
+    let csv =
+        "id,name,stock\n1,pen,10\n2,\"book, used\",\\N\n3,cup,7\n1,duplicate pen,0\n4,mug,2\n";
+    let rows = CsvRows::new(csv.as_bytes())
+        .skip_header()
+        .map(Result::unwrap);
+    let mut reports = 0;
+    let p = item
+        .bulk_load(stream::iter(rows), 2, 2, |_| reports += 1)
+        .await
+        .unwrap();
+    assert_eq!(reports, 3);
+    assert_eq!(p.sent, 5);
+    assert_eq!(p.acknowledged, 5);
+    assert_eq!(p.rejected, 1);
+
+    assert_eq!(
+        read.lookup_after(&[1.into()], &p.token).await.unwrap(),
+        vec![vec![1.into(), "pen".into(), 10.into()]]
+    );
+    assert_eq!(
+        read.lookup_after(&[2.into()], &p.token).await.unwrap(),
+        vec![vec![2.into(), "book, used".into(), 0.into()]]
+    );
+
+    let dump = "
+        LOCK TABLES `Item` WRITE;
+        INSERT INTO `Item` VALUES (5,'it''s a \\'lamp\\'',1),(6,'desk',0);
+        INSERT INTO `Item` (`name`, `id`) VALUES ('chair', 7);
+        UNLOCK TABLES;
+    ";
+    let columns = item.columns().to_vec();
+    let rows: Vec<_> = DumpReader::new(dump.as_bytes())
+        .flat_map(|stmt| stmt.unwrap().rows_for(&columns).unwrap())
+        .collect();
+    let p = item
+        .bulk_load(stream::iter(rows), 1000, 8, |_| {})
+        .await
+        .unwrap();
+    assert_eq!(p.acknowledged, 3);
+    assert_eq!(p.rejected, 0);
+
+    assert_eq!(
+        read.lookup_after(&[5.into()], &p.token).await.unwrap(),
+        vec![vec![5.into(), "it's a 'lamp'".into(), 1.into()]]
+    );
+    assert_eq!(
+        read.lookup_after(&[7.into()], &p.token).await.unwrap(),
+        vec![vec![7.into(), "chair".into(), 0.into()]]
+    );
+
+    // rows that do not fit the table stop the load
+    let rows = vec![vec![8.into(), "too many".into(), 1.into(), 2.into()]];
+    assert!(item
+        .bulk_load(stream::iter(rows.clone()), 1000, 8, |_| {})
+        .await
+        .is_err());
+
+    // as do batches without rows
+    match item.bulk_load(stream::iter(rows), 0, 8, |_| {}).await {
+        Err(noria::error::BulkLoadError::Zero(_)) => {}
+        r => unreachable!("{:?}", r),
+    }
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_snapshots_tables_and_views() {
+    use futures_util::TryStreamExt;
+    use noria::error::SnapshotError;
+
+    let mut b = Builder::default();
+    b.disable_partial();
+    b.set_sharding(Some(DEFAULT_SHARDING));
+    b.set_persistence(get_persistence_params("it_snapshots_tables_and_views"));
+    let mut g = b.start_local().await.unwrap().0;
+    let sql = "
+        CREATE TABLE Article (id int, title varchar(255), PRIMARY KEY(id));
+        QUERY ArticleById: SELECT id, title FROM Article WHERE id = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut article = g.table("Article").await.unwrap();
+    article.set_write_tokens(true);
+    let mut read = g.view("ArticleById").await.unwrap();
+    let mut token = article
+        .insert(vec![1.into(), "first".into()])
+        .await
+        .unwrap()
+        .token;
+    for id in 2..=20 {
+        let title = format!("article {}", id);
+        token.merge(
+            article
+                .insert(vec![id.into(), title.into()])
+                .await
+                .unwrap()
+                .token,
+        );
+    }
+    token.merge(article.delete(vec![2.into()]).await.unwrap().token);
+    assert_eq!(
+        read.lookup_after(&[1.into()], &token).await.unwrap().len(),
+        1
+    );
+
+    for name in &["Article", "ArticleById"] {
+        let snapshot = g.snapshot(name).await.unwrap();
+        assert_eq!(snapshot.columns()[..2], ["id", "title"]);
+        let mut rows: Vec<Vec<DataType>> = snapshot.try_concat().await.unwrap();
+        rows.sort();
+        assert_eq!(rows.len(), 19);
+        assert_eq!(rows[0][..2], [DataType::from(1), DataType::from("first")]);
+        assert_eq!(
+            rows[1][..2],
+            [DataType::from(3), DataType::from("article 3")]
+        );
+    }
+
+    // the table takes writes again once the view has been copied
+    let token = article
+        .insert(vec![21.into(), "late".into()])
+        .await
+        .unwrap()
+        .token;
+    assert_eq!(
+        read.lookup_after(&[21.into()], &token).await.unwrap(),
+        vec![vec![21.into(), "late".into()]]
+    );
+
+    let e = g.snapshot("Nope").await.unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<SnapshotError>(),
+        Some(&SnapshotError::NoSuchName("Nope".to_owned()))
+    );
+
+    // partially materialized views do not have all their rows to hand out
+    let mut g = start_simple("it_snapshots_tables_and_views_partial").await;
+    g.install_recipe(sql).await.unwrap();
+    let e = g.snapshot("ArticleById").await.unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<SnapshotError>(),
+        Some(&SnapshotError::NotMaterialized("ArticleById".to_owned()))
+    );
+    assert!(g.snapshot("Article").await.is_ok());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_reads_from_reader_replicas() {
+    let mut b = Builder::default();
+    b.set_sharding(Some(DEFAULT_SHARDING));
+    b.set_reader_replicas("ArticleById", 3);
+    b.set_persistence(get_persistence_params("it_reads_from_reader_replicas"));
+    let mut g = b.start_local().await.unwrap().0;
+    let table = "CREATE TABLE Article (id int, title varchar(255), PRIMARY KEY(id));";
+    let sql = format!(
+        "{}\nQUERY ArticleById: SELECT id, title FROM Article WHERE id = ?;",
+        table
+    );
+    g.install_recipe(&sql).await.unwrap();
+
+    let mut article = g.table("Article").await.unwrap();
+    article.set_write_tokens(true);
+    let mut read = g.view("ArticleById").await.unwrap();
+    let mut token = article
+        .insert(vec![1.into(), "first".into()])
+        .await
+        .unwrap()
+        .token;
+    token.merge(
+        article
+            .insert(vec![2.into(), "second".into()])
+            .await
+            .unwrap()
+            .token,
+    );
+
+    // consecutive reads go to different replicas, and every replica sees every write
+    for _ in 0..6 {
+        assert_eq!(
+            read.lookup_after(&[1.into()], &token).await.unwrap(),
+            vec![vec![1.into(), "first".into()]]
+        );
+        assert_eq!(
+            read.lookup_after(&[2.into()], &token).await.unwrap(),
+            vec![vec![2.into(), "second".into()]]
+        );
+    }
+
+    // all the replicas go away along with the query
+    g.install_recipe(table).await.unwrap();
+    assert!(g.view("ArticleById").await.is_err());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_ships_writes_to_base_backups() {
+    let mut b = Builder::default();
+    b.set_sharding(Some(DEFAULT_SHARDING));
+    b.set_base_backups(2);
+    b.set_persistence(get_persistence_params("it_ships_writes_to_base_backups"));
+    let mut g = b.start_local().await.unwrap().0;
+    let user = "CREATE TABLE User (id int, name varchar(255), PRIMARY KEY(id));";
+    g.install_recipe(&format!(
+        "CREATE TABLE Article (id int, title varchar(255), PRIMARY KEY(id));
+         {}
+         QUERY ArticleById: SELECT id, title FROM Article WHERE id = ?;",
+        user
+    ))
+    .await
+    .unwrap();
+
+    // backups must not get in the way of writes, and of the views that reflect them
+    let mut article = g.table("Article").await.unwrap();
+    article.set_write_tokens(true);
+    let mut read = g.view("ArticleById").await.unwrap();
+    article
+        .insert(vec![1.into(), "first".into()])
+        .await
+        .unwrap();
+    article
+        .insert(vec![2.into(), "second".into()])
+        .await
+        .unwrap();
+    let token = article.delete(vec![1.into()]).await.unwrap().token;
+    assert!(read
+        .lookup_after(&[1.into()], &token)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        read.lookup_after(&[2.into()], &token).await.unwrap(),
+        vec![vec![2.into(), "second".into()]]
+    );
+
+    // and so must transactions, which commit once the backups have the writes too
+    let mut txn = g.transaction();
+    txn.insert(&article, vec![3.into(), "third".into()]);
+    let token = g.commit(txn).await.unwrap().token;
+    assert_eq!(
+        read.lookup_after(&[3.into()], &token).await.unwrap(),
+        vec![vec![3.into(), "third".into()]]
+    );
+
+    // queries over bases with backups work like any other
+    g.extend_recipe("QUERY UserById: SELECT id, name FROM User WHERE id = ?;")
+        .await
+        .unwrap();
+    let mut user_table = g.table("User").await.unwrap();
+    user_table.set_write_tokens(true);
+    let mut read = g.view("UserById").await.unwrap();
+    let token = user_table
+        .insert(vec![1.into(), "alice".into()])
+        .await
+        .unwrap()
+        .token;
+    assert_eq!(
+        read.lookup_after(&[1.into()], &token).await.unwrap(),
+        vec![vec![1.into(), "alice".into()]]
+    );
+
+    // and lose them along with the base
+    g.install_recipe(&format!(
+        "{}\nQUERY UserById: SELECT id, name FROM User WHERE id = ?;",
+        user
+    ))
+    .await
+    .unwrap();
+    assert!(g.table("Article").await.is_err());
+    let token = user_table
+        .insert(vec![2.into(), "bob".into()])
+        .await
+        .unwrap()
+        .token;
+    assert_eq!(
+        read.lookup_after(&[2.into()], &token).await.unwrap(),
+        vec![vec![2.into(), "bob".into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_reshards_tables() {
+    use noria::error::ReshardError;
+
+    let mut g = start_simple("it_reshards_tables").await;
+    g.install_recipe(
+        "CREATE TABLE Article (id int, title varchar(255), PRIMARY KEY(id));
+         CREATE TABLE Vote (article_id int, user int);
+         CREATE TABLE Tag (article_id int, tag varchar(255), PRIMARY KEY(article_id, tag));
+         QUERY ArticleById: SELECT id, title FROM Article WHERE id = ?;
+         QUERY VotesByArticle: SELECT article_id, COUNT(user) AS votes \
+                    FROM Vote WHERE article_id = ? GROUP BY article_id;",
+    )
+    .await
+    .unwrap();
+
+    let mut article = g.table("Article").await.unwrap();
+    article.set_write_tokens(true);
+    let mut vote = g.table("Vote").await.unwrap();
+    vote.set_write_tokens(true);
+    for id in 0..10 {
+        article
+            .insert(vec![id.into(), format!("article {}", id).into()])
+            .await
+            .unwrap();
+        vote.insert(vec![id.into(), 1.into()]).await.unwrap();
+    }
+    sleep().await;
+
+    let mut read = g.view("ArticleById").await.unwrap();
+    let mut votes = g.view("VotesByArticle").await.unwrap();
+
+    // the rows make it to the new shards, and from there to the rebuilt views, while the handles
+    // from before keep working
+    g.reshard("Article", None, DEFAULT_SHARDING + 1)
+        .await
+        .unwrap();
+    for id in 0..10 {
+        assert_eq!(
+            read.lookup(&[id.into()], true).await.unwrap(),
+            vec![vec![id.into(), format!("article {}", id).into()]]
+        );
+    }
+    let token = article
+        .insert(vec![10.into(), "article 10".into()])
+        .await
+        .unwrap()
+        .token;
+    assert_eq!(
+        read.lookup_after(&[10.into()], &token).await.unwrap(),
+        vec![vec![10.into(), "article 10".into()]]
+    );
+
+    // and so do new handles
+    let mut article = g.table("Article").await.unwrap();
+    article.set_write_tokens(true);
+    let token = article
+        .insert(vec![11.into(), "article 11".into()])
+        .await
+        .unwrap()
+        .token;
+    let mut read = g.view("ArticleById").await.unwrap();
+    assert_eq!(
+        read.lookup_after(&[11.into()], &token).await.unwrap(),
+        vec![vec![11.into(), "article 11".into()]]
+    );
+
+    // a table without a primary key can be sharded by another column
+    g.reshard("Vote", Some("user"), DEFAULT_SHARDING + 1)
+        .await
+        .unwrap();
+    let token = vote.insert(vec![3.into(), 2.into()]).await.unwrap().token;
+    assert_eq!(
+        votes.lookup_after(&[3.into()], &token).await.unwrap(),
+        vec![vec![3.into(), 2.into()]]
+    );
+
+    // views can be resharded too, and their handles must then be fetched again
+    g.reshard("VotesByArticle", None, DEFAULT_SHARDING + 1)
+        .await
+        .unwrap();
+    let token = vote.insert(vec![3.into(), 3.into()]).await.unwrap().token;
+    let mut votes = g.view("VotesByArticle").await.unwrap();
+    assert_eq!(
+        votes.lookup_after(&[3.into()], &token).await.unwrap(),
+        vec![vec![3.into(), 3.into()]]
+    );
+
+    // asking for the current sharding changes nothing
+    g.reshard("Vote", Some("user"), DEFAULT_SHARDING + 1)
+        .await
+        .unwrap();
+    let e = g.reshard("Nope", None, 4).await.unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<ReshardError>(),
+        Some(&ReshardError::NoSuchTable("Nope".to_owned()))
+    );
+    let e = g.reshard("Tag", None, 4).await.unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<ReshardError>(),
+        Some(&ReshardError::NotSharded("Tag".to_owned()))
+    );
+    let e = g.reshard("Vote", None, 1).await.unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<ReshardError>(),
+        Some(&ReshardError::TooFewShards(1))
+    );
+    let e = g.reshard("Vote", Some("nope"), 4).await.unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<ReshardError>(),
+        Some(&ReshardError::NoSuchColumn(
+            "Vote".to_owned(),
+            "nope".to_owned()
+        ))
+    );
+    let e = g.reshard("Article", Some("title"), 4).await.unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<ReshardError>(),
+        Some(&ReshardError::CannotShardBy(
+            "Article".to_owned(),
+            "title".to_owned()
+        ))
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_moves_domains() {
+    use noria::error::MoveDomainError;
+    use noria::internal::DomainIndex;
+
+    let authority = Arc::new(LocalAuthority::new());
+    let mut b = Builder::default();
+    b.set_sharding(Some(DEFAULT_SHARDING));
+    b.set_persistence(get_persistence_params("it_moves_domains"));
+    let (mut g, done) = b.start(authority.clone()).await.unwrap();
+    let (g2, done2) = b.start(authority.clone()).await.unwrap();
+    let workers = loop {
+        let workers = g.instances().await.unwrap();
+        if workers.len() == 2 {
+            break workers;
+        }
+        sleep().await;
+    };
+
+    g.install_recipe(
+        "CREATE TABLE Article (id int, title varchar(255), PRIMARY KEY(id));
+         QUERY ArticleById: SELECT id, title FROM Article WHERE id = ?;",
+    )
+    .await
+    .unwrap();
+    let mut article = g.table("Article").await.unwrap();
+    article.set_write_tokens(true);
+    for id in 0..10 {
+        article
+            .insert(vec![id.into(), format!("article {}", id).into()])
+            .await
+            .unwrap();
+    }
+    sleep().await;
 
-    // send a value on a
-    muta.insert(vec![1.into(), 2.into()]).await.unwrap();
+    // move every domain to each worker in turn, writing through the same table handle as we go
+    for (i, &(worker, _, _)) in workers.iter().enumerate() {
+        let domains: Vec<_> = g.statistics().await.unwrap().keys().cloned().collect();
+        for (domain, shard) in domains {
+            // a shard in the middle of a replay can be moved once it is done
+            while let Err(e) = g.move_domain(domain, shard, worker).await {
+                assert_eq!(
+                    e.downcast_ref::<MoveDomainError>(),
+                    Some(&MoveDomainError::Busy {
+                        domain: domain.index(),
+                        shard
+                    })
+                );
+                sleep().await;
+            }
+        }
+
+        // the old copies of the shards are gone, so the first write through the table handle may
+        // fail, after which the handle writes to where the shards went
+        let id = 10 + i as i32;
+        let row: Vec<DataType> = vec![id.into(), format!("article {}", id).into()];
+        if article.insert(row.clone()).await.is_err() {
+            article.insert(row).await.unwrap();
+        }
+    }
     sleep().await;
+
+    // the rows survive the moves, and so do the writes made through the old table handle
+    let mut read = g.view("ArticleById").await.unwrap();
+    for id in 0..10 + workers.len() as i32 {
+        assert_eq!(
+            read.lookup(&[id.into()], true).await.unwrap(),
+            vec![vec![id.into(), format!("article {}", id).into()]]
+        );
+    }
+    let token = article
+        .insert(vec![100.into(), "article 100".into()])
+        .await
+        .unwrap()
+        .token;
     assert_eq!(
-        cq.lookup(&[1.into()], true).await.unwrap(),
-        vec![vec![1.into(), 2.into()]]
+        read.lookup_after(&[100.into()], &token).await.unwrap(),
+        vec![vec![100.into(), "article 100".into()]]
     );
 
-    // send a value on b
-    mutb.insert(vec![0.into(), 1.into(), 4.into()])
+    let e = g
+        .move_domain(DomainIndex::from(1000), 0, workers[0].0)
         .await
-        .unwrap();
-    sleep().await;
-
-    let res = cq.lookup(&[1.into()], true).await.unwrap();
-    assert_eq!(res.len(), 2);
-    assert!(res.contains(&vec![1.into(), 2.into()]));
-    assert!(res.contains(&vec![1.into(), 4.into()]));
-
-    // delete first value
-    muta.delete(vec![2.into()]).await.unwrap();
-    sleep().await;
+        .unwrap_err();
     assert_eq!(
-        cq.lookup(&[1.into()], true).await.unwrap(),
-        vec![vec![1.into(), 4.into()]]
+        e.downcast_ref::<MoveDomainError>(),
+        Some(&MoveDomainError::NoSuchDomain {
+            domain: 1000,
+            shard: 0
+        })
+    );
+    let nowhere = "127.0.0.1:1".parse().unwrap();
+    let e = g
+        .move_domain(DomainIndex::from(0), 0, nowhere)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<MoveDomainError>(),
+        Some(&MoveDomainError::NoSuchWorker(nowhere))
     );
+
+    drop(g2);
+    done2.await;
+    drop(g);
+    done.await;
 }
 
 #[tokio::test(threaded_scheduler)]
-async fn it_works_with_sql_recipe() {
-    let mut g = start_simple("it_works_with_sql_recipe").await;
-    let sql = "
-        CREATE TABLE Car (id int, brand varchar(255), PRIMARY KEY(id));
-        QUERY CountCars: SELECT COUNT(*) FROM Car WHERE brand = ?;
-    ";
-    g.install_recipe(sql).await.unwrap();
+async fn it_drains_workers() {
+    use noria::error::DrainError;
+    use noria::WorkerState;
 
-    let mut mutator = g.table("Car").await.unwrap();
-    let mut getter = g.view("CountCars").await.unwrap();
+    let authority = Arc::new(LocalAuthority::new());
+    let mut b = Builder::default();
+    b.set_sharding(Some(DEFAULT_SHARDING));
+    b.set_persistence(get_persistence_params("it_drains_workers"));
+    let (mut g, done) = b.start(authority.clone()).await.unwrap();
+    let (g2, done2) = b.start(authority.clone()).await.unwrap();
+    let workers = loop {
+        let workers = g.instances().await.unwrap();
+        if workers.len() == 2 {
+            break workers;
+        }
+        sleep().await;
+    };
+    let (drained, other) = (workers[0].0, workers[1].0);
 
-    assert_eq!(mutator.table_name(), "Car");
-    assert_eq!(mutator.columns(), &["id", "brand"]);
+    g.install_recipe(
+        "CREATE TABLE Article (id int, title varchar(255), PRIMARY KEY(id));
+         QUERY ArticleById: SELECT id, title FROM Article WHERE id = ?;",
+    )
+    .await
+    .unwrap();
+    let mut article = g.table("Article").await.unwrap();
+    for id in 0..10 {
+        article
+            .insert(vec![id.into(), format!("article {}", id).into()])
+            .await
+            .unwrap();
+    }
+    sleep().await;
 
-    let brands = vec!["Volvo", "Volvo", "Volkswagen"];
-    for (i, &brand) in brands.iter().enumerate() {
-        mutator.insert(vec![i.into(), brand.into()]).await.unwrap();
+    // shards in the middle of a replay stay behind, and are moved by draining again
+    while let Err(e) = g.drain(drained).await {
+        assert_eq!(
+            e.downcast_ref::<DrainError>(),
+            Some(&DrainError::StillHoldsDomains(drained))
+        );
+        sleep().await;
     }
+    let state = |workers: Vec<(std::net::SocketAddr, WorkerState, Duration)>, worker| {
+        workers.into_iter().find(|w| w.0 == worker).unwrap().1
+    };
+    let workers = g.instances().await.unwrap();
+    assert_eq!(state(workers.clone(), drained), WorkerState::Drained);
+    assert_eq!(state(workers, other), WorkerState::Healthy);
 
-    // Let writes propagate:
-    sleep().await;
+    // the rows survive the drain
+    let mut read = g.view("ArticleById").await.unwrap();
+    for id in 0..10 {
+        assert_eq!(
+            read.lookup(&[id.into()], true).await.unwrap(),
+            vec![vec![id.into(), format!("article {}", id).into()]]
+        );
+    }
 
-    // Retrieve the result of the count query:
-    let result = getter.lookup(&["Volvo".into()], true).await.unwrap();
-    assert_eq!(result.len(), 1);
-    assert_eq!(result[0][0], 2.into());
+    // and new domains go elsewhere
+    g.extend_recipe("QUERY ArticleByTitle: SELECT id, title FROM Article WHERE title = ?;")
+        .await
+        .unwrap();
+    let workers = g.instances().await.unwrap();
+    assert_eq!(state(workers, drained), WorkerState::Drained);
+    let mut read = g.view("ArticleByTitle").await.unwrap();
+    assert_eq!(
+        read.lookup(&["article 3".into()], true).await.unwrap(),
+        vec![vec![3.into(), "article 3".into()]]
+    );
+
+    let e = g.drain(other).await.unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<DrainError>(),
+        Some(&DrainError::NoOtherWorker(other))
+    );
+    let nowhere = "127.0.0.1:1".parse().unwrap();
+    let e = g.drain(nowhere).await.unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<DrainError>(),
+        Some(&DrainError::NoSuchWorker(nowhere))
+    );
+
+    // a worker that is no longer drained may be given domains again
+    g.cancel_drain(drained).await.unwrap();
+    let workers = g.instances().await.unwrap();
+    assert_eq!(state(workers, drained), WorkerState::Healthy);
+    let e = g.cancel_drain(nowhere).await.unwrap_err();
+    assert_eq!(
+        e.downcast_ref::<DrainError>(),
+        Some(&DrainError::NoSuchWorker(nowhere))
+    );
+
+    drop(g2);
+    done2.await;
+    drop(g);
+    done.await;
 }
 
 #[tokio::test(threaded_scheduler)]
@@ -809,6 +2196,151 @@ async fn it_works_with_double_query_through() {
     assert_eq!(empty.len(), 0);
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_right_and_full_joins() {
+    let mut g = start_simple("it_works_with_right_and_full_joins").await;
+    let sql = "
+        CREATE TABLE Author (id int, name varchar(40), PRIMARY KEY(id));
+        CREATE TABLE Book (id int, author int, title varchar(40), PRIMARY KEY(id));
+        QUERY AllAuthorsAndBooks: SELECT Author.name, Book.title \
+            FROM Author FULL OUTER JOIN Book ON (Author.id = Book.author);
+        QUERY BooksWithAuthors: SELECT Author.name, Book.title \
+            FROM Author RIGHT JOIN Book ON (Author.id = Book.author);
+    ";
+
+    g.install_recipe(sql).await.unwrap();
+    let mut author = g.table("Author").await.unwrap();
+    let mut book = g.table("Book").await.unwrap();
+    let mut full = g.view("AllAuthorsAndBooks").await.unwrap();
+    let mut right = g.view("BooksWithAuthors").await.unwrap();
+
+    author.insert(vec![1.into(), "a".into()]).await.unwrap();
+    author.insert(vec![2.into(), "b".into()]).await.unwrap();
+    book.insert(vec![10.into(), 2.into(), "x".into()])
+        .await
+        .unwrap();
+    book.insert(vec![11.into(), 3.into(), "y".into()])
+        .await
+        .unwrap();
+
+    sleep().await;
+
+    let sorted = |rs: Vec<Vec<DataType>>| {
+        let mut rs: Vec<Vec<DataType>> = rs.into_iter().map(|r| r[..2].to_vec()).collect();
+        rs.sort();
+        rs
+    };
+    let mut expected = vec![
+        vec!["a".into(), DataType::None],
+        vec!["b".into(), "x".into()],
+        vec![DataType::None, "y".into()],
+    ];
+    expected.sort();
+    let rs = full.lookup(&[0.into()], true).await.unwrap();
+    assert_eq!(sorted(rs.into()), expected);
+    let mut expected = vec![
+        vec!["b".into(), "x".into()],
+        vec![DataType::None, "y".into()],
+    ];
+    expected.sort();
+    let rs = right.lookup(&[0.into()], true).await.unwrap();
+    assert_eq!(sorted(rs.into()), expected);
+
+    // a new author takes the place of the padding for their book on both sides
+    author.insert(vec![3.into(), "c".into()]).await.unwrap();
+    sleep().await;
+
+    let rs = full.lookup(&[0.into()], true).await.unwrap();
+    assert_eq!(
+        sorted(rs.into()),
+        vec![
+            vec!["a".into(), DataType::None],
+            vec!["b".into(), "x".into()],
+            vec!["c".into(), "y".into()],
+        ]
+    );
+    let rs = right.lookup(&[0.into()], true).await.unwrap();
+    assert_eq!(
+        sorted(rs.into()),
+        vec![vec!["b".into(), "x".into()], vec!["c".into(), "y".into()]]
+    );
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_subqueries() {
+    let mut g = start_simple("it_works_with_subqueries").await;
+    let sql = "
+        CREATE TABLE Post (id int, author int, PRIMARY KEY(id));
+        CREATE TABLE Seen (reader int, post int, PRIMARY KEY(reader));
+        QUERY Unread: SELECT Post.id FROM Post \
+            WHERE NOT EXISTS (SELECT * FROM Seen WHERE Seen.post = Post.id) AND Post.author = ?;
+        QUERY Read: SELECT Post.id FROM Post \
+            WHERE Post.id IN (SELECT post FROM Seen) AND Post.author = ?;
+        QUERY Unseen: SELECT Post.id FROM Post \
+            WHERE Post.id NOT IN (SELECT post FROM Seen) AND Post.author = ?;
+        QUERY Readers: SELECT Post.id, \
+            (SELECT COUNT(*) FROM Seen WHERE Seen.post = Post.id) AS readers \
+            FROM Post WHERE Post.author = ?;
+    ";
+
+    g.install_recipe(sql).await.unwrap();
+    let mut post = g.table("Post").await.unwrap();
+    let mut seen = g.table("Seen").await.unwrap();
+    let mut unread = g.view("Unread").await.unwrap();
+    let mut read = g.view("Read").await.unwrap();
+    let mut unseen = g.view("Unseen").await.unwrap();
+    let mut readers = g.view("Readers").await.unwrap();
+
+    for id in 1..=3 {
+        post.insert(vec![id.into(), 1.into()]).await.unwrap();
+    }
+    for &(reader, p) in &[(10, 1), (11, 1), (12, 2)] {
+        seen.insert(vec![reader.into(), p.into()]).await.unwrap();
+    }
+
+    sleep().await;
+
+    let sorted = |rs: Vec<Vec<DataType>>, cols: usize| {
+        let mut rs: Vec<Vec<DataType>> = rs.into_iter().map(|r| r[..cols].to_vec()).collect();
+        rs.sort();
+        rs
+    };
+    let rs = unread.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(sorted(rs.into(), 1), vec![vec![3.into()]]);
+    // posts that were read twice still only show up once
+    let rs = read.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(sorted(rs.into(), 1), vec![vec![1.into()], vec![2.into()]]);
+    let rs = readers.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(
+        sorted(rs.into(), 2),
+        vec![
+            vec![1.into(), 2.into()],
+            vec![2.into(), 1.into()],
+            vec![3.into(), DataType::None],
+        ]
+    );
+    let rs = unseen.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(sorted(rs.into(), 1), vec![vec![3.into()]]);
+
+    // a NULL in a NOT IN subquery could be any post, but NOT EXISTS never matches it
+    seen.insert(vec![14.into(), DataType::None]).await.unwrap();
+    sleep().await;
+
+    let rs = unseen.lookup(&[1.into()], true).await.unwrap();
+    assert!(rs.is_empty());
+    let rs = unread.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(sorted(rs.into(), 1), vec![vec![3.into()]]);
+
+    // once the last post has been read, nothing is unread any more
+    seen.insert(vec![13.into(), 3.into()]).await.unwrap();
+    sleep().await;
+
+    let rs = unread.lookup(&[1.into()], true).await.unwrap();
+    assert!(rs.is_empty());
+    let rs = read.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(rs.len(), 3);
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn it_works_with_reads_before_writes() {
     let mut g = start_simple("it_works_with_reads_before_writes").await;
@@ -1302,6 +2834,229 @@ async fn it_works_with_join_arithmetic() {
     assert_eq!(result[0][1], (f64::from(price) * fraction).into());
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_arithmetic_predicates() {
+    let mut g = start_simple("it_works_with_arithmetic_predicates").await;
+    let sql = "
+        CREATE TABLE Line (id int, price int, qty int, PRIMARY KEY(id));
+        QUERY Large: SELECT id, price * qty AS total FROM Line WHERE price * qty > 100;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut mutator = g.table("Line").await.unwrap();
+    let mut getter = g.view("Large").await.unwrap();
+    for &(id, price, qty) in &[(1, 10, 5), (2, 30, 4), (3, 101, 1)] {
+        mutator
+            .insert(vec![id.into(), price.into(), qty.into()])
+            .await
+            .unwrap();
+    }
+
+    // Let writes propagate:
+    sleep().await;
+
+    let mut result: Vec<(DataType, DataType)> = getter
+        .lookup(&[0.into()], true)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| (r[0].clone(), r[1].clone()))
+        .collect();
+    result.sort();
+    assert_eq!(result, vec![(2.into(), 120.into()), (3.into(), 101.into())]);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_rejects_unsupported_queries_without_side_effects() {
+    let mut g = start_simple("it_rejects_unsupported_queries_without_side_effects").await;
+    let sql = "
+        CREATE TABLE Line (id int, price int, PRIMARY KEY(id));
+        QUERY Price: SELECT price FROM Line WHERE id = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
+    let outputs = g.outputs().await.unwrap();
+
+    // the first query is fine, but the second compares a parameter to an arithmetic expression
+    let bad = "
+        QUERY Cheap: SELECT id FROM Line WHERE price < 10;
+        QUERY Broken: SELECT id FROM Line WHERE price + 1 = ?;
+    ";
+    let e = g.extend_recipe(bad).await.unwrap_err();
+    let e = e.downcast_ref::<noria::RecipeError>().unwrap();
+    assert_eq!(e.query, Some("Broken".to_owned()));
+    assert!(e.sql.is_some());
+
+    // neither query was added, and the existing one keeps working
+    assert_eq!(g.outputs().await.unwrap(), outputs);
+    let mut mutator = g.table("Line").await.unwrap();
+    let mut getter = g.view("Price").await.unwrap();
+    mutator.insert(vec![1.into(), 42.into()]).await.unwrap();
+
+    // Let writes propagate:
+    sleep().await;
+
+    let result = getter.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0][0], 42.into());
+
+    // and the recipe can still be extended afterwards
+    g.extend_recipe("QUERY Cheap: SELECT id FROM Line WHERE price < 10;")
+        .await
+        .unwrap();
+    assert!(g.outputs().await.unwrap().contains_key("Cheap"));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_offsets() {
+    let mut g = start_simple("it_works_with_offsets").await;
+    let sql = "
+        CREATE TABLE Post (id int, author int, PRIMARY KEY(id));
+        QUERY Skipped: SELECT id FROM Post WHERE author = ? ORDER BY id LIMIT 2 OFFSET 2;
+        QUERY Paged: SELECT id FROM Post WHERE author = ? ORDER BY id LIMIT 2 OFFSET ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut mutator = g.table("Post").await.unwrap();
+    let mut skipped = g.view("Skipped").await.unwrap();
+    let mut paged = g.view("Paged").await.unwrap();
+    for id in 1..=5 {
+        mutator.insert(vec![id.into(), 1.into()]).await.unwrap();
+    }
+
+    // Let writes propagate:
+    sleep().await;
+
+    let ids = |rs: Vec<Vec<DataType>>| {
+        let mut ids: Vec<i32> = rs.into_iter().map(|r| i32::from(&r[0])).collect();
+        ids.sort();
+        ids
+    };
+
+    let result = skipped.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(ids(result.into()), vec![3, 4]);
+
+    // pages are numbered from 0, and the last page may be partially filled
+    let result = paged.lookup(&[1.into(), 0.into()], true).await.unwrap();
+    assert_eq!(ids(result.into()), vec![1, 2]);
+    let result = paged.lookup(&[1.into(), 2.into()], true).await.unwrap();
+    assert_eq!(ids(result.into()), vec![5]);
+
+    // a new first post shifts every later post forward by one
+    mutator.insert(vec![0.into(), 1.into()]).await.unwrap();
+    sleep().await;
+
+    let result = skipped.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(ids(result.into()), vec![2, 3]);
+    let result = paged.lookup(&[1.into(), 1.into()], true).await.unwrap();
+    assert_eq!(ids(result.into()), vec![2, 3]);
+    let result = paged.lookup(&[1.into(), 2.into()], true).await.unwrap();
+    assert_eq!(ids(result.into()), vec![4, 5]);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_select_list_expressions() {
+    let mut g = start_simple("it_works_with_select_list_expressions").await;
+    let sql = "
+        CREATE TABLE Person (id int, name varchar(255), nick varchar(255), age int, PRIMARY KEY(id));
+        QUERY People: SELECT id, CASE WHEN age >= 18 THEN 'adult' ELSE 'minor' END AS kind, \
+            LOWER(name) AS lname, COALESCE(nick, name) AS known_as, \
+            CONCAT(name, ' (', age + 1, ')') AS next FROM Person WHERE id = ?;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut mutator = g.table("Person").await.unwrap();
+    let mut people = g.view("People").await.unwrap();
+    mutator
+        .insert(vec![1.into(), "Alice".into(), DataType::None, 30.into()])
+        .await
+        .unwrap();
+    mutator
+        .insert(vec![2.into(), "Bob".into(), "bobby".into(), 12.into()])
+        .await
+        .unwrap();
+
+    // Let writes propagate:
+    sleep().await;
+
+    let columns = people.columns().to_vec();
+    let get = |row: &[DataType], column: &str| {
+        let i = columns.iter().position(|c| c == column).unwrap();
+        row[i].clone()
+    };
+
+    let result: Vec<Vec<DataType>> = people.lookup(&[1.into()], true).await.unwrap().into();
+    assert_eq!(result.len(), 1);
+    assert_eq!(get(&result[0], "kind"), DataType::from("adult"));
+    assert_eq!(get(&result[0], "lname"), DataType::from("alice"));
+    assert_eq!(get(&result[0], "known_as"), DataType::from("Alice"));
+    assert_eq!(get(&result[0], "next"), DataType::from("Alice (31)"));
+
+    let result: Vec<Vec<DataType>> = people.lookup(&[2.into()], true).await.unwrap().into();
+    assert_eq!(get(&result[0], "kind"), DataType::from("minor"));
+    assert_eq!(get(&result[0], "known_as"), DataType::from("bobby"));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_avg_and_distinct_aggregates() {
+    let mut g = start_simple("it_works_with_avg_and_distinct_aggregates").await;
+    let sql = "
+        CREATE TABLE Rating (id int, movie int, stars int, PRIMARY KEY(id));
+        QUERY AvgStars: SELECT movie, AVG(stars) AS avg_stars FROM Rating \
+                        WHERE movie = ? GROUP BY movie;
+        QUERY DistinctStars: SELECT movie, COUNT(DISTINCT stars) AS distinct_stars FROM Rating \
+                             WHERE movie = ? GROUP BY movie;
+        QUERY Spread: SELECT movie, VARIANCE(stars) AS var_stars FROM Rating \
+                      WHERE movie = ? GROUP BY movie;
+        QUERY AllRated: SELECT movie, bool_and(stars) AS all_rated FROM Rating \
+                        WHERE movie = ? GROUP BY movie;
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut mutator = g.table("Rating").await.unwrap();
+    let mut avg = g.view("AvgStars").await.unwrap();
+    let mut distinct = g.view("DistinctStars").await.unwrap();
+    let mut spread = g.view("Spread").await.unwrap();
+    let mut all_rated = g.view("AllRated").await.unwrap();
+    for (id, stars) in vec![(1, 2), (2, 4), (3, 4), (4, 6)] {
+        mutator
+            .insert(vec![id.into(), 1.into(), stars.into()])
+            .await
+            .unwrap();
+    }
+
+    // Let writes propagate:
+    sleep().await;
+
+    let value = |rs: Vec<Vec<DataType>>| {
+        assert_eq!(rs.len(), 1);
+        rs[0][1].clone()
+    };
+
+    let result = avg.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(value(result.into()), 4.0.into());
+    let result = distinct.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(value(result.into()), 3.into());
+    let result = spread.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(value(result.into()), 2.0.into());
+    let result = all_rated.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(value(result.into()), 1.into());
+
+    // a duplicate value only stops counting once its last occurrence is gone
+    mutator.delete(vec![2.into()]).await.unwrap();
+    mutator
+        .insert(vec![5.into(), 1.into(), 0.into()])
+        .await
+        .unwrap();
+    sleep().await;
+
+    let result = avg.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(value(result.into()), 3.0.into());
+    let result = distinct.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(value(result.into()), 4.into());
+    let result = all_rated.lookup(&[1.into()], true).await.unwrap();
+    assert_eq!(value(result.into()), 0.into());
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn it_works_with_function_arithmetic() {
     let mut g = start_simple("it_works_with_function_arithmetic").await;
@@ -1327,6 +3082,47 @@ async fn it_works_with_function_arithmetic() {
     assert_eq!(result[0][0], DataType::from(max_price * 2));
 }
 
+#[tokio::test(threaded_scheduler)]
+async fn it_works_with_disjunctive_predicates() {
+    let mut g = start_simple("it_works_with_disjunctive_predicates").await;
+    let sql = "
+        CREATE TABLE Item (id int, kind int, price int, PRIMARY KEY(id));
+        QUERY Picks: SELECT Item.id FROM Item \
+                     WHERE (Item.kind = 1 OR Item.price < 10) AND Item.id NOT IN (3, 4);
+    ";
+    g.install_recipe(sql).await.unwrap();
+
+    let mut mutator = g.table("Item").await.unwrap();
+    let mut getter = g.view("Picks").await.unwrap();
+    for &(id, kind, price) in &[
+        (1, 1, 100),
+        (2, 2, 5),
+        (3, 1, 5),
+        (4, 2, 5),
+        (5, 2, 100),
+        (6, 1, 5),
+    ] {
+        mutator
+            .insert(vec![id.into(), kind.into(), price.into()])
+            .await
+            .unwrap();
+    }
+
+    // Let writes propagate:
+    sleep().await;
+
+    // item 6 matches both sides of the OR, but must only show up once
+    let mut ids: Vec<DataType> = getter
+        .lookup(&[0.into()], true)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r[0].clone())
+        .collect();
+    ids.sort();
+    assert_eq!(ids, vec![1.into(), 2.into(), 6.into()]);
+}
+
 #[tokio::test(threaded_scheduler)]
 async fn votes() {
     // set up graph
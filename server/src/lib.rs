@@ -392,6 +392,7 @@ pub mod manual {
 }
 
 use dataflow::DomainConfig;
+use std::collections::HashMap;
 use std::time;
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
@@ -406,6 +407,9 @@ pub(crate) struct Config {
     pub(crate) quorum: usize,
     pub(crate) reuse: ReuseConfigType,
     pub(crate) threads: Option<usize>,
+    pub(crate) reader_replicas: HashMap<String, usize>,
+    pub(crate) base_backups: usize,
+    pub(crate) rebalance_every: Option<time::Duration>,
 }
 impl Default for Config {
     fn default() -> Self {
@@ -429,6 +433,9 @@ impl Default for Config {
             threads: Some(2),
             #[cfg(not(any(debug_assertions, test)))]
             threads: None,
+            reader_replicas: HashMap::new(),
+            base_backups: 0,
+            rebalance_every: None,
         }
     }
 }
@@ -206,6 +206,7 @@ async fn listen_df<'a>(
 
     // reader setup
     let readers = Arc::new(Mutex::new(HashMap::new()));
+    let change_logs = Arc::new(Mutex::new(HashMap::new()));
     let rport = tokio::net::TcpListener::bind(&SocketAddr::new(on, 0)).await?;
     let raddr = rport.local_addr()?;
     info!(log, "listening for reads"; "on" => ?raddr);
@@ -237,6 +238,7 @@ async fn listen_df<'a>(
         valve.clone(),
         rport,
         readers.clone(),
+        change_logs.clone(),
     ));
 
     // and tell the controller about us
@@ -306,6 +308,7 @@ async fn listen_df<'a>(
                     d.build(
                         log.clone(),
                         readers.clone(),
+                        change_logs.clone(),
                         coord.clone(),
                         dcaddr,
                         &valve,
@@ -316,10 +319,10 @@ async fn listen_df<'a>(
                 let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
 
                 // need to register the domain with the local channel coordinator.
-                // local first to ensure that we don't unnecessarily give away remote for a
-                // local thing if there's a race
-                coord.insert_local((idx, shard), tx);
+                // remote first, since a new address for a shard that moved here before drops the
+                // local channel of the copy it left behind
                 coord.insert_remote((idx, shard), addr);
+                coord.insert_local((idx, shard), tx);
 
                 tokio::task::block_in_place(|| {
                     state_sizes.lock().unwrap().insert((idx, shard), state_size)
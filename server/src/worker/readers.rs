@@ -1,19 +1,20 @@
 use async_bincode::AsyncBincodeStream;
 use dataflow::prelude::DataType;
 use dataflow::prelude::*;
-use dataflow::Readers;
 use dataflow::SingleReadHandle;
+use dataflow::{ChangeLogs, Readers};
 use futures_util::{
     future,
     future::Either,
     future::{FutureExt, TryFutureExt},
     stream::{StreamExt, TryStreamExt},
 };
-use noria::{ReadQuery, ReadReply, Tagged};
+use noria::{Delta, ReadQuery, ReadReply, Tagged, WriteToken};
 use pin_project::pin_project;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::mem;
+use std::ops::Bound;
 use std::time;
 use std::{future::Future, task::Poll};
 use stream_cancel::Valve;
@@ -29,6 +30,18 @@ const RETRY_TIMEOUT: time::Duration = time::Duration::from_micros(100);
 /// while, waiting readers will use exponential backoff on this delay if they continue to miss.
 const TRIGGER_TIMEOUT_MS: u64 = 20;
 
+/// Reads from the change log of a base table return at most this many changes at a time.
+const MAX_CHANGES_PER_READ: usize = 4096;
+
+/// Range reads against partially materialized readers are answered by looking up every key in the
+/// range individually, so we refuse to do so for ranges that cover more keys than this.
+const MAX_RANGE_EXPANSION: i128 = 4096;
+
+/// Subscription polls and change log reads are held open for at most this long while there are no
+/// changes to report. This must stay well below the time after which the reader expires an
+/// unpolled subscription.
+const POLL_WAIT: time::Duration = time::Duration::from_secs(10);
+
 task_local! {
     static READERS: RefCell<HashMap<
         (NodeIndex, usize),
@@ -60,6 +73,7 @@ pub(super) async fn listen(
     valve: Valve,
     mut on: tokio::net::TcpListener,
     readers: Readers,
+    change_logs: ChangeLogs,
 ) {
     let mut stream = valve.wrap(on.incoming()).into_stream();
     while let Some(stream) = stream.next().await {
@@ -70,6 +84,7 @@ pub(super) async fn listen(
 
         let stream = stream.unwrap();
         let readers = readers.clone();
+        let change_logs = change_logs.clone();
         stream.set_nodelay(true).expect("could not set TCP_NODELAY");
         let alive = alive.clone();
 
@@ -119,7 +134,7 @@ pub(super) async fn listen(
             Default::default(),
             server::Server::new(
                 AsyncBincodeStream::from(stream).for_async(),
-                service_fn(move |req| handle_message(req, &readers, &mut tx)),
+                service_fn(move |req| handle_message(req, &readers, &change_logs, &mut tx)),
             ),
         );
         tokio::spawn(
@@ -179,135 +194,438 @@ where
 fn handle_message(
     m: Tagged<ReadQuery>,
     s: &Readers,
+    change_logs: &ChangeLogs,
     wait: &mut tokio::sync::mpsc::UnboundedSender<(BlockingRead, Ack)>,
 ) -> impl Future<Output = Result<Tagged<ReadReply<SerializedReadReplyBatch>>, ()>> + Send {
     let tag = m.tag;
     match m.v {
         ReadQuery::Normal {
             target,
-            mut keys,
+            keys,
+            block,
+            after,
+        } => Either::Left(handle_normal_read(tag, target, keys, block, after, s, wait)),
+        ReadQuery::Range {
+            target,
+            lower,
+            upper,
             block,
         } => {
-            let immediate = READERS.with(|readers_cache| {
+            let scan = READERS.with(|readers_cache| {
                 let mut readers_cache = readers_cache.borrow_mut();
-                let reader = readers_cache.entry(target).or_insert_with(|| {
-                    let readers = s.lock().unwrap();
-                    readers.get(&target).unwrap().clone()
-                });
-
-                let mut ret = Vec::with_capacity(keys.len());
-
-                // first do non-blocking reads for all keys to see if we can return immediately
-                let mut i = -1;
-                let mut ready = true;
-                let mut pending = Vec::new();
-                keys.retain(|key| {
-                    i += 1;
-                    if !ready {
-                        ret.push(SerializedReadReplyBatch::empty());
-                        return false;
-                    }
-                    let rs = reader.try_find_and(key, |rs| serialize(rs)).map(|r| r.0);
-                    match rs {
-                        Ok(Some(rs)) => {
-                            // immediate hit!
-                            ret.push(rs);
-                            false
-                        }
-                        Err(()) => {
-                            // map not yet ready
-                            ready = false;
-                            ret.push(SerializedReadReplyBatch::empty());
-                            false
-                        }
-                        Ok(None) => {
-                            // need to trigger partial replay for this key
-                            pending.push(i as usize);
-                            ret.push(SerializedReadReplyBatch::empty());
-                            true
-                        }
+                let reader = match cached_reader(&mut readers_cache, s, target) {
+                    Some(reader) => reader,
+                    None => {
+                        return Ok(Tagged {
+                            tag,
+                            v: ReadReply::Normal(Err(())),
+                        })
                     }
-                });
-
-                if !ready {
+                };
+                if !reader.range_agrees(&lower, &upper) {
                     return Ok(Tagged {
                         tag,
-                        v: ReadReply::Normal(Err(())),
+                        v: ReadReply::RangeBoundMismatch,
                     });
                 }
 
-                if keys.is_empty() {
-                    // we hit on all the keys!
-                    assert!(pending.is_empty());
+                if reader.is_partial() {
+                    // partial state may have holes anywhere in the range, so the best we can do
+                    // is to look up (and backfill) every key in the range that this shard owns.
+                    let mut keys = match expand_range(&lower, &upper) {
+                        Some(keys) => keys,
+                        None => {
+                            return Ok(Tagged {
+                                tag,
+                                v: ReadReply::UnsupportedRange,
+                            })
+                        }
+                    };
+                    keys.retain(|key| reader.owns_key(key));
+                    if block {
+                        return Err(keys);
+                    }
+
+                    // a non-blocking read must not pass off part of the range as all of it, so
+                    // unless every key hits, it reports a miss and backfills the missing keys.
+                    let mut found = Vec::with_capacity(keys.len());
+                    let mut missing = Vec::new();
+                    for key in &keys {
+                        match reader.try_find_and(key, |rs| serialize(rs)).map(|r| r.0) {
+                            Ok(Some(rs)) => found.push(rs),
+                            Ok(None) => missing.push(key),
+                            Err(()) => {
+                                return Ok(Tagged {
+                                    tag,
+                                    v: ReadReply::Normal(Err(())),
+                                })
+                            }
+                        }
+                    }
+                    if !missing.is_empty() {
+                        reader.trigger(missing.into_iter().map(Vec::as_slice));
+                        return Ok(Tagged {
+                            tag,
+                            v: ReadReply::RangeMiss,
+                        });
+                    }
                     return Ok(Tagged {
                         tag,
-                        v: ReadReply::Normal(Ok(ret)),
+                        v: ReadReply::Normal(Ok(found)),
                     });
                 }
 
-                // trigger backfills for all the keys we missed on
-                reader.trigger(keys.iter().map(Vec::as_slice));
+                let rs = reader.try_find_range_and(
+                    as_slice_bound(&lower),
+                    as_slice_bound(&upper),
+                    |rs| serialize(rs),
+                );
+                Ok(Tagged {
+                    tag,
+                    v: ReadReply::Normal(match rs {
+                        Ok(Some(batches)) => Ok(batches),
+                        Ok(None) => unreachable!("reader is fully materialized"),
+                        Err(()) => Err(()),
+                    }),
+                })
+            });
+
+            match scan {
+                Ok(reply) => Either::Right(Either::Left(future::ready(Ok(reply)))),
+                Err(keys) => {
+                    Either::Left(handle_normal_read(tag, target, keys, true, None, s, wait))
+                }
+            }
+        }
+        ReadQuery::Subscribe { target, keys } => {
+            let id = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                let reader = cached_reader(&mut readers_cache, s, target)?;
+
+                if reader.is_partial() {
+                    // updates to keys that are missing from partial state may be dropped before
+                    // they ever reach the reader, so make sure the keys are filled.
+                    let missing: Vec<_> = keys
+                        .iter()
+                        .filter(|key| matches!(reader.try_find_and(key, |_| ()), Ok((None, _))))
+                        .collect();
+                    if !missing.is_empty() {
+                        reader.trigger(missing.into_iter().map(Vec::as_slice));
+                    }
+                }
+
+                Some(reader.subscribe(keys))
+            });
 
-                Err((keys, ret, pending))
+            Either::Right(Either::Left(future::ready(Ok(Tagged {
+                tag,
+                v: match id {
+                    Some(id) => ReadReply::Subscribed(id),
+                    None => ReadReply::Normal(Err(())),
+                },
+            }))))
+        }
+        ReadQuery::Poll {
+            target,
+            subscription,
+        } => {
+            let deltas = READERS.with(|readers_cache| {
+                let mut readers_cache = readers_cache.borrow_mut();
+                cached_reader(&mut readers_cache, s, target)
+                    .map(|reader| reader.subscription_deltas(subscription))
             });
 
-            match immediate {
-                Ok(reply) => Either::Left(Either::Left(future::ready(Ok(reply)))),
-                Err((keys, ret, pending)) => {
-                    if !block {
-                        Either::Left(Either::Left(future::ready(Ok(Tagged {
-                            tag,
-                            v: ReadReply::Normal(Ok(ret)),
-                        }))))
+            Either::Right(Either::Right(
+                async move {
+                    // hold on to the poll until there is something to report, but not for so long
+                    // that the subscription expires underneath the client. subscriptions to a reader
+                    // that is gone are as good as expired.
+                    let deltas = match deltas {
+                        Some(deltas) => match tokio::time::timeout(POLL_WAIT, deltas).await {
+                            Ok(deltas) => deltas,
+                            Err(_) => Some(Vec::new()),
+                        },
+                        None => None,
+                    };
+                    let deltas = deltas.map(|rs| {
+                        rs.into_iter()
+                            .map(|r| match r {
+                                Record::Positive(r) => Delta::Positive(r),
+                                Record::Negative(r) => Delta::Negative(r),
+                            })
+                            .collect()
+                    });
+                    Ok(Tagged {
+                        tag,
+                        v: ReadReply::Deltas(deltas.ok_or(())),
+                    })
+                }
+                .boxed(),
+            ))
+        }
+        ReadQuery::Changes { target, from } => {
+            let log = change_logs.lock().unwrap().get(&target).cloned();
+            let log = match log {
+                Some(log) => log,
+                None => {
+                    // the base is not ready yet, so it has not made any changes
+                    let changes = if from.is_none() {
+                        Ok((0, Vec::new()))
                     } else {
-                        let (tx, rx) = tokio::sync::oneshot::channel();
-                        let trigger = time::Duration::from_millis(TRIGGER_TIMEOUT_MS);
-                        let now = time::Instant::now();
-                        let r = wait.send((
-                            BlockingRead {
-                                tag,
-                                target,
-                                keys,
-                                pending,
-                                read: ret,
-                                truth: s.clone(),
-                                trigger_timeout: trigger,
-                                next_trigger: now,
-                                first: now,
-                            },
-                            tx,
-                        ));
-                        if r.is_err() {
-                            // we're shutting down
-                            return Either::Left(Either::Left(future::ready(Err(()))));
-                        }
-                        Either::Left(Either::Right(rx.map(|r| match r {
-                            Err(_) => Err(()),
-                            Ok(r) => r,
-                        })))
-                    }
+                        Err(())
+                    };
+                    return Either::Right(Either::Left(future::ready(Ok(Tagged {
+                        tag,
+                        v: ReadReply::Changes(changes),
+                    }))));
                 }
-            }
+            };
+
+            Either::Right(Either::Right(
+                async move {
+                    let epoch = log.epoch();
+                    let changes = log.read(from, MAX_CHANGES_PER_READ);
+                    let changes = match tokio::time::timeout(POLL_WAIT, changes).await {
+                        Ok(changes) => changes,
+                        Err(_) => Some(Vec::new()),
+                    };
+                    let changes = changes.map(|changes| {
+                        let changes = changes
+                            .into_iter()
+                            .map(|(seq, r)| match r {
+                                Record::Positive(r) => (seq, Delta::Positive(r)),
+                                Record::Negative(r) => (seq, Delta::Negative(r)),
+                            })
+                            .collect();
+                        (epoch, changes)
+                    });
+                    Ok(Tagged {
+                        tag,
+                        v: ReadReply::Changes(changes.ok_or(())),
+                    })
+                }
+                .boxed(),
+            ))
         }
         ReadQuery::Size { target } => {
             let size = READERS.with(|readers_cache| {
                 let mut readers_cache = readers_cache.borrow_mut();
-                let reader = readers_cache.entry(target).or_insert_with(|| {
-                    let readers = s.lock().unwrap();
-                    readers.get(&target).unwrap().clone()
-                });
+                cached_reader(&mut readers_cache, s, target).map(|reader| reader.len())
+            });
+
+            Either::Right(Either::Left(future::ready(Ok(Tagged {
+                tag,
+                v: match size {
+                    Some(size) => ReadReply::Size(size),
+                    None => ReadReply::Normal(Err(())),
+                },
+            }))))
+        }
+    }
+}
 
-                reader.len()
+/// The handle for the reader at `target`, taken from the handles cached by this task as long as
+/// the reader it reads from is still around.
+///
+/// Otherwise, the handle is looked up again, since the reader may have been removed with another
+/// one taking over its reads.
+fn cached_reader<'a>(
+    cache: &'a mut HashMap<(NodeIndex, usize), SingleReadHandle>,
+    readers: &Readers,
+    target: (NodeIndex, usize),
+) -> Option<&'a mut SingleReadHandle> {
+    if cache
+        .get(&target)
+        .map_or(true, SingleReadHandle::is_retired)
+    {
+        let reader = readers.lock().unwrap().get(&target).cloned()?;
+        cache.insert(target, reader);
+    }
+    cache.get_mut(&target).filter(|reader| !reader.is_retired())
+}
+
+fn handle_normal_read(
+    tag: u32,
+    target: (NodeIndex, usize),
+    mut keys: Vec<Vec<DataType>>,
+    block: bool,
+    mut after: Option<WriteToken>,
+    s: &Readers,
+    wait: &mut tokio::sync::mpsc::UnboundedSender<(BlockingRead, Ack)>,
+) -> impl Future<Output = Result<Tagged<ReadReply<SerializedReadReplyBatch>>, ()>> + Send {
+    let immediate = READERS.with(|readers_cache| {
+        let mut readers_cache = readers_cache.borrow_mut();
+        let reader = match cached_reader(&mut readers_cache, s, target) {
+            Some(reader) => reader,
+            None => {
+                return Ok(Tagged {
+                    tag,
+                    v: ReadReply::Normal(Err(())),
+                })
+            }
+        };
+
+        let mut ret = Vec::with_capacity(keys.len());
+
+        if after.as_ref().map_or(false, |t| !reader.has_applied(t)) {
+            // the writes we were asked to wait for have not all been applied yet, so none of the
+            // keys can be read. the blocking read will look them up once the writes are in.
+            ret.extend(keys.iter().map(|_| SerializedReadReplyBatch::empty()));
+            let pending = (0..keys.len()).collect();
+            return Err((keys, ret, pending));
+        }
+        after = None;
+
+        // first do non-blocking reads for all keys to see if we can return immediately
+        let mut i = -1;
+        let mut ready = true;
+        let mut pending = Vec::new();
+        keys.retain(|key| {
+            i += 1;
+            if !ready {
+                ret.push(SerializedReadReplyBatch::empty());
+                return false;
+            }
+            let rs = reader.try_find_and(key, |rs| serialize(rs)).map(|r| r.0);
+            match rs {
+                Ok(Some(rs)) => {
+                    // immediate hit!
+                    ret.push(rs);
+                    false
+                }
+                Err(()) => {
+                    // map not yet ready
+                    ready = false;
+                    ret.push(SerializedReadReplyBatch::empty());
+                    false
+                }
+                Ok(None) => {
+                    // need to trigger partial replay for this key
+                    pending.push(i as usize);
+                    ret.push(SerializedReadReplyBatch::empty());
+                    true
+                }
+            }
+        });
+
+        if !ready {
+            return Ok(Tagged {
+                tag,
+                v: ReadReply::Normal(Err(())),
             });
+        }
 
-            Either::Right(future::ready(Ok(Tagged {
+        if keys.is_empty() {
+            // we hit on all the keys!
+            assert!(pending.is_empty());
+            return Ok(Tagged {
                 tag,
-                v: ReadReply::Size(size),
-            })))
+                v: ReadReply::Normal(Ok(ret)),
+            });
+        }
+
+        // trigger backfills for all the keys we missed on
+        reader.trigger(keys.iter().map(Vec::as_slice));
+
+        Err((keys, ret, pending))
+    });
+
+    match immediate {
+        Ok(reply) => Either::Left(future::ready(Ok(reply))),
+        Err((keys, ret, pending)) => {
+            if !block && after.is_none() {
+                Either::Left(future::ready(Ok(Tagged {
+                    tag,
+                    v: ReadReply::Normal(Ok(ret)),
+                })))
+            } else {
+                let (tx, rx) = tokio::sync::oneshot::channel();
+                let trigger = time::Duration::from_millis(TRIGGER_TIMEOUT_MS);
+                let now = time::Instant::now();
+                let r = wait.send((
+                    BlockingRead {
+                        tag,
+                        target,
+                        keys,
+                        pending,
+                        after,
+                        read: ret,
+                        truth: s.clone(),
+                        trigger_timeout: trigger,
+                        next_trigger: now,
+                        first: now,
+                    },
+                    tx,
+                ));
+                if r.is_err() {
+                    // we're shutting down
+                    return Either::Left(future::ready(Err(())));
+                }
+                Either::Right(rx.map(|r| match r {
+                    Err(_) => Err(()),
+                    Ok(r) => r,
+                }))
+            }
         }
     }
 }
 
+fn as_slice_bound(bound: &Bound<Vec<DataType>>) -> Bound<&[DataType]> {
+    match *bound {
+        Bound::Included(ref k) => Bound::Included(&k[..]),
+        Bound::Excluded(ref k) => Bound::Excluded(&k[..]),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Expand a range over a single integral key column into the individual keys it covers.
+///
+/// Integers that are equal hash and compare the same whatever their types, so the keys are
+/// `BigInt`s, or `UnsignedBigInt`s where they don't fit. Returns `None` if the range is unbounded,
+/// is not over a single integral column, or covers more than `MAX_RANGE_EXPANSION` keys.
+fn expand_range(
+    lower: &Bound<Vec<DataType>>,
+    upper: &Bound<Vec<DataType>>,
+) -> Option<Vec<Vec<DataType>>> {
+    fn integral(key: &[DataType]) -> Option<i128> {
+        match *key {
+            [DataType::Int(n)] => Some(i128::from(n)),
+            [DataType::UnsignedInt(n)] => Some(i128::from(n)),
+            [DataType::BigInt(n)] => Some(i128::from(n)),
+            [DataType::UnsignedBigInt(n)] => Some(i128::from(n)),
+            _ => None,
+        }
+    }
+
+    fn key(n: i128) -> Option<DataType> {
+        use std::convert::TryFrom;
+        i64::try_from(n)
+            .map(DataType::BigInt)
+            .or_else(|_| u64::try_from(n).map(DataType::UnsignedBigInt))
+            .ok()
+    }
+
+    let start = match *lower {
+        Bound::Included(ref k) => integral(k)?,
+        Bound::Excluded(ref k) => integral(k)? + 1,
+        Bound::Unbounded => return None,
+    };
+    let end = match *upper {
+        Bound::Included(ref k) => integral(k)?,
+        Bound::Excluded(ref k) => integral(k)? - 1,
+        Bound::Unbounded => return None,
+    };
+
+    if end < start {
+        return Some(Vec::new());
+    }
+    if end - start >= MAX_RANGE_EXPANSION {
+        return None;
+    }
+    // no integral column holds keys outside of the range of the 64-bit integers
+    Some((start..=end).filter_map(key).map(|k| vec![k]).collect())
+}
+
 #[pin_project]
 struct BlockingRead {
     tag: u32,
@@ -318,6 +636,8 @@ struct BlockingRead {
     keys: Vec<Vec<DataType>>,
     // index in self.read that each entyr in keys corresponds to
     pending: Vec<usize>,
+    // writes that must be visible before any of the keys are read
+    after: Option<WriteToken>,
     truth: Readers,
 
     trigger_timeout: time::Duration,
@@ -333,6 +653,7 @@ impl std::fmt::Debug for BlockingRead {
             .field("read", &self.read)
             .field("keys", &self.keys)
             .field("pending", &self.pending)
+            .field("after", &self.after)
             .field("trigger_timeout", &self.trigger_timeout)
             .field("next_trigger", &self.next_trigger)
             .field("first", &self.first)
@@ -344,14 +665,25 @@ impl BlockingRead {
     fn check(&mut self) -> Poll<Result<Tagged<ReadReply<SerializedReadReplyBatch>>, ()>> {
         READERS.with(|readers_cache| {
             let mut readers_cache = readers_cache.borrow_mut();
-            let s = &self.truth;
-            let target = &self.target;
-            let reader = readers_cache.entry(self.target).or_insert_with(|| {
-                let readers = s.lock().unwrap();
-                readers.get(target).unwrap().clone()
-            });
+            let reader = match cached_reader(&mut readers_cache, &self.truth, self.target) {
+                Some(reader) => reader,
+                None => {
+                    // the reader is gone, and no other reader took over its reads
+                    self.pending.clear();
+                    self.keys.clear();
+                    return Err(());
+                }
+            };
 
             let now = time::Instant::now();
+            if let Some(ref token) = self.after {
+                if !reader.has_applied(token) {
+                    // keep waiting for the writes before looking up any keys
+                    return Ok(());
+                }
+                self.after = None;
+            }
+
             let read = &mut self.read;
             let next_trigger = self.next_trigger;
 
@@ -409,7 +741,7 @@ impl BlockingRead {
             Ok(())
         })?;
 
-        if self.keys.is_empty() {
+        if self.keys.is_empty() && self.after.is_none() {
             Poll::Ready(Ok(Tagged {
                 tag: self.tag,
                 v: ReadReply::Normal(Ok(mem::take(&mut self.read))),
@@ -420,10 +752,46 @@ impl BlockingRead {
     }
 }
 
+#[cfg(test)]
+mod range {
+    use super::expand_range;
+    use noria::DataType;
+    use std::ops::Bound;
+
+    #[test]
+    fn expand_large_unsigned() {
+        let lower = Bound::Included(vec![DataType::UnsignedBigInt(u64::max_value() - 1)]);
+        let upper = Bound::Included(vec![DataType::UnsignedBigInt(u64::max_value())]);
+        assert_eq!(
+            expand_range(&lower, &upper),
+            Some(vec![
+                vec![DataType::UnsignedBigInt(u64::max_value() - 1)],
+                vec![DataType::UnsignedBigInt(u64::max_value())],
+            ])
+        );
+    }
+
+    #[test]
+    fn expand_across_types() {
+        let lower = Bound::Excluded(vec![DataType::Int(-2)]);
+        let upper = Bound::Included(vec![DataType::UnsignedBigInt(1)]);
+        let keys = expand_range(&lower, &upper).unwrap();
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys[0], vec![DataType::Int(-1)]);
+        assert_eq!(keys[2], vec![DataType::UnsignedInt(1)]);
+    }
+
+    #[test]
+    fn expand_unbounded() {
+        let lower = Bound::Included(vec![DataType::Int(1)]);
+        assert_eq!(expand_range(&lower, &Bound::Unbounded), None);
+    }
+}
+
 #[cfg(test)]
 mod readreply {
     use super::SerializedReadReplyBatch;
-    use noria::{DataType, ReadReply, Tagged};
+    use noria::{DataType, Delta, ReadReply, Tagged};
 
     fn rtt_ok(data: Vec<Vec<Vec<DataType>>>) {
         let got: Tagged<ReadReply> = bincode::deserialize(
@@ -553,6 +921,57 @@ mod readreply {
         ));
     }
 
+    #[test]
+    fn rtt_deltas() {
+        let deltas = vec![
+            Delta::Positive(vec![DataType::from(1), DataType::from(42)]),
+            Delta::Negative(vec![DataType::from(1), DataType::from(43)]),
+        ];
+        let got: Tagged<ReadReply> = bincode::deserialize(
+            &bincode::serialize(&Tagged {
+                tag: 32,
+                v: ReadReply::Deltas::<SerializedReadReplyBatch>(Ok(deltas.clone())),
+            })
+            .unwrap(),
+        )
+        .unwrap();
+
+        match got {
+            Tagged {
+                v: ReadReply::Deltas(Ok(got)),
+                tag: 32,
+            } => assert_eq!(got, deltas),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn rtt_changes() {
+        let changes = (
+            3,
+            vec![
+                (7, Delta::Positive(vec![DataType::from(1)])),
+                (8, Delta::Negative(vec![DataType::from(1)])),
+            ],
+        );
+        let got: Tagged<ReadReply> = bincode::deserialize(
+            &bincode::serialize(&Tagged {
+                tag: 32,
+                v: ReadReply::Changes::<SerializedReadReplyBatch>(Ok(changes.clone())),
+            })
+            .unwrap(),
+        )
+        .unwrap();
+
+        match got {
+            Tagged {
+                v: ReadReply::Changes(Ok(got)),
+                tag: 32,
+            } => assert_eq!(got, changes),
+            r => panic!("{:?}", r),
+        }
+    }
+
     async fn async_bincode_rtt_ok(data: Vec<Vec<Vec<DataType>>>) {
         use futures_util::{SinkExt, StreamExt};
 
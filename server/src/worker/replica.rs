@@ -20,7 +20,7 @@ use futures_util::{
 use noria::channel::{DualTcpStream, CONNECTION_FROM_BASE};
 use noria::internal::DomainIndex;
 use noria::internal::LocalOrNot;
-use noria::{Input, Tagged};
+use noria::{Input, Tagged, WriteResult};
 use pin_project::pin_project;
 use slog;
 use std::collections::{HashMap, VecDeque};
@@ -92,6 +92,10 @@ pub(super) struct Replica {
             bool,
         ),
     >,
+    /// Connections to shards that have moved, which are closed once they are flushed.
+    closing: Vec<Box<dyn Sink<Box<Packet>, Error = bincode::Error> + Send + Unpin>>,
+    /// Where shards moved to that were last sent to where they were.
+    rerouted: AHashMap<ReplicaAddr, std::net::SocketAddr>,
 
     #[pin]
     timeout: Strawpoll<async_timer::oneshot::Timer>,
@@ -124,6 +128,8 @@ impl Replica {
             log: log.new(o! {"id" => id}),
             inputs: Default::default(),
             outputs: Default::default(),
+            closing: Vec::new(),
+            rerouted: Default::default(),
             out: Outboxes::new(ctrl_tx),
             timeout: Strawpoll::from(async_timer::oneshot::Timer::new(time::Duration::from_secs(
                 3600,
@@ -147,7 +153,7 @@ impl Replica {
             let mut stream = Pin::new(&mut inputs[streami]);
             let mut sent = 0;
 
-            for &tag in &conn.tag_acks {
+            for &(tag, ref result) in &conn.tag_acks {
                 match stream.as_mut().poll_ready(cx) {
                     Poll::Ready(Ok(())) => {}
                     Poll::Pending => break,
@@ -157,7 +163,10 @@ impl Replica {
                     }
                 }
 
-                if let Err(e) = stream.as_mut().start_send(Tagged { tag, v: () }) {
+                if let Err(e) = stream.as_mut().start_send(Tagged {
+                    tag,
+                    v: result.clone(),
+                }) {
                     // start_send shouldn't generally error
                     err.push(e.into());
                     break;
@@ -265,20 +274,21 @@ impl Replica {
         // just like in try_acks:
         // first, queue up any additional writes we have to do
         let mut err = Vec::new();
+        let closing = this.closing;
+        let rerouted = this.rerouted;
         for (&ri, ms) in &mut this.out.domains {
-            if ms.is_empty() {
-                continue;
-            }
-
-            let &mut (ref mut tx, ref mut pending) = outputs.entry(ri).or_insert_with(|| {
-                while !cc.has(&ri) {}
-                let tx = cc.builder_for(&ri).unwrap().build_async().unwrap();
-                (tx, true)
-            });
-
-            let mut tx = Pin::new(tx);
-
             while !ms.is_empty() {
+                let &mut (ref mut tx, ref mut pending) = outputs.entry(ri).or_insert_with(|| {
+                    if let Some(to) = rerouted.remove(&ri) {
+                        // the shard moved, and our worker may not have heard where to yet
+                        while cc.get_addr(&ri) != Some(to) {}
+                    }
+                    while !cc.has(&ri) {}
+                    let tx = cc.builder_for(&ri).unwrap().build_async().unwrap();
+                    (tx, true)
+                });
+
+                let mut tx = Pin::new(tx);
                 match tx.as_mut().poll_ready(cx) {
                     Poll::Ready(Ok(())) => {}
                     Poll::Pending => break,
@@ -289,6 +299,10 @@ impl Replica {
                 }
 
                 let m = ms.pop_front().expect("!is_empty");
+                let moved_to = match *m {
+                    Packet::Rerouted { to } => Some(to),
+                    _ => None,
+                };
                 match tx.as_mut().start_send(m) {
                     Ok(()) => {
                         // we queued something, so we'll need to send!
@@ -299,6 +313,31 @@ impl Replica {
                         break;
                     }
                 }
+
+                if let Some(to) = moved_to {
+                    // that was the last packet for the shard that goes to where it was
+                    let (tx, _) = outputs.remove(&ri).unwrap();
+                    closing.push(tx);
+                    rerouted.insert(ri, to);
+                }
+            }
+        }
+
+        if !err.is_empty() {
+            return Err(err.swap_remove(0).into());
+        }
+
+        // connections to shards that moved are closed once everything sent on them is flushed
+        let mut i = 0;
+        while i < closing.len() {
+            match Pin::new(&mut closing[i]).poll_close(cx) {
+                Poll::Pending => i += 1,
+                Poll::Ready(r) => {
+                    if let Err(e) = r {
+                        err.push(e);
+                    }
+                    closing.swap_remove(i);
+                }
             }
         }
 
@@ -436,8 +475,8 @@ struct ConnState {
     // number of unacked inputs
     unacked: usize,
 
-    // unsent acks (value is the tag)
-    tag_acks: Vec<u32>,
+    // unsent acks (value is the tag and the sequence number the base assigned to the write)
+    tag_acks: Vec<(u32, WriteResult)>,
 
     // epoch counter for each stream index (since they're re-used)
     epoch: usize,
@@ -510,13 +549,13 @@ impl Outboxes {
 }
 
 impl Executor for Outboxes {
-    fn ack(&mut self, id: SourceChannelIdentifier) {
+    fn ack(&mut self, id: SourceChannelIdentifier, result: WriteResult) {
         self.dirty = true;
         let mut c = &mut self.connections[id.token];
         if id.epoch == c.epoch {
             // if the epoch doesn't match, the stream was closed and a new one has been established
             // note that this only matters for connections that do not wait for all acks!
-            c.tag_acks.push(id.tag);
+            c.tag_acks.push((id.tag, result));
 
             // NOTE: it's a little sad we can't crash on underflow here.
             // it is because if a send fails, we set c.unacked = 0, and should the domain _then_
@@ -314,7 +314,13 @@ async fn check_query(
                             let s: &str = (&v).into();
                             s.to_string()
                         }
-                        DataType::Timestamp(_) => unimplemented!(),
+                        DataType::Decimal(_) | DataType::Date(_) | DataType::Time(_) => {
+                            v.to_string()
+                        }
+                        DataType::Bool(b) => (b as u8).to_string(),
+                        DataType::Timestamp(_) | DataType::ByteArray(_) | DataType::Json(_) => {
+                            unimplemented!()
+                        }
                     })
                     .collect()
             })